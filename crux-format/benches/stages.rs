@@ -0,0 +1,111 @@
+//! Durations of the instrumented stages, compare the build with and without
+//! spans to check their overhead:
+//!
+//! ```bash
+//! cargo bench -p crux-format --bench stages
+//! cargo bench -p crux-format --bench stages --features tracing
+//! ```
+
+use std::{
+    hint::black_box,
+    io::Cursor,
+    time::{Duration, Instant},
+};
+
+use crux_format::{
+    query::{CmpOp, Expr},
+    ArrowPointCloud, ChecksumWriter, Point, PointCloudTrait, PointTrait, Query, Sample, Synthetic,
+    AABB,
+};
+
+const RUNS: usize = 10;
+
+/// Fastest of [RUNS] runs of `f`
+fn bench<T>(name: &str, mut f: impl FnMut() -> T) {
+    let fastest = (0..RUNS)
+        .map(|_| {
+            let start = Instant::now();
+            black_box(f());
+            start.elapsed()
+        })
+        .min()
+        .unwrap_or(Duration::ZERO);
+    println!("{name:<10} {:>10.3} ms", fastest.as_secs_f64() * 1e3);
+}
+
+fn main() {
+    let pc = Synthetic::new(2_000_000)
+        .seed(1)
+        .intensity(true)
+        .batch_size(10_000)
+        .uniform()
+        .unwrap();
+
+    let mut writer = ChecksumWriter::try_new(Vec::new(), &pc.schema()).unwrap();
+    for e in pc.store.iter() {
+        for batch in pc.store.batches(e.key()) {
+            writer.write(&batch).unwrap();
+        }
+    }
+    writer.finish().unwrap();
+    let bytes = writer.into_inner();
+
+    let query = Query::new()
+        .bounds(AABB::from_corners(
+            Point::<f64, 3>::from_slice(&[10., 10., 0.]),
+            Point::from_slice(&[60., 60., 100.]),
+        ))
+        .sample(Sample::Seeded { p: 0.5, seed: 1 });
+
+    bench("decode", || {
+        ArrowPointCloud::try_from_reader(Cursor::new(&bytes)).unwrap()
+    });
+    bench("aabb", || pc.aabb::<Point<f64, 3>>());
+    bench("query", || pc.execute(&query).unwrap().num_points());
+    bench("rechunk", || pc.rechunk(65_536));
+    bench("index", || pc.batch_index(&()).unwrap().size());
+    bench("points", || {
+        pc.points::<Point<f64, 3>>().map(|p| p.x()).sum::<f64>()
+    });
+    bench("rows", || {
+        pc.rows()
+            .map(|row| row.point::<Point<f64, 3>>().x() + row.get_f64("intensity").unwrap())
+            .sum::<f64>()
+    });
+
+    // temporal query on a drive of 100 batches, pruned by the time ranges of
+    // the batches or filtering all of them
+    let drive = Synthetic::new(2_000_000)
+        .seed(1)
+        .batch_size(20_000)
+        .drive()
+        .unwrap();
+    let range = 1000.5..1040.5;
+    let touched: usize = drive
+        .time_candidates(&range)
+        .iter()
+        .flat_map(|(_, positions)| positions)
+        .map(Vec::len)
+        .sum();
+    println!(
+        "time range touches {touched} of {} batches",
+        drive.store.len()
+    );
+    bench("time", || {
+        drive
+            .execute(&Query::new().time(range.clone()))
+            .unwrap()
+            .num_points()
+    });
+    let filter = Expr::cmp("gps_time", CmpOp::Ge, range.start).and(Expr::cmp(
+        "gps_time",
+        CmpOp::Lt,
+        range.end,
+    ));
+    bench("time scan", || {
+        drive
+            .execute(&Query::new().filter(filter.clone()))
+            .unwrap()
+            .num_points()
+    });
+}
@@ -0,0 +1,48 @@
+use std::f64::consts::FRAC_PI_4;
+
+use arrow::{compute::concat_batches, ipc::writer::FileWriter, record_batch::RecordBatch};
+use nalgebra::{DMatrix, Isometry3, Point3, Translation3, UnitQuaternion, Vector3};
+
+use crux_format::{ArrowPointCloud, Point, PointCloudTrait, Synthetic, AABB};
+
+fn main() {
+    let pc = Synthetic::new(10_000).intensity(true).terrain().unwrap();
+    let bounds: AABB<Point<f64, 3>> = pc.aabb();
+    println!("Bounds before {:?} - {:?}", bounds.lower(), bounds.upper());
+
+    // rotate around the z axis and shift, e.g. the result of a registration
+    let isometry = Isometry3::from_parts(
+        Translation3::new(100., -50., 2.),
+        UnitQuaternion::from_axis_angle(&Vector3::z_axis(), FRAC_PI_4),
+    );
+
+    let m = pc.to_matrix().unwrap();
+    let transformed = DMatrix::from_fn(m.nrows(), m.ncols(), |i, j| {
+        let p = isometry * Point3::new(m[(i, 0)], m[(i, 1)], m[(i, 2)]);
+        p[j]
+    });
+
+    // the points are the attribute table, their intensities stay with them
+    let batches: Vec<RecordBatch> = pc
+        .store
+        .iter()
+        .flat_map(|e| pc.store.batches(e.key()))
+        .collect();
+    let points = concat_batches(&pc.schema(), &batches).unwrap();
+    let result = ArrowPointCloud::from_matrix(&transformed, pc.schema(), Some(&points)).unwrap();
+
+    let bounds: AABB<Point<f64, 3>> = result.aabb();
+    println!("Bounds after {:?} - {:?}", bounds.lower(), bounds.upper());
+
+    // write the transformed points back
+    let path = std::env::temp_dir().join("rigid_transform.arrow");
+    let mut writer =
+        FileWriter::try_new(std::fs::File::create(&path).unwrap(), &result.schema()).unwrap();
+    for e in result.store.iter() {
+        for batch in result.store.batches(e.key()) {
+            writer.write(&batch).unwrap();
+        }
+    }
+    writer.finish().unwrap();
+    println!("Wrote {} points to {path:?}", result.num_points());
+}
@@ -52,9 +52,47 @@ where
         P: 'a,
     {
         i.into_iter()
-            .fold(<Self as rstar::Envelope>::new_empty(), |aabb, p| {
-                aabb.add_point(p)
-            })
+            .fold(Self::new_empty(), |aabb, p| aabb.add_point(p))
+    }
+
+    /// Returns an empty AABB, the neutral element of [AABB::merged].
+    pub fn new_empty() -> Self {
+        AABB(rstar::Envelope::new_empty())
+    }
+
+    /// Returns the area of the AABB, the volume in three dimensions.
+    pub fn area(&self) -> P::Scalar {
+        rstar::Envelope::area(&self.0)
+    }
+
+    /// Returns the center of the AABB.
+    pub fn center(&self) -> P {
+        rstar::Envelope::center(&self.0)
+    }
+
+    /// Returns whether the point is within the AABB, bounds inclusive.
+    pub fn contains_point(&self, point: &P) -> bool {
+        rstar::Envelope::contains_point(&self.0, point)
+    }
+
+    /// Returns whether `other` is completely within the AABB.
+    pub fn contains_envelope(&self, other: &Self) -> bool {
+        rstar::Envelope::contains_envelope(&self.0, &other.0)
+    }
+
+    /// Returns whether the AABBs share at least one point.
+    pub fn intersects(&self, other: &Self) -> bool {
+        rstar::Envelope::intersects(&self.0, &other.0)
+    }
+
+    /// Extends the AABB to contain `other`.
+    pub fn merge(&mut self, other: &Self) {
+        rstar::Envelope::merge(&mut self.0, &other.0)
+    }
+
+    /// Returns the AABB containing `self` and `other`.
+    pub fn merged(&self, other: &Self) -> Self {
+        AABB(rstar::Envelope::merged(&self.0, &other.0))
     }
 
     /// Returns the AABB that contains `self` and another point.
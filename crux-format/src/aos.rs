@@ -61,7 +61,7 @@ where
 #[cfg(test)]
 mod tests {
     use crate::{aabb::AABB, point::Point, point::PointTrait};
-    use rstar::{Envelope, PointDistance};
+    use rstar::PointDistance;
 
     use super::*;
 
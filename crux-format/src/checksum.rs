@@ -0,0 +1,322 @@
+//! Checksums of the record batches of Arrow IPC streams.
+//!
+//! A [ChecksumWriter] hashes the encoded messages of each batch with xxHash64
+//! and appends the ordered hashes after the end of the stream, as metadata of
+//! a trailing schema-only stream. Arrow readers stop at the end of the first
+//! stream and ignore the trailer, [ArrowPointCloud::try_from_reader] verifies
+//! the batches against it.
+
+use std::{
+    hash::Hasher,
+    io::{Cursor, Read, Write},
+    ops::Range,
+};
+
+use arrow::{
+    datatypes::Schema,
+    error::ArrowError,
+    ipc::{
+        reader::StreamReader,
+        root_as_message,
+        writer::{
+            write_message, DictionaryTracker, IpcDataGenerator, IpcWriteOptions, StreamWriter,
+        },
+        MessageHeader,
+    },
+    record_batch::RecordBatch,
+};
+use twox_hash::XxHash64;
+
+use crate::{trace::span, ArrowPointCloud, PointCloudError};
+
+/// Request header asking for checksums and response header announcing them
+pub const CHECKSUM_HEADER: &str = "x-crux-checksum";
+
+/// Value of [CHECKSUM_HEADER], the only supported algorithm
+pub const XXHASH64: &str = "xxhash64";
+
+/// Schema metadata key of the trailer listing the checksums
+const CHECKSUMS_KEY: &str = "crux:checksums";
+
+/// Marker preceding the length of each message
+const CONTINUATION: u32 = 0xFFFF_FFFF;
+
+fn xxhash64(bytes: &[u8]) -> u64 {
+    let mut hasher = XxHash64::with_seed(0);
+    hasher.write(bytes);
+    hasher.finish()
+}
+
+/// Arrow IPC stream writer followed by the checksums of its batches
+pub struct ChecksumWriter<W: Write> {
+    inner: W,
+    options: IpcWriteOptions,
+    data_gen: IpcDataGenerator,
+    dictionary_tracker: DictionaryTracker,
+    checksums: Vec<u64>,
+}
+
+impl<W: Write> ChecksumWriter<W> {
+    /// Write the schema message to `inner`
+    pub fn try_new(mut inner: W, schema: &Schema) -> Result<Self, ArrowError> {
+        let options = IpcWriteOptions::default();
+        let data_gen = IpcDataGenerator::default();
+        write_message(
+            &mut inner,
+            data_gen.schema_to_bytes(schema, &options),
+            &options,
+        )?;
+
+        Ok(Self {
+            inner,
+            options,
+            data_gen,
+            dictionary_tracker: DictionaryTracker::new(false),
+            checksums: Vec::new(),
+        })
+    }
+
+    /// Write the messages of `batch`, dictionaries included, and hash them
+    pub fn write(&mut self, batch: &RecordBatch) -> Result<(), ArrowError> {
+        let (dictionaries, message) =
+            self.data_gen
+                .encoded_batch(batch, &mut self.dictionary_tracker, &self.options)?;
+
+        let mut encoded = Vec::new();
+        for message in dictionaries.into_iter().chain([message]) {
+            write_message(&mut encoded, message, &self.options)?;
+        }
+        self.checksums.push(xxhash64(&encoded));
+        self.inner.write_all(&encoded)?;
+        Ok(())
+    }
+
+    /// End the stream and append the checksums
+    pub fn finish(&mut self) -> Result<(), ArrowError> {
+        self.inner.write_all(&CONTINUATION.to_le_bytes())?;
+        self.inner.write_all(&0u32.to_le_bytes())?;
+
+        let checksums: Vec<String> = self.checksums.iter().map(|h| format!("{h:016x}")).collect();
+        let trailer = Schema::empty().with_metadata(
+            [(CHECKSUMS_KEY.to_owned(), checksums.join(","))]
+                .into_iter()
+                .collect(),
+        );
+        StreamWriter::try_new(&mut self.inner, &trailer)?.into_inner()?;
+        Ok(())
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+/// Byte ranges of the encoded batches of the stream at the start of `bytes`,
+/// and the offset after its end of stream marker
+fn batches(bytes: &[u8]) -> Result<(Vec<Range<usize>>, usize), ArrowError> {
+    let invalid = |offset: usize| ArrowError::IpcError(format!("truncated message at {offset}"));
+    let read_u32 = |offset: usize| {
+        bytes
+            .get(offset..offset + 4)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .ok_or_else(|| invalid(offset))
+    };
+
+    let mut batches = Vec::new();
+    // dictionaries belong to the batch following them
+    let mut start = None;
+    let mut offset = 0;
+    while offset < bytes.len() {
+        // streams of older writers lack the continuation marker
+        let (length, prefix) = match read_u32(offset)? {
+            CONTINUATION => (read_u32(offset + 4)? as usize, 8),
+            length => (length as usize, 4),
+        };
+        if length == 0 {
+            return Ok((batches, offset + prefix));
+        }
+
+        let metadata = bytes
+            .get(offset + prefix..offset + prefix + length)
+            .ok_or_else(|| invalid(offset))?;
+        let message = root_as_message(metadata)
+            .map_err(|e| ArrowError::IpcError(format!("invalid message at {offset}: {e}")))?;
+        let end = offset + prefix + length + message.bodyLength() as usize;
+        if end > bytes.len() {
+            return Err(invalid(offset));
+        }
+
+        match message.header_type() {
+            MessageHeader::Schema => {}
+            MessageHeader::RecordBatch => {
+                batches.push(start.take().unwrap_or(offset)..end);
+            }
+            _ => {
+                start.get_or_insert(offset);
+            }
+        }
+        offset = end;
+    }
+
+    Ok((batches, offset))
+}
+
+/// Verify the batches of an IPC stream against its trailing checksums, if
+/// any, and return whether it had them
+pub fn verify(bytes: &[u8]) -> Result<bool, PointCloudError> {
+    let _span = span!(DEBUG, "verify", bytes = bytes.len());
+    let (batches, end) = batches(bytes)?;
+    if end >= bytes.len() {
+        return Ok(false);
+    }
+
+    let trailer = StreamReader::try_new(Cursor::new(&bytes[end..]), None)?;
+    let Some(checksums) = trailer.schema().metadata().get(CHECKSUMS_KEY).cloned() else {
+        return Ok(false);
+    };
+    let checksums = checksums
+        .split(',')
+        .filter(|h| !h.is_empty())
+        .map(|h| u64::from_str_radix(h, 16))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| ArrowError::IpcError(format!("invalid checksum: {e}")))?;
+
+    for (batch_index, range) in batches.iter().enumerate() {
+        if checksums.get(batch_index) != Some(&xxhash64(&bytes[range.clone()])) {
+            return Err(PointCloudError::ChecksumMismatch { batch_index });
+        }
+    }
+    if checksums.len() != batches.len() {
+        return Err(PointCloudError::ChecksumMismatch {
+            batch_index: batches.len(),
+        });
+    }
+
+    Ok(true)
+}
+
+impl ArrowPointCloud {
+    /// Points of an Arrow IPC stream, verifying the checksums of the batches
+    /// if the stream is followed by them
+    pub fn try_from_reader<R: Read>(reader: R) -> Result<Self, PointCloudError> {
+        Self::try_from_reader_map(reader, |_, batch| Ok(batch))
+    }
+
+    /// Points of an Arrow IPC stream like [ArrowPointCloud::try_from_reader],
+    /// with every batch passed through `map` with its index as it is decoded
+    pub(crate) fn try_from_reader_map<R: Read>(
+        mut reader: R,
+        mut map: impl FnMut(usize, RecordBatch) -> Result<RecordBatch, PointCloudError>,
+    ) -> Result<Self, PointCloudError> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).map_err(ArrowError::from)?;
+        let span = span!(DEBUG, "decode", bytes = bytes.len(), rows);
+        verify(&bytes)?;
+
+        let reader = StreamReader::try_new(Cursor::new(bytes), None)?;
+        let mut pc = Self::try_new(reader.schema())?;
+        let mut rows = 0;
+        for (j, batch) in reader.enumerate() {
+            let batch = batch?;
+            let empty = batch.num_rows() == 0;
+            let batch = map(j, batch)?;
+            rows += batch.num_rows();
+            if empty || batch.num_rows() > 0 {
+                pc.append(batch)?;
+            }
+        }
+        span.record("rows", rows);
+        Ok(pc)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{synthetic::Synthetic, PointCloudTrait};
+
+    fn record(pc: &ArrowPointCloud) -> Vec<u8> {
+        let mut writer = ChecksumWriter::try_new(Vec::new(), &pc.schema()).unwrap();
+        for e in pc.store.iter() {
+            for batch in pc.store.batches(e.key()) {
+                writer.write(&batch).unwrap();
+            }
+        }
+        writer.finish().unwrap();
+        writer.into_inner()
+    }
+
+    fn survey() -> ArrowPointCloud {
+        let pc = Synthetic::new(3000)
+            .seed(1)
+            .intensity(true)
+            .terrain()
+            .unwrap();
+        // several batches
+        let mut split = ArrowPointCloud::try_new(pc.schema()).unwrap();
+        for part in pc.split_into(3) {
+            for e in part.store.iter() {
+                for batch in part.store.batches(e.key()) {
+                    split.append(batch).unwrap();
+                }
+            }
+        }
+        split
+    }
+
+    #[test]
+    fn round_trip() {
+        let pc = survey();
+        let bytes = record(&pc);
+        assert!(verify(&bytes).unwrap());
+
+        let decoded = ArrowPointCloud::try_from_reader(Cursor::new(&bytes)).unwrap();
+        assert_eq!(decoded.num_points(), pc.num_points());
+        assert_eq!(decoded.content_hash(), pc.content_hash());
+
+        // plain Arrow readers ignore the trailer
+        let reader = StreamReader::try_new(Cursor::new(&bytes), None).unwrap();
+        let rows: usize = reader.map(|batch| batch.unwrap().num_rows()).sum();
+        assert_eq!(rows, pc.num_points());
+
+        // streams without checksums are read unverified
+        let mut writer = StreamWriter::try_new(Vec::new(), &pc.schema()).unwrap();
+        for e in pc.store.iter() {
+            for batch in pc.store.batches(e.key()) {
+                writer.write(&batch).unwrap();
+            }
+        }
+        let plain = writer.into_inner().unwrap();
+        assert!(!verify(&plain).unwrap());
+        let decoded = ArrowPointCloud::try_from_reader(Cursor::new(&plain)).unwrap();
+        assert_eq!(decoded.content_hash(), pc.content_hash());
+    }
+
+    #[test]
+    fn corrupt() {
+        let pc = survey();
+        let bytes = record(&pc);
+        let (batches, _) = batches(&bytes).unwrap();
+        assert_eq!(batches.len(), 3);
+
+        // one flipped byte in the body of the second batch
+        let mut corrupt = bytes.clone();
+        let range = &batches[1];
+        corrupt[range.end - 16] ^= 0x40;
+        assert!(matches!(
+            ArrowPointCloud::try_from_reader(Cursor::new(&corrupt)),
+            Err(PointCloudError::ChecksumMismatch { batch_index: 1 })
+        ));
+
+        // a missing batch
+        let mut dropped = bytes[..batches[2].start].to_vec();
+        dropped.extend_from_slice(&bytes[batches[2].end..]);
+        assert!(matches!(
+            verify(&dropped),
+            Err(PointCloudError::ChecksumMismatch { batch_index: 2 })
+        ));
+
+        // truncated
+        assert!(verify(&bytes[..batches[1].end - 1]).is_err());
+    }
+}
@@ -0,0 +1,179 @@
+use arrow::{compute::concat_batches, error::ArrowError, record_batch::RecordBatch};
+
+use crate::{trace::span, ArrowPointCloud, PointCloudTrait};
+
+/// Concatenates and splits consecutive batches into batches of `target_rows`
+///
+/// Row order and columns are kept. Large batches are split into zero-copy
+/// slices, only small batches and the remainders of split ones are copied.
+pub struct Rechunker {
+    target_rows: usize,
+    pending: Vec<RecordBatch>,
+    rows: usize,
+}
+
+impl Rechunker {
+    pub fn new(target_rows: usize) -> Self {
+        Self {
+            target_rows: target_rows.max(1),
+            pending: Vec::new(),
+            rows: 0,
+        }
+    }
+
+    /// Add a batch, returns the completed batches of `target_rows`
+    pub fn push(&mut self, mut batch: RecordBatch) -> Result<Vec<RecordBatch>, ArrowError> {
+        let mut chunks = Vec::new();
+
+        while self.rows + batch.num_rows() >= self.target_rows {
+            let take = self.target_rows - self.rows;
+            self.pending.push(batch.slice(0, take));
+            batch = batch.slice(take, batch.num_rows() - take);
+            chunks.push(self.take()?);
+        }
+        if batch.num_rows() > 0 {
+            self.rows += batch.num_rows();
+            self.pending.push(batch);
+        }
+
+        Ok(chunks)
+    }
+
+    /// The remaining rows, fewer than `target_rows`
+    pub fn finish(mut self) -> Result<Option<RecordBatch>, ArrowError> {
+        if self.pending.is_empty() {
+            return Ok(None);
+        }
+        self.take().map(Some)
+    }
+
+    fn take(&mut self) -> Result<RecordBatch, ArrowError> {
+        self.rows = 0;
+        let pending = std::mem::take(&mut self.pending);
+        match &pending[..] {
+            [batch] => Ok(batch.clone()),
+            batches => concat_batches(&batches[0].schema(), batches),
+        }
+    }
+}
+
+/// Batches of `target_rows`, except for the last one, see [Rechunker]
+pub fn rechunk(
+    batches: impl IntoIterator<Item = RecordBatch>,
+    target_rows: usize,
+) -> Result<Vec<RecordBatch>, ArrowError> {
+    let mut rechunker = Rechunker::new(target_rows);
+    let mut chunks = Vec::new();
+    for batch in batches {
+        chunks.extend(rechunker.push(batch)?);
+    }
+    chunks.extend(rechunker.finish()?);
+    Ok(chunks)
+}
+
+impl ArrowPointCloud {
+    /// Copy with the points in batches of `target_rows`, in iteration order.
+    ///
+    /// The batches are no longer grouped by cells and the copy is not indexed.
+    pub fn rechunk(&self, target_rows: usize) -> ArrowPointCloud {
+        // the schema has been validated on construction
+        let mut pc = ArrowPointCloud::try_new(self.schema()).unwrap();
+        let span = span!(DEBUG, "rechunk", target_rows = target_rows, batches);
+
+        let batches = self.store.iter().flat_map(|e| self.store.batches(e.key()));
+        // batches of a point cloud share its schema
+        let chunks = rechunk(batches, target_rows).unwrap();
+        span.record("batches", chunks.len());
+        for batch in chunks {
+            pc.append(batch).unwrap();
+        }
+
+        pc
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow::array::{AsArray, Float64Array};
+
+    use super::*;
+    use crate::{Point, PointTrait};
+
+    fn cloud(sizes: &[usize]) -> ArrowPointCloud {
+        let mut pc = ArrowPointCloud::try_new(Point::<f64, 3>::schema()).unwrap();
+        let mut offset = 0;
+        for size in sizes {
+            let points = ArrowPointCloud::from_iter(
+                (offset..offset + size)
+                    .map(|i| Point::<f64, 3>::from_slice(&[i as f64 + 0.5, 0.5, 0.5])),
+            )
+            .unwrap();
+            for e in points.store.iter() {
+                for batch in points.store.batches(e.key()) {
+                    pc.append(batch).unwrap();
+                }
+            }
+            offset += size;
+        }
+        pc
+    }
+
+    fn rows(pc: &ArrowPointCloud) -> Vec<usize> {
+        pc.store
+            .iter()
+            .flat_map(|e| pc.store.batches(e.key()))
+            .map(|batch| batch.num_rows())
+            .collect()
+    }
+
+    fn xs(pc: &ArrowPointCloud) -> Vec<f64> {
+        pc.store
+            .iter()
+            .flat_map(|e| pc.store.batches(e.key()))
+            .flat_map(|batch| {
+                let x: &Float64Array = batch.column(0).as_primitive();
+                x.values().to_vec()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn rechunk() {
+        // tiny and giant batches
+        let pc = cloud(&[1, 3, 2, 250, 1, 1, 40]);
+        assert_eq!(pc.num_points(), 298);
+
+        for target in [1, 7, 50, 100, 298, 1000] {
+            let chunked = pc.rechunk(target);
+            assert_eq!(chunked.num_points(), 298, "{target}");
+            assert_eq!(xs(&chunked), xs(&pc), "{target}");
+            assert_eq!(chunked.schema(), pc.schema());
+
+            let rows = rows(&chunked);
+            assert!(rows.iter().all(|n| *n <= 2 * target), "{target}: {rows:?}");
+            // all but the last batch have the target size
+            assert!(rows[..rows.len() - 1].iter().all(|n| *n == target));
+        }
+        assert_eq!(rows(&pc.rechunk(100)), [100, 100, 98]);
+
+        let empty = ArrowPointCloud::try_new(Point::<f64, 3>::schema()).unwrap();
+        assert_eq!(empty.rechunk(10).num_points(), 0);
+    }
+
+    #[test]
+    fn rechunker() {
+        let pc = cloud(&[25]);
+        let batch = pc.store.batches(pc.store.iter().next().unwrap().key())[0].clone();
+
+        let mut rechunker = Rechunker::new(10);
+        assert_eq!(rechunker.push(batch.slice(0, 5)).unwrap().len(), 0);
+        let chunks = rechunker.push(batch.slice(5, 20)).unwrap();
+        assert_eq!(
+            chunks.iter().map(|c| c.num_rows()).collect::<Vec<_>>(),
+            [10, 10]
+        );
+        assert_eq!(rechunker.finish().unwrap().unwrap().num_rows(), 5);
+
+        assert!(Rechunker::new(10).finish().unwrap().is_none());
+    }
+}
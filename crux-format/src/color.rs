@@ -0,0 +1,416 @@
+use std::{collections::BTreeMap, path::Path};
+
+use arrow::{
+    array::{Array, AsArray},
+    datatypes::{DataType, Float64Type},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{encoding::cast, ArrowPointCloud, ColumnStats, PointCloudError};
+
+pub use colorgrad::{Color, Gradient};
+
+/// Lower and upper stretch bound in attribute units
+pub type Bounds = (f64, f64);
+
+/// 8 bit color with alpha
+pub type Rgba = [u8; 4];
+
+/// Percentile stretch and gamma applied to scalar attributes before coloring
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(default)]
+pub struct Normalization {
+    /// Percentile mapped to 0
+    pub lower: f64,
+    /// Percentile mapped to 1
+    pub upper: f64,
+    /// Gamma, values above 1 brighten dark values
+    pub gamma: f64,
+}
+
+impl Default for Normalization {
+    fn default() -> Self {
+        Self {
+            lower: 2.,
+            upper: 98.,
+            gamma: 1.,
+        }
+    }
+}
+
+impl Normalization {
+    /// Attribute values at the stretch percentiles
+    pub fn bounds(&self, stats: &ColumnStats) -> Bounds {
+        (stats.percentile(self.lower), stats.percentile(self.upper))
+    }
+
+    /// Map a value into [0, 1]
+    pub fn apply(&self, value: f64, (lower, upper): Bounds) -> f64 {
+        let t = if upper > lower {
+            ((value - lower) / (upper - lower)).clamp(0., 1.)
+        } else {
+            0.5
+        };
+
+        t.powf(1. / self.gamma)
+    }
+}
+
+/// Mapping of attribute values onto the gradient
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Stretch {
+    /// Minimum to maximum value
+    MinMax,
+    /// Percentiles and gamma
+    Percentile(Normalization),
+    /// Bounds in attribute units, reversed if the lower bound is larger
+    Fixed(Bounds),
+    /// Centered at zero up to the largest magnitude, e.g. for changes
+    Symmetric,
+}
+
+impl Stretch {
+    /// Attribute values mapped to the start and end of the gradient
+    pub fn bounds(&self, stats: &ColumnStats) -> Bounds {
+        match self {
+            Self::MinMax => (stats.min, stats.max),
+            Self::Percentile(normalization) => normalization.bounds(stats),
+            Self::Fixed(bounds) => *bounds,
+            Self::Symmetric => {
+                let magnitude = stats.min.abs().max(stats.max.abs()).max(f64::EPSILON);
+                (-magnitude, magnitude)
+            }
+        }
+    }
+
+    /// Map a value into [0, 1], the middle if the bounds are equal
+    pub fn apply(&self, value: f64, (lower, upper): Bounds) -> f64 {
+        match self {
+            Self::Percentile(normalization) => normalization.apply(value, (lower, upper)),
+            _ if lower != upper => ((value - lower) / (upper - lower)).clamp(0., 1.),
+            _ => 0.5,
+        }
+    }
+}
+
+/// Colors of integer classes
+#[derive(Debug, Clone, PartialEq)]
+pub struct Palette {
+    pub classes: BTreeMap<i64, Rgba>,
+    /// Color of classes without an entry
+    pub other: Rgba,
+}
+
+impl Palette {
+    /// ASPRS classes of LAS files
+    pub fn classification() -> Self {
+        Self {
+            classes: BTreeMap::from([
+                (0, [128, 128, 128, 255]), // created, never classified
+                (1, [245, 245, 220, 255]), // unclassified
+                (2, [128, 128, 0, 255]),   // ground
+                (3, [51, 204, 51, 255]),   // low vegetation
+                (4, [0, 255, 0, 255]),     // medium vegetation
+                (5, [0, 128, 0, 255]),     // high vegetation
+                (6, [128, 0, 0, 255]),     // building
+                (9, [0, 0, 255, 255]),     // water
+                (11, [64, 64, 64, 255]),   // road surface
+            ]),
+            other: [255, 166, 0, 255],
+        }
+    }
+
+    pub fn color(&self, class: i64) -> Rgba {
+        self.classes.get(&class).copied().unwrap_or(self.other)
+    }
+
+    /// Palette of the `classes` that occur, e.g. by
+    /// [distinct_counts](crate::ArrowPointCloud::distinct_counts), with the
+    /// colors of this one
+    pub fn occurring(&self, classes: impl IntoIterator<Item = i64>) -> Self {
+        Self {
+            classes: classes
+                .into_iter()
+                .map(|class| (class, self.color(class)))
+                .collect(),
+            other: self.other,
+        }
+    }
+}
+
+/// Colors of attribute values, shared by the viewer, raster tiles and exports
+pub enum ColorMap {
+    /// Values as classes
+    Categorical(Palette),
+    /// Scalar values stretched onto a gradient
+    Gradient {
+        gradient: Gradient,
+        normalization: Stretch,
+    },
+}
+
+impl ColorMap {
+    /// Color map by the kind of attribute: classes of `classification`,
+    /// grayscale `intensity`, diverging `delta` and `gradient` for others
+    pub fn for_attribute(
+        attribute: &str,
+        gradient: Gradient,
+        normalization: Normalization,
+    ) -> Self {
+        match attribute {
+            "classification" => Self::Categorical(Palette::classification()),
+            "intensity" => Self::Gradient {
+                gradient: gradient_from_colors(&["#000000", "#ffffff"]).expect("valid colors"),
+                normalization: Stretch::Percentile(normalization),
+            },
+            "delta" => Self::diverging(),
+            _ => Self::Gradient {
+                gradient,
+                normalization: Stretch::Percentile(normalization),
+            },
+        }
+    }
+
+    /// Red for positive and blue for negative values
+    pub fn diverging() -> Self {
+        let colors: Vec<Color> = colorgrad::rd_bu().colors(11).into_iter().rev().collect();
+        Self::Gradient {
+            gradient: colorgrad::CustomGradient::new()
+                .colors(&colors)
+                .build()
+                .expect("valid colors"),
+            normalization: Stretch::Symmetric,
+        }
+    }
+
+    /// Bounds of the values described by `stats`, `None` for categorical maps
+    pub fn bounds(&self, stats: &ColumnStats) -> Option<Bounds> {
+        match self {
+            Self::Categorical(_) => None,
+            Self::Gradient { normalization, .. } => Some(normalization.bounds(stats)),
+        }
+    }
+
+    /// RGBA of `value` with the gradient spanning `bounds`, `None` for NaN and
+    /// infinite values
+    pub fn color(&self, value: f64, bounds: Option<Bounds>) -> Option<Rgba> {
+        if !value.is_finite() {
+            return None;
+        }
+        Some(match self {
+            Self::Categorical(palette) => palette.color(value as i64),
+            Self::Gradient {
+                gradient,
+                normalization,
+            } => {
+                let bounds = bounds.unwrap_or((value, value));
+                gradient.at(normalization.apply(value, bounds)).to_rgba8()
+            }
+        })
+    }
+
+    /// Colors of a column, `None` for null, NaN and infinite values
+    pub fn map(
+        &self,
+        values: &dyn Array,
+        bounds: Option<Bounds>,
+    ) -> Result<Vec<Option<Rgba>>, PointCloudError> {
+        let values = cast(values, &DataType::Float64)?;
+        Ok(values
+            .as_primitive::<Float64Type>()
+            .iter()
+            .map(|v| v.and_then(|v| self.color(v, bounds)))
+            .collect())
+    }
+}
+
+/// Colors of an attribute per point by `map`, `None` for null, NaN and
+/// infinite values. Returns the bounds of gradients in attribute units.
+pub fn colors(
+    pc: &ArrowPointCloud,
+    attribute: &str,
+    map: &ColorMap,
+) -> Result<(Vec<Option<Rgba>>, Option<Bounds>), PointCloudError> {
+    let bounds = match map {
+        ColorMap::Categorical(_) => None,
+        ColorMap::Gradient { .. } => map.bounds(&pc.column_stats(attribute)?),
+    };
+
+    let mut colors = Vec::new();
+    for batch in pc.store.iter().flat_map(|e| pc.store.batches(e.key())) {
+        let column = batch.column_by_name(attribute).ok_or_else(|| {
+            PointCloudError::InvalidArgument(format!("no attribute `{attribute}`"))
+        })?;
+        colors.extend(map.map(column, bounds)?);
+    }
+
+    Ok((colors, bounds))
+}
+
+/// Normalized values of a scalar attribute per point, `None` for null, NaN
+/// and infinite values. Returns the stretch bounds in attribute units.
+pub fn normalized(
+    pc: &ArrowPointCloud,
+    attribute: &str,
+    normalization: &Normalization,
+) -> Result<(Vec<Option<f64>>, Bounds), PointCloudError> {
+    let stats = pc.column_stats(attribute)?;
+    let bounds = normalization.bounds(&stats);
+
+    let mut values = Vec::with_capacity(stats.count + stats.null_count);
+    for batch in pc.store.iter().flat_map(|e| pc.store.batches(e.key())) {
+        let column = cast(batch.column_by_name(attribute).unwrap(), &DataType::Float64)?;
+        values.extend(
+            column
+                .as_primitive::<Float64Type>()
+                .iter()
+                .map(|v| match v {
+                    Some(v) if v.is_finite() => Some(normalization.apply(v, bounds)),
+                    _ => None,
+                }),
+        );
+    }
+
+    Ok((values, bounds))
+}
+
+/// Gradient of a palette file with one hex color per line, turbo if `None`
+pub fn gradient(palette: Option<&Path>) -> Result<Gradient, PointCloudError> {
+    let Some(path) = palette else {
+        return Ok(colorgrad::turbo());
+    };
+
+    let content = std::fs::read_to_string(path).map_err(|e| {
+        PointCloudError::InvalidArgument(format!("failed to read palette `{path:?}`: {e}"))
+    })?;
+    let colors: Vec<&str> = content.split_whitespace().collect();
+
+    gradient_from_colors(&colors)
+}
+
+/// Gradient through html colors, e.g. `#440154`
+pub fn gradient_from_colors(colors: &[&str]) -> Result<Gradient, PointCloudError> {
+    colorgrad::CustomGradient::new()
+        .html_colors(colors)
+        .build()
+        .map_err(|e| PointCloudError::InvalidArgument(format!("invalid palette: {e}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow::array::{Float64Array, UInt8Array};
+
+    use super::*;
+
+    #[test]
+    fn percentile_clipping() {
+        // bimodal 16 bit intensities with a few saturated outliers
+        let values: Vec<f64> = (0..1000)
+            .map(|i| match i % 100 {
+                0 => 65535.,
+                i if i < 50 => 1000. + i as f64,
+                i => 3000. + i as f64,
+            })
+            .collect();
+        let stats = ColumnStats::from_values(values, 0);
+
+        let normalization = Normalization::default();
+        let bounds = normalization.bounds(&stats);
+
+        // outliers are clipped, both modes span the range
+        assert!(bounds.0 >= 1000. && bounds.0 < 1010., "{bounds:?}");
+        assert!(bounds.1 > 3050. && bounds.1 < 3100., "{bounds:?}");
+        assert_eq!(normalization.apply(65535., bounds), 1.);
+        assert_eq!(normalization.apply(0., bounds), 0.);
+
+        let low = normalization.apply(1025., bounds);
+        let high = normalization.apply(3075., bounds);
+        assert!(low < 0.1 && high > 0.9, "{low} {high}");
+
+        // gamma brightens dark values
+        let brighter = Normalization {
+            gamma: 2.,
+            ..normalization
+        };
+        assert!(brighter.apply(1500., bounds) > normalization.apply(1500., bounds));
+    }
+
+    #[test]
+    fn stretch() {
+        let stats = ColumnStats::from_values((0..=100).map(f64::from).collect(), 0);
+
+        assert_eq!(Stretch::MinMax.bounds(&stats), (0., 100.));
+        assert_eq!(Stretch::MinMax.apply(25., (0., 100.)), 0.25);
+        assert_eq!(Stretch::MinMax.apply(200., (0., 100.)), 1.);
+
+        let percentile = Stretch::Percentile(Normalization {
+            lower: 10.,
+            upper: 90.,
+            gamma: 1.,
+        });
+        let bounds = percentile.bounds(&stats);
+        assert!((bounds.0 - 10.).abs() < 1e-9 && (bounds.1 - 90.).abs() < 1e-9);
+        assert_eq!(percentile.apply(5., bounds), 0.);
+
+        // fixed bounds ignore the values, reversed bounds the gradient
+        let fixed = Stretch::Fixed((50., 150.));
+        assert_eq!(fixed.bounds(&stats), (50., 150.));
+        assert_eq!(fixed.apply(100., (50., 150.)), 0.5);
+        assert_eq!(Stretch::Fixed((100., 0.)).apply(25., (100., 0.)), 0.75);
+        assert_eq!(fixed.apply(1., (1., 1.)), 0.5);
+
+        let stats = ColumnStats::from_values(vec![-2., 1., 4.], 0);
+        assert_eq!(Stretch::Symmetric.bounds(&stats), (-4., 4.));
+    }
+
+    #[test]
+    fn color_map() {
+        let gray = ColorMap::Gradient {
+            gradient: gradient_from_colors(&["#000000", "#ffffff"]).unwrap(),
+            normalization: Stretch::MinMax,
+        };
+        let values = Float64Array::from(vec![Some(0.), None, Some(f64::NAN), Some(10.)]);
+        assert_eq!(
+            gray.map(&values, Some((0., 10.))).unwrap(),
+            [Some([0, 0, 0, 255]), None, None, Some([255, 255, 255, 255])]
+        );
+
+        // classes of any integer type, unknown ones in the fallback color
+        let classes = ColorMap::for_attribute(
+            "classification",
+            colorgrad::turbo(),
+            Normalization::default(),
+        );
+        let values = UInt8Array::from(vec![Some(2), Some(200), None]);
+        let palette = Palette::classification();
+        assert_eq!(
+            classes.map(&values, None).unwrap(),
+            [Some(palette.color(2)), Some(palette.other), None]
+        );
+        assert_eq!(classes.bounds(&ColumnStats::from_values(vec![2.], 0)), None);
+
+        // only the occurring classes, unknown ones listed in the fallback color
+        let occurring = palette.occurring([2, 64]);
+        assert_eq!(
+            occurring.classes,
+            BTreeMap::from([(2, palette.color(2)), (64, palette.other)])
+        );
+
+        // diverging maps gains to red and losses to blue
+        let delta = ColorMap::diverging();
+        let bounds = delta.bounds(&ColumnStats::from_values(vec![-1., 2.], 0));
+        let [r, _, b, _] = delta.color(2., bounds).unwrap();
+        assert!(r > b);
+        let [r, _, b, _] = delta.color(-2., bounds).unwrap();
+        assert!(r < b);
+    }
+
+    #[test]
+    fn palette() {
+        let gradient = gradient_from_colors(&["#000000", "#ffffff"]).unwrap();
+        assert_eq!(gradient.at(0.).to_rgba8(), [0, 0, 0, 255]);
+        assert_eq!(gradient.at(1.).to_rgba8(), [255, 255, 255, 255]);
+
+        assert!(gradient_from_colors(&["#zzzzzz"]).is_err());
+    }
+}
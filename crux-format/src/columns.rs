@@ -0,0 +1,373 @@
+use std::sync::Arc;
+
+use arrow::{
+    array::ArrayRef,
+    compute::{can_cast_types, cast_with_options, kernels::cmp::not_distinct, CastOptions},
+    datatypes::{DataType, Field, Schema, SchemaRef},
+    record_batch::RecordBatch,
+};
+
+use crate::{
+    schema::{validate, PCE_DIMENSION_KEY},
+    soa::Index,
+    ArrowPointCloud, PointCloudError,
+};
+
+/// Names and types of the attributes following the crux conventions
+pub const CONVENTIONS: [(&str, DataType); 12] = [
+    ("intensity", DataType::UInt16),
+    ("return_number", DataType::UInt8),
+    ("number_of_returns", DataType::UInt8),
+    ("classification", DataType::UInt8),
+    ("user_data", DataType::UInt8),
+    ("scan_angle", DataType::Float32),
+    ("point_source_id", DataType::UInt16),
+    ("gps_time", DataType::Float64),
+    ("red", DataType::UInt16),
+    ("green", DataType::UInt16),
+    ("blue", DataType::UInt16),
+    ("nir", DataType::UInt16),
+];
+
+/// Name without case, separators and spaces, e.g. `PointSourceId` and
+/// `point_source_id` are the same attribute
+fn canonical(name: &str) -> String {
+    name.chars()
+        .filter(char::is_ascii_alphanumeric)
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+impl ArrowPointCloud {
+    /// Rename the column `old` to `new`, the metadata of the field is kept
+    pub fn rename_column(&mut self, old: &str, new: &str) -> Result<(), PointCloudError> {
+        let (i, field) = self.field(old)?;
+        if old == new {
+            return Ok(());
+        }
+        if self.schema.column_with_name(new).is_some() {
+            return Err(PointCloudError::InvalidArgument(format!(
+                "column `{new}` already exists"
+            )));
+        }
+
+        let field = field.clone().with_name(new);
+        self.rewrite(self.replace_field(i, Some(field)), Ok)
+    }
+
+    /// Cast the column `name` to `data_type`.
+    ///
+    /// Values out of range of the new type are rejected. Unless `lossy`, so
+    /// are values that change on the way, e.g. fractions cast to integers.
+    pub fn cast_column(
+        &mut self,
+        name: &str,
+        data_type: &DataType,
+        lossy: bool,
+    ) -> Result<(), PointCloudError> {
+        let (i, field) = self.field(name)?;
+        let from = field.data_type().to_owned();
+        if from == *data_type {
+            return Ok(());
+        }
+        if !can_cast_types(&from, data_type) {
+            return Err(PointCloudError::InvalidArgument(format!(
+                "column `{name}` cannot be cast from {from} to {data_type}"
+            )));
+        }
+
+        let dimension = field.metadata().contains_key(PCE_DIMENSION_KEY);
+        let field = field.clone().with_data_type(data_type.to_owned());
+        let options = CastOptions {
+            safe: false,
+            ..Default::default()
+        };
+        let invalid = |e| {
+            PointCloudError::InvalidArgument(format!(
+                "column `{name}` cannot be cast from {from} to {data_type}: {e}"
+            ))
+        };
+
+        self.rewrite(self.replace_field(i, Some(field)), |mut columns| {
+            let cast = cast_with_options(&columns[i], data_type, &options).map_err(invalid)?;
+            if !lossy {
+                let back = cast_with_options(&cast, &from, &options).map_err(invalid)?;
+                let same = not_distinct(&back, &columns[i])?;
+                if same.true_count() != same.len() {
+                    return Err(PointCloudError::InvalidArgument(format!(
+                        "casting column `{name}` from {from} to {data_type} loses information"
+                    )));
+                }
+            }
+            columns[i] = cast;
+            Ok(columns)
+        })?;
+
+        // bounds of the batches may have changed
+        if dimension {
+            self.index = Index::None;
+        }
+        Ok(())
+    }
+
+    /// Remove the column `name`, dimensions cannot be removed
+    pub fn drop_column(&mut self, name: &str) -> Result<(), PointCloudError> {
+        let (i, field) = self.field(name)?;
+        if field.metadata().contains_key(PCE_DIMENSION_KEY) {
+            return Err(PointCloudError::InvalidArgument(format!(
+                "column `{name}` is a dimension"
+            )));
+        }
+
+        self.rewrite(self.replace_field(i, None), |mut columns| {
+            columns.remove(i);
+            Ok(columns)
+        })
+    }
+
+    /// Rename and cast attributes to the names and types of [CONVENTIONS],
+    /// e.g. `Classification` as Int32 to `classification` as UInt8.
+    ///
+    /// Names are matched regardless of case and separators. Attributes that
+    /// cannot be cast without loss keep their type.
+    pub fn normalize_columns(&mut self) -> Result<(), PointCloudError> {
+        for (name, data_type) in CONVENTIONS.iter() {
+            let Some(field) = self
+                .schema
+                .field_with_name(name)
+                .ok()
+                .or_else(|| {
+                    self.schema
+                        .fields()
+                        .iter()
+                        .map(AsRef::as_ref)
+                        .find(|field| canonical(field.name()) == canonical(name))
+                })
+                .cloned()
+            else {
+                continue;
+            };
+
+            if field.name() != name {
+                self.rename_column(field.name(), name)?;
+            }
+            match self.cast_column(name, data_type, false) {
+                Ok(()) | Err(PointCloudError::InvalidArgument(_)) => (),
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn field(&self, name: &str) -> Result<(usize, &Field), PointCloudError> {
+        self.schema
+            .column_with_name(name)
+            .ok_or_else(|| PointCloudError::InvalidArgument(format!("no column `{name}`")))
+    }
+
+    /// Schema with the field `i` replaced or removed
+    fn replace_field(&self, i: usize, field: Option<Field>) -> SchemaRef {
+        let mut fields = self.schema.fields().to_vec();
+        match field {
+            Some(field) => fields[i] = Arc::new(field),
+            None => {
+                fields.remove(i);
+            }
+        }
+        Arc::new(Schema::new_with_metadata(
+            fields,
+            self.schema.metadata().to_owned(),
+        ))
+    }
+
+    /// Replace the columns of all batches by `f` and the schema by `schema`.
+    ///
+    /// All batches are rewritten before any is replaced, so the point cloud
+    /// is unchanged if `f` fails. Segments keep their keys and order.
+    fn rewrite(
+        &mut self,
+        schema: SchemaRef,
+        f: impl Fn(Vec<ArrayRef>) -> Result<Vec<ArrayRef>, PointCloudError>,
+    ) -> Result<(), PointCloudError> {
+        validate(&schema)?;
+
+        let entries = self
+            .store
+            .iter()
+            .map(|e| {
+                let batches = self
+                    .store
+                    .batches(e.key())
+                    .into_iter()
+                    .map(|batch| {
+                        let columns = f(batch.columns().to_vec())?;
+                        Ok(RecordBatch::try_new(schema.clone(), columns)?)
+                    })
+                    .collect::<Result<Vec<_>, PointCloudError>>()?;
+                Ok((e.key().to_owned(), batches))
+            })
+            .collect::<Result<Vec<_>, PointCloudError>>()?;
+
+        for (key, batches) in entries {
+            self.store.remove(&key)?;
+            for batch in batches {
+                self.store.push(key.clone(), batch);
+            }
+        }
+        self.schema = schema;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow::{
+        array::{AsArray, Float32Array, Float64Array, Int32Array},
+        datatypes::{Float64Type, UInt16Type, UInt8Type},
+    };
+
+    use super::*;
+    use crate::{Point, PointCloudTrait, PointTrait};
+
+    /// Points with attributes as written by other tools
+    fn cloud(classification: ArrayRef) -> ArrowPointCloud {
+        let mut fields = Point::<f64, 3>::schema().fields().to_vec();
+        fields.extend([
+            Arc::new(Field::new(
+                "Classification",
+                classification.data_type().to_owned(),
+                false,
+            )),
+            Arc::new(Field::new("Intensity", DataType::Float32, false)),
+        ]);
+        let schema = Arc::new(Schema::new(fields));
+
+        let mut pc = ArrowPointCloud::try_new(schema.clone()).unwrap();
+        for offset in [0, 2] {
+            let coordinate = || {
+                Arc::new(Float64Array::from_iter_values(
+                    (offset..offset + 2).map(f64::from),
+                )) as ArrayRef
+            };
+            let batch = RecordBatch::try_new(
+                schema.clone(),
+                vec![
+                    coordinate(),
+                    coordinate(),
+                    coordinate(),
+                    classification.slice(offset as usize, 2),
+                    Arc::new(Float32Array::from(vec![100., 200.])),
+                ],
+            )
+            .unwrap();
+            pc.append(batch).unwrap();
+        }
+        pc
+    }
+
+    #[test]
+    fn rename_and_drop() {
+        let mut pc = cloud(Arc::new(Int32Array::from(vec![2, 2, 6, 6])));
+        let keys: Vec<String> = pc.store.iter().map(|e| e.key().to_owned()).collect();
+
+        pc.rename_column("Intensity", "intensity").unwrap();
+        assert!(pc.schema().field_with_name("intensity").is_ok());
+        assert!(pc.rename_column("Intensity", "i").is_err());
+        assert!(pc.rename_column("intensity", "x").is_err());
+
+        // the metadata of dimensions is kept
+        pc.rename_column("z", "height").unwrap();
+        assert_eq!(pc.aabb::<Point<f64, 3>>().upper().z(), 3.);
+
+        pc.drop_column("intensity").unwrap();
+        assert!(pc.drop_column("x").is_err());
+        assert!(pc.drop_column("intensity").is_err());
+        assert_eq!(pc.schema().fields().len(), 4);
+
+        // all batches follow the schema, in the same segments
+        for e in pc.store.iter() {
+            for batch in pc.store.batches(e.key()) {
+                assert_eq!(batch.schema(), pc.schema());
+            }
+        }
+        let rewritten: Vec<String> = pc.store.iter().map(|e| e.key().to_owned()).collect();
+        assert_eq!(rewritten, keys);
+        assert_eq!(pc.num_points(), 4);
+    }
+
+    #[test]
+    fn cast() {
+        let mut pc = cloud(Arc::new(Int32Array::from(vec![2, 2, 6, 6])));
+        pc.cast_column("Classification", &DataType::UInt8, false)
+            .unwrap();
+        let values: Vec<u8> = pc
+            .store
+            .iter()
+            .flat_map(|e| pc.store.batches(e.key()))
+            .flat_map(|batch| {
+                batch
+                    .column(3)
+                    .as_primitive::<UInt8Type>()
+                    .values()
+                    .to_vec()
+            })
+            .collect();
+        assert_eq!(values, [2, 2, 6, 6]);
+
+        // overflow is rejected, even if lossy
+        let mut pc = cloud(Arc::new(Int32Array::from(vec![2, 2, 6, 300])));
+        let hash = pc.content_hash();
+        assert!(pc
+            .cast_column("Classification", &DataType::UInt8, true)
+            .is_err());
+        assert_eq!(pc.content_hash(), hash);
+
+        // fractions only if lossy
+        let mut pc = cloud(Arc::new(Float64Array::from(vec![2., 2.5, 6., 6.])));
+        let hash = pc.content_hash();
+        let result = pc.cast_column("Classification", &DataType::UInt8, false);
+        assert!(matches!(result, Err(PointCloudError::InvalidArgument(_))));
+        assert_eq!(pc.content_hash(), hash);
+        assert_eq!(
+            pc.schema().field(3).data_type(),
+            &DataType::Float64,
+            "unchanged after a rejected cast"
+        );
+        pc.cast_column("Classification", &DataType::UInt8, true)
+            .unwrap();
+        assert_eq!(pc.schema().field(3).data_type(), &DataType::UInt8);
+
+        assert!(pc.cast_column("none", &DataType::UInt8, true).is_err());
+    }
+
+    #[test]
+    fn normalize() {
+        let mut pc = cloud(Arc::new(Float64Array::from(vec![2., 2.5, 6., 6.])));
+        pc.normalize_columns().unwrap();
+
+        let schema = pc.schema();
+        assert_eq!(
+            schema.field_with_name("intensity").unwrap().data_type(),
+            &DataType::UInt16
+        );
+        // renamed but kept as is, the fraction would be lost
+        assert_eq!(
+            schema
+                .field_with_name("classification")
+                .unwrap()
+                .data_type(),
+            &DataType::Float64
+        );
+
+        let batch = pc.store.batches(pc.store.iter().next().unwrap().key())[0].clone();
+        let intensity = batch.column_by_name("intensity").unwrap();
+        assert_eq!(intensity.as_primitive::<UInt16Type>().values(), &[100, 200]);
+        let classification = batch.column_by_name("classification").unwrap();
+        assert_eq!(
+            classification.as_primitive::<Float64Type>().values(),
+            &[2., 2.5]
+        );
+    }
+}
@@ -1,20 +1,27 @@
-use std::sync::Arc;
+use std::{
+    ops::{Range, RangeInclusive},
+    sync::Arc,
+};
 
 use arrow::{
-    array::{as_primitive_array, AsArray, Float32Array, Float64Array, UInt32Array, UInt64Array},
+    array::{
+        as_primitive_array, new_null_array, Array, ArrayRef, AsArray, BooleanArray,
+        DictionaryArray, Float32Array, Float64Array, Int32Array, UInt32Array, UInt64Array,
+    },
     compute::{
-        and, filter_record_batch,
+        and, cast, filter_record_batch,
         kernels::cmp::{gt_eq, lt},
         max, min,
     },
-    datatypes::{DataType, Float32Type, Float64Type, Int32Type, Int64Type, SchemaRef},
+    datatypes::{
+        DataType, Field, Float32Type, Float64Type, Int32Type, Int64Type, Schema, SchemaRef,
+    },
     error::ArrowError,
     record_batch::RecordBatch,
 };
 use rand::{rngs::SmallRng, Rng, SeedableRng};
-use rstar::Envelope;
 
-use crate::{schema, PointTrait, AABB};
+use crate::{frustum::Frustum, polygon, schema, trace::span, PointCloudError, PointTrait, AABB};
 
 /// add random importance
 pub fn add_importance(batch: RecordBatch, schema: &SchemaRef) -> Result<RecordBatch, ArrowError> {
@@ -53,64 +60,127 @@ pub fn add_importance(batch: RecordBatch, schema: &SchemaRef) -> Result<RecordBa
 }
 
 /// calculate bounds
+///
+/// Points with non-finite coordinates, e.g. NaN returns of sensors, are
+/// ignored, bounds of batches without finite points are empty.
 pub fn aabb<P>(batch: &RecordBatch) -> AABB<P>
 where
     P: PointTrait,
     <P as rstar::Point>::Scalar: num_traits::NumCast,
 {
-    let aabb: AABB<P> = AABB::new_empty();
+    let _span = span!(TRACE, "aabb", rows = batch.num_rows());
 
-    if batch.num_rows() == 0 {
-        return aabb;
+    match bounds(batch, P::DIMENSIONS) {
+        Some(bounds) if bounds.iter().all(|(l, u)| l.is_finite() && u.is_finite()) => {
+            corners(&bounds)
+        }
+        Some(_) => {
+            // minimum and maximum are non-finite with any non-finite value
+            let batch = drop_nonfinite(batch).unwrap();
+            match bounds(&batch, P::DIMENSIONS) {
+                Some(bounds) => corners(&bounds),
+                None => AABB::new_empty(),
+            }
+        }
+        None => AABB::new_empty(),
     }
+}
 
-    let (mut lower, mut upper) = (aabb.lower(), aabb.upper());
+/// Minimum and maximum of the first `n` dimensions, `None` without rows
+fn bounds(batch: &RecordBatch, n: usize) -> Option<Vec<(f64, f64)>> {
+    if batch.num_rows() == 0 {
+        return None;
+    }
 
-    for (d, c) in schema::dimensions(&batch.schema())
+    let bounds = schema::dimensions(&batch.schema())
         .iter()
-        .enumerate()
-        .take(P::DIMENSIONS)
-    {
-        let column = batch.column(*c);
-        let (l, u) = match column.data_type() {
-            DataType::Int32 => {
-                let column = as_primitive_array(column);
-                (
-                    min::<Int32Type>(column).unwrap() as f64,
-                    max::<Int32Type>(column).unwrap() as f64,
-                )
-            }
-            DataType::Int64 => {
-                let column = as_primitive_array(column);
-                (
-                    min::<Int64Type>(column).unwrap() as f64,
-                    max::<Int64Type>(column).unwrap() as f64,
-                )
-            }
-            DataType::Float32 => {
-                let column = as_primitive_array(column);
-                (
-                    min::<Float32Type>(column).unwrap() as f64,
-                    max::<Float32Type>(column).unwrap() as f64,
-                )
-            }
-            DataType::Float64 => {
-                let column = as_primitive_array(column);
-                (
-                    min::<Float64Type>(column).unwrap(),
-                    max::<Float64Type>(column).unwrap(),
-                )
+        .take(n)
+        .map(|c| {
+            let column = batch.column(*c);
+            match column.data_type() {
+                DataType::Int32 => {
+                    let column = as_primitive_array(column);
+                    (
+                        min::<Int32Type>(column).unwrap() as f64,
+                        max::<Int32Type>(column).unwrap() as f64,
+                    )
+                }
+                DataType::Int64 => {
+                    let column = as_primitive_array(column);
+                    (
+                        min::<Int64Type>(column).unwrap() as f64,
+                        max::<Int64Type>(column).unwrap() as f64,
+                    )
+                }
+                DataType::Float32 => {
+                    let column = as_primitive_array(column);
+                    (
+                        min::<Float32Type>(column).unwrap() as f64,
+                        max::<Float32Type>(column).unwrap() as f64,
+                    )
+                }
+                DataType::Float64 => {
+                    let column = as_primitive_array(column);
+                    (
+                        min::<Float64Type>(column).unwrap(),
+                        max::<Float64Type>(column).unwrap(),
+                    )
+                }
+                _ => unimplemented!(),
             }
-            _ => unimplemented!(),
-        };
+        })
+        .collect();
+
+    Some(bounds)
+}
 
-        *rstar::Point::nth_mut(&mut lower, d) = num_traits::cast(l).unwrap();
-        *rstar::Point::nth_mut(&mut upper, d) = num_traits::cast(u).unwrap();
+pub(crate) fn corners<P>(bounds: &[(f64, f64)]) -> AABB<P>
+where
+    P: PointTrait,
+    <P as rstar::Point>::Scalar: num_traits::NumCast,
+{
+    let aabb: AABB<P> = AABB::new_empty();
+    let (mut lower, mut upper) = (aabb.lower(), aabb.upper());
+
+    for (d, (l, u)) in bounds.iter().enumerate() {
+        *rstar::Point::nth_mut(&mut lower, d) = num_traits::cast(*l).unwrap();
+        *rstar::Point::nth_mut(&mut upper, d) = num_traits::cast(*u).unwrap();
     }
 
     AABB::from_corners(lower, upper)
 }
 
+/// Whether the coordinates of the points are finite, `true` for integer and
+/// null coordinates. The importance is not considered.
+pub fn finite_mask(batch: &RecordBatch) -> Result<BooleanArray, ArrowError> {
+    let mut mask = vec![true; batch.num_rows()];
+
+    for c in schema::coordinates(&batch.schema()) {
+        let column = batch.column(c);
+        let column = match column.data_type() {
+            DataType::Float32 | DataType::Float64 => cast(column, &DataType::Float64)?,
+            _ => continue,
+        };
+        for (valid, v) in mask
+            .iter_mut()
+            .zip(column.as_primitive::<Float64Type>().iter())
+        {
+            *valid &= v.is_none_or(f64::is_finite);
+        }
+    }
+
+    Ok(BooleanArray::from(mask))
+}
+
+/// Drop points with non-finite coordinates, see [finite_mask]
+pub fn drop_nonfinite(batch: &RecordBatch) -> Result<RecordBatch, ArrowError> {
+    let mask = finite_mask(batch)?;
+    if mask.true_count() == batch.num_rows() {
+        return Ok(batch.to_owned());
+    }
+    filter_record_batch(batch, &mask)
+}
+
 // filter by bounds
 #[inline]
 pub fn filter_by_aabb<P>(batch: &RecordBatch, aabb: &AABB<P>) -> RecordBatch
@@ -172,3 +242,284 @@ where
 
     filter_record_batch(batch, &filter.unwrap()).unwrap()
 }
+
+/// filter by polygon footprint and optional height range
+pub fn filter_by_polygon(
+    batch: &RecordBatch,
+    ring: &[[f64; 2]],
+    z_range: Option<&RangeInclusive<f64>>,
+) -> Result<RecordBatch, ArrowError> {
+    let dimensions = schema::dimensions(&batch.schema());
+
+    let columns = dimensions
+        .iter()
+        .take(if z_range.is_some() { 3 } else { 2 })
+        .map(|c| cast(batch.column(*c), &DataType::Float64))
+        .collect::<Result<Vec<_>, _>>()?;
+    let columns: Vec<&Float64Array> = columns.iter().map(|c| c.as_primitive()).collect();
+
+    // points with null coordinates never match
+    let filter: BooleanArray = (0..batch.num_rows())
+        .map(|i| {
+            let inside = columns.iter().all(|c| c.is_valid(i))
+                && z_range.is_none_or(|z| z.contains(&columns[2].value(i)))
+                && polygon::contains(ring, [columns[0].value(i), columns[1].value(i)]);
+            Some(inside)
+        })
+        .collect();
+
+    filter_record_batch(batch, &filter)
+}
+
+/// filter by view frustum
+pub fn filter_by_frustum(
+    batch: &RecordBatch,
+    frustum: &Frustum,
+) -> Result<RecordBatch, ArrowError> {
+    let columns = schema::dimensions(&batch.schema())
+        .iter()
+        .take(3)
+        .map(|c| cast(batch.column(*c), &DataType::Float64))
+        .collect::<Result<Vec<_>, _>>()?;
+    let columns: Vec<&Float64Array> = columns.iter().map(|c| c.as_primitive()).collect();
+
+    // points with null coordinates never match
+    let filter: BooleanArray = (0..batch.num_rows())
+        .map(|i| {
+            let inside = columns.iter().all(|c| c.is_valid(i))
+                && frustum.contains(std::array::from_fn(|d| columns[d].value(i)));
+            Some(inside)
+        })
+        .collect();
+
+    filter_record_batch(batch, &filter)
+}
+
+/// Reject radius queries with a center of other than two or three
+/// dimensions, non-finite coordinates or a negative radius
+pub(crate) fn validate_distance(center: &[f64], radius: f64) -> Result<(), PointCloudError> {
+    if !(2..=3).contains(&center.len()) {
+        return Err(PointCloudError::InvalidArgument(format!(
+            "center requires 2 or 3 coordinates, got {}",
+            center.len()
+        )));
+    }
+    if !(center.iter().all(|c| c.is_finite()) && radius.is_finite() && radius >= 0.) {
+        return Err(PointCloudError::InvalidArgument(format!(
+            "invalid center {center:?} or radius {radius}"
+        )));
+    }
+    Ok(())
+}
+
+/// filter by distance to `center`, in as many dimensions as `center` has
+pub fn filter_by_distance(
+    batch: &RecordBatch,
+    center: &[f64],
+    radius: f64,
+) -> Result<RecordBatch, ArrowError> {
+    let columns = schema::dimensions(&batch.schema())
+        .iter()
+        .take(center.len())
+        .map(|c| cast(batch.column(*c), &DataType::Float64))
+        .collect::<Result<Vec<_>, _>>()?;
+    let columns: Vec<&Float64Array> = columns.iter().map(|c| c.as_primitive()).collect();
+
+    // squared distances, points with null coordinates never match
+    let radius2 = radius * radius;
+    let filter: BooleanArray = (0..batch.num_rows())
+        .map(|i| {
+            let inside = columns.iter().all(|c| c.is_valid(i))
+                && columns
+                    .iter()
+                    .zip(center)
+                    .map(|(c, x)| (c.value(i) - x).powi(2))
+                    .sum::<f64>()
+                    <= radius2;
+            Some(inside)
+        })
+        .collect();
+
+    filter_record_batch(batch, &filter)
+}
+
+/// filter by the values of `column` within `[range.start, range.end)`, null
+/// and NaN values never match
+pub fn filter_by_range(
+    batch: &RecordBatch,
+    column: &str,
+    range: &Range<f64>,
+) -> Result<RecordBatch, ArrowError> {
+    let values = batch
+        .column_by_name(column)
+        .ok_or_else(|| ArrowError::SchemaError(format!("no column `{column}`")))?;
+    let values = crate::encoding::cast(values, &DataType::Float64)?;
+
+    let filter: BooleanArray = values
+        .as_primitive::<Float64Type>()
+        .iter()
+        .map(|v| Some(v.is_some_and(|v| range.contains(&v))))
+        .collect();
+
+    filter_record_batch(batch, &filter)
+}
+
+/// Columns of `batch` in the layout of `schema`, null for the columns it
+/// lacks, see [schema::union]
+pub fn conform(batch: &RecordBatch, schema: &SchemaRef) -> Result<RecordBatch, ArrowError> {
+    if batch.schema() == *schema {
+        return Ok(batch.to_owned());
+    }
+
+    let columns = schema
+        .fields()
+        .iter()
+        .map(|field| match batch.column_by_name(field.name()) {
+            Some(column) => column.to_owned(),
+            None => new_null_array(field.data_type(), batch.num_rows()),
+        })
+        .collect();
+
+    RecordBatch::try_new(schema.to_owned(), columns)
+}
+
+/// Append a column `name` of `values[key]` for all rows, dictionary encoded
+/// so that the values are shared rather than repeated per row
+pub fn append_constant_dictionary(
+    batch: &RecordBatch,
+    name: &str,
+    values: &ArrayRef,
+    key: usize,
+) -> Result<RecordBatch, ArrowError> {
+    if key >= values.len() {
+        return Err(ArrowError::InvalidArgumentError(format!(
+            "key {key} out of {} dictionary values",
+            values.len()
+        )));
+    }
+    let keys = Int32Array::from_value(key as i32, batch.num_rows());
+    let column = DictionaryArray::try_new(keys, values.to_owned())?;
+
+    let schema = batch.schema();
+    let mut fields = schema.fields().to_vec();
+    fields.push(Arc::new(Field::new(
+        name,
+        column.data_type().to_owned(),
+        false,
+    )));
+    let mut columns = batch.columns().to_vec();
+    columns.push(Arc::new(column));
+
+    RecordBatch::try_new(
+        Arc::new(Schema::new_with_metadata(
+            fields,
+            schema.metadata().to_owned(),
+        )),
+        columns,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow::array::StringArray;
+
+    use super::*;
+    use crate::{MetadataPolicy, Point};
+
+    #[test]
+    fn polygon_nulls() {
+        // nullable dimensions
+        let fields: Vec<Field> = Point::<f64, 3>::schema()
+            .fields()
+            .iter()
+            .map(|field| field.as_ref().clone().with_nullable(true))
+            .collect();
+        let schema = Arc::new(Schema::new_with_metadata(
+            fields,
+            Point::<f64, 3>::schema().metadata().clone(),
+        ));
+        let column = |v: [Option<f64>; 4]| Arc::new(Float64Array::from(v.to_vec())) as ArrayRef;
+        let batch = RecordBatch::try_new(
+            schema,
+            vec![
+                column([Some(0.5), None, Some(0.5), Some(0.5)]),
+                column([Some(0.5), Some(0.5), Some(0.5), Some(0.5)]),
+                column([Some(1.), Some(1.), None, Some(2.)]),
+            ],
+        )
+        .unwrap();
+        let square = [[0., 0.], [1., 0.], [1., 1.], [0., 1.]];
+
+        // null coordinates never match
+        assert_eq!(
+            filter_by_polygon(&batch, &square, None).unwrap().num_rows(),
+            3
+        );
+        let filtered = filter_by_polygon(&batch, &square, Some(&(0. ..=10.))).unwrap();
+        assert_eq!(filtered.num_rows(), 2);
+        assert_eq!(filtered.column(2).null_count(), 0);
+    }
+
+    #[test]
+    fn nonfinite_bounds() {
+        let schema = Point::<f64, 3>::schema();
+        let column = |v: [f64; 4]| Arc::new(Float64Array::from(v.to_vec())) as ArrayRef;
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                column([f64::NAN, 1., 2., 3.]),
+                column([0., 1., f64::INFINITY, 3.]),
+                column([0., -1., 1., f64::NEG_INFINITY]),
+            ],
+        )
+        .unwrap();
+
+        let mask = finite_mask(&batch).unwrap();
+        assert_eq!(mask, BooleanArray::from(vec![false, true, false, false]));
+        assert_eq!(drop_nonfinite(&batch).unwrap().num_rows(), 1);
+
+        // non-finite points are ignored
+        let bounds: AABB<Point<f64, 3>> = aabb(&batch);
+        assert_eq!(bounds.lower().coords(), &[1., 1., -1.]);
+        assert_eq!(bounds.upper().coords(), &[1., 1., -1.]);
+
+        // bounds without finite points are empty
+        let batch = batch.slice(2, 2);
+        let bounds: AABB<Point<f64, 3>> = aabb(&batch);
+        assert_eq!(bounds, AABB::new_empty());
+    }
+
+    #[test]
+    fn label_batches() {
+        let xyz = Point::<f64, 3>::schema();
+        let batch = |n: usize| {
+            let column = Arc::new(Float64Array::from(vec![1.; n])) as ArrayRef;
+            RecordBatch::try_new(xyz.clone(), vec![column.clone(), column.clone(), column]).unwrap()
+        };
+        let mut fields = xyz.fields().to_vec();
+        fields.push(Arc::new(Field::new("intensity", DataType::UInt16, false)));
+        let intensity = Arc::new(Schema::new_with_metadata(fields, xyz.metadata().clone()));
+
+        // nulls for the missing column
+        let union = schema::union(&[xyz.clone(), intensity], MetadataPolicy::Strict).unwrap();
+        let conformed = conform(&batch(3), &union).unwrap();
+        assert_eq!(conformed.schema(), union);
+        assert_eq!(conformed.column(3).null_count(), 3);
+        assert_eq!(conform(&batch(3), &xyz).unwrap(), batch(3));
+
+        // one dictionary shared by all batches
+        let names = Arc::new(StringArray::from(vec!["epoch1", "epoch2"])) as ArrayRef;
+        let labeled = append_constant_dictionary(&batch(4), "collection", &names, 1).unwrap();
+        assert_eq!(labeled.num_columns(), 4);
+        assert_eq!(
+            labeled.schema().field(3).data_type(),
+            &DataType::Dictionary(Box::new(DataType::Int32), Box::new(DataType::Utf8))
+        );
+        let column = labeled.column(3).as_dictionary::<Int32Type>();
+        assert_eq!(column.keys().values().as_ref(), [1, 1, 1, 1]);
+        assert!(Arc::ptr_eq(column.values(), &names));
+        assert_eq!(labeled.schema().metadata(), xyz.metadata());
+
+        assert!(append_constant_dictionary(&batch(1), "collection", &names, 2).is_err());
+    }
+}
@@ -0,0 +1,219 @@
+use crate::{
+    progress::Tracker, ArrowPointCloud, Point, PointCloudError, PointCloudTrait, PointTrait,
+    ProgressSink, AABB,
+};
+
+/// Gap in the coverage of a point cloud
+pub type Gap = AABB<Point<f64, 2>>;
+
+/// Occupancy raster of the points in the horizontal plane
+struct Occupancy {
+    /// Corner of the first cell, a multiple of the cell size
+    lower: [f64; 2],
+    cell: f64,
+    nx: usize,
+    ny: usize,
+    occupied: Vec<bool>,
+}
+
+impl Occupancy {
+    /// Empty cells grouped into regions connected by their edges, regions
+    /// touching the border of the raster are open to the outside and left out
+    fn enclosed(&self) -> Vec<Vec<usize>> {
+        let (nx, ny) = (self.nx, self.ny);
+        let mut visited = self.occupied.clone();
+        let mut regions = Vec::new();
+
+        for start in 0..nx * ny {
+            if visited[start] {
+                continue;
+            }
+            visited[start] = true;
+
+            let mut region = vec![start];
+            let mut open = false;
+            let mut k = 0;
+            while k < region.len() {
+                let i = region[k];
+                k += 1;
+
+                let (x, y) = (i % nx, i / nx);
+                open |= x == 0 || y == 0 || x == nx - 1 || y == ny - 1;
+                let neighbours = [
+                    (x > 0).then(|| i - 1),
+                    (x + 1 < nx).then(|| i + 1),
+                    (y > 0).then(|| i - nx),
+                    (y + 1 < ny).then(|| i + nx),
+                ];
+                for j in neighbours.into_iter().flatten() {
+                    if !visited[j] {
+                        visited[j] = true;
+                        region.push(j);
+                    }
+                }
+            }
+
+            if !open {
+                regions.push(region);
+            }
+        }
+
+        regions
+    }
+
+    /// Bounds of the cells of a region
+    fn bounds(&self, region: &[usize]) -> Gap {
+        let (mut x0, mut y0, mut x1, mut y1) = (usize::MAX, usize::MAX, 0, 0);
+        for i in region {
+            let (x, y) = (i % self.nx, i / self.nx);
+            (x0, y0, x1, y1) = (x0.min(x), y0.min(y), x1.max(x), y1.max(y));
+        }
+
+        let corner = |x: usize, y: usize| {
+            Point::from_slice(&[
+                self.lower[0] + x as f64 * self.cell,
+                self.lower[1] + y as f64 * self.cell,
+            ])
+        };
+        AABB::from_corners(corner(x0, y0), corner(x1 + 1, y1 + 1))
+    }
+}
+
+impl ArrowPointCloud {
+    /// Areas without points inside the covered region, as bounding boxes.
+    ///
+    /// The points are rasterized into square cells of size `cell`, aligned to
+    /// the origin of the coordinates. Empty cells connected by their edges
+    /// form a gap if they are enclosed by cells with points and number at
+    /// least `min_gap_cells`, empty regions reaching the border of the points
+    /// are outside of the footprint. Gaps are ordered by their first cell from
+    /// south-west to north-east, there are none for an invalid cell size, see
+    /// [ArrowPointCloud::coverage_gaps_with].
+    pub fn coverage_gaps(&self, cell: f64, min_gap_cells: usize) -> Vec<Gap> {
+        self.coverage_gaps_with(cell, min_gap_cells, &())
+            .unwrap_or_default()
+    }
+
+    /// [ArrowPointCloud::coverage_gaps] reporting progress per segment, fails
+    /// for a cell size that is not positive or too small for the extent
+    pub fn coverage_gaps_with(
+        &self,
+        cell: f64,
+        min_gap_cells: usize,
+        progress: &dyn ProgressSink,
+    ) -> Result<Vec<Gap>, PointCloudError> {
+        if cell.is_nan() || cell <= 0. {
+            return Err(PointCloudError::InvalidArgument(
+                "cell size must be positive".to_string(),
+            ));
+        }
+        if self.num_points() == 0 {
+            return Ok(Vec::new());
+        }
+
+        let aabb = self.aabb::<Point<f64, 3>>();
+        let (min, max) = (aabb.lower(), aabb.upper());
+        let lower = [
+            (min.x() / cell).floor() * cell,
+            (min.y() / cell).floor() * cell,
+        ];
+        // points on the upper bounds belong to a cell of their own
+        let nx = ((max.x() - lower[0]) / cell).floor() as usize + 1;
+        let ny = ((max.y() - lower[1]) / cell).floor() as usize + 1;
+        if !(lower[0].is_finite() && lower[1].is_finite()) || nx.saturating_mul(ny) > 1 << 28 {
+            return Err(PointCloudError::InvalidArgument(format!(
+                "cell size {cell} is too small for the extent"
+            )));
+        }
+
+        let mut occupied = vec![false; nx * ny];
+        let tracker = Tracker::new(progress, self.store.len());
+        self.visit_points(&tracker, |p| {
+            let (x, y) = ((p.x() - lower[0]) / cell, (p.y() - lower[1]) / cell);
+            if x.is_finite() && y.is_finite() {
+                occupied[(y as usize).min(ny - 1) * nx + (x as usize).min(nx - 1)] = true;
+            }
+        })?;
+
+        let occupancy = Occupancy {
+            lower,
+            cell,
+            nx,
+            ny,
+            occupied,
+        };
+        Ok(occupancy
+            .enclosed()
+            .into_iter()
+            .filter(|region| region.len() >= min_gap_cells)
+            .map(|region| occupancy.bounds(&region))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::synthetic::Synthetic;
+
+    /// Uniform points over [0, 100]² with the points in `holes` removed
+    fn punched(holes: &[([f64; 2], [f64; 2])]) -> ArrowPointCloud {
+        let pc = Synthetic::new(100_000)
+            .seed(7)
+            .extent([0., 0., 0.], [100., 100., 5.])
+            .uniform()
+            .unwrap();
+        ArrowPointCloud::from_iter(pc.points::<Point<f64, 3>>().filter(|p| {
+            !holes.iter().any(|(lower, upper)| {
+                (lower[0]..upper[0]).contains(&p.x()) && (lower[1]..upper[1]).contains(&p.y())
+            })
+        }))
+        .unwrap()
+    }
+
+    fn corners(gap: &Gap) -> [[f64; 2]; 2] {
+        [
+            [gap.lower().x(), gap.lower().y()],
+            [gap.upper().x(), gap.upper().y()],
+        ]
+    }
+
+    #[test]
+    fn hole() {
+        let pc = punched(&[([40., 20.], [50., 26.])]);
+
+        let gaps = pc.coverage_gaps(2., 4);
+        assert_eq!(
+            gaps.iter().map(corners).collect::<Vec<_>>(),
+            [[[40., 20.], [50., 26.]]]
+        );
+
+        // 5 x 3 cells
+        assert_eq!(pc.coverage_gaps(2., 15).len(), 1);
+        assert!(pc.coverage_gaps(2., 16).is_empty());
+        // the hole is no gap if a cell is larger
+        assert!(pc.coverage_gaps(20., 1).is_empty());
+    }
+
+    #[test]
+    fn outside() {
+        // a notch at the border and an L-shaped hole of two rectangles
+        let pc = punched(&[
+            ([0., 60.], [10., 70.]),
+            ([60., 60.], [70., 80.]),
+            ([70., 60.], [80., 66.]),
+        ]);
+
+        let gaps = pc.coverage_gaps(2., 4);
+        assert_eq!(
+            gaps.iter().map(corners).collect::<Vec<_>>(),
+            [[[60., 60.], [80., 80.]]]
+        );
+
+        assert!(pc.coverage_gaps_with(0., 4, &()).is_err());
+        assert!(pc.coverage_gaps_with(1e-6, 4, &()).is_err());
+        assert!(pc.coverage_gaps(f64::NAN, 4).is_empty());
+        let empty = ArrowPointCloud::from_iter(std::iter::empty::<Point<f64, 3>>()).unwrap();
+        assert!(empty.coverage_gaps(1., 1).is_empty());
+    }
+}
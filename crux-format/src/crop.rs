@@ -0,0 +1,187 @@
+use std::collections::{btree_map::Entry, BTreeMap};
+
+use arrow::{
+    array::{Array, AsArray, UInt32Array},
+    compute::{cast, take},
+    datatypes::{DataType, Float64Type},
+    record_batch::RecordBatch,
+};
+
+use crate::{schema::dimensions, ArrowPointCloud, PointCloudError, PointCloudTrait};
+
+/// Tile of a regular grid of square tiles, `column` counts along x and `row`
+/// along y from the origin of the grid
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct GridTileId {
+    pub column: i64,
+    pub row: i64,
+}
+
+impl GridTileId {
+    /// Tile containing `x`, `y`, `None` if a coordinate is not finite
+    pub fn of(origin: [f64; 2], tile_size: f64, x: f64, y: f64) -> Option<Self> {
+        let column = ((x - origin[0]) / tile_size).floor();
+        let row = ((y - origin[1]) / tile_size).floor();
+        (column.is_finite() && row.is_finite()).then_some(Self {
+            column: column as i64,
+            row: row as i64,
+        })
+    }
+
+    /// Lower and upper XY corner, the tile holds points in `[lower, upper)`
+    pub fn bounds(&self, origin: [f64; 2], tile_size: f64) -> ([f64; 2], [f64; 2]) {
+        let lower = [
+            origin[0] + self.column as f64 * tile_size,
+            origin[1] + self.row as f64 * tile_size,
+        ];
+        (lower, [lower[0] + tile_size, lower[1] + tile_size])
+    }
+}
+
+impl ArrowPointCloud {
+    /// Split into the tiles of a grid of `tile_size` starting at `origin`,
+    /// with all attributes, in order of the tile ids.
+    ///
+    /// Tiles are half-open, points on a tile border belong to the tile above
+    /// or right of it, so each point is in exactly one tile. Points without
+    /// finite XY coordinates are in no tile.
+    pub fn crop_tiles(
+        &self,
+        origin: [f64; 2],
+        tile_size: f64,
+    ) -> Result<impl Iterator<Item = (GridTileId, ArrowPointCloud)>, PointCloudError> {
+        if !(tile_size.is_finite() && tile_size > 0.) {
+            return Err(PointCloudError::InvalidArgument(format!(
+                "tile size must be positive, got {tile_size}"
+            )));
+        }
+
+        let schema = self.schema();
+        let dimensions = dimensions(&schema);
+        let mut tiles: BTreeMap<GridTileId, ArrowPointCloud> = BTreeMap::new();
+
+        for e in self.store.iter() {
+            for batch in self.store.batches(e.key()) {
+                let xy = dimensions[..2]
+                    .iter()
+                    .map(|c| cast(batch.column(*c), &DataType::Float64))
+                    .collect::<Result<Vec<_>, _>>()?;
+                let x = xy[0].as_primitive::<Float64Type>();
+                let y = xy[1].as_primitive::<Float64Type>();
+
+                // rows per tile, in order of the batch
+                let mut rows: BTreeMap<GridTileId, Vec<u32>> = BTreeMap::new();
+                for i in 0..batch.num_rows() {
+                    let tile = (x.is_valid(i) && y.is_valid(i))
+                        .then(|| GridTileId::of(origin, tile_size, x.value(i), y.value(i)))
+                        .flatten();
+                    if let Some(tile) = tile {
+                        rows.entry(tile).or_default().push(i as u32);
+                    }
+                }
+
+                for (tile, rows) in rows {
+                    let pc = match tiles.entry(tile) {
+                        Entry::Occupied(entry) => entry.into_mut(),
+                        Entry::Vacant(entry) => {
+                            entry.insert(ArrowPointCloud::try_new(schema.clone())?)
+                        }
+                    };
+                    pc.append(take_rows(&batch, rows)?)?;
+                }
+            }
+        }
+
+        Ok(tiles.into_iter())
+    }
+}
+
+fn take_rows(batch: &RecordBatch, rows: Vec<u32>) -> Result<RecordBatch, PointCloudError> {
+    let indices = UInt32Array::from(rows);
+    let columns = batch
+        .columns()
+        .iter()
+        .map(|column| take(column, &indices, None))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(RecordBatch::try_new(batch.schema(), columns)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::SmallRng, Rng, SeedableRng};
+
+    use super::*;
+    use crate::{Point, PointTrait};
+
+    #[test]
+    fn tile_of() {
+        let origin = [100., 200.];
+        let tile = |x, y| GridTileId::of(origin, 10., x, y).unwrap();
+
+        assert_eq!(tile(100., 200.), GridTileId { column: 0, row: 0 });
+        assert_eq!(tile(109.99, 209.99), GridTileId { column: 0, row: 0 });
+        // borders belong to the next tile
+        assert_eq!(tile(110., 200.), GridTileId { column: 1, row: 0 });
+        assert_eq!(tile(99.99, 220.), GridTileId { column: -1, row: 2 });
+        assert_eq!(GridTileId::of(origin, 10., f64::NAN, 0.), None);
+
+        let (lower, upper) = GridTileId { column: -1, row: 2 }.bounds(origin, 10.);
+        assert_eq!(lower, [90., 220.]);
+        assert_eq!(upper, [100., 230.]);
+    }
+
+    #[test]
+    fn crop_tiles() {
+        let mut rng = SmallRng::seed_from_u64(3);
+        let mut pc = ArrowPointCloud::try_new(Point::<f64, 3>::schema()).unwrap();
+        for _ in 0..5 {
+            let batch = ArrowPointCloud::from_iter((0..2000).map(|i| {
+                // some points on the tile borders
+                let x = if i % 10 == 0 {
+                    (rng.gen_range(0..10) * 25) as f64
+                } else {
+                    rng.gen_range(-10. ..240.)
+                };
+                Point::<f64, 3>::from_slice(&[x, rng.gen_range(0. ..100.), rng.gen()])
+            }))
+            .unwrap();
+            for e in batch.store.iter() {
+                for b in batch.store.batches(e.key()) {
+                    pc.append(b).unwrap();
+                }
+            }
+        }
+
+        let tiles: Vec<_> = pc.crop_tiles([0., 0.], 25.).unwrap().collect();
+
+        // all points in exactly one tile
+        let total: usize = tiles.iter().map(|(_, tile)| tile.num_points()).sum();
+        assert_eq!(total, pc.num_points());
+        assert_eq!(total, 10_000);
+
+        for (id, tile) in &tiles {
+            let (lower, upper) = id.bounds([0., 0.], 25.);
+            assert!(tile.num_points() > 0);
+            assert!(tile.points::<Point<f64, 3>>().all(|p| {
+                let [x, y, _] = p.coords()[..] else {
+                    unreachable!()
+                };
+                x >= lower[0] && x < upper[0] && y >= lower[1] && y < upper[1]
+            }));
+        }
+
+        // tiles ordered by id, left of the origin first
+        assert_eq!(tiles[0].0, GridTileId { column: -1, row: 0 });
+        assert!(tiles.windows(2).all(|w| w[0].0 < w[1].0));
+
+        // deterministic
+        let again: Vec<_> = pc.crop_tiles([0., 0.], 25.).unwrap().collect();
+        assert!(tiles
+            .iter()
+            .zip(&again)
+            .all(|((a, pa), (b, pb))| a == b && pa.content_hash() == pb.content_hash()));
+
+        assert!(pc.crop_tiles([0., 0.], 0.).is_err());
+    }
+}
@@ -0,0 +1,247 @@
+use std::{collections::HashMap, sync::Arc};
+
+use arrow::{
+    array::{ArrayRef, Float64Array, Int8Array},
+    datatypes::{DataType, Field, Schema, SchemaRef},
+    record_batch::RecordBatch,
+};
+use itertools::Itertools;
+
+use crate::{
+    progress::Tracker, ArrowPointCloud, Point, PointCloudError, PointCloudTrait, PointTrait,
+    ProgressSink,
+};
+
+/// Name of the signed height difference column
+pub const DELTA_COLUMN: &str = "delta";
+
+/// Name of the presence change column
+pub const CHANGE_COLUMN: &str = "change";
+
+/// Cell is occupied in both clouds
+pub const CHANGE_NONE: i8 = 0;
+/// Cell is only occupied in the second cloud
+pub const CHANGE_ADDED: i8 = 1;
+/// Cell is only occupied in the first cloud
+pub const CHANGE_REMOVED: i8 = -1;
+
+/// Schema of a diff point cloud
+pub fn schema() -> SchemaRef {
+    let mut fields = Point::<f64, 3>::schema().fields().to_vec();
+    fields.extend([
+        Arc::new(Field::new(DELTA_COLUMN, DataType::Float64, true)),
+        Arc::new(Field::new(CHANGE_COLUMN, DataType::Int8, false)),
+    ]);
+    Arc::new(Schema::new(fields))
+}
+
+/// Compare two epochs of the same area on a regular XY grid.
+///
+/// Each cell holds the surface height (maximum z) of either cloud. For cells
+/// occupied in both, `delta` is the signed difference `b - a`. Cells occupied
+/// in only one cloud are flagged in the `change` column with a null `delta`.
+/// Only cells within the intersection of both XY extents are reported, one
+/// point per cell located at the cell center.
+pub fn diff(
+    a: &ArrowPointCloud,
+    b: &ArrowPointCloud,
+    cell: f64,
+) -> Result<ArrowPointCloud, PointCloudError> {
+    diff_with(a, b, cell, &())
+}
+
+/// [diff] reporting progress per segment of both clouds
+pub fn diff_with(
+    a: &ArrowPointCloud,
+    b: &ArrowPointCloud,
+    cell: f64,
+    progress: &dyn ProgressSink,
+) -> Result<ArrowPointCloud, PointCloudError> {
+    if !(cell.is_finite() && cell > 0.) {
+        return Err(PointCloudError::InvalidArgument(format!(
+            "cell size must be positive, got `{cell}`"
+        )));
+    }
+
+    let schema = schema();
+    let mut pc = ArrowPointCloud::try_new(schema.clone())?;
+
+    if a.num_points() == 0 || b.num_points() == 0 {
+        return Ok(pc);
+    }
+
+    // intersection of the xy extents
+    let (ea, eb) = (a.aabb::<Point<f64, 3>>(), b.aabb::<Point<f64, 3>>());
+    let lower = [
+        ea.lower().x().max(eb.lower().x()),
+        ea.lower().y().max(eb.lower().y()),
+    ];
+    let upper = [
+        ea.upper().x().min(eb.upper().x()),
+        ea.upper().y().min(eb.upper().y()),
+    ];
+
+    if lower[0] > upper[0] || lower[1] > upper[1] {
+        return Ok(pc);
+    }
+
+    let tracker = Tracker::new(progress, a.store.len() + b.store.len());
+    let (sa, sb) = (surface(a, cell, &tracker)?, surface(b, cell, &tracker)?);
+
+    let mut x = Vec::new();
+    let mut y = Vec::new();
+    let mut z = Vec::new();
+    let mut delta = Vec::new();
+    let mut change = Vec::new();
+
+    for key in sa.keys().chain(sb.keys()).unique().sorted() {
+        let cx = (key.0 as f64 + 0.5) * cell;
+        let cy = (key.1 as f64 + 0.5) * cell;
+
+        if cx < lower[0] || cx > upper[0] || cy < lower[1] || cy > upper[1] {
+            continue;
+        }
+
+        let (h, d, c) = match (sa.get(key), sb.get(key)) {
+            (Some(za), Some(zb)) => (*zb, Some(zb - za), CHANGE_NONE),
+            (None, Some(zb)) => (*zb, None, CHANGE_ADDED),
+            (Some(za), None) => (*za, None, CHANGE_REMOVED),
+            (None, None) => unreachable!(),
+        };
+
+        x.push(cx);
+        y.push(cy);
+        z.push(h);
+        delta.push(d);
+        change.push(c);
+    }
+
+    if !x.is_empty() {
+        let batch = RecordBatch::try_new(
+            schema,
+            vec![
+                Arc::new(Float64Array::from(x)) as ArrayRef,
+                Arc::new(Float64Array::from(y)),
+                Arc::new(Float64Array::from(z)),
+                Arc::new(Float64Array::from(delta)),
+                Arc::new(Int8Array::from(change)),
+            ],
+        )?;
+        pc.append(batch)?;
+    }
+
+    Ok(pc)
+}
+
+/// maximum height per grid cell
+fn surface(
+    pc: &ArrowPointCloud,
+    cell: f64,
+    tracker: &Tracker,
+) -> Result<HashMap<(i64, i64), f64>, PointCloudError> {
+    let mut cells: HashMap<(i64, i64), f64> = HashMap::new();
+
+    pc.visit_points(tracker, |p| {
+        let key = ((p.x() / cell).floor() as i64, (p.y() / cell).floor() as i64);
+        cells
+            .entry(key)
+            .and_modify(|z| *z = z.max(p.z()))
+            .or_insert(p.z());
+    })?;
+
+    Ok(cells)
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow::{
+        array::{Array, AsArray},
+        datatypes::{Float64Type, Int8Type},
+    };
+
+    use super::*;
+
+    fn grid(size: usize, height: impl Fn(f64, f64) -> f64) -> ArrowPointCloud {
+        ArrowPointCloud::from_iter((0..size * size).map(|i| {
+            let x = (i % size) as f64 + 0.5;
+            let y = (i / size) as f64 + 0.5;
+            Point::<f64, 3>::from_slice(&[x, y, height(x, y)])
+        }))
+        .unwrap()
+    }
+
+    fn rows(pc: &ArrowPointCloud) -> Vec<(f64, f64, Option<f64>, i8)> {
+        pc.store
+            .iter()
+            .flat_map(|e| pc.store.batches(e.key()))
+            .flat_map(|batch| {
+                let x = batch.column(0).as_primitive::<Float64Type>().clone();
+                let y = batch.column(1).as_primitive::<Float64Type>().clone();
+                let d = batch.column(3).as_primitive::<Float64Type>().clone();
+                let c = batch.column(4).as_primitive::<Int8Type>().clone();
+                (0..batch.num_rows())
+                    .map(|i| {
+                        let d = d.is_valid(i).then(|| d.value(i));
+                        (x.value(i), y.value(i), d, c.value(i))
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn building_appeared() {
+        let before = grid(10, |_, _| 0.);
+        let after = grid(10, |x, y| {
+            if (2. ..4.).contains(&x) && (2. ..4.).contains(&y) {
+                10.
+            } else {
+                0.
+            }
+        });
+
+        let pc = diff(&before, &after, 1.).unwrap();
+        assert_eq!(pc.num_points(), 100);
+
+        for (x, y, d, c) in rows(&pc) {
+            assert_eq!(c, CHANGE_NONE);
+            if (2. ..4.).contains(&x) && (2. ..4.).contains(&y) {
+                assert_eq!(d, Some(10.), "cell ({x}, {y})");
+            } else {
+                assert_eq!(d, Some(0.), "cell ({x}, {y})");
+            }
+        }
+    }
+
+    #[test]
+    fn presence() {
+        // second epoch has a gap and an additional row
+        let before = grid(4, |_, _| 0.);
+        let after = ArrowPointCloud::from_iter(
+            (0..20)
+                .map(|i| ((i % 4) as f64 + 0.5, (i / 4) as f64 + 0.5))
+                .filter(|(x, y)| !(*x == 1.5 && *y == 1.5))
+                .map(|(x, y)| Point::<f64, 3>::from_slice(&[x, y, 1.])),
+        )
+        .unwrap();
+
+        let rows = rows(&diff(&before, &after, 1.).unwrap());
+
+        // additional row lies outside of the common extent
+        assert_eq!(rows.len(), 16);
+        assert!(rows.iter().any(|r| r == &(1.5, 1.5, None, CHANGE_REMOVED)));
+        assert_eq!(rows.iter().filter(|r| r.3 == CHANGE_NONE).count(), 15);
+    }
+
+    #[test]
+    fn disjoint() {
+        let a = grid(4, |_, _| 0.);
+        let b = ArrowPointCloud::from_iter(
+            (0..4).map(|i| Point::<f64, 3>::from_slice(&[100. + i as f64, 100., 0.])),
+        )
+        .unwrap();
+
+        assert_eq!(diff(&a, &b, 1.).unwrap().num_points(), 0);
+        assert!(diff(&a, &b, 0.).is_err());
+    }
+}
@@ -0,0 +1,456 @@
+//! Summary digests of point clouds.
+//!
+//! A [CloudDigest] tells whether two point clouds hold the same data without
+//! comparing them point by point. Unlike [ArrowPointCloud::content_hash], the
+//! hashes cover the values in row order, so that the same points in batches
+//! of different sizes have the same digest.
+//!
+//! [summarize_diff] tells how two digests differ, e.g. to gate pipelines on
+//! whether the data changed, without the spatial comparison of [crate::diff].
+
+use std::{collections::BTreeMap, fmt, hash::Hasher};
+
+use arrow::{
+    array::{Array, ArrayData, ArrayRef, AsArray},
+    compute::cast,
+    datatypes::{DataType, Float64Type},
+    record_batch::RecordBatch,
+    row::{RowConverter, SortField},
+};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use twox_hash::XxHash64;
+
+use crate::{schema, ArrowPointCloud};
+
+/// Number of points, bounds and hashes of the values of a point cloud
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CloudDigest {
+    pub point_count: usize,
+    /// Lower and upper corner of the finite coordinates, missing coordinates
+    /// are 0, `None` without finite points
+    pub aabb: Option<[[f64; 3]; 2]>,
+    /// Hash of the values and the type of each column
+    pub per_column_hash: BTreeMap<String, u64>,
+    /// Hash of the column hashes in schema order and the number of points
+    pub content_hash: u64,
+}
+
+impl ArrowPointCloud {
+    /// Digest of the points, computed in one pass over the columns in parallel
+    pub fn digest(&self) -> CloudDigest {
+        let batches: Vec<RecordBatch> = self
+            .store
+            .iter()
+            .flat_map(|e| self.store.batches(e.key()))
+            .collect();
+        let point_count = batches.iter().map(RecordBatch::num_rows).sum();
+        let coordinates = schema::coordinates(&self.schema);
+
+        let columns: Vec<(u64, Option<(f64, f64)>)> = (0..self.schema.fields().len())
+            .into_par_iter()
+            .map(|i| {
+                let columns: Vec<&ArrayRef> = batches.iter().map(|b| b.column(i)).collect();
+                let bounds = coordinates[..coordinates.len().min(3)]
+                    .contains(&i)
+                    .then(|| finite_bounds(&columns))
+                    .flatten();
+                (
+                    column_hash(self.schema.field(i).data_type(), &columns),
+                    bounds,
+                )
+            })
+            .collect();
+
+        let mut hasher = XxHash64::with_seed(0);
+        hasher.write_usize(point_count);
+        for (field, (hash, _)) in self.schema.fields().iter().zip(&columns) {
+            hasher.write(field.name().as_bytes());
+            hasher.write_u64(*hash);
+        }
+
+        let mut aabb = [[0.; 3], [0.; 3]];
+        for (d, c) in coordinates.iter().take(3).enumerate() {
+            match columns[*c].1 {
+                Some((lower, upper)) => (aabb[0][d], aabb[1][d]) = (lower, upper),
+                None => {
+                    aabb = [[f64::NAN; 3]; 2];
+                    break;
+                }
+            }
+        }
+
+        CloudDigest {
+            point_count,
+            aabb: aabb[0][0].is_finite().then_some(aabb),
+            per_column_hash: self
+                .schema
+                .fields()
+                .iter()
+                .zip(&columns)
+                .map(|(field, (hash, _))| (field.name().to_owned(), *hash))
+                .collect(),
+            content_hash: hasher.finish(),
+        }
+    }
+}
+
+/// Changes from one digest to another, see [summarize_diff]
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct DiffSummary {
+    /// Points of the second digest less the points of the first
+    pub point_delta: i64,
+    pub bbox_changed: bool,
+    /// Columns only in the second digest
+    pub columns_added: Vec<String>,
+    /// Columns only in the first digest
+    pub columns_removed: Vec<String>,
+    /// Columns in both digests with other values or types
+    pub columns_changed: Vec<String>,
+}
+
+impl DiffSummary {
+    /// Whether the digests describe the same points
+    pub fn is_unchanged(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl fmt::Display for DiffSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_unchanged() {
+            return writeln!(f, "unchanged");
+        }
+        writeln!(f, "points: {:+}", self.point_delta)?;
+        if self.bbox_changed {
+            writeln!(f, "bounds changed")?;
+        }
+        for (label, columns) in [
+            ("added", &self.columns_added),
+            ("removed", &self.columns_removed),
+            ("changed", &self.columns_changed),
+        ] {
+            if !columns.is_empty() {
+                writeln!(f, "columns {label}: {}", columns.join(", "))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Changes from the points of digest `a` to those of digest `b`.
+///
+/// Columns are compared by name and hash, renamed columns are reported as
+/// removed and added.
+pub fn summarize_diff(a: &CloudDigest, b: &CloudDigest) -> DiffSummary {
+    let (a_columns, b_columns) = (&a.per_column_hash, &b.per_column_hash);
+    DiffSummary {
+        point_delta: b.point_count as i64 - a.point_count as i64,
+        bbox_changed: a.aabb != b.aabb,
+        columns_added: b_columns
+            .keys()
+            .filter(|c| !a_columns.contains_key(*c))
+            .cloned()
+            .collect(),
+        columns_removed: a_columns
+            .keys()
+            .filter(|c| !b_columns.contains_key(*c))
+            .cloned()
+            .collect(),
+        columns_changed: a_columns
+            .iter()
+            .filter(|(c, hash)| b_columns.get(*c).is_some_and(|other| other != *hash))
+            .map(|(c, _)| c.clone())
+            .collect(),
+    }
+}
+
+/// Hash of the values of a column split into `arrays`, independent of the
+/// split.
+///
+/// Fixed width values are hashed as bytes, null slots as zeros with the rows
+/// of the nulls hashed separately. Other types are hashed in the row format
+/// of Arrow, which encodes equal values equally.
+fn column_hash(data_type: &DataType, arrays: &[&ArrayRef]) -> u64 {
+    let mut values = XxHash64::with_seed(0);
+    let mut nulls = XxHash64::with_seed(0);
+
+    let converter = match data_type.primitive_width() {
+        Some(_) => None,
+        None => RowConverter::new(vec![SortField::new(data_type.clone())]).ok(),
+    };
+
+    let mut row = 0;
+    for array in arrays {
+        let data = array.to_data();
+        match (data_type.primitive_width(), &converter) {
+            (Some(width), _) => {
+                let bytes = &data.buffers()[0].as_slice()
+                    [data.offset() * width..(data.offset() + data.len()) * width];
+                if data.null_count() == 0 {
+                    values.write(bytes);
+                } else {
+                    let zeros = vec![0; width];
+                    for (i, value) in bytes.chunks_exact(width).enumerate() {
+                        match data.is_null(i) {
+                            true => {
+                                values.write(&zeros);
+                                nulls.write_u64((row + i) as u64);
+                            }
+                            false => values.write(value),
+                        }
+                    }
+                }
+            }
+            (None, Some(converter)) => match converter.convert_columns(&[(*array).clone()]) {
+                Ok(rows) => {
+                    for r in rows.iter() {
+                        values.write(r.as_ref());
+                    }
+                }
+                Err(_) => hash_data(&mut values, &data),
+            },
+            // types without row format hash their layout
+            (None, None) => hash_data(&mut values, &data),
+        }
+        row += data.len();
+    }
+
+    let mut hasher = XxHash64::with_seed(0);
+    hasher.write(format!("{data_type:?}").as_bytes());
+    hasher.write_u64(values.finish());
+    hasher.write_u64(nulls.finish());
+    hasher.finish()
+}
+
+/// Buffers of `data`, see [ArrowPointCloud::content_hash]
+fn hash_data(hasher: &mut XxHash64, data: &ArrayData) {
+    hasher.write_usize(data.len());
+    hasher.write_usize(data.offset());
+    if let Some(nulls) = data.nulls() {
+        hasher.write_usize(nulls.offset());
+        hasher.write(nulls.buffer().as_slice());
+    }
+    for buffer in data.buffers() {
+        hasher.write(buffer.as_slice());
+    }
+    for child in data.child_data() {
+        hash_data(hasher, child);
+    }
+}
+
+/// Lowest and highest finite value of a coordinate column split into `arrays`
+fn finite_bounds(arrays: &[&ArrayRef]) -> Option<(f64, f64)> {
+    let mut bounds: Option<(f64, f64)> = None;
+    for array in arrays {
+        let Ok(array) = cast(array, &DataType::Float64) else {
+            return None;
+        };
+        for v in array.as_primitive::<Float64Type>().iter().flatten() {
+            if v.is_finite() {
+                bounds = Some(bounds.map_or((v, v), |(l, u)| (l.min(v), u.max(v))));
+            }
+        }
+    }
+    bounds
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow::{
+        array::{Float64Array, StringArray, UInt16Array},
+        compute::concat_batches,
+        datatypes::{Field, Schema},
+    };
+
+    use super::*;
+    use crate::{CoordSpec, Point, PointTrait, Rechunker, Synthetic};
+
+    fn rechunked(pc: &ArrowPointCloud, rows: usize) -> ArrowPointCloud {
+        let mut rechunker = Rechunker::new(rows);
+        let mut result = ArrowPointCloud::try_new(pc.schema.clone()).unwrap();
+        for e in pc.store.iter() {
+            for batch in pc.store.batches(e.key()) {
+                for batch in rechunker.push(batch).unwrap() {
+                    result.append(batch).unwrap();
+                }
+            }
+        }
+        if let Some(batch) = rechunker.finish().unwrap() {
+            result.append(batch).unwrap();
+        }
+        result
+    }
+
+    #[test]
+    fn chunking() {
+        let pc = Synthetic::new(1000)
+            .intensity(true)
+            .batch_size(100)
+            .terrain()
+            .unwrap();
+        let digest = pc.digest();
+        assert_eq!(digest.point_count, 1000);
+        assert_eq!(digest.per_column_hash.len(), pc.schema.fields().len());
+        let [lower, upper] = digest.aabb.unwrap();
+        assert!((0..3).all(|d| lower[d] <= upper[d]));
+
+        for rows in [1, 7, 333, 1000] {
+            assert_eq!(rechunked(&pc, rows).digest(), digest, "{rows}");
+        }
+
+        // one changed point
+        let batches: Vec<RecordBatch> = pc
+            .store
+            .iter()
+            .flat_map(|e| pc.store.batches(e.key()))
+            .collect();
+        let batch = concat_batches(&pc.schema, &batches).unwrap();
+        let mut x: Vec<f64> = batch
+            .column(0)
+            .as_primitive::<Float64Type>()
+            .values()
+            .to_vec();
+        x[500] += 0.001;
+        let mut columns = batch.columns().to_vec();
+        columns[0] = Arc::new(Float64Array::from(x));
+        let mut changed = ArrowPointCloud::try_new(pc.schema.clone()).unwrap();
+        changed
+            .append(RecordBatch::try_new(pc.schema.clone(), columns).unwrap())
+            .unwrap();
+
+        let changed = changed.digest();
+        assert_ne!(changed, digest);
+        assert_ne!(changed.content_hash, digest.content_hash);
+        assert_ne!(changed.per_column_hash["x"], digest.per_column_hash["x"]);
+        assert_eq!(changed.per_column_hash["y"], digest.per_column_hash["y"]);
+    }
+
+    #[test]
+    fn nulls_and_strings() {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("x", DataType::Float64, false),
+            Field::new("y", DataType::Float64, false),
+            Field::new("z", DataType::Float64, false),
+            Field::new("intensity", DataType::UInt16, true),
+            Field::new("label", DataType::Utf8, true),
+        ]));
+        let batch = |intensity: Vec<Option<u16>>, label: Vec<Option<&str>>| {
+            let n = intensity.len();
+            let coords = || Arc::new(Float64Array::from_iter_values((0..n).map(|i| i as f64)));
+            RecordBatch::try_new(
+                schema.clone(),
+                vec![
+                    coords(),
+                    coords(),
+                    coords(),
+                    Arc::new(UInt16Array::from(intensity)),
+                    Arc::new(StringArray::from(label)),
+                ],
+            )
+            .unwrap()
+        };
+        let cloud = |batches: Vec<RecordBatch>| {
+            let spec = CoordSpec::new(&["x", "y", "z"]);
+            let mut pc = ArrowPointCloud::try_new_with_coords(schema.clone(), &spec).unwrap();
+            for batch in batches {
+                pc.append(batch).unwrap();
+            }
+            pc
+        };
+
+        let whole = batch(
+            vec![Some(1), None, Some(3), Some(4)],
+            vec![Some("a"), Some("bc"), None, Some("")],
+        );
+        let digest = cloud(vec![whole.clone()]).digest();
+        let split = cloud(vec![
+            whole.slice(0, 1),
+            whole.slice(1, 2),
+            whole.slice(3, 1),
+        ]);
+        assert_eq!(split.digest(), digest);
+
+        // a null differs from a zero, strings differ by their boundaries
+        let other = batch(
+            vec![Some(1), Some(0), Some(3), Some(4)],
+            vec![Some("ab"), Some("c"), None, Some("")],
+        );
+        let other = cloud(vec![other]).digest();
+        assert_ne!(
+            other.per_column_hash["intensity"],
+            digest.per_column_hash["intensity"]
+        );
+        assert_ne!(
+            other.per_column_hash["label"],
+            digest.per_column_hash["label"]
+        );
+        assert_eq!(other.per_column_hash["x"], digest.per_column_hash["x"]);
+
+        // empty point clouds
+        let empty = ArrowPointCloud::try_new(Point::<f64, 3>::schema()).unwrap();
+        assert_eq!(empty.digest().point_count, 0);
+        assert_eq!(empty.digest().aabb, None);
+    }
+
+    fn digest(point_count: usize, aabb: f64, columns: &[(&str, u64)]) -> CloudDigest {
+        CloudDigest {
+            point_count,
+            aabb: Some([[0.; 3], [aabb; 3]]),
+            per_column_hash: columns.iter().map(|(c, h)| (c.to_string(), *h)).collect(),
+            content_hash: 0,
+        }
+    }
+
+    #[test]
+    fn summary() {
+        let a = digest(100, 1., &[("x", 1), ("y", 2), ("z", 3)]);
+        let summary = summarize_diff(&a, &a.clone());
+        assert!(summary.is_unchanged());
+        assert_eq!(summary.to_string(), "unchanged\n");
+
+        // points
+        let summary = summarize_diff(&a, &digest(90, 1., &[("x", 1), ("y", 2), ("z", 3)]));
+        assert_eq!(summary.point_delta, -10);
+        assert!(!summary.is_unchanged());
+
+        // bounds
+        let summary = summarize_diff(&a, &digest(100, 2., &[("x", 1), ("y", 2), ("z", 3)]));
+        assert!(summary.bbox_changed);
+        assert_eq!(summary.point_delta, 0);
+        let mut empty = a.clone();
+        empty.aabb = None;
+        assert!(summarize_diff(&a, &empty).bbox_changed);
+
+        // columns
+        let b = digest(100, 1., &[("x", 1), ("y", 4), ("intensity", 5)]);
+        let summary = summarize_diff(&a, &b);
+        assert_eq!(summary.columns_added, ["intensity"]);
+        assert_eq!(summary.columns_removed, ["z"]);
+        assert_eq!(summary.columns_changed, ["y"]);
+        assert!(!summary.bbox_changed);
+        assert_eq!(
+            summary.to_string(),
+            "points: +0\ncolumns added: intensity\ncolumns removed: z\ncolumns changed: y\n"
+        );
+
+        // the other way around
+        let summary = summarize_diff(&b, &a);
+        assert_eq!(summary.columns_added, ["z"]);
+        assert_eq!(summary.columns_removed, ["intensity"]);
+        assert_eq!(summary.columns_changed, ["y"]);
+    }
+
+    #[test]
+    fn summary_of_clouds() {
+        let pc = Synthetic::new(1000).intensity(true).terrain().unwrap();
+        let sample = Synthetic::new(500).intensity(true).terrain().unwrap();
+        let summary = summarize_diff(&pc.digest(), &sample.digest());
+        assert_eq!(summary.point_delta, -500);
+        assert!(summary.columns_added.is_empty() && summary.columns_removed.is_empty());
+        assert!(summary.columns_changed.contains(&"x".to_owned()));
+        assert!(summarize_diff(&pc.digest(), &rechunked(&pc, 10).digest()).is_unchanged());
+    }
+}
@@ -0,0 +1,522 @@
+//! Encodings of the columns in the segments of a store.
+//!
+//! Attributes like the classification or the return number take a handful
+//! of distinct values, often in long runs of spatially sorted points. The
+//! segments a [PointCloudStore](crate::soa::PointCloudStore) spills are
+//! written with such columns dictionary or run-end encoded, chosen per column
+//! and segment by the estimated size unless configured, see
+//! [ColumnEncodings]. Segments are decoded when read, so that the batches in
+//! memory keep the schema of the point cloud.
+//!
+//! Readers of columns that may be encoded, like IPC inputs with categorical
+//! columns, cast them with [cast] instead of [arrow::compute::cast], which
+//! does not support run-end encoded arrays.
+
+use std::{collections::BTreeMap, fmt, str::FromStr, sync::Arc};
+
+use arrow::{
+    array::{as_run_array, Array, ArrayRef, AsArray, Int32Array, RunArray, UInt32Array},
+    compute::{concat, kernels::cmp::distinct, take},
+    datatypes::{DataType, Field, Int16Type, Int32Type, Int64Type, RunEndIndexType, Schema},
+    error::ArrowError,
+    record_batch::RecordBatch,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::PointCloudError;
+
+/// Maximum size of automatically chosen encodings relative to the plain
+/// column, smaller gains are not worth the decoding
+const MAX_RATIO: f64 = 0.75;
+
+/// Encoding of a column in the segments of a store
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ColumnEncoding {
+    /// The smallest estimated encoding of integer columns, plain otherwise
+    #[default]
+    Auto,
+    Plain,
+    /// Keys into the distinct values of the segment
+    Dictionary,
+    /// Values of runs of equal values with their end
+    RunEnd,
+}
+
+impl FromStr for ColumnEncoding {
+    type Err = PointCloudError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "auto" => Ok(Self::Auto),
+            "plain" => Ok(Self::Plain),
+            "dictionary" | "dict" => Ok(Self::Dictionary),
+            "run-end" | "ree" | "rle" => Ok(Self::RunEnd),
+            _ => Err(PointCloudError::InvalidArgument(format!(
+                "unknown column encoding `{s}`"
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for ColumnEncoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Auto => write!(f, "auto"),
+            Self::Plain => write!(f, "plain"),
+            Self::Dictionary => write!(f, "dictionary"),
+            Self::RunEnd => write!(f, "run-end"),
+        }
+    }
+}
+
+/// Encodings of columns by name, [ColumnEncoding::Auto] for the others
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+pub struct ColumnEncodings(pub BTreeMap<String, ColumnEncoding>);
+
+impl ColumnEncodings {
+    pub fn get(&self, column: &str) -> ColumnEncoding {
+        self.0.get(column).copied().unwrap_or_default()
+    }
+}
+
+impl FromIterator<(String, ColumnEncoding)> for ColumnEncodings {
+    fn from_iter<T: IntoIterator<Item = (String, ColumnEncoding)>>(iter: T) -> Self {
+        Self(iter.into_iter().collect())
+    }
+}
+
+/// Column is dictionary or run-end encoded
+pub fn is_encoded(data_type: &DataType) -> bool {
+    matches!(
+        data_type,
+        DataType::Dictionary(..) | DataType::RunEndEncoded(..)
+    )
+}
+
+/// Smallest estimated encoding of the column in the `chunks` of a segment,
+/// never [ColumnEncoding::Auto].
+///
+/// Only integer columns are encoded, with one byte keys for at most 128
+/// distinct values, so that single byte columns shrink by runs only.
+pub fn choose(chunks: &[ArrayRef]) -> Result<ColumnEncoding, ArrowError> {
+    let Some(data_type) = chunks.first().map(|chunk| chunk.data_type()) else {
+        return Ok(ColumnEncoding::Plain);
+    };
+    if !data_type.is_integer() {
+        return Ok(ColumnEncoding::Plain);
+    }
+    let width = data_type.primitive_width().unwrap_or(8);
+    let rows: usize = chunks.iter().map(|chunk| chunk.len()).sum();
+
+    let distinct = distinct_values(chunks)?;
+    let runs = chunks
+        .iter()
+        .map(|chunk| runs(chunk).map(|ends| ends.len()))
+        .sum::<Result<usize, _>>()?;
+
+    let plain = (rows * width) as f64;
+    let candidates = [
+        (
+            ColumnEncoding::Dictionary,
+            (rows * key_type(distinct).primitive_width().unwrap() + distinct * width) as f64,
+        ),
+        (
+            ColumnEncoding::RunEnd,
+            (runs * (std::mem::size_of::<i32>() + width)) as f64,
+        ),
+    ];
+    Ok(candidates
+        .into_iter()
+        .filter(|(_, size)| *size <= plain * MAX_RATIO)
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+        .map_or(ColumnEncoding::Plain, |(encoding, _)| encoding))
+}
+
+/// Batches of a segment with their columns encoded by `encodings`.
+///
+/// Dictionaries are shared by the batches, as IPC files allow a single
+/// dictionary per column. Encoded columns are kept as they are.
+pub fn encode_segment(
+    batches: &[RecordBatch],
+    encodings: &ColumnEncodings,
+) -> Result<Vec<RecordBatch>, ArrowError> {
+    let Some(first) = batches.first() else {
+        return Ok(Vec::new());
+    };
+    let schema = first.schema();
+
+    let mut fields = Vec::with_capacity(schema.fields().len());
+    let mut columns = vec![Vec::with_capacity(schema.fields().len()); batches.len()];
+    for (i, field) in schema.fields().iter().enumerate() {
+        let chunks: Vec<ArrayRef> = batches
+            .iter()
+            .map(|batch| batch.column(i).clone())
+            .collect();
+        let encoding = match encodings.get(field.name()) {
+            _ if is_encoded(field.data_type()) || !field.data_type().is_primitive() => {
+                ColumnEncoding::Plain
+            }
+            ColumnEncoding::Auto => choose(&chunks)?,
+            encoding => encoding,
+        };
+
+        let (field, chunks) = match encoding {
+            ColumnEncoding::Auto | ColumnEncoding::Plain => (field.as_ref().clone(), chunks),
+            ColumnEncoding::Dictionary => {
+                let data_type = DataType::Dictionary(
+                    Box::new(key_type(distinct_values(&chunks)?)),
+                    Box::new(field.data_type().clone()),
+                );
+                let all = concat(&chunks.iter().map(AsRef::as_ref).collect::<Vec<_>>())?;
+                let dictionary = arrow::compute::cast(&all, &data_type)?;
+                let mut offset = 0;
+                let chunks = chunks
+                    .iter()
+                    .map(|chunk| {
+                        offset += chunk.len();
+                        dictionary.slice(offset - chunk.len(), chunk.len())
+                    })
+                    .collect();
+                let encoded = Field::new_dict(
+                    field.name(),
+                    data_type,
+                    field.is_nullable(),
+                    i as i64,
+                    false,
+                );
+                (encoded.with_metadata(field.metadata().clone()), chunks)
+            }
+            ColumnEncoding::RunEnd => {
+                let chunks = chunks
+                    .iter()
+                    .map(run_end_encode)
+                    .collect::<Result<Vec<_>, _>>()?;
+                let data_type = DataType::RunEndEncoded(
+                    Arc::new(Field::new("run_ends", DataType::Int32, false)),
+                    Arc::new(Field::new("values", field.data_type().clone(), true)),
+                );
+                let encoded = Field::new(field.name(), data_type, field.is_nullable());
+                (encoded.with_metadata(field.metadata().clone()), chunks)
+            }
+        };
+        fields.push(field);
+        for (columns, chunk) in columns.iter_mut().zip(chunks) {
+            columns.push(chunk);
+        }
+    }
+
+    let schema = Arc::new(Schema::new_with_metadata(fields, schema.metadata().clone()));
+    columns
+        .into_iter()
+        .map(|columns| RecordBatch::try_new(schema.clone(), columns))
+        .collect()
+}
+
+/// Plain values of a dictionary or run-end encoded array, other arrays as
+/// they are
+pub fn decode(array: &dyn Array) -> Result<ArrayRef, ArrowError> {
+    match array.data_type() {
+        DataType::Dictionary(_, values) => arrow::compute::cast(array, values),
+        DataType::RunEndEncoded(run_ends, _) => match run_ends.data_type() {
+            DataType::Int16 => expand::<Int16Type>(array),
+            DataType::Int32 => expand::<Int32Type>(array),
+            DataType::Int64 => expand::<Int64Type>(array),
+            data_type => Err(ArrowError::InvalidArgumentError(format!(
+                "invalid run ends of type {data_type}"
+            ))),
+        },
+        _ => Ok(arrow::array::make_array(array.to_data())),
+    }
+}
+
+/// Batch with its encoded columns decoded, see [decode]
+pub fn decode_batch(batch: RecordBatch) -> Result<RecordBatch, ArrowError> {
+    let schema = batch.schema();
+    if !schema.fields().iter().any(|f| is_encoded(f.data_type())) {
+        return Ok(batch);
+    }
+
+    let columns = batch
+        .columns()
+        .iter()
+        .map(|column| decode(column))
+        .collect::<Result<Vec<_>, _>>()?;
+    let fields: Vec<Field> = schema
+        .fields()
+        .iter()
+        .zip(&columns)
+        .map(|(field, column)| {
+            Field::new(
+                field.name(),
+                column.data_type().clone(),
+                field.is_nullable(),
+            )
+            .with_metadata(field.metadata().clone())
+        })
+        .collect();
+    let schema = Schema::new_with_metadata(fields, schema.metadata().clone());
+    RecordBatch::try_new(Arc::new(schema), columns)
+}
+
+/// [arrow::compute::cast] of the decoded values of encoded arrays
+pub fn cast(array: &dyn Array, to_type: &DataType) -> Result<ArrayRef, ArrowError> {
+    match array.data_type() {
+        DataType::RunEndEncoded(..) => arrow::compute::cast(&decode(array)?, to_type),
+        _ => arrow::compute::cast(array, to_type),
+    }
+}
+
+/// Smallest dictionary key type for `distinct` values
+fn key_type(distinct: usize) -> DataType {
+    match distinct {
+        0..=128 => DataType::Int8,
+        129..=32_768 => DataType::Int16,
+        _ => DataType::Int32,
+    }
+}
+
+/// Number of distinct values in the `chunks`, nulls included
+fn distinct_values(chunks: &[ArrayRef]) -> Result<usize, ArrowError> {
+    let Some(first) = chunks.first() else {
+        return Ok(0);
+    };
+    let all = concat(&chunks.iter().map(AsRef::as_ref).collect::<Vec<_>>())?;
+    let data_type = DataType::Dictionary(
+        Box::new(DataType::Int32),
+        Box::new(first.data_type().clone()),
+    );
+    let dictionary = arrow::compute::cast(&all, &data_type)?;
+    let dictionary = dictionary.as_dictionary::<Int32Type>();
+    Ok(dictionary.values().len() + usize::from(dictionary.null_count() > 0))
+}
+
+/// Exclusive ends of the runs of equal values
+fn runs(array: &ArrayRef) -> Result<Vec<i32>, ArrowError> {
+    let rows = array.len();
+    if rows == 0 {
+        return Ok(Vec::new());
+    }
+    let changed = distinct(&array.slice(0, rows - 1), &array.slice(1, rows - 1))?;
+    let mut ends: Vec<i32> = changed
+        .iter()
+        .enumerate()
+        .filter(|(_, changed)| *changed == Some(true))
+        .map(|(i, _)| i as i32 + 1)
+        .collect();
+    ends.push(rows as i32);
+    Ok(ends)
+}
+
+fn run_end_encode(array: &ArrayRef) -> Result<ArrayRef, ArrowError> {
+    let ends = runs(array)?;
+    let starts = UInt32Array::from_iter_values(
+        std::iter::once(0)
+            .chain(ends.iter().copied())
+            .take(ends.len())
+            .map(|i| i as u32),
+    );
+    let values = take(array, &starts, None)?;
+    Ok(Arc::new(RunArray::<Int32Type>::try_new(
+        &Int32Array::from(ends),
+        &values,
+    )?))
+}
+
+fn expand<R: RunEndIndexType>(array: &dyn Array) -> Result<ArrayRef, ArrowError> {
+    let array = as_run_array::<R>(array);
+    let logical: Vec<u32> = (0..array.len() as u32).collect();
+    let physical = array.get_physical_indices(&logical)?;
+    let indices = UInt32Array::from_iter_values(physical.into_iter().map(|i| i as u32));
+    take(array.values(), &indices, None)
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow::{
+        array::{Float64Array, UInt16Array, UInt8Array},
+        datatypes::{Float64Type, Int8Type, UInt8Type},
+    };
+
+    use super::*;
+    use crate::{
+        color::{ColorMap, Palette},
+        query::{CmpOp, Expr, Query},
+        synthetic::Synthetic,
+        ArrowPointCloud, PointCloudTrait,
+    };
+
+    #[test]
+    fn round_trip() {
+        // classes in runs, intensities without
+        let class = |offset: usize| {
+            Arc::new(UInt8Array::from_iter_values(
+                (offset..offset + 1000).map(|i| [2, 6, 9][i / 300 % 3]),
+            )) as ArrayRef
+        };
+        let intensity = |offset: usize| {
+            Arc::new(UInt16Array::from_iter_values(
+                (offset..offset + 1000).map(|i| (i * 7919 % 65_536) as u16),
+            )) as ArrayRef
+        };
+        let wide = |offset: usize| {
+            Arc::new(arrow::array::Int64Array::from_iter(
+                (offset..offset + 1000).map(|i| (i % 7 != 0).then_some((i % 5) as i64)),
+            )) as ArrayRef
+        };
+        let x = |offset: usize| {
+            Arc::new(Float64Array::from_iter_values(
+                (offset..offset + 1000).map(|i| i as f64),
+            )) as ArrayRef
+        };
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("x", DataType::Float64, false),
+            Field::new("classification", DataType::UInt8, false),
+            Field::new("intensity", DataType::UInt16, false),
+            Field::new("label", DataType::Int64, true),
+        ]));
+        let batches: Vec<RecordBatch> = [0, 1000]
+            .map(|offset| {
+                RecordBatch::try_new(
+                    schema.clone(),
+                    vec![x(offset), class(offset), intensity(offset), wide(offset)],
+                )
+                .unwrap()
+            })
+            .to_vec();
+
+        assert_eq!(choose(&[x(0)]).unwrap(), ColumnEncoding::Plain);
+        assert_eq!(
+            choose(&[class(0), class(1000)]).unwrap(),
+            ColumnEncoding::RunEnd
+        );
+        assert_eq!(choose(&[intensity(0)]).unwrap(), ColumnEncoding::Plain);
+        assert_eq!(choose(&[wide(0)]).unwrap(), ColumnEncoding::Dictionary);
+
+        let encoded = encode_segment(&batches, &ColumnEncodings::default()).unwrap();
+        let types: Vec<DataType> = encoded[0]
+            .schema()
+            .fields()
+            .iter()
+            .map(|f| f.data_type().clone())
+            .collect();
+        assert_eq!(types[0], DataType::Float64);
+        assert!(matches!(types[1], DataType::RunEndEncoded(..)));
+        assert_eq!(types[2], DataType::UInt16);
+        assert_eq!(
+            types[3],
+            DataType::Dictionary(Box::new(DataType::Int8), Box::new(DataType::Int64))
+        );
+
+        // configured encodings, in an IPC file with one dictionary per column
+        let encodings: ColumnEncodings = [
+            ("classification".to_string(), ColumnEncoding::Dictionary),
+            ("label".to_string(), ColumnEncoding::Plain),
+        ]
+        .into_iter()
+        .collect();
+        let configured = encode_segment(&batches, &encodings).unwrap();
+        assert!(matches!(
+            configured[0].schema().field(1).data_type(),
+            DataType::Dictionary(..)
+        ));
+        assert_eq!(
+            configured[0].schema().field(3).data_type(),
+            &DataType::Int64
+        );
+
+        for encoded in [encoded, configured] {
+            let mut writer =
+                arrow::ipc::writer::FileWriter::try_new(Vec::new(), &encoded[0].schema()).unwrap();
+            for batch in &encoded {
+                writer.write(batch).unwrap();
+            }
+            writer.finish().unwrap();
+            let file = std::io::Cursor::new(writer.into_inner().unwrap());
+            let read: Vec<RecordBatch> = arrow::ipc::reader::FileReader::try_new(file, None)
+                .unwrap()
+                .map(|batch| decode_batch(batch.unwrap()).unwrap())
+                .collect();
+            assert_eq!(read, batches);
+        }
+
+        assert_eq!(
+            "rle".parse::<ColumnEncoding>().unwrap(),
+            ColumnEncoding::RunEnd
+        );
+        assert_eq!(ColumnEncoding::RunEnd.to_string(), "run-end");
+        assert!("zip".parse::<ColumnEncoding>().is_err());
+    }
+
+    #[test]
+    fn encoded_readers() {
+        // the classification dictionary encoded, like categoricals of pandas
+        let plain = Synthetic::new(1000).terrain().unwrap();
+        let class = |i: usize| [2u8, 6, 9][i % 3];
+        let mut pc = None::<ArrowPointCloud>;
+        let mut offset = 0;
+        for batch in plain
+            .store
+            .iter()
+            .flat_map(|e| plain.store.batches(e.key()))
+        {
+            let keys = arrow::array::Int8Array::from_iter_values(
+                (offset..offset + batch.num_rows()).map(|i| (i % 3) as i8),
+            );
+            offset += batch.num_rows();
+            let values = Arc::new(UInt8Array::from(vec![2, 6, 9]));
+            let classification =
+                arrow::array::DictionaryArray::<Int8Type>::try_new(keys, values).unwrap();
+            let i = batch.schema().index_of("classification").unwrap();
+            let mut fields: Vec<Field> = batch
+                .schema()
+                .fields()
+                .iter()
+                .map(|f| f.as_ref().clone())
+                .collect();
+            fields[i] = Field::new("classification", classification.data_type().clone(), false);
+            let mut columns = batch.columns().to_vec();
+            columns[i] = Arc::new(classification);
+            let batch = RecordBatch::try_new(Arc::new(Schema::new(fields)), columns).unwrap();
+            let pc = pc.get_or_insert_with(|| ArrowPointCloud::try_new(batch.schema()).unwrap());
+            pc.append(batch).unwrap();
+        }
+        let pc = pc.unwrap();
+
+        // filter
+        let ground = pc
+            .execute(&Query::new().filter(Expr::cmp("classification", CmpOp::Eq, 2.)))
+            .unwrap();
+        let expected = (0..1000).filter(|i| class(*i) == 2).count();
+        assert_eq!(ground.num_points(), expected);
+
+        // stats
+        let stats = pc.column_stats("classification").unwrap();
+        assert_eq!((stats.min, stats.max, stats.count), (2., 9., 1000));
+
+        // color mapping
+        let map = ColorMap::Categorical(Palette::classification());
+        let building = Palette::classification().color(6);
+        let (colors, _) = crate::color::colors(&pc, "classification", &map).unwrap();
+        assert_eq!(colors.len(), 1000);
+        assert_eq!(
+            colors.iter().filter(|c| **c == Some(building)).count(),
+            (0..1000).filter(|i| class(*i) == 6).count()
+        );
+
+        // the same once run-end encoded
+        let runs =
+            run_end_encode(&(Arc::new(UInt8Array::from(vec![2, 2, 6, 6, 6, 9])) as ArrayRef))
+                .unwrap();
+        assert_eq!(runs.len(), 6);
+        let values = cast(&runs, &DataType::Float64).unwrap();
+        assert_eq!(
+            values.as_primitive::<Float64Type>().values(),
+            &[2., 2., 6., 6., 6., 9.]
+        );
+        let sliced = decode(&runs.slice(1, 3)).unwrap();
+        assert_eq!(sliced.as_primitive::<UInt8Type>().values(), &[2, 6, 6]);
+        let colors = map.map(&runs, None).unwrap();
+        assert_eq!(colors[2], Some(building));
+    }
+}
@@ -0,0 +1,318 @@
+use arrow::record_batch::RecordBatch;
+
+use crate::{
+    compute::{aabb, filter_by_frustum},
+    ArrowPointCloud, Point, PointCloudError, PointTrait, AABB,
+};
+
+/// Plane `normal · p + d = 0`, points with a positive distance are inside
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Plane {
+    /// Unit normal pointing inside
+    pub normal: [f64; 3],
+    pub d: f64,
+}
+
+impl Plane {
+    /// Plane through `point` with the inward `normal`, which is normalized
+    pub fn new(normal: [f64; 3], point: [f64; 3]) -> Self {
+        let normal = normalize(normal);
+        Self {
+            normal,
+            d: -dot(normal, point),
+        }
+    }
+
+    /// Signed distance of a point, negative outside
+    pub fn distance(&self, p: [f64; 3]) -> f64 {
+        dot(self.normal, p) + self.d
+    }
+
+    pub fn contains(&self, p: [f64; 3]) -> bool {
+        self.distance(p) >= 0.
+    }
+
+    /// Whether any part of the box is inside
+    pub fn intersects_aabb(&self, lower: [f64; 3], upper: [f64; 3]) -> bool {
+        // the corner farthest along the normal
+        let corner = std::array::from_fn(|i| {
+            if self.normal[i] >= 0. {
+                upper[i]
+            } else {
+                lower[i]
+            }
+        });
+        self.contains(corner)
+    }
+}
+
+/// View frustum of a perspective camera as six inward facing planes
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frustum {
+    /// Near, far, left, right, bottom and top plane
+    pub planes: [Plane; 6],
+    corners: [[f64; 3]; 8],
+}
+
+impl Frustum {
+    /// Frustum of a camera at `position` looking along `direction` with the
+    /// vertical field of view `fov` in radians and the width to height ratio
+    /// `aspect`.
+    ///
+    /// The camera is upright, its up vector is +z unless it looks straight up
+    /// or down, then it is +y.
+    pub fn from_camera(
+        position: [f64; 3],
+        direction: [f64; 3],
+        fov: f64,
+        aspect: f64,
+        near: f64,
+        far: f64,
+    ) -> Result<Self, PointCloudError> {
+        let invalid = |msg: &str| PointCloudError::InvalidArgument(format!("frustum: {msg}"));
+        let camera = [fov, aspect, near, far];
+        let mut values = position.iter().chain(&direction).chain(&camera);
+        if values.any(|v| !v.is_finite()) {
+            return Err(invalid("parameters must be finite"));
+        }
+        if dot(direction, direction) == 0. {
+            return Err(invalid("direction must not be zero"));
+        }
+        if !(fov > 0. && fov < std::f64::consts::PI) {
+            return Err(invalid("field of view must be within (0, 180) degrees"));
+        }
+        if aspect <= 0. {
+            return Err(invalid("aspect ratio must be positive"));
+        }
+        if !(0. <= near && near < far) {
+            return Err(invalid("expected 0 <= near < far"));
+        }
+
+        let forward = normalize(direction);
+        let up = if forward[2].abs() > 0.999 {
+            [0., 1., 0.]
+        } else {
+            [0., 0., 1.]
+        };
+        let right = normalize(cross(forward, up));
+        let up = cross(right, forward);
+
+        let tan_v = (fov / 2.).tan();
+        let tan_h = tan_v * aspect;
+        let at = |distance: f64| add(position, scale(forward, distance));
+
+        let planes = [
+            Plane::new(forward, at(near)),
+            Plane::new(scale(forward, -1.), at(far)),
+            Plane::new(add(right, scale(forward, tan_h)), position),
+            Plane::new(add(scale(right, -1.), scale(forward, tan_h)), position),
+            Plane::new(add(up, scale(forward, tan_v)), position),
+            Plane::new(add(scale(up, -1.), scale(forward, tan_v)), position),
+        ];
+
+        let mut corners = [[0.; 3]; 8];
+        for (i, corner) in corners.iter_mut().enumerate() {
+            let distance = if i < 4 { near } else { far };
+            let h = if i % 2 == 0 { -tan_h } else { tan_h };
+            let v = if i % 4 < 2 { -tan_v } else { tan_v };
+            *corner = add(
+                at(distance),
+                add(scale(right, h * distance), scale(up, v * distance)),
+            );
+        }
+
+        Ok(Self { planes, corners })
+    }
+
+    pub fn contains(&self, p: [f64; 3]) -> bool {
+        self.planes.iter().all(|plane| plane.contains(p))
+    }
+
+    /// Whether the box may intersect the frustum.
+    ///
+    /// Boxes outside of a single plane are rejected, some boxes near the edges
+    /// of the frustum pass although they are outside.
+    pub fn intersects_aabb(&self, lower: [f64; 3], upper: [f64; 3]) -> bool {
+        self.planes
+            .iter()
+            .all(|plane| plane.intersects_aabb(lower, upper))
+    }
+
+    /// Lower and upper corner of the bounding box of the frustum
+    pub fn bounds(&self) -> ([f64; 3], [f64; 3]) {
+        self.corners.iter().fold(
+            ([f64::INFINITY; 3], [f64::NEG_INFINITY; 3]),
+            |(lower, upper), c| {
+                (
+                    std::array::from_fn(|i| lower[i].min(c[i])),
+                    std::array::from_fn(|i| upper[i].max(c[i])),
+                )
+            },
+        )
+    }
+}
+
+/// Parse camera parameters `x,y,z,dx,dy,dz,fov,aspect,near,far` with the
+/// vertical field of view in degrees, see [Frustum::from_camera]
+pub fn parse(s: &str) -> Result<Frustum, PointCloudError> {
+    let v: Vec<f64> = s
+        .split(',')
+        .map(|v| v.trim().parse())
+        .collect::<Result<_, _>>()
+        .map_err(|_| PointCloudError::InvalidArgument(format!("invalid frustum `{s}`")))?;
+
+    let [x, y, z, dx, dy, dz, fov, aspect, near, far] = v[..] else {
+        return Err(PointCloudError::InvalidArgument(format!(
+            "frustum requires 10 values `x,y,z,dx,dy,dz,fov,aspect,near,far`, got {}",
+            v.len()
+        )));
+    };
+
+    Frustum::from_camera([x, y, z], [dx, dy, dz], fov.to_radians(), aspect, near, far)
+}
+
+impl ArrowPointCloud {
+    /// Points within the view frustum, batches outside are skipped by their
+    /// bounds
+    pub fn points_in_frustum<'a>(
+        &'a self,
+        frustum: &'a Frustum,
+    ) -> impl Iterator<Item = Result<RecordBatch, PointCloudError>> + 'a {
+        self.store
+            .iter()
+            .flat_map(|e| self.store.batches(e.key()))
+            .filter(|batch| {
+                let bounds: AABB<Point<f64, 3>> = aabb(batch);
+                let (lower, upper) = (bounds.lower(), bounds.upper());
+                batch.num_rows() > 0
+                    && frustum.intersects_aabb(
+                        [lower.x(), lower.y(), lower.z()],
+                        [upper.x(), upper.y(), upper.z()],
+                    )
+            })
+            .map(|batch| filter_by_frustum(&batch, frustum).map_err(PointCloudError::from))
+            .filter(|batch| !matches!(batch, Ok(batch) if batch.num_rows() == 0))
+    }
+}
+
+fn dot(a: [f64; 3], b: [f64; 3]) -> f64 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn cross(a: [f64; 3], b: [f64; 3]) -> [f64; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+fn add(a: [f64; 3], b: [f64; 3]) -> [f64; 3] {
+    [a[0] + b[0], a[1] + b[1], a[2] + b[2]]
+}
+
+fn scale(a: [f64; 3], s: f64) -> [f64; 3] {
+    [a[0] * s, a[1] * s, a[2] * s]
+}
+
+fn normalize(a: [f64; 3]) -> [f64; 3] {
+    scale(a, 1. / dot(a, a).sqrt())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PointCloudTrait;
+
+    /// Camera at the origin looking east, 90° field of view, 1 to 10 units
+    fn camera() -> Frustum {
+        parse("0,0,0,1,0,0,90,1,1,10").unwrap()
+    }
+
+    #[test]
+    fn planes() {
+        let frustum = camera();
+
+        // at x = 5 the view spans [-5, 5] in y and z
+        let e = 0.01;
+        for (inside, outside) in [
+            ([1. + e, 0., 0.], [1. - e, 0., 0.]),
+            ([10. - e, 0., 0.], [10. + e, 0., 0.]),
+            ([5., 5. - e, 0.], [5., 5. + e, 0.]),
+            ([5., -5. + e, 0.], [5., -5. - e, 0.]),
+            ([5., 0., 5. - e], [5., 0., 5. + e]),
+            ([5., 0., -5. + e], [5., 0., -5. - e]),
+        ] {
+            assert!(frustum.contains(inside), "{inside:?}");
+            assert!(!frustum.contains(outside), "{outside:?}");
+            // exactly one plane excludes the point
+            let excluding = frustum.planes.iter().filter(|p| !p.contains(outside));
+            assert_eq!(excluding.count(), 1, "{outside:?}");
+        }
+        assert!(!frustum.contains([-5., 0., 0.]));
+
+        let (lower, upper) = frustum.bounds();
+        let rounded = |v: [f64; 3]| v.map(|v| (v * 1e9).round() / 1e9);
+        assert_eq!(rounded(lower), [1., -10., -10.]);
+        assert_eq!(rounded(upper), [10., 10., 10.]);
+
+        // aspect widens the horizontal extent, looking down uses +y as up
+        let wide = parse("0,0,0,1,0,0,90,2,1,10").unwrap();
+        assert!(wide.contains([5., 9.9, 0.]) && !wide.contains([5., 0., 5.1]));
+        let down = parse("0,0,10,0,0,-1,90,1,1,20").unwrap();
+        assert!(down.contains([4., 4., 5.]) && !down.contains([6., 0., 5.]));
+    }
+
+    #[test]
+    fn boxes() {
+        let frustum = camera();
+
+        assert!(frustum.intersects_aabb([4., -1., -1.], [6., 1., 1.]));
+        // containing the whole frustum
+        assert!(frustum.intersects_aabb([-100.; 3], [100.; 3]));
+        // behind the camera, beyond the far plane and beside the view
+        assert!(!frustum.intersects_aabb([-5., -1., -1.], [-2., 1., 1.]));
+        assert!(!frustum.intersects_aabb([10.5, -1., -1.], [12., 1., 1.]));
+        assert!(!frustum.intersects_aabb([4., 6., -1.], [5., 8., 1.]));
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(parse("0,0,0,1,0,0,90,1,1").is_err());
+        assert!(parse("0,0,0,0,0,0,90,1,1,10").is_err());
+        assert!(parse("0,0,0,1,0,0,180,1,1,10").is_err());
+        assert!(parse("0,0,0,1,0,0,90,1,10,1").is_err());
+        assert!(parse("0,0,0,1,0,0,90,0,1,10").is_err());
+        assert!(parse("0,0,0,1,0,0,NaN,1,1,10").is_err());
+    }
+
+    #[test]
+    fn point_cloud() {
+        // a line of points along x, in batches of 10
+        let mut pc = ArrowPointCloud::from_iter(
+            (0..10).map(|i| Point::<f64, 3>::from_slice(&[i as f64 + 0.5, 0.5, 0.5])),
+        )
+        .unwrap();
+        for batch in 1..4 {
+            let other =
+                ArrowPointCloud::from_iter((0..10).map(|i| {
+                    Point::<f64, 3>::from_slice(&[(batch * 10 + i) as f64 + 0.5, 0.5, 0.5])
+                }))
+                .unwrap();
+            for e in other.store.iter() {
+                for batch in other.store.batches(e.key()) {
+                    pc.append(batch).unwrap();
+                }
+            }
+        }
+        assert_eq!(pc.num_points(), 40);
+
+        let batches: Vec<RecordBatch> = pc
+            .points_in_frustum(&camera())
+            .collect::<Result<_, _>>()
+            .unwrap();
+        // 1.5 to 9.5, the other batches are culled
+        assert_eq!(batches.len(), 1);
+        assert_eq!(batches[0].num_rows(), 9);
+    }
+}
@@ -0,0 +1,331 @@
+use serde_json::json;
+
+use crate::{
+    color::{self, ColorMap, Normalization},
+    ArrowPointCloud, Point, PointCloudError, PointCloudTrait, PointTrait,
+};
+
+const GLB_MAGIC: u32 = 0x4654_6C67; // "glTF"
+const GLB_VERSION: u32 = 2;
+const CHUNK_JSON: u32 = 0x4E4F_534A; // "JSON"
+const CHUNK_BIN: u32 = 0x004E_4942; // "BIN\0"
+
+const MODE_POINTS: u32 = 0;
+const FLOAT: u32 = 5126;
+const UNSIGNED_BYTE: u32 = 5121;
+const ARRAY_BUFFER: u32 = 34962;
+
+/// Color of points without a value
+const NULL_COLOR: [u8; 4] = [128, 128, 128, 255];
+
+/// Options of the binary glTF export
+#[derive(Default)]
+pub struct GlbOptions {
+    /// Attribute mapped to the vertex colors, no colors if `None`
+    pub color: Option<String>,
+    /// Colors of the attribute, [ColorMap::for_attribute] with turbo if `None`
+    pub color_map: Option<ColorMap>,
+    /// Decimate evenly to at most this many vertices
+    pub max_vertices: Option<usize>,
+}
+
+impl ArrowPointCloud {
+    /// Encode as binary glTF with a single `POINTS` primitive.
+    ///
+    /// Positions are converted from east-north-up to the y-up glTF frame and
+    /// recentered at the bounding box center, the offset in the data reference
+    /// system is recorded in the node extras as `crux.offset`.
+    pub fn to_glb(&self, options: &GlbOptions) -> Result<Vec<u8>, PointCloudError> {
+        let num_points = self.num_points();
+        if num_points == 0 {
+            return Err(PointCloudError::InvalidArgument(
+                "cannot export an empty point cloud".to_string(),
+            ));
+        }
+
+        // evenly spaced selection
+        let count = options.max_vertices.unwrap_or(num_points).min(num_points);
+        if count == 0 {
+            return Err(PointCloudError::InvalidArgument(
+                "max vertices must be positive".to_string(),
+            ));
+        }
+        let selected = |i: usize| (i * count) / num_points != ((i + 1) * count) / num_points;
+
+        let aabb = self.aabb::<Point<f64, 3>>();
+        let center = aabb.center();
+
+        // positions
+        let mut positions = Vec::with_capacity(count * 12);
+        let (mut min, mut max) = ([f32::MAX; 3], [f32::MIN; 3]);
+        for p in self
+            .points::<Point<f64, 3>>()
+            .enumerate()
+            .filter_map(|(i, p)| selected(i).then_some(p))
+        {
+            let p = [
+                (p.x() - center.x()) as f32,
+                (p.z() - center.z()) as f32,
+                -(p.y() - center.y()) as f32,
+            ];
+            for d in 0..3 {
+                min[d] = min[d].min(p[d]);
+                max[d] = max[d].max(p[d]);
+                positions.extend_from_slice(&p[d].to_le_bytes());
+            }
+        }
+
+        // colors
+        let colors = match &options.color {
+            Some(attribute) => {
+                let fallback;
+                let map = match &options.color_map {
+                    Some(map) => map,
+                    None => {
+                        fallback = ColorMap::for_attribute(
+                            attribute,
+                            colorgrad::turbo(),
+                            Normalization::default(),
+                        );
+                        &fallback
+                    }
+                };
+
+                let (values, _) = color::colors(self, attribute, map)?;
+                let mut colors = Vec::with_capacity(count * 4);
+                for v in values
+                    .into_iter()
+                    .enumerate()
+                    .filter_map(|(i, v)| selected(i).then_some(v))
+                {
+                    colors.extend(v.unwrap_or(NULL_COLOR));
+                }
+                Some(colors)
+            }
+            None => None,
+        };
+
+        // buffer views are 4 byte aligned by construction
+        let mut bin = positions;
+        let mut buffer_views = vec![json!({
+            "buffer": 0,
+            "byteOffset": 0,
+            "byteLength": count * 12,
+            "target": ARRAY_BUFFER,
+        })];
+        let mut accessors = vec![json!({
+            "bufferView": 0,
+            "componentType": FLOAT,
+            "count": count,
+            "type": "VEC3",
+            "min": min,
+            "max": max,
+        })];
+        let mut attributes = json!({ "POSITION": 0 });
+
+        if let Some(colors) = colors {
+            buffer_views.push(json!({
+                "buffer": 0,
+                "byteOffset": bin.len(),
+                "byteLength": colors.len(),
+                "target": ARRAY_BUFFER,
+            }));
+            accessors.push(json!({
+                "bufferView": 1,
+                "componentType": UNSIGNED_BYTE,
+                "normalized": true,
+                "count": count,
+                "type": "VEC4",
+            }));
+            attributes["COLOR_0"] = json!(1);
+            bin.extend(colors);
+        }
+
+        let document = json!({
+            "asset": { "version": "2.0", "generator": "crux" },
+            "scene": 0,
+            "scenes": [{ "nodes": [0] }],
+            "nodes": [{
+                "mesh": 0,
+                "extras": { "crux": { "offset": [center.x(), center.y(), center.z()] } },
+            }],
+            "meshes": [{ "primitives": [{ "attributes": attributes, "mode": MODE_POINTS }] }],
+            "accessors": accessors,
+            "bufferViews": buffer_views,
+            "buffers": [{ "byteLength": bin.len() }],
+        });
+
+        let mut json = serde_json::to_vec(&document)
+            .map_err(|e| PointCloudError::InvalidArgument(e.to_string()))?;
+        json.resize(json.len().next_multiple_of(4), b' ');
+        bin.resize(bin.len().next_multiple_of(4), 0);
+
+        let length = 12 + 8 + json.len() + 8 + bin.len();
+        let mut glb = Vec::with_capacity(length);
+        for word in [GLB_MAGIC, GLB_VERSION, length as u32] {
+            glb.extend_from_slice(&word.to_le_bytes());
+        }
+        for (kind, chunk) in [(CHUNK_JSON, json), (CHUNK_BIN, bin)] {
+            glb.extend_from_slice(&(chunk.len() as u32).to_le_bytes());
+            glb.extend_from_slice(&kind.to_le_bytes());
+            glb.extend(chunk);
+        }
+
+        Ok(glb)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow::{
+        array::{ArrayRef, Float64Array},
+        datatypes::{DataType, Field, Schema},
+        record_batch::RecordBatch,
+    };
+    use serde_json::Value;
+
+    use super::*;
+
+    fn cloud(n: usize) -> ArrowPointCloud {
+        let mut fields: Vec<Field> = Point::<f64, 3>::schema()
+            .fields()
+            .iter()
+            .map(|field| field.as_ref().clone())
+            .collect();
+        fields.push(Field::new("intensity", DataType::Float64, true));
+        let schema = Arc::new(Schema::new(fields));
+
+        let column = |f: fn(usize) -> f64| {
+            Arc::new(Float64Array::from_iter_values((0..n).map(f))) as ArrayRef
+        };
+        let intensity = Arc::new(Float64Array::from_iter(
+            (0..n).map(|i| (i % 7 != 0).then_some(i as f64)),
+        ));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                column(|i| 1000. + i as f64),
+                column(|i| 2000. + (i % 10) as f64),
+                column(|i| (i % 3) as f64),
+                intensity,
+            ],
+        )
+        .unwrap();
+
+        let mut pc = ArrowPointCloud::try_new(schema).unwrap();
+        pc.append(batch).unwrap();
+        pc
+    }
+
+    /// Structure level validation, returns the document and binary chunk
+    fn validate(glb: &[u8]) -> (Value, &[u8]) {
+        let word = |offset: usize| u32::from_le_bytes(glb[offset..offset + 4].try_into().unwrap());
+
+        assert_eq!(word(0), GLB_MAGIC);
+        assert_eq!(word(4), GLB_VERSION);
+        assert_eq!(word(8) as usize, glb.len());
+
+        let json_length = word(12) as usize;
+        assert_eq!(word(16), CHUNK_JSON);
+        assert!(json_length.is_multiple_of(4));
+        let document: Value = serde_json::from_slice(&glb[20..20 + json_length]).unwrap();
+
+        let offset = 20 + json_length;
+        let bin_length = word(offset) as usize;
+        assert_eq!(word(offset + 4), CHUNK_BIN);
+        assert!(bin_length.is_multiple_of(4));
+        assert_eq!(offset + 8 + bin_length, glb.len());
+        let bin = &glb[offset + 8..];
+
+        assert_eq!(document["asset"]["version"], "2.0");
+        assert!(document["buffers"][0]["byteLength"].as_u64().unwrap() as usize <= bin.len());
+
+        // buffer views within the buffer, vertex attributes aligned
+        for view in document["bufferViews"].as_array().unwrap() {
+            let offset = view["byteOffset"].as_u64().unwrap() as usize;
+            let length = view["byteLength"].as_u64().unwrap() as usize;
+            assert!(offset.is_multiple_of(4));
+            assert!(offset + length <= bin.len());
+        }
+
+        // accessors fit their views
+        for accessor in document["accessors"].as_array().unwrap() {
+            let view = &document["bufferViews"][accessor["bufferView"].as_u64().unwrap() as usize];
+            let size = match (
+                accessor["componentType"].as_u64().unwrap() as u32,
+                accessor["type"].as_str().unwrap(),
+            ) {
+                (FLOAT, "VEC3") => 12,
+                (UNSIGNED_BYTE, "VEC4") => 4,
+                other => panic!("unexpected accessor {other:?}"),
+            };
+            assert_eq!(
+                accessor["count"].as_u64().unwrap() * size,
+                view["byteLength"].as_u64().unwrap()
+            );
+        }
+
+        let primitive = &document["meshes"][0]["primitives"][0];
+        assert_eq!(primitive["mode"], MODE_POINTS);
+        let position =
+            &document["accessors"][primitive["attributes"]["POSITION"].as_u64().unwrap() as usize];
+        assert_eq!(position["min"].as_array().unwrap().len(), 3);
+        assert_eq!(position["max"].as_array().unwrap().len(), 3);
+
+        (document, bin)
+    }
+
+    #[test]
+    fn export() {
+        let pc = cloud(100);
+        let options = GlbOptions {
+            color: Some("intensity".to_string()),
+            ..Default::default()
+        };
+
+        let glb = pc.to_glb(&options).unwrap();
+        let (document, bin) = validate(&glb);
+
+        assert_eq!(document["accessors"][0]["count"], 100);
+        assert_eq!(document["accessors"][1]["normalized"], true);
+
+        // recentered at the bounds center, in y-up
+        let offset = &document["nodes"][0]["extras"]["crux"]["offset"];
+        assert_eq!(offset, &json!([1049.5, 2004.5, 1.]));
+        assert_eq!(document["accessors"][0]["min"], json!([-49.5, -1., -4.5]));
+        assert_eq!(document["accessors"][0]["max"], json!([49.5, 1., 4.5]));
+
+        // null intensity of the first point is gray
+        let colors = &bin[100 * 12..];
+        assert_eq!(colors[..4], NULL_COLOR);
+        assert_ne!(colors[4..8], NULL_COLOR);
+    }
+
+    #[test]
+    fn decimate() {
+        let pc = cloud(1000);
+        let options = GlbOptions {
+            max_vertices: Some(64),
+            ..Default::default()
+        };
+
+        let glb = pc.to_glb(&options).unwrap();
+        let (document, _) = validate(&glb);
+
+        assert_eq!(document["accessors"][0]["count"], 64);
+        assert!(document["meshes"][0]["primitives"][0]["attributes"]
+            .get("COLOR_0")
+            .is_none());
+
+        // evenly spread over the data
+        let max = document["accessors"][0]["max"][0].as_f64().unwrap();
+        assert!(max > 480., "{max}");
+
+        assert!(ArrowPointCloud::try_new(pc.schema())
+            .unwrap()
+            .to_glb(&options)
+            .is_err());
+    }
+}
@@ -0,0 +1,312 @@
+//! Spatial indices as files.
+//!
+//! Building the index of a large collection reads all of its points, so the
+//! index is written next to the segments and read again instead of rebuilt.
+//! An index file is an Arrow IPC file with a record per leaf of the R-tree,
+//! the key and bounds of a segment for a [BatchIndex] and the row and
+//! coordinates of a point for a [PointIndex]. Reading bulk loads the tree
+//! from the records, so queries return the same results as on the written
+//! index.
+//!
+//! The custom metadata of the file holds the version of the format, the kind
+//! of the index and the content version of the point cloud it was built for.
+//! Files of another format, kind or content version are stale and read as
+//! `None`, as are batch indices referencing keys missing in the point cloud.
+
+use std::{fs::File, io::BufWriter, path::Path, sync::Arc};
+
+use arrow::{
+    array::{Array, ArrayRef, AsArray, Float64Array, StringArray, UInt64Array},
+    compute::concat_batches,
+    datatypes::{DataType, Field, Float64Type, Schema, SchemaRef, UInt64Type},
+    error::ArrowError,
+    ipc::{reader::FileReader, writer::FileWriter},
+    record_batch::RecordBatch,
+};
+use rstar::{primitives::GeomWithData, Point as _, RTree};
+
+use crate::{
+    soa::{BatchIndex, PointIndex},
+    ArrowPointCloud, Point, PointCloudError, AABB,
+};
+
+/// Version of the file format, files of other versions are stale
+const FORMAT: &str = "1";
+const FORMAT_KEY: &str = "crux:index_format";
+/// Kind of the index, `batch` or `point`
+const KIND_KEY: &str = "crux:index_kind";
+/// Content version of the point cloud the index was built for
+const VERSION_KEY: &str = "crux:index_version";
+
+/// Number of coordinates of indexed points
+const DIMENSIONS: usize = 4;
+
+/// Index that can be written to and read from a file
+pub trait IndexFile: Sized {
+    /// Write the index of the point cloud at content `version` to `path`
+    fn write(&self, path: &Path, version: u64) -> Result<(), PointCloudError>;
+
+    /// Index written to `path` for `pc` at content `version`, `None` if the
+    /// file is stale
+    fn read(
+        path: &Path,
+        pc: &ArrowPointCloud,
+        version: u64,
+    ) -> Result<Option<Self>, PointCloudError>;
+}
+
+impl IndexFile for BatchIndex {
+    fn write(&self, path: &Path, version: u64) -> Result<(), PointCloudError> {
+        let (keys, aabbs): (Vec<&str>, Vec<&AABB<Point<f64, DIMENSIONS>>>) =
+            self.iter().map(|e| (e.data.as_str(), e.geom())).unzip();
+
+        let mut columns = vec![Arc::new(StringArray::from(keys)) as ArrayRef];
+        columns.extend(coordinates(aabbs.iter().map(|aabb| aabb.lower())));
+        columns.extend(coordinates(aabbs.iter().map(|aabb| aabb.upper())));
+
+        let batch = RecordBatch::try_new(batch_schema(), columns)?;
+        write_records(path, "batch", version, &batch)
+    }
+
+    fn read(
+        path: &Path,
+        pc: &ArrowPointCloud,
+        version: u64,
+    ) -> Result<Option<Self>, PointCloudError> {
+        let Some(batch) = read_records(path, "batch", version, batch_schema())? else {
+            return Ok(None);
+        };
+
+        let keys = batch.column(0).as_string::<i32>();
+        if keys.iter().flatten().any(|key| !pc.store.contains_key(key)) {
+            return Ok(None);
+        }
+
+        let lower = points(&batch, 1);
+        let upper = points(&batch, 1 + DIMENSIONS);
+        let objects = keys
+            .iter()
+            .zip(lower.zip(upper))
+            .map(|(key, (lower, upper))| {
+                GeomWithData::new(
+                    AABB::from_corners(lower, upper),
+                    key.unwrap_or_default().to_owned(),
+                )
+            })
+            .collect();
+
+        Ok(Some(RTree::bulk_load_with_params(objects)))
+    }
+}
+
+impl IndexFile for PointIndex {
+    fn write(&self, path: &Path, version: u64) -> Result<(), PointCloudError> {
+        let rows = UInt64Array::from_iter_values(self.iter().map(|e| e.data));
+
+        let mut columns = vec![Arc::new(rows) as ArrayRef];
+        columns.extend(coordinates(self.iter().map(|e| *e.geom())));
+
+        let batch = RecordBatch::try_new(point_schema(), columns)?;
+        write_records(path, "point", version, &batch)
+    }
+
+    fn read(
+        path: &Path,
+        _pc: &ArrowPointCloud,
+        version: u64,
+    ) -> Result<Option<Self>, PointCloudError> {
+        let Some(batch) = read_records(path, "point", version, point_schema())? else {
+            return Ok(None);
+        };
+
+        let rows = batch.column(0).as_primitive::<UInt64Type>();
+        let objects = points(&batch, 1)
+            .zip(rows.values().iter())
+            .map(|(p, row)| GeomWithData::new(p, *row))
+            .collect();
+
+        Ok(Some(RTree::bulk_load_with_params(objects)))
+    }
+}
+
+/// Content version the index file at `path` was built for, `None` for files
+/// of another format
+pub fn content_version(path: &Path) -> Result<Option<u64>, PointCloudError> {
+    let reader = FileReader::try_new(File::open(path).map_err(ArrowError::from)?, None)?;
+    let metadata = reader.custom_metadata();
+    if metadata.get(FORMAT_KEY).map(String::as_str) != Some(FORMAT) {
+        return Ok(None);
+    }
+    Ok(metadata.get(VERSION_KEY).and_then(|v| v.parse().ok()))
+}
+
+fn batch_schema() -> SchemaRef {
+    let mut fields = vec![Field::new("key", DataType::Utf8, false)];
+    for corner in ["lower", "upper"] {
+        fields.extend(
+            (0..DIMENSIONS).map(|i| Field::new(format!("{corner}_{i}"), DataType::Float64, false)),
+        );
+    }
+    Arc::new(Schema::new(fields))
+}
+
+fn point_schema() -> SchemaRef {
+    let mut fields = vec![Field::new("row", DataType::UInt64, false)];
+    fields.extend(
+        (0..DIMENSIONS).map(|i| Field::new(format!("coord_{i}"), DataType::Float64, false)),
+    );
+    Arc::new(Schema::new(fields))
+}
+
+/// Columns of the coordinates of `points`
+fn coordinates(points: impl Iterator<Item = Point<f64, DIMENSIONS>>) -> Vec<ArrayRef> {
+    let points: Vec<_> = points.collect();
+    (0..DIMENSIONS)
+        .map(|i| {
+            Arc::new(Float64Array::from_iter_values(
+                points.iter().map(|p| p.nth(i)),
+            )) as ArrayRef
+        })
+        .collect()
+}
+
+/// Points of the coordinate columns starting at `offset`
+fn points(batch: &RecordBatch, offset: usize) -> impl Iterator<Item = Point<f64, DIMENSIONS>> + '_ {
+    let columns: Vec<&Float64Array> = (offset..offset + DIMENSIONS)
+        .map(|i| batch.column(i).as_primitive::<Float64Type>())
+        .collect();
+    (0..batch.num_rows()).map(move |row| Point::generate(|i| columns[i].value(row)))
+}
+
+fn write_records(
+    path: &Path,
+    kind: &str,
+    version: u64,
+    batch: &RecordBatch,
+) -> Result<(), PointCloudError> {
+    let file = BufWriter::new(File::create(path).map_err(ArrowError::from)?);
+    let mut writer = FileWriter::try_new(file, &batch.schema())?;
+    writer.write_metadata(FORMAT_KEY, FORMAT);
+    writer.write_metadata(KIND_KEY, kind);
+    writer.write_metadata(VERSION_KEY, version.to_string());
+    writer.write(batch)?;
+    writer.finish()?;
+    Ok(())
+}
+
+/// Records of the index file at `path`, `None` if stale
+fn read_records(
+    path: &Path,
+    kind: &str,
+    version: u64,
+    schema: SchemaRef,
+) -> Result<Option<RecordBatch>, PointCloudError> {
+    let reader = FileReader::try_new(File::open(path).map_err(ArrowError::from)?, None)?;
+    let metadata = reader.custom_metadata();
+    let matches = |key, value: &str| metadata.get(key).is_some_and(|v| v == value);
+    if !matches(FORMAT_KEY, FORMAT)
+        || !matches(KIND_KEY, kind)
+        || !matches(VERSION_KEY, &version.to_string())
+    {
+        return Ok(None);
+    }
+    if reader.schema() != schema {
+        return Err(PointCloudError::SchemaError(format!(
+            "unexpected schema of index file {path:?}"
+        )));
+    }
+
+    let batches = reader.collect::<Result<Vec<_>, ArrowError>>()?;
+    let batch = concat_batches(&schema, &batches)?;
+    if batch.columns().iter().any(|c| c.null_count() > 0) {
+        return Err(PointCloudError::SchemaError(format!(
+            "null values in index file {path:?}"
+        )));
+    }
+    Ok(Some(batch))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{PointCloudTrait, PointTrait, Synthetic};
+
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        let dir = tempfile::tempdir().unwrap();
+        let pc = Synthetic::new(2_000).batch_size(250).terrain().unwrap();
+        assert_eq!(pc.store.len(), 8);
+        let queries: Vec<Point<f64, DIMENSIONS>> = (0..20)
+            .map(|i| Point::from_slice(&[i as f64 * 5., 100. - i as f64 * 3., 1., 0.]))
+            .collect();
+
+        // segments around the queries
+        let index = pc.batch_index(&()).unwrap();
+        let path = dir.path().join("batch.index");
+        index.write(&path, 3).unwrap();
+        let read = BatchIndex::read(&path, &pc, 3).unwrap().unwrap();
+        assert_eq!(read.size(), index.size());
+        for q in &queries {
+            let around = AABB::from_corners(
+                Point::generate(|i| q.nth(i) - 10.),
+                Point::generate(|i| q.nth(i) + 10.),
+            );
+            let intersecting = |index: &BatchIndex| {
+                let mut keys: Vec<String> = index
+                    .locate_in_envelope_intersecting(&around)
+                    .map(|e| e.data.clone())
+                    .collect();
+                keys.sort();
+                keys
+            };
+            assert_eq!(intersecting(&read), intersecting(&index));
+        }
+
+        // nearest points
+        let index = PointIndex::bulk_load_with_params(
+            pc.points::<Point<f64, DIMENSIONS>>()
+                .enumerate()
+                .map(|(i, p)| GeomWithData::new(p, i as u64))
+                .collect(),
+        );
+        let path = dir.path().join("point.index");
+        index.write(&path, 3).unwrap();
+        let read = PointIndex::read(&path, &pc, 3).unwrap().unwrap();
+        assert_eq!(read.size(), pc.num_points());
+        for q in &queries {
+            let nearest = |index: &PointIndex| {
+                index
+                    .nearest_neighbor_iter(q)
+                    .take(10)
+                    .map(|e| e.data)
+                    .collect::<Vec<_>>()
+            };
+            assert_eq!(nearest(&read), nearest(&index));
+        }
+    }
+
+    #[test]
+    fn stale() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("index");
+        let pc = Synthetic::new(100).batch_size(10).terrain().unwrap();
+        pc.batch_index(&()).unwrap().write(&path, 1).unwrap();
+
+        // other versions and kinds
+        assert!(BatchIndex::read(&path, &pc, 2).unwrap().is_none());
+        assert!(PointIndex::read(&path, &pc, 1).unwrap().is_none());
+        assert!(BatchIndex::read(&path, &pc, 1).unwrap().is_some());
+        assert_eq!(content_version(&path).unwrap(), Some(1));
+
+        // keys missing in the point cloud
+        let key = pc.store.iter().next().unwrap().key().to_owned();
+        pc.store.remove(&key).unwrap();
+        assert!(BatchIndex::read(&path, &pc, 1).unwrap().is_none());
+
+        // missing files fail, empty indices are fine
+        assert!(BatchIndex::read(&dir.path().join("missing"), &pc, 1).is_err());
+        BatchIndex::new_with_params().write(&path, 1).unwrap();
+        assert_eq!(BatchIndex::read(&path, &pc, 1).unwrap().unwrap().size(), 0);
+    }
+}
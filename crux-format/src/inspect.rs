@@ -0,0 +1,406 @@
+//! Point count and extent of point cloud files without reading their points.
+//!
+//! [inspect_with] reads only the parts of a file that describe it through a
+//! [RangeRead], e.g. ranges of an object in a remote store: the header of
+//! LAS and LAZ files, the footer and the headers of the record batches of
+//! Arrow IPC files and the manifest of the collections persisted by the
+//! server. Fields that are not recorded are estimated or left out, see
+//! [Estimated].
+
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+    ops::Range,
+    path::Path,
+};
+
+use arrow::ipc::{convert::fb_to_schema, root_as_footer, root_as_message};
+use serde::{Deserialize, Serialize};
+
+use crate::PointCloudError;
+
+/// Bytes read from the start of a file, the size of a LAS 1.4 header
+const HEAD_SIZE: u64 = 375;
+/// Magic bytes at the start and end of Arrow IPC files
+const IPC_MAGIC: &[u8; 6] = b"ARROW1";
+/// Record batch headers read from an IPC file, the counts of files with more
+/// batches are extrapolated from a sample
+pub const MAX_BLOCKS: usize = 256;
+
+/// Source of byte ranges, like a local file or an object in a store
+pub trait RangeRead {
+    /// Size in bytes
+    fn size(&self) -> std::io::Result<u64>;
+
+    /// Bytes in `range`, fewer if the source ends within it
+    fn read_range(&self, range: Range<u64>) -> std::io::Result<Vec<u8>>;
+}
+
+impl RangeRead for File {
+    fn size(&self) -> std::io::Result<u64> {
+        Ok(self.metadata()?.len())
+    }
+
+    fn read_range(&self, range: Range<u64>) -> std::io::Result<Vec<u8>> {
+        let mut file = self;
+        file.seek(SeekFrom::Start(range.start))?;
+        let mut bytes = Vec::new();
+        file.take(range.end.saturating_sub(range.start))
+            .read_to_end(&mut bytes)?;
+        Ok(bytes)
+    }
+}
+
+impl RangeRead for [u8] {
+    fn size(&self) -> std::io::Result<u64> {
+        Ok(self.len() as u64)
+    }
+
+    fn read_range(&self, range: Range<u64>) -> std::io::Result<Vec<u8>> {
+        let end = (range.end as usize).min(self.len());
+        Ok(self[(range.start as usize).min(end)..end].to_vec())
+    }
+}
+
+/// Format of an inspected file
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum InspectFormat {
+    Las,
+    Laz,
+    /// Arrow IPC file
+    Ipc,
+    /// Manifest of a collection persisted by the server
+    Collection,
+}
+
+/// A value recorded in the file or estimated from a part of it
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Estimated<T> {
+    pub value: T,
+    pub exact: bool,
+}
+
+impl<T> Estimated<T> {
+    fn exact(value: T) -> Self {
+        Self { value, exact: true }
+    }
+
+    fn estimate(value: T) -> Self {
+        Self {
+            value,
+            exact: false,
+        }
+    }
+}
+
+/// What the metadata of a file tells about its points
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct InspectReport {
+    pub format: InspectFormat,
+    /// Number of points, unknown for manifests of older servers
+    pub num_points: Option<Estimated<u64>>,
+    /// Lower and upper corner of the points, as recorded in LAS headers and
+    /// manifests, unknown for IPC files
+    pub bounds: Option<Estimated<[[f64; 3]; 2]>>,
+    /// Columns of IPC files
+    pub columns: Vec<String>,
+    /// Point data record format of LAS files
+    pub point_format: Option<u8>,
+}
+
+/// Store entries and content version of a collection persisted to an object
+/// store, written by the server as `MANIFEST.json`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Manifest {
+    /// Content version of the collection
+    pub version: u64,
+    /// Store entries in iteration order with the size of their segment files
+    pub segments: Vec<(String, u64)>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub num_points: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bounds: Option<[[f64; 3]; 2]>,
+    /// Named queries of the collection, as defined on the server
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub views: BTreeMap<String, serde_json::Value>,
+}
+
+/// Inspect the file at `path`, see [inspect_with]
+pub fn inspect(path: impl AsRef<Path>) -> Result<InspectReport, PointCloudError> {
+    inspect_with(&File::open(path)?)
+}
+
+/// Inspect a LAS, LAZ or Arrow IPC file or a collection manifest by its
+/// metadata, recognized by the first bytes.
+///
+/// Reads a few KiB: the header of LAS files, and the footer and up to
+/// [MAX_BLOCKS] record batch headers of IPC files.
+pub fn inspect_with<R: RangeRead + ?Sized>(source: &R) -> Result<InspectReport, PointCloudError> {
+    let size = source.size()?;
+    let head = source.read_range(0..HEAD_SIZE.min(size))?;
+
+    if head.starts_with(b"LASF") {
+        las(&head)
+    } else if head.starts_with(IPC_MAGIC) {
+        ipc(source, size)
+    } else if head.first() == Some(&b'{') {
+        let manifest = source.read_range(0..size)?;
+        let manifest: Manifest = serde_json::from_slice(&manifest)
+            .map_err(|e| PointCloudError::InvalidArgument(format!("invalid manifest: {e}")))?;
+        Ok(InspectReport {
+            format: InspectFormat::Collection,
+            num_points: manifest.num_points.map(Estimated::exact),
+            bounds: manifest.bounds.map(Estimated::exact),
+            columns: Vec::new(),
+            point_format: None,
+        })
+    } else {
+        Err(PointCloudError::InvalidArgument(
+            "not a LAS, LAZ or Arrow IPC file or a manifest".to_string(),
+        ))
+    }
+}
+
+/// Report of the public header block of a LAS file
+fn las(head: &[u8]) -> Result<InspectReport, PointCloudError> {
+    let truncated = || PointCloudError::InvalidArgument("truncated LAS header".to_string());
+    let bytes = |offset: usize, n: usize| head.get(offset..offset + n).ok_or_else(truncated);
+    let u32_at = |offset| -> Result<u32, PointCloudError> {
+        Ok(u32::from_le_bytes(bytes(offset, 4)?.try_into().unwrap()))
+    };
+    let f64_at = |offset| -> Result<f64, PointCloudError> {
+        Ok(f64::from_le_bytes(bytes(offset, 8)?.try_into().unwrap()))
+    };
+
+    let minor = bytes(25, 1)?[0];
+    // the upper bits flag compression
+    let format_id = bytes(104, 1)?[0];
+    let mut num_points = u32_at(107)? as u64;
+    if minor >= 4 && num_points == 0 {
+        num_points = u64::from_le_bytes(bytes(247, 8)?.try_into().unwrap());
+    }
+
+    // maximum before minimum per axis
+    let (mut lower, mut upper) = ([0.; 3], [0.; 3]);
+    for (d, (min, max)) in lower.iter_mut().zip(&mut upper).enumerate() {
+        *max = f64_at(179 + 16 * d)?;
+        *min = f64_at(187 + 16 * d)?;
+    }
+    let bounds = [lower, upper];
+
+    Ok(InspectReport {
+        format: match format_id & 0xc0 {
+            0 => InspectFormat::Las,
+            _ => InspectFormat::Laz,
+        },
+        num_points: Some(Estimated::exact(num_points)),
+        bounds: (num_points > 0).then_some(Estimated::exact(bounds)),
+        columns: Vec::new(),
+        point_format: Some(format_id & 0x3f),
+    })
+}
+
+/// Report of the footer of an IPC file, with the rows of its record batches
+/// read from their headers
+fn ipc<R: RangeRead + ?Sized>(source: &R, size: u64) -> Result<InspectReport, PointCloudError> {
+    let invalid =
+        |detail: &str| PointCloudError::InvalidArgument(format!("invalid IPC file: {detail}"));
+
+    // footer, its length and the magic bytes
+    let tail = source.read_range(size.saturating_sub(10)..size)?;
+    if tail.len() != 10 || &tail[4..] != IPC_MAGIC {
+        return Err(invalid("missing footer"));
+    }
+    let footer_len = i32::from_le_bytes(tail[..4].try_into().unwrap()) as u64;
+    let footer_start = size
+        .checked_sub(10 + footer_len)
+        .ok_or_else(|| invalid("footer length"))?;
+    let footer = source.read_range(footer_start..size - 10)?;
+    let footer = root_as_footer(&footer).map_err(|e| invalid(&e.to_string()))?;
+
+    let schema = footer
+        .schema()
+        .map(fb_to_schema)
+        .ok_or_else(|| invalid("missing schema"))?;
+    let blocks = footer
+        .recordBatches()
+        .map(|b| b.iter().copied().collect::<Vec<_>>())
+        .unwrap_or_default();
+
+    // every block if there are few, else evenly spaced ones
+    let step = blocks.len().div_ceil(MAX_BLOCKS).max(1);
+    let (mut rows, mut body) = (0u64, 0u64);
+    for block in blocks.iter().step_by(step) {
+        let start = block.offset() as u64;
+        let header = source.read_range(start..start + block.metaDataLength() as u64)?;
+        // continuation marker and length of the message
+        let message = match header.get(..4) {
+            Some([0xff, 0xff, 0xff, 0xff]) => header.get(8..),
+            _ => header.get(4..),
+        }
+        .ok_or_else(|| invalid("truncated block"))?;
+        let batch = root_as_message(message)
+            .map_err(|e| invalid(&e.to_string()))?
+            .header_as_record_batch()
+            .ok_or_else(|| invalid("block is no record batch"))?;
+        rows += batch.length() as u64;
+        body += block.bodyLength() as u64;
+    }
+
+    let num_points = if step == 1 {
+        Estimated::exact(rows)
+    } else {
+        let total: u64 = blocks.iter().map(|b| b.bodyLength() as u64).sum();
+        let estimate = match body {
+            0 => 0,
+            body => (rows as f64 * total as f64 / body as f64).round() as u64,
+        };
+        Estimated::estimate(estimate)
+    };
+
+    Ok(InspectReport {
+        format: InspectFormat::Ipc,
+        num_points: Some(num_points),
+        bounds: None,
+        columns: schema
+            .fields()
+            .iter()
+            .map(|f| f.name().to_owned())
+            .collect(),
+        point_format: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::Cell, sync::Arc};
+
+    use arrow::{array::Float64Array, ipc::writer::FileWriter, record_batch::RecordBatch};
+
+    use super::*;
+    use crate::{Point, PointTrait};
+
+    /// Source counting the bytes read
+    struct Counting<'a>(&'a [u8], Cell<u64>);
+
+    impl RangeRead for Counting<'_> {
+        fn size(&self) -> std::io::Result<u64> {
+            self.0.size()
+        }
+
+        fn read_range(&self, range: Range<u64>) -> std::io::Result<Vec<u8>> {
+            let bytes = self.0.read_range(range)?;
+            self.1.set(self.1.get() + bytes.len() as u64);
+            Ok(bytes)
+        }
+    }
+
+    /// IPC file of `batches` batches of `rows` points
+    fn ipc_file(batches: usize, rows: usize) -> Vec<u8> {
+        let schema = Point::<f64, 3>::schema();
+        let mut writer = FileWriter::try_new(Vec::new(), &schema).unwrap();
+        for _ in 0..batches {
+            let column = Arc::new(Float64Array::from_iter_values((0..rows).map(|i| i as f64)));
+            let batch =
+                RecordBatch::try_new(schema.clone(), vec![column.clone(), column.clone(), column])
+                    .unwrap();
+            writer.write(&batch).unwrap();
+        }
+        writer.finish().unwrap();
+        writer.into_inner().unwrap()
+    }
+
+    /// LAS 1.4 header of `num_points` points of format 6 in the 64-bit count,
+    /// followed by their records
+    fn las_file(num_points: u64, compressed: bool) -> Vec<u8> {
+        let mut bytes = vec![0u8; HEAD_SIZE as usize];
+        bytes[..4].copy_from_slice(b"LASF");
+        bytes[24] = 1;
+        bytes[25] = 4;
+        bytes[104] = 6 | if compressed { 0x80 } else { 0 };
+        bytes[247..255].copy_from_slice(&num_points.to_le_bytes());
+        for (d, (lower, upper)) in [(1., 2.), (3., 4.), (5., 6.)].into_iter().enumerate() {
+            bytes[179 + 16 * d..187 + 16 * d].copy_from_slice(&f64::to_le_bytes(upper));
+            bytes[187 + 16 * d..195 + 16 * d].copy_from_slice(&f64::to_le_bytes(lower));
+        }
+        bytes.resize(bytes.len() + 30 * num_points as usize, 0);
+        bytes
+    }
+
+    #[test]
+    fn las() {
+        let bytes = las_file(10_000, false);
+        let source = Counting(&bytes, Cell::new(0));
+        let report = inspect_with(&source).unwrap();
+        assert_eq!(report.format, InspectFormat::Las);
+        assert_eq!(report.num_points, Some(Estimated::exact(10_000)));
+        assert_eq!(report.bounds.unwrap().value, [[1., 3., 5.], [2., 4., 6.]]);
+        assert_eq!(report.point_format, Some(6));
+        assert_eq!(source.1.get(), HEAD_SIZE);
+
+        let report = inspect_with(las_file(0, true).as_slice()).unwrap();
+        assert_eq!(report.format, InspectFormat::Laz);
+        assert_eq!(report.bounds, None);
+
+        assert!(inspect_with(&bytes[..100]).is_err());
+        assert!(inspect_with(b"x,y,z\n".as_slice()).is_err());
+    }
+
+    #[test]
+    fn ipc() {
+        let bytes = ipc_file(100, 1000);
+        let source = Counting(&bytes, Cell::new(0));
+        let report = inspect_with(&source).unwrap();
+        assert_eq!(report.format, InspectFormat::Ipc);
+        assert_eq!(report.num_points, Some(Estimated::exact(100_000)));
+        assert_eq!(report.columns, ["x", "y", "z"]);
+        assert_eq!(report.bounds, None);
+        // of about 2.4 MB
+        assert!(source.1.get() < 32 * 1024, "{}", source.1.get());
+
+        // extrapolated from a sample of the batches
+        let bytes = ipc_file(2 * MAX_BLOCKS + 1, 10);
+        let report = inspect_with(bytes.as_slice()).unwrap();
+        let num_points = report.num_points.unwrap();
+        assert!(!num_points.exact);
+        assert_eq!(num_points.value, (2 * MAX_BLOCKS as u64 + 1) * 10);
+
+        // streams have no footer
+        assert!(inspect_with(&bytes[..bytes.len() - 10]).is_err());
+    }
+
+    #[test]
+    fn manifest() {
+        let mut manifest = Manifest {
+            version: 7,
+            segments: vec![("a".to_owned(), 100)],
+            num_points: Some(10),
+            bounds: Some([[0.; 3], [1.; 3]]),
+            views: BTreeMap::new(),
+        };
+        let report = inspect_with(serde_json::to_vec(&manifest).unwrap().as_slice()).unwrap();
+        assert_eq!(report.format, InspectFormat::Collection);
+        assert_eq!(report.num_points, Some(Estimated::exact(10)));
+        assert_eq!(report.bounds.unwrap().value, [[0.; 3], [1.; 3]]);
+
+        // written by older servers
+        manifest.num_points = None;
+        manifest.bounds = None;
+        let bytes = serde_json::to_vec(&manifest).unwrap();
+        assert!(!String::from_utf8_lossy(&bytes).contains("bounds"));
+        let report = inspect_with(bytes.as_slice()).unwrap();
+        assert_eq!(report.num_points, None);
+    }
+
+    #[test]
+    fn file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("points.arrow");
+        std::fs::write(&path, ipc_file(3, 5)).unwrap();
+        let report = inspect(&path).unwrap();
+        assert_eq!(report.num_points, Some(Estimated::exact(15)));
+        assert!(inspect(dir.path().join("missing.las")).is_err());
+    }
+}
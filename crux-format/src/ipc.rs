@@ -0,0 +1,221 @@
+//! Reading Arrow IPC files and streams.
+//!
+//! The server responds with IPC streams, while pyarrow and pandas
+//! (`feather.write_feather`, Feather version 2) write IPC files with a footer.
+//! Files start with the [FILE_MAGIC], streams with the first message, so
+//! [ArrowPointCloud::from_ipc_bytes] and [ArrowPointCloud::from_ipc_path]
+//! read either without relying on the extension.
+
+use std::{
+    fs::File,
+    io::{BufReader, Cursor, Read, Seek},
+    path::Path,
+};
+
+use arrow::{error::ArrowError, ipc::reader::FileReader, record_batch::RecordBatch};
+
+use crate::{trace::span, ArrowPointCloud, PointCloudError};
+
+/// Magic bytes at the start and the end of Arrow IPC files
+pub const FILE_MAGIC: &[u8; 6] = b"ARROW1";
+
+/// Layout of Arrow IPC data
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IpcFormat {
+    /// Random access file format with a footer, e.g. Feather version 2
+    File,
+    /// Streaming format, e.g. responses of the server
+    Stream,
+}
+
+impl IpcFormat {
+    /// Format of data starting with `head`, a stream unless it starts with
+    /// the [FILE_MAGIC]
+    pub fn sniff(head: &[u8]) -> Self {
+        if head.starts_with(FILE_MAGIC) {
+            Self::File
+        } else {
+            Self::Stream
+        }
+    }
+}
+
+impl ArrowPointCloud {
+    /// Points of the Arrow IPC file at `path`
+    pub fn from_ipc_file(path: impl AsRef<Path>) -> Result<Self, PointCloudError> {
+        let file = File::open(path).map_err(ArrowError::from)?;
+        Self::from_file_reader(BufReader::new(file), |_, batch| Ok(batch))
+    }
+
+    /// Points of an Arrow IPC file or stream, see [IpcFormat::sniff]. The
+    /// checksums of streams are verified if they are followed by them.
+    pub fn from_ipc_bytes(bytes: impl AsRef<[u8]>) -> Result<Self, PointCloudError> {
+        Self::from_ipc_bytes_map(bytes, |_, batch| Ok(batch))
+    }
+
+    /// Points of an Arrow IPC file or stream like
+    /// [ArrowPointCloud::from_ipc_bytes], with every batch passed through
+    /// `map` with its index before it is stored.
+    ///
+    /// Batches are decoded one at a time, so that thinning them in `map`
+    /// never holds more than one batch at full resolution. Batches emptied
+    /// by `map` are not stored.
+    pub fn from_ipc_bytes_map(
+        bytes: impl AsRef<[u8]>,
+        map: impl FnMut(usize, RecordBatch) -> Result<RecordBatch, PointCloudError>,
+    ) -> Result<Self, PointCloudError> {
+        let bytes = bytes.as_ref();
+        match IpcFormat::sniff(bytes) {
+            IpcFormat::File => Self::from_file_reader(Cursor::new(bytes), map),
+            IpcFormat::Stream => Self::try_from_reader_map(bytes, map),
+        }
+    }
+
+    /// Points of the Arrow IPC file or stream at `path`, see
+    /// [ArrowPointCloud::from_ipc_bytes]
+    pub fn from_ipc_path(path: impl AsRef<Path>) -> Result<Self, PointCloudError> {
+        let mut file = File::open(path).map_err(ArrowError::from)?;
+        let mut head = [0; FILE_MAGIC.len()];
+        let read = file.read(&mut head).map_err(ArrowError::from)?;
+        file.rewind().map_err(ArrowError::from)?;
+
+        match IpcFormat::sniff(&head[..read]) {
+            IpcFormat::File => Self::from_file_reader(BufReader::new(file), |_, batch| Ok(batch)),
+            IpcFormat::Stream => Self::try_from_reader(BufReader::new(file)),
+        }
+    }
+
+    fn from_file_reader<R: Read + Seek>(
+        reader: R,
+        mut map: impl FnMut(usize, RecordBatch) -> Result<RecordBatch, PointCloudError>,
+    ) -> Result<Self, PointCloudError> {
+        let span = span!(DEBUG, "decode", rows);
+        let reader = FileReader::try_new(reader, None)?;
+        let mut pc = Self::try_new(reader.schema())?;
+        let mut rows = 0;
+        for (j, batch) in reader.enumerate() {
+            let batch = batch?;
+            let empty = batch.num_rows() == 0;
+            let batch = map(j, batch)?;
+            rows += batch.num_rows();
+            if empty || batch.num_rows() > 0 {
+                pc.append(batch)?;
+            }
+        }
+        span.record("rows", rows);
+        Ok(pc)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow::ipc::writer::{FileWriter, StreamWriter};
+
+    use super::*;
+    use crate::{synthetic::Synthetic, ChecksumWriter, PointCloudTrait};
+
+    /// The points in the file and the stream format, and in a stream
+    /// followed by checksums
+    fn fixtures(pc: &ArrowPointCloud) -> [Vec<u8>; 3] {
+        let batches: Vec<_> = pc
+            .store
+            .iter()
+            .flat_map(|e| pc.store.batches(e.key()))
+            .collect();
+
+        let mut file = FileWriter::try_new(Vec::new(), &pc.schema).unwrap();
+        let mut stream = StreamWriter::try_new(Vec::new(), &pc.schema).unwrap();
+        let mut checksums = ChecksumWriter::try_new(Vec::new(), &pc.schema).unwrap();
+        for batch in &batches {
+            file.write(batch).unwrap();
+            stream.write(batch).unwrap();
+            checksums.write(batch).unwrap();
+        }
+        file.finish().unwrap();
+        stream.finish().unwrap();
+        checksums.finish().unwrap();
+
+        [
+            file.into_inner().unwrap(),
+            stream.into_inner().unwrap(),
+            checksums.into_inner(),
+        ]
+    }
+
+    #[test]
+    fn formats() {
+        let pc = Synthetic::new(1000)
+            .intensity(true)
+            .batch_size(300)
+            .terrain()
+            .unwrap();
+        let digest = pc.digest();
+        let [file, stream, checksums] = fixtures(&pc);
+
+        assert_eq!(IpcFormat::sniff(&file), IpcFormat::File);
+        assert_eq!(IpcFormat::sniff(&stream), IpcFormat::Stream);
+        assert_eq!(IpcFormat::sniff(&[]), IpcFormat::Stream);
+
+        for bytes in [&file, &stream, &checksums] {
+            assert_eq!(
+                ArrowPointCloud::from_ipc_bytes(bytes).unwrap().digest(),
+                digest
+            );
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let (feather, arrows) = (dir.path().join("a.feather"), dir.path().join("a.arrows"));
+        std::fs::write(&feather, &file).unwrap();
+        std::fs::write(&arrows, &stream).unwrap();
+        assert_eq!(
+            ArrowPointCloud::from_ipc_file(&feather).unwrap().digest(),
+            digest
+        );
+        assert_eq!(
+            ArrowPointCloud::from_ipc_path(&feather).unwrap().digest(),
+            digest
+        );
+        assert_eq!(
+            ArrowPointCloud::from_ipc_path(&arrows).unwrap().digest(),
+            digest
+        );
+
+        // a stream is no file, a truncated file neither
+        assert!(ArrowPointCloud::from_ipc_file(&arrows).is_err());
+        assert!(ArrowPointCloud::from_ipc_bytes(&file[..file.len() / 2]).is_err());
+    }
+
+    #[test]
+    fn map() {
+        let pc = Synthetic::new(1000).batch_size(300).uniform().unwrap();
+        for bytes in fixtures(&pc) {
+            let mut indices = Vec::new();
+            let halved = ArrowPointCloud::from_ipc_bytes_map(&bytes, |j, batch| {
+                indices.push(j);
+                Ok(batch.slice(0, batch.num_rows() / 2))
+            })
+            .unwrap();
+            assert_eq!(indices, [0, 1, 2, 3]);
+            assert_eq!(halved.num_points(), 150 * 3 + 50);
+
+            let first = ArrowPointCloud::from_ipc_bytes_map(&bytes, |j, batch| {
+                Ok(batch.slice(0, if j == 0 { batch.num_rows() } else { 0 }))
+            })
+            .unwrap();
+            assert_eq!(first.num_points(), 300);
+            assert_eq!(
+                first
+                    .store
+                    .iter()
+                    .map(|e| first.store.batches(e.key()).len())
+                    .sum::<usize>(),
+                1
+            );
+
+            let failed = ArrowPointCloud::from_ipc_bytes_map(&bytes, |_, _| {
+                Err(PointCloudError::InvalidArgument("thinning".to_owned()))
+            });
+            assert!(failed.is_err());
+        }
+    }
+}
@@ -0,0 +1,407 @@
+//! Legends of color maps as images, e.g. next to screenshots of the viewer.
+//!
+//! Gradients are drawn as a horizontal colorbar sampled from the
+//! [ColorMap] with tick labels at the stretch bounds and in between, classes
+//! as a list of swatches. The [Mapping] records the parameters of the color
+//! map for reports, see [Mapping::new].
+
+use std::{collections::BTreeMap, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    color::{Bounds, ColorMap, Normalization, Palette, Rgba, Stretch},
+    png,
+};
+
+/// Samples of the gradient, the width of the colorbar in pixels
+pub const COLORBAR_SAMPLES: u32 = 256;
+/// Positions of the ticks on the colorbar
+pub const TICKS: [f64; 5] = [0., 0.25, 0.5, 0.75, 1.];
+
+/// Height of the colorbar
+const BAR_HEIGHT: u32 = 24;
+/// Length of the tick marks below the colorbar
+const TICK_LENGTH: u32 = 4;
+/// Edge of the swatches of classes
+const SWATCH: u32 = 16;
+/// Margin around the legend and between its parts
+const MARGIN: u32 = 4;
+/// Pixels per font pixel
+const FONT_SCALE: u32 = 2;
+
+const BACKGROUND: Rgba = [255, 255, 255, 255];
+const FOREGROUND: Rgba = [0, 0, 0, 255];
+
+/// Glyphs of the tick and class labels, 3 x 5 pixels by rows
+const GLYPHS: [(char, [&str; 5]); 14] = [
+    ('0', ["###", "#.#", "#.#", "#.#", "###"]),
+    ('1', [".#.", "##.", ".#.", ".#.", "###"]),
+    ('2', ["###", "..#", "###", "#..", "###"]),
+    ('3', ["###", "..#", "###", "..#", "###"]),
+    ('4', ["#.#", "#.#", "###", "..#", "..#"]),
+    ('5', ["###", "#..", "###", "..#", "###"]),
+    ('6', ["###", "#..", "###", "#.#", "###"]),
+    ('7', ["###", "..#", "..#", "..#", "..#"]),
+    ('8', ["###", "#.#", "###", "#.#", "###"]),
+    ('9', ["###", "#.#", "###", "..#", "###"]),
+    ('-', ["...", "...", "###", "...", "..."]),
+    ('+', ["...", ".#.", "###", ".#.", "..."]),
+    ('.', ["...", "...", "...", "...", ".#."]),
+    ('e', ["...", "###", "###", "#..", "###"]),
+];
+
+/// RGBA image in row major order
+#[derive(Debug, Clone, PartialEq)]
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub rgba: Vec<u8>,
+}
+
+impl Image {
+    fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            rgba: BACKGROUND.repeat((width * height) as usize),
+        }
+    }
+
+    /// Color of the pixel at `x`, `y`
+    pub fn pixel(&self, x: u32, y: u32) -> Rgba {
+        let i = ((y * self.width + x) * 4) as usize;
+        self.rgba[i..i + 4].try_into().unwrap()
+    }
+
+    fn fill(&mut self, x: u32, y: u32, width: u32, height: u32, color: Rgba) {
+        for y in y..(y + height).min(self.height) {
+            for x in x..(x + width).min(self.width) {
+                let i = ((y * self.width + x) * 4) as usize;
+                self.rgba[i..i + 4].copy_from_slice(&color);
+            }
+        }
+    }
+
+    /// Draw `text` with its top left corner at `x`, `y`, characters without
+    /// a glyph are left blank
+    fn text(&mut self, x: u32, y: u32, text: &str) {
+        for (i, c) in text.chars().enumerate() {
+            let Some((_, rows)) = GLYPHS.iter().find(|(glyph, _)| *glyph == c) else {
+                continue;
+            };
+            let left = x + i as u32 * text_width(" ");
+            for (row, pixels) in rows.iter().enumerate() {
+                for (column, pixel) in pixels.chars().enumerate() {
+                    if pixel == '#' {
+                        let (px, py) = (
+                            left + column as u32 * FONT_SCALE,
+                            y + row as u32 * FONT_SCALE,
+                        );
+                        self.fill(px, py, FONT_SCALE, FONT_SCALE, FOREGROUND);
+                    }
+                }
+            }
+        }
+    }
+
+    /// PNG file of the image
+    pub fn png(&self) -> Vec<u8> {
+        png::encode_rgba(self.width, self.height, &self.rgba)
+    }
+}
+
+/// Width of `text` in pixels, with a pixel of space after each character
+fn text_width(text: &str) -> u32 {
+    text.chars().count() as u32 * 4 * FONT_SCALE
+}
+
+/// Height of a line of text in pixels
+const TEXT_HEIGHT: u32 = 5 * FONT_SCALE;
+
+/// Attribute value at `position` on a gradient spanning `bounds`, the inverse
+/// of [Stretch::apply] within the bounds
+pub fn value_at(stretch: &Stretch, (lower, upper): Bounds, position: f64) -> f64 {
+    let t = match stretch {
+        Stretch::Percentile(normalization) => position.powf(normalization.gamma),
+        _ => position,
+    };
+    lower + (upper - lower) * t
+}
+
+/// Label of a tick value with four significant digits
+pub fn label(value: f64) -> String {
+    if value == 0. || !value.is_finite() {
+        return format!("{value}");
+    }
+    let magnitude = value.abs().log10().floor() as i32;
+    if !(-3..6).contains(&magnitude) {
+        return format!("{value:.2e}");
+    }
+    let precision = (3 - magnitude).max(0) as usize;
+    format!("{value:.precision$}")
+}
+
+/// Legend image of `map`, a colorbar spanning `bounds` for gradients and
+/// swatches for classes
+pub fn legend(map: &ColorMap, bounds: Option<Bounds>) -> Image {
+    match map {
+        ColorMap::Categorical(palette) => swatches(palette),
+        ColorMap::Gradient { normalization, .. } => {
+            colorbar(map, normalization, bounds.unwrap_or((0., 1.)))
+        }
+    }
+}
+
+/// Horizontal colorbar of [COLORBAR_SAMPLES] samples of the gradient with
+/// labelled ticks at [TICKS]
+fn colorbar(map: &ColorMap, stretch: &Stretch, bounds: Bounds) -> Image {
+    let labels: Vec<String> = TICKS
+        .iter()
+        .map(|position| label(value_at(stretch, bounds, *position)))
+        .collect();
+    // the outer labels are centered on their ticks as far as possible
+    let overhang = labels
+        .iter()
+        .map(|label| text_width(label) / 2)
+        .max()
+        .unwrap_or(0);
+    let left = MARGIN + overhang;
+    let width = left + COLORBAR_SAMPLES + overhang + MARGIN;
+    let height = MARGIN + BAR_HEIGHT + TICK_LENGTH + MARGIN + TEXT_HEIGHT + MARGIN;
+    let mut image = Image::new(width, height);
+
+    for x in 0..COLORBAR_SAMPLES {
+        let position = x as f64 / (COLORBAR_SAMPLES - 1) as f64;
+        let value = value_at(stretch, bounds, position);
+        let color = map.color(value, Some(bounds)).unwrap_or(FOREGROUND);
+        image.fill(left + x, MARGIN, 1, BAR_HEIGHT, color);
+    }
+
+    for (position, label) in TICKS.iter().zip(&labels) {
+        let x = left + (position * (COLORBAR_SAMPLES - 1) as f64).round() as u32;
+        image.fill(x, MARGIN + BAR_HEIGHT, 1, TICK_LENGTH, FOREGROUND);
+        let text_x = x.saturating_sub(text_width(label) / 2).max(MARGIN);
+        let text_y = MARGIN + BAR_HEIGHT + TICK_LENGTH + MARGIN;
+        image.text(text_x, text_y, label);
+    }
+    image
+}
+
+/// Swatches of the classes of `palette` with their numbers, one per row
+fn swatches(palette: &Palette) -> Image {
+    let labels: Vec<String> = palette.classes.keys().map(i64::to_string).collect();
+    let label_width = labels
+        .iter()
+        .map(|label| text_width(label))
+        .max()
+        .unwrap_or(0);
+    let width = MARGIN + SWATCH + MARGIN + label_width + MARGIN;
+    let height = MARGIN + palette.classes.len() as u32 * (SWATCH + MARGIN);
+    let mut image = Image::new(width, height.max(2 * MARGIN));
+
+    for (row, (color, label)) in palette.classes.values().zip(&labels).enumerate() {
+        let y = MARGIN + row as u32 * (SWATCH + MARGIN);
+        image.fill(MARGIN, y, SWATCH, SWATCH, *color);
+        image.text(
+            MARGIN + SWATCH + MARGIN,
+            y + (SWATCH - TEXT_HEIGHT) / 2,
+            label,
+        );
+    }
+    image
+}
+
+/// Parameters of a color map, written next to its legend
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Mapping {
+    pub attribute: String,
+    /// Name of the gradient, e.g. `turbo` or the stem of a palette file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gradient: Option<String>,
+    /// Attribute values at the start and end of the gradient
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bounds: Option<Bounds>,
+    /// Percentile stretch and gamma of the bounds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub normalization: Option<Normalization>,
+    /// Attribute values at the ticks of the colorbar
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ticks: Vec<f64>,
+    /// Colors of the classes
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub palette: BTreeMap<i64, Rgba>,
+    /// Color of classes without an entry in the palette
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub other: Option<Rgba>,
+}
+
+impl Mapping {
+    /// Parameters of `map` for `attribute` with the gradient of the `palette`
+    /// file, turbo if `None`, and the stretch `bounds` of gradients
+    pub fn new(
+        attribute: &str,
+        map: &ColorMap,
+        palette: Option<&Path>,
+        bounds: Option<Bounds>,
+    ) -> Self {
+        let mut mapping = Self {
+            attribute: attribute.to_owned(),
+            gradient: None,
+            bounds: None,
+            normalization: None,
+            ticks: Vec::new(),
+            palette: BTreeMap::new(),
+            other: None,
+        };
+        match map {
+            ColorMap::Categorical(classes) => {
+                mapping.palette = classes.classes.clone();
+                mapping.other = Some(classes.other);
+            }
+            ColorMap::Gradient { normalization, .. } => {
+                mapping.gradient = Some(gradient_name(attribute, palette));
+                mapping.normalization = match normalization {
+                    Stretch::Percentile(normalization) => Some(*normalization),
+                    _ => None,
+                };
+                if let Some(bounds) = bounds {
+                    mapping.bounds = Some(bounds);
+                    mapping.ticks = TICKS
+                        .iter()
+                        .map(|position| value_at(normalization, bounds, *position))
+                        .collect();
+                }
+            }
+        }
+        mapping
+    }
+}
+
+/// Name of the gradient [ColorMap::for_attribute] picks for `attribute`
+fn gradient_name(attribute: &str, palette: Option<&Path>) -> String {
+    match (attribute, palette) {
+        ("intensity", _) => "grayscale".to_owned(),
+        ("delta", _) => "diverging".to_owned(),
+        (_, Some(path)) => path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "palette".to_owned()),
+        (_, None) => "turbo".to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::hash::Hasher;
+
+    use twox_hash::XxHash64;
+
+    use super::*;
+    use crate::color::gradient_from_colors;
+
+    fn hash(image: &Image) -> u64 {
+        let mut hasher = XxHash64::with_seed(0);
+        hasher.write(&image.rgba);
+        hasher.finish()
+    }
+
+    fn gray(normalization: Stretch) -> ColorMap {
+        ColorMap::Gradient {
+            gradient: gradient_from_colors(&["#000000", "#ffffff"]).unwrap(),
+            normalization,
+        }
+    }
+
+    #[test]
+    fn colorbar() {
+        let map = gray(Stretch::MinMax);
+        let image = legend(&map, Some((0., 100.)));
+
+        // the same pixels for the same mapping
+        assert_eq!(hash(&image), hash(&legend(&map, Some((0., 100.)))));
+        assert_ne!(hash(&image), hash(&legend(&map, Some((0., 10.)))));
+        assert_ne!(hash(&image), hash(&legend(&map, Some((100., 0.)))));
+
+        // black to white from the left to the right end of the bar
+        let left = (image.width - COLORBAR_SAMPLES) / 2;
+        let y = MARGIN + BAR_HEIGHT / 2;
+        assert_eq!(image.pixel(left, y), [0, 0, 0, 255]);
+        assert_eq!(
+            image.pixel(left + COLORBAR_SAMPLES - 1, y),
+            [255, 255, 255, 255]
+        );
+        assert_eq!(image.pixel(left - 1, y), BACKGROUND);
+        // tick marks below the bar
+        assert_eq!(image.pixel(left, MARGIN + BAR_HEIGHT), FOREGROUND);
+        assert_eq!(image.pixel(left + 1, MARGIN + BAR_HEIGHT), BACKGROUND);
+
+        let png = image.png();
+        assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+    }
+
+    #[test]
+    fn swatches() {
+        let palette = Palette::classification();
+        let map = ColorMap::Categorical(palette.clone());
+        let image = legend(&map, None);
+        assert_eq!(hash(&image), hash(&legend(&map, Some((0., 1.)))));
+
+        // one row per class in palette order
+        assert_eq!(
+            image.height,
+            MARGIN + palette.classes.len() as u32 * (SWATCH + MARGIN)
+        );
+        let center = MARGIN + SWATCH / 2;
+        assert_eq!(image.pixel(center, center), palette.color(0));
+        assert_eq!(
+            image.pixel(center, center + SWATCH + MARGIN),
+            palette.color(1)
+        );
+    }
+
+    #[test]
+    fn ticks() {
+        assert_eq!(value_at(&Stretch::MinMax, (0., 100.), 0.25), 25.);
+        assert_eq!(value_at(&Stretch::Fixed((100., 0.)), (100., 0.), 0.25), 75.);
+
+        // the inverse of the gamma of percentile stretches
+        let stretch = Stretch::Percentile(Normalization {
+            gamma: 2.,
+            ..Default::default()
+        });
+        let value = value_at(&stretch, (0., 100.), 0.5);
+        assert!((stretch.apply(value, (0., 100.)) - 0.5).abs() < 1e-12);
+
+        assert_eq!(label(0.), "0");
+        assert_eq!(label(1234.4), "1234");
+        assert_eq!(label(12.3456), "12.35");
+        assert_eq!(label(-0.5), "-0.5000");
+        assert_eq!(label(1.5e7), "1.50e7");
+        assert!(label(-2.5e-5)
+            .chars()
+            .all(|c| GLYPHS.iter().any(|(g, _)| *g == c)));
+    }
+
+    #[test]
+    fn mapping() {
+        let map =
+            ColorMap::for_attribute("intensity", colorgrad::turbo(), Normalization::default());
+        let mapping = Mapping::new("intensity", &map, None, Some((0., 100.)));
+        assert_eq!(mapping.gradient.as_deref(), Some("grayscale"));
+        assert_eq!(mapping.normalization, Some(Normalization::default()));
+        assert_eq!(mapping.ticks, [0., 25., 50., 75., 100.]);
+
+        let map = ColorMap::for_attribute("z", colorgrad::turbo(), Normalization::default());
+        let mapping = Mapping::new("z", &map, Some(Path::new("viridis.txt")), None);
+        assert_eq!(mapping.gradient.as_deref(), Some("viridis"));
+        assert!(mapping.ticks.is_empty());
+
+        let map = ColorMap::Categorical(Palette::classification());
+        let mapping = Mapping::new("classification", &map, None, None);
+        assert_eq!(mapping.palette[&2], [128, 128, 0, 255]);
+        let json = serde_json::to_string(&mapping).unwrap();
+        assert!(json.contains(r#""2":[128,128,0,255]"#), "{json}");
+        assert_eq!(serde_json::from_str::<Mapping>(&json).unwrap(), mapping);
+    }
+}
@@ -4,22 +4,117 @@ pub use aabb::AABB;
 pub mod aos;
 pub use aos::VecPointCloud;
 
+pub mod checksum;
+pub use checksum::ChecksumWriter;
+
+pub mod chunk;
+pub use chunk::Rechunker;
+
+pub mod color;
+
+pub mod columns;
+
 pub mod compute;
 
+pub mod coverage;
+
+pub mod crop;
+pub use crop::GridTileId;
+
+pub mod diff;
+pub use diff::{diff, diff_with};
+
+pub mod digest;
+pub use digest::{summarize_diff, CloudDigest, DiffSummary};
+
+pub mod encoding;
+pub use encoding::{ColumnEncoding, ColumnEncodings};
+
 pub mod framework;
 pub use framework::{Cell, Framework};
 
+pub mod frustum;
+pub use frustum::Frustum;
+
+pub mod glb;
+pub use glb::GlbOptions;
+
+pub mod index;
+pub use index::IndexFile;
+
+pub mod inspect;
+pub use inspect::{inspect, inspect_with, InspectReport};
+
+pub mod ipc;
+pub use ipc::IpcFormat;
+
+pub mod legend;
+
+#[cfg(any(feature = "nalgebra", feature = "glam"))]
+mod linalg;
+
+pub mod metadata;
+pub use metadata::{CloudMetadata, LengthUnit, MetadataPolicy};
+
+pub mod png;
+
 pub mod point;
 pub use point::{Coord, Point, PointTrait};
 
 pub mod pointcloud;
 pub use pointcloud::PointCloudTrait;
 
+pub mod polygon;
+
+pub mod prelude;
+
+pub mod profile;
+pub use profile::Profile;
+
+pub mod progress;
+pub use progress::{AtomicProgress, CancelToken, ProgressSink};
+
+pub mod query;
+pub use query::{Expr, Query, Sample};
+
+pub mod rows;
+pub use rows::PointRow;
+
+pub mod sample;
+
 pub mod schema;
+pub use schema::CoordSpec;
 
 pub mod soa;
 pub use soa::ArrowPointCloud;
 
+pub mod stats;
+pub use stats::{ColumnStats, DistinctCounts};
+
+pub mod synthetic;
+pub use synthetic::Synthetic;
+
+pub mod temporal;
+
+pub mod tiles;
+pub use tiles::{Tile, TileId, TileScheme, TileSummary};
+
+mod trace;
+
+pub mod trajectory;
+pub use trajectory::Trajectory;
+
+pub mod volume;
+pub use volume::{BaseSurface, VolumeReport};
+
+/// Arrow types of the builder API, e.g. [ArrowPointCloud::try_new] and
+/// [ArrowPointCloud::append], so that embeddings need not depend on arrow
+pub use arrow::{
+    datatypes::{Schema, SchemaRef},
+    error::ArrowError,
+    record_batch::RecordBatch,
+};
+
 #[derive(thiserror::Error, Debug)]
 pub enum PointCloudError {
     #[error("arrow error")]
@@ -28,4 +123,12 @@ pub enum PointCloudError {
     SchemaError(String),
     #[error("cache error: {0}")]
     CacheError(String),
+    #[error("io error: {0}")]
+    IoError(#[from] std::io::Error),
+    #[error("invalid argument: {0}")]
+    InvalidArgument(String),
+    #[error("operation cancelled")]
+    Cancelled,
+    #[error("checksum mismatch in batch {batch_index}")]
+    ChecksumMismatch { batch_index: usize },
 }
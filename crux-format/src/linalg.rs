@@ -0,0 +1,277 @@
+//! Coordinates as matrices and vectors of linear algebra crates.
+//!
+//! With the `nalgebra` feature, [ArrowPointCloud::to_matrix] and
+//! [ArrowPointCloud::from_matrix] convert the coordinates to and from an
+//! `n x d` [nalgebra::DMatrix], e.g. for registration. With the `glam`
+//! feature, [ArrowPointCloud::positions_glam] returns the positions as
+//! [glam::DVec3].
+//!
+//! Both copy. Arrow stores each coordinate in its own buffer and a point cloud
+//! may consist of many batches, while the matrix and the vectors own one
+//! allocation. The column-major layout of the matrix matches the columns of
+//! Arrow, so `f64` coordinates are copied per column rather than per point.
+//! Null coordinates become NaN.
+
+use arrow::{
+    array::{Array, AsArray},
+    compute::cast,
+    datatypes::{DataType, Float64Type},
+    record_batch::RecordBatch,
+};
+
+use crate::{schema, ArrowPointCloud, PointCloudError, PointCloudTrait};
+
+impl ArrowPointCloud {
+    /// Coordinates per coordinate column, in iteration order of the points
+    fn coordinate_columns(&self) -> Result<Vec<Vec<f64>>, PointCloudError> {
+        let batches: Vec<RecordBatch> = self
+            .store
+            .iter()
+            .flat_map(|e| self.store.batches(e.key()))
+            .collect();
+        let num_points = batches.iter().map(RecordBatch::num_rows).sum();
+
+        schema::coordinates(&self.schema())
+            .into_iter()
+            .map(|c| {
+                let mut values = Vec::with_capacity(num_points);
+                for batch in &batches {
+                    let column = cast(batch.column(c), &DataType::Float64)?;
+                    let column = column.as_primitive::<Float64Type>();
+                    if column.null_count() == 0 {
+                        values.extend_from_slice(column.values());
+                    } else {
+                        values.extend(column.iter().map(|v| v.unwrap_or(f64::NAN)));
+                    }
+                }
+                Ok(values)
+            })
+            .collect()
+    }
+}
+
+#[cfg(feature = "nalgebra")]
+mod matrix {
+    use std::sync::Arc;
+
+    use arrow::{
+        array::{new_null_array, ArrayRef, Float64Array},
+        compute::cast,
+        datatypes::SchemaRef,
+        record_batch::RecordBatch,
+    };
+    use nalgebra::DMatrix;
+
+    use crate::{schema, ArrowPointCloud, PointCloudError};
+
+    impl ArrowPointCloud {
+        /// Coordinates as `n x d` matrix of the `n` points in iteration order
+        /// and their `d` coordinates, the importance is not included
+        pub fn to_matrix(&self) -> Result<DMatrix<f64>, PointCloudError> {
+            let columns = self.coordinate_columns()?;
+            let rows = columns.first().map_or(0, Vec::len);
+            Ok(DMatrix::from_vec(rows, columns.len(), columns.concat()))
+        }
+
+        /// Point cloud of `schema` with the coordinates of the rows of `m`.
+        ///
+        /// The other columns, e.g. the importance and attributes, are taken
+        /// by name from `attributes`, which has a row per point. Columns of
+        /// `attributes` named like coordinates are ignored, so the points of
+        /// a point cloud can serve as attribute table of its transformed
+        /// coordinates. Missing columns are null, which fails for columns
+        /// that are not nullable.
+        pub fn from_matrix(
+            m: &DMatrix<f64>,
+            schema: SchemaRef,
+            attributes: Option<&RecordBatch>,
+        ) -> Result<Self, PointCloudError> {
+            let coordinates = schema::coordinates(&schema);
+            if m.ncols() != coordinates.len() {
+                return Err(PointCloudError::InvalidArgument(format!(
+                    "matrix of {} columns for {} coordinates",
+                    m.ncols(),
+                    coordinates.len()
+                )));
+            }
+            if let Some(attributes) = attributes.filter(|a| a.num_rows() != m.nrows()) {
+                return Err(PointCloudError::InvalidArgument(format!(
+                    "attribute table of {} rows for {} points",
+                    attributes.num_rows(),
+                    m.nrows()
+                )));
+            }
+
+            let columns = schema
+                .fields()
+                .iter()
+                .enumerate()
+                .map(|(i, field)| {
+                    if let Some(d) = coordinates.iter().position(|c| *c == i) {
+                        let column = Float64Array::from_iter_values(m.column(d).iter().copied());
+                        return Ok(cast(&column, field.data_type())?);
+                    }
+                    let column = attributes.and_then(|a| a.column_by_name(field.name()));
+                    Ok(match column {
+                        Some(column) => cast(column, field.data_type())?,
+                        None => new_null_array(field.data_type(), m.nrows()),
+                    })
+                })
+                .collect::<Result<Vec<ArrayRef>, PointCloudError>>()?;
+
+            let mut pc = ArrowPointCloud::try_new(schema.clone())?;
+            if m.nrows() > 0 {
+                pc.append(RecordBatch::try_new(Arc::clone(&schema), columns)?)?;
+            }
+            Ok(pc)
+        }
+    }
+}
+
+#[cfg(feature = "glam")]
+impl ArrowPointCloud {
+    /// Positions of the points in iteration order, without the importance.
+    /// Point clouds need at least three coordinates.
+    pub fn positions_glam(&self) -> Result<Vec<glam::DVec3>, PointCloudError> {
+        let columns = self.coordinate_columns()?;
+        let [x, y, z, ..] = &columns[..] else {
+            return Err(PointCloudError::InvalidArgument(format!(
+                "positions require 3 coordinates, got {}",
+                columns.len()
+            )));
+        };
+
+        Ok(x.iter()
+            .zip(y)
+            .zip(z)
+            .map(|((x, y), z)| glam::DVec3::new(*x, *y, *z))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow::{
+        array::{ArrayRef, Float32Array, UInt16Array},
+        datatypes::{Field, Schema},
+    };
+
+    use super::*;
+    use crate::CoordSpec;
+
+    /// Two batches of f32 coordinates with an intensity attribute and a null
+    /// height
+    fn cloud() -> ArrowPointCloud {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("x", DataType::Float32, false),
+            Field::new("y", DataType::Float32, false),
+            Field::new("z", DataType::Float32, true),
+            Field::new("intensity", DataType::UInt16, false),
+        ]));
+        let spec = CoordSpec::new(&["x", "y", "z"]);
+        let mut pc = ArrowPointCloud::try_new_with_coords(schema, &spec).unwrap();
+        for i in 0..2 {
+            let values = |offset: f32| {
+                Arc::new(Float32Array::from_iter_values(
+                    (0..3).map(|j| offset + (i * 3 + j) as f32),
+                )) as ArrayRef
+            };
+            let z = Float32Array::from(vec![Some(0.5), None, Some(2.5)]);
+            let batch = RecordBatch::try_new(
+                pc.schema(),
+                vec![
+                    values(0.),
+                    values(10.),
+                    Arc::new(z),
+                    Arc::new(UInt16Array::from(vec![100 * i as u16; 3])),
+                ],
+            )
+            .unwrap();
+            pc.append(batch).unwrap();
+        }
+        pc
+    }
+
+    #[test]
+    fn coordinate_columns() {
+        let columns = cloud().coordinate_columns().unwrap();
+        assert_eq!(columns.len(), 3);
+        assert_eq!(columns[0], [0., 1., 2., 3., 4., 5.]);
+        assert_eq!(columns[1], [10., 11., 12., 13., 14., 15.]);
+        assert!(columns[2][1].is_nan());
+    }
+
+    #[cfg(feature = "nalgebra")]
+    #[test]
+    fn matrix() {
+        use arrow::compute::concat_batches;
+        use nalgebra::{DMatrix, Rotation3, Vector3};
+
+        use crate::{Point, PointTrait};
+
+        let pc = cloud();
+        let m = pc.to_matrix().unwrap();
+        assert_eq!(m.shape(), (6, 3));
+        assert_eq!(m[(4, 0)], 4.);
+        assert_eq!(m[(4, 1)], 14.);
+        assert!(m[(4, 2)].is_nan());
+
+        // round trip with the points as attribute table
+        let batches: Vec<RecordBatch> = pc
+            .store
+            .iter()
+            .flat_map(|e| pc.store.batches(e.key()))
+            .collect();
+        let points = concat_batches(&pc.schema(), &batches).unwrap();
+        let decoded = ArrowPointCloud::from_matrix(&m, pc.schema(), Some(&points)).unwrap();
+        assert_eq!(decoded.num_points(), 6);
+        assert_eq!(decoded.schema(), pc.schema());
+        let decoded = decoded
+            .store
+            .batches(decoded.store.iter().next().unwrap().key());
+        assert_eq!(decoded[0].column(3), points.column(3));
+        assert_eq!(decoded[0].column(0), points.column(0));
+
+        // rigid transform, the attributes stay with their points
+        let rotation = Rotation3::from_axis_angle(&Vector3::z_axis(), std::f64::consts::FRAC_PI_2);
+        let rotation = DMatrix::from_column_slice(3, 3, rotation.matrix().as_slice());
+        let transformed = &m * rotation.transpose();
+        let rotated =
+            ArrowPointCloud::from_matrix(&transformed, pc.schema(), Some(&points)).unwrap();
+        let r = rotated.to_matrix().unwrap();
+        assert!((r[(2, 0)] + 12.).abs() < 1e-5);
+        assert!((r[(2, 1)] - 2.).abs() < 1e-5);
+        assert_eq!(r[(2, 2)], 2.5);
+
+        // without attributes, shapes are checked
+        assert!(ArrowPointCloud::from_matrix(&m, pc.schema(), None).is_err());
+        let bare = ArrowPointCloud::from_matrix(&m, Point::<f64, 3>::schema(), None).unwrap();
+        assert_eq!(bare.num_points(), 6);
+        assert!(ArrowPointCloud::from_matrix(&m.columns(0, 2).into(), pc.schema(), None).is_err());
+        assert!(ArrowPointCloud::from_matrix(&m, pc.schema(), Some(&points.slice(0, 2))).is_err());
+        assert_eq!(
+            ArrowPointCloud::from_matrix(&DMatrix::zeros(0, 3), pc.schema(), None)
+                .unwrap()
+                .num_points(),
+            0
+        );
+    }
+
+    #[cfg(feature = "glam")]
+    #[test]
+    fn positions_glam() {
+        let positions = cloud().positions_glam().unwrap();
+        assert_eq!(positions.len(), 6);
+        assert_eq!(positions[3], glam::DVec3::new(3., 13., 0.5));
+
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("x", DataType::Float64, false),
+            Field::new("y", DataType::Float64, false),
+        ]));
+        let pc =
+            ArrowPointCloud::try_new_with_coords(schema, &CoordSpec::new(&["x", "y"])).unwrap();
+        assert!(pc.positions_glam().is_err());
+    }
+}
@@ -0,0 +1,285 @@
+use std::{collections::HashMap, fmt, str::FromStr, sync::Arc};
+
+use arrow::{datatypes::Schema, record_batch::RecordBatch};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    schema::{CRUX_CRS_KEY, CRUX_UNITS_KEY, CRUX_VERTICAL_DATUM_KEY},
+    ArrowPointCloud, PointCloudError, PointCloudTrait,
+};
+
+/// Linear unit of the coordinates
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LengthUnit {
+    #[serde(rename = "m")]
+    Metre,
+    #[serde(rename = "ft")]
+    Foot,
+    #[serde(rename = "us-ft")]
+    UsSurveyFoot,
+}
+
+impl LengthUnit {
+    /// Length of the unit in metres
+    pub fn metres(&self) -> f64 {
+        match self {
+            Self::Metre => 1.,
+            Self::Foot => 0.3048,
+            Self::UsSurveyFoot => 1200. / 3937.,
+        }
+    }
+
+    /// Unit symbol
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            Self::Metre => "m",
+            Self::Foot => "ft",
+            Self::UsSurveyFoot => "us-ft",
+        }
+    }
+}
+
+impl fmt::Display for LengthUnit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.symbol())
+    }
+}
+
+impl FromStr for LengthUnit {
+    type Err = PointCloudError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "m" | "metre" | "meter" | "metres" | "meters" => Ok(Self::Metre),
+            "ft" | "foot" | "feet" => Ok(Self::Foot),
+            "us-ft" | "us survey foot" | "us survey feet" => Ok(Self::UsSurveyFoot),
+            _ => Err(PointCloudError::InvalidArgument(format!(
+                "unknown length unit `{s}`"
+            ))),
+        }
+    }
+}
+
+/// Spatial reference metadata of a point cloud
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct CloudMetadata {
+    /// Coordinate reference system, e.g. `EPSG:28992`
+    pub crs: Option<String>,
+    /// Linear unit of the coordinates
+    pub units: Option<LengthUnit>,
+    /// Vertical datum of the heights
+    pub vertical_datum: Option<String>,
+}
+
+impl CloudMetadata {
+    /// Read from schema metadata, ignoring unknown units
+    pub fn from_schema(schema: &Schema) -> Self {
+        let metadata = schema.metadata();
+        Self {
+            crs: metadata.get(CRUX_CRS_KEY).cloned(),
+            units: metadata
+                .get(CRUX_UNITS_KEY)
+                .and_then(|units| units.parse().ok()),
+            vertical_datum: metadata.get(CRUX_VERTICAL_DATUM_KEY).cloned(),
+        }
+    }
+
+    /// Write into schema metadata, removing unset entries
+    pub fn apply(&self, metadata: &mut HashMap<String, String>) {
+        for (key, value) in [
+            (CRUX_CRS_KEY, self.crs.clone()),
+            (CRUX_UNITS_KEY, self.units.map(|u| u.to_string())),
+            (CRUX_VERTICAL_DATUM_KEY, self.vertical_datum.clone()),
+        ] {
+            match value {
+                Some(value) => metadata.insert(key.to_owned(), value),
+                None => metadata.remove(key),
+            };
+        }
+    }
+
+    /// Entries set in both with different values
+    pub fn conflicts(&self, other: &Self) -> Vec<MetadataConflict> {
+        let mut conflicts = Vec::new();
+
+        let mut check = |key: &str, a: Option<String>, b: Option<String>| {
+            if let (Some(a), Some(b)) = (a, b) {
+                if a != b {
+                    conflicts.push(MetadataConflict {
+                        key: key.to_owned(),
+                        ours: a,
+                        theirs: b,
+                    });
+                }
+            }
+        };
+
+        check(CRUX_CRS_KEY, self.crs.clone(), other.crs.clone());
+        check(
+            CRUX_UNITS_KEY,
+            self.units.map(|u| u.to_string()),
+            other.units.map(|u| u.to_string()),
+        );
+        check(
+            CRUX_VERTICAL_DATUM_KEY,
+            self.vertical_datum.clone(),
+            other.vertical_datum.clone(),
+        );
+
+        conflicts
+    }
+
+    /// Fill unset entries from `other`
+    pub(crate) fn fill(&mut self, other: &Self) {
+        self.crs = self.crs.take().or_else(|| other.crs.clone());
+        self.units = self.units.or(other.units);
+        self.vertical_datum = self
+            .vertical_datum
+            .take()
+            .or_else(|| other.vertical_datum.clone());
+    }
+}
+
+/// Differing metadata entry of two merged point clouds
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MetadataConflict {
+    pub key: String,
+    pub ours: String,
+    pub theirs: String,
+}
+
+impl fmt::Display for MetadataConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "`{}`: `{}` vs. `{}`", self.key, self.ours, self.theirs)
+    }
+}
+
+/// Handling of conflicting metadata when merging point clouds
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MetadataPolicy {
+    /// Reject the merge
+    #[default]
+    Strict,
+    /// Keep the existing metadata and report the conflicts
+    Lenient,
+}
+
+impl ArrowPointCloud {
+    /// Spatial reference metadata
+    pub fn metadata(&self) -> CloudMetadata {
+        CloudMetadata::from_schema(&self.schema)
+    }
+
+    /// Replace the spatial reference metadata
+    pub fn set_metadata(&mut self, metadata: &CloudMetadata) {
+        let mut entries = self.schema.metadata().to_owned();
+        metadata.apply(&mut entries);
+
+        self.schema = Arc::new(self.schema.as_ref().clone().with_metadata(entries));
+    }
+
+    /// Append the points of `other`.
+    ///
+    /// Unset metadata is taken from `other`. Conflicting metadata is rejected
+    /// under [MetadataPolicy::Strict] and returned otherwise.
+    pub fn merge(
+        &mut self,
+        other: &ArrowPointCloud,
+        policy: MetadataPolicy,
+    ) -> Result<Vec<MetadataConflict>, PointCloudError> {
+        let (mut ours, theirs) = (self.metadata(), other.metadata());
+
+        let conflicts = ours.conflicts(&theirs);
+        if policy == MetadataPolicy::Strict && !conflicts.is_empty() {
+            let conflicts: Vec<String> = conflicts.iter().map(|c| c.to_string()).collect();
+            return Err(PointCloudError::SchemaError(format!(
+                "conflicting metadata {}",
+                conflicts.join(", ")
+            )));
+        }
+
+        ours.fill(&theirs);
+        self.set_metadata(&ours);
+
+        let schema = self.schema();
+        for batch in other
+            .store
+            .iter()
+            .flat_map(|e| other.store.batches(e.key()))
+        {
+            let batch = RecordBatch::try_new(schema.clone(), batch.columns().to_vec())?;
+            self.append(batch)?;
+        }
+
+        Ok(conflicts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow::ipc::{reader::StreamReader, writer::StreamWriter};
+
+    use super::*;
+    use crate::{Point, PointTrait};
+
+    fn cloud(units: LengthUnit) -> ArrowPointCloud {
+        let mut pc = ArrowPointCloud::from_iter(
+            (0..10).map(|i| Point::<f64, 3>::from_slice(&[i as f64, 0., 0.])),
+        )
+        .unwrap();
+        pc.set_metadata(&CloudMetadata {
+            crs: Some("EPSG:2263".to_string()),
+            units: Some(units),
+            vertical_datum: None,
+        });
+        pc
+    }
+
+    #[test]
+    fn round_trip() {
+        let pc = cloud(LengthUnit::UsSurveyFoot);
+
+        let mut writer = StreamWriter::try_new(Vec::new(), &pc.schema()).unwrap();
+        for batch in pc.store.iter().flat_map(|e| pc.store.batches(e.key())) {
+            writer.write(&batch).unwrap();
+        }
+        let buffer = writer.into_inner().unwrap();
+
+        let reader = StreamReader::try_new(std::io::Cursor::new(buffer), None).unwrap();
+        let read = ArrowPointCloud::from(reader);
+
+        assert_eq!(read.metadata(), pc.metadata());
+        assert_eq!(read.metadata().units, Some(LengthUnit::UsSurveyFoot));
+        assert_eq!(read.num_points(), 10);
+    }
+
+    #[test]
+    fn merge_units() {
+        let mut metres = cloud(LengthUnit::Metre);
+
+        // compatible
+        metres
+            .merge(&cloud(LengthUnit::Metre), MetadataPolicy::Strict)
+            .unwrap();
+        assert_eq!(metres.num_points(), 20);
+
+        // conflicting
+        let feet = cloud(LengthUnit::Foot);
+        assert!(metres.merge(&feet, MetadataPolicy::Strict).is_err());
+        assert_eq!(metres.num_points(), 20);
+
+        let conflicts = metres.merge(&feet, MetadataPolicy::Lenient).unwrap();
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].key, CRUX_UNITS_KEY);
+        assert_eq!(metres.metadata().units, Some(LengthUnit::Metre));
+        assert_eq!(metres.num_points(), 30);
+    }
+
+    #[test]
+    fn units() {
+        assert_eq!("ft".parse::<LengthUnit>().unwrap(), LengthUnit::Foot);
+        assert_eq!("Meters".parse::<LengthUnit>().unwrap(), LengthUnit::Metre);
+        assert!("furlong".parse::<LengthUnit>().is_err());
+        assert!((LengthUnit::UsSurveyFoot.metres() - 0.3048006).abs() < 1e-6);
+    }
+}
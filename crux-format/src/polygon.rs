@@ -0,0 +1,204 @@
+use crate::PointCloudError;
+
+/// Parse a polygon ring from WKT (`POLYGON((x y, ...))`) or a flat coordinate
+/// list (`x,y,x,y,...`). Only the exterior ring of a WKT polygon is used.
+pub fn parse(s: &str) -> Result<Vec<[f64; 2]>, PointCloudError> {
+    let s = s.trim();
+
+    let invalid = |msg: &str| PointCloudError::InvalidArgument(format!("{msg}: `{s}`"));
+
+    let coords: Vec<f64> = if s.to_ascii_uppercase().starts_with("POLYGON") {
+        let start = s
+            .find("((")
+            .ok_or_else(|| invalid("missing polygon ring"))?;
+        let end = s[start..]
+            .find(')')
+            .ok_or_else(|| invalid("unclosed polygon ring"))?;
+
+        s[start + 2..start + end]
+            .split([',', ' ', '\t', '\n'])
+            .filter(|v| !v.is_empty())
+            .map(str::parse)
+            .collect::<Result<_, _>>()
+            .map_err(|_| invalid("invalid coordinate"))?
+    } else {
+        s.split(',')
+            .map(|v| v.trim().parse())
+            .collect::<Result<_, _>>()
+            .map_err(|_| invalid("invalid coordinate"))?
+    };
+
+    if !coords.len().is_multiple_of(2) {
+        return Err(invalid("odd number of coordinates"));
+    }
+
+    Ok(coords.chunks_exact(2).map(|c| [c[0], c[1]]).collect())
+}
+
+/// Validate a polygon ring and return it without the closing vertex.
+///
+/// Rings require at least three distinct vertices, a non-zero area and must
+/// not intersect themselves.
+pub fn validate(ring: &[[f64; 2]]) -> Result<Vec<[f64; 2]>, PointCloudError> {
+    let mut ring = ring.to_vec();
+
+    if ring.iter().flatten().any(|v| !v.is_finite()) {
+        return Err(PointCloudError::InvalidArgument(
+            "polygon coordinates must be finite".to_string(),
+        ));
+    }
+
+    // remove repeated and closing vertices
+    ring.dedup();
+    while ring.len() > 1 && ring.first() == ring.last() {
+        ring.pop();
+    }
+
+    if ring.len() < 3 {
+        return Err(PointCloudError::InvalidArgument(format!(
+            "polygon requires at least 3 distinct vertices, got {}",
+            ring.len()
+        )));
+    }
+
+    if area(&ring) == 0. {
+        return Err(PointCloudError::InvalidArgument(
+            "polygon has zero area".to_string(),
+        ));
+    }
+
+    let n = ring.len();
+    for i in 0..n {
+        for j in i + 1..n {
+            // skip adjacent edges
+            if j == i + 1 || (i == 0 && j == n - 1) {
+                continue;
+            }
+
+            if intersects(ring[i], ring[(i + 1) % n], ring[j], ring[(j + 1) % n]) {
+                return Err(PointCloudError::InvalidArgument(
+                    "polygon intersects itself".to_string(),
+                ));
+            }
+        }
+    }
+
+    Ok(ring)
+}
+
+/// Point in polygon test (even-odd rule)
+#[inline]
+pub fn contains(ring: &[[f64; 2]], p: [f64; 2]) -> bool {
+    let mut inside = false;
+
+    let mut j = ring.len() - 1;
+    for i in 0..ring.len() {
+        let (a, b) = (ring[i], ring[j]);
+        if (a[1] > p[1]) != (b[1] > p[1])
+            && p[0] < (b[0] - a[0]) * (p[1] - a[1]) / (b[1] - a[1]) + a[0]
+        {
+            inside = !inside;
+        }
+        j = i;
+    }
+
+    inside
+}
+
+/// Lower and upper corner of the ring
+pub fn bounds(ring: &[[f64; 2]]) -> ([f64; 2], [f64; 2]) {
+    ring.iter().fold(
+        ([f64::INFINITY; 2], [f64::NEG_INFINITY; 2]),
+        |(lower, upper), p| {
+            (
+                [lower[0].min(p[0]), lower[1].min(p[1])],
+                [upper[0].max(p[0]), upper[1].max(p[1])],
+            )
+        },
+    )
+}
+
+/// Signed area (shoelace formula)
+fn area(ring: &[[f64; 2]]) -> f64 {
+    let n = ring.len();
+    (0..n)
+        .map(|i| {
+            let (a, b) = (ring[i], ring[(i + 1) % n]);
+            a[0] * b[1] - b[0] * a[1]
+        })
+        .sum::<f64>()
+        / 2.
+}
+
+fn orientation(a: [f64; 2], b: [f64; 2], c: [f64; 2]) -> f64 {
+    (b[0] - a[0]) * (c[1] - a[1]) - (b[1] - a[1]) * (c[0] - a[0])
+}
+
+fn on_segment(a: [f64; 2], b: [f64; 2], p: [f64; 2]) -> bool {
+    p[0] >= a[0].min(b[0])
+        && p[0] <= a[0].max(b[0])
+        && p[1] >= a[1].min(b[1])
+        && p[1] <= a[1].max(b[1])
+}
+
+/// Segments `ab` and `cd` intersect or touch
+fn intersects(a: [f64; 2], b: [f64; 2], c: [f64; 2], d: [f64; 2]) -> bool {
+    let (o1, o2) = (orientation(a, b, c), orientation(a, b, d));
+    let (o3, o4) = (orientation(c, d, a), orientation(c, d, b));
+
+    if o1 * o2 < 0. && o3 * o4 < 0. {
+        return true;
+    }
+
+    (o1 == 0. && on_segment(a, b, c))
+        || (o2 == 0. && on_segment(a, b, d))
+        || (o3 == 0. && on_segment(c, d, a))
+        || (o4 == 0. && on_segment(c, d, b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_formats() {
+        let wkt = parse("POLYGON((0 0, 10 0, 0 10, 0 0))").unwrap();
+        let flat = parse("0,0,10,0,0,10").unwrap();
+
+        assert_eq!(validate(&wkt).unwrap(), flat);
+        assert!(parse("0,0,10").is_err());
+        assert!(parse("POLYGON((0 0, a 0))").is_err());
+    }
+
+    #[test]
+    fn degenerate() {
+        // too few distinct vertices
+        assert!(validate(&[[0., 0.], [1., 1.], [0., 0.]]).is_err());
+        // collinear
+        assert!(validate(&[[0., 0.], [1., 1.], [2., 2.]]).is_err());
+        // bow tie
+        assert!(validate(&[[0., 0.], [1., 1.], [1., 0.], [0., 1.]]).is_err());
+        // square
+        assert!(validate(&[[0., 0.], [1., 0.], [1., 1.], [0., 1.], [0., 0.]]).is_ok());
+    }
+
+    #[test]
+    fn even_odd() {
+        // concave u-shape
+        let ring = [
+            [0., 0.],
+            [3., 0.],
+            [3., 3.],
+            [2., 3.],
+            [2., 1.],
+            [1., 1.],
+            [1., 3.],
+            [0., 3.],
+        ];
+
+        assert!(contains(&ring, [0.5, 2.]));
+        assert!(contains(&ring, [1.5, 0.5]));
+        assert!(!contains(&ring, [1.5, 2.]));
+        assert!(!contains(&ring, [4., 1.]));
+    }
+}
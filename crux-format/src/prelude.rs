@@ -0,0 +1,25 @@
+//! Traits and types most embeddings need, so that
+//!
+//! ```
+//! use crux_format::prelude::*;
+//!
+//! let pc = ArrowPointCloud::from_iter(
+//!     (0..10).map(|i| Point::<f64, 3>::from_slice(&[i as f64, 0., 0.])),
+//! )
+//! .unwrap();
+//! let aabb: AABB<Point<f64, 3>> = pc.aabb();
+//! assert_eq!(aabb.center().coords(), &[4.5, 0., 0.]);
+//!
+//! let sample = pc.execute(&PointQuery::new().limit(3)).unwrap();
+//! assert_eq!(sample.num_points(), 3);
+//! ```
+//!
+//! compiles without further imports. The prelude only re-exports, the items
+//! keep their paths. Queries are re-exported as `PointQuery`, since a glob
+//! import of `Query` would clash with the ECS query of bevy.
+
+pub use crate::{
+    query::{self, Query as PointQuery},
+    ArrowError, ArrowPointCloud, CoordSpec, Point, PointCloudError, PointCloudTrait, PointTrait,
+    RecordBatch, Schema, SchemaRef, AABB,
+};
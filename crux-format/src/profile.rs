@@ -0,0 +1,240 @@
+use std::io::Write;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{progress::Tracker, ArrowPointCloud, PointCloudError, PointTrait, ProgressSink};
+
+/// Elevation statistics of the points in a distance bin
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct ProfileBin {
+    /// Distance of the bin center from the start of the line
+    pub distance: f64,
+    /// Number of points in the bin
+    pub count: usize,
+    /// Minimum, mean and maximum z, `None` for empty bins
+    pub min: Option<f64>,
+    pub mean: Option<f64>,
+    pub max: Option<f64>,
+}
+
+/// Height profile along a line
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Profile {
+    /// Start of the line
+    pub a: [f64; 2],
+    /// End of the line
+    pub b: [f64; 2],
+    /// Corridor width, centered on the line
+    pub width: f64,
+    /// Bins of equal length from `a` to `b`
+    pub bins: Vec<ProfileBin>,
+}
+
+impl Profile {
+    /// Length of the line
+    pub fn length(&self) -> f64 {
+        (self.b[0] - self.a[0]).hypot(self.b[1] - self.a[1])
+    }
+
+    /// Range of z over all bins, `None` if no point is in the corridor
+    pub fn z_range(&self) -> Option<(f64, f64)> {
+        self.bins
+            .iter()
+            .filter_map(|bin| bin.min.zip(bin.max))
+            .reduce(|(min, max), (lower, upper)| (min.min(lower), max.max(upper)))
+    }
+
+    /// Write the bins as CSV with a header row, empty bins have empty z values
+    pub fn write_csv(&self, mut writer: impl Write) -> std::io::Result<()> {
+        let value = |v: Option<f64>| v.map(|v| v.to_string()).unwrap_or_default();
+
+        writeln!(writer, "distance,count,min,mean,max")?;
+        for bin in &self.bins {
+            writeln!(
+                writer,
+                "{},{},{},{},{}",
+                bin.distance,
+                bin.count,
+                value(bin.min),
+                value(bin.mean),
+                value(bin.max)
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+impl ArrowPointCloud {
+    /// Height profile along the XY line from `a` to `b`.
+    ///
+    /// Points within `width / 2` of the line, whose projection falls onto the
+    /// line, are bucketed into `bins` bins of equal length by their distance
+    /// along the line.
+    pub fn profile(
+        &self,
+        a: [f64; 2],
+        b: [f64; 2],
+        width: f64,
+        bins: usize,
+    ) -> Result<Profile, PointCloudError> {
+        self.profile_with(a, b, width, bins, &())
+    }
+
+    /// [ArrowPointCloud::profile] reporting progress per segment
+    pub fn profile_with(
+        &self,
+        a: [f64; 2],
+        b: [f64; 2],
+        width: f64,
+        bins: usize,
+        progress: &dyn ProgressSink,
+    ) -> Result<Profile, PointCloudError> {
+        let (dx, dy) = (b[0] - a[0], b[1] - a[1]);
+        let length = dx.hypot(dy);
+
+        if length.is_nan() || length <= 0. {
+            return Err(PointCloudError::InvalidArgument(
+                "profile line must have a positive length".to_string(),
+            ));
+        }
+        if width.is_nan() || width <= 0. || bins == 0 {
+            return Err(PointCloudError::InvalidArgument(
+                "profile width and number of bins must be positive".to_string(),
+            ));
+        }
+
+        // unit direction
+        let (ux, uy) = (dx / length, dy / length);
+        let half_width = width / 2.;
+        let bin_length = length / bins as f64;
+
+        let mut stats = vec![(0, f64::INFINITY, 0., f64::NEG_INFINITY); bins];
+        let tracker = Tracker::new(progress, self.store.len());
+        self.visit_points(&tracker, |p| {
+            let (px, py) = (p.x() - a[0], p.y() - a[1]);
+
+            // distance along and across the line
+            let along = px * ux + py * uy;
+            let across = px * uy - py * ux;
+            if !(0. ..=length).contains(&along) || across.abs() > half_width || p.z().is_nan() {
+                return;
+            }
+
+            let i = ((along / bin_length) as usize).min(bins - 1);
+            let (count, min, sum, max) = &mut stats[i];
+            *count += 1;
+            *min = p.z().min(*min);
+            *sum += p.z();
+            *max = p.z().max(*max);
+        })?;
+
+        let bins = stats
+            .into_iter()
+            .enumerate()
+            .map(|(i, (count, min, sum, max))| {
+                let some = |v: f64| (count > 0).then_some(v);
+                ProfileBin {
+                    distance: (i as f64 + 0.5) * bin_length,
+                    count,
+                    min: some(min),
+                    mean: some(sum / count as f64),
+                    max: some(max),
+                }
+            })
+            .collect();
+
+        Ok(Profile { a, b, width, bins })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Point, PointCloudTrait};
+
+    /// Plane `z = slope(x, y)` sampled on a 0.1 grid over [0, 10]²
+    fn plane(slope: fn(f64, f64) -> f64) -> ArrowPointCloud {
+        ArrowPointCloud::from_iter((0..100 * 100).map(|i| {
+            let x = (i % 100) as f64 / 10. + 0.05;
+            let y = (i / 100) as f64 / 10. + 0.05;
+            Point::<f64, 3>::from_slice(&[x, y, slope(x, y)])
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn slope() {
+        let pc = plane(|x, _| 0.5 * x);
+
+        // along the slope, the corridor covers 10 rows
+        let profile = pc.profile([0., 5.], [10., 5.], 1., 10).unwrap();
+        assert_eq!(profile.length(), 10.);
+        assert_eq!(profile.bins.len(), 10);
+
+        for (i, bin) in profile.bins.iter().enumerate() {
+            let start = i as f64;
+            assert_eq!(bin.distance, start + 0.5);
+            assert_eq!(bin.count, 10 * 10);
+            assert!((bin.min.unwrap() - 0.5 * (start + 0.05)).abs() < 1e-9);
+            assert!((bin.mean.unwrap() - 0.5 * (start + 0.5)).abs() < 1e-9);
+            assert!((bin.max.unwrap() - 0.5 * (start + 0.95)).abs() < 1e-9);
+        }
+        assert!((profile.z_range().unwrap().1 - 0.5 * 9.95).abs() < 1e-9);
+
+        // across the slope the profile is flat
+        let profile = pc.profile([5.01, 0.], [5.01, 10.], 0.1, 5).unwrap();
+        for bin in &profile.bins {
+            assert_eq!(bin.count, 20);
+            assert!((bin.mean.unwrap() - 0.5 * 5.05).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn diagonal() {
+        // z only depends on the distance along the diagonal, z = √2 t
+        let pc = plane(|x, y| x + y);
+        let profile = pc.profile([0., 0.], [10., 10.], 2., 7).unwrap();
+
+        let bin_length = profile.length() / 7.;
+        for (i, bin) in profile.bins.iter().enumerate() {
+            let (start, end) = (i as f64 * bin_length, (i + 1) as f64 * bin_length);
+            assert!(bin.count > 0);
+            assert!(bin.min.unwrap() >= 2f64.sqrt() * start - 1e-9);
+            assert!(bin.max.unwrap() <= 2f64.sqrt() * end + 1e-9);
+            let mean = 2f64.sqrt() * bin.distance;
+            assert!(
+                (bin.mean.unwrap() - mean).abs() < bin_length / 2.,
+                "{bin:?}"
+            );
+        }
+
+        // points beyond the ends of the line are excluded
+        let profile = pc.profile([20., 20.], [30., 20.], 2., 4).unwrap();
+        assert!(profile
+            .bins
+            .iter()
+            .all(|bin| bin.count == 0 && bin.mean.is_none()));
+        assert!(profile.z_range().is_none());
+
+        assert!(pc.profile([1., 1.], [1., 1.], 1., 10).is_err());
+        assert!(pc.profile([0., 0.], [1., 1.], 0., 10).is_err());
+        assert!(pc.profile([0., 0.], [1., 1.], 1., 0).is_err());
+    }
+
+    #[test]
+    fn csv() {
+        let pc = plane(|x, _| x);
+        let profile = pc.profile([0., 5.], [20., 5.], 1., 4).unwrap();
+
+        let mut buffer = Vec::new();
+        profile.write_csv(&mut buffer).unwrap();
+        let csv = String::from_utf8(buffer).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+
+        assert_eq!(lines[0], "distance,count,min,mean,max");
+        assert_eq!(lines.len(), 5);
+        assert!(lines[1].starts_with("2.5,500,0.05,"));
+        assert_eq!(lines[4], "17.5,0,,,");
+    }
+}
@@ -0,0 +1,393 @@
+use std::{
+    io::Write,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use arrow::{
+    array::AsArray,
+    compute::{cast, concat_batches},
+    datatypes::{DataType, Float64Type},
+    ipc::writer::FileWriter,
+};
+use rayon::iter::ParallelIterator;
+use rstar::{primitives::GeomWithData, RTree};
+
+use crate::{
+    compute::aabb,
+    schema::dimensions,
+    soa::{BatchIndex, Index},
+    trace::span,
+    ArrowPointCloud, Point, PointCloudError, PointCloudTrait, PointTrait, Rechunker,
+};
+
+/// Receiver of progress reports of long running operations
+pub trait ProgressSink: Sync {
+    /// `done` of `total` units of work are completed
+    fn report(&self, done: usize, total: usize);
+
+    /// Operations abort with [PointCloudError::Cancelled] once this returns `true`
+    fn is_cancelled(&self) -> bool {
+        false
+    }
+}
+
+/// Ignore progress
+impl ProgressSink for () {
+    fn report(&self, _done: usize, _total: usize) {}
+}
+
+/// Shared flag to cancel operations from another thread
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancel all operations observing this token or one of its clones
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Cancellation only, progress is ignored
+impl ProgressSink for CancelToken {
+    fn report(&self, _done: usize, _total: usize) {}
+
+    fn is_cancelled(&self) -> bool {
+        CancelToken::is_cancelled(self)
+    }
+}
+
+/// Latest progress report for polling from another thread, cancelled by its token
+#[derive(Debug, Default)]
+pub struct AtomicProgress {
+    done: AtomicUsize,
+    total: AtomicUsize,
+    token: CancelToken,
+}
+
+impl AtomicProgress {
+    pub fn new(token: CancelToken) -> Self {
+        Self {
+            token,
+            ..Default::default()
+        }
+    }
+
+    pub fn done(&self) -> usize {
+        self.done.load(Ordering::Relaxed)
+    }
+
+    pub fn total(&self) -> usize {
+        self.total.load(Ordering::Relaxed)
+    }
+
+    /// Completed fraction, `0` before the first report
+    pub fn fraction(&self) -> f64 {
+        match self.total() {
+            0 => 0.,
+            total => self.done().min(total) as f64 / total as f64,
+        }
+    }
+
+    pub fn token(&self) -> &CancelToken {
+        &self.token
+    }
+}
+
+impl ProgressSink for AtomicProgress {
+    fn report(&self, done: usize, total: usize) {
+        self.total.store(total, Ordering::Relaxed);
+        self.done.store(done, Ordering::Relaxed);
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.token.is_cancelled()
+    }
+}
+
+/// Counts completed units from parallel workers and forwards them to a sink
+pub(crate) struct Tracker<'a> {
+    sink: &'a dyn ProgressSink,
+    done: AtomicUsize,
+    total: usize,
+}
+
+impl<'a> Tracker<'a> {
+    pub(crate) fn new(sink: &'a dyn ProgressSink, total: usize) -> Self {
+        sink.report(0, total);
+        Self {
+            sink,
+            done: AtomicUsize::new(0),
+            total,
+        }
+    }
+
+    pub(crate) fn check(&self) -> Result<(), PointCloudError> {
+        if self.sink.is_cancelled() {
+            Err(PointCloudError::Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+
+    pub(crate) fn step(&self) {
+        let done = self.done.fetch_add(1, Ordering::Relaxed) + 1;
+        self.sink.report(done, self.total);
+    }
+}
+
+impl ArrowPointCloud {
+    /// Visit the first three dimensions of all points segment by segment,
+    /// checking for cancellation before and stepping after each segment
+    pub(crate) fn visit_points(
+        &self,
+        tracker: &Tracker,
+        mut visit: impl FnMut(Point<f64, 3>),
+    ) -> Result<(), PointCloudError> {
+        let dimensions = dimensions(&self.schema());
+
+        for e in self.store.iter() {
+            tracker.check()?;
+            for batch in self.store.batches(e.key()) {
+                let columns = dimensions
+                    .iter()
+                    .take(3)
+                    .map(|i| cast(batch.column(*i), &DataType::Float64))
+                    .collect::<Result<Vec<_>, _>>()?;
+                let columns: Vec<_> = columns
+                    .iter()
+                    .map(|column| column.as_primitive::<Float64Type>())
+                    .collect();
+
+                for i in 0..batch.num_rows() {
+                    let coord = |nth: usize| columns.get(nth).map_or(0., |column| column.value(i));
+                    visit(Point::from_slice(&[coord(0), coord(1), coord(2)]));
+                }
+            }
+            tracker.step();
+        }
+
+        Ok(())
+    }
+
+    /// Index the bounds of all segments, reporting progress per segment
+    pub fn batch_index(&self, progress: &dyn ProgressSink) -> Result<BatchIndex, PointCloudError> {
+        let _span = span!(DEBUG, "index", entries = self.store.len());
+        let tracker = Tracker::new(progress, self.store.len());
+
+        let objects = self
+            .store
+            .par_iter()
+            .map(|e| {
+                tracker.check()?;
+
+                let batches = self.store.batches(e.key());
+                let aabb = if batches.len() == 1 {
+                    aabb::<Point<f64, 4>>(&batches[0])
+                } else {
+                    aabb(&concat_batches(&batches[0].schema(), batches.iter())?)
+                };
+
+                tracker.step();
+                Ok(GeomWithData::new(aabb, e.key().to_string()))
+            })
+            .collect::<Result<Vec<_>, PointCloudError>>()?;
+
+        Ok(RTree::bulk_load_with_params(objects))
+    }
+
+    /// Index in place, see [ArrowPointCloud::batch_index]
+    pub fn build_index(&mut self, progress: &dyn ProgressSink) -> Result<(), PointCloudError> {
+        self.index = Index::Batch(self.batch_index(progress)?);
+        Ok(())
+    }
+
+    /// Write all points as Arrow IPC file, reporting progress per segment
+    ///
+    /// Batches are written as stored, or in batches of `chunk_rows` if given,
+    /// see [Rechunker].
+    pub fn write_ipc(
+        &self,
+        writer: impl Write,
+        chunk_rows: Option<usize>,
+        progress: &dyn ProgressSink,
+    ) -> Result<(), PointCloudError> {
+        let tracker = Tracker::new(progress, self.store.len());
+        let mut writer = FileWriter::try_new(writer, &self.schema())?;
+        let mut rechunker = chunk_rows.map(Rechunker::new);
+
+        for e in self.store.iter() {
+            tracker.check()?;
+            for batch in self.store.batches(e.key()) {
+                match rechunker.as_mut() {
+                    Some(rechunker) => {
+                        for batch in rechunker.push(batch)? {
+                            writer.write(&batch)?;
+                        }
+                    }
+                    None => writer.write(&batch)?,
+                }
+            }
+            tracker.step();
+        }
+        if let Some(batch) = rechunker.map(Rechunker::finish).transpose()?.flatten() {
+            writer.write(&batch)?;
+        }
+
+        writer.finish()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use arrow::ipc::reader::FileReader;
+
+    use super::*;
+    use crate::PointTrait;
+
+    #[derive(Default)]
+    struct Recorder {
+        reports: Mutex<Vec<(usize, usize)>>,
+        cancel_after: Option<usize>,
+    }
+
+    impl ProgressSink for Recorder {
+        fn report(&self, done: usize, total: usize) {
+            self.reports.lock().unwrap().push((done, total));
+        }
+
+        fn is_cancelled(&self) -> bool {
+            self.cancel_after
+                .is_some_and(|n| self.reports.lock().unwrap().len() > n)
+        }
+    }
+
+    fn cloud() -> ArrowPointCloud {
+        let mut pc = ArrowPointCloud::try_new(Point::<f64, 3>::schema()).unwrap();
+        for i in 0..8 {
+            let batch = ArrowPointCloud::from_iter(
+                (0..10).map(|j| Point::<f64, 3>::from_slice(&[i as f64, j as f64, 0.])),
+            )
+            .unwrap();
+            for batch in batch
+                .store
+                .iter()
+                .flat_map(|e| batch.store.batches(e.key()))
+            {
+                pc.append(batch).unwrap();
+            }
+        }
+        pc
+    }
+
+    #[test]
+    fn report() {
+        let mut pc = cloud();
+
+        let recorder = Recorder::default();
+        pc.build_index(&recorder).unwrap();
+        assert!(matches!(&pc.index, Index::Batch(index) if index.size() == 8));
+
+        let mut reports = recorder.reports.into_inner().unwrap();
+        reports.sort();
+        assert_eq!(reports, (0..=8).map(|i| (i, 8)).collect::<Vec<_>>());
+
+        let rows = |chunk_rows| {
+            let mut buffer = Vec::new();
+            pc.write_ipc(&mut buffer, chunk_rows, &()).unwrap();
+            let reader = FileReader::try_new(std::io::Cursor::new(buffer), None).unwrap();
+            reader
+                .map(|batch| batch.unwrap().num_rows())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(rows(None), [10; 8]);
+        assert_eq!(rows(Some(30)), [30, 30, 20]);
+    }
+
+    #[test]
+    fn cancel() {
+        let pc = cloud();
+
+        let recorder = Recorder {
+            cancel_after: Some(3),
+            ..Default::default()
+        };
+        let result = pc.write_ipc(Vec::new(), None, &recorder);
+        assert!(matches!(result, Err(PointCloudError::Cancelled)));
+        assert_eq!(recorder.reports.into_inner().unwrap().last(), Some(&(3, 8)));
+    }
+
+    #[test]
+    fn cancel_operations() {
+        let (a, b) = (cloud(), cloud());
+        let hash = a.content_hash();
+        let footprint = [[0., 0.], [8., 0.], [8., 10.], [0., 10.]];
+
+        let results = [
+            crate::diff_with(
+                &a,
+                &b,
+                1.,
+                &Recorder {
+                    cancel_after: Some(3),
+                    ..Default::default()
+                },
+            )
+            .map(|_| ()),
+            a.volume_above_with(
+                &footprint,
+                1.,
+                crate::BaseSurface::MinSurface,
+                &Recorder {
+                    cancel_after: Some(3),
+                    ..Default::default()
+                },
+            )
+            .map(|_| ()),
+            a.profile_with(
+                [0., 5.],
+                [8., 5.],
+                1.,
+                8,
+                &Recorder {
+                    cancel_after: Some(3),
+                    ..Default::default()
+                },
+            )
+            .map(|_| ()),
+        ];
+        for result in results {
+            assert!(matches!(result, Err(PointCloudError::Cancelled)));
+        }
+        assert_eq!(a.num_points(), 80);
+        assert_eq!(a.content_hash(), hash);
+
+        // cancelled before the first segment
+        let token = CancelToken::new();
+        let progress = AtomicProgress::new(token.clone());
+        token.cancel();
+        let result = a.profile_with([0., 5.], [8., 5.], 1., 8, &progress);
+        assert!(matches!(result, Err(PointCloudError::Cancelled)));
+        assert_eq!((progress.done(), progress.total()), (0, 8));
+
+        let progress = AtomicProgress::default();
+        let profile = a
+            .profile_with([0., 5.], [8., 5.], 1., 8, &progress)
+            .unwrap();
+        assert_eq!(profile, a.profile([0., 5.], [8., 5.], 1., 8).unwrap());
+        assert_eq!(progress.fraction(), 1.);
+    }
+}
@@ -0,0 +1,1300 @@
+use std::{
+    collections::{HashMap, HashSet},
+    hash::Hasher,
+    ops::{Range, RangeInclusive},
+    str::FromStr,
+};
+
+use arrow::{
+    array::{Array, AsArray, BooleanArray, Float64Array},
+    compute::{
+        and, filter_record_batch,
+        kernels::cmp::{eq, gt, gt_eq, lt, lt_eq, neq},
+        not, or, prep_null_mask_filter,
+    },
+    datatypes::{DataType, Int64Type, SchemaRef},
+    error::ArrowError,
+    record_batch::RecordBatch,
+};
+use rand::{rngs::SmallRng, Rng, SeedableRng};
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use twox_hash::XxHash64;
+
+use crate::{
+    compute::{
+        self, filter_by_aabb, filter_by_distance, filter_by_frustum, filter_by_polygon,
+        filter_by_range,
+    },
+    encoding::cast,
+    polygon,
+    progress::Tracker,
+    schema,
+    soa::Index,
+    trace::span,
+    trajectory::TIME_COLUMN,
+    ArrowPointCloud, Frustum, Point, PointCloudError, PointCloudTrait, PointTrait, ProgressSink,
+    AABB,
+};
+
+/// Comparison operator of an [Expr]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CmpOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+/// Attribute predicate.
+///
+/// Columns are compared as `f64`, rows with null values never match.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Cmp {
+        column: String,
+        op: CmpOp,
+        value: f64,
+    },
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+}
+
+impl Expr {
+    pub fn cmp(column: impl Into<String>, op: CmpOp, value: f64) -> Self {
+        Self::Cmp {
+            column: column.into(),
+            op,
+            value,
+        }
+    }
+
+    pub fn and(self, other: Expr) -> Self {
+        Self::And(Box::new(self), Box::new(other))
+    }
+
+    pub fn or(self, other: Expr) -> Self {
+        Self::Or(Box::new(self), Box::new(other))
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn not(self) -> Self {
+        Self::Not(Box::new(self))
+    }
+
+    /// Columns the predicate refers to
+    pub fn columns(&self) -> Vec<&str> {
+        match self {
+            Self::Cmp { column, .. } => vec![column],
+            Self::And(a, b) | Self::Or(a, b) => [a.columns(), b.columns()].concat(),
+            Self::Not(a) => a.columns(),
+        }
+    }
+
+    /// Mask of the matching rows, null where a compared value is null
+    pub fn evaluate(&self, batch: &RecordBatch) -> Result<BooleanArray, ArrowError> {
+        match self {
+            Self::Cmp { column, op, value } => {
+                let array = batch.column_by_name(column).ok_or_else(|| {
+                    ArrowError::InvalidArgumentError(format!("no column `{column}`"))
+                })?;
+                let array = cast(array, &DataType::Float64)?;
+                let value = Float64Array::new_scalar(*value);
+                match op {
+                    CmpOp::Eq => eq(&array, &value),
+                    CmpOp::Ne => neq(&array, &value),
+                    CmpOp::Lt => lt(&array, &value),
+                    CmpOp::Le => lt_eq(&array, &value),
+                    CmpOp::Gt => gt(&array, &value),
+                    CmpOp::Ge => gt_eq(&array, &value),
+                }
+            }
+            Self::And(a, b) => and(&a.evaluate(batch)?, &b.evaluate(batch)?),
+            Self::Or(a, b) => or(&a.evaluate(batch)?, &b.evaluate(batch)?),
+            Self::Not(a) => not(&a.evaluate(batch)?),
+        }
+    }
+
+    /// Mask of the rows not matching, including those where a compared value
+    /// is null, e.g. to [retain](ArrowPointCloud::retain) all but the matching
+    /// points
+    pub fn exclude(&self, batch: &RecordBatch) -> Result<BooleanArray, ArrowError> {
+        let mask = self.evaluate(batch)?;
+        match mask.nulls() {
+            Some(_) => not(&prep_null_mask_filter(&mask)),
+            None => not(&mask),
+        }
+    }
+}
+
+/// Conjunction of comparisons `<column><op><value>` separated by commas, e.g.
+/// `classification=2,intensity>=100` with the operators `=`, `!=`, `<`, `<=`,
+/// `>` and `>=`
+impl FromStr for Expr {
+    type Err = PointCloudError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || PointCloudError::InvalidArgument(format!("invalid filter `{s}`"));
+
+        s.split(',')
+            .map(|term| {
+                let start = term.find(['<', '>', '=', '!']).ok_or_else(invalid)?;
+                let (column, rest) = term.split_at(start);
+                let (op, value) = [
+                    ("<=", CmpOp::Le),
+                    (">=", CmpOp::Ge),
+                    ("!=", CmpOp::Ne),
+                    ("=", CmpOp::Eq),
+                    ("<", CmpOp::Lt),
+                    (">", CmpOp::Gt),
+                ]
+                .into_iter()
+                .find_map(|(token, op)| rest.strip_prefix(token).map(|value| (op, value)))
+                .ok_or_else(invalid)?;
+
+                let column = column.trim();
+                let value = value.trim().parse().map_err(|_| invalid())?;
+                if column.is_empty() {
+                    return Err(invalid());
+                }
+                Ok(Expr::cmp(column, op, value))
+            })
+            .reduce(|a, b| Ok(a?.and(b?)))
+            .unwrap_or_else(|| Err(invalid()))
+    }
+}
+
+/// Sampling stage of a [Query]
+#[derive(Debug, Clone, PartialEq)]
+pub enum Sample {
+    /// Points with an importance below `p`, i.e. a fraction `p` of the points
+    /// for random importance
+    P(f64),
+    /// A fraction `p` of the points drawn with `seed` regardless of their
+    /// importance. Batches are sampled before they are filtered, so that the
+    /// samples of overlapping queries agree and smaller `p` select subsets.
+    Seeded { p: f64, seed: u64 },
+    /// A fraction `p` of the points, each decided by the hash of its identity,
+    /// the [ID_COLUMN] if present and otherwise its store entry, batch and
+    /// row. Decisions never change when points are appended and smaller `p`
+    /// select subsets.
+    Stable(f64),
+    /// Stratified sample of the selected points, see
+    /// [ArrowPointCloud::sample_stratified]
+    Stratified {
+        column: String,
+        total: usize,
+        min_per_class: usize,
+    },
+}
+
+/// Integer column identifying points across loads, used by [Sample::Stable]
+pub const ID_COLUMN: &str = "point_id";
+
+/// Polygon ring and inclusive height range
+type Footprint = (Vec<[f64; 2]>, Option<RangeInclusive<f64>>);
+
+/// Selection of points from a point cloud, see [ArrowPointCloud::execute]
+///
+/// ```
+/// # use crux_format::{query::{CmpOp, Expr, Query, Sample}, Point, PointTrait, AABB};
+/// let query = Query::new()
+///     .bounds(AABB::from_corners(
+///         Point::<f64, 3>::from_slice(&[0., 0., 0.]),
+///         Point::from_slice(&[100., 100., 50.]),
+///     ))
+///     .filter(Expr::cmp("intensity", CmpOp::Gt, 100.))
+///     .columns(&["x", "y", "z", "intensity"])
+///     .sample(Sample::P(0.01))
+///     .limit(1_000_000);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Query {
+    /// Lower and upper corner, see [Query::bounds]
+    bounds: Option<(Vec<f64>, Vec<f64>)>,
+    polygon: Option<Footprint>,
+    frustum: Option<Frustum>,
+    /// Center and radius, see [Query::near]
+    near: Option<(Vec<f64>, f64)>,
+    /// Column and half-open range of its values, see [Query::range]
+    range: Option<(String, Range<f64>)>,
+    filter: Option<Expr>,
+    columns: Option<Vec<String>>,
+    sample: Option<Sample>,
+    limit: Option<usize>,
+}
+
+impl Query {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Points within `[lower, upper)` in the dimensions of `P`, the fourth
+    /// one is the importance or the fourth coordinate
+    pub fn bounds<P: PointTrait<Scalar = f64>>(mut self, aabb: AABB<P>) -> Self {
+        let (lower, upper) = (aabb.lower(), aabb.upper());
+        self.bounds = Some((lower.coords().to_vec(), upper.coords().to_vec()));
+        self
+    }
+
+    /// Points whose XY lies inside the polygon `ring` and whose z is within
+    /// `z_range`, if given
+    pub fn polygon(mut self, ring: Vec<[f64; 2]>, z_range: Option<RangeInclusive<f64>>) -> Self {
+        self.polygon = Some((ring, z_range));
+        self
+    }
+
+    /// Points within the view frustum
+    pub fn frustum(mut self, frustum: Frustum) -> Self {
+        self.frustum = Some(frustum);
+        self
+    }
+
+    /// Points within `radius` of `center` in the dimensions of `P`, so a
+    /// two-dimensional center ignores z
+    pub fn near<P: PointTrait<Scalar = f64>>(mut self, center: P, radius: f64) -> Self {
+        self.near = Some((center.coords().to_vec(), radius));
+        self
+    }
+
+    /// Points with values of `column` within `[range.start, range.end)`,
+    /// replacing an earlier range. Ranges of the gps time skip the batches
+    /// outside of them unread, see [temporal](crate::temporal).
+    pub fn range(mut self, column: impl Into<String>, range: Range<f64>) -> Self {
+        self.range = Some((column.into(), range));
+        self
+    }
+
+    /// Points with a gps time within `[range.start, range.end)`, see
+    /// [Query::range]
+    pub fn time(self, range: Range<f64>) -> Self {
+        self.range(TIME_COLUMN, range)
+    }
+
+    /// Points matching the predicate, combined with earlier filters
+    pub fn filter(mut self, expr: Expr) -> Self {
+        self.filter = Some(match self.filter.take() {
+            Some(filter) => filter.and(expr),
+            None => expr,
+        });
+        self
+    }
+
+    /// Columns of the result, dimensions are always kept
+    pub fn columns(mut self, columns: &[&str]) -> Self {
+        self.columns = Some(columns.iter().map(|c| c.to_string()).collect());
+        self
+    }
+
+    pub fn sample(mut self, sample: Sample) -> Self {
+        self.sample = Some(sample);
+        self
+    }
+
+    /// At most `limit` points, the first ones in iteration order
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    /// Bounds of the selected points in x, y, z and importance, or the
+    /// fourth coordinate
+    pub fn aabb(&self) -> AABB<Point<f64, 4>> {
+        let mut lower = [f64::MIN; 4];
+        let mut upper = [f64::MAX; 4];
+
+        let mut restrict = |l: &[f64], u: &[f64]| {
+            for d in 0..l.len() {
+                lower[d] = lower[d].max(l[d]);
+                upper[d] = upper[d].min(u[d]);
+            }
+        };
+        if let Some((l, u)) = &self.bounds {
+            restrict(&l[..l.len().min(4)], &u[..u.len().min(4)]);
+        }
+        if let Some((ring, z_range)) = &self.polygon {
+            let (l, u) = polygon::bounds(ring);
+            restrict(&l, &u);
+            if let Some(z) = z_range {
+                // inclusive upper bound
+                restrict(&[f64::MIN, f64::MIN, *z.start()], &[f64::MAX; 3]);
+            }
+        }
+        if let Some(frustum) = &self.frustum {
+            let (l, u) = frustum.bounds();
+            restrict(&l, &u);
+        }
+        if let Some((center, radius)) = &self.near {
+            // inclusive upper bound
+            let l: Vec<f64> = center.iter().map(|c| c - radius).collect();
+            let u: Vec<f64> = center.iter().map(|c| (c + radius).next_up()).collect();
+            restrict(&l, &u);
+        }
+        if let Some(Sample::P(p)) = self.sample {
+            restrict(
+                &[f64::MIN, f64::MIN, f64::MIN, 0.],
+                &[f64::MAX, f64::MAX, f64::MAX, p],
+            );
+        }
+        // disjoint restrictions select nothing
+        for d in 0..4 {
+            upper[d] = upper[d].max(lower[d]);
+        }
+
+        AABB::from_corners(Point::from_slice(&lower), Point::from_slice(&upper))
+    }
+
+    /// Schema of the result for points of `schema`
+    pub fn schema(&self, schema: &SchemaRef) -> Result<SchemaRef, PointCloudError> {
+        match self.projection(schema)? {
+            Some(indices) => Ok(std::sync::Arc::new(schema.project(&indices)?)),
+            None => Ok(schema.clone()),
+        }
+    }
+
+    /// Indices of the result columns, dimensions and requested columns in
+    /// schema order
+    fn projection(&self, schema: &SchemaRef) -> Result<Option<Vec<usize>>, PointCloudError> {
+        let Some(columns) = &self.columns else {
+            return Ok(None);
+        };
+
+        let mut indices = schema::dimensions(schema);
+        for column in columns {
+            indices.push(
+                schema.index_of(column).map_err(|_| {
+                    PointCloudError::InvalidArgument(format!("no column `{column}`"))
+                })?,
+            );
+        }
+        indices.sort_unstable();
+        indices.dedup();
+
+        Ok(Some(indices))
+    }
+
+    /// First column of the range, the filter, the stratified sample or the
+    /// projection missing in `schema`
+    pub fn missing_column(&self, schema: &SchemaRef) -> Option<&str> {
+        let range = self.range.iter().map(|(column, _)| column.as_str());
+        let filter = self.filter.iter().flat_map(|filter| filter.columns());
+        let sample = match &self.sample {
+            Some(Sample::Stratified { column, .. }) => Some(column.as_str()),
+            _ => None,
+        };
+        let columns = self.columns.iter().flatten().map(String::as_str);
+        range
+            .chain(filter)
+            .chain(sample)
+            .chain(columns)
+            .find(|column| schema.index_of(column).is_err())
+    }
+
+    /// Reject queries referring to missing columns or invalid geometries
+    pub fn validate(&self, schema: &SchemaRef) -> Result<(), PointCloudError> {
+        if let Some((ring, _)) = &self.polygon {
+            polygon::validate(ring)?;
+        }
+        if let Some((center, radius)) = &self.near {
+            compute::validate_distance(center, *radius)?;
+        }
+        if let Some(column) = self.missing_column(schema) {
+            return Err(PointCloudError::InvalidArgument(format!(
+                "no column `{column}`"
+            )));
+        }
+        match &self.sample {
+            Some(Sample::P(_)) if schema::importance(schema).is_none() => {
+                return Err(PointCloudError::InvalidArgument(
+                    "no importance dimension to sample by".to_string(),
+                ))
+            }
+            // bounded as fourth dimension
+            Some(Sample::P(_))
+                if schema::importance(schema) != schema::dimensions(schema).get(3).copied() =>
+            {
+                return Err(PointCloudError::InvalidArgument(
+                    "the importance is not the fourth dimension".to_string(),
+                ))
+            }
+            _ => (),
+        }
+        self.projection(schema)?;
+
+        Ok(())
+    }
+
+    /// Whether all points within the envelope of the first `dims` dimensions
+    /// are selected
+    fn covers(
+        &self,
+        aabb: &AABB<Point<f64, 4>>,
+        envelope: &AABB<Point<f64, 4>>,
+        dims: usize,
+    ) -> bool {
+        let (l, u) = (aabb.lower(), aabb.upper());
+        let (lower, upper) = (envelope.lower(), envelope.upper());
+        self.polygon.is_none()
+            && self.frustum.is_none()
+            && self.near.is_none()
+            && self.range.is_none()
+            && self.filter.is_none()
+            && (0..dims)
+                .all(|d| l.coords()[d] <= lower.coords()[d] && upper.coords()[d] < u.coords()[d])
+    }
+
+    /// Whether the envelope of the first `dims` dimensions may contain
+    /// selected points
+    fn intersects(aabb: &AABB<Point<f64, 4>>, envelope: &AABB<Point<f64, 4>>, dims: usize) -> bool {
+        let (l, u) = (aabb.lower(), aabb.upper());
+        let (lower, upper) = (envelope.lower(), envelope.upper());
+        (0..dims).all(|d| lower.coords()[d] < u.coords()[d] && l.coords()[d] <= upper.coords()[d])
+    }
+
+    /// Points of the batch within the spatial selection and matching the filter
+    pub fn filter_batch(
+        &self,
+        batch: &RecordBatch,
+        aabb: &AABB<Point<f64, 4>>,
+    ) -> Result<RecordBatch, ArrowError> {
+        let span = span!(TRACE, "filter", input = batch.num_rows(), rows);
+        let mut batch = filter_by_aabb(batch, aabb);
+
+        if let Some((ring, z_range)) = &self.polygon {
+            if batch.num_rows() > 0 {
+                batch = filter_by_polygon(&batch, ring, z_range.as_ref())?;
+            }
+        }
+        if let Some(frustum) = &self.frustum {
+            if batch.num_rows() > 0 {
+                batch = filter_by_frustum(&batch, frustum)?;
+            }
+        }
+        if let Some((center, radius)) = &self.near {
+            if batch.num_rows() > 0 {
+                batch = filter_by_distance(&batch, center, *radius)?;
+            }
+        }
+        if let Some((column, range)) = &self.range {
+            if batch.num_rows() > 0 {
+                batch = filter_by_range(&batch, column, range)?;
+            }
+        }
+        if let Some(filter) = &self.filter {
+            if batch.num_rows() > 0 {
+                batch = filter_record_batch(&batch, &filter.evaluate(&batch)?)?;
+            }
+        }
+
+        span.record("rows", batch.num_rows());
+        Ok(batch)
+    }
+}
+
+/// Seed of the generator of batch `j` of store entry `i`
+fn batch_seed(seed: u64, i: usize, j: usize) -> u64 {
+    seed ^ (i as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15)
+        ^ (j as u64).wrapping_mul(0xc2b2_ae3d_27d4_eb4f)
+}
+
+/// Mask of the seeded sample of batch `j` of store entry `i`
+fn sample_mask(rows: usize, p: f64, seed: u64, i: usize, j: usize) -> BooleanArray {
+    let mut rng = SmallRng::seed_from_u64(batch_seed(seed, i, j));
+    (0..rows).map(|_| Some(rng.gen::<f64>() < p)).collect()
+}
+
+/// Uniform value in `[0, 1)` of the hash of `identity`
+fn unit_hash(identity: impl FnOnce(&mut XxHash64)) -> f64 {
+    let mut hasher = XxHash64::with_seed(0);
+    identity(&mut hasher);
+    (hasher.finish() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// Mask of the stable sample of batch `j` of the store entry `key`, by the
+/// [ID_COLUMN] or the position of the rows without an id
+pub(crate) fn stable_mask(
+    batch: &RecordBatch,
+    p: f64,
+    key: &str,
+    j: usize,
+) -> Result<BooleanArray, PointCloudError> {
+    let ids = match batch.column_by_name(ID_COLUMN) {
+        Some(ids) => Some(cast(ids, &DataType::Int64).map_err(|_| {
+            PointCloudError::InvalidArgument(format!("column `{ID_COLUMN}` is not an integer"))
+        })?),
+        None => None,
+    };
+    let ids = ids.as_ref().map(|ids| ids.as_primitive::<Int64Type>());
+    Ok((0..batch.num_rows())
+        .map(|row| {
+            let hash = match ids.filter(|ids| ids.is_valid(row)) {
+                Some(ids) => unit_hash(|hasher| hasher.write_i64(ids.value(row))),
+                None => unit_hash(|hasher| {
+                    hasher.write(key.as_bytes());
+                    hasher.write_u64(j as u64);
+                    hasher.write_u64(row as u64);
+                }),
+            };
+            Some(hash < p)
+        })
+        .collect())
+}
+
+/// Truncates and projects the selected batches
+struct Output<F> {
+    emit: F,
+    remaining: usize,
+    projection: Option<Vec<usize>>,
+}
+
+impl<F> Output<F>
+where
+    F: FnMut(RecordBatch) -> Result<(), PointCloudError>,
+{
+    fn done(&self) -> bool {
+        self.remaining == 0
+    }
+
+    fn push(&mut self, batch: RecordBatch) -> Result<(), PointCloudError> {
+        let rows = batch.num_rows().min(self.remaining);
+        if rows == 0 {
+            return Ok(());
+        }
+        self.remaining -= rows;
+
+        let batch = batch.slice(0, rows);
+        match &self.projection {
+            Some(indices) => (self.emit)(batch.project(indices)?),
+            None => (self.emit)(batch),
+        }
+    }
+}
+
+impl ArrowPointCloud {
+    /// Select points, see [ArrowPointCloud::stream]
+    pub fn execute(&self, query: &Query) -> Result<ArrowPointCloud, PointCloudError> {
+        let mut pc = ArrowPointCloud::try_new(query.schema(&self.schema())?)?;
+        self.stream(query, |batch| pc.append(batch))?;
+        Ok(pc)
+    }
+
+    /// Pass the selected points to `emit` in iteration order, as batches of
+    /// the schema [Query::schema].
+    ///
+    /// The stages are applied in this order:
+    ///
+    /// 1. seeded and stable sampling ([Sample::Seeded], [Sample::Stable]),
+    /// 2. bounds, importance ([Sample::P]), polygon, frustum and radius,
+    /// 3. range and attribute filter,
+    /// 4. stratified sampling ([Sample::Stratified]) of the selected points,
+    /// 5. limit and
+    /// 6. projection.
+    ///
+    /// Batches outside of the bounds of the query are skipped by their index
+    /// entry or their bounds, batches within are not filtered. Batches outside
+    /// of the time range are skipped by the ranges recorded by the store.
+    /// Store entries are selected in parallel, a few at a time, and no further
+    /// entries are read once the limit is reached.
+    pub fn stream(
+        &self,
+        query: &Query,
+        emit: impl FnMut(RecordBatch) -> Result<(), PointCloudError>,
+    ) -> Result<(), PointCloudError> {
+        self.stream_with(query, &(), emit)
+    }
+
+    /// Stream reporting progress per store entry, see [ArrowPointCloud::stream]
+    ///
+    /// Aborts with [PointCloudError::Cancelled] before the next entry is read
+    /// once `progress` is cancelled, also while no points are emitted.
+    pub fn stream_with(
+        &self,
+        query: &Query,
+        progress: &dyn ProgressSink,
+        emit: impl FnMut(RecordBatch) -> Result<(), PointCloudError>,
+    ) -> Result<(), PointCloudError> {
+        let span = span!(DEBUG, "query", entries = self.store.len(), rows);
+        let schema = self.schema();
+        query.validate(&schema)?;
+
+        let limit = query.limit.unwrap_or(usize::MAX);
+        let mut output = Output {
+            emit,
+            remaining: limit,
+            projection: query.projection(&schema)?,
+        };
+        let mut aabb = query.aabb();
+        let dims = schema::dimensions(&schema).len().min(4);
+        if dims < 4 {
+            // unbounded missing dimensions, e.g. the importance, the index
+            // and bounds of point clouds without them are empty there
+            let (mut lower, mut upper) = (aabb.lower(), aabb.upper());
+            for d in dims..4 {
+                *rstar::Point::nth_mut(&mut lower, d) = f64::MIN;
+                *rstar::Point::nth_mut(&mut upper, d) = f64::MAX;
+            }
+            aabb = AABB::from_corners(lower, upper);
+        }
+
+        // indexed entries intersecting the query
+        let candidates: Option<HashSet<String>> = match &self.index {
+            Index::Batch(index) => Some(
+                index
+                    .locate_in_envelope_intersecting(&aabb)
+                    .map(|object| object.data.clone())
+                    .collect(),
+            ),
+            _ => None,
+        };
+
+        // batches within the time range, by entry
+        let times: HashMap<String, Vec<usize>> = match &query.range {
+            Some((column, range)) if column == TIME_COLUMN => self
+                .time_candidates(range)
+                .into_iter()
+                .filter_map(|(key, positions)| positions.map(|positions| (key, positions)))
+                .collect(),
+            _ => HashMap::new(),
+        };
+
+        let tracker = Tracker::new(progress, self.store.len());
+        let select = |i: usize, key: &str| -> Result<Vec<RecordBatch>, PointCloudError> {
+            tracker.check()?;
+            let positions = times.get(key);
+            if candidates.as_ref().is_some_and(|c| !c.contains(key))
+                || positions.is_some_and(Vec::is_empty)
+            {
+                tracker.step();
+                return Ok(Vec::new());
+            }
+
+            let mut selected = Vec::new();
+            for (j, batch) in self.store.batches(key).into_iter().enumerate() {
+                if positions.is_some_and(|positions| !positions.contains(&j)) {
+                    continue;
+                }
+                let batch = match &query.sample {
+                    Some(Sample::Seeded { p, seed }) => {
+                        let span = span!(TRACE, "sample", input = batch.num_rows(), rows);
+                        let mask = sample_mask(batch.num_rows(), *p, *seed, i, j);
+                        let batch = filter_record_batch(&batch, &mask)?;
+                        span.record("rows", batch.num_rows());
+                        batch
+                    }
+                    Some(Sample::Stable(p)) => {
+                        let span = span!(TRACE, "sample", input = batch.num_rows(), rows);
+                        let batch = filter_record_batch(&batch, &stable_mask(&batch, *p, key, j)?)?;
+                        span.record("rows", batch.num_rows());
+                        batch
+                    }
+                    _ => batch,
+                };
+                if batch.num_rows() == 0 {
+                    continue;
+                }
+
+                let envelope = compute::aabb(&batch);
+                if !Query::intersects(&aabb, &envelope, dims) {
+                    continue;
+                }
+                let batch = if query.covers(&aabb, &envelope, dims) {
+                    batch
+                } else {
+                    query.filter_batch(&batch, &aabb)?
+                };
+                if batch.num_rows() > 0 {
+                    selected.push(batch);
+                }
+            }
+            tracker.step();
+            Ok(selected)
+        };
+
+        let entries: Vec<(usize, String)> = self
+            .store
+            .iter()
+            .enumerate()
+            .map(|(i, e)| (i, e.key().to_owned()))
+            .collect();
+
+        if let Some(Sample::Stratified {
+            column,
+            total,
+            min_per_class,
+        }) = &query.sample
+        {
+            let batches = entries
+                .par_iter()
+                .map(|(i, key)| select(*i, key))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            let mut selected = ArrowPointCloud::try_new(schema)?;
+            for batch in batches.into_iter().flatten() {
+                selected.append(batch)?;
+            }
+            let sample = selected.sample_stratified(column, *total, *min_per_class)?;
+
+            for batch in sample
+                .store
+                .iter()
+                .flat_map(|e| sample.store.batches(e.key()))
+            {
+                output.push(batch)?;
+                if output.done() {
+                    break;
+                }
+            }
+            span.record("rows", limit - output.remaining);
+            return Ok(());
+        }
+
+        let window = rayon::current_num_threads() * 4;
+        for entries in entries.chunks(window) {
+            if output.done() {
+                break;
+            }
+            let batches = entries
+                .par_iter()
+                .map(|(i, key)| select(*i, key))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            for batch in batches.into_iter().flatten() {
+                output.push(batch)?;
+                if output.done() {
+                    break;
+                }
+            }
+        }
+
+        span.record("rows", limit - output.remaining);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow::{
+        array::{Array, ArrayRef, AsArray, Float32Array, UInt32Array, UInt8Array},
+        compute::concat_batches,
+        datatypes::{Field, Float64Type, Schema},
+    };
+
+    use super::*;
+    use crate::{frustum, CoordSpec};
+
+    /// Points on a 20 x 20 grid with random importance, intensity and classes
+    /// in batches of varying size
+    fn cloud(rng: &mut SmallRng) -> ArrowPointCloud {
+        let mut fields: Vec<Field> = Point::<f64, 3>::schema()
+            .fields()
+            .iter()
+            .map(|field| field.as_ref().clone())
+            .collect();
+        fields.push(Field::new("intensity", DataType::Float64, true));
+        fields.push(Field::new("classification", DataType::UInt8, false));
+        let schema = schema::add_importance(
+            Arc::new(Schema::new_with_metadata(
+                fields,
+                Point::<f64, 3>::schema().metadata().clone(),
+            )),
+            "i",
+            DataType::Float32,
+            3,
+        );
+
+        let mut pc = ArrowPointCloud::try_new(schema.clone()).unwrap();
+        let mut offset = 0;
+        while offset < 400 {
+            let rows = rng.gen_range(1..60).min(400 - offset);
+            let range = offset..offset + rows;
+            let column = |f: fn(usize) -> f64| {
+                Arc::new(Float64Array::from_iter_values(range.clone().map(f))) as ArrayRef
+            };
+            let intensity: Float64Array = range
+                .clone()
+                .map(|i| (i % 7 != 0).then(|| (i * 37 % 200) as f64))
+                .collect();
+            let batch = RecordBatch::try_new(
+                schema.clone(),
+                vec![
+                    column(|i| (i % 20) as f64 + 0.5),
+                    column(|i| (i / 20) as f64 + 0.5),
+                    column(|i| (i % 13) as f64),
+                    Arc::new(Float32Array::from_iter_values(
+                        range.clone().map(|_| rng.gen::<f32>()),
+                    )),
+                    Arc::new(intensity),
+                    Arc::new(UInt8Array::from_iter_values(
+                        range.clone().map(|i| [2, 2, 2, 5, 5, 14][i % 6]),
+                    )),
+                ],
+            )
+            .unwrap();
+            pc.append(batch).unwrap();
+            offset += rows;
+        }
+        pc
+    }
+
+    fn batches(pc: &ArrowPointCloud) -> Vec<RecordBatch> {
+        pc.store
+            .iter()
+            .flat_map(|e| pc.store.batches(e.key()))
+            .collect()
+    }
+
+    fn concat(schema: &SchemaRef, batches: &[RecordBatch]) -> RecordBatch {
+        concat_batches(schema, batches).unwrap()
+    }
+
+    /// The stages of the query applied one after another to all points
+    fn naive(pc: &ArrowPointCloud, query: &Query) -> RecordBatch {
+        let schema = pc.schema();
+
+        // seeded sample per stored batch
+        let mut sampled = Vec::new();
+        for (i, e) in pc.store.iter().enumerate() {
+            for (j, batch) in pc.store.batches(e.key()).iter().enumerate() {
+                let batch = match &query.sample {
+                    Some(Sample::Seeded { p, seed }) => {
+                        let mask = sample_mask(batch.num_rows(), *p, *seed, i, j);
+                        filter_record_batch(batch, &mask).unwrap()
+                    }
+                    Some(Sample::Stable(p)) => {
+                        let mask = stable_mask(batch, *p, e.key(), j).unwrap();
+                        filter_record_batch(batch, &mask).unwrap()
+                    }
+                    _ => batch.clone(),
+                };
+                sampled.push(batch);
+            }
+        }
+        let mut all = concat(&schema, &sampled);
+
+        // row by row spatial and attribute tests
+        let x = all.column(0).as_primitive::<Float64Type>().clone();
+        let y = all.column(1).as_primitive::<Float64Type>().clone();
+        let z = all.column(2).as_primitive::<Float64Type>().clone();
+        let importance = all
+            .column(3)
+            .as_primitive::<arrow::datatypes::Float32Type>()
+            .clone();
+        let value = |column: &str, row: usize| -> Option<f64> {
+            let array = cast(all.column_by_name(column).unwrap(), &DataType::Float64).unwrap();
+            let array = array.as_primitive::<Float64Type>();
+            array.is_valid(row).then(|| array.value(row))
+        };
+        fn matches(
+            expr: &Expr,
+            value: &dyn Fn(&str, usize) -> Option<f64>,
+            row: usize,
+        ) -> Option<bool> {
+            match expr {
+                Expr::Cmp {
+                    column,
+                    op,
+                    value: v,
+                } => value(column, row).map(|x| match op {
+                    CmpOp::Eq => x == *v,
+                    CmpOp::Ne => x != *v,
+                    CmpOp::Lt => x < *v,
+                    CmpOp::Le => x <= *v,
+                    CmpOp::Gt => x > *v,
+                    CmpOp::Ge => x >= *v,
+                }),
+                Expr::And(a, b) => Some(matches(a, value, row)? & matches(b, value, row)?),
+                Expr::Or(a, b) => Some(matches(a, value, row)? | matches(b, value, row)?),
+                Expr::Not(a) => matches(a, value, row).map(|m| !m),
+            }
+        }
+
+        let mask: BooleanArray = (0..all.num_rows())
+            .map(|row| {
+                let p = [x.value(row), y.value(row), z.value(row)];
+                let mut keep = true;
+                if let Some((l, u)) = &query.bounds {
+                    keep &= (0..3).all(|d| l[d] <= p[d] && p[d] < u[d]);
+                }
+                if let Some((ring, z_range)) = &query.polygon {
+                    keep &= polygon::contains(ring, [p[0], p[1]]);
+                    keep &= z_range.as_ref().is_none_or(|z| z.contains(&p[2]));
+                }
+                if let Some(frustum) = &query.frustum {
+                    keep &= frustum.contains(p);
+                }
+                if let Some((center, radius)) = &query.near {
+                    let distance2: f64 = center.iter().zip(p).map(|(c, x)| (x - c).powi(2)).sum();
+                    keep &= distance2 <= radius * radius;
+                }
+                if let Some(Sample::P(v)) = query.sample {
+                    keep &= (importance.value(row) as f64) < v;
+                }
+                if let Some(filter) = &query.filter {
+                    keep &= matches(filter, &value, row) == Some(true);
+                }
+                Some(keep)
+            })
+            .collect();
+        all = filter_record_batch(&all, &mask).unwrap();
+
+        if let Some(Sample::Stratified {
+            column,
+            total,
+            min_per_class,
+        }) = &query.sample
+        {
+            let mut selected = ArrowPointCloud::try_new(schema.clone()).unwrap();
+            selected.append(all).unwrap();
+            let sample = selected
+                .sample_stratified(column, *total, *min_per_class)
+                .unwrap();
+            all = concat(&schema, &batches(&sample));
+        }
+
+        if let Some(limit) = query.limit {
+            all = all.slice(0, limit.min(all.num_rows()));
+        }
+        match query.projection(&schema).unwrap() {
+            Some(indices) => all.project(&indices).unwrap(),
+            None => all,
+        }
+    }
+
+    fn random_query(rng: &mut SmallRng) -> Query {
+        let mut query = Query::new();
+        if rng.gen_bool(0.5) {
+            let l = [
+                rng.gen_range(0.0..15.),
+                rng.gen_range(0.0..15.),
+                rng.gen_range(0.0..6.),
+            ];
+            query = query.bounds(AABB::from_corners(
+                Point::<f64, 3>::from_slice(&l),
+                Point::from_slice(&[
+                    l[0] + rng.gen_range(1.0..10.),
+                    l[1] + rng.gen_range(1.0..10.),
+                    l[2] + 6.,
+                ]),
+            ));
+        }
+        if rng.gen_bool(0.3) {
+            let z = rng.gen_bool(0.5).then_some(2.0..=9.);
+            query = query.polygon(vec![[0., 0.], [20., 0.], [0., 20.], [0., 0.]], z);
+        }
+        if rng.gen_bool(0.2) {
+            query = query.frustum(frustum::parse("10,10,50,0,0,-1,20,1,1,100").unwrap());
+        }
+        if rng.gen_bool(0.2) {
+            let center = [rng.gen_range(0.0..20.), rng.gen_range(0.0..20.), 5.];
+            let radius = rng.gen_range(1.0..8.);
+            query = match rng.gen_bool(0.5) {
+                true => query.near(Point::<f64, 3>::from_slice(&center), radius),
+                false => query.near(Point::<f64, 2>::from_slice(&center), radius),
+            };
+        }
+        if rng.gen_bool(0.5) {
+            let threshold = rng.gen_range(0.0..200.);
+            let mut filter = Expr::cmp("intensity", CmpOp::Ge, threshold);
+            if rng.gen_bool(0.5) {
+                filter = filter.or(Expr::cmp("classification", CmpOp::Eq, 14.));
+            }
+            if rng.gen_bool(0.3) {
+                filter = filter.not();
+            }
+            query = query.filter(filter);
+        }
+        if rng.gen_bool(0.3) {
+            query = query.columns(&["classification"]);
+        }
+        query = match rng.gen_range(0..5) {
+            0 => query.sample(Sample::P(rng.gen())),
+            1 => query.sample(Sample::Seeded {
+                p: rng.gen(),
+                seed: rng.gen(),
+            }),
+            2 => query.sample(Sample::Stratified {
+                column: "classification".to_string(),
+                total: rng.gen_range(1..100),
+                min_per_class: rng.gen_range(0..10),
+            }),
+            3 => query.sample(Sample::Stable(rng.gen())),
+            _ => query,
+        };
+        if rng.gen_bool(0.3) {
+            query = query.limit(rng.gen_range(0..100));
+        }
+        query
+    }
+
+    #[test]
+    fn four_dimensional_bounds() {
+        // a 10 x 10 grid observed at times 0 to 99, sampled by importance
+        let schema = Arc::new(Schema::new(
+            ["x", "y", "z", "t"]
+                .map(|name| Field::new(name, DataType::Float64, false))
+                .to_vec(),
+        ));
+        let schema = schema::add_importance(schema, "i", DataType::Float32, 4);
+        let spec = CoordSpec::new(&["x", "y", "z", "t"]);
+        let mut pc = ArrowPointCloud::try_new_with_coords(schema, &spec).unwrap();
+        let column = |f: fn(usize) -> f64| {
+            Arc::new(Float64Array::from_iter_values((0..100).map(f))) as ArrayRef
+        };
+        let batch = RecordBatch::try_new(
+            pc.schema(),
+            vec![
+                column(|i| (i % 10) as f64),
+                column(|i| (i / 10) as f64),
+                column(|_| 0.),
+                column(|i| i as f64),
+                Arc::new(Float32Array::from(vec![0.5; 100])),
+            ],
+        )
+        .unwrap();
+        pc.append(batch).unwrap();
+        pc.build_index(&()).unwrap();
+
+        let query = Query::new().bounds(AABB::from_corners(
+            Point::<f64, 4>::from_slice(&[0., 0., -1., 20.]),
+            Point::from_slice(&[5., 10., 1., 60.]),
+        ));
+        let expected = (20..60).filter(|i| i % 10 < 5).count();
+        assert_eq!(pc.execute(&query).unwrap().num_points(), expected);
+
+        // the fourth dimension is the time, not the importance
+        let sampled = Query::new().sample(Sample::P(0.5));
+        assert!(pc.execute(&sampled).is_err());
+    }
+
+    #[test]
+    fn stages() {
+        let mut rng = SmallRng::seed_from_u64(0);
+        let mut pc = cloud(&mut rng);
+
+        for round in 0..200 {
+            if round == 100 {
+                // the same with batch index
+                pc.build_index(&()).unwrap();
+            }
+
+            let query = random_query(&mut rng);
+            let result = pc.execute(&query).unwrap();
+            let schema = query.schema(&pc.schema()).unwrap();
+            assert_eq!(result.schema(), schema);
+            assert_eq!(
+                concat(&schema, &batches(&result)),
+                naive(&pc, &query),
+                "{query:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn stable() {
+        let mut rng = SmallRng::seed_from_u64(2);
+        // the positions of the rows are hashed with the key of their entry,
+        // seeded keys instead of random ones keep the samples deterministic
+        let mut keys = SmallRng::seed_from_u64(3);
+        let mut key = move || format!("{:016x}", keys.gen::<u64>());
+        let generated = cloud(&mut rng);
+        let pc = ArrowPointCloud::try_new(generated.schema()).unwrap();
+        for batch in batches(&generated) {
+            pc.store.push(key(), batch);
+        }
+
+        // points by their unique x and y
+        let sample = |pc: &ArrowPointCloud, p: f64| -> HashSet<(u64, u64)> {
+            let result = pc.execute(&Query::new().sample(Sample::Stable(p))).unwrap();
+            batches(&result)
+                .iter()
+                .flat_map(|batch| {
+                    let x = batch.column(0).as_primitive::<Float64Type>().clone();
+                    let y = batch.column(1).as_primitive::<Float64Type>().clone();
+                    (0..batch.num_rows())
+                        .map(move |row| (x.value(row).to_bits(), y.value(row).to_bits()))
+                })
+                .collect()
+        };
+        let dense = sample(&pc, 0.1);
+        let sparse = sample(&pc, 0.01);
+        assert!(sparse.len() < dense.len());
+        assert!(!sparse.is_empty() && sparse.is_subset(&dense));
+        assert!((20..60).contains(&dense.len()), "{}", dense.len());
+
+        // appended points are sampled, the sampled points are kept
+        let appended = cloud(&mut rng);
+        let shifted = |batch: &RecordBatch| {
+            let x: Float64Array = batch
+                .column(0)
+                .as_primitive::<Float64Type>()
+                .iter()
+                .map(|x| x.map(|x| x + 100.))
+                .collect();
+            let mut columns = batch.columns().to_vec();
+            columns[0] = Arc::new(x);
+            RecordBatch::try_new(batch.schema(), columns).unwrap()
+        };
+        for batch in batches(&appended) {
+            pc.store.push(key(), shifted(&batch));
+        }
+        let grown = sample(&pc, 0.1);
+        assert!(dense.is_subset(&grown) && grown.len() > dense.len());
+        assert!(sample(&pc, 0.01).is_superset(&sparse));
+
+        // by the point ids regardless of the batches
+        let mut fields: Vec<Field> = Point::<f64, 3>::schema()
+            .fields()
+            .iter()
+            .map(|field| field.as_ref().clone())
+            .collect();
+        fields.push(Field::new(ID_COLUMN, DataType::UInt32, false));
+        let schema = Arc::new(Schema::new_with_metadata(
+            fields,
+            Point::<f64, 3>::schema().metadata().clone(),
+        ));
+        let points = |ids: Range<u32>| {
+            let values = || ids.clone().map(f64::from);
+            RecordBatch::try_new(
+                schema.clone(),
+                vec![
+                    Arc::new(Float64Array::from_iter_values(values())),
+                    Arc::new(Float64Array::from_iter_values(values())),
+                    Arc::new(Float64Array::from_iter_values(values())),
+                    Arc::new(UInt32Array::from_iter_values(ids)),
+                ],
+            )
+            .unwrap()
+        };
+        let mut a = ArrowPointCloud::try_new(schema.clone()).unwrap();
+        a.append(points(0..1000)).unwrap();
+        let mut b = ArrowPointCloud::try_new(schema.clone()).unwrap();
+        for start in (0..1000).step_by(100) {
+            b.append(points(start..start + 100)).unwrap();
+        }
+        assert_eq!(sample(&a, 0.05), sample(&b, 0.05));
+    }
+
+    #[test]
+    fn builder() {
+        let mut rng = SmallRng::seed_from_u64(1);
+        let pc = cloud(&mut rng);
+
+        let query = Query::new()
+            .filter(Expr::cmp("classification", CmpOp::Eq, 14.))
+            .filter(Expr::cmp("intensity", CmpOp::Lt, 100.))
+            .columns(&["x", "y", "z", "intensity"])
+            .limit(10);
+        let result = pc.execute(&query).unwrap();
+        assert_eq!(result.num_points(), 10);
+        let names: Vec<_> = result
+            .schema()
+            .fields()
+            .iter()
+            .map(|f| f.name().clone())
+            .collect();
+        assert_eq!(names, ["x", "y", "z", "i", "intensity"]);
+
+        // the limit stops reading
+        let mut emitted = 0;
+        pc.stream(&Query::new().limit(1), |_| {
+            emitted += 1;
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(emitted, 1);
+
+        // cancelled while nothing is emitted
+        struct CancelAfter(std::sync::atomic::AtomicUsize);
+        impl ProgressSink for CancelAfter {
+            fn report(&self, done: usize, _total: usize) {
+                self.0.store(done, std::sync::atomic::Ordering::Relaxed);
+            }
+
+            fn is_cancelled(&self) -> bool {
+                self.0.load(std::sync::atomic::Ordering::Relaxed) >= 3
+            }
+        }
+        let progress = CancelAfter(Default::default());
+        let nothing = Query::new().filter(Expr::cmp("classification", CmpOp::Gt, 255.));
+        let sequential = rayon::ThreadPoolBuilder::new()
+            .num_threads(1)
+            .build()
+            .unwrap();
+        let result = sequential.install(|| pc.stream_with(&nothing, &progress, |_| unreachable!()));
+        assert!(matches!(result, Err(PointCloudError::Cancelled)));
+        assert_eq!(progress.0.into_inner(), 3);
+
+        for query in [
+            Query::new().filter(Expr::cmp("missing", CmpOp::Eq, 1.)),
+            Query::new().columns(&["missing"]),
+            Query::new().polygon(vec![[0., 0.], [1., 1.], [0., 0.]], None),
+            Query::new().near(Point::<f64, 3>::from_slice(&[0., 0., 0.]), -1.),
+            Query::new().near(Point::<f64, 2>::from_slice(&[f64::NAN, 0.]), 1.),
+            Query::new().sample(Sample::Stratified {
+                column: "missing".to_string(),
+                total: 10,
+                min_per_class: 1,
+            }),
+        ] {
+            assert!(pc.execute(&query).is_err(), "{query:?}");
+        }
+        let schema = pc.schema();
+        assert_eq!(query.missing_column(&schema), None);
+        let missing = Query::new()
+            .filter(Expr::cmp("intensity", CmpOp::Lt, 100.))
+            .columns(&["x", "missing"]);
+        assert_eq!(missing.missing_column(&schema), Some("missing"));
+
+        // without importance
+        let points = ArrowPointCloud::from_iter(
+            (0..10).map(|i| Point::<f64, 3>::from_slice(&[i as f64 + 0.5, 0.5, 0.5])),
+        )
+        .unwrap();
+        assert!(points
+            .execute(&Query::new().sample(Sample::P(0.5)))
+            .is_err());
+        assert_eq!(points.execute(&Query::new()).unwrap().num_points(), 10);
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(
+            "classification=2, intensity >= 100"
+                .parse::<Expr>()
+                .unwrap(),
+            Expr::cmp("classification", CmpOp::Eq, 2.).and(Expr::cmp("intensity", CmpOp::Ge, 100.))
+        );
+        assert_eq!(
+            "a!=1".parse::<Expr>().unwrap(),
+            Expr::cmp("a", CmpOp::Ne, 1.)
+        );
+        assert_eq!(
+            "a<-1.5".parse::<Expr>().unwrap(),
+            Expr::cmp("a", CmpOp::Lt, -1.5)
+        );
+        for invalid in ["", "a", "=1", "a=", "a=b", "a=>1"] {
+            assert!(invalid.parse::<Expr>().is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn exclude() {
+        let schema = Arc::new(Schema::new(vec![Field::new(
+            "intensity",
+            DataType::Float64,
+            true,
+        )]));
+        let intensity = Float64Array::from(vec![Some(50.), None, Some(150.)]);
+        let batch = RecordBatch::try_new(schema, vec![Arc::new(intensity)]).unwrap();
+
+        // rows with null values never match, so they are kept
+        let expr: Expr = "intensity>100".parse().unwrap();
+        let excluded = expr.exclude(&batch).unwrap();
+        assert_eq!(excluded, BooleanArray::from(vec![true, true, false]));
+        assert_eq!(excluded.null_count(), 0);
+    }
+}
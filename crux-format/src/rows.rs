@@ -0,0 +1,229 @@
+//! Points together with their attributes.
+//!
+//! [PointCloudTrait::points] yields the coordinates only, code that needs
+//! attributes of the same points, like the classification, iterates
+//! [ArrowPointCloud::rows] instead of zipping separately read columns. The
+//! coordinates of a batch are cast once, attributes on first access, so
+//! getters of a row are a lookup among the accessed columns.
+
+use std::{cell::RefCell, rc::Rc};
+
+use arrow::{
+    array::{Array, ArrayRef, AsArray},
+    datatypes::{
+        ArrowPrimitiveType, DataType, Float32Type, Float64Type, Int32Type, Int64Type, UInt16Type,
+        UInt8Type,
+    },
+    record_batch::RecordBatch,
+};
+
+use crate::{
+    compute::{aabb, filter_by_aabb},
+    encoding::cast,
+    schema::dimensions,
+    ArrowPointCloud, PointTrait, AABB,
+};
+
+/// Batch of the rows with its cast columns
+struct RowBatch {
+    batch: RecordBatch,
+    coordinates: Vec<ArrayRef>,
+    /// Attributes cast on first access by name and type, `None` if missing
+    /// or not castable
+    attributes: RefCell<Vec<(String, DataType, Option<ArrayRef>)>>,
+}
+
+impl RowBatch {
+    fn new(batch: RecordBatch) -> Self {
+        let coordinates = dimensions(&batch.schema())
+            .into_iter()
+            .map(|d| cast(batch.column(d), &DataType::Float64).unwrap())
+            .collect();
+        Self {
+            batch,
+            coordinates,
+            attributes: RefCell::new(Vec::new()),
+        }
+    }
+
+    fn value<T: ArrowPrimitiveType>(&self, name: &str, row: usize) -> Option<T::Native> {
+        let data_type = T::DATA_TYPE;
+        let mut attributes = self.attributes.borrow_mut();
+        let i = match attributes
+            .iter()
+            .position(|(n, t, _)| n == name && *t == data_type)
+        {
+            Some(i) => i,
+            None => {
+                let column = self
+                    .batch
+                    .column_by_name(name)
+                    .and_then(|column| cast(column, &data_type).ok());
+                attributes.push((name.to_owned(), data_type, column));
+                attributes.len() - 1
+            }
+        };
+        let column = attributes[i].2.as_ref()?.as_primitive::<T>();
+        column.is_valid(row).then(|| column.value(row))
+    }
+}
+
+/// Point of a cloud with access to its attributes, see
+/// [ArrowPointCloud::rows]
+#[derive(Clone)]
+pub struct PointRow {
+    batch: Rc<RowBatch>,
+    row: usize,
+}
+
+impl PointRow {
+    /// Coordinates of the point, missing dimensions are 0
+    pub fn point<P>(&self) -> P
+    where
+        P: PointTrait,
+        <P as rstar::Point>::Scalar: num_traits::NumCast,
+    {
+        let coordinates = &self.batch.coordinates;
+        P::generate(|nth| {
+            coordinates
+                .get(nth)
+                .and_then(|column| {
+                    num_traits::cast(column.as_primitive::<Float64Type>().value(self.row))
+                })
+                .unwrap_or_else(num_traits::zero)
+        })
+    }
+
+    /// Value of the attribute `name` cast to `T`, `None` if null, missing or
+    /// not castable
+    pub fn get<T: ArrowPrimitiveType>(&self, name: &str) -> Option<T::Native> {
+        self.batch.value::<T>(name, self.row)
+    }
+
+    pub fn get_u8(&self, name: &str) -> Option<u8> {
+        self.get::<UInt8Type>(name)
+    }
+
+    pub fn get_u16(&self, name: &str) -> Option<u16> {
+        self.get::<UInt16Type>(name)
+    }
+
+    pub fn get_i32(&self, name: &str) -> Option<i32> {
+        self.get::<Int32Type>(name)
+    }
+
+    pub fn get_i64(&self, name: &str) -> Option<i64> {
+        self.get::<Int64Type>(name)
+    }
+
+    pub fn get_f32(&self, name: &str) -> Option<f32> {
+        self.get::<Float32Type>(name)
+    }
+
+    pub fn get_f64(&self, name: &str) -> Option<f64> {
+        self.get::<Float64Type>(name)
+    }
+}
+
+impl ArrowPointCloud {
+    /// Points with their attributes in iteration order
+    pub fn rows(&self) -> impl Iterator<Item = PointRow> + '_ {
+        self.store
+            .iter()
+            .flat_map(|e| self.store.batches(e.key()))
+            .flat_map(batch_rows)
+    }
+
+    /// Points within `bounds` with their attributes in iteration order, the
+    /// upper bounds are exclusive like those of queries
+    pub fn rows_in<'a, P>(&'a self, bounds: &'a AABB<P>) -> impl Iterator<Item = PointRow> + 'a
+    where
+        P: PointTrait,
+        <P as rstar::Point>::Scalar: num_traits::NumCast,
+    {
+        self.store
+            .iter()
+            .flat_map(|e| self.store.batches(e.key()))
+            .filter(|batch| {
+                let envelope: AABB<P> = aabb(batch);
+                rstar::Envelope::intersects(&envelope, bounds)
+            })
+            .map(|batch| filter_by_aabb(&batch, bounds))
+            .flat_map(batch_rows)
+    }
+}
+
+fn batch_rows(batch: RecordBatch) -> impl Iterator<Item = PointRow> {
+    let rows = batch.num_rows();
+    let batch = Rc::new(RowBatch::new(batch));
+    (0..rows).map(move |row| PointRow {
+        batch: batch.clone(),
+        row,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Point, PointCloudTrait, Synthetic};
+
+    #[test]
+    fn aligned_rows() {
+        let pc = Synthetic::new(1000)
+            .intensity(true)
+            .batch_size(128)
+            .terrain()
+            .unwrap();
+        assert!(
+            pc.store
+                .iter()
+                .flat_map(|e| pc.store.batches(e.key()))
+                .count()
+                > 1
+        );
+
+        // the same points as the coordinates and columns read separately
+        let points: Vec<Point<f64, 3>> = pc.points().collect();
+        let column = |name: &str| -> Vec<Option<f64>> {
+            pc.store
+                .iter()
+                .flat_map(|e| pc.store.batches(e.key()))
+                .flat_map(|batch| {
+                    cast(batch.column_by_name(name).unwrap(), &DataType::Float64)
+                        .unwrap()
+                        .as_primitive::<Float64Type>()
+                        .iter()
+                        .collect::<Vec<_>>()
+                })
+                .collect()
+        };
+        let (intensity, class) = (column("intensity"), column("classification"));
+
+        let rows: Vec<PointRow> = pc.rows().collect();
+        assert_eq!(rows.len(), 1000);
+        for (i, row) in rows.iter().enumerate() {
+            assert_eq!(row.point::<Point<f64, 3>>(), points[i]);
+            assert_eq!(row.get_f64("intensity"), intensity[i]);
+            assert_eq!(row.get_u16("intensity").map(f64::from), intensity[i]);
+            assert_eq!(row.get_u8("classification").map(f64::from), class[i]);
+        }
+
+        // missing attributes, fewer and more dimensions
+        assert_eq!(rows[0].get_f64("gps_time"), None);
+        let p = rows[1].point::<Point<f64, 2>>();
+        assert_eq!(p.coords(), &points[1].coords()[..2]);
+        let p = rows[1].point::<Point<f64, 4>>();
+        assert_eq!(p.coords()[3], 0.);
+
+        // within bounds
+        let bounds = AABB::from_corners(
+            Point::<f64, 3>::from_slice(&[-1e9, -1e9, -1e9]),
+            Point::from_slice(&[points[500].x(), 1e9, 1e9]),
+        );
+        let within: Vec<Point<f64, 3>> = pc.rows_in(&bounds).map(|row| row.point()).collect();
+        let expected: Vec<&Point<f64, 3>> =
+            points.iter().filter(|p| p.x() < points[500].x()).collect();
+        assert_eq!(within.iter().collect::<Vec<_>>(), expected);
+        assert!(!within.is_empty() && within.len() < 1000);
+    }
+}
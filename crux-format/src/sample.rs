@@ -0,0 +1,337 @@
+use std::collections::{HashMap, HashSet};
+
+use arrow::{
+    array::{Array, AsArray, BooleanArray},
+    compute::{cast, filter_record_batch},
+    datatypes::{DataType, Float64Type, Int64Type},
+    record_batch::RecordBatch,
+};
+use rand::{rngs::SmallRng, Rng, SeedableRng};
+
+use crate::{
+    query::stable_mask, schema, trace::span, ArrowPointCloud, PointCloudError, PointCloudTrait,
+};
+
+/// Seed of the stratified selection, samples are reproducible
+const SEED: u64 = 0;
+
+/// Split a budget of `total` points across classes with `counts` points.
+///
+/// Every class keeps at least `min_per_class` points (or all of them), the
+/// rest of the budget is allocated proportionally to the remaining points. If
+/// the minimums exceed the budget, it is split evenly instead.
+pub fn stratified_quotas(counts: &[usize], total: usize, min_per_class: usize) -> Vec<usize> {
+    if counts.iter().sum::<usize>() <= total {
+        return counts.to_vec();
+    }
+
+    let minimums: Vec<usize> = counts.iter().map(|n| (*n).min(min_per_class)).collect();
+    let reserved: usize = minimums.iter().sum();
+
+    if reserved >= total {
+        // smallest classes first, so that their unused share goes to the others
+        let mut order: Vec<usize> = (0..counts.len()).collect();
+        order.sort_by_key(|i| minimums[*i]);
+
+        let mut quotas = vec![0; counts.len()];
+        let mut remaining = total;
+        for (k, i) in order.into_iter().enumerate() {
+            let share = remaining.div_ceil(counts.len() - k);
+            quotas[i] = minimums[i].min(share);
+            remaining -= quotas[i];
+        }
+        return quotas;
+    }
+
+    // largest remainder allocation of the rest
+    let rest = total - reserved;
+    let excess: Vec<usize> = counts.iter().zip(&minimums).map(|(n, m)| n - m).collect();
+    let sum = excess.iter().sum::<usize>() as f64;
+    let shares: Vec<f64> = excess
+        .iter()
+        .map(|n| *n as f64 / sum * rest as f64)
+        .collect();
+
+    let mut quotas: Vec<usize> = minimums
+        .iter()
+        .zip(&shares)
+        .map(|(m, share)| m + share.floor() as usize)
+        .collect();
+    let mut order: Vec<usize> = (0..counts.len()).collect();
+    order.sort_by(|a, b| {
+        let fraction = |i: usize| shares[i] - shares[i].floor();
+        fraction(*b).total_cmp(&fraction(*a))
+    });
+    let missing = total - quotas.iter().sum::<usize>();
+    for i in order.into_iter().take(missing) {
+        quotas[i] += 1;
+    }
+
+    quotas
+}
+
+/// Stable sample of a fraction `p` of the rows of a single batch, the rows
+/// [Sample::Stable](crate::query::Sample::Stable) selects from batch `j` of
+/// the store entry `key`
+pub fn sample_batch(
+    batch: &RecordBatch,
+    p: f64,
+    key: &str,
+    j: usize,
+) -> Result<RecordBatch, PointCloudError> {
+    if !(0. ..=1.).contains(&p) {
+        return Err(PointCloudError::InvalidArgument(format!(
+            "sample fraction {p} is not within [0, 1]"
+        )));
+    }
+    let _span = span!(TRACE, "sample_batch", input = batch.num_rows());
+    Ok(filter_record_batch(batch, &stable_mask(batch, p, key, j)?)?)
+}
+
+/// First point of every voxel with edge length `size` of a single batch.
+///
+/// Voxels are aligned to the origin of the coordinates, points with
+/// non-finite or missing coordinates are dropped.
+pub fn voxel_downsample(batch: &RecordBatch, size: f64) -> Result<RecordBatch, PointCloudError> {
+    if !(size.is_finite() && size > 0.) {
+        return Err(PointCloudError::InvalidArgument(format!(
+            "voxel size {size} is not positive"
+        )));
+    }
+    let _span = span!(TRACE, "voxel_downsample", input = batch.num_rows());
+
+    let columns = schema::coordinates(&batch.schema())
+        .into_iter()
+        .map(|c| cast(batch.column(c), &DataType::Float64))
+        .collect::<Result<Vec<_>, _>>()?;
+    let columns: Vec<_> = columns
+        .iter()
+        .map(|c| c.as_primitive::<Float64Type>())
+        .collect();
+    if columns.is_empty() {
+        return Err(PointCloudError::InvalidArgument(
+            "schema has no coordinates".to_owned(),
+        ));
+    }
+
+    let mut occupied = HashSet::new();
+    let mask: BooleanArray = (0..batch.num_rows())
+        .map(|row| {
+            let voxel: Option<Vec<i64>> = columns
+                .iter()
+                .map(|c| {
+                    let v = c.is_valid(row).then(|| c.value(row))?;
+                    v.is_finite().then(|| (v / size).floor() as i64)
+                })
+                .collect();
+            Some(voxel.is_some_and(|voxel| occupied.insert(voxel)))
+        })
+        .collect();
+    Ok(filter_record_batch(batch, &mask)?)
+}
+
+impl ArrowPointCloud {
+    /// Sample `total` points, stratified by the values of the categorical
+    /// `column`, see [stratified_quotas].
+    ///
+    /// Points are drawn uniformly within each class, the sample is the same
+    /// for the same points.
+    pub fn sample_stratified(
+        &self,
+        column: &str,
+        total: usize,
+        min_per_class: usize,
+    ) -> Result<ArrowPointCloud, PointCloudError> {
+        let span = span!(DEBUG, "sample_stratified", total = total, input, rows);
+        let mut values = Vec::with_capacity(self.num_points());
+        for batch in self.store.iter().flat_map(|e| self.store.batches(e.key())) {
+            let array = batch
+                .column_by_name(column)
+                .ok_or_else(|| PointCloudError::InvalidArgument(format!("no column `{column}`")))?;
+            let array = cast(array, &DataType::Int64).map_err(|_| {
+                PointCloudError::InvalidArgument(format!("column `{column}` is not categorical"))
+            })?;
+            values.extend(array.as_primitive::<Int64Type>().iter());
+        }
+
+        // classes in order of appearance, nulls are a class of their own
+        let mut classes: HashMap<Option<i64>, usize> = HashMap::new();
+        let mut counts = Vec::new();
+        let labels: Vec<usize> = values
+            .iter()
+            .map(|value| {
+                let class = *classes.entry(*value).or_insert_with(|| {
+                    counts.push(0);
+                    counts.len() - 1
+                });
+                counts[class] += 1;
+                class
+            })
+            .collect();
+
+        // selection sampling, exactly the quota of every class is selected
+        let mut needed = stratified_quotas(&counts, total, min_per_class);
+        let mut left = counts;
+        let mut rng = SmallRng::seed_from_u64(SEED);
+        let mask: BooleanArray = labels
+            .into_iter()
+            .map(|class| {
+                let selected = rng.gen_range(0..left[class]) < needed[class];
+                left[class] -= 1;
+                needed[class] -= selected as usize;
+                Some(selected)
+            })
+            .collect();
+
+        span.record("input", mask.len());
+        let sample = self.filter_mask(&mask)?;
+        span.record("rows", sample.num_points());
+        Ok(sample)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow::{
+        array::{ArrayRef, Float64Array, UInt8Array},
+        datatypes::{Field, Schema},
+        record_batch::RecordBatch,
+    };
+
+    use super::*;
+    use crate::{Point, PointTrait};
+
+    #[test]
+    fn quotas() {
+        // everything fits
+        assert_eq!(stratified_quotas(&[10, 5], 20, 3), [10, 5]);
+
+        // proportional beyond the minimum
+        assert_eq!(stratified_quotas(&[900, 90, 10], 100, 10), [74, 16, 10]);
+        assert_eq!(stratified_quotas(&[1000, 2], 10, 5), [8, 2]);
+
+        // minimums exceed the budget
+        assert_eq!(stratified_quotas(&[100, 100, 1], 9, 5), [4, 4, 1]);
+        assert_eq!(stratified_quotas(&[100, 100], 5, 5), [3, 2]);
+    }
+
+    #[test]
+    fn skewed() {
+        // ground (2) dominates, a few vegetation (5) and powerline (14) points
+        let classes: Vec<u8> = (0..10_000)
+            .map(|i| match i % 1000 {
+                0..=4 => 14,
+                5..=104 => 5,
+                _ => 2,
+            })
+            .collect();
+
+        let mut fields: Vec<Field> = Point::<f64, 3>::schema()
+            .fields()
+            .iter()
+            .map(|field| field.as_ref().clone())
+            .collect();
+        fields.push(Field::new("classification", DataType::UInt8, false));
+        let schema = Arc::new(Schema::new_with_metadata(
+            fields,
+            Point::<f64, 3>::schema().metadata().clone(),
+        ));
+
+        let mut pc = ArrowPointCloud::try_new(schema.clone()).unwrap();
+        for chunk in classes.chunks(1000) {
+            let column = |i: usize| {
+                Arc::new(Float64Array::from_iter_values(
+                    (0..chunk.len()).map(|j| (i * j) as f64 + 0.5),
+                )) as ArrayRef
+            };
+            let batch = RecordBatch::try_new(
+                schema.clone(),
+                vec![
+                    column(1),
+                    column(2),
+                    column(3),
+                    Arc::new(UInt8Array::from(chunk.to_vec())),
+                ],
+            )
+            .unwrap();
+            pc.append(batch).unwrap();
+        }
+
+        let count = |pc: &ArrowPointCloud, class: u8| -> usize {
+            pc.store
+                .iter()
+                .flat_map(|e| pc.store.batches(e.key()))
+                .map(|batch| {
+                    batch
+                        .column_by_name("classification")
+                        .unwrap()
+                        .as_primitive::<arrow::datatypes::UInt8Type>()
+                        .values()
+                        .iter()
+                        .filter(|c| **c == class)
+                        .count()
+                })
+                .sum()
+        };
+
+        assert_eq!(pc.num_points(), 10_000);
+
+        // a uniform 1% sample would keep half a powerline point
+        let sample = pc.sample_stratified("classification", 100, 20).unwrap();
+        assert_eq!(sample.num_points(), 100);
+        assert_eq!(count(&sample, 14), 20);
+        assert!(count(&sample, 5) >= 20);
+        assert_eq!(
+            count(&sample, 2) + count(&sample, 5) + count(&sample, 14),
+            100
+        );
+
+        // all points of a class smaller than the minimum
+        let sample = pc.sample_stratified("classification", 300, 100).unwrap();
+        assert_eq!(sample.num_points(), 300);
+        assert_eq!(count(&sample, 14), 50);
+        assert!(count(&sample, 5) >= 100);
+
+        // reproducible
+        let sample = |total| {
+            pc.sample_stratified("classification", total, 20)
+                .unwrap()
+                .content_hash()
+        };
+        assert_eq!(sample(100), sample(100));
+        assert_ne!(sample(100), sample(101));
+
+        assert!(pc.sample_stratified("intensity", 100, 20).is_err());
+    }
+
+    #[test]
+    fn batches() {
+        let pc = crate::synthetic::Synthetic::new(2000)
+            .seed(1)
+            .extent([0., 0., 0.], [100., 100., 100.])
+            .uniform()
+            .unwrap();
+        let batch = pc.store.batches(pc.store.iter().next().unwrap().key())[0].clone();
+        let rows = batch.num_rows();
+
+        // subsets for smaller fractions, as the stable sample of the store
+        let half = sample_batch(&batch, 0.5, "a", 0).unwrap();
+        let tenth = sample_batch(&batch, 0.1, "a", 0).unwrap();
+        assert!(half.num_rows() > rows / 3 && half.num_rows() < 2 * rows / 3);
+        assert!(tenth.num_rows() < half.num_rows());
+        assert_eq!(sample_batch(&batch, 1., "a", 0).unwrap().num_rows(), rows);
+        assert!(sample_batch(&batch, 1.5, "a", 0).is_err());
+
+        // a single point per voxel
+        let all = voxel_downsample(&batch, 1e-9).unwrap();
+        assert_eq!(all.num_rows(), rows);
+        let one = voxel_downsample(&batch, 1e9).unwrap();
+        assert_eq!(one.num_rows(), 1);
+        let octants = voxel_downsample(&batch, 50.).unwrap();
+        assert_eq!(octants.num_rows(), 8);
+        assert_eq!(voxel_downsample(&octants, 50.).unwrap(), octants);
+        assert!(voxel_downsample(&batch, 0.).is_err());
+    }
+}
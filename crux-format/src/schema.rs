@@ -1,9 +1,9 @@
-use std::sync::Arc;
+use std::{collections::HashSet, sync::Arc};
 
-use arrow::datatypes::{DataType, Field, Schema, SchemaRef};
+use arrow::datatypes::{DataType, Field, FieldRef, Schema, SchemaRef};
 use itertools::Itertools;
 
-use crate::PointCloudError;
+use crate::{CloudMetadata, MetadataPolicy, PointCloudError, PointTrait};
 
 /// Indexable dimension like location, time or importance.
 ///
@@ -16,6 +16,13 @@ pub const PCE_IMPORTANCE_KEY: &str = "PCE:importance";
 pub const PCE_OFFSET_KEY: &str = "PCE:offset";
 pub const PCE_SCALE_KEY: &str = "PCE:scale";
 
+/// Coordinate reference system of a point cloud (schema metadata), e.g. `EPSG:28992`
+pub const CRUX_CRS_KEY: &str = "crux:crs";
+/// Linear unit of the coordinates (schema metadata), see [crate::metadata::LengthUnit]
+pub const CRUX_UNITS_KEY: &str = "crux:units";
+/// Vertical datum of the heights (schema metadata), e.g. `NAP`
+pub const CRUX_VERTICAL_DATUM_KEY: &str = "crux:vertical_datum";
+
 /// extract dimensions from schema
 pub fn dimensions(schema: &SchemaRef) -> Vec<usize> {
     schema
@@ -28,6 +35,14 @@ pub fn dimensions(schema: &SchemaRef) -> Vec<usize> {
         .collect_vec()
 }
 
+/// Dimensions of the coordinates, all but the importance in dimension order
+pub fn coordinates(schema: &SchemaRef) -> Vec<usize> {
+    dimensions(schema)
+        .into_iter()
+        .filter(|i| !schema.field(*i).metadata().contains_key(PCE_IMPORTANCE_KEY))
+        .collect()
+}
+
 /// test whether schema has importance dimension
 pub fn importance(schema: &SchemaRef) -> Option<usize> {
     schema
@@ -78,10 +93,10 @@ pub fn add_importance(
 pub fn validate(schema: &SchemaRef) -> Result<(), PointCloudError> {
     let dimensions = dimensions(schema);
 
-    // assert schema has at least 3 dimensions
-    if dimensions.len() < 3 {
+    // assert schema has at least 2 dimensions
+    if dimensions.len() < 2 {
         return Err(PointCloudError::SchemaError(
-            "schema has at least 3 dimensions".to_string(),
+            "schema has at least 2 dimensions".to_string(),
         ));
     }
 
@@ -97,3 +112,260 @@ pub fn validate(schema: &SchemaRef) -> Result<(), PointCloudError> {
 
     Ok(())
 }
+
+/// Axes of the coordinates by dimension, the location of [PCE_LOCATION_KEY]
+const AXES: [&str; 4] = ["x", "y", "z", "t"];
+
+/// Names of the coordinate columns in dimension order, e.g. `x`, `y`, `depth`
+/// of bathymetry or `x`, `y`, `z`, `t` of a spatio-temporal cloud.
+///
+/// The spec is kept as field metadata of the schema ([PCE_DIMENSION_KEY] and
+/// [PCE_LOCATION_KEY]) and thus survives IPC round trips, [dimensions] and
+/// everything built on it follow it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CoordSpec {
+    pub names: Vec<String>,
+}
+
+impl Default for CoordSpec {
+    fn default() -> Self {
+        Self::new(&AXES[..3])
+    }
+}
+
+impl CoordSpec {
+    pub fn new(names: &[&str]) -> Self {
+        Self {
+            names: names.iter().map(|name| name.to_string()).collect(),
+        }
+    }
+
+    /// Coordinates of `schema`, see [coordinates]
+    pub fn from_schema(schema: &SchemaRef) -> Self {
+        Self {
+            names: coordinates(schema)
+                .into_iter()
+                .map(|i| schema.field(i).name().to_owned())
+                .collect(),
+        }
+    }
+
+    /// Number of coordinates
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    /// Reject points of `P` unless they have one dimension per coordinate
+    pub fn check<P: PointTrait>(&self) -> Result<(), PointCloudError> {
+        if P::DIMENSIONS != self.len() {
+            return Err(PointCloudError::InvalidArgument(format!(
+                "points of {} dimensions, the coordinates are {}",
+                P::DIMENSIONS,
+                self.names.join(", ")
+            )));
+        }
+        Ok(())
+    }
+
+    /// `schema` with these coordinates as its first dimensions, followed by
+    /// the importance if any.
+    ///
+    /// Two to four distinct numeric columns are required, other columns are
+    /// no longer dimensions.
+    pub fn apply(&self, schema: &SchemaRef) -> Result<SchemaRef, PointCloudError> {
+        let invalid = |message: String| Err(PointCloudError::SchemaError(message));
+        if !(2..=AXES.len()).contains(&self.len()) {
+            return invalid(format!("2 to 4 coordinates, got {}", self.len()));
+        }
+        if self.names.iter().collect::<HashSet<_>>().len() != self.len() {
+            return invalid(format!("duplicate coordinates {}", self.names.join(", ")));
+        }
+        if let Some(name) = self.names.iter().find(|name| {
+            schema
+                .field_with_name(name)
+                .map_or(true, |field| !field.data_type().is_numeric())
+        }) {
+            return invalid(format!("no numeric column `{name}`"));
+        }
+
+        let fields: Vec<FieldRef> = schema
+            .fields()
+            .iter()
+            .map(|field| {
+                let mut metadata = field.metadata().to_owned();
+                metadata.remove(PCE_LOCATION_KEY);
+                let dimension = match self.names.iter().position(|name| name == field.name()) {
+                    Some(d) => {
+                        metadata.insert(PCE_LOCATION_KEY.to_owned(), AXES[d].to_owned());
+                        Some(d + 1)
+                    }
+                    None if metadata.contains_key(PCE_IMPORTANCE_KEY) => Some(self.len() + 1),
+                    None => None,
+                };
+                match dimension {
+                    Some(d) => metadata.insert(PCE_DIMENSION_KEY.to_owned(), d.to_string()),
+                    None => metadata.remove(PCE_DIMENSION_KEY),
+                };
+                Arc::new(field.as_ref().clone().with_metadata(metadata))
+            })
+            .collect();
+
+        let schema = Arc::new(Schema::new_with_metadata(
+            fields,
+            schema.metadata().to_owned(),
+        ));
+        validate(&schema)?;
+        Ok(schema)
+    }
+}
+
+/// Union of the fields of `schemas` by name, in order of first occurrence.
+///
+/// Fields missing in some of the schemas are nullable, fields of the same
+/// name must share the data type. Unset spatial reference metadata is taken
+/// from later schemas, conflicting metadata is rejected under
+/// [MetadataPolicy::Strict] and the first one kept otherwise.
+pub fn union(schemas: &[SchemaRef], policy: MetadataPolicy) -> Result<SchemaRef, PointCloudError> {
+    let Some(first) = schemas.first() else {
+        return Ok(Arc::new(Schema::empty()));
+    };
+
+    let mut fields: Vec<FieldRef> = Vec::new();
+    let mut metadata = CloudMetadata::from_schema(first);
+    for schema in schemas {
+        let theirs = CloudMetadata::from_schema(schema);
+        let conflicts = metadata.conflicts(&theirs);
+        if policy == MetadataPolicy::Strict && !conflicts.is_empty() {
+            let conflicts: Vec<String> = conflicts.iter().map(|c| c.to_string()).collect();
+            return Err(PointCloudError::SchemaError(format!(
+                "conflicting metadata {}",
+                conflicts.join(", ")
+            )));
+        }
+        metadata.fill(&theirs);
+
+        for field in schema.fields() {
+            match fields.iter_mut().find(|f| f.name() == field.name()) {
+                Some(existing) if existing.data_type() != field.data_type() => {
+                    return Err(PointCloudError::SchemaError(format!(
+                        "column `{}` is {} and {}",
+                        field.name(),
+                        existing.data_type(),
+                        field.data_type()
+                    )));
+                }
+                Some(existing) => {
+                    if field.is_nullable() && !existing.is_nullable() {
+                        *existing = Arc::new(existing.as_ref().clone().with_nullable(true));
+                    }
+                }
+                None => fields.push(field.clone()),
+            }
+        }
+    }
+
+    // missing in some
+    let fields: Vec<FieldRef> = fields
+        .into_iter()
+        .map(|field| {
+            if schemas
+                .iter()
+                .all(|s| s.field_with_name(field.name()).is_ok())
+            {
+                field
+            } else {
+                Arc::new(field.as_ref().clone().with_nullable(true))
+            }
+        })
+        .collect();
+
+    let mut entries = first.metadata().to_owned();
+    metadata.apply(&mut entries);
+
+    Ok(Arc::new(Schema::new_with_metadata(fields, entries)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{LengthUnit, Point, PointTrait};
+
+    fn with_metadata(schema: SchemaRef, metadata: &CloudMetadata) -> SchemaRef {
+        let mut entries = schema.metadata().to_owned();
+        metadata.apply(&mut entries);
+        Arc::new(schema.as_ref().clone().with_metadata(entries))
+    }
+
+    #[test]
+    fn union_of_schemas() {
+        let xyz = Point::<f64, 3>::schema();
+        let mut fields = xyz.fields().to_vec();
+        fields.push(Arc::new(Field::new("intensity", DataType::UInt16, false)));
+        let intensity = Arc::new(Schema::new_with_metadata(fields, xyz.metadata().clone()));
+
+        let union = union(&[xyz.clone(), intensity.clone()], MetadataPolicy::Strict).unwrap();
+        assert_eq!(union.fields().len(), 4);
+        assert_eq!(dimensions(&union), [0, 1, 2]);
+        assert!(!union.field(0).is_nullable());
+        // missing in the first
+        assert!(union.field(3).is_nullable());
+        assert_eq!(union.field(3).data_type(), &DataType::UInt16);
+
+        // unset metadata is filled, conflicts follow the policy
+        let metres = CloudMetadata {
+            crs: Some("EPSG:28992".to_string()),
+            units: Some(LengthUnit::Metre),
+            vertical_datum: None,
+        };
+        let feet = CloudMetadata {
+            units: Some(LengthUnit::Foot),
+            ..metres.clone()
+        };
+        let union = self::union(
+            &[xyz.clone(), with_metadata(intensity.clone(), &metres)],
+            MetadataPolicy::Strict,
+        )
+        .unwrap();
+        assert_eq!(CloudMetadata::from_schema(&union), metres);
+
+        let mixed = [
+            with_metadata(xyz.clone(), &metres),
+            with_metadata(intensity, &feet),
+        ];
+        assert!(self::union(&mixed, MetadataPolicy::Strict).is_err());
+        let union = self::union(&mixed, MetadataPolicy::Lenient).unwrap();
+        assert_eq!(CloudMetadata::from_schema(&union), metres);
+
+        // same name, other type
+        let f32 = Point::<f32, 3>::schema();
+        assert!(self::union(&[xyz, f32], MetadataPolicy::Lenient).is_err());
+    }
+
+    #[test]
+    fn coord_spec() {
+        let xyzi = Point::<f64, 4>::schema();
+        assert_eq!(CoordSpec::from_schema(&xyzi), CoordSpec::default());
+
+        // bathymetry with the depth as third coordinate, importance kept last
+        let mut fields = xyzi.fields().to_vec();
+        fields.push(Arc::new(Field::new("depth", DataType::Float32, false)));
+        let schema = Arc::new(Schema::new(fields));
+        let spec = CoordSpec::new(&["x", "y", "depth"]);
+        let applied = spec.apply(&schema).unwrap();
+        assert_eq!(CoordSpec::from_schema(&applied), spec);
+        assert_eq!(dimensions(&applied), [0, 1, 4, 3]);
+        assert_eq!(importance(&applied), Some(3));
+        assert!(spec.check::<Point<f64, 3>>().is_ok());
+        assert!(spec.check::<Point<f64, 4>>().is_err());
+
+        assert!(CoordSpec::new(&["x"]).apply(&schema).is_err());
+        assert!(CoordSpec::new(&["x", "x"]).apply(&schema).is_err());
+        assert!(CoordSpec::new(&["x", "y", "height"])
+            .apply(&schema)
+            .is_err());
+    }
+}
@@ -1,6 +1,7 @@
 use std::{
     fs::File,
-    ops::Deref,
+    hash::{Hash, Hasher},
+    ops::RangeInclusive,
     path::{Path, PathBuf},
     sync::{Arc, RwLock},
 };
@@ -8,10 +9,12 @@ use std::{
 use ahash::RandomState;
 use arrow::{
     array::{
-        as_primitive_array, ArrayBuilder, ArrayRef, Float32Builder, Float64Builder, Int32Builder,
-        Int64Builder,
+        as_primitive_array, ArrayBuilder, ArrayData, ArrayRef, BooleanArray, Float32Builder,
+        Float64Builder, Int32Builder, Int64Builder,
     },
+    compute::filter_record_batch,
     datatypes::{DataType, Float32Type, Float64Type, Int32Type, Int64Type, SchemaRef},
+    error::ArrowError,
     ipc::{
         reader::FileReader,
         writer::{FileWriter, IpcWriteOptions},
@@ -19,31 +22,69 @@ use arrow::{
     },
     record_batch::{RecordBatch, RecordBatchReader},
 };
-use dashmap::DashMap;
+use indexmap::IndexMap;
 use moka::{notification::RemovalCause, sync::Cache};
-use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
-use rstar::{primitives::GeomWithData, Envelope, RStarInsertionStrategy, RTree, RTreeParams};
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use rstar::{primitives::GeomWithData, RStarInsertionStrategy, RTree, RTreeParams};
+use twox_hash::XxHash64;
 use uuid::Uuid;
 
 use crate::{
-    compute::{aabb, filter_by_aabb},
-    schema::{dimensions, validate},
-    Framework, Point, PointCloudError, PointCloudTrait, PointTrait, AABB,
+    compute::{
+        aabb, corners, drop_nonfinite, filter_by_aabb, filter_by_distance, filter_by_polygon,
+        finite_mask, validate_distance,
+    },
+    encoding::{decode_batch, encode_segment, ColumnEncodings},
+    polygon,
+    schema::{dimensions, validate, CoordSpec},
+    stats::Summary,
+    temporal::TimeRanges,
+    trace::span,
+    Framework, Point, PointCloudError, PointCloudTrait, PointTrait, ProgressSink, AABB,
 };
 
 /// Point cloud data store
+///
+/// Batches are grouped by key. Keys are visited in insertion order, so that
+/// repeated iteration, and iteration of stores filled in the same order, yield
+/// the batches in the same order.
 #[derive(Clone)]
 pub struct PointCloudStore {
     pub dir: PathBuf,
-    store: Arc<DashMap<String, PathBuf, RandomState>>,
+    store: Arc<RwLock<IndexMap<String, PathBuf>>>,
     cache: Cache<String, Arc<RwLock<Vec<RecordBatch>>>, RandomState>,
+    source: Option<Arc<dyn SegmentSource>>,
+    /// Whether spill files are compressed
+    compress: bool,
+    /// Encodings of the columns in spill files
+    encodings: Arc<RwLock<ColumnEncodings>>,
+    /// Bounds and statistics maintained on push, see [stats](crate::stats)
+    pub(crate) summary: Arc<RwLock<Summary>>,
+    /// Time ranges of the batches, see [temporal](crate::temporal)
+    pub(crate) times: Arc<RwLock<TimeRanges>>,
+}
+
+/// Origin of spill files that are missing in the store directory, such as an
+/// object store the directory caches
+pub trait SegmentSource: Send + Sync {
+    /// Write the spill file of `key` to `path`
+    fn fetch(&self, key: &str, path: &Path) -> Result<(), PointCloudError>;
 }
 
-impl Deref for PointCloudStore {
-    type Target = DashMap<String, PathBuf, RandomState>;
+/// Key of a store entry and the path its batches are spilled to
+#[derive(Debug, Clone, PartialEq)]
+pub struct StoreEntry {
+    key: String,
+    path: PathBuf,
+}
+
+impl StoreEntry {
+    pub fn key(&self) -> &str {
+        &self.key
+    }
 
-    fn deref(&self) -> &Self::Target {
-        &self.store
+    pub fn value(&self) -> &PathBuf {
+        &self.path
     }
 }
 
@@ -66,6 +107,8 @@ impl PointCloudStore {
         }
 
         let eviction_dir = dir.clone();
+        let encodings: Arc<RwLock<ColumnEncodings>> = Default::default();
+        let eviction_encodings = encodings.clone();
 
         let cache = Cache::builder()
             .max_capacity(capacity)
@@ -89,6 +132,8 @@ impl PointCloudStore {
                 let path = format!("{}/{k}.arrow", eviction_dir.to_string_lossy());
                 let file = File::create(path).expect("failed to create file");
 
+                let batches = encode_segment(&batches, &eviction_encodings.read().unwrap())
+                    .expect("failed to encode segment");
                 let schema = batches[0].schema();
 
                 let options = if compress {
@@ -112,43 +157,202 @@ impl PointCloudStore {
 
         Ok(Self {
             dir,
-            store: Arc::new(DashMap::default()),
+            store: Default::default(),
             cache,
+            source: None,
+            compress,
+            encodings,
+            summary: Default::default(),
+            times: Default::default(),
         })
     }
 
+    /// Encode the columns of spill files by `encodings`, see
+    /// [encoding](crate::encoding)
+    pub fn with_encodings(self, encodings: ColumnEncodings) -> Self {
+        *self.encodings.write().unwrap() = encodings;
+        self
+    }
+
+    /// Fetch missing spill files from `source`
+    pub fn with_source(mut self, source: Arc<dyn SegmentSource>) -> Self {
+        self.source = Some(source);
+        self
+    }
+
+    /// Store without entries spilling to the same directory, with the same
+    /// encodings and segment source, e.g. for a version of the points that
+    /// keeps some of the spill files of this one
+    pub fn empty_like(&self) -> Result<Self, PointCloudError> {
+        let capacity = self.cache.policy().max_capacity().unwrap_or(u64::MAX);
+        let mut store = Self::try_new(capacity, &self.dir, self.compress)?
+            .with_encodings(self.encodings.read().unwrap().clone());
+        store.source = self.source.clone();
+        Ok(store)
+    }
+
+    /// Whether the batches of `key` are held in memory, possibly newer than
+    /// its spill file
+    fn is_cached(&self, key: &str) -> bool {
+        self.cache.contains_key(key)
+    }
+
+    /// Add the entry `key` with `batches` in memory, without reading a spill
+    /// file of the key
+    fn insert_batches(&self, key: &str, batches: Vec<RecordBatch>) {
+        let mut summary = self.summary.write().unwrap();
+        self.store
+            .write()
+            .unwrap()
+            .entry(key.to_owned())
+            .or_insert_with(|| self.dir.join(format!("{key}.arrow")));
+
+        let mut times = self.times.write().unwrap();
+        for (i, batch) in batches.iter().enumerate() {
+            summary.push(batch);
+            times.push(key, batch, i == 0);
+        }
+        self.cache
+            .insert(key.to_owned(), Arc::new(RwLock::new(batches)));
+    }
+
+    /// Add the entry `key` with its batches in the spill file, which is
+    /// fetched from the source on first access if missing
+    pub fn insert_spilled(&self, key: &str) -> PathBuf {
+        let mut summary = self.summary.write().unwrap();
+        let mut store = self.store.write().unwrap();
+        if !store.contains_key(key) {
+            summary.clear();
+        }
+        store
+            .entry(key.to_owned())
+            .or_insert_with(|| self.dir.join(format!("{key}.arrow")))
+            .to_owned()
+    }
+
+    /// Read the spill file of `key`, fetching it from the source if missing
+    fn read_spilled(&self, key: &str, path: &Path) -> Vec<RecordBatch> {
+        let open = || File::open(path);
+        let file = match (open(), &self.source) {
+            (Ok(file), _) => file,
+            // missing or removed from the cache since
+            (Err(e), Some(source)) if e.kind() == std::io::ErrorKind::NotFound => {
+                source
+                    .fetch(key, path)
+                    .unwrap_or_else(|e| panic!("failed to fetch segment `{key}`: {e}"));
+                open().unwrap()
+            }
+            (Err(e), _) => panic!("failed to open segment `{key}`: {e}"),
+        };
+        let reader = FileReader::try_new(file, None).unwrap();
+        reader
+            .map(|batch| decode_batch(batch.unwrap()).unwrap())
+            .collect()
+    }
+
+    /// Number of keys
+    pub fn len(&self) -> usize {
+        self.store.read().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.store.read().unwrap().is_empty()
+    }
+
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.store.read().unwrap().contains_key(key)
+    }
+
+    /// Spill path of `key`
+    pub fn get(&self, key: &str) -> Option<PathBuf> {
+        self.store.read().unwrap().get(key).cloned()
+    }
+
+    /// Entries in insertion order
+    ///
+    /// The entries are a snapshot, keys pushed during iteration are not visited.
+    pub fn iter(&self) -> std::vec::IntoIter<StoreEntry> {
+        self.entries().into_iter()
+    }
+
+    /// Parallel iterator over the entries, see [PointCloudStore::iter]
+    pub fn par_iter(&self) -> rayon::vec::IntoIter<StoreEntry> {
+        self.entries().into_par_iter()
+    }
+
+    fn entries(&self) -> Vec<StoreEntry> {
+        self.store
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(key, path)| StoreEntry {
+                key: key.to_owned(),
+                path: path.to_owned(),
+            })
+            .collect()
+    }
+
+    /// Remove `key` with its batches and spill file, the order of the
+    /// remaining keys is preserved. Fails if the spill file can not be
+    /// removed, the key is removed nonetheless.
+    pub fn remove(&self, key: &str) -> Result<Option<PathBuf>, PointCloudError> {
+        let mut summary = self.summary.write().unwrap();
+        let Some(path) = self.store.write().unwrap().shift_remove(key) else {
+            return Ok(None);
+        };
+        summary.clear();
+        self.times.write().unwrap().remove(key);
+
+        // clear before invalidating, so that the batches are not spilled
+        if let Some(batches) = self.cache.get(key) {
+            batches.write().unwrap().clear();
+        }
+        self.cache.invalidate(key);
+
+        match std::fs::remove_file(&path) {
+            Ok(_) => Ok(Some(path)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Some(path)),
+            Err(e) => Err(e.into()),
+        }
+    }
+
     pub fn batches(&self, key: &str) -> Vec<RecordBatch> {
         if let Some(v) = self.cache.get(key) {
             v.read().unwrap().to_owned()
         } else {
-            self.store
-                .get(key)
-                .map(|path| {
-                    let file = File::open(path.as_path()).unwrap();
-                    let reader = FileReader::try_new(file, None).unwrap();
-                    // XXX: cache?
-                    reader.map(Result::unwrap).collect()
-                })
+            // XXX: cache?
+            self.get(key)
+                .map(|path| self.read_spilled(key, &path))
                 .unwrap()
         }
     }
 
+    /// Append `batch` to the entry `id`, updating the summary by the batch
+    /// instead of rescanning the store
     pub fn push(&self, id: String, batch: RecordBatch) {
+        // held until the batch is inserted, so that scans of the store see
+        // the batch only if they see the update
+        let mut summary = self.summary.write().unwrap();
+        summary.push(&batch);
+
         // create store entry if missing
-        self.store
-            .entry(id.clone())
-            .or_insert_with(|| self.dir.clone().join(format!("{id}.arrow")));
+        let (path, existed) = {
+            let mut store = self.store.write().unwrap();
+            let existed = store.contains_key(&id);
+            let path = store
+                .entry(id.clone())
+                .or_insert_with(|| self.dir.clone().join(format!("{id}.arrow")))
+                .to_owned();
+            (path, existed)
+        };
+        self.times.write().unwrap().push(&id, &batch, !existed);
         // insert batch
         self.cache
             .entry(id.clone())
             .or_insert_with(|| {
-                let path = self.store.get(&id).unwrap();
-                if path.exists() {
+                if path.exists() || (existed && self.source.is_some()) {
                     eprintln!("CACHE MISS!!!");
-                    let file = File::open(path.as_path()).unwrap();
-                    let reader = FileReader::try_new(file, None).unwrap();
-                    let batches = reader.map(Result::unwrap).collect();
-                    Arc::new(RwLock::new(batches))
+                    Arc::new(RwLock::new(self.read_spilled(&id, &path)))
                 } else {
                     Arc::new(RwLock::new(Vec::new()))
                 }
@@ -181,6 +385,16 @@ pub type PointIndex = RTree<GeomWithData<Point<f64, 4>, u64>, PointCloudParams>;
 pub type BatchIndex = RTree<GeomWithData<AABB<Point<f64, 4>>, String>, PointCloudParams>;
 pub type MultiLevelIndex = RTree<GeomWithData<AABB<Point<f64, 4>>, (String, PointIndex)>>;
 
+/// Points kept by [ArrowPointCloud::retain]
+pub struct Retained {
+    pub pc: ArrowPointCloud,
+    /// Number of points not kept
+    pub dropped: usize,
+    /// Keys of the rewritten entries and of the entries replacing them,
+    /// `None` where no points were kept
+    pub rewritten: Vec<(String, Option<String>)>,
+}
+
 /// Point cloud
 pub struct ArrowPointCloud {
     pub schema: SchemaRef,
@@ -199,6 +413,15 @@ impl ArrowPointCloud {
         ArrowPointCloud::try_new_with(schema, store)
     }
 
+    /// Point cloud of `schema` with the coordinate columns of `coords`, see
+    /// [CoordSpec::apply]
+    pub fn try_new_with_coords(
+        schema: SchemaRef,
+        coords: &CoordSpec,
+    ) -> Result<Self, PointCloudError> {
+        ArrowPointCloud::try_new(coords.apply(&schema)?)
+    }
+
     pub fn try_new_with(
         schema: SchemaRef,
         store: PointCloudStore,
@@ -213,7 +436,24 @@ impl ArrowPointCloud {
         })
     }
 
+    /// Names of the coordinate columns
+    pub fn coords(&self) -> CoordSpec {
+        CoordSpec::from_schema(&self.schema)
+    }
+
+    /// Coordinates of the points, unlike [PointCloudTrait::points] rejecting
+    /// points of another number of dimensions, see [CoordSpec::check]
+    pub fn coordinates<'a, P>(&'a self) -> Result<Box<dyn Iterator<Item = P> + 'a>, PointCloudError>
+    where
+        P: PointTrait + 'a,
+        <P as rstar::Point>::Scalar: num_traits::NumCast,
+    {
+        self.coords().check::<P>()?;
+        Ok(self.points())
+    }
+
     pub fn append(&mut self, batch: RecordBatch) -> Result<(), PointCloudError> {
+        let span = span!(TRACE, "append", rows = batch.num_rows(), cells);
         let batch = batch.with_schema(self.schema())?;
 
         let aabb: AABB<Point<f64, 4>> = aabb(&batch);
@@ -221,6 +461,7 @@ impl ArrowPointCloud {
         if self.framework.delta.is_some() {
             // create missing cells
             let cells = self.framework.create_cells(&aabb);
+            span.record("cells", cells.len());
 
             for cell in cells {
                 // get points for cell
@@ -239,10 +480,294 @@ impl ArrowPointCloud {
         Ok(())
     }
 
+    /// Select points whose XY lies inside the polygon `ring` and whose z is
+    /// within `z_range`, if given.
+    ///
+    /// Batches are prefiltered by their bounds before the point-in-polygon test.
+    pub fn points_in_polygon(
+        &self,
+        ring: &[[f64; 2]],
+        z_range: Option<RangeInclusive<f64>>,
+    ) -> Result<ArrowPointCloud, PointCloudError> {
+        let ring = polygon::validate(ring)?;
+        let (lower, upper) = polygon::bounds(&ring);
+
+        let mut pc = ArrowPointCloud::try_new(self.schema())?;
+
+        for e in self.store.iter() {
+            for batch in self.store.batches(e.key()) {
+                let bounds: AABB<Point<f64, 3>> = aabb(&batch);
+                let (l, u) = (bounds.lower(), bounds.upper());
+                if l.x() > upper[0] || u.x() < lower[0] || l.y() > upper[1] || u.y() < lower[1] {
+                    continue;
+                }
+                if let Some(z) = &z_range {
+                    if l.z() > *z.end() || u.z() < *z.start() {
+                        continue;
+                    }
+                }
+
+                let batch = filter_by_polygon(&batch, &ring, z_range.as_ref())?;
+                if batch.num_rows() > 0 {
+                    pc.append(batch)?;
+                }
+            }
+        }
+
+        Ok(pc)
+    }
+
+    /// Select points within `radius` of `center` in the dimensions of `P`, so
+    /// a two-dimensional center ignores z.
+    ///
+    /// Batches are prefiltered by their bounds before the distance test, both
+    /// compare squared distances.
+    pub fn points_within_radius<P>(
+        &self,
+        center: P,
+        radius: f64,
+    ) -> Result<ArrowPointCloud, PointCloudError>
+    where
+        P: PointTrait<Scalar = f64>,
+    {
+        let center = center.coords();
+        validate_distance(center, radius)?;
+
+        let mut pc = ArrowPointCloud::try_new(self.schema())?;
+
+        for e in self.store.iter() {
+            for batch in self.store.batches(e.key()) {
+                // distance of the center to the bounds of the batch
+                let bounds: AABB<Point<f64, 3>> = aabb(&batch);
+                let (l, u) = (bounds.lower(), bounds.upper());
+                let distance2: f64 = center
+                    .iter()
+                    .enumerate()
+                    .map(|(d, c)| (l.coords()[d] - c).max(c - u.coords()[d]).max(0.).powi(2))
+                    .sum();
+                if distance2 > radius * radius {
+                    continue;
+                }
+
+                let batch = filter_by_distance(&batch, center, radius)?;
+                if batch.num_rows() > 0 {
+                    pc.append(batch)?;
+                }
+            }
+        }
+
+        Ok(pc)
+    }
+
+    /// Keep the points selected by `mask`, given in iteration order of the points.
+    ///
+    /// Null mask entries are treated as `false`.
+    pub fn filter_mask(&self, mask: &BooleanArray) -> Result<ArrowPointCloud, PointCloudError> {
+        let num_points = self.num_points();
+        if mask.len() != num_points {
+            return Err(PointCloudError::InvalidArgument(format!(
+                "mask length {} does not match number of points {num_points}",
+                mask.len()
+            )));
+        }
+
+        let mut pc = ArrowPointCloud::try_new(self.schema())?;
+
+        let mut offset = 0;
+        for e in self.store.iter() {
+            for batch in self.store.batches(e.key()) {
+                let filter = mask.slice(offset, batch.num_rows());
+                offset += batch.num_rows();
+
+                let batch = filter_record_batch(&batch, &filter)?;
+                if batch.num_rows() > 0 {
+                    pc.append(batch)?;
+                }
+            }
+        }
+
+        Ok(pc)
+    }
+
+    /// Keep the points for which `predicate` holds, e.g. all but those of a
+    /// deletion by [Expr::exclude](crate::query::Expr::exclude). Null mask
+    /// entries are treated as `false`.
+    ///
+    /// The points are kept in a new store in the directory of this one, which
+    /// is left unchanged for its readers. Only entries with dropped points are
+    /// rewritten, under new keys. The others keep their key and batches,
+    /// spilled ones their spill file, which is read to evaluate the predicate
+    /// but neither kept in memory nor written again.
+    pub fn retain<F>(
+        &self,
+        predicate: F,
+        progress: &dyn ProgressSink,
+    ) -> Result<Retained, PointCloudError>
+    where
+        F: Fn(&RecordBatch) -> Result<BooleanArray, ArrowError>,
+    {
+        let store = self.store.empty_like()?;
+        let mut retained = Retained {
+            pc: ArrowPointCloud::try_new_with(self.schema(), store)?,
+            dropped: 0,
+            rewritten: Vec::new(),
+        };
+
+        let entries = self.store.len();
+        for (i, e) in self.store.iter().enumerate() {
+            progress.report(i, entries);
+            if progress.is_cancelled() {
+                return Err(PointCloudError::Cancelled);
+            }
+
+            let batches = self.store.batches(e.key());
+            let masks = batches
+                .iter()
+                .map(&predicate)
+                .collect::<Result<Vec<_>, _>>()?;
+            let kept: usize = masks.iter().map(|mask| mask.true_count()).sum();
+            let rows: usize = batches.iter().map(|batch| batch.num_rows()).sum();
+
+            if kept == rows {
+                if self.store.is_cached(e.key()) {
+                    retained.pc.store.insert_batches(e.key(), batches);
+                } else {
+                    retained.pc.store.insert_spilled(e.key());
+                }
+                continue;
+            }
+
+            retained.dropped += rows - kept;
+            let mut remaining = Vec::new();
+            for (batch, mask) in batches.iter().zip(&masks) {
+                let batch = filter_record_batch(batch, mask)?;
+                if batch.num_rows() > 0 {
+                    remaining.push(batch);
+                }
+            }
+            let key = (!remaining.is_empty()).then(|| Uuid::new_v4().to_string());
+            if let Some(key) = &key {
+                retained.pc.store.insert_batches(key, remaining);
+            }
+            retained.rewritten.push((e.key().to_owned(), key));
+        }
+        progress.report(entries, entries);
+
+        Ok(retained)
+    }
+
+    /// Whether the coordinates of the points are finite, in iteration order of
+    /// the points, see [crate::compute::finite_mask]
+    pub fn validity(&self) -> Result<BooleanArray, PointCloudError> {
+        let mut validity = Vec::new();
+        for e in self.store.iter() {
+            for batch in self.store.batches(e.key()) {
+                validity.extend(finite_mask(&batch)?.values().iter());
+            }
+        }
+        Ok(BooleanArray::from(validity))
+    }
+
+    /// Keep the points with finite coordinates, e.g. to drop NaN returns of
+    /// sensors before indexing or rendering
+    pub fn drop_nonfinite(&self) -> Result<ArrowPointCloud, PointCloudError> {
+        let mut pc = ArrowPointCloud::try_new(self.schema())?;
+
+        for e in self.store.iter() {
+            for batch in self.store.batches(e.key()) {
+                let batch = drop_nonfinite(&batch)?;
+                if batch.num_rows() > 0 {
+                    pc.append(batch)?;
+                }
+            }
+        }
+
+        Ok(pc)
+    }
+
+    /// Zero-copy view of `len` points starting at `offset`, in iteration order
+    /// of the points.
+    ///
+    /// Batches overlapping the range are sliced, so the view shares the buffers
+    /// of this point cloud. The range is clamped to the available points.
+    pub fn slice(&self, offset: usize, len: usize) -> ArrowPointCloud {
+        // the schema has been validated on construction
+        let mut pc = ArrowPointCloud::try_new(self.schema()).unwrap();
+        let end = offset.saturating_add(len);
+
+        let mut start = 0;
+        for e in self.store.iter() {
+            for batch in self.store.batches(e.key()) {
+                let (lower, upper) = (start, start + batch.num_rows());
+                start = upper;
+
+                if upper <= offset || lower >= end {
+                    continue;
+                }
+                let from = offset.saturating_sub(lower);
+                let to = end.min(upper) - lower;
+                pc.append(batch.slice(from, to - from)).unwrap();
+            }
+            if start >= end {
+                break;
+            }
+        }
+
+        pc
+    }
+
+    /// Split into `n` zero-copy views of consecutive points whose sizes differ
+    /// by at most one point, see [`ArrowPointCloud::slice`].
+    pub fn split_into(&self, n: usize) -> Vec<ArrowPointCloud> {
+        let num_points = self.num_points();
+
+        (0..n)
+            .map(|i| {
+                let offset = i * num_points / n;
+                self.slice(offset, (i + 1) * num_points / n - offset)
+            })
+            .collect()
+    }
+
+    /// Hash of the point data, e.g. to detect changed collections.
+    ///
+    /// Hashes the schema and the buffers of the batches in iteration order with
+    /// xxHash. The hash is stable for the same data in the same layout, but the
+    /// same points split into different batches hash differently.
+    pub fn content_hash(&self) -> u64 {
+        fn hash_data(hasher: &mut XxHash64, data: &ArrayData) {
+            hasher.write_usize(data.len());
+            hasher.write_usize(data.offset());
+            if let Some(nulls) = data.nulls() {
+                hasher.write_usize(nulls.offset());
+                hasher.write(nulls.buffer().as_slice());
+            }
+            for buffer in data.buffers() {
+                hasher.write(buffer.as_slice());
+            }
+            for child in data.child_data() {
+                hash_data(hasher, child);
+            }
+        }
+
+        let mut hasher = XxHash64::with_seed(0);
+        self.schema.hash(&mut hasher);
+
+        for e in self.store.iter() {
+            for batch in self.store.batches(e.key()) {
+                for column in batch.columns() {
+                    hash_data(&mut hasher, &column.to_data());
+                }
+            }
+        }
+
+        hasher.finish()
+    }
+
     pub fn flush(&self) {
         self.store.cache.invalidate_all();
         self.store.cache.run_pending_tasks();
-        self.store.shrink_to_fit();
+        self.store.store.write().unwrap().shrink_to_fit();
     }
 }
 
@@ -252,16 +777,7 @@ impl PointCloudTrait for ArrowPointCloud {
     }
 
     fn num_points(&self) -> usize {
-        self.store
-            .par_iter()
-            .flat_map_iter(|e| {
-                self.store
-                    .batches(e.key())
-                    .iter()
-                    .map(|batch| batch.num_rows())
-                    .collect::<Vec<_>>()
-            })
-            .sum()
+        self.store.summary().0
     }
 
     fn points<'a, P>(&'a self) -> Box<dyn Iterator<Item = P> + 'a>
@@ -373,15 +889,31 @@ impl PointCloudTrait for ArrowPointCloud {
         P: PointTrait,
         <P as rstar::Point>::Scalar: num_traits::NumCast,
     {
-        self.store
-            .par_iter()
-            .map(|e| {
-                self.store
-                    .batches(e.key())
-                    .iter()
-                    .fold(AABB::new_empty(), |acc, batch| acc.merged(&aabb(batch)))
-            })
-            .reduce(AABB::new_empty, |a, b| a.merged(&b))
+        if P::DIMENSIONS > 4 {
+            return self
+                .store
+                .par_iter()
+                .map(|e| {
+                    self.store
+                        .batches(e.key())
+                        .iter()
+                        .fold(AABB::new_empty(), |acc, batch| acc.merged(&aabb(batch)))
+                })
+                .reduce(AABB::new_empty, |a, b| a.merged(&b));
+        }
+
+        let (_, bounds) = self.store.summary();
+        let (lower, upper) = (bounds.lower(), bounds.upper());
+        if lower.x() > upper.x() {
+            return AABB::new_empty();
+        }
+        let n = dimensions(&self.schema).len().min(P::DIMENSIONS);
+        let bounds: Vec<(f64, f64)> = lower.coords()[..n]
+            .iter()
+            .copied()
+            .zip(upper.coords()[..n].iter().copied())
+            .collect();
+        corners(&bounds)
     }
 
     fn from_iter<P>(iter: impl Iterator<Item = P>) -> Result<Self, PointCloudError>
@@ -454,10 +986,641 @@ where
 
         let mut pc = Self::try_new(schema).unwrap();
 
+        let span = span!(DEBUG, "decode", rows);
+        let mut rows = 0;
         for batch in value {
-            pc.append(batch.unwrap()).unwrap();
+            let batch = batch.unwrap();
+            rows += batch.num_rows();
+            pc.append(batch).unwrap();
         }
+        span.record("rows", rows);
 
         pc
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ColumnEncoding;
+
+    #[test]
+    fn points_in_polygon() {
+        let pc = ArrowPointCloud::from_iter((0..100).map(|i| {
+            Point::<f64, 3>::from_slice(&[(i % 10) as f64 + 0.5, (i / 10) as f64 + 0.5, i as f64])
+        }))
+        .unwrap();
+
+        // lower left half of the grid
+        let triangle = [[0., 0.], [10., 0.], [0., 10.]];
+
+        let selection = pc.points_in_polygon(&triangle, None).unwrap();
+        let mut points: Vec<_> = selection
+            .points::<Point<f64, 3>>()
+            .map(|p| (p.x(), p.y()))
+            .collect();
+        points.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+        let mut expected: Vec<_> = (0..10)
+            .flat_map(|x| (0..10).map(move |y| (x as f64 + 0.5, y as f64 + 0.5)))
+            .filter(|(x, y)| x + y < 10.)
+            .collect();
+        expected.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+        assert_eq!(points, expected);
+
+        // restricted to the first rows
+        let selection = pc.points_in_polygon(&triangle, Some(0. ..=19.)).unwrap();
+        assert_eq!(selection.num_points(), 9 + 8);
+
+        // degenerate
+        assert!(pc.points_in_polygon(&[[0., 0.], [1., 1.]], None).is_err());
+    }
+
+    #[test]
+    fn depth_coordinates() {
+        use std::io::Cursor;
+
+        use arrow::{
+            array::{Float64Array, UInt16Array},
+            datatypes::{Field, Schema},
+        };
+
+        use crate::{query::Query, ChecksumWriter};
+
+        // bathymetry without PCE metadata, the depth is the third coordinate
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("x", DataType::Float64, false),
+            Field::new("y", DataType::Float64, false),
+            Field::new("intensity", DataType::UInt16, false),
+            Field::new("depth", DataType::Float64, false),
+        ]));
+        let spec = CoordSpec::new(&["x", "y", "depth"]);
+        let mut pc = ArrowPointCloud::try_new_with_coords(schema.clone(), &spec).unwrap();
+        let column = |f: fn(f64) -> f64| {
+            Arc::new(Float64Array::from_iter_values((0..10).map(|i| f(i as f64)))) as ArrayRef
+        };
+        let batch = RecordBatch::try_new(
+            schema,
+            vec![
+                column(|i| i),
+                column(|i| 2. * i),
+                Arc::new(UInt16Array::from(vec![100; 10])),
+                column(|i| -i - 0.5),
+            ],
+        )
+        .unwrap();
+        pc.append(batch).unwrap();
+
+        assert_eq!(pc.coords(), spec);
+        assert!(pc.coordinates::<Point<f64, 4>>().is_err());
+        let points: Vec<[f64; 3]> = pc
+            .coordinates::<Point<f64, 3>>()
+            .unwrap()
+            .map(|p| [p.x(), p.y(), p.z()])
+            .collect();
+        assert_eq!(points[3], [3., 6., -3.5]);
+
+        let bounds: AABB<Point<f64, 3>> = pc.aabb();
+        assert_eq!(bounds.lower().z(), -9.5);
+        assert_eq!(bounds.upper().z(), -0.5);
+
+        let query = Query::new().bounds(AABB::from_corners(
+            Point::<f64, 3>::from_slice(&[0., 0., -5.]),
+            Point::from_slice(&[10., 20., 0.]),
+        ));
+        assert_eq!(pc.execute(&query).unwrap().num_points(), 5);
+
+        // the spec survives the IPC round trip
+        let mut writer = ChecksumWriter::try_new(Vec::new(), &pc.schema()).unwrap();
+        for e in pc.store.iter() {
+            for batch in pc.store.batches(e.key()) {
+                writer.write(&batch).unwrap();
+            }
+        }
+        writer.finish().unwrap();
+        let decoded = ArrowPointCloud::try_from_reader(Cursor::new(writer.into_inner())).unwrap();
+        assert_eq!(decoded.coords(), spec);
+        let decoded: Vec<[f64; 3]> = decoded
+            .coordinates::<Point<f64, 3>>()
+            .unwrap()
+            .map(|p| [p.x(), p.y(), p.z()])
+            .collect();
+        assert_eq!(decoded, points);
+    }
+
+    #[test]
+    fn points_within_radius() {
+        // a pole at (50, 50) and points along x on either side of 10 m, one
+        // batch per side
+        let mut pc = ArrowPointCloud::try_new(Point::<f64, 3>::schema()).unwrap();
+        for xs in [[40.001, 39.999, 30.], [60., 60.001, 45.]] {
+            let batch = ArrowPointCloud::from_iter(
+                xs.iter()
+                    .map(|x| Point::<f64, 3>::from_slice(&[*x, 50., 0.])),
+            )
+            .unwrap();
+            for e in batch.store.iter() {
+                for batch in batch.store.batches(e.key()) {
+                    pc.append(batch).unwrap();
+                }
+            }
+        }
+        let xs = |selection: ArrowPointCloud| {
+            let mut xs: Vec<f64> = selection.points::<Point<f64, 3>>().map(|p| p.x()).collect();
+            xs.sort_by(f64::total_cmp);
+            xs
+        };
+
+        let pole = Point::<f64, 3>::from_slice(&[50., 50., 0.]);
+        let selection = pc.points_within_radius(pole, 10.).unwrap();
+        assert_eq!(xs(selection), [40.001, 45., 60.]);
+
+        // the height counts unless in 2D
+        let above = Point::<f64, 3>::from_slice(&[50., 50., 8.]);
+        let selection = pc.points_within_radius(above, 10.).unwrap();
+        assert_eq!(xs(selection), [45.]);
+        let above = Point::<f64, 2>::from_slice(&[50., 50.]);
+        let selection = pc.points_within_radius(above, 10.).unwrap();
+        assert_eq!(xs(selection), [40.001, 45., 60.]);
+
+        // the same through a query, with the batch bounds as prefilter
+        let query = crate::Query::new().near(pole, 10.);
+        assert_eq!(xs(pc.execute(&query).unwrap()), [40.001, 45., 60.]);
+
+        assert!(pc.points_within_radius(pole, 0.).unwrap().num_points() == 0);
+        assert!(pc.points_within_radius(pole, -1.).is_err());
+        assert!(pc.points_within_radius(pole, f64::NAN).is_err());
+    }
+
+    #[test]
+    fn filter_mask() {
+        // several batches
+        let mut pc = ArrowPointCloud::try_new(Point::<f64, 3>::schema()).unwrap();
+        for i in 0..4 {
+            let batch = ArrowPointCloud::from_iter(
+                (0..10).map(|j| Point::<f64, 3>::from_slice(&[i as f64, j as f64, 0.])),
+            )
+            .unwrap();
+            for e in batch.store.iter() {
+                for batch in batch.store.batches(e.key()) {
+                    pc.append(batch).unwrap();
+                }
+            }
+        }
+
+        // every third point in iteration order
+        let points: Vec<_> = pc.points::<Point<f64, 3>>().collect();
+        let mask = BooleanArray::from_iter((0..points.len()).map(|i| Some(i % 3 == 0)));
+
+        let selection = pc.filter_mask(&mask).unwrap();
+        assert_eq!(selection.num_points(), 14);
+
+        let mut expected: Vec<_> = points.iter().step_by(3).map(|p| (p.x(), p.y())).collect();
+        let mut selected: Vec<_> = selection
+            .points::<Point<f64, 3>>()
+            .map(|p| (p.x(), p.y()))
+            .collect();
+        expected.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        selected.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert_eq!(selected, expected);
+
+        // length mismatch
+        assert!(pc.filter_mask(&BooleanArray::from(vec![true])).is_err());
+    }
+
+    #[test]
+    fn nonfinite() {
+        use crate::color::{colors, ColorMap, Normalization};
+
+        // non-finite coordinates at the boundaries of the batches
+        let mut pc = ArrowPointCloud::try_new(Point::<f64, 3>::schema()).unwrap();
+        for i in 0..3 {
+            let points = ArrowPointCloud::from_iter((0..10).map(|j| {
+                let z = match j {
+                    0 => f64::NAN,
+                    9 if i == 1 => f64::INFINITY,
+                    _ => j as f64,
+                };
+                Point::<f64, 3>::from_slice(&[i as f64, j as f64, z])
+            }))
+            .unwrap();
+            for e in points.store.iter() {
+                for batch in points.store.batches(e.key()) {
+                    pc.append(batch).unwrap();
+                }
+            }
+        }
+
+        let bounds: AABB<Point<f64, 3>> = pc.aabb();
+        assert_eq!(bounds.lower().coords(), &[0., 1., 1.]);
+        assert_eq!(bounds.upper().coords(), &[2., 9., 9.]);
+
+        let validity = pc.validity().unwrap();
+        assert_eq!(validity.len(), 30);
+        assert_eq!(validity.true_count(), 26);
+
+        let finite = pc.drop_nonfinite().unwrap();
+        assert_eq!(finite.num_points(), 26);
+        assert_eq!(finite.aabb::<Point<f64, 3>>(), bounds);
+        assert!(finite.validity().unwrap().true_count() == 26);
+
+        // statistics and colors ignore the non-finite heights
+        let stats = pc.column_stats("z").unwrap();
+        assert_eq!((stats.min, stats.max), (1., 9.));
+        assert!(stats.mean.is_finite());
+        let map = ColorMap::for_attribute("z", colorgrad::turbo(), Normalization::default());
+        let (colors, bounds) = colors(&pc, "z", &map).unwrap();
+        assert_eq!(colors.iter().flatten().count(), 26);
+        assert!(bounds.is_some_and(|(l, u)| l.is_finite() && u.is_finite()));
+    }
+
+    #[test]
+    fn slice() {
+        // several batches of different size
+        let mut pc = ArrowPointCloud::try_new(Point::<f64, 3>::schema()).unwrap();
+        for i in 0..5 {
+            let batch = ArrowPointCloud::from_iter(
+                (0..(i + 1) * 7).map(|j| Point::<f64, 3>::from_slice(&[i as f64, j as f64, 0.])),
+            )
+            .unwrap();
+            for e in batch.store.iter() {
+                for batch in batch.store.batches(e.key()) {
+                    pc.append(batch).unwrap();
+                }
+            }
+        }
+        let points: Vec<_> = pc.points::<Point<f64, 3>>().collect();
+        assert_eq!(points.len(), 105);
+
+        for (offset, len) in [
+            (0, 105),
+            (3, 2),
+            (5, 40),
+            (7, 14),
+            (100, 10),
+            (105, 1),
+            (200, 3),
+        ] {
+            let slice = pc.slice(offset, len);
+            let expected: Vec<_> = points.iter().skip(offset).take(len).cloned().collect();
+
+            assert_eq!(slice.num_points(), expected.len());
+            assert_eq!(
+                slice.points::<Point<f64, 3>>().collect::<Vec<_>>(),
+                expected
+            );
+
+            let aabb: AABB<Point<f64, 3>> = slice.aabb();
+            if let (Some(first), Some(last)) = (expected.first(), expected.last()) {
+                assert_eq!(aabb.lower().x(), first.x());
+                assert_eq!(aabb.upper().x(), last.x());
+            }
+        }
+        assert_eq!(pc.slice(10, 0).num_points(), 0);
+        assert_eq!(pc.slice(usize::MAX, usize::MAX).num_points(), 0);
+
+        // buffers are shared
+        let batch = |pc: &ArrowPointCloud| {
+            pc.store.batches(pc.store.iter().next().unwrap().key())[0].clone()
+        };
+        let x = |batch: &RecordBatch| {
+            as_primitive_array::<Float64Type>(batch.column(0))
+                .values()
+                .as_ptr()
+        };
+        assert_eq!(x(&batch(&pc.slice(3, 2))), x(&batch(&pc)).wrapping_add(3));
+    }
+
+    #[test]
+    fn split_into() {
+        let pc = ArrowPointCloud::from_iter(
+            (0..10).map(|i| Point::<f64, 3>::from_slice(&[i as f64, 0., 0.])),
+        )
+        .unwrap();
+
+        let parts = pc.split_into(3);
+        let sizes: Vec<usize> = parts.iter().map(|part| part.num_points()).collect();
+        assert_eq!(sizes, [3, 3, 4]);
+
+        let joined: Vec<_> = parts
+            .iter()
+            .flat_map(|part| part.points::<Point<f64, 3>>().collect::<Vec<_>>())
+            .collect();
+        assert_eq!(joined, pc.points::<Point<f64, 3>>().collect::<Vec<_>>());
+
+        // more parts than points
+        let sizes: Vec<usize> = pc
+            .split_into(12)
+            .iter()
+            .map(|part| part.num_points())
+            .collect();
+        assert_eq!(sizes.len(), 12);
+        assert_eq!(sizes.iter().sum::<usize>(), 10);
+        assert!(sizes.iter().all(|size| *size <= 1));
+        assert!(pc.split_into(0).is_empty());
+    }
+
+    #[test]
+    fn content_hash() {
+        let points = |n: usize| {
+            ArrowPointCloud::from_iter(
+                (0..n).map(|i| Point::<f64, 3>::from_slice(&[i as f64, 0., 0.])),
+            )
+            .unwrap()
+        };
+
+        let mut pc = points(10);
+        let hash = pc.content_hash();
+        assert_eq!(points(10).content_hash(), hash);
+        assert_ne!(points(9).content_hash(), hash);
+        assert_ne!(pc.slice(1, 9).content_hash(), hash);
+
+        let batch = pc.store.batches(pc.store.iter().next().unwrap().key())[0].clone();
+        pc.append(batch.slice(0, 1)).unwrap();
+        assert_ne!(pc.content_hash(), hash);
+    }
+
+    #[test]
+    fn store_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = PointCloudStore::try_new(u64::MAX, dir.path(), false).unwrap();
+        let pc =
+            ArrowPointCloud::from_iter([Point::<f64, 3>::from_slice(&[0., 0., 0.])].into_iter())
+                .unwrap();
+        let batch = pc.store.batches(pc.store.iter().next().unwrap().key())[0].clone();
+
+        // keys are visited in insertion order, not hash order
+        let keys: Vec<String> = (0..100).rev().map(|i| format!("{i:03}")).collect();
+        for key in &keys {
+            store.push(key.to_owned(), batch.clone());
+        }
+        let visited: Vec<String> = store.iter().map(|e| e.key().to_owned()).collect();
+        assert_eq!(visited, keys);
+        let visited: Vec<String> = store.par_iter().map(|e| e.key().to_owned()).collect();
+        assert_eq!(visited, keys);
+
+        // pushing to an existing key keeps its position
+        store.push("050".to_owned(), batch.clone());
+        assert_eq!(store.len(), 100);
+        assert_eq!(store.batches("050").len(), 2);
+
+        // removal preserves the order of the remaining keys
+        assert!(store.contains_key("050"));
+        assert_eq!(
+            store.remove("050").unwrap(),
+            Some(dir.path().join("050.arrow"))
+        );
+        assert!(!store.contains_key("050"));
+        assert!(store.remove("050").unwrap().is_none());
+        assert_eq!(store.batches("051").len(), 1);
+        assert!(!dir.path().join("050.arrow").exists());
+
+        let expected: Vec<&String> = keys.iter().filter(|key| *key != "050").collect();
+        let visited: Vec<String> = store.iter().map(|e| e.key().to_owned()).collect();
+        assert_eq!(visited.iter().collect::<Vec<_>>(), expected);
+    }
+
+    #[test]
+    fn segment_source() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        /// Copies spill files from another directory
+        struct Copy(PathBuf, AtomicUsize);
+        impl SegmentSource for Copy {
+            fn fetch(&self, key: &str, path: &Path) -> Result<(), PointCloudError> {
+                self.1.fetch_add(1, Ordering::Relaxed);
+                std::fs::copy(self.0.join(format!("{key}.arrow")), path)
+                    .map_err(|e| PointCloudError::CacheError(e.to_string()))?;
+                Ok(())
+            }
+        }
+
+        let remote = tempfile::tempdir().unwrap();
+        let pc = ArrowPointCloud::try_new_with(
+            Point::<f64, 3>::schema(),
+            PointCloudStore::try_new(u64::MAX, remote.path(), false).unwrap(),
+        )
+        .unwrap();
+        let points = ArrowPointCloud::from_iter(
+            (0..10).map(|i| Point::<f64, 3>::from_slice(&[i as f64, 0., 0.])),
+        )
+        .unwrap();
+        let batch = points
+            .store
+            .batches(points.store.iter().next().unwrap().key())[0]
+            .clone();
+        pc.store.push("a".to_owned(), batch.clone());
+        pc.store.push("b".to_owned(), batch.slice(0, 4));
+        pc.flush();
+
+        // registered without reading, fetched on first access
+        let local = tempfile::tempdir().unwrap();
+        let source = Arc::new(Copy(remote.path().to_owned(), AtomicUsize::new(0)));
+        let store = PointCloudStore::try_new(u64::MAX, local.path(), false)
+            .unwrap()
+            .with_source(source.clone());
+        for key in ["a", "b"] {
+            store.insert_spilled(key);
+        }
+        assert_eq!(source.1.load(Ordering::Relaxed), 0);
+        assert_eq!(store.batches("b")[0].num_rows(), 4);
+        assert_eq!(store.batches("b")[0].num_rows(), 4);
+        assert_eq!(source.1.load(Ordering::Relaxed), 1);
+
+        // fetched again once removed from the cache directory
+        std::fs::remove_file(local.path().join("b.arrow")).unwrap();
+        assert_eq!(store.batches("b")[0].num_rows(), 4);
+        assert_eq!(source.1.load(Ordering::Relaxed), 2);
+
+        // new keys are not fetched, existing ones before appending
+        store.push("c".to_owned(), batch.clone());
+        assert_eq!(source.1.load(Ordering::Relaxed), 2);
+        store.push("a".to_owned(), batch.slice(0, 1));
+        assert_eq!(source.1.load(Ordering::Relaxed), 3);
+        assert_eq!(store.batches("a").len(), 2);
+    }
+
+    #[test]
+    fn retain() {
+        use crate::query::{CmpOp, Expr};
+
+        let dir = tempfile::tempdir().unwrap();
+        let store = PointCloudStore::try_new(u64::MAX, dir.path(), false).unwrap();
+        let pc = ArrowPointCloud::try_new_with(Point::<f64, 3>::schema(), store).unwrap();
+        // two batches of ten points per entry, at y = the number of the entry
+        for (y, key) in ["a", "b", "c", "d"].into_iter().enumerate() {
+            let points = ArrowPointCloud::from_iter(
+                (0..20).map(|x| Point::<f64, 3>::from_slice(&[x as f64, y as f64, 0.])),
+            )
+            .unwrap();
+            let e = points.store.iter().next().unwrap();
+            let batch = points.store.batches(e.key())[0].clone();
+            pc.store.push(key.to_owned(), batch.slice(0, 10));
+            pc.store.push(key.to_owned(), batch.slice(10, 10));
+            if key == "c" {
+                pc.flush();
+            }
+        }
+        let spilled = |key: &str| std::fs::read(dir.path().join(format!("{key}.arrow"))).ok();
+        let before = spilled("a").unwrap();
+
+        // all of entry b and the first five points of entry c
+        let deleted = Expr::cmp("y", CmpOp::Eq, 1.)
+            .or(Expr::cmp("y", CmpOp::Eq, 2.).and(Expr::cmp("x", CmpOp::Lt, 5.)));
+        let retained = pc.retain(|batch| deleted.exclude(batch), &()).unwrap();
+        assert_eq!(retained.dropped, 25);
+        assert_eq!(retained.pc.num_points(), 55);
+        assert_eq!(pc.num_points(), 80);
+
+        let keys: Vec<String> = retained
+            .pc
+            .store
+            .iter()
+            .map(|e| e.key().to_owned())
+            .collect();
+        assert_eq!(retained.rewritten.len(), 2);
+        assert_eq!(retained.rewritten[0], ("b".to_owned(), None));
+        let (key, replacement) = &retained.rewritten[1];
+        assert_eq!(key, "c");
+        assert_eq!(keys, ["a", replacement.as_deref().unwrap(), "d"]);
+
+        // the untouched spill file is neither read nor written again
+        assert!(!retained.pc.store.is_cached("a"));
+        retained.pc.flush();
+        assert_eq!(spilled("a").unwrap(), before);
+        assert_eq!(retained.pc.store.batches("d").len(), 2);
+        assert!(spilled(replacement.as_deref().unwrap()).is_some());
+        assert!(spilled("c").is_some());
+    }
+
+    #[test]
+    fn spilled_encodings() {
+        use arrow::{
+            array::{Float64Array, UInt8Array},
+            datatypes::Field,
+            ipc::reader::FileReader,
+        };
+
+        // classes in long runs, as of spatially sorted points
+        let schema = Point::<f64, 3>::schema();
+        let mut fields: Vec<Field> = schema.fields().iter().map(|f| f.as_ref().clone()).collect();
+        fields.push(Field::new("classification", DataType::UInt8, false));
+        let schema = Arc::new(arrow::datatypes::Schema::new(fields));
+        let coordinate = || Arc::new(Float64Array::from_iter_values((0..10_000).map(f64::from)));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                coordinate(),
+                coordinate(),
+                coordinate(),
+                Arc::new(UInt8Array::from_iter_values(
+                    (0..10_000).map(|i| [2, 6][i / 2_500 % 2]),
+                )),
+            ],
+        )
+        .unwrap();
+
+        let spill = |encodings: ColumnEncodings| {
+            let dir = tempfile::tempdir().unwrap();
+            let store = PointCloudStore::try_new(u64::MAX, dir.path(), false)
+                .unwrap()
+                .with_encodings(encodings);
+            let pc = ArrowPointCloud::try_new_with(schema.clone(), store).unwrap();
+            pc.store.push("a".to_owned(), batch.clone());
+            pc.flush();
+
+            // decoded when read
+            assert_eq!(pc.store.batches("a"), std::slice::from_ref(&batch));
+            let path = dir.path().join("a.arrow");
+            let file = FileReader::try_new(File::open(&path).unwrap(), None).unwrap();
+            let data_type = file.schema().field(3).data_type().clone();
+            (data_type, std::fs::metadata(&path).unwrap().len())
+        };
+
+        let (encoded, size) = spill(ColumnEncodings::default());
+        assert!(matches!(encoded, DataType::RunEndEncoded(..)), "{encoded}");
+        let plain = [("classification".to_string(), ColumnEncoding::Plain)];
+        let (data_type, plain_size) = spill(plain.into_iter().collect());
+        assert_eq!(data_type, DataType::UInt8);
+        assert!(size + 9_000 < plain_size, "{size} {plain_size}");
+        let dictionary = [("classification".to_string(), ColumnEncoding::Dictionary)];
+        let (data_type, _) = spill(dictionary.into_iter().collect());
+        assert!(matches!(data_type, DataType::Dictionary(..)), "{data_type}");
+    }
+
+    #[test]
+    fn deterministic_load() {
+        use arrow::{
+            array::Float64Array,
+            datatypes::{Field, Schema},
+            ipc::{reader::StreamReader, writer::StreamWriter},
+        };
+
+        // multi-batch stream with an intensity attribute
+        let mut fields: Vec<Field> = Point::<f64, 3>::schema()
+            .fields()
+            .iter()
+            .map(|field| field.as_ref().clone())
+            .collect();
+        fields.push(Field::new("intensity", DataType::Float64, false));
+        let schema = Arc::new(Schema::new(fields));
+
+        let mut writer = StreamWriter::try_new(Vec::new(), &schema).unwrap();
+        for i in 0..32 {
+            let column = |f: &dyn Fn(usize) -> f64| {
+                Arc::new(Float64Array::from_iter_values((0..50).map(f))) as ArrayRef
+            };
+            let batch = RecordBatch::try_new(
+                schema.clone(),
+                vec![
+                    column(&|j| (i * 50 + j) as f64),
+                    column(&|j| j as f64),
+                    column(&|_| i as f64),
+                    column(&|j| ((i * 7919 + j * 104729) % 1000) as f64),
+                ],
+            )
+            .unwrap();
+            writer.write(&batch).unwrap();
+        }
+        let stream = writer.into_inner().unwrap();
+
+        let load = || -> ArrowPointCloud {
+            StreamReader::try_new(std::io::Cursor::new(stream.clone()), None)
+                .unwrap()
+                .into()
+        };
+        let (a, b) = (load(), load());
+
+        // identical point sequences, in stream order
+        let points = |pc: &ArrowPointCloud| -> Vec<[f64; 3]> {
+            pc.points::<Point<f64, 3>>()
+                .map(|p| [p.x(), p.y(), p.z()])
+                .collect()
+        };
+        assert_eq!(points(&a), points(&b));
+        assert!(points(&a).windows(2).all(|w| w[0][0] < w[1][0]));
+
+        // identical color assignment
+        let normalization = crate::color::Normalization::default();
+        let (colors_a, _) = crate::color::normalized(&a, "intensity", &normalization).unwrap();
+        let (colors_b, _) = crate::color::normalized(&b, "intensity", &normalization).unwrap();
+        assert_eq!(colors_a, colors_b);
+
+        // colors line up with the positions
+        let intensities: Vec<f64> = a
+            .store
+            .iter()
+            .flat_map(|e| a.store.batches(e.key()))
+            .flat_map(|batch| {
+                as_primitive_array::<Float64Type>(batch.column_by_name("intensity").unwrap())
+                    .values()
+                    .to_vec()
+            })
+            .collect();
+        for (p, intensity) in points(&a).iter().zip(intensities) {
+            let (i, j) = (p[2] as usize, p[1] as usize);
+            assert_eq!(intensity, ((i * 7919 + j * 104729) % 1000) as f64);
+        }
+    }
+}
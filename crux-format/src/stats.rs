@@ -0,0 +1,769 @@
+//! Summary statistics of numeric columns.
+//!
+//! Statistics are cached in the store together with the number of points and
+//! their bounds, see [Summary]. Batches pushed to the store update the count,
+//! minimum, maximum, mean and variance of the cached columns in place, so
+//! appends do not rescan the cloud. Quantiles can not be updated like that,
+//! they are kept from the last full scan and the statistics are marked
+//! [approximate](ColumnStats::approximate) until
+//! [refreshed](ArrowPointCloud::refresh_stats). The distinct values of
+//! integer columns are counted on request and cached the same way, see
+//! [ArrowPointCloud::distinct_counts].
+
+use std::collections::{BTreeMap, HashMap};
+
+use arrow::{
+    array::{Array, AsArray},
+    datatypes::{DataType, Float64Type, Int64Type},
+    record_batch::RecordBatch,
+};
+use rayon::iter::ParallelIterator;
+
+use crate::{
+    compute::aabb, encoding::cast, soa::PointCloudStore, ArrowPointCloud, Point, PointCloudError,
+    AABB,
+};
+
+/// Number of quantile knots kept for percentile lookups (0.1% resolution)
+const QUANTILES: usize = 1000;
+
+/// Summary statistics of a numeric column
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColumnStats {
+    pub count: usize,
+    pub null_count: usize,
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+    /// Population variance
+    pub variance: f64,
+    /// Whether the percentiles predate points appended since, the other
+    /// statistics are exact
+    pub approximate: bool,
+    quantiles: Vec<f64>,
+}
+
+impl ColumnStats {
+    /// Statistics of the valid, finite values
+    pub fn from_values(mut values: Vec<f64>, null_count: usize) -> Self {
+        values.retain(|v| v.is_finite());
+
+        let count = values.len();
+        let mean = values.iter().sum::<f64>() / count as f64;
+        let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / count as f64;
+
+        let quantiles = if values.is_empty() {
+            Vec::new()
+        } else {
+            let rank = |i: usize| i as f64 / QUANTILES as f64 * (count - 1) as f64;
+            let mut ranks: Vec<usize> = (0..=QUANTILES)
+                .flat_map(|i| [rank(i).floor() as usize, rank(i).ceil() as usize])
+                .collect();
+            ranks.sort_unstable();
+            ranks.dedup();
+            select(&mut values, 0, &ranks);
+
+            (0..=QUANTILES)
+                .map(|i| {
+                    let rank = rank(i);
+                    let (l, u) = (rank.floor() as usize, rank.ceil() as usize);
+                    values[l] + (values[u] - values[l]) * (rank - l as f64)
+                })
+                .collect()
+        };
+
+        Self {
+            count,
+            null_count,
+            min: quantiles.first().copied().unwrap_or(f64::NAN),
+            max: quantiles.last().copied().unwrap_or(f64::NAN),
+            mean,
+            variance,
+            approximate: false,
+            quantiles,
+        }
+    }
+
+    /// Value at percentile `p` in [0, 100], NaN for empty columns
+    pub fn percentile(&self, p: f64) -> f64 {
+        if self.quantiles.is_empty() {
+            return f64::NAN;
+        }
+
+        let rank = p.clamp(0., 100.) / 100. * QUANTILES as f64;
+        let (l, u) = (rank.floor() as usize, rank.ceil() as usize);
+        self.quantiles[l] + (self.quantiles[u] - self.quantiles[l]) * (rank - l as f64)
+    }
+
+    /// Number of values in `bins` bins of equal width from the minimum to the
+    /// maximum, interpolated between the percentiles
+    pub fn histogram(&self, bins: usize) -> Vec<usize> {
+        let mut histogram = vec![0; bins];
+        if bins == 0 || self.quantiles.is_empty() {
+            return histogram;
+        }
+
+        // fraction of the values below `value`
+        let fraction = |value: f64| {
+            let knots = &self.quantiles;
+            let above = knots.partition_point(|knot| *knot <= value);
+            if above == 0 {
+                return 0.;
+            } else if above == knots.len() {
+                return 1.;
+            }
+            let (l, u) = (knots[above - 1], knots[above]);
+            ((above - 1) as f64 + (value - l) / (u - l)) / QUANTILES as f64
+        };
+
+        let width = (self.max - self.min) / bins as f64;
+        let mut below = 0;
+        for (i, bin) in histogram.iter_mut().enumerate() {
+            let upper = match i + 1 == bins {
+                true => self.count,
+                false => (fraction(self.min + width * (i + 1) as f64) * self.count as f64).round()
+                    as usize,
+            };
+            *bin = upper.saturating_sub(below);
+            below = below.max(upper);
+        }
+        histogram
+    }
+
+    /// Statistics of `running` with the percentiles of `self`, approximate if
+    /// values were added since
+    fn updated(&self, running: &RunningStats) -> Self {
+        let mut stats = Self {
+            count: running.count,
+            null_count: running.null_count,
+            min: running.min,
+            max: running.max,
+            mean: running.mean,
+            variance: running.variance(),
+            approximate: self.approximate,
+            quantiles: self.quantiles.clone(),
+        };
+        if running.count != self.count || running.null_count != self.null_count {
+            stats.approximate = true;
+        }
+        if running.count == 0 {
+            (stats.min, stats.max, stats.mean) = (f64::NAN, f64::NAN, f64::NAN);
+        }
+        stats
+    }
+}
+
+/// Count, extrema, mean and variance of the valid, finite values of a column,
+/// updated value by value with Welford's algorithm
+#[derive(Debug, Clone, PartialEq)]
+pub struct RunningStats {
+    pub count: usize,
+    pub null_count: usize,
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+    /// Sum of the squared differences from the mean
+    m2: f64,
+}
+
+impl Default for RunningStats {
+    fn default() -> Self {
+        Self {
+            count: 0,
+            null_count: 0,
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+            mean: 0.,
+            m2: 0.,
+        }
+    }
+}
+
+impl RunningStats {
+    /// Statistics of `column` cast to `f64`
+    pub fn from_array(column: &dyn Array) -> Result<Self, PointCloudError> {
+        let column = cast(column, &DataType::Float64)?;
+        let mut stats = Self {
+            null_count: column.null_count(),
+            ..Default::default()
+        };
+        for v in column.as_primitive::<Float64Type>().iter().flatten() {
+            stats.push(v);
+        }
+        Ok(stats)
+    }
+
+    /// Add a value, non-finite values are skipped
+    pub fn push(&mut self, value: f64) {
+        if !value.is_finite() {
+            return;
+        }
+        self.count += 1;
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        let delta = value - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (value - self.mean);
+    }
+
+    /// Combine with the statistics of other values
+    pub fn merge(&mut self, other: &Self) {
+        self.null_count += other.null_count;
+        if other.count == 0 {
+            return;
+        }
+        let count = self.count + other.count;
+        let delta = other.mean - self.mean;
+        self.mean += delta * other.count as f64 / count as f64;
+        self.m2 += other.m2 + delta * delta * (self.count * other.count) as f64 / count as f64;
+        self.count = count;
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+    }
+
+    /// Population variance, NaN without values
+    pub fn variance(&self) -> f64 {
+        self.m2 / self.count as f64
+    }
+}
+
+/// Distinct values of an integer column with their number of points, see
+/// [ArrowPointCloud::distinct_counts]
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DistinctCounts {
+    pub values: BTreeMap<i64, usize>,
+    pub null_count: usize,
+}
+
+impl DistinctCounts {
+    /// Counts of the values of `column` cast to `i64`
+    pub fn from_array(column: &dyn Array) -> Result<Self, PointCloudError> {
+        let column = cast(column, &DataType::Int64)?;
+        let mut counts = Self {
+            null_count: column.null_count(),
+            ..Default::default()
+        };
+        for v in column.as_primitive::<Int64Type>().iter().flatten() {
+            *counts.values.entry(v).or_default() += 1;
+        }
+        Ok(counts)
+    }
+
+    /// Combine with the counts of other values
+    pub fn merge(&mut self, other: &Self) {
+        self.null_count += other.null_count;
+        for (value, count) in &other.values {
+            *self.values.entry(*value).or_default() += count;
+        }
+    }
+}
+
+/// Number of points, bounds and column statistics cached by a
+/// [PointCloudStore], see [the module](self)
+#[derive(Debug)]
+pub(crate) struct Summary {
+    /// Number of rows and bounds of the first four dimensions, `None` until
+    /// scanned
+    bounds: Option<(usize, AABB<Point<f64, 4>>)>,
+    /// Statistics of the columns requested since the last scan
+    columns: HashMap<String, (RunningStats, ColumnStats)>,
+    /// Distinct values of the columns requested since the last scan with the
+    /// largest number requested, `None` if there are more
+    distinct: HashMap<String, (usize, Option<DistinctCounts>)>,
+    /// Incremented on every change of the store, scans are only cached if
+    /// the store did not change meanwhile
+    generation: u64,
+}
+
+impl Default for Summary {
+    /// Summary of an empty store
+    fn default() -> Self {
+        Self {
+            bounds: Some((0, AABB::new_empty())),
+            columns: HashMap::new(),
+            distinct: HashMap::new(),
+            generation: 0,
+        }
+    }
+}
+
+impl Summary {
+    /// Update by a batch pushed to the store
+    pub(crate) fn push(&mut self, batch: &RecordBatch) {
+        self.generation += 1;
+        if let Some((rows, bounds)) = &mut self.bounds {
+            *rows += batch.num_rows();
+            *bounds = bounds.merged(&aabb(batch));
+        }
+        self.columns.retain(|name, (running, _)| {
+            match batch
+                .column_by_name(name)
+                .map(|column| RunningStats::from_array(column))
+            {
+                Some(Ok(stats)) => {
+                    running.merge(&stats);
+                    true
+                }
+                // rescanned on request
+                _ => false,
+            }
+        });
+        self.distinct.retain(|name, (max_distinct, counts)| {
+            let Some(distinct) = counts else {
+                // appends do not take values away
+                return true;
+            };
+            match batch
+                .column_by_name(name)
+                .map(|column| DistinctCounts::from_array(column))
+            {
+                Some(Ok(batch)) => {
+                    distinct.merge(&batch);
+                    if distinct.values.len() > *max_distinct {
+                        *counts = None;
+                    }
+                    true
+                }
+                _ => false,
+            }
+        });
+    }
+
+    /// Forget everything, e.g. after entries were removed
+    pub(crate) fn clear(&mut self) {
+        self.generation += 1;
+        self.bounds = None;
+        self.columns.clear();
+        self.distinct.clear();
+    }
+}
+
+/// Move the values of the ascending `ranks` to their sorted positions in
+/// O(n log k) instead of sorting all n values, `values` start at rank `offset`
+fn select(values: &mut [f64], offset: usize, ranks: &[usize]) {
+    if ranks.is_empty() {
+        return;
+    }
+
+    let mid = ranks.len() / 2;
+    let (lower, _, upper) = values.select_nth_unstable_by(ranks[mid] - offset, f64::total_cmp);
+    select(lower, offset, &ranks[..mid]);
+    select(upper, ranks[mid] + 1, &ranks[mid + 1..]);
+}
+
+impl PointCloudStore {
+    /// Number of rows and bounds of the first four dimensions of the batches
+    pub fn summary(&self) -> (usize, AABB<Point<f64, 4>>) {
+        let generation = {
+            let summary = self.summary.read().unwrap();
+            if let Some(bounds) = summary.bounds {
+                return bounds;
+            }
+            summary.generation
+        };
+
+        // scanned without holding the lock, which pushes of other tasks on
+        // the same threads would wait for
+        let bounds = self
+            .par_iter()
+            .map(|e| {
+                self.batches(e.key())
+                    .iter()
+                    .fold((0, AABB::new_empty()), |(rows, bounds), batch| {
+                        (rows + batch.num_rows(), bounds.merged(&aabb(batch)))
+                    })
+            })
+            .reduce(
+                || (0, AABB::new_empty()),
+                |a, b| (a.0 + b.0, a.1.merged(&b.1)),
+            );
+
+        let mut summary = self.summary.write().unwrap();
+        if summary.generation == generation {
+            summary.bounds = Some(bounds);
+        }
+        bounds
+    }
+
+    /// Statistics of the column `name`, scanned on first request and after
+    /// [refresh](PointCloudStore::refresh_stats)
+    fn column_stats(&self, name: &str) -> Result<ColumnStats, PointCloudError> {
+        let generation = {
+            let summary = self.summary.read().unwrap();
+            if let Some((running, stats)) = summary.columns.get(name) {
+                return Ok(stats.updated(running));
+            }
+            summary.generation
+        };
+
+        let mut running = RunningStats::default();
+        let mut values = Vec::new();
+        for batch in self.iter().flat_map(|e| self.batches(e.key())) {
+            let Some(column) = batch.column_by_name(name) else {
+                continue;
+            };
+            let column = cast(column, &DataType::Float64)?;
+            let column = column.as_primitive::<Float64Type>();
+
+            running.null_count += column.null_count();
+            for v in column.iter().flatten() {
+                running.push(v);
+                values.push(v);
+            }
+        }
+        let stats = ColumnStats::from_values(values, running.null_count);
+
+        let mut summary = self.summary.write().unwrap();
+        if summary.generation == generation {
+            summary
+                .columns
+                .insert(name.to_owned(), (running, stats.clone()));
+        }
+        Ok(stats)
+    }
+
+    /// Distinct values of the column `name`, `None` if there are more than
+    /// `max_distinct`. Scanned on first request and when more values are
+    /// requested than before.
+    fn distinct_counts(
+        &self,
+        name: &str,
+        max_distinct: usize,
+    ) -> Result<Option<DistinctCounts>, PointCloudError> {
+        let generation = {
+            let summary = self.summary.read().unwrap();
+            match summary.distinct.get(name) {
+                Some((_, Some(counts))) => {
+                    return Ok((counts.values.len() <= max_distinct).then(|| counts.clone()));
+                }
+                Some((scanned, None)) if max_distinct <= *scanned => return Ok(None),
+                _ => (),
+            }
+            summary.generation
+        };
+
+        let mut counts = DistinctCounts::default();
+        for batch in self.iter().flat_map(|e| self.batches(e.key())) {
+            let Some(column) = batch.column_by_name(name) else {
+                continue;
+            };
+            counts.merge(&DistinctCounts::from_array(column)?);
+            if counts.values.len() > max_distinct {
+                break;
+            }
+        }
+        let counts = (counts.values.len() <= max_distinct).then_some(counts);
+
+        let mut summary = self.summary.write().unwrap();
+        if summary.generation == generation {
+            summary
+                .distinct
+                .insert(name.to_owned(), (max_distinct, counts.clone()));
+        }
+        Ok(counts)
+    }
+
+    /// Rescan the columns with approximate statistics
+    pub fn refresh_stats(&self) -> Result<(), PointCloudError> {
+        let approximate: Vec<String> = self
+            .summary
+            .read()
+            .unwrap()
+            .columns
+            .iter()
+            .filter(|(_, (running, stats))| stats.updated(running).approximate)
+            .map(|(name, _)| name.to_owned())
+            .collect();
+
+        for name in approximate {
+            self.summary.write().unwrap().columns.remove(&name);
+            self.column_stats(&name)?;
+        }
+        Ok(())
+    }
+}
+
+impl ArrowPointCloud {
+    /// Statistics of the column `name`, cast to `f64`
+    pub fn column_stats(&self, name: &str) -> Result<ColumnStats, PointCloudError> {
+        if self.schema.column_with_name(name).is_none() {
+            return Err(PointCloudError::InvalidArgument(format!(
+                "no column `{name}`"
+            )));
+        }
+
+        self.store.column_stats(name)
+    }
+
+    /// Distinct values of the integer column `name` with their number of
+    /// points, e.g. the classes of `classification`. `None` for other columns
+    /// and if there are more than `max_distinct` values.
+    pub fn distinct_counts(
+        &self,
+        name: &str,
+        max_distinct: usize,
+    ) -> Result<Option<DistinctCounts>, PointCloudError> {
+        let Some((_, field)) = self.schema.column_with_name(name) else {
+            return Err(PointCloudError::InvalidArgument(format!(
+                "no column `{name}`"
+            )));
+        };
+        let integer = match field.data_type() {
+            DataType::Dictionary(_, values) => values.is_integer(),
+            data_type => data_type.is_integer(),
+        };
+        if !integer {
+            return Ok(None);
+        }
+
+        self.store.distinct_counts(name, max_distinct)
+    }
+
+    /// Rescan the columns whose statistics became approximate by appends,
+    /// see [ColumnStats::approximate]
+    pub fn refresh_stats(&self) -> Result<(), PointCloudError> {
+        self.store.refresh_stats()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Grid with an intensity attribute that is null for every second point
+    fn half_null_intensity() -> ArrowPointCloud {
+        use std::sync::Arc;
+
+        use arrow::{
+            array::{ArrayRef, Float64Array, UInt16Array},
+            datatypes::{Field, Schema},
+            record_batch::RecordBatch,
+        };
+
+        use crate::{Point, PointTrait};
+
+        let mut fields: Vec<Field> = Point::<f64, 3>::schema()
+            .fields()
+            .iter()
+            .map(|field| field.as_ref().clone())
+            .collect();
+        fields.push(Field::new("intensity", DataType::UInt16, true));
+        let schema = Arc::new(Schema::new(fields));
+
+        let coordinate = |f: fn(u16) -> f64| {
+            Arc::new(Float64Array::from_iter_values((0..100).map(f))) as ArrayRef
+        };
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                coordinate(|i| (i % 10) as f64),
+                coordinate(|i| (i / 10) as f64),
+                coordinate(|_| 0.),
+                Arc::new(UInt16Array::from_iter(
+                    (0..100).map(|i| (i % 2 == 0).then_some(i)),
+                )),
+            ],
+        )
+        .unwrap();
+
+        let mut pc = ArrowPointCloud::try_new(schema).unwrap();
+        pc.append(batch).unwrap();
+        pc
+    }
+
+    #[test]
+    fn nulls() {
+        let pc = half_null_intensity();
+
+        let stats = pc.column_stats("intensity").unwrap();
+        assert_eq!((stats.count, stats.null_count), (50, 50));
+        assert_eq!((stats.min, stats.max, stats.mean), (0., 98., 49.));
+        assert_eq!(stats.percentile(50.), 49.);
+
+        // nulls are not normalized
+        let (values, _) = crate::color::normalized(&pc, "intensity", &Default::default()).unwrap();
+        assert_eq!(values.len(), 100);
+        assert!(values.iter().step_by(2).all(Option::is_some));
+        assert!(values.iter().skip(1).step_by(2).all(Option::is_none));
+    }
+
+    #[test]
+    fn percentiles() {
+        // a permutation of 0..=100
+        let values = (0..=100).map(|i| f64::from(i * 37 % 101)).collect();
+        let stats = ColumnStats::from_values(values, 3);
+
+        assert_eq!(stats.count, 101);
+        assert_eq!(stats.null_count, 3);
+        assert_eq!((stats.min, stats.max, stats.mean), (0., 100., 50.));
+        assert_eq!(stats.percentile(0.), 0.);
+        assert_eq!(stats.percentile(2.), 2.);
+        assert!((stats.percentile(98.5) - 98.5).abs() < 1e-9);
+        assert_eq!(stats.percentile(100.), 100.);
+
+        let empty = ColumnStats::from_values(vec![f64::NAN], 0);
+        assert_eq!(empty.count, 0);
+        assert!(empty.percentile(50.).is_nan());
+    }
+
+    #[test]
+    fn running() {
+        let values: Vec<f64> = (0..100).map(|i| (i * 37 % 101) as f64 / 3.).collect();
+        let stats = ColumnStats::from_values(values.clone(), 0);
+
+        let (mut a, mut b) = (RunningStats::default(), RunningStats::default());
+        values[..30].iter().for_each(|v| a.push(*v));
+        values[30..].iter().for_each(|v| b.push(*v));
+        b.push(f64::NAN);
+        a.merge(&b);
+        a.merge(&RunningStats::default());
+
+        assert_eq!((a.count, a.min, a.max), (100, stats.min, stats.max));
+        assert!((a.mean - stats.mean).abs() < 1e-9);
+        assert!((a.variance() - stats.variance).abs() < 1e-9);
+    }
+
+    #[test]
+    fn incremental() {
+        use crate::{Point, PointCloudTrait, Synthetic};
+
+        let source = Synthetic::new(1000)
+            .intensity(true)
+            .batch_size(100)
+            .terrain()
+            .unwrap();
+        let batches: Vec<RecordBatch> = source
+            .store
+            .iter()
+            .flat_map(|e| source.store.batches(e.key()))
+            .collect();
+
+        // statistics of the appended batches by a full scan
+        let expected = |batches: &[RecordBatch], name: &str| {
+            let mut values = Vec::new();
+            let mut null_count = 0;
+            for batch in batches {
+                let column = cast(batch.column_by_name(name).unwrap(), &DataType::Float64).unwrap();
+                null_count += column.null_count();
+                values.extend(column.as_primitive::<Float64Type>().iter().flatten());
+            }
+            ColumnStats::from_values(values, null_count)
+        };
+
+        let pc = ArrowPointCloud::try_new(source.schema()).unwrap();
+        for (i, batch) in batches.iter().enumerate() {
+            pc.store.push(i.to_string(), batch.clone());
+            let appended = &batches[..=i];
+
+            assert_eq!(
+                pc.num_points(),
+                appended.iter().map(RecordBatch::num_rows).sum::<usize>()
+            );
+            let bounds = appended
+                .iter()
+                .fold(AABB::new_empty(), |acc, batch| acc.merged(&aabb(batch)));
+            assert_eq!(pc.aabb::<Point<f64, 3>>(), bounds);
+
+            for name in ["z", "intensity"] {
+                let stats = pc.column_stats(name).unwrap();
+                let full = expected(appended, name);
+                assert_eq!(
+                    (stats.count, stats.null_count, stats.min, stats.max),
+                    (full.count, full.null_count, full.min, full.max)
+                );
+                assert!((stats.mean - full.mean).abs() < 1e-9 * full.mean.abs().max(1.));
+                assert!(
+                    (stats.variance - full.variance).abs() < 1e-9 * full.variance.max(1.),
+                    "{name}: {} != {}",
+                    stats.variance,
+                    full.variance
+                );
+                // the percentiles of the first scan until refreshed
+                assert_eq!(stats.approximate, i > 0);
+            }
+        }
+
+        pc.refresh_stats().unwrap();
+        let stats = pc.column_stats("z").unwrap();
+        assert!(!stats.approximate);
+        assert_eq!(
+            stats.percentile(50.),
+            expected(&batches, "z").percentile(50.)
+        );
+
+        // removed entries are rescanned
+        pc.store.remove("0").unwrap();
+        assert_eq!(pc.num_points(), 900);
+        assert_eq!(pc.column_stats("z").unwrap(), expected(&batches[1..], "z"));
+        assert_eq!(
+            pc.aabb::<Point<f64, 3>>(),
+            batches[1..]
+                .iter()
+                .fold(AABB::new_empty(), |acc, batch| acc.merged(&aabb(batch)))
+        );
+
+        // empty clouds
+        let empty = ArrowPointCloud::try_new(source.schema()).unwrap();
+        assert_eq!(empty.num_points(), 0);
+        assert_eq!(empty.aabb::<Point<f64, 3>>(), AABB::new_empty());
+    }
+
+    #[test]
+    fn histogram() {
+        let stats = ColumnStats::from_values((0..=100).map(f64::from).collect(), 0);
+        let histogram = stats.histogram(10);
+        assert_eq!(histogram.iter().sum::<usize>(), 101);
+        assert!(histogram.iter().all(|count| (9..=12).contains(count)));
+
+        // skewed towards the minimum
+        let values = (0..100).map(|i| if i < 90 { 0. } else { 10. }).collect();
+        let histogram = ColumnStats::from_values(values, 0).histogram(2);
+        assert_eq!(histogram, [90, 10]);
+
+        let empty = ColumnStats::from_values(Vec::new(), 0);
+        assert_eq!(empty.histogram(3), [0, 0, 0]);
+    }
+
+    #[test]
+    fn distinct() {
+        use crate::synthetic::{BUILDING, GROUND};
+
+        // 30% of the points on buildings
+        let pc = crate::Synthetic::new(1000)
+            .intensity(true)
+            .batch_size(100)
+            .buildings(5)
+            .unwrap();
+        let counts = pc.distinct_counts("classification", 100).unwrap().unwrap();
+        assert_eq!(
+            counts.values,
+            BTreeMap::from([(GROUND as i64, 700), (BUILDING as i64, 300)])
+        );
+        assert_eq!(counts.null_count, 0);
+        assert_eq!(pc.distinct_counts("classification", 1).unwrap(), None);
+
+        // neither floats nor high cardinality columns
+        assert_eq!(pc.distinct_counts("x", 100).unwrap(), None);
+        assert_eq!(pc.distinct_counts("intensity", 10).unwrap(), None);
+        assert!(pc.distinct_counts("missing", 100).is_err());
+
+        // appends update the cached counts
+        let first = pc.store.iter().next().unwrap();
+        let batch = pc.store.batches(first.key())[0].clone();
+        let mut expected = counts.clone();
+        expected.merge(
+            &DistinctCounts::from_array(batch.column_by_name("classification").unwrap()).unwrap(),
+        );
+        pc.store.push("again".to_owned(), batch);
+        assert_eq!(
+            pc.distinct_counts("classification", 100).unwrap(),
+            Some(expected)
+        );
+
+        let pc = half_null_intensity();
+        let counts = pc.distinct_counts("intensity", 50).unwrap().unwrap();
+        assert_eq!(counts.values.len(), 50);
+        assert!(counts.values.values().all(|count| *count == 1));
+        assert_eq!(counts.null_count, 50);
+    }
+}
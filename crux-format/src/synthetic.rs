@@ -0,0 +1,518 @@
+use std::sync::Arc;
+
+use arrow::{
+    array::{ArrayRef, Float64Array, UInt16Array, UInt8Array},
+    datatypes::{DataType, Field, Schema, SchemaRef},
+    record_batch::RecordBatch,
+};
+use rand::{rngs::SmallRng, Rng, SeedableRng};
+
+use crate::{
+    trajectory::TIME_COLUMN, ArrowPointCloud, Point, PointCloudError, PointCloudTrait, PointTrait,
+};
+
+/// ASPRS class of unclassified points
+pub const UNCLASSIFIED: u8 = 1;
+/// ASPRS class of ground points
+pub const GROUND: u8 = 2;
+/// ASPRS class of building points
+pub const BUILDING: u8 = 6;
+
+/// Octaves of the fractal heightfield
+const OCTAVES: u32 = 5;
+/// Lattice cells of the first octave across the extent
+const BASE_FREQUENCY: f64 = 4.;
+/// Points per second of [Synthetic::drive]
+const SCAN_RATE: f64 = 1000.;
+/// Standard deviation of the acquisition times of [Synthetic::drive] in
+/// seconds
+const TIME_JITTER: f64 = 0.01;
+
+/// Generator of reproducible synthetic point clouds.
+///
+/// Points have the `x`, `y` and `z` dimensions of [Point] and a LAS style
+/// `classification`, optionally `intensity` and `red`, `green` and `blue`
+/// colored by height. Drives also have the `gps_time` of the points.
+///
+/// ```
+/// use crux_format::{synthetic::Synthetic, PointCloudTrait};
+///
+/// let pc = Synthetic::new(1000)
+///     .extent([0., 0., 0.], [100., 100., 20.])
+///     .seed(42)
+///     .rgb(true)
+///     .buildings(5)
+///     .unwrap();
+/// assert_eq!(pc.num_points(), 1000);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Synthetic {
+    points: usize,
+    lower: [f64; 3],
+    upper: [f64; 3],
+    seed: u64,
+    intensity: bool,
+    rgb: bool,
+    batch_size: usize,
+    /// Add `gps_time`, set by [Synthetic::drive]
+    time: bool,
+}
+
+/// Generated points before they are converted to batches
+#[derive(Default)]
+struct Points {
+    coords: Vec<[f64; 3]>,
+    classes: Vec<u8>,
+    /// Acquisition times, empty unless timed
+    times: Vec<f64>,
+}
+
+impl Points {
+    fn push(&mut self, coords: [f64; 3], class: u8) {
+        self.coords.push(coords);
+        self.classes.push(class);
+    }
+}
+
+impl Synthetic {
+    /// `points` points within `[0, 100] x [0, 100] x [0, 20]`, seed 0
+    pub fn new(points: usize) -> Self {
+        Self {
+            points,
+            lower: [0.; 3],
+            upper: [100., 100., 20.],
+            seed: 0,
+            intensity: false,
+            rgb: false,
+            batch_size: 65536,
+            time: false,
+        }
+    }
+
+    /// Bounds of the generated points
+    pub fn extent(mut self, lower: [f64; 3], upper: [f64; 3]) -> Self {
+        self.lower = lower;
+        self.upper = upper;
+        self
+    }
+
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Add `intensity`, varying around a mean per class
+    pub fn intensity(mut self, intensity: bool) -> Self {
+        self.intensity = intensity;
+        self
+    }
+
+    /// Add `red`, `green` and `blue` of the height on the turbo gradient
+    pub fn rgb(mut self, rgb: bool) -> Self {
+        self.rgb = rgb;
+        self
+    }
+
+    /// Rows per batch
+    pub fn batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// Schema of the generated point clouds
+    pub fn schema(&self) -> SchemaRef {
+        let mut fields: Vec<Field> = Point::<f64, 3>::schema()
+            .fields()
+            .iter()
+            .map(|f| f.as_ref().clone())
+            .collect();
+        fields.push(Field::new("classification", DataType::UInt8, false));
+        if self.time {
+            fields.push(Field::new(TIME_COLUMN, DataType::Float64, false));
+        }
+        if self.intensity {
+            fields.push(Field::new("intensity", DataType::UInt16, false));
+        }
+        if self.rgb {
+            for color in ["red", "green", "blue"] {
+                fields.push(Field::new(color, DataType::UInt16, false));
+            }
+        }
+        Arc::new(Schema::new(fields))
+    }
+
+    fn size(&self) -> [f64; 3] {
+        [0, 1, 2].map(|d| self.upper[d] - self.lower[d])
+    }
+
+    /// Uniformly distributed point within the extent
+    fn uniform_point(&self, rng: &mut SmallRng) -> [f64; 3] {
+        [0, 1, 2].map(|d| self.lower[d] + rng.gen::<f64>() * (self.upper[d] - self.lower[d]))
+    }
+
+    /// Height of the fractal heightfield at `x`, `y`, in `[0, 1)` of `amplitude`
+    fn height(&self, x: f64, y: f64, amplitude: f64) -> f64 {
+        let size = self.size();
+        let u = (x - self.lower[0]) / size[0].max(f64::EPSILON);
+        let v = (y - self.lower[1]) / size[1].max(f64::EPSILON);
+        self.lower[2] + fractal(self.seed, u, v) * amplitude
+    }
+
+    /// Points uniformly distributed in the extent
+    pub fn uniform(&self) -> Result<ArrowPointCloud, PointCloudError> {
+        let mut rng = SmallRng::seed_from_u64(self.seed);
+        let mut points = Points::default();
+        for _ in 0..self.points {
+            points.push(self.uniform_point(&mut rng), UNCLASSIFIED);
+        }
+        self.build(points, &mut rng)
+    }
+
+    /// Points normally distributed around `clusters` centers, with a standard
+    /// deviation of 5% of the extent, clamped to the extent
+    pub fn clusters(&self, clusters: usize) -> Result<ArrowPointCloud, PointCloudError> {
+        let mut rng = SmallRng::seed_from_u64(self.seed);
+        let centers: Vec<[f64; 3]> = (0..clusters.max(1))
+            .map(|_| self.uniform_point(&mut rng))
+            .collect();
+        let sigma = self.size().map(|s| s * 0.05);
+
+        let mut points = Points::default();
+        for _ in 0..self.points {
+            let center = centers[rng.gen_range(0..centers.len())];
+            let coords = [0, 1, 2].map(|d| {
+                let value = center[d] + gaussian(&mut rng) * sigma[d];
+                value.clamp(self.lower[d], self.upper[d])
+            });
+            points.push(coords, UNCLASSIFIED);
+        }
+        self.build(points, &mut rng)
+    }
+
+    /// Ground points on a fractal heightfield spanning the height of the extent
+    pub fn terrain(&self) -> Result<ArrowPointCloud, PointCloudError> {
+        let mut rng = SmallRng::seed_from_u64(self.seed);
+        let amplitude = self.size()[2];
+
+        let mut points = Points::default();
+        for _ in 0..self.points {
+            let [x, y, _] = self.uniform_point(&mut rng);
+            points.push([x, y, self.height(x, y, amplitude)], GROUND);
+        }
+        self.build(points, &mut rng)
+    }
+
+    /// Box shaped buildings on terrain in the lower third of the extent.
+    ///
+    /// 30% of the points are on the roofs and walls of the buildings, the
+    /// others on the ground around them.
+    pub fn buildings(&self, buildings: usize) -> Result<ArrowPointCloud, PointCloudError> {
+        let mut rng = SmallRng::seed_from_u64(self.seed);
+        let size = self.size();
+        let amplitude = size[2] * 0.3;
+
+        // footprint (x, y, width, depth), base and roof height
+        let footprints: Vec<([f64; 4], f64, f64)> = (0..buildings)
+            .map(|_| {
+                let width = size[0] * rng.gen_range(0.05..0.15);
+                let depth = size[1] * rng.gen_range(0.05..0.15);
+                let x = self.lower[0] + rng.gen::<f64>() * (size[0] - width);
+                let y = self.lower[1] + rng.gen::<f64>() * (size[1] - depth);
+                let base = self.height(x + width / 2., y + depth / 2., amplitude);
+                let roof = base + size[2] * rng.gen_range(0.2..0.7);
+                ([x, y, width, depth], base, roof)
+            })
+            .collect();
+        let inside = |x: f64, y: f64| {
+            footprints.iter().any(|([fx, fy, w, d], _, _)| {
+                (*fx..fx + w).contains(&x) && (*fy..fy + d).contains(&y)
+            })
+        };
+
+        let on_buildings = if footprints.is_empty() {
+            0
+        } else {
+            self.points * 3 / 10
+        };
+
+        let mut points = Points::default();
+        for _ in 0..on_buildings {
+            let ([x, y, w, d], base, roof) = footprints[rng.gen_range(0..footprints.len())];
+            let perimeter = 2. * (w + d);
+            let walls = perimeter * (roof - base);
+
+            if rng.gen::<f64>() * (walls + w * d) < w * d {
+                let coords = [x + rng.gen::<f64>() * w, y + rng.gen::<f64>() * d, roof];
+                points.push(coords, BUILDING);
+            } else {
+                // position along the perimeter
+                let t = rng.gen::<f64>() * perimeter;
+                let (px, py) = if t < w {
+                    (x + t, y)
+                } else if t < w + d {
+                    (x + w, y + t - w)
+                } else if t < 2. * w + d {
+                    (x + 2. * w + d - t, y + d)
+                } else {
+                    (x, y + perimeter - t)
+                };
+                let z = base + rng.gen::<f64>() * (roof - base);
+                points.push([px, py, z], BUILDING);
+            }
+        }
+        while points.coords.len() < self.points {
+            let [x, y, _] = self.uniform_point(&mut rng);
+            if !inside(x, y) {
+                points.push([x, y, self.height(x, y, amplitude)], GROUND);
+            }
+        }
+        self.build(points, &mut rng)
+    }
+
+    /// Ground points scanned from a vehicle on a winding road through the
+    /// extent, with the `gps_time` of their acquisition at a constant rate.
+    /// The times are sorted up to the jitter of the scanner, so that
+    /// neighboring batches overlap slightly.
+    pub fn drive(&self) -> Result<ArrowPointCloud, PointCloudError> {
+        let generator = Self {
+            time: true,
+            ..self.clone()
+        };
+        let mut rng = SmallRng::seed_from_u64(self.seed);
+        let size = self.size();
+        let amplitude = size[2] * 0.3;
+
+        let mut points = Points::default();
+        for i in 0..self.points {
+            let u = i as f64 / self.points.max(1) as f64;
+            let road = [
+                self.lower[0] + u * size[0],
+                self.lower[1] + size[1] * (0.5 + 0.3 * (u * std::f64::consts::TAU).sin()),
+            ];
+            // within a tenth of the extent around the vehicle
+            let [x, y] = [0, 1].map(|d| {
+                (road[d] + (rng.gen::<f64>() - 0.5) * size[d] * 0.1)
+                    .clamp(self.lower[d], self.upper[d])
+            });
+            points.push([x, y, self.height(x, y, amplitude)], GROUND);
+            points
+                .times
+                .push(i as f64 / SCAN_RATE + gaussian(&mut rng) * TIME_JITTER);
+        }
+        generator.build(points, &mut rng)
+    }
+
+    /// Point cloud of the generated points with the optional attributes
+    fn build(
+        &self,
+        points: Points,
+        rng: &mut SmallRng,
+    ) -> Result<ArrowPointCloud, PointCloudError> {
+        let schema = self.schema();
+        let gradient = colorgrad::turbo();
+        let mut pc = ArrowPointCloud::try_new(schema.clone())?;
+
+        for (coords, classes) in points
+            .coords
+            .chunks(self.batch_size)
+            .zip(points.classes.chunks(self.batch_size))
+        {
+            let mut columns: Vec<ArrayRef> = (0..3)
+                .map(|d| {
+                    Arc::new(Float64Array::from_iter_values(coords.iter().map(|c| c[d])))
+                        as ArrayRef
+                })
+                .collect();
+            columns.push(Arc::new(UInt8Array::from(classes.to_vec())));
+
+            if self.time {
+                let offset = pc.num_points();
+                let times = &points.times[offset..offset + coords.len()];
+                columns.push(Arc::new(Float64Array::from(times.to_vec())));
+            }
+
+            if self.intensity {
+                let intensity = classes.iter().map(|class| {
+                    let mean = match *class {
+                        GROUND => 800.,
+                        BUILDING => 1500.,
+                        _ => 1000.,
+                    };
+                    (mean + gaussian(rng) * 150.).clamp(0., u16::MAX as f64) as u16
+                });
+                columns.push(Arc::new(UInt16Array::from_iter_values(intensity)));
+            }
+            if self.rgb {
+                let size = self.size()[2].max(f64::EPSILON);
+                let colors: Vec<[u16; 4]> = coords
+                    .iter()
+                    .map(|c| gradient.at((c[2] - self.lower[2]) / size).to_rgba16())
+                    .collect();
+                for channel in 0..3 {
+                    let values = colors.iter().map(|color| color[channel]);
+                    columns.push(Arc::new(UInt16Array::from_iter_values(values)));
+                }
+            }
+
+            pc.append(RecordBatch::try_new(schema.clone(), columns)?)?;
+        }
+
+        Ok(pc)
+    }
+}
+
+/// Standard normally distributed value (Box-Muller)
+fn gaussian(rng: &mut SmallRng) -> f64 {
+    let u: f64 = 1. - rng.gen::<f64>();
+    let v: f64 = rng.gen();
+    (-2. * u.ln()).sqrt() * (std::f64::consts::TAU * v).cos()
+}
+
+/// Value in `[0, 1)` of the lattice point `(i, j)` of an octave
+fn lattice(seed: u64, octave: u32, i: i64, j: i64) -> f64 {
+    // splitmix64 of the combined coordinates
+    let mut z = seed
+        ^ (octave as u64).wrapping_mul(0xd1b5_4a32_d192_ed03)
+        ^ (i as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15)
+        ^ (j as u64).wrapping_mul(0xc2b2_ae3d_27d4_eb4f);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^= z >> 31;
+    (z >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// Fractal value noise in `[0, 1)` at `u`, `v` in units of the extent
+fn fractal(seed: u64, u: f64, v: f64) -> f64 {
+    let (mut sum, mut total) = (0., 0.);
+    let mut amplitude = 1.;
+    for octave in 0..OCTAVES {
+        let frequency = BASE_FREQUENCY * 2f64.powi(octave as i32);
+        let (x, y) = (u * frequency, v * frequency);
+        let (i, j) = (x.floor(), y.floor());
+        // smoothstep between the corners
+        let (s, t) = (x - i, y - j);
+        let (s, t) = (s * s * (3. - 2. * s), t * t * (3. - 2. * t));
+        let (i, j) = (i as i64, j as i64);
+
+        let value = |di, dj| lattice(seed, octave, i + di, j + dj);
+        let bottom = value(0, 0) * (1. - s) + value(1, 0) * s;
+        let top = value(0, 1) * (1. - s) + value(1, 1) * s;
+        sum += (bottom * (1. - t) + top * t) * amplitude;
+        total += amplitude;
+        amplitude *= 0.5;
+    }
+    sum / total
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow::{
+        array::AsArray,
+        datatypes::{UInt16Type, UInt8Type},
+    };
+
+    use super::*;
+    use crate::{PointCloudTrait, AABB};
+
+    /// Classes of the points
+    fn classes(pc: &ArrowPointCloud) -> Vec<u8> {
+        pc.store
+            .iter()
+            .flat_map(|e| pc.store.batches(e.key()))
+            .flat_map(|batch| {
+                let column = batch.column_by_name("classification").unwrap();
+                column.as_primitive::<UInt8Type>().values().to_vec()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn deterministic() {
+        let synthetic = Synthetic::new(5000).seed(7).intensity(true).rgb(true);
+        for generate in [
+            Synthetic::uniform,
+            Synthetic::terrain,
+            |s: &Synthetic| s.clusters(3),
+            |s: &Synthetic| s.buildings(4),
+        ] {
+            let a = generate(&synthetic).unwrap();
+            let b = generate(&synthetic).unwrap();
+            assert_eq!(a.content_hash(), b.content_hash());
+
+            let c = generate(&synthetic.clone().seed(8)).unwrap();
+            assert_ne!(a.content_hash(), c.content_hash());
+        }
+    }
+
+    #[test]
+    fn schema() {
+        let pc = Synthetic::new(10).uniform().unwrap();
+        let schema = pc.schema();
+        assert_eq!(crate::schema::dimensions(&schema), [0, 1, 2]);
+        crate::schema::validate(&schema).unwrap();
+        let names: Vec<&str> = schema.fields().iter().map(|f| f.name().as_str()).collect();
+        assert_eq!(names, ["x", "y", "z", "classification"]);
+
+        let synthetic = Synthetic::new(10).intensity(true).rgb(true);
+        let pc = synthetic.terrain().unwrap();
+        assert_eq!(pc.schema(), synthetic.schema());
+        assert_eq!(
+            pc.schema().field_with_name("red").unwrap().data_type(),
+            &DataType::UInt16
+        );
+
+        // rgb follows the height
+        let batch = pc.store.batches(pc.store.iter().next().unwrap().key())[0].clone();
+        let red = batch
+            .column_by_name("red")
+            .unwrap()
+            .as_primitive::<UInt16Type>();
+        assert!(red.values().iter().any(|r| *r > 0));
+    }
+
+    #[test]
+    fn scenes() {
+        let (lower, upper) = ([1000., 2000., 10.], [1100., 2050., 40.]);
+        let synthetic = Synthetic::new(10_000)
+            .extent(lower, upper)
+            .seed(1)
+            .batch_size(4096);
+        let within = |pc: &ArrowPointCloud| {
+            let aabb: AABB<Point<f64, 3>> = pc.aabb();
+            (0..3).all(|d| {
+                aabb.lower().coords()[d] >= lower[d] && aabb.upper().coords()[d] <= upper[d]
+            })
+        };
+
+        let uniform = synthetic.uniform().unwrap();
+        assert_eq!(uniform.num_points(), 10_000);
+        assert_eq!(uniform.store.len(), 3);
+        assert!(within(&uniform));
+
+        let clusters = synthetic.clusters(2).unwrap();
+        assert_eq!(clusters.num_points(), 10_000);
+        assert!(within(&clusters));
+
+        let terrain = synthetic.terrain().unwrap();
+        assert!(within(&terrain));
+        assert!(classes(&terrain).iter().all(|c| *c == GROUND));
+
+        let buildings = synthetic.buildings(5).unwrap();
+        assert_eq!(buildings.num_points(), 10_000);
+        assert!(within(&buildings));
+        let classes = classes(&buildings);
+        let on_buildings = classes.iter().filter(|c| **c == BUILDING).count();
+        assert_eq!(on_buildings, 3000);
+        assert_eq!(classes.len() - on_buildings, 7000);
+    }
+
+    #[test]
+    fn heightfield() {
+        // continuous and within [0, 1)
+        let values: Vec<f64> = (0..=1000)
+            .map(|i| fractal(3, i as f64 / 1000., 0.5))
+            .collect();
+        assert!(values.iter().all(|v| (0. ..1.).contains(v)));
+        assert!(values.windows(2).all(|w| (w[0] - w[1]).abs() < 0.05));
+    }
+}
@@ -0,0 +1,306 @@
+//! Time ranges of batches for temporal queries.
+//!
+//! Mobile mapping data is acquired along a trajectory, so the gps time of the
+//! points is nearly sorted within and across batches. The store records the
+//! range of [TIME_COLUMN] of every batch pushed to it and queries within a
+//! time range, see [Query::time](crate::Query::time), skip the batches outside
+//! of it before reading them. If the ranges of all batches are known and
+//! sorted in iteration order, e.g. after [ArrowPointCloud::sort_by_time], the
+//! first and last batch within the range are found by binary search.
+
+use std::{collections::HashMap, ops::Range};
+
+use arrow::{
+    array::AsArray,
+    compute::{concat_batches, sort_to_indices, take, SortOptions},
+    datatypes::{DataType, Float64Type},
+    record_batch::RecordBatch,
+};
+
+use crate::{
+    encoding::cast, trajectory::TIME_COLUMN, ArrowPointCloud, PointCloudError, PointCloudTrait,
+    ProgressSink,
+};
+
+/// Range of the finite times of a batch, `None` without any
+type TimeRange = Option<(f64, f64)>;
+
+/// Time ranges of the batches by store entry
+#[derive(Debug, Default)]
+pub(crate) struct TimeRanges {
+    /// Ranges of all batches of an entry in order, entries whose batches
+    /// were not all pushed, e.g. spilled ones, are missing
+    entries: HashMap<String, Vec<TimeRange>>,
+}
+
+impl TimeRanges {
+    /// Record the range of a batch pushed to the entry `key`, which is `new`
+    /// if it had no batches before
+    pub(crate) fn push(&mut self, key: &str, batch: &RecordBatch, new: bool) {
+        match batch_range(batch) {
+            Some(range) if new => {
+                self.entries.insert(key.to_owned(), vec![range]);
+            }
+            Some(range) => {
+                if let Some(ranges) = self.entries.get_mut(key) {
+                    ranges.push(range);
+                }
+            }
+            None => {
+                self.entries.remove(key);
+            }
+        }
+    }
+
+    pub(crate) fn remove(&mut self, key: &str) {
+        self.entries.remove(key);
+    }
+
+    fn get(&self, key: &str) -> Option<&Vec<TimeRange>> {
+        self.entries.get(key)
+    }
+}
+
+/// Range of the times of `batch`, `None` without a time column
+fn batch_range(batch: &RecordBatch) -> Option<TimeRange> {
+    let column = cast(batch.column_by_name(TIME_COLUMN)?, &DataType::Float64).ok()?;
+    let range = column
+        .as_primitive::<Float64Type>()
+        .iter()
+        .flatten()
+        .filter(|t| t.is_finite())
+        .fold(None, |range: TimeRange, t| match range {
+            Some((min, max)) => Some((min.min(t), max.max(t))),
+            None => Some((t, t)),
+        });
+    Some(range)
+}
+
+impl ArrowPointCloud {
+    /// Positions of the batches of each store entry, in iteration order,
+    /// that may contain points within `range` of the gps time. The positions
+    /// are `None` for entries with unknown time ranges, which have to be read.
+    pub fn time_candidates(&self, range: &Range<f64>) -> Vec<(String, Option<Vec<usize>>)> {
+        let times = self.store.times.read().unwrap();
+        let entries: Vec<(String, Option<&Vec<TimeRange>>)> = self
+            .store
+            .iter()
+            .map(|e| (e.key().to_owned(), times.get(e.key())))
+            .collect();
+
+        let overlaps =
+            |r: &TimeRange| r.is_some_and(|(min, max)| min < range.end && range.start <= max);
+
+        // batches with times in iteration order, by entry and position
+        let sorted: Option<Vec<(usize, usize, _)>> = entries
+            .iter()
+            .enumerate()
+            .map(|(i, (_, ranges))| {
+                ranges.map(|ranges| {
+                    ranges
+                        .iter()
+                        .enumerate()
+                        .filter_map(move |(j, r)| r.map(|r| (i, j, r)))
+                })
+            })
+            .collect::<Option<Vec<_>>>()
+            .map(|ranges| ranges.into_iter().flatten().collect::<Vec<_>>())
+            .filter(|ranges| ranges.windows(2).all(|w| w[0].2 .1 <= w[1].2 .0));
+
+        match sorted {
+            Some(ranges) => {
+                let first = ranges.partition_point(|(_, _, (_, max))| *max < range.start);
+                let last = ranges.partition_point(|(_, _, (min, _))| *min < range.end);
+                let mut candidates: Vec<(String, Option<Vec<usize>>)> = entries
+                    .iter()
+                    .map(|(key, _)| (key.clone(), Some(Vec::new())))
+                    .collect();
+                for (i, j, _) in &ranges[first..last.max(first)] {
+                    if let Some(positions) = &mut candidates[*i].1 {
+                        positions.push(*j);
+                    }
+                }
+                candidates
+            }
+            None => entries
+                .into_iter()
+                .map(|(key, ranges)| {
+                    let positions = ranges.map(|ranges| {
+                        ranges
+                            .iter()
+                            .enumerate()
+                            .filter(|(_, r)| overlaps(r))
+                            .map(|(j, _)| j)
+                            .collect()
+                    });
+                    (key, positions)
+                })
+                .collect(),
+        }
+    }
+
+    /// Whether the time ranges of all batches are known and sorted in
+    /// iteration order, see [the module](self)
+    pub fn is_sorted_by_time(&self) -> bool {
+        let times = self.store.times.read().unwrap();
+        let mut last = f64::NEG_INFINITY;
+        for e in self.store.iter() {
+            let Some(ranges) = times.get(e.key()) else {
+                return false;
+            };
+            for (min, max) in ranges.iter().flatten() {
+                if *min < last {
+                    return false;
+                }
+                last = *max;
+            }
+        }
+        true
+    }
+
+    /// Points with values of `column` within `[range.start, range.end)`, the
+    /// batches outside of the range are skipped for the gps time, see
+    /// [Query::range](crate::Query::range)
+    pub fn filter_by_range(
+        &self,
+        column: &str,
+        range: Range<f64>,
+    ) -> Result<ArrowPointCloud, PointCloudError> {
+        self.execute(&crate::Query::new().range(column, range))
+    }
+
+    /// The points ordered by gps time in batches of `batch_size` rows, points
+    /// without time last
+    pub fn sort_by_time(
+        &self,
+        batch_size: usize,
+        progress: &dyn ProgressSink,
+    ) -> Result<ArrowPointCloud, PointCloudError> {
+        let schema = self.schema();
+        if schema.column_with_name(TIME_COLUMN).is_none() {
+            return Err(PointCloudError::InvalidArgument(format!(
+                "no column `{TIME_COLUMN}`"
+            )));
+        }
+
+        let entries = self.store.len();
+        let mut batches = Vec::new();
+        for (i, e) in self.store.iter().enumerate() {
+            progress.report(i, entries + 1);
+            if progress.is_cancelled() {
+                return Err(PointCloudError::Cancelled);
+            }
+            batches.extend(self.store.batches(e.key()));
+        }
+        let batch = concat_batches(&schema, &batches)?;
+        drop(batches);
+
+        let times = cast(
+            batch.column_by_name(TIME_COLUMN).unwrap(),
+            &DataType::Float64,
+        )?;
+        let options = SortOptions {
+            descending: false,
+            nulls_first: false,
+        };
+        let indices = sort_to_indices(&times, Some(options), None)?;
+        let columns = batch
+            .columns()
+            .iter()
+            .map(|column| take(column, &indices, None))
+            .collect::<Result<Vec<_>, _>>()?;
+        let batch = RecordBatch::try_new(batch.schema(), columns)?;
+
+        let mut pc = ArrowPointCloud::try_new(schema)?;
+        let batch_size = batch_size.max(1);
+        for offset in (0..batch.num_rows()).step_by(batch_size) {
+            let rows = batch_size.min(batch.num_rows() - offset);
+            pc.append(batch.slice(offset, rows))?;
+        }
+        progress.report(entries + 1, entries + 1);
+
+        Ok(pc)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{compute::filter_by_range, Query, Synthetic};
+
+    #[test]
+    fn pruning() {
+        let pc = Synthetic::new(100_000).batch_size(1000).drive().unwrap();
+        assert_eq!(pc.store.len(), 100);
+        let range = 42.5..44.5;
+
+        // nearly sorted, the batches of seconds 42 to 44 overlap the range
+        let touched: usize = pc
+            .time_candidates(&range)
+            .iter()
+            .map(|(_, positions)| positions.as_ref().unwrap().len())
+            .sum();
+        assert_eq!(touched, 3);
+
+        // the same points as a scan of all batches
+        let expected: usize = pc
+            .store
+            .iter()
+            .flat_map(|e| pc.store.batches(e.key()))
+            .map(|batch| {
+                filter_by_range(&batch, TIME_COLUMN, &range)
+                    .unwrap()
+                    .num_rows()
+            })
+            .sum();
+        assert!(expected > 1900);
+        let selected = pc.execute(&Query::new().time(range.clone())).unwrap();
+        assert_eq!(selected.num_points(), expected);
+        assert_eq!(
+            pc.filter_by_range(TIME_COLUMN, range.clone())
+                .unwrap()
+                .num_points(),
+            expected
+        );
+
+        // sorted after the maintenance, found by binary search
+        assert!(!pc.is_sorted_by_time());
+        let sorted = pc.sort_by_time(1000, &()).unwrap();
+        assert!(sorted.is_sorted_by_time());
+        assert_eq!(sorted.num_points(), pc.num_points());
+        let touched: Vec<usize> = sorted
+            .time_candidates(&range)
+            .iter()
+            .map(|(_, positions)| positions.as_ref().unwrap().len())
+            .collect();
+        assert_eq!(touched.iter().sum::<usize>(), 3);
+        assert_eq!(
+            sorted
+                .execute(&Query::new().time(range))
+                .unwrap()
+                .num_points(),
+            expected
+        );
+
+        // unknown ranges of spilled entries are read
+        let spilled = ArrowPointCloud::try_new(pc.schema()).unwrap();
+        spilled.store.insert_spilled("a");
+        assert_eq!(
+            spilled.time_candidates(&(0.0..1.0)),
+            vec![("a".to_owned(), None)]
+        );
+        assert!(!spilled.is_sorted_by_time());
+    }
+
+    #[test]
+    fn ranges() {
+        let pc = Synthetic::new(100).batch_size(10).terrain().unwrap();
+        // without time column
+        assert!(pc
+            .time_candidates(&(0.0..1.0))
+            .iter()
+            .all(|(_, positions)| positions.is_none()));
+        assert!(pc.sort_by_time(10, &()).is_err());
+        assert!(pc.execute(&Query::new().time(0.0..1.0)).is_err());
+    }
+}
@@ -0,0 +1,408 @@
+use std::{collections::HashMap, f64::consts::PI};
+
+use arrow::{
+    array::{Array, AsArray},
+    datatypes::{DataType, Float64Type, UInt8Type},
+    record_batch::RecordBatch,
+};
+use serde::Serialize;
+
+use crate::{
+    color::{Bounds, ColorMap},
+    compute::aabb,
+    encoding::cast,
+    schema::dimensions,
+    soa::Index,
+    ArrowPointCloud, Point, PointCloudError, PointCloudTrait, PointTrait, AABB,
+};
+
+/// Number of sub-cells along each side of a tile
+pub const TILE_CELLS: usize = 64;
+/// Highest zoom level
+pub const MAX_ZOOM: u8 = 24;
+/// Column holding the point class
+pub const CLASSIFICATION_COLUMN: &str = "classification";
+
+/// Half the extent of the Web Mercator projection in metres
+const MERCATOR_EXTENT: f64 = PI * 6_378_137.;
+
+/// Tile of a XYZ (slippy map) pyramid, `y` counts from the top
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TileId {
+    pub z: u8,
+    pub x: u32,
+    pub y: u32,
+}
+
+impl TileId {
+    pub fn new(z: u8, x: u32, y: u32) -> Result<Self, PointCloudError> {
+        if z > MAX_ZOOM || x >> z != 0 || y >> z != 0 {
+            return Err(PointCloudError::InvalidArgument(format!(
+                "no tile {z}/{x}/{y}"
+            )));
+        }
+
+        Ok(Self { z, x, y })
+    }
+}
+
+/// Mapping of data coordinates to the level 0 tile
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TileScheme {
+    /// Web Mercator tiles of longitude (x) and latitude (y) in degrees
+    Geographic,
+    /// Web Mercator tiles of EPSG:3857 coordinates
+    WebMercator,
+    /// Square level 0 tile with the top left corner at `left`, `top`, for any
+    /// other reference system
+    Local { left: f64, top: f64, size: f64 },
+}
+
+impl TileScheme {
+    /// Scheme matching the reference system of a point cloud, the local
+    /// scheme covers its bounds
+    pub fn for_cloud(pc: &ArrowPointCloud) -> Self {
+        let crs = pc.metadata().crs.unwrap_or_default().to_ascii_uppercase();
+        match crs.trim() {
+            "EPSG:4326" | "OGC:CRS84" | "CRS84" => return Self::Geographic,
+            "EPSG:3857" | "EPSG:900913" => return Self::WebMercator,
+            _ => (),
+        }
+
+        let aabb: AABB<Point<f64, 3>> = pc.aabb();
+        let (lower, upper) = (aabb.lower(), aabb.upper());
+        if pc.num_points() == 0 {
+            return Self::Local {
+                left: 0.,
+                top: 1.,
+                size: 1.,
+            };
+        }
+        let size = (upper.x() - lower.x()).max(upper.y() - lower.y());
+
+        Self::Local {
+            left: lower.x(),
+            top: upper.y(),
+            size: if size > 0. { size } else { 1. },
+        }
+    }
+
+    /// Position in the level 0 tile, within [0, 1]² if covered by the pyramid
+    pub fn project(&self, [x, y]: [f64; 2]) -> [f64; 2] {
+        match *self {
+            Self::Geographic => {
+                let lat = y.to_radians();
+                [(x + 180.) / 360., (1. - lat.tan().asinh() / PI) / 2.]
+            }
+            Self::WebMercator => [
+                (x + MERCATOR_EXTENT) / (2. * MERCATOR_EXTENT),
+                (MERCATOR_EXTENT - y) / (2. * MERCATOR_EXTENT),
+            ],
+            Self::Local { left, top, size } => [(x - left) / size, (top - y) / size],
+        }
+    }
+
+    /// Inverse of [`TileScheme::project`]
+    pub fn unproject(&self, [u, v]: [f64; 2]) -> [f64; 2] {
+        match *self {
+            Self::Geographic => [
+                u * 360. - 180.,
+                (PI * (1. - 2. * v)).sinh().atan().to_degrees(),
+            ],
+            Self::WebMercator => [
+                u * 2. * MERCATOR_EXTENT - MERCATOR_EXTENT,
+                MERCATOR_EXTENT - v * 2. * MERCATOR_EXTENT,
+            ],
+            Self::Local { left, top, size } => [left + u * size, top - v * size],
+        }
+    }
+
+    /// Lower and upper corner of a tile in data coordinates
+    pub fn bounds(&self, tile: TileId) -> ([f64; 2], [f64; 2]) {
+        let n = (1u64 << tile.z) as f64;
+        let [left, top] = self.unproject([tile.x as f64 / n, tile.y as f64 / n]);
+        let [right, bottom] = self.unproject([(tile.x + 1) as f64 / n, (tile.y + 1) as f64 / n]);
+
+        ([left, bottom], [right, top])
+    }
+}
+
+/// Aggregated points of a tile
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct TileSummary {
+    pub count: usize,
+    pub z_min: Option<f64>,
+    pub z_max: Option<f64>,
+    pub z_mean: Option<f64>,
+    /// Most frequent class, if the points are classified
+    pub classification: Option<u8>,
+    /// Lower and upper corner in data coordinates
+    pub bounds: [[f64; 2]; 2],
+}
+
+/// Summary and sub-cell raster of a tile
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tile {
+    pub summary: TileSummary,
+    /// Mean z of `TILE_CELLS`² sub-cells in rows from the top left corner
+    pub cells: Vec<Option<f64>>,
+}
+
+impl Tile {
+    /// RGBA image with `scale`² pixels per sub-cell, z colored by `map` with
+    /// the gradient spanning `range`, empty cells are transparent
+    pub fn to_rgba(&self, map: &ColorMap, range: Bounds, scale: usize) -> Vec<u8> {
+        let width = TILE_CELLS * scale;
+        let mut rgba = vec![0; width * width * 4];
+
+        for (i, z) in self.cells.iter().enumerate() {
+            let Some(color) = z.and_then(|z| map.color(z, Some(range))) else {
+                continue;
+            };
+
+            let (row, column) = (i / TILE_CELLS, i % TILE_CELLS);
+            for y in row * scale..(row + 1) * scale {
+                for x in column * scale..(column + 1) * scale {
+                    rgba[(y * width + x) * 4..][..4].copy_from_slice(&color);
+                }
+            }
+        }
+
+        rgba
+    }
+}
+
+impl ArrowPointCloud {
+    /// Aggregate the points within a tile of `scheme`.
+    ///
+    /// Batches outside the tile are skipped by the batch index, if built, and
+    /// by their bounds.
+    pub fn tile(&self, scheme: &TileScheme, tile: TileId) -> Result<Tile, PointCloudError> {
+        let (lower, upper) = scheme.bounds(tile);
+        let n = (1u64 << tile.z) as f64;
+
+        let keys: Vec<String> = match &self.index {
+            Index::Batch(index) => {
+                let envelope = AABB::from_corners(
+                    Point::from_slice(&[lower[0], lower[1], f64::MIN, f64::MIN]),
+                    Point::from_slice(&[upper[0], upper[1], f64::MAX, f64::MAX]),
+                );
+                index
+                    .locate_in_envelope_intersecting(&envelope)
+                    .map(|object| object.data.to_owned())
+                    .collect()
+            }
+            _ => self.store.iter().map(|e| e.key().to_owned()).collect(),
+        };
+
+        let mut cells: Vec<(f64, usize)> = vec![(0., 0); TILE_CELLS * TILE_CELLS];
+        let mut classes: HashMap<u8, usize> = HashMap::new();
+        let (mut count, mut sum) = (0, 0.);
+        let (mut z_min, mut z_max) = (f64::INFINITY, f64::NEG_INFINITY);
+
+        for batch in keys.iter().flat_map(|key| self.store.batches(key)) {
+            let extent: AABB<Point<f64, 3>> = aabb(&batch);
+            if batch.num_rows() == 0
+                || extent.upper().x() < lower[0]
+                || extent.lower().x() > upper[0]
+                || extent.upper().y() < lower[1]
+                || extent.lower().y() > upper[1]
+            {
+                continue;
+            }
+
+            let (xyz, class) = columns(&batch)?;
+            let [x, y, z] = [0, 1, 2].map(|d| xyz[d].as_primitive::<Float64Type>());
+            let class = class.as_ref().map(|c| c.as_primitive::<UInt8Type>());
+
+            for i in 0..batch.num_rows() {
+                if x.is_null(i) || y.is_null(i) || z.is_null(i) {
+                    continue;
+                }
+                let [u, v] = scheme.project([x.value(i), y.value(i)]);
+                let (u, v) = (u * n - tile.x as f64, v * n - tile.y as f64);
+                if !(0. ..1.).contains(&u) || !(0. ..1.).contains(&v) || z.value(i).is_nan() {
+                    continue;
+                }
+
+                let z = z.value(i);
+                let cell = &mut cells[(v * TILE_CELLS as f64) as usize * TILE_CELLS
+                    + (u * TILE_CELLS as f64) as usize];
+                cell.0 += z;
+                cell.1 += 1;
+
+                count += 1;
+                sum += z;
+                z_min = z_min.min(z);
+                z_max = z_max.max(z);
+                if let Some(class) = class.filter(|c| c.is_valid(i)) {
+                    *classes.entry(class.value(i)).or_default() += 1;
+                }
+            }
+        }
+
+        // ties resolve to the lower class
+        let classification = classes
+            .into_iter()
+            .max_by_key(|(class, n)| (*n, std::cmp::Reverse(*class)))
+            .map(|(class, _)| class);
+
+        Ok(Tile {
+            summary: TileSummary {
+                count,
+                z_min: (count > 0).then_some(z_min),
+                z_max: (count > 0).then_some(z_max),
+                z_mean: (count > 0).then(|| sum / count as f64),
+                classification,
+                bounds: [lower, upper],
+            },
+            cells: cells
+                .into_iter()
+                .map(|(sum, n)| (n > 0).then(|| sum / n as f64))
+                .collect(),
+        })
+    }
+}
+
+type Columns = (Vec<arrow::array::ArrayRef>, Option<arrow::array::ArrayRef>);
+
+/// Coordinates as f64 and the classification as u8 columns
+fn columns(batch: &RecordBatch) -> Result<Columns, PointCloudError> {
+    let dimensions = dimensions(&batch.schema());
+    if dimensions.len() < 3 {
+        return Err(PointCloudError::SchemaError(
+            "expected three dimensions".to_string(),
+        ));
+    }
+
+    let xyz = dimensions[..3]
+        .iter()
+        .map(|d| cast(batch.column(*d), &DataType::Float64))
+        .collect::<Result<_, _>>()?;
+    let class = batch
+        .column_by_name(CLASSIFICATION_COLUMN)
+        .map(|c| cast(c, &DataType::UInt8))
+        .transpose()?;
+
+    Ok((xyz, class))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow::{
+        array::{ArrayRef, Float64Array, UInt8Array},
+        datatypes::{Field, Schema},
+    };
+
+    use super::*;
+
+    #[test]
+    fn schemes() {
+        let tile = TileId::new(1, 1, 0).unwrap();
+        assert!(TileId::new(1, 2, 0).is_err());
+        assert!(TileId::new(MAX_ZOOM + 1, 0, 0).is_err());
+
+        // north east quadrant
+        let (lower, upper) = TileScheme::Geographic.bounds(tile);
+        assert_eq!([lower[0], upper[0]], [0., 180.]);
+        assert!(lower[1].abs() < 1e-9 && (upper[1] - 85.0511).abs() < 1e-4);
+
+        let (lower, upper) = TileScheme::WebMercator.bounds(tile);
+        assert_eq!(lower, [0., 0.]);
+        assert_eq!(upper, [MERCATOR_EXTENT, MERCATOR_EXTENT]);
+
+        let local = TileScheme::Local {
+            left: 100.,
+            top: 50.,
+            size: 20.,
+        };
+        assert_eq!(local.bounds(tile), ([110., 40.], [120., 50.]));
+
+        for scheme in [TileScheme::Geographic, TileScheme::WebMercator, local] {
+            let [u, v] = scheme.project(scheme.unproject([0.25, 0.75]));
+            assert!((u - 0.25).abs() < 1e-9 && (v - 0.75).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn summary() {
+        // 10 x 10 points in [0, 10]², class 2 in the west and 6 in the east
+        let mut fields: Vec<Field> = Point::<f64, 3>::schema()
+            .fields()
+            .iter()
+            .map(|field| field.as_ref().clone())
+            .collect();
+        fields.push(Field::new(CLASSIFICATION_COLUMN, DataType::UInt8, false));
+        let schema = Arc::new(Schema::new_with_metadata(
+            fields,
+            Point::<f64, 3>::schema().metadata().clone(),
+        ));
+
+        let column = |f: &dyn Fn(usize) -> f64| {
+            Arc::new(Float64Array::from_iter_values((0..100).map(f))) as ArrayRef
+        };
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                column(&|i| (i % 10) as f64 + 0.5),
+                column(&|i| (i / 10) as f64 + 0.5),
+                column(&|i| i as f64),
+                Arc::new(UInt8Array::from_iter_values((0..100).map(|i| {
+                    if i % 10 < 3 {
+                        2
+                    } else {
+                        6
+                    }
+                }))),
+            ],
+        )
+        .unwrap();
+        let mut pc = ArrowPointCloud::try_new(schema).unwrap();
+        pc.append(batch).unwrap();
+
+        let scheme = TileScheme::for_cloud(&pc);
+        assert_eq!(
+            scheme,
+            TileScheme::Local {
+                left: 0.5,
+                top: 9.5,
+                size: 9.
+            }
+        );
+
+        let tile = pc.tile(&scheme, TileId::new(0, 0, 0).unwrap()).unwrap();
+        // the points on the right and bottom edge are outside
+        assert_eq!(tile.summary.count, 81);
+        assert_eq!(tile.summary.z_min, Some(10.));
+        assert_eq!(tile.summary.z_max, Some(98.));
+        assert_eq!(tile.summary.classification, Some(6));
+        assert_eq!(tile.cells.iter().flatten().count(), 81);
+
+        // top left quadrant, mostly in the west
+        let tile = pc.tile(&scheme, TileId::new(1, 0, 0).unwrap()).unwrap();
+        assert_eq!(tile.summary.count, 25);
+        assert_eq!(tile.summary.z_mean, Some(72.));
+        assert_eq!(tile.summary.classification, Some(2));
+        assert_eq!(tile.summary.bounds, [[0.5, 5.], [5., 9.5]]);
+
+        let empty = pc
+            .tile(&TileScheme::WebMercator, TileId::new(2, 0, 0).unwrap())
+            .unwrap();
+        assert_eq!(empty.summary.count, 0);
+        assert_eq!(empty.summary.z_mean, None);
+
+        let map = ColorMap::Gradient {
+            gradient: colorgrad::turbo(),
+            normalization: crate::color::Stretch::Fixed((0., 100.)),
+        };
+        let rgba = tile.to_rgba(&map, (0., 100.), 2);
+        assert_eq!(rgba.len(), TILE_CELLS * TILE_CELLS * 16);
+        // transparent empty cells
+        assert_eq!(
+            rgba.chunks(4).filter(|p| p[3] == 0).count(),
+            (64 * 64 - 25) * 4
+        );
+    }
+}
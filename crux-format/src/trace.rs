@@ -0,0 +1,129 @@
+//! Spans of the expensive stages of loads and queries.
+//!
+//! With the `tracing` feature, [span!] enters a [tracing] span of the given
+//! level, whole operations (decode, index, rechunk, sampling) are `DEBUG`
+//! spans and per-batch stages (aabb, append, filter) are `TRACE` spans. Row
+//! counts are span fields, durations are measured by the subscriber. Without
+//! the feature the guard is zero-sized and the field values are not
+//! evaluated.
+
+/// Guard of a span, exited on drop
+#[must_use]
+pub(crate) struct Span(#[cfg(feature = "tracing")] pub(crate) tracing::span::EnteredSpan);
+
+impl Span {
+    /// Record a field declared without a value, e.g. the rows left by a filter
+    #[inline(always)]
+    pub(crate) fn record(&self, field: &'static str, value: usize) {
+        #[cfg(feature = "tracing")]
+        self.0.record(field, value);
+        #[cfg(not(feature = "tracing"))]
+        let _ = (field, value);
+    }
+}
+
+/// Enter a span of `level` named `name` with `field = value` pairs, fields
+/// without value are recorded later with [Span::record]
+macro_rules! span {
+    ($level:ident, $name:literal $(, $field:ident $(= $value:expr)?)* $(,)?) => {{
+        #[cfg(feature = "tracing")]
+        let span = $crate::trace::Span(
+            ::tracing::span!(
+                ::tracing::Level::$level,
+                $name,
+                $($field = $crate::trace::field!($($value)?),)*
+            )
+            .entered(),
+        );
+        #[cfg(not(feature = "tracing"))]
+        let span = $crate::trace::Span();
+        span
+    }};
+}
+
+#[cfg(feature = "tracing")]
+macro_rules! field {
+    () => {
+        ::tracing::field::Empty
+    };
+    ($value:expr) => {
+        $value
+    };
+}
+
+#[cfg(feature = "tracing")]
+pub(crate) use field;
+pub(crate) use span;
+
+#[cfg(all(test, feature = "tracing"))]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use tracing::{
+        field::{Field, Visit},
+        span::{Attributes, Id, Record},
+        Event, Metadata, Subscriber,
+    };
+
+    type Fields = Vec<(String, u64)>;
+
+    /// Names and fields of the created spans
+    #[derive(Default, Clone)]
+    struct Recorder(Arc<Mutex<Vec<(String, Fields)>>>);
+
+    struct Visitor<'a>(&'a mut Fields);
+
+    impl Visit for Visitor<'_> {
+        fn record_u64(&mut self, field: &Field, value: u64) {
+            self.0.push((field.name().to_owned(), value));
+        }
+
+        fn record_debug(&mut self, _: &Field, _: &dyn std::fmt::Debug) {}
+    }
+
+    impl Subscriber for Recorder {
+        fn enabled(&self, _: &Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, span: &Attributes<'_>) -> Id {
+            let mut fields = Vec::new();
+            span.record(&mut Visitor(&mut fields));
+            let mut spans = self.0.lock().unwrap();
+            spans.push((span.metadata().name().to_owned(), fields));
+            Id::from_u64(spans.len() as u64)
+        }
+
+        fn record(&self, span: &Id, values: &Record<'_>) {
+            let mut spans = self.0.lock().unwrap();
+            let (_, fields) = &mut spans[span.into_u64() as usize - 1];
+            values.record(&mut Visitor(fields));
+        }
+
+        fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+        fn event(&self, _: &Event<'_>) {}
+
+        fn enter(&self, _: &Id) {}
+
+        fn exit(&self, _: &Id) {}
+    }
+
+    #[test]
+    fn fields() {
+        let recorder = Recorder::default();
+        tracing::subscriber::with_default(recorder.clone(), || {
+            let span = span!(DEBUG, "filter", input = 10usize, rows);
+            span.record("rows", 4);
+        });
+
+        let spans = recorder.0.lock().unwrap();
+        assert_eq!(
+            *spans,
+            [(
+                "filter".to_owned(),
+                vec![("input".to_owned(), 10), ("rows".to_owned(), 4)]
+            )]
+        );
+    }
+}
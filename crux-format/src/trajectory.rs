@@ -0,0 +1,226 @@
+use std::io::BufRead;
+
+use arrow::{
+    array::AsArray,
+    compute::cast,
+    datatypes::{DataType, Float64Type},
+};
+
+use crate::{ArrowPointCloud, Point, PointCloudError, PointCloudTrait, PointTrait};
+
+/// Column holding the time of a trajectory pose
+pub const TIME_COLUMN: &str = "gps_time";
+
+/// Timestamped sensor position
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Pose {
+    pub time: f64,
+    pub position: [f64; 3],
+}
+
+/// Sensor positions ordered by time
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Trajectory {
+    pub poses: Vec<Pose>,
+}
+
+impl Trajectory {
+    /// Trajectory from poses in any order, poses with non-finite values are dropped
+    pub fn new(mut poses: Vec<Pose>) -> Self {
+        poses.retain(|p| p.time.is_finite() && p.position.iter().all(|v| v.is_finite()));
+        poses.sort_by(|a, b| a.time.total_cmp(&b.time));
+        Self { poses }
+    }
+
+    /// Read comma separated poses with a header row naming the columns `x`,
+    /// `y`, `z` and `time` (or `gps_time`), other columns are ignored.
+    pub fn read_csv(reader: impl BufRead) -> Result<Self, PointCloudError> {
+        let invalid = |line: usize, message: String| {
+            PointCloudError::InvalidArgument(format!("trajectory line {line}: {message}"))
+        };
+
+        let mut lines = reader.lines().enumerate();
+        let header = match lines.next() {
+            Some((_, line)) => line.map_err(|e| invalid(1, e.to_string()))?,
+            None => return Ok(Self::default()),
+        };
+        let header: Vec<String> = header
+            .split(',')
+            .map(|name| name.trim().to_ascii_lowercase())
+            .collect();
+        let column = |names: &[&str]| {
+            header
+                .iter()
+                .position(|name| names.contains(&name.as_str()))
+                .ok_or_else(|| invalid(1, format!("missing column `{}`", names[0])))
+        };
+        let columns = [
+            column(&["time", TIME_COLUMN])?,
+            column(&["x"])?,
+            column(&["y"])?,
+            column(&["z"])?,
+        ];
+
+        let mut poses = Vec::new();
+        for (i, line) in lines {
+            let line = line.map_err(|e| invalid(i + 1, e.to_string()))?;
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+            let mut values = [0.; 4];
+            for (value, c) in values.iter_mut().zip(columns) {
+                let field = fields
+                    .get(c)
+                    .ok_or_else(|| invalid(i + 1, format!("expected {} fields", header.len())))?;
+                *value = field
+                    .parse()
+                    .map_err(|_| invalid(i + 1, format!("invalid number `{field}`")))?;
+            }
+
+            let [time, x, y, z] = values;
+            poses.push(Pose {
+                time,
+                position: [x, y, z],
+            });
+        }
+
+        Ok(Self::new(poses))
+    }
+
+    /// Trajectory from the points of a point cloud with a `gps_time` column
+    pub fn from_point_cloud(pc: &ArrowPointCloud) -> Result<Self, PointCloudError> {
+        if pc.schema().column_with_name(TIME_COLUMN).is_none() {
+            return Err(PointCloudError::InvalidArgument(format!(
+                "no column `{TIME_COLUMN}`"
+            )));
+        }
+
+        let mut times = Vec::new();
+        for batch in pc.store.iter().flat_map(|e| pc.store.batches(e.key())) {
+            let column = cast(
+                batch.column_by_name(TIME_COLUMN).unwrap(),
+                &DataType::Float64,
+            )?;
+            times.extend(
+                column
+                    .as_primitive::<Float64Type>()
+                    .iter()
+                    .map(|t| t.unwrap_or(f64::NAN)),
+            );
+        }
+
+        let poses = pc
+            .points::<Point<f64, 3>>()
+            .zip(times)
+            .map(|(p, time)| Pose {
+                time,
+                position: [p.x(), p.y(), p.z()],
+            })
+            .collect();
+
+        Ok(Self::new(poses))
+    }
+
+    /// First and last time, `None` if empty
+    pub fn time_range(&self) -> Option<(f64, f64)> {
+        Some((self.poses.first()?.time, self.poses.last()?.time))
+    }
+
+    /// Position at `time`, linearly interpolated between the adjacent poses.
+    /// `None` outside of the time range.
+    pub fn position_at(&self, time: f64) -> Option<[f64; 3]> {
+        let (start, end) = self.time_range()?;
+        if !(start..=end).contains(&time) {
+            return None;
+        }
+
+        let i = self.poses.partition_point(|p| p.time < time);
+        let b = self.poses[i];
+        if b.time == time || i == 0 {
+            return Some(b.position);
+        }
+
+        let a = self.poses[i - 1];
+        let t = (time - a.time) / (b.time - a.time);
+        Some(std::array::from_fn(|d| {
+            a.position[d] + (b.position[d] - a.position[d]) * t
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow::{
+        array::{ArrayRef, Float64Array},
+        datatypes::{Field, Schema},
+        record_batch::RecordBatch,
+    };
+
+    use super::*;
+
+    #[test]
+    fn csv() {
+        let csv = "Time, X, Y, Z, heading\n\
+                   2, 10, 0, 1, 90\n\
+                   \n\
+                   0, 0, 0, 1, 90\n\
+                   1, 5, 0, 1, 90\n";
+        let trajectory = Trajectory::read_csv(csv.as_bytes()).unwrap();
+
+        // ordered by time
+        let times: Vec<f64> = trajectory.poses.iter().map(|p| p.time).collect();
+        assert_eq!(times, [0., 1., 2.]);
+        assert_eq!(trajectory.time_range(), Some((0., 2.)));
+
+        assert_eq!(trajectory.position_at(0.), Some([0., 0., 1.]));
+        assert_eq!(trajectory.position_at(1.5), Some([7.5, 0., 1.]));
+        assert_eq!(trajectory.position_at(2.), Some([10., 0., 1.]));
+        assert_eq!(trajectory.position_at(2.1), None);
+
+        assert!(Trajectory::read_csv("time,x,y\n0,0,0".as_bytes()).is_err());
+        assert!(Trajectory::read_csv("gps_time,x,y,z\n0,0,a,0".as_bytes()).is_err());
+        assert!(Trajectory::read_csv("gps_time,x,y,z\n0,0".as_bytes()).is_err());
+        assert!(Trajectory::read_csv("".as_bytes())
+            .unwrap()
+            .poses
+            .is_empty());
+    }
+
+    #[test]
+    fn point_cloud() {
+        let mut fields: Vec<Field> = Point::<f64, 3>::schema()
+            .fields()
+            .iter()
+            .map(|field| field.as_ref().clone())
+            .collect();
+        fields.push(Field::new(TIME_COLUMN, DataType::Float64, false));
+        let schema = Arc::new(Schema::new(fields));
+
+        let column = |v: [f64; 3]| Arc::new(Float64Array::from(v.to_vec())) as ArrayRef;
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                column([0., 2., 1.]),
+                column([0., 4., 2.]),
+                column([0., 0., 0.]),
+                column([10., 12., 11.]),
+            ],
+        )
+        .unwrap();
+        let mut pc = ArrowPointCloud::try_new(schema).unwrap();
+        pc.append(batch).unwrap();
+
+        let trajectory = Trajectory::from_point_cloud(&pc).unwrap();
+        assert_eq!(trajectory.poses.len(), 3);
+        assert_eq!(trajectory.position_at(11.5), Some([1.5, 3., 0.]));
+
+        let pc =
+            ArrowPointCloud::from_iter([Point::<f64, 3>::from_slice(&[0., 0., 0.])].into_iter())
+                .unwrap();
+        assert!(Trajectory::from_point_cloud(&pc).is_err());
+    }
+}
@@ -0,0 +1,246 @@
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    polygon, progress::Tracker, ArrowPointCloud, PointCloudError, PointTrait, ProgressSink,
+};
+
+/// Lower surface of a volume estimation
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(try_from = "String")]
+pub enum BaseSurface {
+    /// Horizontal plane at the given z
+    Plane(f64),
+    /// Minimum z per cell
+    MinSurface,
+}
+
+impl FromStr for BaseSurface {
+    type Err = PointCloudError;
+
+    /// `min` or the z of a plane
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "min" => Ok(Self::MinSurface),
+            z => z
+                .parse()
+                .ok()
+                .filter(|z: &f64| z.is_finite())
+                .map(Self::Plane)
+                .ok_or_else(|| {
+                    PointCloudError::InvalidArgument(format!(
+                        "base must be `min` or the z of a plane, got `{s}`"
+                    ))
+                }),
+        }
+    }
+}
+
+impl TryFrom<String> for BaseSurface {
+    type Error = PointCloudError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+/// Result of a volume estimation
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct VolumeReport {
+    /// Volume between the surface and the base, parts below the base are not counted
+    pub volume: f64,
+    /// Area of the cells with points
+    pub area: f64,
+    /// Number of cells with points
+    pub cell_count: usize,
+    /// Fraction of the footprint cells with points
+    pub coverage: f64,
+}
+
+impl ArrowPointCloud {
+    /// Volume between the surface (maximum z per cell) and `base` over a
+    /// polygon footprint.
+    ///
+    /// The footprint is rasterized into square cells of size `cell`, a cell is
+    /// part of the footprint if its center is inside the polygon. Cells without
+    /// points are excluded from the volume and reported by the coverage.
+    pub fn volume_above(
+        &self,
+        footprint: &[[f64; 2]],
+        cell: f64,
+        base: BaseSurface,
+    ) -> Result<VolumeReport, PointCloudError> {
+        self.volume_above_with(footprint, cell, base, &())
+    }
+
+    /// [ArrowPointCloud::volume_above] reporting progress per segment
+    pub fn volume_above_with(
+        &self,
+        footprint: &[[f64; 2]],
+        cell: f64,
+        base: BaseSurface,
+        progress: &dyn ProgressSink,
+    ) -> Result<VolumeReport, PointCloudError> {
+        let ring = polygon::validate(footprint)?;
+        if cell.is_nan() || cell <= 0. {
+            return Err(PointCloudError::InvalidArgument(
+                "cell size must be positive".to_string(),
+            ));
+        }
+
+        let (lower, upper) = polygon::bounds(&ring);
+        let nx = ((upper[0] - lower[0]) / cell).ceil().max(1.) as usize;
+        let ny = ((upper[1] - lower[1]) / cell).ceil().max(1.) as usize;
+        if nx.saturating_mul(ny) > 1 << 28 {
+            return Err(PointCloudError::InvalidArgument(format!(
+                "cell size {cell} is too small for the footprint"
+            )));
+        }
+
+        let footprint: Vec<bool> = (0..nx * ny)
+            .map(|i| {
+                let center = [
+                    lower[0] + ((i % nx) as f64 + 0.5) * cell,
+                    lower[1] + ((i / nx) as f64 + 0.5) * cell,
+                ];
+                polygon::contains(&ring, center)
+            })
+            .collect();
+
+        // minimum and maximum z per cell
+        let mut cells: Vec<Option<(f64, f64)>> = vec![None; nx * ny];
+        let tracker = Tracker::new(progress, self.store.len());
+        self.visit_points(&tracker, |p| {
+            let (x, y) = ((p.x() - lower[0]) / cell, (p.y() - lower[1]) / cell);
+            if !(0. ..=nx as f64).contains(&x) || !(0. ..=ny as f64).contains(&y) || p.z().is_nan()
+            {
+                return;
+            }
+
+            let i = (y as usize).min(ny - 1) * nx + (x as usize).min(nx - 1);
+            if !footprint[i] {
+                return;
+            }
+
+            let z = p.z();
+            cells[i] = Some(match cells[i] {
+                Some((min, max)) => (min.min(z), max.max(z)),
+                None => (z, z),
+            });
+        })?;
+
+        let cell_area = cell * cell;
+        let (volume, cell_count) =
+            cells
+                .iter()
+                .flatten()
+                .fold((0., 0), |(volume, count), (min, max)| {
+                    let base = match base {
+                        BaseSurface::Plane(z) => z,
+                        BaseSurface::MinSurface => *min,
+                    };
+                    (volume + (max - base).max(0.) * cell_area, count + 1)
+                });
+
+        let footprint_count = footprint.iter().filter(|inside| **inside).count();
+
+        Ok(VolumeReport {
+            volume,
+            area: cell_count as f64 * cell_area,
+            cell_count,
+            coverage: if footprint_count > 0 {
+                cell_count as f64 / footprint_count as f64
+            } else {
+                0.
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Point, PointCloudTrait};
+
+    /// Flat ground on a 0.1 grid over [0, 20]² with a 5 x 4 x 3 box on top
+    /// and a hole without points at [14, 16]²
+    fn stockpile() -> ArrowPointCloud {
+        ArrowPointCloud::from_iter((0..200 * 200).filter_map(|i| {
+            let x = (i % 200) as f64 / 10. + 0.05;
+            let y = (i / 200) as f64 / 10. + 0.05;
+
+            if (14. ..16.).contains(&x) && (14. ..16.).contains(&y) {
+                return None;
+            }
+            let z = if (5. ..10.).contains(&x) && (5. ..9.).contains(&y) {
+                3.
+            } else {
+                0.
+            };
+
+            Some(Point::<f64, 3>::from_slice(&[x, y, z]))
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn box_on_ground() {
+        let pc = stockpile();
+        let square = [[2., 2.], [18., 2.], [18., 18.], [2., 18.]];
+
+        let report = pc
+            .volume_above(&square, 0.5, BaseSurface::Plane(0.))
+            .unwrap();
+        assert!((report.volume - 60.).abs() < 0.6, "{report:?}");
+
+        // the hole is excluded and reported
+        assert_eq!(report.cell_count, 32 * 32 - 16);
+        assert_eq!(report.area, (32 * 32 - 16) as f64 * 0.25);
+        assert_eq!(report.coverage, (32. * 32. - 16.) / (32. * 32.));
+
+        // the base plane cuts the box
+        let report = pc
+            .volume_above(&square, 0.5, BaseSurface::Plane(2.))
+            .unwrap();
+        assert!((report.volume - 20.).abs() < 0.2, "{report:?}");
+
+        // only the top of the box is sampled
+        let report = pc
+            .volume_above(&square, 0.5, BaseSurface::MinSurface)
+            .unwrap();
+        assert_eq!(report.volume, 0.);
+
+        // footprint cutting the box in half, finer cells
+        let half = [[0., 0.], [7.5, 0.], [7.5, 20.], [0., 20.]];
+        let report = pc
+            .volume_above(&half, 0.25, BaseSurface::Plane(0.))
+            .unwrap();
+        assert!((report.volume - 30.).abs() < 0.3, "{report:?}");
+        assert_eq!(report.coverage, 1.);
+    }
+
+    #[test]
+    fn invalid() {
+        let pc = stockpile();
+        let square = [[2., 2.], [18., 2.], [18., 18.], [2., 18.]];
+
+        assert!(pc
+            .volume_above(&square, 0., BaseSurface::MinSurface)
+            .is_err());
+        assert!(pc
+            .volume_above(&square[..2], 1., BaseSurface::MinSurface)
+            .is_err());
+
+        assert_eq!(
+            "min".parse::<BaseSurface>().unwrap(),
+            BaseSurface::MinSurface
+        );
+        assert_eq!(
+            "1.5".parse::<BaseSurface>().unwrap(),
+            BaseSurface::Plane(1.5)
+        );
+        assert!("max".parse::<BaseSurface>().is_err());
+        assert!("inf".parse::<BaseSurface>().is_err());
+    }
+}
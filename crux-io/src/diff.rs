@@ -0,0 +1,107 @@
+use std::{error::Error, path::Path};
+
+use crux_format::{summarize_diff, CloudDigest, DiffSummary};
+
+use crate::{problem, tile::read, FormatExt};
+
+#[derive(clap::Args, Debug)]
+pub struct DiffArgs {
+    /// Point cloud file or collection url, e.g. `http://0.0.0.0:3000/collections/ahn`
+    pub a: String,
+    /// Point cloud file or collection url compared to `a`
+    pub b: String,
+}
+
+/// Changes from the points of `args.a` to those of `args.b`
+pub fn diff(args: &DiffArgs) -> Result<DiffSummary, Box<dyn Error>> {
+    Ok(summarize_diff(&digest(&args.a)?, &digest(&args.b)?))
+}
+
+/// Digest of a file, or of a collection from the stats of the server
+fn digest(src: &str) -> Result<CloudDigest, Box<dyn Error>> {
+    if !(src.starts_with("http://") || src.starts_with("https://")) {
+        let path = Path::new(src);
+        let format: FormatExt = path.extension().ok_or("missing extension")?.try_into()?;
+        return Ok(read(path, &format)?.digest());
+    }
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    let url = stats_url(src);
+    runtime.block_on(async move {
+        let response = problem::check(reqwest::get(&url).await?).await?;
+        let mut stats: serde_json::Value = serde_json::from_slice(&response.bytes().await?)?;
+        Ok(serde_json::from_value(stats["digest"].take())?)
+    })
+}
+
+/// Stats request with the digest of a collection url, which may select a
+/// version with `at`
+fn stats_url(collection: &str) -> String {
+    match collection.split_once('?') {
+        Some((path, query)) => {
+            format!("{}/stats?{query}&digest=true", path.trim_end_matches('/'))
+        }
+        None => format!("{}/stats?digest=true", collection.trim_end_matches('/')),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+
+    use arrow::ipc::writer::FileWriter;
+    use crux_format::synthetic::Synthetic;
+
+    use super::*;
+
+    #[test]
+    fn files() {
+        let dir = tempfile::tempdir().unwrap();
+        let write = |name: &str, points: usize| {
+            let pc = Synthetic::new(points).terrain().unwrap();
+            let path = dir.path().join(name);
+            let mut writer = FileWriter::try_new(File::create(&path).unwrap(), &pc.schema).unwrap();
+            for e in pc.store.iter() {
+                for batch in pc.store.batches(e.key()) {
+                    writer.write(&batch).unwrap();
+                }
+            }
+            writer.finish().unwrap();
+            path.to_string_lossy().into_owned()
+        };
+
+        let args = DiffArgs {
+            a: write("a.arrow", 1000),
+            b: write("b.arrow", 1000),
+        };
+        assert!(diff(&args).unwrap().is_unchanged());
+
+        let args = DiffArgs {
+            b: write("c.arrow", 800),
+            ..args
+        };
+        let summary = diff(&args).unwrap();
+        assert_eq!(summary.point_delta, -200);
+        assert!(!summary.is_unchanged());
+
+        let args = DiffArgs {
+            b: "missing.txt".to_owned(),
+            ..args
+        };
+        assert!(diff(&args).is_err());
+    }
+
+    #[test]
+    fn urls() {
+        assert_eq!(
+            stats_url("http://localhost:3000/collections/ahn/"),
+            "http://localhost:3000/collections/ahn/stats?digest=true"
+        );
+        assert_eq!(
+            stats_url("http://localhost:3000/collections/ahn?at=2"),
+            "http://localhost:3000/collections/ahn/stats?at=2&digest=true"
+        );
+    }
+}
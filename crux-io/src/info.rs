@@ -0,0 +1,290 @@
+use std::{error::Error, fmt::Write, ops::Range, path::Path};
+
+use crux_format::{
+    inspect::{Estimated, RangeRead},
+    inspect_with, InspectReport, Point, PointCloudTrait, PointTrait, AABB,
+};
+use object_store::{path::Path as ObjectPath, ObjectStore};
+
+use crate::{tile::read, FormatExt};
+
+#[derive(clap::Args, Debug)]
+pub struct InfoArgs {
+    /// Point cloud to describe (LAS, LAZ, PLY or Arrow IPC), or the url of a
+    /// LAS, LAZ or Arrow IPC file or a collection in an object store
+    pub src: String,
+    /// Hash the values, equal digests mean equal points regardless of batches
+    #[arg(long)]
+    pub digest: bool,
+    /// Read only the header of LAS files and the footer of IPC files, as for urls
+    #[arg(long, conflicts_with = "digest")]
+    pub inspect: bool,
+}
+
+/// Object in a store read by range requests
+struct Object {
+    store: Box<dyn ObjectStore>,
+    path: ObjectPath,
+    runtime: tokio::runtime::Runtime,
+}
+
+impl Object {
+    /// Object of a url like `s3://bucket/huge.las`, the manifest of a
+    /// collection for urls without extension
+    fn open(url: &str) -> Result<Self, Box<dyn Error>> {
+        let (store, mut path) = object_store::parse_url(&url.parse()?)?;
+        if path.extension().is_none() {
+            path = path.child("MANIFEST.json");
+        }
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        Ok(Self {
+            store,
+            path,
+            runtime,
+        })
+    }
+}
+
+impl RangeRead for Object {
+    fn size(&self) -> std::io::Result<u64> {
+        let meta = self.runtime.block_on(self.store.head(&self.path));
+        Ok(meta.map_err(std::io::Error::other)?.size as u64)
+    }
+
+    fn read_range(&self, range: Range<u64>) -> std::io::Result<Vec<u8>> {
+        let range = range.start as usize..range.end as usize;
+        let bytes = self
+            .runtime
+            .block_on(self.store.get_range(&self.path, range));
+        Ok(bytes.map_err(std::io::Error::other)?.to_vec())
+    }
+}
+
+/// Number of points, bounds and columns of `args.src`, and its digest if
+/// requested. Urls and `--inspect` read the metadata of the file only.
+pub fn info(args: &InfoArgs) -> Result<String, Box<dyn Error>> {
+    if args.src.contains("://") {
+        if args.digest {
+            return Err("digests of urls are not supported, they read all points".into());
+        }
+        return report(&inspect_with(&Object::open(&args.src)?)?);
+    }
+    if args.inspect {
+        return report(&crux_format::inspect(&args.src)?);
+    }
+
+    let src = Path::new(&args.src);
+    let format: FormatExt = src.extension().ok_or("missing extension")?.try_into()?;
+    let pc = read(src, &format)?;
+
+    let schema = pc.schema();
+    let columns: Vec<&str> = schema.fields().iter().map(|f| f.name().as_str()).collect();
+    let digest = args.digest.then(|| pc.digest());
+    let (num_points, bounds) = match &digest {
+        Some(digest) => (digest.point_count, digest.aabb),
+        None => {
+            let num_points = pc.num_points();
+            let aabb: AABB<Point<f64, 3>> = pc.aabb();
+            let (lower, upper) = (aabb.lower(), aabb.upper());
+            let bounds = [
+                [lower.x(), lower.y(), lower.z()],
+                [upper.x(), upper.y(), upper.z()],
+            ];
+            (num_points, (num_points > 0).then_some(bounds))
+        }
+    };
+
+    let mut out = String::new();
+    writeln!(out, "points: {num_points}")?;
+    if let Some([lower, upper]) = bounds {
+        writeln!(out, "bounds: {lower:?} - {upper:?}")?;
+    }
+    writeln!(out, "columns: {}", columns.join(", "))?;
+    if let Some(digest) = digest {
+        writeln!(out, "digest: {:016x}", digest.content_hash)?;
+        for column in columns {
+            writeln!(out, "  {column}: {:016x}", digest.per_column_hash[column])?;
+        }
+    }
+    Ok(out)
+}
+
+/// Description of the metadata of a file, like [info]
+fn report(report: &InspectReport) -> Result<String, Box<dyn Error>> {
+    fn estimated<T>(value: &Estimated<T>) -> &'static str {
+        if value.exact {
+            ""
+        } else {
+            " (estimated)"
+        }
+    }
+
+    let mut out = String::new();
+    match &report.num_points {
+        Some(n) => writeln!(out, "points: {}{}", n.value, estimated(n))?,
+        None => writeln!(out, "points: unknown")?,
+    }
+    if let Some(bounds) = &report.bounds {
+        let [lower, upper] = bounds.value;
+        writeln!(out, "bounds: {lower:?} - {upper:?}{}", estimated(bounds))?;
+    }
+    if !report.columns.is_empty() {
+        writeln!(out, "columns: {}", report.columns.join(", "))?;
+    }
+    writeln!(out, "format: {:?}", report.format)?;
+    if let Some(point_format) = report.point_format {
+        writeln!(out, "point format: {point_format}")?;
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+
+    use arrow::ipc::writer::FileWriter;
+    use crux_format::synthetic::Synthetic;
+
+    use super::*;
+
+    #[test]
+    fn digest() {
+        let dir = tempfile::tempdir().unwrap();
+        let pc = Synthetic::new(1000).terrain().unwrap();
+        let write = |name: &str, rows: usize| {
+            let path = dir.path().join(name);
+            let mut writer =
+                FileWriter::try_new(File::create(&path).unwrap(), &pc.schema()).unwrap();
+            let batch = pc.store.batches(pc.store.iter().next().unwrap().key())[0].clone();
+            for offset in (0..batch.num_rows()).step_by(rows) {
+                let len = rows.min(batch.num_rows() - offset);
+                writer.write(&batch.slice(offset, len)).unwrap();
+            }
+            writer.finish().unwrap();
+            path.to_string_lossy().into_owned()
+        };
+
+        let mut args = InfoArgs {
+            src: write("a.arrow", 1000),
+            digest: false,
+            inspect: false,
+        };
+        let plain = info(&args).unwrap();
+        assert!(plain.starts_with("points: 1000\nbounds: "));
+        assert!(!plain.contains("digest"));
+
+        args.digest = true;
+        let a = info(&args).unwrap();
+        assert!(a.starts_with(&plain));
+        assert!(a.contains("digest: "));
+        assert!(a.contains("  x: "));
+
+        // the same points in other batches
+        args.src = write("b.arrow", 64);
+        assert_eq!(info(&args).unwrap(), a);
+    }
+
+    /// LAS file of 1000 points along a line
+    fn las_file(path: &Path) {
+        use las::Write;
+
+        let mut builder = las::Builder::from((1, 4));
+        builder.point_format = las::point::Format::new(6).unwrap();
+        let mut writer = las::Writer::from_path(path, builder.into_header().unwrap()).unwrap();
+        for i in 0..1000 {
+            let point = las::Point {
+                x: i as f64,
+                y: 2. * i as f64,
+                z: 3.,
+                gps_time: Some(0.),
+                ..Default::default()
+            };
+            writer.write(point).unwrap();
+        }
+        writer.close().unwrap();
+    }
+
+    /// Local file counting the bytes read
+    struct Counting(std::fs::File, std::cell::Cell<u64>);
+
+    impl RangeRead for Counting {
+        fn size(&self) -> std::io::Result<u64> {
+            self.0.size()
+        }
+
+        fn read_range(&self, range: Range<u64>) -> std::io::Result<Vec<u8>> {
+            let bytes = self.0.read_range(range)?;
+            self.1.set(self.1.get() + bytes.len() as u64);
+            Ok(bytes)
+        }
+    }
+
+    #[test]
+    fn objects() {
+        // collections by their manifest
+        let object = Object::open("s3://bucket/collections/city").unwrap();
+        assert_eq!(object.path.as_ref(), "collections/city/MANIFEST.json");
+        let object = Object::open("gs://bucket/huge.las").unwrap();
+        assert_eq!(object.path.as_ref(), "huge.las");
+    }
+
+    #[test]
+    fn inspect() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("line.las");
+        las_file(&path);
+
+        let file = Counting(std::fs::File::open(&path).unwrap(), Default::default());
+        let report = inspect_with(&file).unwrap();
+        assert_eq!(
+            report.num_points,
+            Some(Estimated {
+                value: 1000,
+                exact: true
+            })
+        );
+        assert_eq!(
+            report.bounds.unwrap().value,
+            [[0., 0., 3.], [999., 1998., 3.]]
+        );
+        assert_eq!(report.point_format, Some(6));
+        // of about 30 KB
+        assert!(file.1.get() < 1024);
+
+        // the same through an object store
+        let url = format!("file://{}", path.display());
+        let mut args = InfoArgs {
+            src: url,
+            digest: false,
+            inspect: false,
+        };
+        let out = info(&args).unwrap();
+        assert!(out.starts_with("points: 1000\nbounds: [0.0, 0.0, 3.0] - [999.0, 1998.0, 3.0]\n"));
+        assert!(out.ends_with("format: Las\npoint format: 6\n"));
+
+        args.src = path.to_string_lossy().into_owned();
+        args.inspect = true;
+        assert_eq!(info(&args).unwrap(), out);
+
+        // collections by their manifest
+        let manifest = crux_format::inspect::Manifest {
+            version: 1,
+            segments: Vec::new(),
+            num_points: Some(10),
+            bounds: None,
+            views: Default::default(),
+        };
+        let collection = dir.path().join("grid");
+        std::fs::create_dir(&collection).unwrap();
+        let json = serde_json::to_vec(&manifest).unwrap();
+        std::fs::write(collection.join("MANIFEST.json"), json).unwrap();
+        args.src = format!("file://{}", collection.display());
+        args.inspect = false;
+        assert_eq!(info(&args).unwrap(), "points: 10\nformat: Collection\n");
+
+        args.digest = true;
+        assert!(info(&args).is_err());
+    }
+}
@@ -3,18 +3,20 @@ use std::{
     collections::HashMap,
     fmt::{Debug, Formatter},
     fs::File,
-    io::BufReader,
-    path::PathBuf,
+    io::{BufReader, Cursor},
+    path::{Path, PathBuf},
     sync::Arc,
     thread,
 };
 
 use arrow::{
     array::{
-        ArrayRef, BooleanArray, BooleanBuilder, Float32Array, Float32Builder, Float64Array,
-        Float64Builder, StructArray, UInt16Array, UInt16Builder, UInt8Array, UInt8Builder,
+        Array, ArrayRef, AsArray, BooleanArray, BooleanBuilder, Float32Array, Float32Builder,
+        Float64Array, Float64Builder, PrimitiveArray, StructArray, UInt16Array, UInt16Builder,
+        UInt8Array, UInt8Builder,
     },
-    datatypes::{DataType, Field, Schema, SchemaRef},
+    compute::cast,
+    datatypes::{ArrowPrimitiveType, DataType, Field, Schema, SchemaRef},
     error::ArrowError,
     record_batch::RecordBatch,
 };
@@ -29,15 +31,15 @@ use datafusion::{
         ExecutionPlan, SendableRecordBatchStream,
     },
 };
-use las::{Header, Read};
+use las::{Header, Read, Write};
 use laz::{las::file::read_header_and_vlrs, laszip::ChunkTable};
 use rayon::iter::{
     IndexedParallelIterator, IntoParallelIterator, IntoParallelRefIterator, ParallelIterator,
 };
 
 use crux_format::{
-    schema::{PCE_DIMENSION_KEY, PCE_LOCATION_KEY},
-    PointCloudError,
+    schema::{dimensions, PCE_DIMENSION_KEY, PCE_LOCATION_KEY},
+    ArrowPointCloud, Point, PointCloudError, PointCloudTrait, PointTrait,
 };
 
 use crate::DEFAULT_BATCH_SIZE;
@@ -165,7 +167,8 @@ impl RowBuilder {
         self.scan_angle.append_value(p.scan_angle);
         self.point_source_id.append_value(p.point_source_id);
         if header.point_format().has_gps_time {
-            self.gps_time.append_value(p.gps_time.unwrap());
+            // a time of 0 is read as none
+            self.gps_time.append_value(p.gps_time.unwrap_or(0.));
         }
         if header.point_format().has_color {
             let color = p.color.unwrap();
@@ -307,6 +310,211 @@ impl RowBuilder {
 //     }
 // }
 
+/// Points at the head of a LAS/LAZ file
+#[derive(Debug)]
+pub struct LasHead {
+    pub batch: RecordBatch,
+    /// Point count of the header
+    pub number_of_points: u64,
+    /// Points are compressed (LAZ)
+    pub compressed: bool,
+}
+
+/// Read the points of the file `bytes` up to the first point that cannot be
+/// read, e.g. of the first megabytes of an upload
+pub fn read_head(bytes: &[u8]) -> Result<LasHead, PointCloudError> {
+    let mut reader = las::Reader::new(Cursor::new(bytes))
+        .map_err(|e| PointCloudError::InvalidArgument(format!("invalid LAS file: {e}")))?;
+    let header = reader.header().to_owned();
+    let schema = schema_from_header(&header);
+
+    let capacity = header.number_of_points().min(DEFAULT_BATCH_SIZE as u64);
+    let mut builder = RowBuilder::new(capacity as usize);
+    for point in reader.points().map_while(Result::ok) {
+        builder.append(point, &header);
+    }
+
+    Ok(LasHead {
+        batch: RecordBatch::from(builder.finish(&schema, &header)),
+        number_of_points: header.number_of_points(),
+        compressed: header.point_format().is_compressed,
+    })
+}
+
+/// Write the points of `pc` as LAS 1.4 file, compressed if the extension of
+/// `path` is `laz`.
+///
+/// Attributes are taken from the columns named like those read from LAS
+/// files, missing ones are left at their default. Coordinates are stored in
+/// millimetres relative to the lower corner of the points.
+pub fn write_las<P: AsRef<Path>>(path: P, pc: &ArrowPointCloud) -> Result<(), PointCloudError> {
+    let invalid = |e: las::Error| PointCloudError::InvalidArgument(format!("LAS: {e}"));
+    let schema = pc.schema();
+    let has = |name: &str| schema.column_with_name(name).is_some();
+
+    let mut builder = las::Builder::from((1, 4));
+    builder.point_format = match (has("red") && has("green") && has("blue"), has("nir")) {
+        (true, true) => las::point::Format::new(8),
+        (true, false) => las::point::Format::new(7),
+        _ => las::point::Format::new(6),
+    }
+    .map_err(invalid)?;
+    let lower = pc.aabb::<Point<f64, 3>>().lower();
+    let transform = |d: usize| las::Transform {
+        scale: 0.001,
+        offset: if lower.coords()[d].is_finite() {
+            lower.coords()[d].floor()
+        } else {
+            0.
+        },
+    };
+    builder.transforms = las::Vector {
+        x: transform(0),
+        y: transform(1),
+        z: transform(2),
+    };
+    let header = builder.into_header().map_err(invalid)?;
+    let format = *header.point_format();
+
+    let mut writer = las::Writer::from_path(path, header).map_err(invalid)?;
+    for e in pc.store.iter() {
+        for batch in pc.store.batches(e.key()) {
+            let columns = LasColumns::try_new(&batch)?;
+            for i in 0..batch.num_rows() {
+                writer.write(columns.point(i, &format)).map_err(invalid)?;
+            }
+        }
+    }
+    writer.close().map_err(invalid)?;
+
+    Ok(())
+}
+
+/// Columns of a batch cast to the types of the LAS point attributes
+struct LasColumns {
+    coords: Vec<Float64Array>,
+    intensity: Option<UInt16Array>,
+    return_number: Option<UInt8Array>,
+    number_of_returns: Option<UInt8Array>,
+    is_synthetic: Option<BooleanArray>,
+    is_key_point: Option<BooleanArray>,
+    is_withheld: Option<BooleanArray>,
+    is_overlap: Option<BooleanArray>,
+    scanner_channel: Option<UInt8Array>,
+    is_edge_of_flight_line: Option<BooleanArray>,
+    classification: Option<UInt8Array>,
+    user_data: Option<UInt8Array>,
+    scan_angle: Option<Float32Array>,
+    point_source_id: Option<UInt16Array>,
+    gps_time: Option<Float64Array>,
+    red: Option<UInt16Array>,
+    green: Option<UInt16Array>,
+    blue: Option<UInt16Array>,
+    nir: Option<UInt16Array>,
+}
+
+impl LasColumns {
+    fn try_new(batch: &RecordBatch) -> Result<Self, PointCloudError> {
+        fn column<T: ArrowPrimitiveType>(
+            batch: &RecordBatch,
+            name: &str,
+        ) -> Result<Option<PrimitiveArray<T>>, ArrowError> {
+            batch
+                .column_by_name(name)
+                .map(|c| Ok(cast(c, &T::DATA_TYPE)?.as_primitive::<T>().to_owned()))
+                .transpose()
+        }
+        fn flag(batch: &RecordBatch, name: &str) -> Result<Option<BooleanArray>, ArrowError> {
+            batch
+                .column_by_name(name)
+                .map(|c| Ok(cast(c, &DataType::Boolean)?.as_boolean().to_owned()))
+                .transpose()
+        }
+
+        let coords = dimensions(&batch.schema())
+            .into_iter()
+            .take(3)
+            .map(|c| {
+                Ok(cast(batch.column(c), &DataType::Float64)?
+                    .as_primitive()
+                    .to_owned())
+            })
+            .collect::<Result<Vec<_>, ArrowError>>()?;
+        if coords.len() < 3 {
+            return Err(PointCloudError::SchemaError(
+                "LAS requires three dimensions".to_string(),
+            ));
+        }
+
+        Ok(Self {
+            coords,
+            intensity: column(batch, "intensity")?,
+            return_number: column(batch, "return_number")?,
+            number_of_returns: column(batch, "number_of_returns")?,
+            is_synthetic: flag(batch, "is_synthetic")?,
+            is_key_point: flag(batch, "is_key_point")?,
+            is_withheld: flag(batch, "is_withheld")?,
+            is_overlap: flag(batch, "is_overlap")?,
+            scanner_channel: column(batch, "scanner_channel")?,
+            is_edge_of_flight_line: flag(batch, "is_edge_of_flight_line")?,
+            classification: column(batch, "classification")?,
+            user_data: column(batch, "user_data")?,
+            scan_angle: column(batch, "scan_angle")?,
+            point_source_id: column(batch, "point_source_id")?,
+            gps_time: column(batch, "gps_time")?,
+            red: column(batch, "red")?,
+            green: column(batch, "green")?,
+            blue: column(batch, "blue")?,
+            nir: column(batch, "nir")?,
+        })
+    }
+
+    fn point(&self, i: usize, format: &las::point::Format) -> las::Point {
+        fn value<T: ArrowPrimitiveType>(c: &Option<PrimitiveArray<T>>, i: usize) -> T::Native {
+            c.as_ref()
+                .filter(|c| c.is_valid(i))
+                .map_or_else(T::Native::default, |c| c.value(i))
+        }
+        fn flag(c: &Option<BooleanArray>, i: usize) -> bool {
+            c.as_ref().is_some_and(|c| c.is_valid(i) && c.value(i))
+        }
+
+        // the overlap class of older formats is a flag since LAS 1.4
+        let class = value(&self.classification, i);
+        let (classification, is_overlap) = match las::point::Classification::new(class) {
+            Ok(classification) => (classification, flag(&self.is_overlap, i)),
+            Err(_) => (las::point::Classification::Unclassified, true),
+        };
+
+        las::Point {
+            x: self.coords[0].value(i),
+            y: self.coords[1].value(i),
+            z: self.coords[2].value(i),
+            intensity: value(&self.intensity, i),
+            return_number: value(&self.return_number, i),
+            number_of_returns: value(&self.number_of_returns, i),
+            is_edge_of_flight_line: flag(&self.is_edge_of_flight_line, i),
+            classification,
+            is_synthetic: flag(&self.is_synthetic, i),
+            is_key_point: flag(&self.is_key_point, i),
+            is_withheld: flag(&self.is_withheld, i),
+            is_overlap,
+            scanner_channel: value(&self.scanner_channel, i),
+            scan_angle: value(&self.scan_angle, i),
+            user_data: value(&self.user_data, i),
+            point_source_id: value(&self.point_source_id, i),
+            gps_time: format.has_gps_time.then(|| value(&self.gps_time, i)),
+            color: format.has_color.then(|| las::Color {
+                red: value(&self.red, i),
+                green: value(&self.green, i),
+                blue: value(&self.blue, i),
+            }),
+            nir: format.has_nir.then(|| value(&self.nir, i)),
+            ..Default::default()
+        }
+    }
+}
+
 /// A custom datasource, used to represent a datastore with a single index
 #[derive(Clone)]
 pub struct LasDataSource {
@@ -380,7 +588,7 @@ impl LasDataSource {
 
                                 let res = (i, offset, num_points);
                                 offset += chunck_size;
-                                total_points -= chunck_size;
+                                total_points -= num_points;
                                 res
                             })
                             .collect::<Vec<_>>()
@@ -555,10 +763,40 @@ fn into_iter<T: Send + 'static>(
 #[cfg(test)]
 mod tests {
 
-    use crux_format::{ArrowPointCloud, Point, PointCloudTrait};
-
     use super::*;
 
+    #[test]
+    fn head() {
+        let mut builder = las::Builder::from((1, 2));
+        builder.point_format = las::point::Format::new(1).unwrap();
+        let mut writer =
+            las::Writer::new(Cursor::new(Vec::new()), builder.into_header().unwrap()).unwrap();
+        for i in 0..100 {
+            writer
+                .write(las::Point {
+                    x: i as f64,
+                    y: 2. * i as f64,
+                    z: 1.,
+                    gps_time: Some(i as f64),
+                    ..Default::default()
+                })
+                .unwrap();
+        }
+        let bytes = writer.into_inner().unwrap().into_inner();
+
+        let head = read_head(&bytes).unwrap();
+        assert_eq!(head.number_of_points, 100);
+        assert_eq!(head.batch.num_rows(), 100);
+        assert!(head.batch.schema().column_with_name("gps_time").is_some());
+
+        // cut within the points
+        let head = read_head(&bytes[..bytes.len() - 10 * 28 - 5]).unwrap();
+        assert_eq!(head.number_of_points, 100);
+        assert_eq!(head.batch.num_rows(), 89);
+
+        assert!(read_head(&bytes[..50]).is_err());
+    }
+
     #[test]
     fn count_points() {
         let filenames = [
@@ -672,9 +910,7 @@ mod tests {
 
         println!(
             "{}",
-            arrow::util::pretty::pretty_format_batches(&results)
-                .unwrap()
-                .to_string()
+            arrow::util::pretty::pretty_format_batches(&results).unwrap()
         );
     }
 }
@@ -1,7 +1,14 @@
 pub mod convert;
+pub mod diff;
+pub mod info;
 pub mod las;
 pub mod parquet;
 pub mod ply;
+pub mod problem;
+pub mod table;
+pub mod tile;
+pub mod upload;
+pub mod view;
 
 pub const DEFAULT_BATCH_SIZE: usize = 1024 * 1024;
 
@@ -44,7 +51,7 @@ impl TryFrom<&std::ffi::OsStr> for FormatExt {
 
     fn try_from(value: &std::ffi::OsStr) -> Result<Self, Self::Error> {
         match value.to_ascii_lowercase().to_str() {
-            Some("arrow") | Some("ipc") => Ok(FormatExt::IPC),
+            Some("arrow") | Some("ipc") | Some("feather") => Ok(FormatExt::IPC),
             Some("las") => Ok(FormatExt::LAS),
             Some("laz") => Ok(FormatExt::LAZ),
             Some("parquet") => Ok(FormatExt::Parquet),
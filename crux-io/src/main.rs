@@ -1,3 +1,5 @@
+use std::io::Write;
+
 use clap::{Parser, Subcommand};
 use rayon::prelude::{IntoParallelRefIterator, ParallelIterator};
 
@@ -13,6 +15,16 @@ struct Cli {
 enum Commands {
     /// Point cloud format conversion
     Convert(crux_io::convert::ConversionArgs),
+    /// Upload a point cloud to a server, or validate it with `--dry-run`
+    Upload(crux_io::upload::UploadArgs),
+    /// Split a point cloud into square tiles, one file per tile
+    Tile(crux_io::tile::TileArgs),
+    /// Number of points, bounds and columns of a point cloud
+    Info(crux_io::info::InfoArgs),
+    /// Changes between two point clouds by their digests, exits with 1 if they differ
+    Diff(crux_io::diff::DiffArgs),
+    /// Create, list and run named queries of a collection on a server
+    View(crux_io::view::ViewArgs),
 }
 
 fn main() {
@@ -23,6 +35,52 @@ fn main() {
             crux_io::convert::convert(src, args.dst.as_ref(), args.overwrite, args.importance)
                 .unwrap();
         }),
+        Some(Commands::Upload(args)) => match crux_io::upload::upload(args) {
+            Ok(response) => println!("{response}"),
+            Err(e) => {
+                eprintln!("Upload failed: {e}");
+                std::process::exit(1)
+            }
+        },
+        Some(Commands::Tile(args)) => match crux_io::tile::tile(args) {
+            Ok(written) => {
+                for (path, points) in written {
+                    println!("{}: {points} points", path.display());
+                }
+            }
+            Err(e) => {
+                eprintln!("Tiling failed: {e}");
+                std::process::exit(1)
+            }
+        },
+        Some(Commands::Info(args)) => match crux_io::info::info(args) {
+            Ok(info) => print!("{info}"),
+            Err(e) => {
+                eprintln!("Reading failed: {e}");
+                std::process::exit(1)
+            }
+        },
+        Some(Commands::Diff(args)) => match crux_io::diff::diff(args) {
+            Ok(summary) => {
+                print!("{summary}");
+                if !summary.is_unchanged() {
+                    std::process::exit(1)
+                }
+            }
+            Err(e) => {
+                eprintln!("Comparing failed: {e}");
+                std::process::exit(2)
+            }
+        },
+        Some(Commands::View(args)) => match crux_io::view::view(args) {
+            Ok(body) => {
+                std::io::stdout().write_all(&body).unwrap();
+            }
+            Err(e) => {
+                eprintln!("View request failed: {e}");
+                std::process::exit(1)
+            }
+        },
         None => {}
     }
 }
@@ -324,7 +324,6 @@ fn element_from_row(i: usize, batch: &RecordBatch) -> DefaultElement {
 
 #[cfg(test)]
 mod tests {
-    use rstar::Envelope;
 
     use crux_format::{ArrowPointCloud, Point, PointCloudTrait, PointTrait, AABB};
 
@@ -395,7 +394,7 @@ mod tests {
 
         // write
         let mut writer = PlyWriter::new("../data/sofa_transformed.ply", pc.schema());
-        for batch in pc.store.iter().map(|e| pc.store.batches(e.key())).flatten() {
+        for batch in pc.store.iter().flat_map(|e| pc.store.batches(e.key())) {
             writer.write(&batch).unwrap();
         }
         writer.close().unwrap();
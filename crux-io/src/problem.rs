@@ -0,0 +1,100 @@
+//! Error responses of the server as RFC 7807 problem details.
+//!
+//! Errors are answered with an `application/problem+json` body, the `type`
+//! tells the kind of error, e.g. `crux:unknown-collection`, and the `detail`
+//! explains it. Parameters of the request relevant to the error are echoed
+//! as further members, e.g. the unknown `collection`.
+
+use std::{error::Error, fmt};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+/// Content type of problem details
+pub const PROBLEM_CONTENT_TYPE: &str = "application/problem+json";
+
+/// Problem details of an error response
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Problem {
+    /// Kind of the error, e.g. `crux:invalid-bounds`
+    #[serde(rename = "type")]
+    pub kind: String,
+    /// Summary of the kind of the error
+    pub title: String,
+    /// HTTP status code
+    pub status: u16,
+    /// Explanation of this occurrence of the error
+    pub detail: String,
+    /// Parameters of the request relevant to the error
+    #[serde(flatten)]
+    pub params: Map<String, Value>,
+}
+
+impl Problem {
+    /// Problem details of an error response body, `None` for other bodies
+    pub fn parse(body: &[u8]) -> Option<Self> {
+        serde_json::from_slice(body).ok()
+    }
+
+    /// Detail of an error response, the body itself if it is not a problem
+    pub fn detail(status: u16, body: &[u8]) -> String {
+        match Self::parse(body) {
+            Some(problem) => problem.detail,
+            None => {
+                let body = String::from_utf8_lossy(body);
+                match body.trim() {
+                    "" => format!("status {status}"),
+                    body => body.to_owned(),
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.detail)
+    }
+}
+
+impl Error for Problem {}
+
+/// `response` if successful, the detail of the error response otherwise
+pub async fn check(response: reqwest::Response) -> Result<reqwest::Response, Box<dyn Error>> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let body = response.bytes().await?;
+    Err(format!("{status}: {}", Problem::detail(status.as_u16(), &body)).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let body = br#"{
+            "type": "crux:unknown-collection",
+            "title": "Unknown collection",
+            "status": 404,
+            "detail": "no collection `missing`",
+            "collection": "missing"
+        }"#;
+        let problem = Problem::parse(body).unwrap();
+        assert_eq!(problem.kind, "crux:unknown-collection");
+        assert_eq!(problem.status, 404);
+        assert_eq!(problem.params["collection"], "missing");
+        assert_eq!(problem.to_string(), "no collection `missing`");
+        assert_eq!(
+            serde_json::to_value(&problem).unwrap()["type"],
+            "crux:unknown-collection"
+        );
+
+        // plain bodies of other servers
+        assert_eq!(Problem::detail(404, body), "no collection `missing`");
+        assert_eq!(Problem::detail(502, b"Bad Gateway\n"), "Bad Gateway");
+        assert_eq!(Problem::detail(500, b""), "status 500");
+    }
+}
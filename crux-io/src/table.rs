@@ -0,0 +1,465 @@
+use std::{
+    any::Any,
+    fmt::{Debug, Formatter},
+    sync::Arc,
+};
+
+use arrow::{
+    datatypes::{DataType, SchemaRef},
+    record_batch::RecordBatch,
+};
+use crux_format::{ArrowPointCloud, Point, PointCloudTrait, PointTrait, AABB};
+use datafusion::{
+    common::ScalarValue,
+    datasource::TableProvider,
+    error::DataFusionError,
+    execution::{context::SessionState, TaskContext},
+    logical_expr::{Between, BinaryExpr, Expr, Operator, TableProviderFilterPushDown, TableType},
+    physical_expr::PhysicalSortExpr,
+    physical_plan::{
+        metrics::{ExecutionPlanMetricsSet, MetricBuilder, MetricsSet},
+        project_schema,
+        stream::RecordBatchStreamAdapter,
+        DisplayAs, DisplayFormatType, ExecutionPlan, Partitioning, SendableRecordBatchStream,
+    },
+};
+
+/// Name of the metric of batches read by a scan
+pub const BATCHES_SCANNED: &str = "batches_scanned";
+/// Name of the metric of batches skipped by their bounds
+pub const BATCHES_PRUNED: &str = "batches_pruned";
+
+/// Values of a dimension that satisfy the pushed down filters
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Range {
+    lower: f64,
+    upper: f64,
+    /// Whether the bounds themselves are excluded
+    lower_open: bool,
+    upper_open: bool,
+}
+
+impl Range {
+    const ALL: Self = Self::closed(f64::MIN, f64::MAX);
+
+    const fn closed(lower: f64, upper: f64) -> Self {
+        Self {
+            lower,
+            upper,
+            lower_open: false,
+            upper_open: false,
+        }
+    }
+
+    fn intersect(self, other: Self) -> Self {
+        let tighter_lower =
+            other.lower > self.lower || (other.lower == self.lower && other.lower_open);
+        let tighter_upper =
+            other.upper < self.upper || (other.upper == self.upper && other.upper_open);
+        let (lower, lower_open) = match tighter_lower {
+            true => (other.lower, other.lower_open),
+            false => (self.lower, self.lower_open),
+        };
+        let (upper, upper_open) = match tighter_upper {
+            true => (other.upper, other.upper_open),
+            false => (self.upper, self.upper_open),
+        };
+        Self {
+            lower,
+            upper,
+            lower_open,
+            upper_open,
+        }
+    }
+
+    /// Whether values within `[min, max]` may satisfy the filters
+    fn overlaps(&self, min: f64, max: f64) -> bool {
+        let above = match self.lower_open {
+            true => max > self.lower,
+            false => max >= self.lower,
+        };
+        let below = match self.upper_open {
+            true => min < self.upper,
+            false => min <= self.upper,
+        };
+        above && below
+    }
+}
+
+/// Ranges of x, y, z and importance
+type Ranges = [Range; 4];
+
+/// DataFusion table of a point cloud.
+///
+/// Range predicates on dimensions (`x >= 10`, `z BETWEEN 0 AND 5`) skip the
+/// batches outside of their bounds, projections are applied per batch.
+///
+/// ```ignore
+/// let ctx = SessionContext::new();
+/// ctx.register_table("lidar", Arc::new(PointCloudTable::new(pc)))?;
+/// ctx.sql("SELECT classification, count(*) FROM lidar WHERE x BETWEEN 0 AND 100 GROUP BY 1").await?;
+/// ```
+#[derive(Clone)]
+pub struct PointCloudTable {
+    pc: Arc<ArrowPointCloud>,
+}
+
+impl Debug for PointCloudTable {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PointCloudTable")
+            .field("schema", &self.pc.schema())
+            .finish()
+    }
+}
+
+impl PointCloudTable {
+    pub fn new(pc: Arc<ArrowPointCloud>) -> Self {
+        Self { pc }
+    }
+
+    /// Dimension of a column, if it is one
+    fn dimension(&self, column: &str) -> Option<usize> {
+        let schema = self.pc.schema();
+        let index = schema.index_of(column).ok()?;
+        crux_format::schema::dimensions(&schema)
+            .iter()
+            .take(4)
+            .position(|i| *i == index)
+    }
+
+    /// Dimension and range of a range predicate on a dimension
+    fn range(&self, filter: &Expr) -> Option<(usize, Range)> {
+        match filter {
+            Expr::BinaryExpr(BinaryExpr { left, op, right }) => {
+                let (column, op, value) = match (left.as_ref(), right.as_ref()) {
+                    (Expr::Column(column), value) => (column, *op, value),
+                    (value, Expr::Column(column)) => (column, op.swap()?, value),
+                    _ => return None,
+                };
+                let d = self.dimension(&column.name)?;
+                let value = literal(value)?;
+                let range = match op {
+                    Operator::Eq => Range::closed(value, value),
+                    Operator::LtEq => Range::closed(f64::MIN, value),
+                    Operator::GtEq => Range::closed(value, f64::MAX),
+                    Operator::Lt => Range {
+                        upper_open: true,
+                        ..Range::closed(f64::MIN, value)
+                    },
+                    Operator::Gt => Range {
+                        lower_open: true,
+                        ..Range::closed(value, f64::MAX)
+                    },
+                    _ => return None,
+                };
+                Some((d, range))
+            }
+            Expr::Between(Between {
+                expr,
+                negated: false,
+                low,
+                high,
+            }) => match expr.as_ref() {
+                Expr::Column(column) => Some((
+                    self.dimension(&column.name)?,
+                    Range::closed(literal(low)?, literal(high)?),
+                )),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Intersection of the ranges of the filters, non-range filters do not
+    /// restrict it
+    fn ranges(&self, filters: &[Expr]) -> Ranges {
+        let mut ranges = [Range::ALL; 4];
+        for (d, range) in filters.iter().filter_map(|f| self.range(f)) {
+            ranges[d] = ranges[d].intersect(range);
+        }
+        ranges
+    }
+}
+
+/// Numeric value of a literal, also through casts
+fn literal(expr: &Expr) -> Option<f64> {
+    match expr {
+        Expr::Literal(value) => match value.cast_to(&DataType::Float64).ok()? {
+            ScalarValue::Float64(value) => value,
+            _ => None,
+        },
+        Expr::Cast(cast) => literal(&cast.expr),
+        _ => None,
+    }
+}
+
+#[async_trait::async_trait]
+impl TableProvider for PointCloudTable {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn schema(&self) -> SchemaRef {
+        self.pc.schema()
+    }
+
+    fn table_type(&self) -> TableType {
+        TableType::Base
+    }
+
+    fn supports_filters_pushdown(
+        &self,
+        filters: &[&Expr],
+    ) -> datafusion::common::Result<Vec<TableProviderFilterPushDown>> {
+        // batches are pruned, not filtered
+        Ok(filters
+            .iter()
+            .map(|f| match self.range(f) {
+                Some(_) => TableProviderFilterPushDown::Inexact,
+                None => TableProviderFilterPushDown::Unsupported,
+            })
+            .collect())
+    }
+
+    async fn scan(
+        &self,
+        _state: &SessionState,
+        projection: Option<&Vec<usize>>,
+        filters: &[Expr],
+        limit: Option<usize>,
+    ) -> datafusion::common::Result<Arc<dyn ExecutionPlan>> {
+        Ok(Arc::new(PointCloudExec::try_new(
+            self.pc.clone(),
+            projection,
+            self.ranges(filters),
+            limit,
+        )?))
+    }
+}
+
+/// Scan of the batches of a point cloud within the ranges of the filters
+pub struct PointCloudExec {
+    pc: Arc<ArrowPointCloud>,
+    projection: Option<Vec<usize>>,
+    projected_schema: SchemaRef,
+    ranges: Ranges,
+    limit: Option<usize>,
+    metrics: ExecutionPlanMetricsSet,
+}
+
+impl PointCloudExec {
+    fn try_new(
+        pc: Arc<ArrowPointCloud>,
+        projection: Option<&Vec<usize>>,
+        ranges: Ranges,
+        limit: Option<usize>,
+    ) -> datafusion::common::Result<Self> {
+        let projected_schema = project_schema(&pc.schema(), projection)?;
+        Ok(Self {
+            pc,
+            projection: projection.cloned(),
+            projected_schema,
+            ranges,
+            limit,
+            metrics: ExecutionPlanMetricsSet::new(),
+        })
+    }
+}
+
+impl Debug for PointCloudExec {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PointCloudExec")
+            .field("projection", &self.projection)
+            .field("ranges", &self.ranges)
+            .field("limit", &self.limit)
+            .finish()
+    }
+}
+
+impl DisplayAs for PointCloudExec {
+    fn fmt_as(&self, _t: DisplayFormatType, f: &mut Formatter) -> std::fmt::Result {
+        write!(f, "PointCloudExec: ranges={:?}", self.ranges)
+    }
+}
+
+impl ExecutionPlan for PointCloudExec {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn schema(&self) -> SchemaRef {
+        self.projected_schema.clone()
+    }
+
+    fn output_partitioning(&self) -> Partitioning {
+        Partitioning::UnknownPartitioning(1)
+    }
+
+    fn output_ordering(&self) -> Option<&[PhysicalSortExpr]> {
+        None
+    }
+
+    fn children(&self) -> Vec<Arc<dyn ExecutionPlan>> {
+        vec![]
+    }
+
+    fn with_new_children(
+        self: Arc<Self>,
+        _: Vec<Arc<dyn ExecutionPlan>>,
+    ) -> datafusion::common::Result<Arc<dyn ExecutionPlan>> {
+        Ok(self)
+    }
+
+    fn execute(
+        &self,
+        partition: usize,
+        _context: Arc<TaskContext>,
+    ) -> datafusion::common::Result<SendableRecordBatchStream> {
+        let scanned = MetricBuilder::new(&self.metrics).counter(BATCHES_SCANNED, partition);
+        let pruned = MetricBuilder::new(&self.metrics).counter(BATCHES_PRUNED, partition);
+
+        let ranges = self.ranges;
+        let dims = crux_format::schema::dimensions(&self.pc.schema())
+            .len()
+            .min(4);
+
+        let pc = self.pc.clone();
+        let projection = self.projection.clone();
+        let mut remaining = self.limit.unwrap_or(usize::MAX);
+
+        let batches = pc
+            .store
+            .iter()
+            .flat_map(move |entry| pc.store.batches(entry.key()))
+            .filter(move |batch: &RecordBatch| {
+                if intersects(&ranges, batch, dims) {
+                    scanned.add(1);
+                    true
+                } else {
+                    pruned.add(1);
+                    false
+                }
+            })
+            .take_while(move |batch| {
+                let more = remaining > 0;
+                remaining = remaining.saturating_sub(batch.num_rows());
+                more
+            })
+            .map(move |batch| {
+                match &projection {
+                    Some(indices) => batch.project(indices),
+                    None => Ok(batch),
+                }
+                .map_err(|e| DataFusionError::ArrowError(e, None))
+            });
+
+        Ok(Box::pin(RecordBatchStreamAdapter::new(
+            self.schema(),
+            futures::stream::iter(batches),
+        )))
+    }
+
+    fn metrics(&self) -> Option<MetricsSet> {
+        Some(self.metrics.clone_inner())
+    }
+}
+
+/// Whether the bounds of a batch overlap the ranges in the first `dims`
+/// dimensions
+fn intersects(ranges: &Ranges, batch: &RecordBatch, dims: usize) -> bool {
+    let envelope: AABB<Point<f64, 4>> = crux_format::compute::aabb(batch);
+    let (lower, upper) = (envelope.lower(), envelope.upper());
+    (0..dims).all(|d| ranges[d].overlaps(lower.coords()[d], upper.coords()[d]))
+}
+
+#[cfg(test)]
+mod tests {
+    use datafusion::{execution::context::SessionContext, physical_plan::collect};
+
+    use super::*;
+
+    /// Point cloud of 10 batches of 10 points, batch `i` spans x in
+    /// `[10i, 10i + 9]`
+    fn grid() -> Arc<ArrowPointCloud> {
+        let mut pc = ArrowPointCloud::try_new(Point::<f64, 3>::schema()).unwrap();
+        for i in 0..10 {
+            let batch = ArrowPointCloud::from_iter(
+                (0..10).map(|j| Point::<f64, 3>::from_slice(&[(i * 10 + j) as f64, j as f64, 0.])),
+            )
+            .unwrap();
+            for entry in batch.store.iter() {
+                for batch in batch.store.batches(entry.key()) {
+                    pc.append(batch).unwrap();
+                }
+            }
+        }
+        Arc::new(pc)
+    }
+
+    /// Rows and scanned and pruned batches of a query
+    async fn run(sql: &str) -> (usize, usize, usize) {
+        let ctx = SessionContext::new();
+        ctx.register_table("lidar", Arc::new(PointCloudTable::new(grid())))
+            .unwrap();
+
+        let plan = ctx
+            .sql(sql)
+            .await
+            .unwrap()
+            .create_physical_plan()
+            .await
+            .unwrap();
+        let batches = collect(plan.clone(), ctx.task_ctx()).await.unwrap();
+        let rows = batches.iter().map(RecordBatch::num_rows).sum();
+
+        let mut metrics = None;
+        let mut stack = vec![plan];
+        while let Some(plan) = stack.pop() {
+            if plan.as_any().is::<PointCloudExec>() {
+                metrics = plan.metrics();
+            }
+            stack.extend(plan.children());
+        }
+        let metrics = metrics.expect("scan in plan");
+        let count = |name| metrics.sum_by_name(name).map_or(0, |v| v.as_usize());
+
+        (rows, count(BATCHES_SCANNED), count(BATCHES_PRUNED))
+    }
+
+    #[tokio::test]
+    async fn pruning() {
+        assert_eq!(run("SELECT * FROM lidar").await, (100, 10, 0));
+
+        // bounds equivalent predicates only scan the intersecting batches
+        let (rows, scanned, pruned) = run("SELECT x FROM lidar WHERE x >= 20 AND x < 30").await;
+        assert_eq!((rows, scanned, pruned), (10, 1, 9));
+        let sql = "SELECT * FROM lidar WHERE x BETWEEN 25 AND 44 AND y <= 2";
+        assert_eq!(run(sql).await, (6, 3, 7));
+        let sql = "SELECT * FROM lidar WHERE 50 > x";
+        assert_eq!(run(sql).await, (50, 5, 5));
+
+        // other predicates are evaluated on all batches
+        let sql = "SELECT * FROM lidar WHERE x + y > 90";
+        assert_eq!(run(sql).await.1, 10);
+        let sql = "SELECT * FROM lidar WHERE x > 90 OR x < 5";
+        assert_eq!(run(sql).await.1, 10);
+    }
+
+    #[tokio::test]
+    async fn aggregate() {
+        let ctx = SessionContext::new();
+        ctx.register_table("lidar", Arc::new(PointCloudTable::new(grid())))
+            .unwrap();
+
+        let batches = ctx
+            .sql("SELECT y, count(*) AS n FROM lidar WHERE x < 30 GROUP BY y ORDER BY y")
+            .await
+            .unwrap()
+            .collect()
+            .await
+            .unwrap();
+        let rows: usize = batches.iter().map(RecordBatch::num_rows).sum();
+        assert_eq!(rows, 10);
+        assert_eq!(batches[0].schema().field(1).name(), "n");
+    }
+}
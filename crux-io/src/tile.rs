@@ -0,0 +1,160 @@
+use std::{
+    error::Error,
+    fs::File,
+    path::{Path, PathBuf},
+};
+
+use arrow::ipc::writer::FileWriter;
+
+use crux_format::{ArrowPointCloud, GridTileId, PointCloudTrait};
+
+use crate::{
+    las::{write_las, LasDataSource},
+    ply::PlyReader,
+    FormatExt, PointCloudReader,
+};
+
+#[derive(clap::Args, Debug)]
+pub struct TileArgs {
+    /// Point cloud to split (LAS, LAZ, PLY or Arrow IPC)
+    pub src: String,
+    /// Edge length of the square tiles
+    #[arg(long)]
+    pub size: f64,
+    /// Lower left corner of the tile grid
+    #[arg(long, value_delimiter = ',', num_args = 2, default_value = "0,0")]
+    pub origin: Vec<f64>,
+    /// Directory the tiles are written to
+    #[arg(long)]
+    pub out_dir: PathBuf,
+    /// Format of the tiles, LAS for LAS and LAZ sources, Arrow IPC otherwise
+    #[arg(long)]
+    pub format: Option<FormatExt>,
+}
+
+/// Split `args.src` into tiles, one file per tile named by its lower left
+/// corner, and return the number of points per written file
+pub fn tile(args: &TileArgs) -> Result<Vec<(PathBuf, usize)>, Box<dyn Error>> {
+    let src = Path::new(&args.src);
+    let input: FormatExt = src.extension().ok_or("missing extension")?.try_into()?;
+    let format = match (&args.format, &input) {
+        (Some(format), _) => format.clone(),
+        (None, FormatExt::LAS | FormatExt::LAZ) => FormatExt::LAS,
+        (None, _) => FormatExt::IPC,
+    };
+    if !matches!(format, FormatExt::IPC | FormatExt::LAS | FormatExt::LAZ) {
+        return Err(format!("cannot write {format:?} tiles").into());
+    }
+
+    let pc = read(src, &input)?;
+    let origin = [args.origin[0], args.origin[1]];
+
+    std::fs::create_dir_all(&args.out_dir)?;
+    let mut written = Vec::new();
+    for (id, tile) in pc.crop_tiles(origin, args.size)? {
+        let path = args.out_dir.join(file_name(id, origin, args.size, &format));
+        match format {
+            FormatExt::IPC => {
+                let mut writer = FileWriter::try_new(File::create(&path)?, &tile.schema())?;
+                for e in tile.store.iter() {
+                    for batch in tile.store.batches(e.key()) {
+                        writer.write(&batch)?;
+                    }
+                }
+                writer.finish()?;
+            }
+            _ => write_las(&path, &tile)?,
+        }
+        written.push((path, tile.num_points()));
+    }
+
+    Ok(written)
+}
+
+/// Lower left corner of the tile
+fn file_name(id: GridTileId, origin: [f64; 2], size: f64, format: &FormatExt) -> String {
+    let ([x, y], _) = id.bounds(origin, size);
+    format!("{x}_{y}.{}", format.as_ref().to_string_lossy())
+}
+
+pub(crate) fn read(src: &Path, format: &FormatExt) -> Result<ArrowPointCloud, Box<dyn Error>> {
+    let pc = match format {
+        FormatExt::LAS | FormatExt::LAZ => {
+            let reader = LasDataSource::try_new(&[src.to_string_lossy()])?;
+            let mut pc = ArrowPointCloud::try_new(reader.schema())?;
+            for batch in reader.record_batch_iter() {
+                pc.append(batch?)?;
+            }
+            pc
+        }
+        FormatExt::PLY => PlyReader::from_path(src)?.record_batch_reader().into(),
+        FormatExt::IPC => ArrowPointCloud::from_ipc_path(src)?,
+        FormatExt::Parquet => return Err("cannot read Parquet files".into()),
+    };
+
+    Ok(pc)
+}
+
+#[cfg(test)]
+mod tests {
+    use crux_format::{synthetic::Synthetic, Point, PointTrait};
+
+    use super::*;
+
+    #[test]
+    fn split() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("survey.arrow");
+        let pc = Synthetic::new(5000).intensity(true).terrain().unwrap();
+        let mut writer = FileWriter::try_new(File::create(&src).unwrap(), &pc.schema()).unwrap();
+        for e in pc.store.iter() {
+            for batch in pc.store.batches(e.key()) {
+                writer.write(&batch).unwrap();
+            }
+        }
+        writer.finish().unwrap();
+
+        let mut args = TileArgs {
+            src: src.to_string_lossy().into_owned(),
+            size: 50.,
+            origin: vec![0., 0.],
+            out_dir: dir.path().join("tiles"),
+            format: None,
+        };
+        let written = tile(&args).unwrap();
+        let names: Vec<_> = written
+            .iter()
+            .map(|(path, _)| path.file_name().unwrap().to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(
+            names,
+            ["0_0.arrow", "0_50.arrow", "50_0.arrow", "50_50.arrow"]
+        );
+        assert_eq!(written.iter().map(|(_, n)| n).sum::<usize>(), 5000);
+
+        // read back
+        let first = read(&written[0].0, &FormatExt::IPC).unwrap();
+        assert_eq!(first.num_points(), written[0].1);
+        assert_eq!(first.schema(), pc.schema());
+
+        // LAS tiles keep the coordinates to the millimetre and the attributes
+        args.format = Some(FormatExt::LAS);
+        args.out_dir = dir.path().join("las");
+        let written = tile(&args).unwrap();
+        assert_eq!(written.iter().map(|(_, n)| n).sum::<usize>(), 5000);
+
+        let las = read(&written[0].0, &FormatExt::LAS).unwrap();
+        assert_eq!(las.num_points(), written[0].1);
+        let arrow = read(&dir.path().join("tiles/0_0.arrow"), &FormatExt::IPC).unwrap();
+        for (a, b) in las
+            .points::<Point<f64, 3>>()
+            .zip(arrow.points::<Point<f64, 3>>())
+        {
+            assert!((0..3).all(|d| (a.coords()[d] - b.coords()[d]).abs() <= 0.0005));
+        }
+        assert!(las.schema().column_with_name("intensity").is_some());
+
+        args.format = Some(FormatExt::PLY);
+        assert!(tile(&args).is_err());
+    }
+}
@@ -0,0 +1,155 @@
+use std::{error::Error, fs::File, io::Read, path::Path};
+
+use arrow::{
+    datatypes::SchemaRef,
+    error::ArrowError,
+    ipc::writer::StreamWriter,
+    record_batch::{RecordBatch, RecordBatchIterator, RecordBatchReader},
+};
+
+use crate::{las::LasDataSource, ply::PlyReader, problem, FormatExt, PointCloudReader};
+
+#[derive(clap::Args, Debug)]
+pub struct UploadArgs {
+    /// File to upload (LAS, LAZ or PLY, CSV and Arrow IPC streams are validated only)
+    pub src: String,
+    /// Server base url
+    #[arg(long, default_value = "http://0.0.0.0:3000")]
+    pub server: String,
+    /// Collection, defaults to the file stem
+    #[arg(short, long)]
+    pub collection: Option<String>,
+    /// Validate the head of the file instead, nothing is stored
+    #[arg(long)]
+    pub dry_run: bool,
+    /// MiB of the file validated by dry runs
+    #[arg(long, default_value_t = 16)]
+    pub sample_size: u64,
+}
+
+impl UploadArgs {
+    fn collection(&self) -> String {
+        self.collection.clone().unwrap_or_else(|| {
+            Path::new(&self.src)
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .unwrap_or_else(|| "default".to_string())
+        })
+    }
+
+    /// Validation request of a file of `size` bytes
+    fn validate_url(&self, size: u64) -> String {
+        let mut url = format!(
+            "{}/collections/{}/validate?size={size}",
+            self.server.trim_end_matches('/'),
+            self.collection()
+        );
+        let extension = Path::new(&self.src)
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_ascii_lowercase());
+        if let Some(format) = match extension.as_deref() {
+            Some("arrow") | Some("ipc") => Some("arrow"),
+            Some(ext @ ("las" | "laz" | "csv")) => Some(ext),
+            _ => None,
+        } {
+            url += &format!("&format={format}");
+        }
+        url
+    }
+}
+
+/// Upload a file to the server, or validate its head for dry runs, and
+/// return the response
+pub fn upload(args: &UploadArgs) -> Result<String, Box<dyn Error>> {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    let client = reqwest::Client::new();
+
+    let request = if args.dry_run {
+        let file = File::open(&args.src)?;
+        let size = file.metadata()?.len();
+        let mut head = Vec::new();
+        file.take(args.sample_size * 1024 * 1024)
+            .read_to_end(&mut head)?;
+
+        client.post(args.validate_url(size)).body(head)
+    } else {
+        let url = format!(
+            "{}/load?collection={}",
+            args.server.trim_end_matches('/'),
+            args.collection()
+        );
+        client.post(url).body(stream(&args.src)?)
+    };
+
+    runtime.block_on(async move {
+        let response = problem::check(request.send().await?).await?;
+        Ok(response.text().await?)
+    })
+}
+
+/// Points of `src` as Arrow IPC stream, as expected by `/load`
+fn stream(src: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+    let format: FormatExt = Path::new(src)
+        .extension()
+        .ok_or("missing extension")?
+        .try_into()?;
+
+    let mut ply;
+    let reader: Box<dyn RecordBatchReader + '_> = match format {
+        FormatExt::LAS | FormatExt::LAZ => {
+            let reader = LasDataSource::try_new(&[src])?;
+            let schema = reader.schema();
+            Box::new(RecordBatchIterator::new(reader.record_batch_iter(), schema))
+        }
+        FormatExt::PLY => {
+            ply = PlyReader::from_path(src)?;
+            Box::new(ply.record_batch_reader())
+        }
+        format => return Err(format!("cannot upload {format:?} files").into()),
+    };
+
+    write_stream(reader.schema(), reader)
+}
+
+fn write_stream(
+    schema: SchemaRef,
+    batches: impl Iterator<Item = Result<RecordBatch, ArrowError>>,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut writer = StreamWriter::try_new(Vec::new(), &schema)?;
+    for batch in batches {
+        writer.write(&batch?)?;
+    }
+    Ok(writer.into_inner()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validate_url() {
+        let args = UploadArgs {
+            src: "./data/AHN3/C_69AZ1.LAZ".to_string(),
+            server: "http://localhost:3000/".to_string(),
+            collection: None,
+            dry_run: true,
+            sample_size: 16,
+        };
+        assert_eq!(
+            args.validate_url(1000),
+            "http://localhost:3000/collections/C_69AZ1/validate?size=1000&format=laz"
+        );
+
+        let args = UploadArgs {
+            src: "points.txt".to_string(),
+            collection: Some("ahn".to_string()),
+            ..args
+        };
+        assert_eq!(
+            args.validate_url(1000),
+            "http://localhost:3000/collections/ahn/validate?size=1000"
+        );
+    }
+}
@@ -0,0 +1,133 @@
+use std::error::Error;
+
+use reqwest::Method;
+
+use crate::problem;
+
+#[derive(clap::Args, Debug)]
+pub struct ViewArgs {
+    #[command(subcommand)]
+    pub command: ViewCommand,
+    /// Server base url
+    #[arg(long, global = true, default_value = "http://0.0.0.0:3000")]
+    pub server: String,
+}
+
+#[derive(clap::Subcommand, Debug)]
+pub enum ViewCommand {
+    /// Define a named query of a collection, or redefine it
+    Create {
+        collection: String,
+        view: String,
+        /// Parameters of `/collections/{name}/points` as JSON object, e.g.
+        /// `{"filter":"classification=2"}`
+        query: String,
+    },
+    /// Views of a collection
+    List { collection: String },
+    /// Points of a view as Arrow IPC stream, or as JSON with `format=json`
+    Run {
+        collection: String,
+        view: String,
+        /// Parameters overriding those of the view, e.g. `p=0.1&format=json`
+        #[arg(long)]
+        with: Option<String>,
+    },
+}
+
+impl ViewArgs {
+    /// Method, url and body of the request
+    fn request(&self) -> (Method, String, Option<String>) {
+        let server = self.server.trim_end_matches('/');
+        match &self.command {
+            ViewCommand::Create {
+                collection,
+                view,
+                query,
+            } => (
+                Method::PUT,
+                format!("{server}/collections/{collection}/views/{view}"),
+                Some(query.to_owned()),
+            ),
+            ViewCommand::List { collection } => (
+                Method::GET,
+                format!("{server}/collections/{collection}/views"),
+                None,
+            ),
+            ViewCommand::Run {
+                collection,
+                view,
+                with,
+            } => {
+                let mut url = format!("{server}/collections/{collection}/views/{view}/points");
+                if let Some(with) = with.as_deref().filter(|with| !with.is_empty()) {
+                    url += &format!("?{}", with.trim_start_matches('?'));
+                }
+                (Method::GET, url, None)
+            }
+        }
+    }
+}
+
+/// Send the request of the view command and return the response body
+pub fn view(args: &ViewArgs) -> Result<Vec<u8>, Box<dyn Error>> {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    let (method, url, body) = args.request();
+
+    let mut request = reqwest::Client::new().request(method, url);
+    if let Some(body) = body {
+        request = request
+            .header("content-type", "application/json")
+            .body(body);
+    }
+
+    runtime.block_on(async move {
+        let response = problem::check(request.send().await?).await?;
+        Ok(response.bytes().await?.to_vec())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn request() {
+        let mut args = ViewArgs {
+            command: ViewCommand::Create {
+                collection: "city".to_string(),
+                view: "ground".to_string(),
+                query: r#"{"filter":"classification=2"}"#.to_string(),
+            },
+            server: "http://localhost:3000/".to_string(),
+        };
+        let (method, url, body) = args.request();
+        assert_eq!(method, Method::PUT);
+        assert_eq!(url, "http://localhost:3000/collections/city/views/ground");
+        assert_eq!(body.as_deref(), Some(r#"{"filter":"classification=2"}"#));
+
+        args.command = ViewCommand::List {
+            collection: "city".to_string(),
+        };
+        assert_eq!(
+            args.request(),
+            (
+                Method::GET,
+                "http://localhost:3000/collections/city/views".to_string(),
+                None
+            )
+        );
+
+        args.command = ViewCommand::Run {
+            collection: "city".to_string(),
+            view: "ground".to_string(),
+            with: Some("p=0.1&format=json".to_string()),
+        };
+        assert_eq!(
+            args.request().1,
+            "http://localhost:3000/collections/city/views/ground/points?p=0.1&format=json"
+        );
+    }
+}
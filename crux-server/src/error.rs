@@ -1,20 +1,84 @@
 use axum::{
-    http::StatusCode,
+    http::{
+        header::{CONTENT_TYPE, RETRY_AFTER},
+        StatusCode,
+    },
     response::{IntoResponse, Response},
+    Json,
 };
+use serde_json::{json, Map, Value};
+
+use crux_io::problem::{Problem, PROBLEM_CONTENT_TYPE};
 
 /// A common error type that can be used throughout the API.
 ///
 /// Can be returned in a `Result` from an API handler function.
 ///
 /// For convenience, this represents both API errors as well as internal recoverable errors,
-/// and maps them to appropriate status codes along with at least a minimally useful error
-/// message in a plain text body, or a JSON body in the case of `UnprocessableEntity`.
+/// and maps them to appropriate status codes along with RFC 7807 problem details, see
+/// [crux_io::problem]. The `type` of the problem tells the variant, the `detail` is the
+/// message of the error and the parameters of the request it refers to are echoed.
 #[derive(thiserror::Error, Debug)]
 pub enum AppError {
+    /// Return `400 Bad Request`
+    #[error("{0}")]
+    BadRequest(String),
+
+    /// Return `400 Bad Request` for bounds that are no lower and upper corner
+    #[error("{detail}")]
+    InvalidBounds { detail: String, bounds: Vec<f64> },
+
+    /// Return `404 Not Found` for a collection that does not exist
+    #[error("no collection `{0}`")]
+    UnknownCollection(String),
+
+    /// Return `404 Not Found` for a missing version of a collection
+    #[error("no version {at} of collection `{collection}`")]
+    UnknownVersion { collection: String, at: u64 },
+
+    /// Return `400 Bad Request` for a query referring to a missing column
+    #[error("no column `{column}` in collection `{collection}`")]
+    UnknownColumn { column: String, collection: String },
+
+    /// Return `409 Conflict` for distinct values of a column that is not
+    /// categorical, with a hint to the histogram of its statistics
+    #[error("{detail}")]
+    NotCategorical {
+        detail: String,
+        column: String,
+        collection: String,
+    },
+
     /// Return `404 Not Found`
-    #[error("request path not found")]
-    NotFound,
+    #[error("{0}")]
+    NotFound(String),
+
+    /// Return `413 Payload Too Large`
+    #[error("{0}")]
+    PayloadTooLarge(String),
+
+    /// Return `413 Payload Too Large` with a hint to narrow the query
+    #[error("more than {limit} points selected")]
+    TooManyPoints { limit: usize },
+
+    /// Return `405 Method Not Allowed` for a request changing the collections
+    /// of a read-only replica
+    #[error("`{method} {path}` is not allowed on a read-only replica")]
+    ReadOnly { method: String, path: String },
+
+    /// Return `429 Too Many Requests` with `Retry-After` for a client over
+    /// its `limit` of requests or points per window
+    #[error("more than {limit} {kind} per {window} s")]
+    RateLimited {
+        kind: &'static str,
+        limit: u64,
+        window: u64,
+        retry_after: u64,
+    },
+
+    /// Return `503 Service Unavailable`
+    #[error("{0}")]
+    ServiceUnavailable(String),
 
     /// Return `500 Internal Server Error` on a `anyhow::Error`.
     ///
@@ -27,10 +91,88 @@ pub enum AppError {
 impl AppError {
     fn status_code(&self) -> StatusCode {
         match self {
-            Self::NotFound => StatusCode::NOT_FOUND,
+            Self::BadRequest(_) | Self::InvalidBounds { .. } | Self::UnknownColumn { .. } => {
+                StatusCode::BAD_REQUEST
+            }
+            Self::UnknownCollection(_) | Self::UnknownVersion { .. } | Self::NotFound(_) => {
+                StatusCode::NOT_FOUND
+            }
+            Self::NotCategorical { .. } => StatusCode::CONFLICT,
+            Self::PayloadTooLarge(_) | Self::TooManyPoints { .. } => StatusCode::PAYLOAD_TOO_LARGE,
+            Self::ReadOnly { .. } => StatusCode::METHOD_NOT_ALLOWED,
+            Self::RateLimited { .. } => StatusCode::TOO_MANY_REQUESTS,
+            Self::ServiceUnavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
             Self::Anyhow(_) => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
+
+    /// Problem type and title
+    fn kind(&self) -> (&'static str, &'static str) {
+        match self {
+            Self::BadRequest(_) => ("crux:bad-request", "Bad request"),
+            Self::InvalidBounds { .. } => ("crux:invalid-bounds", "Invalid bounds"),
+            Self::UnknownCollection(_) => ("crux:unknown-collection", "Unknown collection"),
+            Self::UnknownVersion { .. } => ("crux:unknown-version", "Unknown version"),
+            Self::UnknownColumn { .. } => ("crux:unknown-column", "Unknown column"),
+            Self::NotCategorical { .. } => ("crux:not-categorical", "Not categorical"),
+            Self::NotFound(_) => ("crux:not-found", "Not found"),
+            Self::PayloadTooLarge(_) => ("crux:payload-too-large", "Payload too large"),
+            Self::TooManyPoints { .. } => ("crux:too-many-points", "Too many points"),
+            Self::ReadOnly { .. } => ("crux:read-only", "Read-only replica"),
+            Self::RateLimited { .. } => ("crux:rate-limited", "Too many requests"),
+            Self::ServiceUnavailable(_) => ("crux:unavailable", "Service unavailable"),
+            Self::Anyhow(_) => ("crux:internal", "Internal server error"),
+        }
+    }
+
+    /// Parameters of the request the error refers to
+    fn params(&self) -> Map<String, Value> {
+        let params = match self {
+            Self::InvalidBounds { bounds, .. } => json!({ "bounds": bounds }),
+            Self::UnknownCollection(collection) => json!({ "collection": collection }),
+            Self::UnknownVersion { collection, at } => {
+                json!({ "collection": collection, "at": at })
+            }
+            Self::UnknownColumn { column, collection } => {
+                json!({ "column": column, "collection": collection })
+            }
+            Self::NotCategorical {
+                column, collection, ..
+            } => json!({
+                "column": column,
+                "collection": collection,
+                "hint": format!("see the histogram of `/collections/{collection}/stats?columns={column}`"),
+            }),
+            Self::TooManyPoints { limit } => json!({
+                "limit": limit,
+                "hint": "sample with `p` or `seed`, or narrow the query with `bounds`, `polygon`, `frustum` or `filter`",
+            }),
+            Self::ReadOnly { method, path } => json!({ "method": method, "path": path }),
+            Self::RateLimited {
+                limit,
+                window,
+                retry_after,
+                ..
+            } => json!({ "limit": limit, "window": window, "retry_after": retry_after }),
+            _ => return Map::new(),
+        };
+        match params {
+            Value::Object(params) => params,
+            _ => unreachable!(),
+        }
+    }
+
+    /// Problem details of the error
+    pub(crate) fn problem(&self) -> Problem {
+        let (kind, title) = self.kind();
+        Problem {
+            kind: kind.to_owned(),
+            title: title.to_owned(),
+            status: self.status_code().as_u16(),
+            detail: self.to_string(),
+            params: self.params(),
+        }
+    }
 }
 
 // Tell axum how to convert `AppError` into a response.
@@ -40,6 +182,13 @@ impl IntoResponse for AppError {
             tracing::error!("Generic error: {:?}", e);
         }
 
-        (self.status_code(), self.to_string()).into_response()
+        let header = [(CONTENT_TYPE, PROBLEM_CONTENT_TYPE)];
+        let mut response = (self.status_code(), header, Json(self.problem())).into_response();
+        if let AppError::RateLimited { retry_after, .. } = self {
+            response
+                .headers_mut()
+                .insert(RETRY_AFTER, retry_after.into());
+        }
+        response
     }
 }
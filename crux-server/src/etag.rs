@@ -0,0 +1,32 @@
+use axum::{
+    http::{
+        header::{ETAG, IF_NONE_MATCH},
+        HeaderMap, HeaderValue, StatusCode,
+    },
+    response::{IntoResponse, Response},
+};
+
+/// Whether the `If-None-Match` header of a request matches `etag`
+pub(crate) fn matches(headers: &HeaderMap, etag: &str) -> bool {
+    headers
+        .get_all(IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|tag| tag.trim())
+        // weak comparison
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+}
+
+/// `304 Not Modified` response for `etag`
+pub(crate) fn not_modified(etag: &str) -> Response {
+    tag(StatusCode::NOT_MODIFIED.into_response(), etag)
+}
+
+/// Add the `ETag` header to a response
+pub(crate) fn tag(mut response: Response, etag: &str) -> Response {
+    if let Ok(value) = HeaderValue::from_str(etag) {
+        response.headers_mut().insert(ETAG, value);
+    }
+    response
+}
@@ -0,0 +1,1039 @@
+use std::{collections::BTreeMap, sync::Arc};
+
+use arrow::datatypes::DataType;
+use axum::{
+    body::Bytes,
+    extract::Path,
+    http::{header::CONTENT_TYPE, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use serde::{Deserialize, Serialize};
+use serde_with::{formats::CommaSeparator, serde_as, StringWithSeparator};
+
+use crux_format::{
+    coverage::Gap, polygon, summarize_diff, BaseSurface, CancelToken, CloudDigest, CloudMetadata,
+    ColumnStats, DiffSummary, Point, PointCloudTrait, PointTrait, VolumeReport,
+};
+
+use crate::{
+    error::AppError,
+    etag,
+    jobs::{Job, JobInfo},
+    state::{Collection, SharedState, Version},
+    validate::{validate, UploadFormat, Validation},
+    Qs,
+};
+
+// Collection
+#[derive(Serialize)]
+pub(crate) struct CollectionInfo {
+    name: String,
+    num_points: usize,
+    num_segments: usize,
+    columns: Vec<String>,
+    /// Latest ingest of collections loaded from the watched directory
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ingest: Option<JobInfo>,
+}
+
+impl CollectionInfo {
+    fn new(name: &str, collection: &Collection, ingest: Option<&Arc<Job>>) -> Self {
+        Self {
+            name: name.to_owned(),
+            num_points: collection.num_points(),
+            num_segments: collection.store.len(),
+            columns: collection
+                .schema()
+                .fields()
+                .iter()
+                .map(|field| field.name().to_owned())
+                .collect(),
+            ingest: ingest.map(|job| job.info()),
+        }
+    }
+
+    /// Collection of the watched directory that is not loaded yet
+    fn pending(name: &str, ingest: &Job) -> Self {
+        Self {
+            name: name.to_owned(),
+            num_points: 0,
+            num_segments: 0,
+            columns: Vec::new(),
+            ingest: Some(ingest.info()),
+        }
+    }
+}
+
+pub(crate) async fn collections(
+    Extension(state): Extension<SharedState>,
+) -> Json<Vec<CollectionInfo>> {
+    let state = state.read().await;
+
+    let mut collections: Vec<CollectionInfo> = state
+        .data
+        .iter()
+        .map(|(name, collection)| CollectionInfo::new(name, collection, state.watched.get(name)))
+        .chain(
+            state
+                .watched
+                .iter()
+                .filter(|(name, _)| !state.data.contains_key(*name))
+                .map(|(name, job)| CollectionInfo::pending(name, job)),
+        )
+        .collect();
+    collections.sort_by(|a, b| a.name.cmp(&b.name));
+
+    Json(collections)
+}
+
+pub(crate) async fn collection(
+    Extension(state): Extension<SharedState>,
+    Path(name): Path<String>,
+) -> Result<Json<CollectionInfo>, AppError> {
+    let state = state.read().await;
+
+    let collection = state.collection(&name)?;
+
+    Ok(Json(CollectionInfo::new(
+        &name,
+        collection,
+        state.watched.get(&name),
+    )))
+}
+
+#[derive(Deserialize)]
+pub(crate) struct VersionsQuery {
+    /// Include the changes from the previous version, which reads the points
+    /// of all versions
+    #[serde(default)]
+    changes: bool,
+}
+
+#[derive(Serialize)]
+pub(crate) struct VersionInfo {
+    #[serde(flatten)]
+    version: Version,
+    /// Changes from the previous retained version
+    #[serde(skip_serializing_if = "Option::is_none")]
+    changes: Option<DiffSummary>,
+}
+
+/// Retained versions of a collection, oldest first
+pub(crate) async fn collection_versions(
+    Extension(state): Extension<SharedState>,
+    Path(name): Path<String>,
+    Qs(query): Qs<VersionsQuery>,
+) -> Result<Json<Vec<VersionInfo>>, AppError> {
+    let (versions, snapshots) = {
+        let state = state.read().await;
+        let versions: Vec<Version> = state.collection(&name)?.versions().cloned().collect();
+        let snapshots = match query.changes {
+            true => versions
+                .iter()
+                .map(|v| Ok(state.collection_at(&name, Some(v.version))?.0))
+                .collect::<Result<Vec<_>, AppError>>()?,
+            false => Vec::new(),
+        };
+        (versions, snapshots)
+    };
+
+    let digests: Vec<CloudDigest> = snapshots.iter().map(|pc| pc.digest()).collect();
+    Ok(Json(
+        versions
+            .into_iter()
+            .enumerate()
+            .map(|(i, version)| VersionInfo {
+                version,
+                changes: (i > 0)
+                    .then(|| digests.get(i - 1).zip(digests.get(i)))
+                    .flatten()
+                    .map(|(a, b)| summarize_diff(a, b)),
+            })
+            .collect(),
+    ))
+}
+
+#[serde_as]
+#[derive(Deserialize)]
+pub(crate) struct StatsQuery {
+    /// Version of the collection, the current one if not given
+    at: Option<u64>,
+    /// Include the digest of the points, which reads all of them
+    #[serde(default)]
+    digest: bool,
+    /// Include the statistics and histograms of these columns, separated by
+    /// commas
+    #[serde_as(as = "Option<StringWithSeparator::<CommaSeparator, String>>")]
+    columns: Option<Vec<String>>,
+}
+
+/// Number of bins of the histograms of column statistics
+const HISTOGRAM_BINS: usize = 32;
+
+// Collection statistics
+#[derive(Serialize)]
+pub(crate) struct CollectionStats {
+    num_points: usize,
+    bounds: Option<[[f64; 3]; 2]>,
+    #[serde(flatten)]
+    metadata: CloudMetadata,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    digest: Option<CloudDigest>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    columns: BTreeMap<String, ColumnSummary>,
+}
+
+/// Statistics of a column, the histogram over equal bins from the minimum to
+/// the maximum is interpolated between percentiles
+#[derive(Serialize)]
+pub(crate) struct ColumnSummary {
+    count: usize,
+    null_count: usize,
+    min: f64,
+    max: f64,
+    mean: f64,
+    variance: f64,
+    approximate: bool,
+    histogram: Vec<usize>,
+}
+
+impl From<ColumnStats> for ColumnSummary {
+    fn from(stats: ColumnStats) -> Self {
+        Self {
+            count: stats.count,
+            null_count: stats.null_count,
+            min: stats.min,
+            max: stats.max,
+            mean: stats.mean,
+            variance: stats.variance,
+            approximate: stats.approximate,
+            histogram: stats.histogram(HISTOGRAM_BINS),
+        }
+    }
+}
+
+pub(crate) async fn collection_stats(
+    Extension(state): Extension<SharedState>,
+    Path(name): Path<String>,
+    Qs(query): Qs<StatsQuery>,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    let (collection, etag) = state.read().await.collection_at(&name, query.at)?;
+
+    if etag::matches(&headers, &etag) {
+        return Ok(etag::not_modified(&etag));
+    }
+
+    let num_points = collection.num_points();
+    let bounds = (num_points > 0).then(|| {
+        let aabb = collection.aabb::<Point<f64, 3>>();
+        let (lower, upper) = (aabb.lower(), aabb.upper());
+        [
+            [lower.x(), lower.y(), lower.z()],
+            [upper.x(), upper.y(), upper.z()],
+        ]
+    });
+
+    let columns = query
+        .columns
+        .unwrap_or_default()
+        .into_iter()
+        .map(|column| {
+            let stats = collection
+                .column_stats(&column)
+                .map_err(|_| AppError::UnknownColumn {
+                    column: column.clone(),
+                    collection: name.clone(),
+                })?;
+            Ok((column, stats.into()))
+        })
+        .collect::<Result<_, AppError>>()?;
+
+    let stats = Json(CollectionStats {
+        num_points,
+        bounds,
+        metadata: collection.metadata(),
+        digest: query.digest.then(|| collection.digest()),
+        columns,
+    });
+
+    Ok(etag::tag(stats.into_response(), &etag))
+}
+
+#[derive(Deserialize)]
+pub(crate) struct ValuesQuery {
+    /// Version of the collection, the current one if not given
+    at: Option<u64>,
+    /// Maximum number of distinct values
+    #[serde(default = "default_limit")]
+    limit: usize,
+}
+
+fn default_limit() -> usize {
+    100
+}
+
+/// Upper bound of the `limit` of distinct values
+const MAX_DISTINCT: usize = 10_000;
+
+/// Distinct values of a column with their number of points
+#[derive(Serialize)]
+pub(crate) struct ColumnValues {
+    column: String,
+    values: Vec<ValueCount>,
+    null_count: usize,
+}
+
+#[derive(Serialize)]
+struct ValueCount {
+    value: i64,
+    count: usize,
+}
+
+/// Distinct values of an integer column like `classification`, for offering
+/// filters of the values that occur. Columns with more than `limit` values or
+/// of floats are rejected.
+pub(crate) async fn column_values(
+    Extension(state): Extension<SharedState>,
+    Path((name, column)): Path<(String, String)>,
+    Qs(query): Qs<ValuesQuery>,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    if query.limit > MAX_DISTINCT {
+        return Err(AppError::BadRequest(format!(
+            "limit {} of distinct values is above {MAX_DISTINCT}",
+            query.limit
+        )));
+    }
+
+    let (collection, etag) = state.read().await.collection_at(&name, query.at)?;
+
+    if etag::matches(&headers, &etag) {
+        return Ok(etag::not_modified(&etag));
+    }
+
+    let schema = collection.schema();
+    let Some((_, field)) = schema.column_with_name(&column) else {
+        return Err(AppError::UnknownColumn {
+            column,
+            collection: name,
+        });
+    };
+    let categorical = match field.data_type() {
+        DataType::Dictionary(_, values) => values.is_integer(),
+        data_type => data_type.is_integer(),
+    };
+    let data_type = field.data_type().clone();
+
+    let limit = query.limit;
+    let scanned = column.clone();
+    let counts = tokio::task::spawn_blocking(move || collection.distinct_counts(&scanned, limit))
+        .await
+        .map_err(anyhow::Error::from)?
+        .map_err(anyhow::Error::from)?;
+
+    let Some(counts) = counts else {
+        let detail = match categorical {
+            true => format!("more than {limit} distinct values of column `{column}`"),
+            false => format!("column `{column}` of type {data_type} is not categorical"),
+        };
+        return Err(AppError::NotCategorical {
+            detail,
+            column,
+            collection: name,
+        });
+    };
+
+    let values = Json(ColumnValues {
+        column,
+        values: counts
+            .values
+            .into_iter()
+            .map(|(value, count)| ValueCount { value, count })
+            .collect(),
+        null_count: counts.null_count,
+    });
+
+    Ok(etag::tag(values.into_response(), &etag))
+}
+
+// Volume estimation
+#[derive(Deserialize)]
+pub(crate) struct VolumeQuery {
+    /// Footprint as WKT polygon or flat coordinate list
+    polygon: String,
+    /// Cell size
+    #[serde(default = "default_cell")]
+    cell: f64,
+    /// `min` or the z of the base plane
+    base: BaseSurface,
+}
+
+fn default_cell() -> f64 {
+    1.
+}
+
+pub(crate) async fn collection_volume(
+    Extension(state): Extension<SharedState>,
+    Path(name): Path<String>,
+    Qs(query): Qs<VolumeQuery>,
+) -> Result<Json<VolumeReport>, AppError> {
+    let collection = state.read().await.collection(&name)?.snapshot();
+
+    let ring = polygon::parse(&query.polygon).map_err(|e| AppError::BadRequest(e.to_string()))?;
+
+    // stop rasterizing once the client is gone and the handler is dropped
+    let token = CancelToken::new();
+    let _guard = CancelOnDrop(token.clone());
+
+    let report = tokio::task::spawn_blocking(move || {
+        collection.volume_above_with(&ring, query.cell, query.base, &token)
+    })
+    .await
+    .map_err(anyhow::Error::from)?
+    .map_err(|e| AppError::BadRequest(e.to_string()))?;
+
+    Ok(Json(report))
+}
+
+// Coverage gaps
+#[derive(Deserialize)]
+pub(crate) struct GapsQuery {
+    /// Cell size
+    #[serde(default = "default_cell")]
+    cell: f64,
+    /// Minimum number of empty cells of a gap
+    #[serde(default = "default_min_cells")]
+    min_cells: usize,
+}
+
+fn default_min_cells() -> usize {
+    1
+}
+
+/// Media type of GeoJSON responses
+const GEOJSON_CONTENT_TYPE: &str = "application/geo+json";
+
+/// GeoJSON feature collection of the gaps
+#[derive(Serialize)]
+#[serde(tag = "type", rename = "FeatureCollection")]
+pub(crate) struct GapCollection {
+    features: Vec<GapFeature>,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename = "Feature")]
+struct GapFeature {
+    geometry: GapPolygon,
+    properties: GapProperties,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename = "Polygon")]
+struct GapPolygon {
+    /// Exterior ring, counterclockwise and closed
+    coordinates: [[[f64; 2]; 5]; 1],
+}
+
+#[derive(Serialize)]
+struct GapProperties {
+    area: f64,
+}
+
+impl From<&Gap> for GapFeature {
+    fn from(gap: &Gap) -> Self {
+        let ([x0, y0], [x1, y1]) = (
+            [gap.lower().x(), gap.lower().y()],
+            [gap.upper().x(), gap.upper().y()],
+        );
+        Self {
+            geometry: GapPolygon {
+                coordinates: [[[x0, y0], [x1, y0], [x1, y1], [x0, y1], [x0, y0]]],
+            },
+            properties: GapProperties { area: gap.area() },
+        }
+    }
+}
+
+/// Areas without points inside the footprint of a collection as GeoJSON
+/// polygons, see [ArrowPointCloud::coverage_gaps](crux_format::ArrowPointCloud::coverage_gaps)
+pub(crate) async fn collection_gaps(
+    Extension(state): Extension<SharedState>,
+    Path(name): Path<String>,
+    Qs(query): Qs<GapsQuery>,
+) -> Result<Response, AppError> {
+    let collection = state.read().await.collection(&name)?.snapshot();
+
+    // stop rasterizing once the client is gone and the handler is dropped
+    let token = CancelToken::new();
+    let _guard = CancelOnDrop(token.clone());
+
+    let gaps = tokio::task::spawn_blocking(move || {
+        collection.coverage_gaps_with(query.cell, query.min_cells, &token)
+    })
+    .await
+    .map_err(anyhow::Error::from)?
+    .map_err(|e| AppError::BadRequest(e.to_string()))?;
+
+    let features = GapCollection {
+        features: gaps.iter().map(GapFeature::from).collect(),
+    };
+    Ok(([(CONTENT_TYPE, GEOJSON_CONTENT_TYPE)], Json(features)).into_response())
+}
+
+struct CancelOnDrop(CancelToken);
+
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        self.0.cancel();
+    }
+}
+
+// Ingest dry run
+#[derive(Deserialize)]
+pub(crate) struct ValidateQuery {
+    /// Size of the complete upload in bytes, if only its head is sent
+    size: Option<u64>,
+    /// Detected from the first bytes if missing
+    format: Option<UploadFormat>,
+}
+
+/// Read an upload, or its first megabytes, and report its schema and
+/// suspicious values without storing anything
+pub(crate) async fn validate_upload(
+    Extension(state): Extension<SharedState>,
+    Path(name): Path<String>,
+    Qs(query): Qs<ValidateQuery>,
+    body: Bytes,
+) -> Result<Json<Validation>, AppError> {
+    let exists = state.read().await.data.contains_key(&name);
+
+    let mut validation =
+        tokio::task::spawn_blocking(move || validate(&body, query.size, query.format))
+            .await
+            .map_err(anyhow::Error::from)?
+            .map_err(|e| AppError::BadRequest(format!("{e:#}")))?;
+
+    if exists {
+        validation.warnings.push(format!(
+            "collection `{name}` exists, the upload is appended"
+        ));
+    }
+
+    Ok(Json(validation))
+}
+
+/// Soft delete a collection, disk segments are released by the garbage collector
+pub(crate) async fn delete_collection(
+    Extension(state): Extension<SharedState>,
+    Path(name): Path<String>,
+) -> Result<Response, AppError> {
+    if !state.write().await.remove(&name) {
+        return Err(AppError::UnknownCollection(name));
+    }
+
+    Ok(StatusCode::ACCEPTED.into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use arrow::ipc::reader::StreamReader;
+    use axum::{
+        body::{Body, Bytes},
+        http::{header::ETAG, Method, StatusCode},
+    };
+    use clap::Parser;
+    use http_body_util::BodyExt;
+
+    use crate::{
+        handlers::testing::{buildings, grid, problem, send},
+        Config,
+    };
+
+    const BATCHES: usize = 64;
+    const ROWS: usize = 1000;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn delete_while_streaming() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = dir.path().join("store");
+
+        // one segment per batch
+        let app = crate::app(Config::parse_from([
+            "crux-server",
+            "--gc-interval",
+            "1",
+            "--chunk-size",
+            &ROWS.to_string(),
+        ]));
+
+        let uri = format!("/load?collection=test&store={}", store.to_string_lossy());
+        let response = send(&app, Method::POST, &uri, Body::from(grid(BATCHES, ROWS))).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let segments = std::fs::read_dir(&store)
+            .unwrap()
+            .filter(|entry| {
+                entry
+                    .as_ref()
+                    .unwrap()
+                    .path()
+                    .extension()
+                    .is_some_and(|ext| ext == "arrow")
+            })
+            .count();
+        assert_eq!(segments, BATCHES);
+        assert!(store.join("VERSION").is_file());
+
+        // start a slow reader
+        let response = send(&app, Method::GET, "/points?collection=test", Body::empty()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let mut stream = response.into_body();
+        let mut buffer = stream
+            .frame()
+            .await
+            .unwrap()
+            .unwrap()
+            .into_data()
+            .unwrap()
+            .to_vec();
+
+        // delete mid-stream
+        let response = send(&app, Method::DELETE, "/collections/test", Body::empty()).await;
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        let response = send(&app, Method::GET, "/collections/test", Body::empty()).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        // segments are retained while referenced
+        tokio::time::sleep(Duration::from_millis(1500)).await;
+        assert!(store.is_dir());
+
+        while let Some(frame) = stream.frame().await {
+            buffer.extend(frame.unwrap().into_data().unwrap());
+        }
+        drop(stream);
+
+        let reader =
+            StreamReader::try_new(std::io::Cursor::new(Bytes::from(buffer)), None).unwrap();
+        let rows: usize = reader.map(|batch| batch.unwrap().num_rows()).sum();
+        assert_eq!(rows, BATCHES * ROWS);
+
+        // segments are collected after the reader dropped
+        for _ in 0..50 {
+            if !store.exists() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        assert!(!store.exists());
+    }
+
+    #[tokio::test]
+    async fn stats() {
+        let app = crate::app(Config::parse_from(["crux-server"]));
+
+        let response = send(
+            &app,
+            Method::POST,
+            "/load?collection=grid",
+            Body::from(grid(2, 3)),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = send(&app, Method::GET, "/collections/grid/stats", Body::empty()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(
+            std::str::from_utf8(&body).unwrap(),
+            r#"{"num_points":6,"bounds":[[0.5,0.5,0.0],[2.5,1.5,5.0]],"crs":null,"units":null,"vertical_datum":null}"#
+        );
+
+        let response = send(&app, Method::GET, "/collections/none/stats", Body::empty()).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        // the digest on request
+        let uri = "/collections/grid/stats?digest=true";
+        let response = send(&app, Method::GET, uri, Body::empty()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let stats: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(stats["digest"]["point_count"], 6);
+        assert_eq!(
+            stats["digest"]["aabb"],
+            serde_json::json!([[0.5, 0.5, 0.0], [2.5, 1.5, 5.0]])
+        );
+        assert!(stats["digest"]["per_column_hash"]["x"].is_u64());
+    }
+
+    #[tokio::test]
+    async fn column_values() {
+        let app = crate::app(Config::parse_from(["crux-server"]));
+        let response = send(
+            &app,
+            Method::POST,
+            "/load?collection=city",
+            Body::from(buildings(1000, 100)),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let json = |response: axum::response::Response| async move {
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            serde_json::from_slice::<serde_json::Value>(&body).unwrap()
+        };
+
+        // 30% of the points on buildings, the others on the ground
+        let uri = "/collections/city/columns/classification/values";
+        let response = send(&app, Method::GET, uri, Body::empty()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.headers().contains_key(ETAG));
+        assert_eq!(
+            json(response).await,
+            serde_json::json!({
+                "column": "classification",
+                "values": [{"value": 2, "count": 700}, {"value": 6, "count": 300}],
+                "null_count": 0,
+            })
+        );
+
+        // more values than requested, or floats
+        let uri = "/collections/city/columns/classification/values?limit=1";
+        let response = send(&app, Method::GET, uri, Body::empty()).await;
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        let conflict = problem(response).await;
+        assert_eq!(conflict.kind, "crux:not-categorical");
+        assert!(
+            conflict.detail.contains("more than 1"),
+            "{}",
+            conflict.detail
+        );
+
+        let uri = "/collections/city/columns/z/values";
+        let response = send(&app, Method::GET, uri, Body::empty()).await;
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        let conflict = problem(response).await;
+        assert_eq!(
+            conflict.params["hint"],
+            "see the histogram of `/collections/city/stats?columns=z`"
+        );
+
+        // which is included in the statistics
+        let response = send(
+            &app,
+            Method::GET,
+            "/collections/city/stats?columns=z",
+            Body::empty(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let stats = json(response).await;
+        assert_eq!(stats["columns"]["z"]["count"], 1000);
+        let histogram = stats["columns"]["z"]["histogram"].as_array().unwrap();
+        assert_eq!(histogram.len(), super::HISTOGRAM_BINS);
+        let total: u64 = histogram.iter().map(|count| count.as_u64().unwrap()).sum();
+        assert_eq!(total, 1000);
+
+        // several separated by commas
+        let uri = "/collections/city/stats?columns=z,classification";
+        let response = send(&app, Method::GET, uri, Body::empty()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let stats = json(response).await;
+        assert_eq!(stats["columns"].as_object().unwrap().len(), 2);
+
+        for (uri, status) in [
+            (
+                "/collections/city/columns/missing/values",
+                StatusCode::BAD_REQUEST,
+            ),
+            (
+                "/collections/city/stats?columns=missing",
+                StatusCode::BAD_REQUEST,
+            ),
+            ("/collections/none/columns/z/values", StatusCode::NOT_FOUND),
+            (
+                "/collections/city/columns/classification/values?limit=100000",
+                StatusCode::BAD_REQUEST,
+            ),
+        ] {
+            let response = send(&app, Method::GET, uri, Body::empty()).await;
+            assert_eq!(response.status(), status, "{uri}");
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn versions() {
+        let app = crate::app(Config::parse_from([
+            "crux-server",
+            "--history-versions",
+            "2",
+        ]));
+
+        let json = |uri: &'static str| {
+            let app = app.clone();
+            async move {
+                let response = send(&app, Method::GET, uri, Body::empty()).await;
+                assert_eq!(response.status(), StatusCode::OK, "{uri}");
+                let body = response.into_body().collect().await.unwrap().to_bytes();
+                serde_json::from_slice::<serde_json::Value>(&body).unwrap()
+            }
+        };
+        let count = |uri: &'static str| {
+            let app = app.clone();
+            async move {
+                let response = send(&app, Method::GET, uri, Body::empty()).await;
+                assert_eq!(response.status(), StatusCode::OK, "{uri}");
+                let body = response.into_body().collect().await.unwrap().to_bytes();
+                StreamReader::try_new(std::io::Cursor::new(body), None)
+                    .unwrap()
+                    .map(|batch| batch.unwrap().num_rows())
+                    .sum::<usize>()
+            }
+        };
+
+        // append twice
+        for rows in [5, 3] {
+            let uri = "/load?collection=grid";
+            let response = send(&app, Method::POST, uri, Body::from(grid(2, rows))).await;
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+
+        let versions = json("/collections/grid/versions").await;
+        let versions = versions.as_array().unwrap();
+        assert_eq!(versions.len(), 2);
+        assert_eq!(versions[0]["version"], 1);
+        assert_eq!(versions[0]["num_points"], 10);
+        assert_eq!(versions[1]["version"], 2);
+        assert_eq!(versions[1]["num_points"], 16);
+        assert_ne!(versions[0]["etag"], versions[1]["etag"]);
+        assert!(versions[1].get("changes").is_none());
+
+        // changes from the previous version, the appended points are within
+        // its bounds
+        let changes = json("/collections/grid/versions?changes=true").await;
+        assert!(changes[0].get("changes").is_none());
+        assert_eq!(changes[1]["version"], 2);
+        assert_eq!(
+            changes[1]["changes"],
+            serde_json::json!({
+                "point_delta": 6,
+                "bbox_changed": false,
+                "columns_added": [],
+                "columns_removed": [],
+                "columns_changed": ["i", "x", "y", "z"],
+            })
+        );
+
+        // the count before the second append
+        let stats = json("/collections/grid/stats?at=1").await;
+        assert_eq!(stats["num_points"], 10);
+        assert_eq!(count("/points?collection=grid&at=1").await, 10);
+        assert_eq!(count("/points?collection=grid&at=2").await, 16);
+        assert_eq!(count("/points?collection=grid").await, 16);
+
+        // historical versions are tagged by their content
+        let response = send(
+            &app,
+            Method::GET,
+            "/collections/grid/stats?at=1",
+            Body::empty(),
+        )
+        .await;
+        assert_eq!(
+            response.headers()[ETAG],
+            versions[0]["etag"].as_str().unwrap()
+        );
+        let response = send(&app, Method::GET, "/collections/grid/stats", Body::empty()).await;
+        assert_eq!(
+            response.headers()[ETAG],
+            versions[1]["etag"].as_str().unwrap()
+        );
+
+        // the first version falls out of the retention window
+        let response = send(
+            &app,
+            Method::POST,
+            "/load?collection=grid",
+            Body::from(grid(1, 1)),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let versions = json("/collections/grid/versions").await;
+        let numbers: Vec<_> = versions
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|v| v["version"].as_u64().unwrap())
+            .collect();
+        assert_eq!(numbers, [2, 3]);
+        for uri in [
+            "/collections/grid/stats?at=1",
+            "/points?collection=grid&at=1",
+            "/collections/missing/versions",
+        ] {
+            let response = send(&app, Method::GET, uri, Body::empty()).await;
+            assert_eq!(response.status(), StatusCode::NOT_FOUND, "{uri}");
+        }
+    }
+
+    #[tokio::test]
+    async fn volume() {
+        let app = crate::app(Config::parse_from(["crux-server"]));
+
+        let response = send(
+            &app,
+            Method::POST,
+            "/load?collection=grid",
+            Body::from(grid(2, 5)),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // one point per cell with z = 0..9
+        let uri = "/collections/grid/volume?polygon=0,0,5,0,5,2,0,2&base=0";
+        let response = send(&app, Method::GET, uri, Body::empty()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(
+            std::str::from_utf8(&body).unwrap(),
+            r#"{"volume":45.0,"area":10.0,"cell_count":10,"coverage":1.0}"#
+        );
+
+        // z spread of 6 and 7 within the two coarse cells
+        let uri = "/collections/grid/volume?polygon=0,0,5,0,5,2,0,2&cell=2.5&base=min";
+        let response = send(&app, Method::GET, uri, Body::empty()).await;
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert!(std::str::from_utf8(&body)
+            .unwrap()
+            .starts_with(r#"{"volume":81.25,"#));
+
+        for uri in [
+            "/collections/grid/volume?polygon=0,0,5,0&base=0",
+            "/collections/grid/volume?polygon=0,0,5,0,5,2&base=top",
+            "/collections/grid/volume?polygon=0,0,5,0,5,2&base=0&cell=0",
+        ] {
+            let response = send(&app, Method::GET, uri, Body::empty()).await;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{uri}");
+        }
+
+        let uri = "/collections/none/volume?polygon=0,0,5,0,5,2&base=0";
+        let response = send(&app, Method::GET, uri, Body::empty()).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn gaps() {
+        use arrow::{
+            array::{ArrayRef, Float64Array},
+            ipc::writer::StreamWriter,
+            record_batch::RecordBatch,
+        };
+        use axum::http::header::CONTENT_TYPE;
+        use crux_format::{Point, PointTrait};
+
+        let app = crate::app(Config::parse_from(["crux-server"]));
+
+        // one point per unit cell of [0, 10]², but none in [3, 5] x [6, 7]
+        let (x, y): (Vec<f64>, Vec<f64>) = (0..100)
+            .map(|i| ((i % 10) as f64 + 0.5, (i / 10) as f64 + 0.5))
+            .filter(|(x, y)| !((3. ..5.).contains(x) && (6. ..7.).contains(y)))
+            .unzip();
+        let z = Float64Array::from(vec![0.; x.len()]);
+        let schema = Point::<f64, 3>::schema();
+        let columns = vec![
+            Arc::new(Float64Array::from(x)) as ArrayRef,
+            Arc::new(Float64Array::from(y)),
+            Arc::new(z),
+        ];
+        let mut writer = StreamWriter::try_new(Vec::new(), &schema).unwrap();
+        writer
+            .write(&RecordBatch::try_new(schema.clone(), columns).unwrap())
+            .unwrap();
+        let body = Body::from(writer.into_inner().unwrap());
+        let response = send(&app, Method::POST, "/load?collection=hole", body).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = send(&app, Method::GET, "/collections/hole/gaps", Body::empty()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[CONTENT_TYPE], "application/geo+json");
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(
+            std::str::from_utf8(&body).unwrap(),
+            r#"{"type":"FeatureCollection","features":[{"type":"Feature","geometry":{"type":"Polygon","coordinates":[[[3.0,6.0],[5.0,6.0],[5.0,7.0],[3.0,7.0],[3.0,6.0]]]},"properties":{"area":2.0}}]}"#
+        );
+
+        // smaller than the threshold, or a cell larger than the hole
+        for uri in [
+            "/collections/hole/gaps?min_cells=3",
+            "/collections/hole/gaps?cell=5",
+        ] {
+            let response = send(&app, Method::GET, uri, Body::empty()).await;
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            assert_eq!(
+                std::str::from_utf8(&body).unwrap(),
+                r#"{"type":"FeatureCollection","features":[]}"#,
+                "{uri}"
+            );
+        }
+
+        let response = send(
+            &app,
+            Method::GET,
+            "/collections/hole/gaps?cell=0",
+            Body::empty(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let response = send(&app, Method::GET, "/collections/none/gaps", Body::empty()).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn validate() {
+        let app = crate::app(Config::parse_from(["crux-server"]));
+
+        let json = |response: axum::response::Response| async move {
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            serde_json::from_slice::<serde_json::Value>(&body).unwrap()
+        };
+
+        let uri = "/collections/grid/validate";
+        let response = send(&app, Method::POST, uri, Body::from(grid(2, 5))).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let validation = json(response).await;
+        assert_eq!(validation["format"], "arrow");
+        assert_eq!(validation["points_read"], 10);
+        assert_eq!(
+            validation["warnings"],
+            serde_json::json!(["no CRS metadata"])
+        );
+
+        // nothing is stored
+        let response = send(&app, Method::GET, "/collections/grid", Body::empty()).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        // the head of a large CSV upload
+        let uri = "/collections/grid/validate?size=1000&format=csv";
+        let response = send(&app, Method::POST, uri, Body::from("x,y,z\n1,2,3\n4,5")).await;
+        let validation = json(response).await;
+        assert_eq!(validation["partial"], true);
+        assert_eq!(validation["estimated_points"], 83);
+
+        // appended to existing collections
+        send(
+            &app,
+            Method::POST,
+            "/load?collection=grid",
+            Body::from(grid(1, 2)),
+        )
+        .await;
+        let response = send(&app, Method::POST, uri, Body::from("x,y,z\n1,2,3\n")).await;
+        let warnings = json(response).await["warnings"].to_string();
+        assert!(warnings.contains("collection `grid` exists"));
+
+        let uri = "/collections/grid/validate";
+        let response = send(&app, Method::POST, uri, Body::from("LASF")).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+}
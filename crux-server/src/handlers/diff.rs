@@ -0,0 +1,59 @@
+use anyhow::Context;
+use arrow::ipc::writer::StreamWriter;
+use axum::{
+    body::Bytes,
+    http::header::CONTENT_TYPE,
+    response::{IntoResponse, Response},
+    Extension,
+};
+use serde::{Deserialize, Serialize};
+
+use crux_format::{PointCloudError, PointCloudTrait};
+
+use crate::{error::AppError, state::SharedState, Qs};
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct DiffQuery {
+    a: String,
+    b: String,
+    cell: f64,
+}
+
+#[axum::debug_handler]
+pub(crate) async fn diff(
+    Extension(state): Extension<SharedState>,
+    Qs(query): Qs<DiffQuery>,
+) -> Result<Response, AppError> {
+    tracing::debug!("{query:#?}");
+
+    // snapshots instead of the collections, the lock is not held while diffing
+    let (a, b) = {
+        let state = state.read().await;
+        let a = state.collection(&query.a)?.snapshot();
+        (a, state.collection(&query.b)?.snapshot())
+    };
+
+    let cell = query.cell;
+    let pc = match tokio::task::spawn_blocking(move || crux_format::diff(&a, &b, cell))
+        .await
+        .context("Join diff task")?
+    {
+        Ok(pc) => pc,
+        Err(PointCloudError::InvalidArgument(e)) => return Err(AppError::BadRequest(e)),
+        Err(e) => return Err(anyhow::Error::from(e).into()),
+    };
+
+    let mut writer =
+        StreamWriter::try_new(Vec::new(), &pc.schema()).context("Create stream writer")?;
+    for batch in pc.store.iter().flat_map(|e| pc.store.batches(e.key())) {
+        writer.write(&batch).context("Write batch to stream")?;
+    }
+    writer.finish().context("Finish stream")?;
+
+    let buffer = writer.into_inner().context("Get stream buffer")?;
+
+    let header = [(CONTENT_TYPE, "application/vnd.apache.arrow.stream")];
+    let body: Bytes = buffer.into();
+
+    Ok((header, body).into_response())
+}
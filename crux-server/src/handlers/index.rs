@@ -1,15 +1,22 @@
-use arrow::compute::concat_batches;
+use anyhow::Context;
 use axum::{http::StatusCode, response::IntoResponse, Extension};
-use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
-use rstar::{primitives::GeomWithData, RTree};
 use tokio::task::JoinSet;
 
-use crux_format::{compute::aabb, soa::Index, Point};
+use crux_format::{
+    soa::{BatchIndex, Index},
+    ArrowPointCloud, PointCloudTrait,
+};
 
-use crate::state::SharedState;
+use crate::{
+    error::AppError,
+    handlers::worker_response,
+    state::{Collection, SharedState},
+};
 
 #[axum::debug_handler]
-pub(crate) async fn index(Extension(state): Extension<SharedState>) -> impl IntoResponse {
+pub(crate) async fn index(
+    Extension(state): Extension<SharedState>,
+) -> Result<StatusCode, AppError> {
     if !state.read().await.workers.is_empty() {
         let mut set = JoinSet::new();
 
@@ -18,8 +25,7 @@ pub(crate) async fn index(Extension(state): Extension<SharedState>) -> impl Into
         }
 
         while let Some(res) = set.join_next().await {
-            let out = res.unwrap();
-            out.unwrap().error_for_status().unwrap();
+            worker_response(res)?;
         }
     } else {
         // tracing::info!("Indexing points");
@@ -61,39 +67,36 @@ pub(crate) async fn index(Extension(state): Extension<SharedState>) -> impl Into
         // tracing::info!("Indexed {} point locations", n);
 
         let mut state = state.write().await;
-        let pc = state.data.get_mut("default").unwrap();
-        let objects = pc
-            .store
-            .par_iter()
-            .map(|e| {
-                let batches = pc.store.batches(e.key());
-
-                let aabb = if batches.len() == 1 {
-                    aabb::<Point<f64, 4>>(&batches[0])
-                } else {
-                    aabb(&concat_batches(&batches[0].schema(), batches.iter()).unwrap())
-                };
-
-                // XXX: Memory usage (failure to free?)
-                // match e.value_mut() {
-                //     PointCloudData::InMemory(batches) => {
-                //         batches.clear();
-                //         batches.push(batch);
-                //     }
-                //     PointCloudData::OnDisk(_) => todo!(),
-                // }
-
-                GeomWithData::new(aabb, e.key().to_string())
-            })
-            .collect();
-
-        let index = RTree::bulk_load_with_params(objects);
+        let collection = state
+            .data
+            .get_mut("default")
+            .ok_or_else(|| AppError::UnknownCollection("default".to_owned()))?;
+        let pc = collection.snapshot();
+        let index = pc.batch_index(&()).context("Index batches")?;
         tracing::info!("Indexed {} point batches", index.size());
 
-        pc.index = Index::Batch(index);
+        drop(pc);
+        let version = collection.version();
+        set_index(collection, index, version);
     }
 
-    StatusCode::OK
+    Ok(StatusCode::OK)
+}
+
+/// Replace the index built at content `version`, publishing a new version if
+/// readers still hold the current one
+pub(crate) fn set_index(collection: &mut Collection, index: BatchIndex, version: u64) {
+    collection.persist_index(Some(&index), version);
+    match collection.get_mut() {
+        Some(pc) => pc.index = Index::Batch(index),
+        None => {
+            let mut pc =
+                ArrowPointCloud::try_new_with(collection.schema(), collection.store.clone())
+                    .unwrap();
+            pc.index = Index::Batch(index);
+            collection.publish(pc);
+        }
+    }
 }
 
 #[axum::debug_handler]
@@ -104,5 +107,18 @@ pub(crate) async fn remove_index(Extension(state): Extension<SharedState>) -> im
         .await
         .data
         .values_mut()
-        .for_each(|pc| pc.index = Index::None)
+        .for_each(|collection| {
+            collection.persist_index(None, collection.version());
+            match collection.get_mut() {
+                Some(pc) => pc.index = Index::None,
+                None => {
+                    let pc = ArrowPointCloud::try_new_with(
+                        collection.schema(),
+                        collection.store.clone(),
+                    )
+                    .unwrap();
+                    collection.publish(pc);
+                }
+            }
+        })
 }
@@ -0,0 +1,450 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::Path,
+    http::{header::LOCATION, StatusCode},
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use serde::{Deserialize, Serialize};
+
+use crux_format::{
+    query::{Expr, Query},
+    PointCloudError, PointCloudTrait,
+};
+
+use crate::{
+    error::AppError,
+    handlers::check_schema,
+    jobs::{self, Job, JobInfo, JobSpec},
+    state::SharedState,
+    Qs,
+};
+
+#[derive(Serialize)]
+pub(crate) struct JobCreated {
+    id: String,
+}
+
+pub(crate) async fn submit_job(
+    Extension(state): Extension<SharedState>,
+    Path(name): Path<String>,
+    Json(spec): Json<JobSpec>,
+) -> Result<Response, AppError> {
+    let job = {
+        let mut state = state.write().await;
+        state.collection(&name)?;
+        // sorting publishes a new version
+        if state.config.read_only && matches!(spec, JobSpec::Sort) {
+            return Err(AppError::ReadOnly {
+                method: "POST".to_owned(),
+                path: format!("/collections/{name}/jobs"),
+            });
+        }
+
+        let job = Arc::new(Job::new(&name, spec));
+        state.jobs.insert(job.id.clone(), job.clone());
+        job
+    };
+
+    Ok(queue(state, job))
+}
+
+/// Run `job` in the background, the response points to its status
+fn queue(state: SharedState, job: Arc<Job>) -> Response {
+    tracing::info!(
+        "Queued job {} on `{}`: {:?}",
+        job.id,
+        job.collection,
+        job.spec
+    );
+    let id = job.id.clone();
+    tokio::spawn(jobs::run(state, job));
+
+    (
+        StatusCode::ACCEPTED,
+        [(LOCATION, format!("/jobs/{id}"))],
+        Json(JobCreated { id }),
+    )
+        .into_response()
+}
+
+#[derive(Deserialize)]
+pub(crate) struct DeleteQuery {
+    /// Points to delete, comparisons like the filter of point queries, e.g.
+    /// `point_source_id=17` or `gps_time>=1000,gps_time<1200`
+    filter: String,
+}
+
+/// Delete the points of a collection matching the filter in a job, which
+/// reports the number of deleted points. Readers of the current version keep
+/// it until they are done.
+pub(crate) async fn delete_points(
+    Extension(state): Extension<SharedState>,
+    Path(name): Path<String>,
+    Qs(query): Qs<DeleteQuery>,
+) -> Result<Response, AppError> {
+    let filter: Expr = query
+        .filter
+        .parse()
+        .map_err(|e: PointCloudError| AppError::BadRequest(e.to_string()))?;
+
+    let job = {
+        let mut state = state.write().await;
+        let schema = state.collection(&name)?.schema();
+        check_schema(&Query::new().filter(filter), &name, &schema)?;
+
+        let spec = JobSpec::Delete {
+            filter: query.filter,
+        };
+        let job = Arc::new(Job::new(&name, spec));
+        state.jobs.insert(job.id.clone(), job.clone());
+        job
+    };
+
+    Ok(queue(state, job))
+}
+
+fn unknown_job(id: &str) -> AppError {
+    AppError::NotFound(format!("no job `{id}`"))
+}
+
+pub(crate) async fn job(
+    Extension(state): Extension<SharedState>,
+    Path(id): Path<String>,
+) -> Result<Json<JobInfo>, AppError> {
+    let state = state.read().await;
+    let job = state.jobs.get(&id).ok_or_else(|| unknown_job(&id))?;
+
+    Ok(Json(job.info()))
+}
+
+/// Cancel a pending job, or forget a finished one
+pub(crate) async fn cancel_job(
+    Extension(state): Extension<SharedState>,
+    Path(id): Path<String>,
+) -> Result<Response, AppError> {
+    let mut state = state.write().await;
+    let job = state.jobs.get(&id).ok_or_else(|| unknown_job(&id))?;
+
+    if job.cancel() {
+        tracing::info!("Cancelling job {id}");
+        Ok((StatusCode::ACCEPTED, Json(job.info())).into_response())
+    } else {
+        state.jobs.remove(&id);
+        Ok(StatusCode::NO_CONTENT.into_response())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use axum::{
+        body::Body,
+        http::{Method, StatusCode},
+        Router,
+    };
+    use clap::Parser;
+    use http_body_util::BodyExt;
+    use serde_json::Value;
+    use tokio::sync::RwLock;
+
+    use crux_format::{PointCloudError, ProgressSink};
+
+    use crate::{
+        handlers::testing::{drive, grid, problem, send},
+        jobs::{self, Job, JobSpec},
+        state::{AppState, SharedState},
+        Config,
+    };
+
+    async fn json(app: &Router, method: Method, uri: &str, body: &str) -> (StatusCode, Value) {
+        let request = axum::http::Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_owned()))
+            .unwrap();
+        let response = tower::ServiceExt::oneshot(app.clone(), request)
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        (status, serde_json::from_slice(&body).unwrap_or(Value::Null))
+    }
+
+    async fn count(app: &Router, uri: &str) -> usize {
+        let response = send(app, Method::GET, uri, Body::empty()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        arrow::ipc::reader::StreamReader::try_new(std::io::Cursor::new(body), None)
+            .unwrap()
+            .map(|batch| batch.unwrap().num_rows())
+            .sum()
+    }
+
+    /// Poll until the job state satisfies `done`
+    async fn poll(app: &Router, id: &str, done: impl Fn(&Value) -> bool) -> Value {
+        for _ in 0..200 {
+            let (status, job) = json(app, Method::GET, &format!("/jobs/{id}"), "").await;
+            assert_eq!(status, StatusCode::OK);
+            if done(&job) {
+                return job;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        panic!("job {id} did not progress");
+    }
+
+    /// Export of the collection `grid` preceded by `steps` steps of 20
+    /// milliseconds, checking for cancellation after each step
+    async fn slow(state: &SharedState, steps: usize) -> String {
+        let job = Arc::new(Job::new("grid", JobSpec::Export));
+        state.write().await.jobs.insert(job.id.clone(), job.clone());
+        tokio::spawn(jobs::run_with(
+            state.clone(),
+            job.clone(),
+            move |job, pc, context| {
+                for step in 0..steps {
+                    job.report(step, steps);
+                    if job.is_cancelled() {
+                        return Err(PointCloudError::Cancelled.into());
+                    }
+                    std::thread::sleep(Duration::from_millis(20));
+                }
+                jobs::execute(job, pc, context)
+            },
+        ));
+
+        job.id.clone()
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn submit_poll_cancel() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = Arc::new(RwLock::new(AppState::new(Config::parse_from([
+            "crux-server",
+            "--storage-dir",
+            dir.path().to_str().unwrap(),
+        ]))));
+        let app = crate::router(state.clone());
+
+        let response = send(
+            &app,
+            Method::POST,
+            "/load?collection=grid",
+            Body::from(grid(4, 5)),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // slow export runs to completion
+        let id = slow(&state, 10).await;
+        let job = poll(&app, &id, |job| job["state"] == "completed").await;
+        assert_eq!(job["kind"], "export");
+        assert_eq!(job["done"], job["total"]);
+        assert!(job["result"].is_string());
+
+        // finished jobs are kept for the retention period
+        assert_eq!(state.write().await.expire_jobs(), 0);
+        state.write().await.config.job_retention = 0;
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert!(state.write().await.expire_jobs() >= 1);
+        let (status, _) = json(&app, Method::GET, &format!("/jobs/{id}"), "").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        // cancel another one mid-flight
+        let id = slow(&state, 1000).await;
+        poll(&app, &id, |job| {
+            job["state"] == "running" && job["done"] != 0
+        })
+        .await;
+
+        let (status, _) = json(&app, Method::DELETE, &format!("/jobs/{id}"), "").await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+        let job = poll(&app, &id, |job| job["state"] == "cancelled").await;
+        assert!(job["done"].as_u64().unwrap() < 1000);
+
+        // finished jobs are forgotten on delete
+        let (status, _) = json(&app, Method::DELETE, &format!("/jobs/{id}"), "").await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let (status, _) = json(&app, Method::GET, &format!("/jobs/{id}"), "").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        // export reports the result location
+        let spec = r#"{"kind":"export"}"#;
+        let (_, created) = json(&app, Method::POST, "/collections/grid/jobs", spec).await;
+        let job = poll(&app, created["id"].as_str().unwrap(), |job| {
+            job["state"] == "completed"
+        })
+        .await;
+        let path = job["result"].as_str().unwrap();
+        assert!(path.starts_with(dir.path().join("exports").to_str().unwrap()));
+        let reader =
+            arrow::ipc::reader::FileReader::try_new(std::fs::File::open(path).unwrap(), None)
+                .unwrap();
+        let rows: usize = reader.map(|batch| batch.unwrap().num_rows()).sum();
+        assert_eq!(rows, 20);
+
+        // unknown collections and job kinds are rejected
+        let (status, _) = json(&app, Method::POST, "/collections/none/jobs", spec).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, _) = json(
+            &app,
+            Method::POST,
+            "/collections/grid/jobs",
+            r#"{"kind":"x"}"#,
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn sort() {
+        let app = crate::app(Config::parse_from(["crux-server"]));
+        let response = send(
+            &app,
+            Method::POST,
+            "/load?collection=drive",
+            Body::from(drive(10_000, 1000)),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let uri = "/points?collection=drive&time=2.5,4.5";
+        let before = count(&app, uri).await;
+
+        let spec = r#"{"kind":"sort"}"#;
+        let (status, created) = json(&app, Method::POST, "/collections/drive/jobs", spec).await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+        let job = poll(&app, created["id"].as_str().unwrap(), |job| {
+            job["state"] != "queued" && job["state"] != "running"
+        })
+        .await;
+        assert_eq!(job["state"], "completed", "{job}");
+
+        // published as a new version with the same points
+        assert_eq!(count(&app, uri).await, before);
+        let (_, versions) = json(&app, Method::GET, "/collections/drive/versions", "").await;
+        assert_eq!(versions.as_array().unwrap().len(), 2);
+        assert_eq!(versions[1]["num_points"], 10_000);
+
+        // persisted collections are not sorted
+        let dir = tempfile::tempdir().unwrap();
+        let app = crate::app(Config::parse_from([
+            "crux-server",
+            "--storage-dir",
+            dir.path().to_str().unwrap(),
+        ]));
+        send(
+            &app,
+            Method::POST,
+            "/load?collection=drive",
+            Body::from(drive(1000, 100)),
+        )
+        .await;
+        let (_, created) = json(&app, Method::POST, "/collections/drive/jobs", spec).await;
+        let job = poll(&app, created["id"].as_str().unwrap(), |job| {
+            job["state"] == "failed"
+        })
+        .await;
+        assert!(
+            job["error"].as_str().unwrap().contains("persisted"),
+            "{job}"
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn delete_by_filter() {
+        let dir = tempfile::tempdir().unwrap();
+        let app = crate::app(Config::parse_from([
+            "crux-server",
+            "--storage-dir",
+            dir.path().to_str().unwrap(),
+            "--chunk-size",
+            "0",
+        ]));
+        let response = send(
+            &app,
+            Method::POST,
+            "/load?collection=grid",
+            Body::from(grid(4, 25)),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let segments = || {
+            let mut files: Vec<_> = std::fs::read_dir(dir.path().join("grid"))
+                .unwrap()
+                .map(|e| e.unwrap().path())
+                .filter(|path| path.extension().is_some_and(|ext| ext == "arrow"))
+                .map(|path| {
+                    let modified = std::fs::metadata(&path).unwrap().modified().unwrap();
+                    (path, modified)
+                })
+                .collect();
+            files.sort();
+            files
+        };
+        let before = segments();
+        assert_eq!(before.len(), 4);
+
+        // 15 points of the first row of segments
+        let (status, created) = json(
+            &app,
+            Method::POST,
+            "/collections/grid/delete?filter=y%3C1,z%3E%3D10",
+            "",
+        )
+        .await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+        let job = poll(&app, created["id"].as_str().unwrap(), |job| {
+            job["state"] != "queued" && job["state"] != "running"
+        })
+        .await;
+        assert_eq!(job["state"], "completed", "{job}");
+        assert_eq!(job["kind"], "delete");
+        assert_eq!(job["deleted"], 15);
+
+        assert_eq!(count(&app, "/points?collection=grid").await, 85);
+        assert_eq!(
+            count(&app, "/points?collection=grid&filter=y%3C1").await,
+            10
+        );
+        let (_, versions) = json(&app, Method::GET, "/collections/grid/versions", "").await;
+        assert_eq!(versions.as_array().unwrap().len(), 2);
+        assert_eq!(count(&app, "/points?collection=grid&at=1").await, 100);
+
+        // one segment is written, the others are left untouched
+        let after = segments();
+        assert_eq!(after.len(), 5);
+        assert!(before.iter().all(|segment| after.contains(segment)));
+
+        // filters are checked against the schema before queueing
+        let response = send(
+            &app,
+            Method::POST,
+            "/collections/grid/delete?filter=intensity%3C1",
+            Body::empty(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(problem(response).await.kind, "crux:unknown-column");
+        let response = send(
+            &app,
+            Method::POST,
+            "/collections/grid/delete?filter=y%3C",
+            Body::empty(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let response = send(
+            &app,
+            Method::POST,
+            "/collections/none/delete?filter=y%3C1",
+            Body::empty(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+}
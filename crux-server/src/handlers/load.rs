@@ -1,8 +1,11 @@
+use std::sync::Arc;
+
 use anyhow::Context;
 use arrow::{
     array::BooleanArray,
     compute::filter_record_batch,
-    datatypes::DataType,
+    datatypes::{DataType, SchemaRef},
+    error::ArrowError,
     ipc::{reader::StreamReader, writer::StreamWriter},
     record_batch::RecordBatch,
 };
@@ -10,7 +13,7 @@ use axum::{
     body::Bytes,
     http::StatusCode,
     response::{IntoResponse, Response},
-    Extension,
+    Extension, Json,
 };
 use rand::{
     distributions::{Standard, Uniform},
@@ -20,16 +23,26 @@ use rand::{
 use rayon::iter::ParallelIterator;
 use serde::{Deserialize, Serialize};
 use serde_with::{formats::CommaSeparator, serde_as, StringWithSeparator};
-use tokio::{runtime::Handle, task::JoinSet};
+use tokio::{
+    runtime::Handle,
+    task::{JoinError, JoinSet},
+};
 
 use crux_format::{
-    compute::{aabb, filter_by_aabb},
+    chunk::rechunk,
+    compute::{aabb, filter_by_aabb, finite_mask},
     soa::PointCloudStore,
-    ArrowPointCloud, Framework, Point, PointTrait,
+    ArrowPointCloud, ColumnEncodings, Framework, Point, PointTrait,
 };
 use crux_io::las::LasDataSource;
 
-use crate::{error::AppError, state::SharedState, Qs};
+use crate::{
+    error::AppError,
+    jobs,
+    remote::Remote,
+    state::{Collection, Retention, SharedState},
+    Qs,
+};
 
 #[serde_as]
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -46,27 +59,92 @@ pub(crate) struct LoadRequest {
     store: Option<String>,
     #[serde(default)]
     compress: bool,
+    /// Encodings of the columns in the store, from the configuration
+    #[serde(skip)]
+    encodings: ColumnEncodings,
+}
+
+/// Points of an ingest, returned by loads
+#[derive(Serialize, Deserialize, Default, Debug, Clone, Copy, PartialEq)]
+pub(crate) struct IngestSummary {
+    /// Points inserted into the collection
+    pub points: usize,
+    /// Points with non-finite coordinates
+    pub nonfinite: usize,
+    /// Points with non-finite coordinates that were not inserted
+    pub dropped: usize,
+}
+
+impl IngestSummary {
+    fn add(self, other: Self) -> Self {
+        Self {
+            points: self.points + other.points,
+            nonfinite: self.nonfinite + other.nonfinite,
+            dropped: self.dropped + other.dropped,
+        }
+    }
+}
+
+impl LoadRequest {
+    /// Store the collection in the configured storage directory, if any, or
+    /// the cache directory of the object store, with the configured column
+    /// encodings
+    async fn set_default_store(&mut self, state: &SharedState) {
+        let state = state.read().await;
+        self.encodings = state.config.encodings();
+        if self.store.is_some() {
+            return;
+        }
+
+        let dir = state
+            .config
+            .storage_dir
+            .as_ref()
+            .or(state.remote.as_ref().map(|remote| &remote.cache_dir));
+        if let (Some(dir), Some(collection)) = (dir, &self.collection) {
+            self.store = Some(dir.join(collection).to_string_lossy().into_owned());
+        }
+    }
+
+    /// Empty collection of the request, uploaded to the object store if stored
+    fn new_collection(&self, schema: SchemaRef, remote: Option<&Arc<Remote>>) -> Collection {
+        let Some(store) = self.store.as_ref() else {
+            return Collection::empty(ArrowPointCloud::try_new(schema).unwrap());
+        };
+
+        let capacity = 1000; // Cells
+        let store = PointCloudStore::try_new(capacity, store, self.compress)
+            .unwrap()
+            .with_encodings(self.encodings.clone());
+        let collection = Collection::empty(ArrowPointCloud::try_new_with(schema, store).unwrap());
+        match (remote, &self.collection) {
+            (Some(remote), Some(name)) => collection.with_remote(remote.collection(name)),
+            _ => collection,
+        }
+    }
 }
 
 #[axum::debug_handler]
 pub(crate) async fn load(
     Extension(state): Extension<SharedState>,
     Qs(mut query): Qs<LoadRequest>,
-) -> Result<Response, AppError> {
+) -> Result<Json<IngestSummary>, AppError> {
     // Set default collection (FIXME: should be required)
     query.collection.get_or_insert("default".to_string());
+    query.set_default_store(&state).await;
 
     tracing::info!("{:#?}", &query);
 
     // Distribute or load
+    let mut summary = IngestSummary::default();
     match query.workers {
         None => {
             if state.read().await.workers.is_empty() {
                 // load fraction
-                load_fraction(&query, &state).await?;
+                summary = load_fraction(&query, &state).await?;
             } else {
                 // distribute the dataset for load among all workers
-                distribute_load(&mut query, &state).await?;
+                summary = distribute_load(&mut query, &state).await?;
             }
         }
         Some(ref workers) => {
@@ -83,18 +161,18 @@ pub(crate) async fn load(
                 // check if path exists
                 if !path.exists() {
                     tracing::warn!("File does not exist: {path:?}");
-                    return Err(AppError::NotFound);
+                    return Err(AppError::NotFound(format!("no file `{uri}`")));
                 }
 
                 // read points from file
-                let reader = LasDataSource::try_new(&[uri]).unwrap();
+                let reader = LasDataSource::try_new(&[uri]).context("Read LAS file")?;
 
                 let schema = reader.schema();
 
                 let step = Uniform::new(0, workers.len());
 
                 for batch in reader.record_batch_iter() {
-                    let batch = batch.unwrap();
+                    let batch = batch.context("Read LAS batch")?;
 
                     let fraction: Vec<usize> = SmallRng::seed_from_u64(0)
                         .sample_iter(step)
@@ -104,14 +182,15 @@ pub(crate) async fn load(
                     for (i, url) in workers.iter().enumerate() {
                         let filter =
                             BooleanArray::from_iter(fraction.iter().map(|f| Some(f == &i)));
-                        let selection = filter_record_batch(&batch, &filter).unwrap();
+                        let selection =
+                            filter_record_batch(&batch, &filter).context("Select fraction")?;
 
                         let mut writer = StreamWriter::try_new(Vec::new(), &schema)
                             .context("Create stream writer")?;
                         writer.write(&selection).context("Write batch to stream")?;
                         writer.finish().context("Finish stream")?;
 
-                        let buffer = writer.into_inner().unwrap();
+                        let buffer = writer.into_inner().context("Get stream buffer")?;
 
                         let url = format!("{}/load?{}", url, serde_qs::to_string(&query).unwrap());
 
@@ -119,21 +198,21 @@ pub(crate) async fn load(
                     }
 
                     while let Some(res) = set.join_next().await {
-                        let out = res.unwrap();
-                        out.unwrap().error_for_status().unwrap();
+                        let response = worker_response(res)?;
+                        summary = summary.add(response.json().await.unwrap_or_default());
                     }
                 }
             }
         }
     }
 
-    Ok(StatusCode::OK.into_response())
+    Ok(Json(summary))
 }
 
 async fn distribute_load(
     query: &mut LoadRequest,
     state: &SharedState,
-) -> Result<Response, AppError> {
+) -> Result<IngestSummary, AppError> {
     let workers = state.read().await.workers.to_owned();
     tracing::debug!("Distribute load among {} workers", workers.len());
 
@@ -172,15 +251,46 @@ async fn distribute_load(
         set.spawn(reqwest::get(url));
     }
 
+    let mut summary = IngestSummary::default();
     while let Some(res) = set.join_next().await {
-        let out = res.unwrap();
-        out.unwrap().error_for_status().unwrap();
+        let response = worker_response(res)?;
+        summary = summary.add(response.json().await.unwrap_or_default());
     }
 
-    Ok(StatusCode::OK.into_response())
+    Ok(summary)
+}
+
+/// Successful response of a worker, its failure is an internal error
+pub(crate) fn worker_response(
+    result: Result<reqwest::Result<reqwest::Response>, JoinError>,
+) -> anyhow::Result<reqwest::Response> {
+    let response = result
+        .context("Join worker request")?
+        .context("Request worker")?;
+    response.error_for_status().context("Worker failed")
 }
 
-async fn load_fraction(query: &LoadRequest, state: &SharedState) -> Result<Response, AppError> {
+/// Batches of the configured chunk size, unless it is 0
+fn chunks(
+    batches: impl IntoIterator<Item = RecordBatch>,
+    chunk_size: usize,
+) -> Result<Vec<RecordBatch>, AppError> {
+    if chunk_size == 0 {
+        return Ok(batches.into_iter().collect());
+    }
+    Ok(rechunk(batches, chunk_size).context("Rechunk batches")?)
+}
+
+async fn load_fraction(
+    query: &LoadRequest,
+    state: &SharedState,
+) -> Result<IngestSummary, AppError> {
+    let (chunk_size, drop_nonfinite) = {
+        let config = &state.read().await.config;
+        (config.chunk_size, config.drop_nonfinite)
+    };
+    let mut summary = IngestSummary::default();
+
     for uri in &query.uris {
         tracing::info!("Processing uri {}", &uri);
 
@@ -189,11 +299,11 @@ async fn load_fraction(query: &LoadRequest, state: &SharedState) -> Result<Respo
         // check if path exists
         if !path.exists() {
             tracing::warn!("File does not exist: {path:?}");
-            return Err(AppError::NotFound);
+            return Err(AppError::NotFound(format!("no file `{uri}`")));
         }
 
         // load points from file
-        let reader = LasDataSource::try_new(&[uri]).unwrap();
+        let reader = LasDataSource::try_new(&[uri]).context("Read LAS file")?;
 
         let schema =
             crux_format::schema::add_importance(reader.schema(), "i", DataType::Float32, 0);
@@ -205,7 +315,7 @@ async fn load_fraction(query: &LoadRequest, state: &SharedState) -> Result<Respo
         tracing::info!("Set fraction to {fraction:?}");
 
         let handle = Handle::current();
-        reader.par_record_batch_iter().for_each(|batch| {
+        let loaded = reader.par_record_batch_iter().map(|batch| {
             let mut batch = batch.unwrap();
             // apply fraction
             if let Some(fraction) = &fraction {
@@ -222,24 +332,50 @@ async fn load_fraction(query: &LoadRequest, state: &SharedState) -> Result<Respo
             // add importance
             batch = crux_format::compute::add_importance(batch, &schema).unwrap();
 
-            handle.block_on(insert_batch(batch, query, state));
+            // batches are read in parallel, so large ones are split only
+            chunks([batch], chunk_size)
+                .unwrap()
+                .into_iter()
+                .map(|batch| handle.block_on(insert_batch(batch, query, drop_nonfinite, state)))
+                .fold(IngestSummary::default(), IngestSummary::add)
         });
+        summary = summary.add(loaded.reduce(IngestSummary::default, IngestSummary::add));
     }
 
-    if query.store.is_some() {
-        state
-            .read()
-            .await
-            .data
-            .get(query.collection.as_ref().unwrap())
-            .unwrap()
-            .flush();
-    }
+    commit(query, state).await;
+    report(query, &summary);
 
-    Ok(StatusCode::OK.into_response())
+    Ok(summary)
 }
 
-async fn insert_batch(batch: RecordBatch, query: &LoadRequest, state: &SharedState) {
+/// Insert the points of `batch` into the collection of the request, counting
+/// points with non-finite coordinates and dropping them if `drop_nonfinite`.
+///
+/// Gridded collections always drop them, they lie in no cell.
+async fn insert_batch(
+    batch: RecordBatch,
+    query: &LoadRequest,
+    drop_nonfinite: bool,
+    state: &SharedState,
+) -> IngestSummary {
+    let mask = finite_mask(&batch).unwrap();
+    let nonfinite = batch.num_rows() - mask.true_count();
+    let dropped = if drop_nonfinite || query.delta.is_some() {
+        nonfinite
+    } else {
+        0
+    };
+    let batch = if dropped > 0 {
+        filter_record_batch(&batch, &mask).unwrap()
+    } else {
+        batch
+    };
+    let summary = IngestSummary {
+        points: batch.num_rows(),
+        nonfinite,
+        dropped,
+    };
+
     // set delta
     if let Some(delta) = &query.delta {
         let delta = Point::from_slice(delta);
@@ -253,63 +389,95 @@ async fn insert_batch(batch: RecordBatch, query: &LoadRequest, state: &SharedSta
                 continue;
             }
 
-            state
-                .write()
-                .await
+            let mut state = state.write().await;
+            let remote = state.remote.clone();
+            let collection = state
                 .data
                 .entry(query.collection.clone().unwrap())
-                .or_insert_with(|| {
-                    if let Some(store) = query.store.as_ref() {
-                        let capacity = 1000; // Cells
-                        let store =
-                            PointCloudStore::try_new(capacity, store, query.compress).unwrap();
-                        ArrowPointCloud::try_new_with(partition.schema(), store).unwrap()
-                    } else {
-                        ArrowPointCloud::try_new(partition.schema()).unwrap()
-                    }
-                })
-                .store
-                .push(cell.id(), partition);
+                .or_insert_with(|| query.new_collection(partition.schema(), remote.as_ref()));
+            collection.store.push(cell.id(), partition);
+            collection.touch();
         }
     } else {
-        state
-            .write()
-            .await
+        let mut state = state.write().await;
+        let remote = state.remote.clone();
+        let collection = state
             .data
             .entry(query.collection.clone().unwrap())
-            .or_insert_with(|| {
-                if let Some(store) = query.store.as_ref() {
-                    let capacity = 1000; // Cells
-                    let store = PointCloudStore::try_new(capacity, store, query.compress).unwrap();
-                    ArrowPointCloud::try_new_with(batch.schema(), store).unwrap()
-                } else {
-                    ArrowPointCloud::try_new(batch.schema()).unwrap()
-                }
-            })
+            .or_insert_with(|| query.new_collection(batch.schema(), remote.as_ref()));
+        collection
             .store
             .push(uuid::Uuid::new_v4().to_string(), batch);
+        collection.touch();
     }
+
+    summary
 }
 
 pub(crate) async fn push_batch(
-    Qs(query): Qs<LoadRequest>,
+    Qs(mut query): Qs<LoadRequest>,
     Extension(state): Extension<SharedState>,
     body: Bytes,
-) {
-    let reader = StreamReader::try_new(std::io::Cursor::new(body), None).unwrap();
+) -> Result<Json<IngestSummary>, AppError> {
+    query.set_default_store(&state).await;
+
+    let invalid = |e: ArrowError| AppError::BadRequest(format!("invalid Arrow IPC stream: {e}"));
+    let reader = StreamReader::try_new(std::io::Cursor::new(body), None).map_err(invalid)?;
 
     let schema = crux_format::schema::add_importance(reader.schema(), "i", DataType::Float32, 0);
 
-    for batch in reader {
-        let batch = crux_format::compute::add_importance(batch.unwrap(), &schema).unwrap();
-        insert_batch(batch, &query, &state).await;
+    let (chunk_size, drop_nonfinite) = {
+        let config = &state.read().await.config;
+        (config.chunk_size, config.drop_nonfinite)
+    };
+    let batches = reader
+        .map(|batch| crux_format::compute::add_importance(batch?, &schema))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(invalid)?;
+    let mut summary = IngestSummary::default();
+    for batch in chunks(batches, chunk_size)? {
+        summary = summary.add(insert_batch(batch, &query, drop_nonfinite, &state).await);
     }
+
+    commit(&query, &state).await;
+    report(&query, &summary);
+
+    Ok(Json(summary))
 }
 
-#[serde_as]
-#[derive(Serialize, Deserialize, Clone, Debug)]
-pub(crate) struct DeleteRequest {
-    collection: Option<String>,
+/// Warn about points with non-finite coordinates
+fn report(query: &LoadRequest, summary: &IngestSummary) {
+    if summary.nonfinite > 0 {
+        tracing::warn!(
+            "{} points with non-finite coordinates in `{}`, {} dropped",
+            summary.nonfinite,
+            query.collection.as_deref().unwrap_or_default(),
+            summary.dropped
+        );
+    }
+}
+
+/// Commit the loaded points as a new version of the collection and persist it
+/// if it is stored, then refresh its column statistics and preview
+async fn commit(query: &LoadRequest, state: &SharedState) {
+    let name = query.collection.as_ref().unwrap();
+    let pc = {
+        let mut state = state.write().await;
+        let retention = Retention::from(&state.config);
+        state.data.get_mut(name).map(|collection| {
+            collection.commit(retention);
+            collection.revalidate_views(name);
+            if query.store.is_some() {
+                collection.persist();
+            }
+            collection.snapshot()
+        })
+    };
+    // the appends keep the statistics exact except for their percentiles
+    if let Some(pc) = pc {
+        tokio::task::spawn_blocking(move || pc.refresh_stats());
+    }
+    jobs::refresh_preview(state, name).await;
 }
 
 #[axum::debug_handler]
@@ -325,9 +493,8 @@ pub(crate) async fn delete(
     // Distribute or load
     match query.workers {
         None => {
-            if let Some(pc) = state.write().await.data.remove(&query.collection.unwrap()) {
-                std::fs::remove_dir_all(pc.store.dir).unwrap();
-            };
+            // segments are removed by the garbage collector once unreferenced
+            state.write().await.remove(&query.collection.unwrap());
         }
         Some(ref workers) => {
             // dirstribute request
@@ -342,11 +509,119 @@ pub(crate) async fn delete(
             }
 
             while let Some(res) = set.join_next().await {
-                let out = res.unwrap();
-                out.unwrap().error_for_status().unwrap();
+                worker_response(res)?;
             }
         }
     }
 
     Ok(StatusCode::OK.into_response())
 }
+
+#[cfg(test)]
+mod tests {
+    use arrow::ipc::reader::StreamReader;
+    use axum::{
+        body::Body,
+        http::{Method, StatusCode},
+    };
+    use clap::Parser;
+    use http_body_util::BodyExt;
+
+    use super::IngestSummary;
+    use crate::{
+        handlers::testing::{grid, send},
+        Config,
+    };
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn chunk_size() {
+        let app = crate::app(Config::parse_from(["crux-server", "--chunk-size", "30"]));
+
+        // one batch of 100 points and ten of 2 points
+        for (collection, body) in [("large", grid(1, 100)), ("small", grid(10, 2))] {
+            let uri = format!("/load?collection={collection}");
+            let response = send(&app, Method::POST, &uri, Body::from(body)).await;
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+
+        let rows = |collection: &'static str| {
+            let app = app.clone();
+            async move {
+                let uri = format!("/points?collection={collection}&p=1");
+                let response = send(&app, Method::GET, &uri, Body::empty()).await;
+                let body = response.into_body().collect().await.unwrap().to_bytes();
+                let mut rows: Vec<usize> = StreamReader::try_new(std::io::Cursor::new(body), None)
+                    .unwrap()
+                    .map(|batch| batch.unwrap().num_rows())
+                    .collect();
+                rows.sort();
+                rows
+            }
+        };
+        assert_eq!(rows("large").await, [10, 30, 30, 30]);
+        assert_eq!(rows("small").await, [20]);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn nonfinite() {
+        use std::sync::Arc;
+
+        use arrow::{
+            array::{ArrayRef, Float64Array},
+            ipc::writer::StreamWriter,
+            record_batch::RecordBatch,
+        };
+        use crux_format::{Point, PointTrait};
+
+        // NaN and infinite coordinates at the first and last rows of batches
+        let schema = Point::<f64, 3>::schema();
+        let mut writer = StreamWriter::try_new(Vec::new(), &schema).unwrap();
+        for i in 0..3 {
+            let x = Float64Array::from_iter_values((0..10).map(|j| match j {
+                0 => f64::NAN,
+                _ => j as f64,
+            }));
+            let y = Float64Array::from_iter_values((0..10).map(|j| match j {
+                9 if i == 1 => f64::NEG_INFINITY,
+                _ => i as f64,
+            }));
+            let z = Float64Array::from_iter_values((0..10).map(|j| j as f64));
+            let columns = vec![Arc::new(x) as ArrayRef, Arc::new(y), Arc::new(z)];
+            writer
+                .write(&RecordBatch::try_new(schema.clone(), columns).unwrap())
+                .unwrap();
+        }
+        let body = writer.into_inner().unwrap();
+
+        for (args, expected) in [(vec![], (30, 4, 0)), (vec!["--drop-nonfinite"], (26, 4, 4))] {
+            let app = crate::app(Config::parse_from(
+                [vec!["crux-server", "--chunk-size", "0"], args].concat(),
+            ));
+            let uri = "/load?collection=default";
+            let response = send(&app, Method::POST, uri, Body::from(body.clone())).await;
+            assert_eq!(response.status(), StatusCode::OK);
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            let summary: IngestSummary = serde_json::from_slice(&body).unwrap();
+            let (points, nonfinite, dropped) = expected;
+            assert_eq!(
+                summary,
+                IngestSummary {
+                    points,
+                    nonfinite,
+                    dropped
+                }
+            );
+
+            // the bounds of the collection are finite
+            let uri = "/collections/default/stats";
+            let response = send(&app, Method::GET, uri, Body::empty()).await;
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            let stats: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(stats["num_points"], points);
+            assert_eq!(
+                stats["bounds"],
+                serde_json::json!([[1., 0., 1.], [9., 2., 9.]])
+            );
+        }
+    }
+}
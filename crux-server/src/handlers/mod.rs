@@ -1,11 +1,23 @@
+mod collections;
+mod diff;
 mod index;
+mod jobs;
 mod load;
 mod points;
 mod status;
+#[cfg(test)]
+pub(crate) mod testing;
+mod tiles;
+mod views;
 mod worker;
 
+pub(crate) use collections::*;
+pub(crate) use diff::*;
 pub(crate) use index::*;
+pub(crate) use jobs::*;
 pub(crate) use load::*;
 pub(crate) use points::*;
 pub(crate) use status::*;
+pub(crate) use tiles::*;
+pub(crate) use views::*;
 pub(crate) use worker::*;
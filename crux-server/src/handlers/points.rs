@@ -1,60 +1,505 @@
 use std::{
-    io::{BufReader, Cursor},
-    sync::RwLock,
+    collections::HashSet,
+    io::{BufReader, Cursor, Write},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, RwLock,
+    },
+    time::{Duration, Instant},
 };
 
 use anyhow::Context;
-use arrow::ipc::{reader::StreamReader, writer::StreamWriter};
+use arrow::{
+    array::{Array, ArrayRef, AsArray, StringArray},
+    compute::cast,
+    datatypes::{DataType, Float64Type, SchemaRef},
+    error::ArrowError,
+    ipc::{reader::StreamReader, writer::StreamWriter},
+    record_batch::RecordBatch,
+};
 use axum::{
-    body::Bytes,
-    http::header::CONTENT_TYPE,
+    body::{Body, Bytes},
+    extract::Path,
+    http::{
+        header::{ACCEPT, CONTENT_TYPE},
+        HeaderMap, HeaderName, HeaderValue,
+    },
     response::{IntoResponse, Response},
-    Extension,
+    Extension, Json,
 };
 use once_cell::sync::OnceCell;
 use rayon::iter::{IntoParallelRefIterator, ParallelBridge, ParallelIterator};
-use rstar::Envelope;
 use serde::{Deserialize, Serialize};
 
-use crux_format::{compute::filter_by_aabb, soa::Index, Point, PointTrait, AABB};
+use crux_format::{
+    checksum::{CHECKSUM_HEADER, XXHASH64},
+    compute::{append_constant_dictionary, conform},
+    frustum, polygon, schema, ArrowPointCloud, ChecksumWriter, MetadataPolicy, Point,
+    PointCloudError, PointCloudTrait, PointTrait, ProgressSink, Query, Sample, AABB,
+};
 use serde_with::{formats::CommaSeparator, serde_as, StringWithSeparator};
-use tokio::runtime::Handle;
+use tokio::{runtime::Handle, sync::mpsc};
 
-use crate::{error::AppError, state::SharedState, Qs};
+use crate::{
+    error::AppError, etag, handlers::unknown_view, limits::Charge, state::SharedState, Qs,
+};
 
 #[serde_as]
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub(crate) struct BoxQuery {
     collection: Option<String>,
+    /// Several collections in one response, the batches of each labeled by a
+    /// dictionary encoded `collection` column
+    #[serde_as(as = "Option<StringWithSeparator::<CommaSeparator, String>>")]
+    collections: Option<Vec<String>>,
     #[serde_as(as = "Option<StringWithSeparator::<CommaSeparator, f64>>")]
     bounds: Option<Vec<f64>>,
     p: Option<f64>,
+    /// Sample a fraction `p` of the points with this seed instead of selecting
+    /// by importance. The response is identical for the same collection
+    /// version, `p` and seed.
+    seed: Option<u64>,
+    /// Sampling of the selected points, `stratified:<column>:<total>[:<min>]`
+    /// allocates `total` points across the values of a categorical column
+    /// with at least `min` points per value (1% of `total` by default),
+    /// `stable:<p>` selects a fraction `p` by a hash of the point identity
+    /// that is kept when the collection is appended to
+    sample: Option<String>,
     budget: Option<u64>,
+    /// Footprint as WKT polygon or flat coordinate list
+    polygon: Option<String>,
+    zmin: Option<f64>,
+    zmax: Option<f64>,
+    /// View frustum `x,y,z,dx,dy,dz,fov,aspect,near,far` of a camera at
+    /// `x,y,z` looking along `dx,dy,dz`, vertical field of view in degrees
+    frustum: Option<String>,
+    /// Sphere `x,y,z,r` of radius `r` around a point
+    #[serde_as(as = "Option<StringWithSeparator::<CommaSeparator, f64>>")]
+    near: Option<Vec<f64>>,
+    /// Circle `x,y,r` of radius `r` around a point, regardless of the height
+    #[serde_as(as = "Option<StringWithSeparator::<CommaSeparator, f64>>")]
+    near2d: Option<Vec<f64>>,
+    /// GPS time range `start,end`, the end is exclusive
+    #[serde_as(as = "Option<StringWithSeparator::<CommaSeparator, f64>>")]
+    time: Option<Vec<f64>>,
+    /// Attribute filter, comparisons like `classification=2,intensity>=100`
+    /// that all must hold
+    filter: Option<String>,
+    /// Columns of the response, the dimensions are always included
+    #[serde_as(as = "Option<StringWithSeparator::<CommaSeparator, String>>")]
+    columns: Option<Vec<String>>,
+    /// Version of the collection, see `/collections/{name}/versions`
+    at: Option<u64>,
+    /// Query the preview drawn after ingests instead of the collection
+    #[serde(default)]
+    preview: bool,
+    /// Response format, negotiated by the `Accept` header if missing
+    format: Option<PointsFormat>,
+    /// Suffix of the entity tag, distinguishing the definitions of a view
+    #[serde(skip)]
+    tag: Option<String>,
+}
+
+impl BoxQuery {
+    /// Parameters of a view, whose collection is given by the path and that
+    /// queries its latest version
+    pub(crate) fn check_view(&self) -> Result<Query, AppError> {
+        if self.collection.is_some() || self.collections.is_some() {
+            return Err(AppError::BadRequest(
+                "the collection of a view is given by the path".to_owned(),
+            ));
+        }
+        if self.at.is_some() {
+            return Err(AppError::BadRequest(
+                "views query the latest version, omit `at`".to_owned(),
+            ));
+        }
+        // all points without `p`, also of collections without importance
+        let selection = filters(self)?;
+        match (self.p, &self.sample, self.seed) {
+            (None, None, None) => Ok(selection),
+            _ => Ok(selection.sample(sampling(self)?)),
+        }
+    }
+
+    /// The query with the parameters set in `overrides` replaced
+    pub(crate) fn with_overrides(self, overrides: Self) -> Self {
+        Self {
+            collection: overrides.collection.or(self.collection),
+            collections: overrides.collections.or(self.collections),
+            bounds: overrides.bounds.or(self.bounds),
+            p: overrides.p.or(self.p),
+            seed: overrides.seed.or(self.seed),
+            sample: overrides.sample.or(self.sample),
+            budget: overrides.budget.or(self.budget),
+            polygon: overrides.polygon.or(self.polygon),
+            zmin: overrides.zmin.or(self.zmin),
+            zmax: overrides.zmax.or(self.zmax),
+            frustum: overrides.frustum.or(self.frustum),
+            near: overrides.near.or(self.near),
+            near2d: overrides.near2d.or(self.near2d),
+            time: overrides.time.or(self.time),
+            filter: overrides.filter.or(self.filter),
+            columns: overrides.columns.or(self.columns),
+            at: overrides.at.or(self.at),
+            preview: overrides.preview || self.preview,
+            format: overrides.format.or(self.format),
+            tag: overrides.tag.or(self.tag),
+        }
+    }
+}
+
+/// Encoding of the points response
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum PointsFormat {
+    /// Arrow IPC stream
+    Arrow,
+    /// JSON rows of the numeric attributes, for browser clients
+    Json,
+}
+
+impl PointsFormat {
+    /// Explicit format or JSON if accepted but Arrow is not
+    fn negotiate(format: Option<Self>, headers: &HeaderMap) -> Self {
+        if let Some(format) = format {
+            return format;
+        }
+
+        let accept = headers
+            .get(ACCEPT)
+            .and_then(|accept| accept.to_str().ok())
+            .unwrap_or_default();
+
+        if accept.contains(JSON_CONTENT_TYPE) && !accept.contains(ARROW_CONTENT_TYPE) {
+            Self::Json
+        } else {
+            Self::Arrow
+        }
+    }
+}
+
+const ARROW_CONTENT_TYPE: &str = "application/vnd.apache.arrow.stream";
+const JSON_CONTENT_TYPE: &str = "application/json";
+
+/// Points as rows of the numeric attributes
+#[derive(Serialize, Deserialize, Debug)]
+pub(crate) struct JsonPoints {
+    columns: Vec<String>,
+    points: Vec<Vec<Option<f64>>>,
+}
+
+impl JsonPoints {
+    fn try_new(batches: &[RecordBatch]) -> Result<Self, ArrowError> {
+        let Some(schema) = batches.first().map(|batch| batch.schema()) else {
+            return Ok(Self {
+                columns: Vec::new(),
+                points: Vec::new(),
+            });
+        };
+
+        let numeric: Vec<usize> = schema
+            .fields()
+            .iter()
+            .enumerate()
+            .filter(|(_, field)| field.data_type().is_numeric())
+            .map(|(i, _)| i)
+            .collect();
+
+        let mut points = Vec::with_capacity(batches.iter().map(|b| b.num_rows()).sum());
+        for batch in batches {
+            let columns = numeric
+                .iter()
+                .map(|i| cast(batch.column(*i), &DataType::Float64))
+                .collect::<Result<Vec<_>, _>>()?;
+            let columns: Vec<_> = columns
+                .iter()
+                .map(|column| column.as_primitive::<Float64Type>())
+                .collect();
+
+            points.extend((0..batch.num_rows()).map(|row| {
+                columns
+                    .iter()
+                    .map(|column| column.is_valid(row).then(|| column.value(row)))
+                    .collect()
+            }));
+        }
+
+        Ok(Self {
+            columns: numeric
+                .iter()
+                .map(|i| schema.field(*i).name().to_owned())
+                .collect(),
+            points,
+        })
+    }
+}
+
+/// JSON response of at most `limit` points
+fn json_response(batches: &[RecordBatch], limit: usize) -> Result<Response, AppError> {
+    let num_points: usize = batches.iter().map(|batch| batch.num_rows()).sum();
+    if num_points > limit {
+        return Err(AppError::PayloadTooLarge(format!(
+            "{num_points} points exceed the JSON limit of {limit}, use the Arrow format or narrow the query"
+        )));
+    }
+
+    let points = JsonPoints::try_new(batches).context("Convert points to JSON")?;
+
+    Ok(Json(points).into_response())
+}
+
+/// Stratified sample of a categorical column
+#[derive(Debug, Clone, PartialEq)]
+struct Stratified {
+    column: String,
+    total: usize,
+    min_per_class: usize,
+}
+
+impl FromStr for Stratified {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            AppError::BadRequest(format!(
+                "invalid sample `{s}`, expected `stratified:<column>:<total>[:<min>]` or `stable:<p>`"
+            ))
+        };
+
+        let mut parts = s.split(':');
+        if parts.next() != Some("stratified") {
+            return Err(invalid());
+        }
+        let column = parts.next().filter(|c| !c.is_empty()).ok_or_else(invalid)?;
+        let total: usize = parts
+            .next()
+            .and_then(|n| n.parse().ok())
+            .ok_or_else(invalid)?;
+        let min_per_class = match parts.next() {
+            Some(n) => n.parse().map_err(|_| invalid())?,
+            None => total / 100,
+        };
+        if parts.next().is_some() {
+            return Err(invalid());
+        }
+
+        Ok(Self {
+            column: column.to_owned(),
+            total,
+            min_per_class,
+        })
+    }
+}
+
+impl From<Stratified> for Sample {
+    fn from(stratified: Stratified) -> Self {
+        Sample::Stratified {
+            column: stratified.column,
+            total: stratified.total,
+            min_per_class: stratified.min_per_class,
+        }
+    }
+}
+
+/// Sample of the `sample` parameter, stable or stratified
+fn parse_sample(s: &str) -> Result<Sample, AppError> {
+    let Some(p) = s.strip_prefix("stable:") else {
+        return Ok(Stratified::from_str(s)?.into());
+    };
+    match p.parse::<f64>() {
+        Ok(p) if (0. ..=1.).contains(&p) => Ok(Sample::Stable(p)),
+        _ => Err(AppError::BadRequest(format!(
+            "invalid sample `{s}`, expected `stable:<p>` with `p` between 0 and 1"
+        ))),
+    }
+}
+
+/// Translate the query parameters into a query of the format
+fn selection(query: &BoxQuery) -> Result<Query, AppError> {
+    Ok(filters(query)?.sample(sampling(query)?))
+}
+
+/// The query of the parameters other than the sampling
+fn filters(query: &BoxQuery) -> Result<Query, AppError> {
+    let invalid = |e: PointCloudError| AppError::BadRequest(e.to_string());
+    let mut selection = Query::new();
+
+    // the fourth dimension of the bounds is given by `p`
+    if let Some(bounds) = &query.bounds {
+        let invalid = |detail: String| AppError::InvalidBounds {
+            detail,
+            bounds: bounds.to_owned(),
+        };
+        if bounds.len() < 6 || bounds.len() % 2 != 0 {
+            return Err(invalid(format!(
+                "bounds require lower and upper corner, got {} values",
+                bounds.len()
+            )));
+        }
+        let (lower, upper) = bounds.split_at(bounds.len() / 2);
+        if let Some(d) = (0..lower.len()).find(|d| lower[*d] > upper[*d]) {
+            return Err(invalid(format!(
+                "lower corner above the upper corner in dimension {}",
+                d + 1
+            )));
+        }
+        selection = selection.bounds(AABB::from_corners(
+            Point::<f64, 3>::from_slice(&lower[..3]),
+            Point::from_slice(&upper[..3]),
+        ));
+    }
+
+    if let Some(polygon) = &query.polygon {
+        let ring = polygon::parse(polygon)
+            .and_then(|ring| polygon::validate(&ring))
+            .map_err(invalid)?;
+        let z_range = (query.zmin.is_some() || query.zmax.is_some())
+            .then(|| query.zmin.unwrap_or(f64::MIN)..=query.zmax.unwrap_or(f64::MAX));
+        selection = selection.polygon(ring, z_range);
+    }
+
+    if let Some(frustum) = &query.frustum {
+        selection = selection.frustum(frustum::parse(frustum).map_err(invalid)?);
+    }
+
+    match (query.near.as_deref(), query.near2d.as_deref()) {
+        (Some(_), Some(_)) => {
+            return Err(AppError::BadRequest(
+                "`near` and `near2d` exclude each other".to_owned(),
+            ))
+        }
+        (Some(&[x, y, z, r]), None) => {
+            selection = selection.near(Point::<f64, 3>::from_slice(&[x, y, z]), r);
+        }
+        (None, Some(&[x, y, r])) => {
+            selection = selection.near(Point::<f64, 2>::from_slice(&[x, y]), r);
+        }
+        (Some(_), None) => return Err(AppError::BadRequest("`near` requires x,y,z,r".to_owned())),
+        (None, Some(_)) => return Err(AppError::BadRequest("`near2d` requires x,y,r".to_owned())),
+        (None, None) => (),
+    }
+
+    match query.time.as_deref() {
+        Some(&[start, end]) if start <= end => selection = selection.time(start..end),
+        Some(&[_, _]) => {
+            return Err(AppError::BadRequest(
+                "`time` starts after its end".to_owned(),
+            ))
+        }
+        Some(_) => return Err(AppError::BadRequest("`time` requires start,end".to_owned())),
+        None => (),
+    }
+
+    if let Some(filter) = &query.filter {
+        selection = selection.filter(filter.parse().map_err(invalid)?);
+    }
+
+    if let Some(columns) = &query.columns {
+        let columns: Vec<&str> = columns.iter().map(String::as_str).collect();
+        selection = selection.columns(&columns);
+    }
+
+    Ok(selection)
+}
+
+/// Sampling of the parameters, by importance unless `sample` or `seed` is
+/// given
+fn sampling(query: &BoxQuery) -> Result<Sample, AppError> {
+    let p = query.p.unwrap_or(1.);
+    match (query.sample.as_deref(), query.seed) {
+        (Some(sample), _) => parse_sample(sample),
+        (None, Some(seed)) => Ok(Sample::Seeded { p, seed }),
+        (None, None) => Ok(Sample::P(p)),
+    }
+}
+
+/// Reject a selection of columns that are missing in, or of a type not
+/// supported by, the `schema` of `collection`
+pub(crate) fn check_schema(
+    selection: &Query,
+    collection: &str,
+    schema: &SchemaRef,
+) -> Result<(), AppError> {
+    if let Some(column) = selection.missing_column(schema) {
+        return Err(AppError::UnknownColumn {
+            column: column.to_owned(),
+            collection: collection.to_owned(),
+        });
+    }
+    selection
+        .validate(schema)
+        .map_err(|e| AppError::BadRequest(format!("`{collection}`: {e}")))
 }
 
 #[axum::debug_handler]
 pub(crate) async fn points(
     Extension(state): Extension<SharedState>,
+    charge: Option<Extension<Charge>>,
+    headers: HeaderMap,
     Qs(mut query): Qs<BoxQuery>,
 ) -> Result<Response, AppError> {
+    let charge = charge.map_or_else(Charge::none, |Extension(charge)| charge);
+
     // Set default collection (FIXME: should be collections and required)
-    query.collection.get_or_insert("default".to_string());
+    match &query.collections {
+        Some(_) if query.collection.is_some() => {
+            return Err(AppError::BadRequest(
+                "`collection` and `collections` exclude each other".to_owned(),
+            ))
+        }
+        _ if query.preview && query.at.is_some() => {
+            return Err(AppError::BadRequest(
+                "previews are not versioned, omit `at`".to_owned(),
+            ))
+        }
+        Some(_) if query.at.is_some() => {
+            return Err(AppError::BadRequest(
+                "versions of several collections are not supported, omit `at`".to_owned(),
+            ))
+        }
+        Some(collections)
+            if collections.is_empty()
+                || collections.iter().collect::<HashSet<_>>().len() < collections.len() =>
+        {
+            return Err(AppError::BadRequest(
+                "`collections` requires distinct names".to_owned(),
+            ))
+        }
+        Some(_) => (),
+        None => {
+            query.collection.get_or_insert("default".to_string());
+        }
+    }
 
-    // get extent
-    let mut lower: Point<f64, 4> = query.bounds.as_ref().map_or_else(
-        || Point::from_slice(&[f64::MIN; 4]),
-        |c| Point::from_slice(&c[..(c.len() / 2)]),
-    );
-    let mut upper: Point<f64, 4> = query.bounds.as_ref().map_or_else(
-        || Point::from_slice(&[f64::MAX; 4]),
-        |c| Point::from_slice(&c[(c.len() / 2)..]),
-    );
+    let (default_p, max_points, max_json_points, budget) = {
+        let state = state.read().await;
+        let config = &state.config;
+        let budget = Budget::new(config.query_timeout, state.scanned.clone());
+        (
+            config.default_p,
+            config.max_points,
+            config.max_json_points,
+            budget,
+        )
+    };
+    query.p.get_or_insert(default_p);
 
-    *rstar::Point::nth_mut(&mut lower, 3) = 0.;
-    *rstar::Point::nth_mut(&mut upper, 3) = query.p.unwrap_or(1.);
+    // workers always respond with Arrow
+    let format = PointsFormat::negotiate(query.format.take(), &headers);
+    if query.collections.is_some() && format == PointsFormat::Json {
+        return Err(AppError::BadRequest(
+            "`collections` requires the Arrow format".to_owned(),
+        ));
+    }
+    let checksums = format == PointsFormat::Arrow
+        && headers
+            .get(CHECKSUM_HEADER)
+            .is_some_and(|value| value == XXHASH64);
 
-    let aabb: AABB<Point<f64, 4>> = AABB::from_corners(lower, upper);
-    tracing::debug!("{query:#?}");
+    let selection = selection(&query)?;
+    tracing::debug!("{selection:#?}");
 
     // setup writer
     let mut writer: OnceCell<RwLock<StreamWriter<Vec<u8>>>> = OnceCell::new();
@@ -77,130 +522,118 @@ pub(crate) async fn points(
                     )
                 });
                 writer.write().unwrap().write(batch).unwrap();
+                charge.points(batch.num_rows());
             })
         });
     } else {
-        // Execute query
-        let collection = query.collection.as_ref().unwrap();
+        // Execute query on snapshots, so that the collections may be deleted or
+        // republished while the response is streamed
+        let collections = match &query.collections {
+            Some(collections) => collections.to_owned(),
+            None => vec![query.collection.to_owned().unwrap()],
+        };
 
-        if !state.read().await.data.contains_key(collection) {
-            tracing::warn!("No data for collection `{collection}`");
-            return Err(AppError::NotFound);
+        let mut pcs = Vec::with_capacity(collections.len());
+        let mut etags = Vec::with_capacity(collections.len());
+        for collection in &collections {
+            let (pc, etag) = {
+                let state = state.read().await;
+                if query.preview {
+                    let preview = state.collection(collection)?.preview().ok_or_else(|| {
+                        tracing::warn!("No preview of collection `{collection}`");
+                        AppError::NotFound(format!("no preview of collection `{collection}`"))
+                    })?;
+                    (preview.snapshot(), preview.etag().to_owned())
+                } else {
+                    state.collection_at(collection, query.at)?
+                }
+            };
+
+            check_schema(&selection, collection, &pc.schema())?;
+            pcs.push(pc);
+            etags.push(etag);
         }
+        let collection = collections.join(",");
+        tracing::info!("Querying collection `{collection}`");
 
-        let state = state.read().await;
-        let pc = state.data.get(collection).unwrap();
-        match &pc.index {
-            Index::Point(_index) => {
-                todo!()
-                // tracing::info!("Querying point-indexed collection `{collection}`");
-
-                // let mut indices: Vec<u64> = state
-                //     .read()
-                //     .await
-                //     .index
-                //     .locate_in_envelope_intersecting(&aabb)
-                //     .map(|p| p.data)
-                //     .collect();
-                // indices.sort_unstable();
-
-                // if !indices.is_empty() {
-                //     let last = indices.last().unwrap().to_owned();
-
-                //     let mut i = 0;
-                //     let mut j = 0;
-
-                //     let mut cell_ids: Vec<String> = state
-                //         .read()
-                //         .await
-                //         .framework
-                //         .iter()
-                //         .map(|cell| cell.id())
-                //         .collect();
-                //     cell_ids.sort();
-
-                //     for id in cell_ids {
-                //         for batch in state
-                //             .read()
-                //             .await
-                //             .ctx
-                //             .table(id)
-                //             .await
-                //             .unwrap()
-                //             .collect()
-                //             .await
-                //             .unwrap()
-                //         {
-                //             let to = i + batch.num_rows() as u64;
-                //             let filter: Vec<bool> = (i..to)
-                //                 .map(|id| {
-                //                     if id > last {
-                //                         false
-                //                     } else if id == indices[j] {
-                //                         j += 1;
-                //                         true
-                //                     } else {
-                //                         false
-                //                     }
-                //                 })
-                //                 .collect();
-
-                //             i = to;
-
-                //             pcs.push(filter_record_batch(&batch, &filter.into()).unwrap());
-                //         }
-                //     }
-                // }
+        let labels = match query.collections {
+            Some(_) => Some(Labels::try_new(&collections, &pcs, &selection)?),
+            None => None,
+        };
+        // the versions of all collections
+        let mut etag = match &etags[..] {
+            [etag] => etag.to_owned(),
+            etags => {
+                let versions: Vec<&str> = etags.iter().map(|etag| etag.trim_matches('"')).collect();
+                format!("\"{}\"", versions.join("-"))
             }
-            Index::Batch(index) => {
-                tracing::info!("Querying block-indexed collection `{collection}`");
-                index
-                    .locate_in_envelope_intersecting(&aabb)
-                    .par_bridge()
-                    .for_each(|object| {
-                        pc.store
-                            .batches(&object.data)
-                            .into_iter()
-                            .for_each(|batch| {
-                                let batch = if aabb.contains_envelope(object.geom()) {
-                                    batch
-                                } else {
-                                    filter_by_aabb(&batch, &aabb)
-                                };
-                                let writer = writer.get_or_init(|| {
-                                    RwLock::new(
-                                        StreamWriter::try_new(Vec::new(), &batch.schema())
-                                            .context("Create stream writer")
-                                            .unwrap(),
-                                    )
-                                });
-                                writer.write().unwrap().write(&batch).unwrap();
-                            });
-                    })
+        };
+
+        // the query is part of the URL, only the format is negotiated
+        if format == PointsFormat::Json {
+            etag.insert_str(etag.len() - 1, "-json");
+        }
+        if checksums {
+            etag.insert_str(etag.len() - 1, "-xxh64");
+        }
+        if let Some(tag) = &query.tag {
+            etag.insert_str(etag.len() - 1, &format!("-{tag}"));
+        }
+        if etag::matches(&headers, &etag) {
+            return Ok(etag::not_modified(&etag));
+        }
+
+        if format == PointsFormat::Json {
+            // one more than the limit suffices to reject the request
+            let limit = max_json_points.min(max_points.unwrap_or(usize::MAX));
+            let selection = selection.limit(limit + 1);
+            let _disconnect = Disconnect::new(&budget);
+            let batches = {
+                let (pc, budget) = (pcs[0].clone(), budget.clone());
+                tokio::task::spawn_blocking(move || collect_points(&pc, &selection, &budget))
             }
-            Index::Multi(_) => todo!(),
-            Index::None => {
-                tracing::info!("Querying unindexed collection `{collection}`");
-                pc.store.par_iter().for_each(|e| {
-                    pc.store.batches(e.key()).par_iter().for_each(|batch| {
-                        let batch = filter_by_aabb(batch, &aabb);
-                        if batch.num_rows() > 0 {
-                            let writer = writer.get_or_init(|| {
-                                RwLock::new(
-                                    StreamWriter::try_new(Vec::new(), &batch.schema())
-                                        .context("Create stream writer")
-                                        .unwrap(),
-                                )
-                            });
-                            writer.write().unwrap().write(&batch).unwrap();
-                        }
-                    })
-                });
+            .await
+            .context("Join query task")?
+            .map_err(|e| budget.error(e))?;
+
+            let num_points: usize = batches.iter().map(|batch| batch.num_rows()).sum();
+            if let Some(max_points) = max_points.filter(|max| num_points > *max) {
+                return Err(AppError::TooManyPoints { limit: max_points });
             }
+            let response = json_response(&batches, max_json_points)?;
+            charge.points(num_points);
+            return Ok(etag::tag(response, &etag));
         }
-    };
 
-    let writer = writer.take().unwrap();
+        // reject instead of truncating the response, streamed responses
+        // cannot be rejected once started
+        let total: usize = pcs.iter().map(|pc| pc.num_points()).sum();
+        if let Some(max_points) = max_points.filter(|max| total > *max) {
+            let disconnect = Disconnect::new(&budget);
+            let num_points = {
+                let (pcs, selection) = (pcs.clone(), selection.clone().limit(max_points + 1));
+                let budget = budget.clone();
+                tokio::task::spawn_blocking(move || count_points(&pcs, &selection, &budget))
+            }
+            .await
+            .context("Join query task")?
+            .map_err(|e| budget.error(e))?;
+            disconnect.disarm();
+
+            if num_points > max_points {
+                return Err(AppError::TooManyPoints { limit: max_points });
+            }
+        }
+
+        let response = stream_points(
+            pcs, labels, selection, budget, collection, checksums, charge,
+        );
+        return Ok(etag::tag(response, &etag));
+    }
+
+    let Some(writer) = writer.take() else {
+        return Err(AppError::NotFound("no points from the workers".to_owned()));
+    };
 
     writer.write().unwrap().finish().context("Finish stream")?;
 
@@ -210,12 +643,333 @@ pub(crate) async fn points(
         .into_inner()
         .context("Get stream buffer")?;
 
-    let header = [(CONTENT_TYPE, "application/vnd.apache.arrow.stream")];
+    if format == PointsFormat::Json {
+        let batches = StreamReader::try_new(Cursor::new(buffer), None)
+            .and_then(|reader| reader.collect::<Result<Vec<_>, _>>())
+            .context("Decode worker points")?;
+        return json_response(&batches, max_json_points);
+    }
+
+    let header = [(CONTENT_TYPE, ARROW_CONTENT_TYPE)];
     let body: Bytes = buffer.into();
 
     Ok((header, body).into_response())
 }
 
+/// Points of the collection of the path, e.g. its preview with `preview=true`
+pub(crate) async fn collection_points(
+    Extension(state): Extension<SharedState>,
+    charge: Option<Extension<Charge>>,
+    Path(name): Path<String>,
+    headers: HeaderMap,
+    Qs(mut query): Qs<BoxQuery>,
+) -> Result<Response, AppError> {
+    if query.collection.is_some() || query.collections.is_some() {
+        return Err(AppError::BadRequest(
+            "the collection is given by the path".to_owned(),
+        ));
+    }
+    query.collection = Some(name);
+    points(Extension(state), charge, headers, Qs(query)).await
+}
+
+/// Points of a view of the collection of the path, parameters of the request
+/// override those of the view
+pub(crate) async fn view_points(
+    Extension(state): Extension<SharedState>,
+    charge: Option<Extension<Charge>>,
+    Path((name, view)): Path<(String, String)>,
+    headers: HeaderMap,
+    Qs(overrides): Qs<BoxQuery>,
+) -> Result<Response, AppError> {
+    if overrides.collection.is_some() || overrides.collections.is_some() {
+        return Err(AppError::BadRequest(
+            "the collection is given by the path".to_owned(),
+        ));
+    }
+    let definition = match state.read().await.collection(&name)?.views().get(&view) {
+        Some(definition) => definition.clone(),
+        None => return Err(unknown_view(&name, &view)),
+    };
+
+    let mut query = definition.query.with_overrides(overrides);
+    query.collection = Some(name);
+    query.tag = Some(definition.etag);
+    points(Extension(state), charge, headers, Qs(query)).await
+}
+
+/// Column labeling the points of several collections
+pub(crate) const COLLECTION_COLUMN: &str = "collection";
+
+/// Labels the batches of several collections with their collection, in the
+/// union of their response schemas
+struct Labels {
+    /// Union schema without the label
+    union: SchemaRef,
+    /// Schema of the response
+    schema: SchemaRef,
+    names: ArrayRef,
+}
+
+impl Labels {
+    fn try_new(
+        collections: &[String],
+        pcs: &[Arc<ArrowPointCloud>],
+        selection: &Query,
+    ) -> Result<Self, AppError> {
+        let invalid = |e: PointCloudError| AppError::BadRequest(e.to_string());
+        let schemas = pcs
+            .iter()
+            .map(|pc| selection.schema(&pc.schema()))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(invalid)?;
+        let union = schema::union(&schemas, MetadataPolicy::Strict).map_err(invalid)?;
+        if union.field_with_name(COLLECTION_COLUMN).is_ok() {
+            return Err(AppError::BadRequest(format!(
+                "column `{COLLECTION_COLUMN}` is reserved for the collection of the points"
+            )));
+        }
+
+        let names: ArrayRef = Arc::new(StringArray::from(collections.to_vec()));
+        let empty = RecordBatch::new_empty(union.clone());
+        let schema = append_constant_dictionary(&empty, COLLECTION_COLUMN, &names, 0)
+            .context("Label points")?
+            .schema();
+
+        Ok(Self {
+            union,
+            schema,
+            names,
+        })
+    }
+
+    /// Batch of the `i`th collection
+    fn label(&self, i: usize, batch: &RecordBatch) -> Result<RecordBatch, ArrowError> {
+        let batch = conform(batch, &self.union)?;
+        append_constant_dictionary(&batch, COLLECTION_COLUMN, &self.names, i)
+    }
+}
+
+/// Selected points of each snapshot, labeled if several are combined
+fn for_each_batch(
+    pcs: &[Arc<ArrowPointCloud>],
+    labels: Option<&Labels>,
+    selection: &Query,
+    budget: &Budget,
+    mut emit: impl FnMut(RecordBatch) -> Result<(), PointCloudError>,
+) -> Result<(), PointCloudError> {
+    for (i, pc) in pcs.iter().enumerate() {
+        pc.stream_with(selection, budget, |batch| match labels {
+            Some(labels) => emit(labels.label(i, &batch)?),
+            None => emit(batch),
+        })?;
+    }
+    Ok(())
+}
+
+/// Number of encoded messages buffered ahead of a slow client
+const STREAM_BUFFER: usize = 4;
+
+/// Limits of a running point query
+struct Budget {
+    deadline: Option<Instant>,
+    /// Set once the client is gone
+    disconnected: AtomicBool,
+    /// Store entries read by point queries of the server
+    scanned: Arc<AtomicUsize>,
+}
+
+impl Budget {
+    fn new(timeout: Option<u64>, scanned: Arc<AtomicUsize>) -> Arc<Self> {
+        Arc::new(Self {
+            deadline: timeout.map(|secs| Instant::now() + Duration::from_secs(secs)),
+            disconnected: AtomicBool::new(false),
+            scanned,
+        })
+    }
+
+    fn expired(&self) -> bool {
+        self.deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+    }
+
+    /// Error response of a failed query
+    fn error(&self, e: PointCloudError) -> AppError {
+        match e {
+            PointCloudError::Cancelled if self.expired() => AppError::ServiceUnavailable(
+                "query timed out, sample with `p` or narrow the query".to_owned(),
+            ),
+            e => anyhow::Error::from(e).context("Query points").into(),
+        }
+    }
+}
+
+impl ProgressSink for Budget {
+    fn report(&self, done: usize, _total: usize) {
+        if done > 0 {
+            self.scanned.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.disconnected.load(Ordering::Relaxed) || self.expired()
+    }
+}
+
+/// Cancels the query when dropped along with the response or the handler
+struct Disconnect(Option<Arc<Budget>>);
+
+impl Disconnect {
+    fn new(budget: &Arc<Budget>) -> Self {
+        Self(Some(budget.clone()))
+    }
+
+    /// Keep the query running when dropped
+    fn disarm(mut self) {
+        self.0 = None;
+    }
+}
+
+impl Drop for Disconnect {
+    fn drop(&mut self) {
+        if let Some(budget) = &self.0 {
+            budget.disconnected.store(true, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Stream the selected points of collection snapshots
+///
+/// The query is aborted once the client disconnects or the budget expires,
+/// the latter ends the stream with an error. With `checksums`, the stream is
+/// followed by the checksums of its batches, see [crux_format::checksum]. The
+/// points are charged to the client as they are sent.
+fn stream_points(
+    pcs: Vec<Arc<ArrowPointCloud>>,
+    labels: Option<Labels>,
+    selection: Query,
+    budget: Arc<Budget>,
+    collection: String,
+    checksums: bool,
+    charge: Charge,
+) -> Response {
+    let (tx, mut rx) = mpsc::channel(STREAM_BUFFER);
+    let disconnect = Disconnect::new(&budget);
+
+    tokio::task::spawn_blocking(move || {
+        let writer = ChannelWriter(tx.clone());
+        match write_points(
+            &pcs,
+            labels.as_ref(),
+            &selection,
+            &budget,
+            writer,
+            checksums,
+            &charge,
+        ) {
+            Ok(()) => (),
+            Err(PointCloudError::Cancelled) if budget.expired() => {
+                tracing::warn!("Stream of collection `{collection}` timed out");
+                let _ = tx.blocking_send(Err(std::io::Error::other("query timed out")));
+            }
+            Err(e) => {
+                tracing::debug!("Stream of collection `{collection}` aborted: {e}");
+                let _ = tx.blocking_send(Err(std::io::Error::other(e)));
+            }
+        }
+    });
+
+    let header = [(CONTENT_TYPE, ARROW_CONTENT_TYPE)];
+    let body = Body::from_stream(futures::stream::poll_fn(move |cx| {
+        let _disconnect = &disconnect;
+        rx.poll_recv(cx)
+    }));
+
+    let mut response = (header, body).into_response();
+    if checksums {
+        response.headers_mut().insert(
+            HeaderName::from_static(CHECKSUM_HEADER),
+            HeaderValue::from_static(XXHASH64),
+        );
+    }
+    response
+}
+
+fn write_points(
+    pcs: &[Arc<ArrowPointCloud>],
+    labels: Option<&Labels>,
+    selection: &Query,
+    budget: &Budget,
+    writer: ChannelWriter,
+    checksums: bool,
+    charge: &Charge,
+) -> Result<(), PointCloudError> {
+    let schema = match labels {
+        Some(labels) => labels.schema.clone(),
+        None => selection.schema(&pcs[0].schema())?,
+    };
+    if checksums {
+        let mut writer = ChecksumWriter::try_new(writer, &schema)?;
+        for_each_batch(pcs, labels, selection, budget, |batch| {
+            writer.write(&batch)?;
+            charge.points(batch.num_rows());
+            Ok(())
+        })?;
+        writer.finish()?;
+    } else {
+        let mut writer = StreamWriter::try_new(writer, &schema)?;
+        for_each_batch(pcs, labels, selection, budget, |batch| {
+            writer.write(&batch)?;
+            charge.points(batch.num_rows());
+            Ok(())
+        })?;
+        writer.finish()?;
+    }
+    Ok(())
+}
+
+fn collect_points(
+    pc: &ArrowPointCloud,
+    selection: &Query,
+    budget: &Budget,
+) -> Result<Vec<RecordBatch>, PointCloudError> {
+    let mut batches = Vec::new();
+    pc.stream_with(selection, budget, |batch| {
+        batches.push(batch);
+        Ok(())
+    })?;
+    Ok(batches)
+}
+
+fn count_points(
+    pcs: &[Arc<ArrowPointCloud>],
+    selection: &Query,
+    budget: &Budget,
+) -> Result<usize, PointCloudError> {
+    let mut num_points = 0;
+    for_each_batch(pcs, None, selection, budget, |batch| {
+        num_points += batch.num_rows();
+        Ok(())
+    })?;
+    Ok(num_points)
+}
+
+/// Writer forwarding encoded messages to a response body
+struct ChannelWriter(mpsc::Sender<std::io::Result<Bytes>>);
+
+impl Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0
+            .blocking_send(Ok(Bytes::copy_from_slice(buf)))
+            .map_err(|_| std::io::Error::from(std::io::ErrorKind::BrokenPipe))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
 async fn get_points(url: String) -> Result<StreamReader<BufReader<Cursor<Bytes>>>, AppError> {
     let response = reqwest::get(url).await.context("Request error")?;
 
@@ -230,3 +984,975 @@ async fn get_points(url: String) -> Result<StreamReader<BufReader<Cursor<Bytes>>
 
     Ok(reader)
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeMap, io::Cursor};
+
+    use arrow::{
+        array::{AsArray, StringArray},
+        datatypes::{Float64Type, Int32Type},
+        ipc::{reader::StreamReader, writer::StreamWriter},
+    };
+    use axum::{
+        body::{Body, Bytes},
+        http::{
+            header::{
+                ACCEPT, ACCESS_CONTROL_ALLOW_ORIGIN, ACCESS_CONTROL_REQUEST_METHOD, CONTENT_TYPE,
+                ETAG, IF_NONE_MATCH, ORIGIN,
+            },
+            HeaderValue, Method, Request, StatusCode,
+        },
+    };
+    use clap::Parser;
+    use crux_format::{
+        checksum::{verify, CHECKSUM_HEADER, XXHASH64},
+        ArrowPointCloud, PointCloudError, PointCloudTrait, Synthetic,
+    };
+    use http_body_util::BodyExt;
+    use tower::ServiceExt;
+
+    use super::{JsonPoints, COLLECTION_COLUMN};
+    use crate::{
+        handlers::testing::{drive, grid, problem, send},
+        Config,
+    };
+
+    async fn count(app: &axum::Router, uri: &str) -> usize {
+        let response = send(app, Method::GET, uri, Body::empty()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        StreamReader::try_new(std::io::Cursor::new(body), None)
+            .unwrap()
+            .map(|batch| batch.unwrap().num_rows())
+            .sum()
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn polygon() {
+        let app = crate::app(Config::parse_from(["crux-server"]));
+
+        let response = send(
+            &app,
+            Method::POST,
+            "/load?collection=grid",
+            Body::from(grid(10, 10)),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // lower left half of the grid
+        let triangle = "/points?collection=grid&polygon=0,0,10,0,0,10";
+        assert_eq!(count(&app, triangle).await, 45);
+
+        let wkt = "/points?collection=grid&polygon=POLYGON((0%200,10%200,0%2010,0%200))&zmax=19";
+        assert_eq!(count(&app, wkt).await, 9 + 8);
+
+        // degenerate
+        for polygon in ["0,0,1,1,0,0", "0,0,1,1,1,0,0,1", "0,0,1"] {
+            let uri = format!("/points?collection=grid&polygon={polygon}");
+            let response = send(&app, Method::GET, &uri, Body::empty()).await;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{polygon}");
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn frustum() {
+        let app = crate::app(Config::parse_from(["crux-server"]));
+
+        let response = send(
+            &app,
+            Method::POST,
+            "/load?collection=grid",
+            Body::from(grid(10, 10)),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // looking down from above, the far plane cuts off z < 50
+        let above = "/points?collection=grid&frustum=5,5,200,0,0,-1,90,1,1,150";
+        assert_eq!(count(&app, above).await, 50);
+
+        // narrow view of the first columns, within bounds
+        let narrow = "/points?collection=grid&frustum=0,5,200,0,0,-1,0.2,1,1,1000";
+        assert_eq!(count(&app, narrow).await, 0);
+        let narrow = "/points?collection=grid&frustum=0.5,0.5,200,0,0,-1,0.2,1,1,1000";
+        assert_eq!(count(&app, narrow).await, 1);
+        let bounded =
+            "/points?collection=grid&frustum=5,5,200,0,0,-1,90,1,1,150&bounds=0,0,0,0,10,10,60.5,1";
+        assert_eq!(count(&app, bounded).await, 11);
+
+        for frustum in [
+            "0,0,0,1,0,0,90,1,1",
+            "0,0,0,0,0,0,90,1,1,10",
+            "a,0,0,1,0,0,90,1,1,10",
+        ] {
+            let uri = format!("/points?collection=grid&frustum={frustum}");
+            let response = send(&app, Method::GET, &uri, Body::empty()).await;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{frustum}");
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn near() {
+        let app = crate::app(Config::parse_from(["crux-server"]));
+
+        let response = send(
+            &app,
+            Method::POST,
+            "/load?collection=grid",
+            Body::from(grid(10, 10)),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // the four points around the center, one row per batch
+        assert_eq!(count(&app, "/points?collection=grid&near2d=5,5,1").await, 4);
+        assert_eq!(
+            count(&app, "/points?collection=grid&near2d=5,5,0.7").await,
+            0
+        );
+        let upper = "/points?collection=grid&near2d=5,5,1&filter=z%3E=50";
+        assert_eq!(count(&app, upper).await, 2);
+
+        // the height counts in 3D, up to and including the radius
+        assert_eq!(
+            count(&app, "/points?collection=grid&near=4.5,4.5,44,0").await,
+            1
+        );
+        assert_eq!(
+            count(&app, "/points?collection=grid&near=4.5,4.5,45,0.99").await,
+            0
+        );
+        // (4.5, 4.5, 44) and (5.5, 4.5, 45)
+        assert_eq!(
+            count(&app, "/points?collection=grid&near=4.5,4.5,45,1").await,
+            2
+        );
+        // far above the grid in 3D, all of it in 2D
+        assert_eq!(
+            count(&app, "/points?collection=grid&near=4.5,4.5,1000,100").await,
+            0
+        );
+        assert_eq!(
+            count(&app, "/points?collection=grid&near2d=4.5,4.5,100").await,
+            100
+        );
+
+        // sampled by importance within the circle
+        let all = count(&app, "/points?collection=grid&near2d=5,5,3").await;
+        let sample = count(&app, "/points?collection=grid&near2d=5,5,3&p=0.5").await;
+        assert!(sample < all);
+
+        for near in [
+            "near=1,2,3",
+            "near2d=1,2",
+            "near=1,2,3,4&near2d=1,2,3",
+            "near2d=1,2,-1",
+        ] {
+            let uri = format!("/points?collection=grid&{near}");
+            let response = send(&app, Method::GET, &uri, Body::empty()).await;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{near}");
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn collections() {
+        let app = crate::app(Config::parse_from(["crux-server"]));
+
+        // a grid and a survey with more attributes
+        let survey = Synthetic::new(500)
+            .extent([0., 0., 0.], [10., 10., 10.])
+            .intensity(true)
+            .uniform()
+            .unwrap();
+        let mut writer = StreamWriter::try_new(Vec::new(), &survey.schema()).unwrap();
+        for e in survey.store.iter() {
+            for batch in survey.store.batches(e.key()) {
+                writer.write(&batch).unwrap();
+            }
+        }
+        for (collection, body) in [
+            ("grid", grid(10, 10)),
+            ("survey", writer.into_inner().unwrap()),
+        ] {
+            let uri = format!("/load?collection={collection}");
+            let response = send(&app, Method::POST, &uri, Body::from(body)).await;
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+
+        let per_collection = |body: Bytes| {
+            let reader = StreamReader::try_new(Cursor::new(body), None).unwrap();
+            let schema = reader.schema();
+            let mut counts = BTreeMap::new();
+            for batch in reader {
+                let batch = batch.unwrap();
+                let labels = batch
+                    .column_by_name(COLLECTION_COLUMN)
+                    .unwrap()
+                    .as_dictionary::<Int32Type>()
+                    .downcast_dict::<StringArray>()
+                    .unwrap();
+                for label in labels.into_iter() {
+                    *counts.entry(label.unwrap().to_owned()).or_insert(0) += 1;
+                }
+                // nulls for the attributes of the other collection
+                let intensity = batch.column_by_name("intensity").unwrap();
+                assert!(intensity.null_count() == 0 || intensity.null_count() == batch.num_rows());
+            }
+            (schema, counts)
+        };
+
+        for params in ["", "&bounds=0,0,-100,5,5,100", "&near2d=5,5,3&p=0.5&seed=1"] {
+            let uri = format!("/points?collections=grid,survey{params}");
+            let response = send(&app, Method::GET, &uri, Body::empty()).await;
+            assert_eq!(response.status(), StatusCode::OK);
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            let (schema, counts) = per_collection(body);
+            assert!(schema.field_with_name("intensity").unwrap().is_nullable());
+
+            // the parameters apply to each collection
+            for collection in ["grid", "survey"] {
+                let single = count(&app, &format!("/points?collection={collection}{params}")).await;
+                assert_eq!(counts.get(collection).copied().unwrap_or(0), single);
+            }
+            if params.is_empty() {
+                assert_eq!(counts["grid"], 100);
+                assert_eq!(counts["survey"], 500);
+            }
+        }
+
+        // with checksums
+        let request = Request::builder()
+            .uri("/points?collections=survey,grid&p=0.5")
+            .header(CHECKSUM_HEADER, XXHASH64)
+            .body(Body::empty())
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let etag = response.headers()[ETAG].to_str().unwrap().to_owned();
+        assert_eq!(etag.matches('-').count(), 2);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert!(verify(&body).unwrap());
+
+        for (uri, status) in [
+            ("/points?collections=grid,missing", StatusCode::NOT_FOUND),
+            ("/points?collections=grid,grid", StatusCode::BAD_REQUEST),
+            (
+                "/points?collections=grid&collection=grid",
+                StatusCode::BAD_REQUEST,
+            ),
+            (
+                "/points?collections=grid,survey&at=1",
+                StatusCode::BAD_REQUEST,
+            ),
+            (
+                "/points?collections=grid,survey&format=json",
+                StatusCode::BAD_REQUEST,
+            ),
+            // unknown in one of them
+            (
+                "/points?collections=grid,survey&filter=intensity%3E=0",
+                StatusCode::BAD_REQUEST,
+            ),
+        ] {
+            let response = send(&app, Method::GET, uri, Body::empty()).await;
+            assert_eq!(response.status(), status, "{uri}");
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn json() {
+        let app = crate::app(Config::parse_from([
+            "crux-server",
+            "--max-json-points",
+            "50",
+        ]));
+
+        let response = send(
+            &app,
+            Method::POST,
+            "/load?collection=grid",
+            Body::from(grid(10, 10)),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // preflight of a browser client
+        let request = Request::builder()
+            .method(Method::OPTIONS)
+            .uri("/points")
+            .header(ORIGIN, "http://localhost:8080")
+            .header(ACCESS_CONTROL_REQUEST_METHOD, "GET")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.headers().contains_key(ACCESS_CONTROL_ALLOW_ORIGIN));
+
+        // explicit format
+        let uri = "/points?collection=grid&bounds=0,0,0,0,5,3,100,1&format=json";
+        let response = send(&app, Method::GET, uri, Body::empty()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let points: JsonPoints = serde_json::from_slice(&body).unwrap();
+        assert_eq!(points.columns, ["i", "x", "y", "z"]);
+        assert_eq!(points.points.len(), 5 * 3);
+        assert!(points.points.iter().all(|p| p.len() == 4));
+
+        // content negotiation
+        let request = Request::builder()
+            .uri(uri.trim_end_matches("&format=json"))
+            .header(ACCEPT, "application/json")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[CONTENT_TYPE], "application/json");
+
+        // beyond the limit
+        let uri = "/points?collection=grid&format=json";
+        let response = send(&app, Method::GET, uri, Body::empty()).await;
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+        // arrow remains the default
+        assert_eq!(count(&app, "/points?collection=grid").await, 100);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn seed() {
+        let app = crate::app(Config::parse_from(["crux-server"]));
+
+        let response = send(
+            &app,
+            Method::POST,
+            "/load?collection=grid",
+            Body::from(grid(8, 100)),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = |uri: &'static str| async {
+            let response = send(&app, Method::GET, uri, Body::empty()).await;
+            assert_eq!(response.status(), StatusCode::OK);
+            response.into_body().collect().await.unwrap().to_bytes()
+        };
+        let rows = |body: &Bytes| {
+            StreamReader::try_new(std::io::Cursor::new(body.clone()), None)
+                .unwrap()
+                .flat_map(|batch| {
+                    let batch = batch.unwrap();
+                    let z = batch
+                        .column_by_name("z")
+                        .unwrap()
+                        .as_primitive::<Float64Type>();
+                    z.values().to_vec()
+                })
+                .collect::<Vec<f64>>()
+        };
+
+        let a = body("/points?collection=grid&p=0.5&seed=1").await;
+        assert_eq!(a, body("/points?collection=grid&p=0.5&seed=1").await);
+        assert_ne!(a, body("/points?collection=grid&p=0.5&seed=2").await);
+        assert!((300..500).contains(&rows(&a).len()));
+
+        // smaller fractions and bounds select subsets of the same sample
+        let sample = rows(&a);
+        let b = rows(&body("/points?collection=grid&p=0.2&seed=1").await);
+        assert!(b.len() < sample.len() && b.iter().all(|z| sample.contains(z)));
+        let c =
+            rows(&body("/points?collection=grid&bounds=0,0,0,0,50,4,1000,1&p=0.5&seed=1").await);
+        assert!(c.len() < sample.len() && c.iter().all(|z| sample.contains(z)));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn stable() {
+        use std::{collections::HashSet, sync::Arc};
+
+        use arrow::{
+            array::{ArrayRef, Float64Array},
+            record_batch::RecordBatch,
+        };
+        use crux_format::{Point, PointTrait};
+
+        let app = crate::app(Config::parse_from(["crux-server"]));
+
+        // points with distinct z, starting at `offset`
+        let points = |offset: usize| {
+            let schema = Point::<f64, 3>::schema();
+            let column = |f: &dyn Fn(usize) -> f64| {
+                Arc::new(Float64Array::from_iter_values((0..1000).map(f))) as ArrayRef
+            };
+            let batch = RecordBatch::try_new(
+                schema.clone(),
+                vec![
+                    column(&|i| (i % 100) as f64 + 0.5),
+                    column(&|i| (i / 100) as f64 + 0.5),
+                    column(&|i| (offset + i) as f64),
+                ],
+            )
+            .unwrap();
+            let mut writer = StreamWriter::try_new(Vec::new(), &schema).unwrap();
+            writer.write(&batch).unwrap();
+            Body::from(writer.into_inner().unwrap())
+        };
+        let sample = |uri: &'static str| async {
+            let response = send(&app, Method::GET, uri, Body::empty()).await;
+            assert_eq!(response.status(), StatusCode::OK);
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            StreamReader::try_new(std::io::Cursor::new(body), None)
+                .unwrap()
+                .flat_map(|batch| {
+                    let batch = batch.unwrap();
+                    let z = batch.column_by_name("z").unwrap();
+                    z.as_primitive::<Float64Type>().values().to_vec()
+                })
+                .map(|z| z as u64)
+                .collect::<HashSet<u64>>()
+        };
+
+        let response = send(&app, Method::POST, "/load?collection=grid", points(0)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let dense = sample("/points?collection=grid&sample=stable:0.1").await;
+        let sparse = sample("/points?collection=grid&sample=stable:0.01").await;
+        assert!((50..150).contains(&dense.len()), "{}", dense.len());
+        assert!(!sparse.is_empty() && sparse.len() < dense.len());
+        assert!(sparse.is_subset(&dense));
+
+        // appended points are sampled, previously sampled points are kept
+        let response = send(&app, Method::POST, "/load?collection=grid", points(1000)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let grown = sample("/points?collection=grid&sample=stable:0.1").await;
+        assert!(dense.is_subset(&grown));
+        assert!(grown.iter().any(|z| *z >= 1000));
+        assert!(sample("/points?collection=grid&sample=stable:0.01")
+            .await
+            .is_superset(&sparse));
+
+        for uri in [
+            "/points?collection=grid&sample=stable:2",
+            "/points?collection=grid&sample=stable:",
+        ] {
+            let response = send(&app, Method::GET, uri, Body::empty()).await;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{uri}");
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn preview() {
+        let dir = tempfile::tempdir().unwrap();
+        let app = crate::app(Config::parse_from([
+            "crux-server",
+            "--storage-dir",
+            dir.path().to_str().unwrap(),
+            "--preview-points",
+            "1000",
+            "--preview-refresh",
+            "0.5",
+        ]));
+        let load = || async {
+            let body = Body::from(grid(20, 500));
+            let response = send(&app, Method::POST, "/load?collection=grid", body).await;
+            assert_eq!(response.status(), StatusCode::OK);
+        };
+        let scanned = || async {
+            let response = send(&app, Method::GET, "/status", Body::empty()).await;
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            let status: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            status["scanned_segments"].as_u64().unwrap()
+        };
+        // entity tag and number of points of the preview, once drawn
+        let router = &app;
+        let preview = move |previous: Option<String>| async move {
+            let uri = "/collections/grid/points?preview=true";
+            for _ in 0..200 {
+                let response = send(router, Method::GET, uri, Body::empty()).await;
+                if response.status() == StatusCode::OK {
+                    let etag = response.headers()[ETAG].to_str().unwrap().to_owned();
+                    let body = response.into_body().collect().await.unwrap().to_bytes();
+                    let rows: usize = StreamReader::try_new(Cursor::new(body), None)
+                        .unwrap()
+                        .map(|batch| batch.unwrap().num_rows())
+                        .sum();
+                    if previous.as_ref() != Some(&etag) {
+                        return (etag, rows);
+                    }
+                } else {
+                    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+                }
+                tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+            }
+            panic!("no preview drawn");
+        };
+
+        // drawn after the ingest and served without reading the 20 entries of
+        // the store
+        load().await;
+        let (etag, rows) = preview(None).await;
+        assert!((850..=1150).contains(&rows), "{rows}");
+        let before = scanned().await;
+        preview(None).await;
+        assert!(scanned().await - before < 20);
+        assert!(dir.path().join("grid.preview").exists());
+
+        // drawn again once the appends exceed half of the points
+        load().await;
+        let (_, rows) = preview(Some(etag)).await;
+        assert!((850..=1150).contains(&rows), "{rows}");
+
+        for uri in [
+            "/collections/grid/points?preview=true&at=1",
+            "/collections/grid/points?collection=grid",
+        ] {
+            let response = send(&app, Method::GET, uri, Body::empty()).await;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{uri}");
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn filter() {
+        let app = crate::app(Config::parse_from(["crux-server"]));
+
+        let response = send(
+            &app,
+            Method::POST,
+            "/load?collection=grid",
+            Body::from(grid(10, 10)),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // the sixth row
+        let uri = "/points?collection=grid&filter=z%3E=50,z%3C60";
+        assert_eq!(count(&app, uri).await, 10);
+        let uri = "/points?collection=grid&filter=z!=0&bounds=0,0,0,0,2,2,100,1";
+        assert_eq!(count(&app, uri).await, 3);
+
+        // dimensions are always included
+        let uri = "/points?collection=grid&filter=x%3C1&columns=z&format=json";
+        let response = send(&app, Method::GET, uri, Body::empty()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let points: JsonPoints = serde_json::from_slice(&body).unwrap();
+        assert_eq!(points.columns, ["i", "x", "y", "z"]);
+        assert_eq!(points.points.len(), 10);
+
+        for uri in [
+            "/points?collection=grid&filter=z",
+            "/points?collection=grid&filter=intensity%3E1",
+            "/points?collection=grid&columns=intensity",
+            "/points?collection=grid&bounds=0,0,1,1",
+        ] {
+            let response = send(&app, Method::GET, uri, Body::empty()).await;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{uri}");
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn time() {
+        let app = crate::app(Config::parse_from(["crux-server"]));
+
+        // 10 seconds of 1000 points each
+        let response = send(
+            &app,
+            Method::POST,
+            "/load?collection=drive",
+            Body::from(drive(10_000, 1000)),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let in_range = count(&app, "/points?collection=drive&time=2.5,4.5").await;
+        let filtered = count(
+            &app,
+            "/points?collection=drive&filter=gps_time%3E=2.5,gps_time%3C4.5",
+        )
+        .await;
+        assert_eq!(in_range, filtered);
+        assert!((1900..2100).contains(&in_range), "{in_range}");
+        assert_eq!(count(&app, "/points?collection=drive&time=20,30").await, 0);
+
+        let response = send(
+            &app,
+            Method::POST,
+            "/load?collection=grid",
+            Body::from(grid(2, 2)),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        for uri in [
+            "/points?collection=drive&time=1",
+            "/points?collection=drive&time=2,1",
+            "/points?collection=drive&time=1,2,3",
+            "/points?collection=grid&time=1,2",
+        ] {
+            let response = send(&app, Method::GET, uri, Body::empty()).await;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{uri}");
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn etag() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = dir.path().join("store");
+        let app = crate::app(Config::parse_from(["crux-server"]));
+
+        let load = format!("/load?collection=grid&store={}", store.to_string_lossy());
+        let response = send(&app, Method::POST, &load, Body::from(grid(2, 5))).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let get = |uri: &'static str, etag: Option<HeaderValue>| {
+            let mut request = Request::builder().uri(uri);
+            if let Some(etag) = etag {
+                request = request.header(IF_NONE_MATCH, etag);
+            }
+            app.clone().oneshot(request.body(Body::empty()).unwrap())
+        };
+        let version = |etag: &HeaderValue| {
+            u64::from_str_radix(etag.to_str().unwrap().trim_matches('"'), 16).unwrap()
+        };
+
+        let uri = "/points?collection=grid&p=1";
+        let response = get(uri, None).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let etag = response.headers()[ETAG].clone();
+        // finish the stream
+        response.into_body().collect().await.unwrap();
+
+        // persisted with the store
+        let persisted = std::fs::read_to_string(store.join("VERSION")).unwrap();
+        assert_eq!(persisted.parse::<u64>().unwrap(), version(&etag));
+
+        let response = get(uri, Some(etag.clone())).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(response.headers()[ETAG], etag);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert!(body.is_empty());
+
+        // the JSON representation has its own tag
+        let response = get("/points?collection=grid&format=json", Some(etag.clone()))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_ne!(response.headers()[ETAG], etag);
+
+        let stats = "/collections/grid/stats";
+        let response = get(stats, Some(etag.clone())).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+
+        // appending a batch bumps the version
+        let response = send(&app, Method::POST, &load, Body::from(grid(1, 5))).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = get(uri, Some(etag.clone())).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(version(&response.headers()[ETAG]), version(&etag) + 1);
+        response.into_body().collect().await.unwrap();
+        let response = get(stats, Some(etag.clone())).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn checksums() {
+        let app = crate::app(Config::parse_from(["crux-server"]));
+        let response = send(
+            &app,
+            Method::POST,
+            "/load?collection=grid",
+            Body::from(grid(3, 50)),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let get = |checksums: bool| {
+            let mut request = Request::builder().uri("/points?collection=grid&p=1");
+            if checksums {
+                request = request.header(CHECKSUM_HEADER, XXHASH64);
+            }
+            app.clone().oneshot(request.body(Body::empty()).unwrap())
+        };
+
+        // old clients get the plain stream
+        let response = get(false).await.unwrap();
+        assert!(response.headers().get(CHECKSUM_HEADER).is_none());
+        let etag = response.headers()[ETAG].clone();
+        let plain = response.into_body().collect().await.unwrap().to_bytes();
+        assert!(!verify(&plain).unwrap());
+
+        let response = get(true).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[CHECKSUM_HEADER], XXHASH64);
+        assert_ne!(response.headers()[ETAG], etag);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert!(verify(&body).unwrap());
+        let pc = ArrowPointCloud::try_from_reader(Cursor::new(&body)).unwrap();
+        assert_eq!(pc.num_points(), 150);
+
+        // the same stream, followed by the checksums
+        assert_eq!(body[..plain.len()], plain[..]);
+
+        // a byte flipped by a proxy, the last one of the last batch before the
+        // end of stream marker
+        let mut corrupt = body.to_vec();
+        corrupt[plain.len() - 9] ^= 0x01;
+        let batches = StreamReader::try_new(Cursor::new(&plain), None)
+            .unwrap()
+            .count();
+        assert!(matches!(
+            ArrowPointCloud::try_from_reader(Cursor::new(&corrupt)),
+            Err(PointCloudError::ChecksumMismatch { batch_index }) if batch_index == batches - 1
+        ));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn stratified() {
+        use std::sync::Arc;
+
+        use arrow::{
+            array::{ArrayRef, Float64Array, UInt8Array},
+            datatypes::{DataType, Field, Schema},
+            ipc::writer::StreamWriter,
+            record_batch::RecordBatch,
+        };
+        use crux_format::{Point, PointTrait};
+
+        let app = crate::app(Config::parse_from(["crux-server"]));
+
+        // 1000 ground points with 5 powerline points among them
+        let mut fields: Vec<Field> = Point::<f64, 3>::schema()
+            .fields()
+            .iter()
+            .map(|field| field.as_ref().clone())
+            .collect();
+        fields.push(Field::new("classification", DataType::UInt8, false));
+        let schema = Arc::new(Schema::new_with_metadata(
+            fields,
+            Point::<f64, 3>::schema().metadata().clone(),
+        ));
+        let column = |f: fn(usize) -> f64| {
+            Arc::new(Float64Array::from_iter_values((0..1000).map(f))) as ArrayRef
+        };
+        let classes =
+            UInt8Array::from_iter_values((0..1000).map(|i| if i % 200 == 0 { 14 } else { 2 }));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                column(|i| (i % 100) as f64 + 0.5),
+                column(|i| (i / 100) as f64 + 0.5),
+                column(|i| i as f64),
+                Arc::new(classes),
+            ],
+        )
+        .unwrap();
+        let mut writer = StreamWriter::try_new(Vec::new(), &schema).unwrap();
+        writer.write(&batch).unwrap();
+        let body = writer.into_inner().unwrap();
+
+        let response = send(
+            &app,
+            Method::POST,
+            "/load?collection=lines",
+            Body::from(body),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let classes = |uri: &'static str| async {
+            let response = send(&app, Method::GET, uri, Body::empty()).await;
+            assert_eq!(response.status(), StatusCode::OK);
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            let points: JsonPoints = serde_json::from_slice(&body).unwrap();
+            let c = points
+                .columns
+                .iter()
+                .position(|c| c == "classification")
+                .unwrap();
+            points
+                .points
+                .iter()
+                .map(|point| point[c].unwrap() as u8)
+                .collect::<Vec<_>>()
+        };
+
+        // a 2% sample keeps all powerline points
+        let sample =
+            classes("/points?collection=lines&format=json&sample=stratified:classification:20:5")
+                .await;
+        assert_eq!(sample.len(), 20);
+        assert_eq!(sample.iter().filter(|c| **c == 14).count(), 5);
+
+        // within bounds, with the default minimum of one point
+        let sample = classes(
+            "/points?collection=lines&format=json&bounds=0,0,0,0,100,5,1000,1&sample=stratified:classification:100",
+        )
+        .await;
+        assert_eq!(sample.len(), 100);
+        assert!(sample.contains(&14));
+
+        for uri in [
+            "/points?collection=lines&sample=stratified:intensity:20",
+            "/points?collection=lines&sample=stratified:classification",
+            "/points?collection=lines&sample=random:20",
+        ] {
+            let response = send(&app, Method::GET, uri, Body::empty()).await;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{uri}");
+        }
+    }
+    #[tokio::test(flavor = "multi_thread")]
+    async fn max_points() {
+        let app = crate::app(Config::parse_from(["crux-server", "--max-points", "50"]));
+
+        let response = send(
+            &app,
+            Method::POST,
+            "/load?collection=grid",
+            Body::from(grid(10, 10)),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // rejected with a hint instead of truncated
+        for uri in [
+            "/points?collection=grid",
+            "/points?collection=grid&format=json",
+        ] {
+            let response = send(&app, Method::GET, uri, Body::empty()).await;
+            assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE, "{uri}");
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            let error: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(error["type"], "crux:too-many-points");
+            assert_eq!(error["limit"], 50);
+            assert!(error["hint"].as_str().unwrap().contains("bounds"));
+        }
+
+        assert_eq!(
+            count(&app, "/points?collection=grid&bounds=0,0,0,10,5,100").await,
+            50
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn problems() {
+        let app = crate::app(Config::parse_from(["crux-server", "--max-points", "50"]));
+        let response = send(
+            &app,
+            Method::POST,
+            "/load?collection=grid",
+            Body::from(grid(10, 10)),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        for (uri, kind, param) in [
+            (
+                "/points?collection=grid&bounds=0,0,1,1",
+                "crux:invalid-bounds",
+                Some(("bounds", serde_json::json!([0., 0., 1., 1.]))),
+            ),
+            (
+                "/points?collection=grid&bounds=5,0,0,0,1,10,10,1",
+                "crux:invalid-bounds",
+                None,
+            ),
+            (
+                "/points?collection=missing",
+                "crux:unknown-collection",
+                Some(("collection", "missing".into())),
+            ),
+            (
+                "/points?collections=grid,missing",
+                "crux:unknown-collection",
+                Some(("collection", "missing".into())),
+            ),
+            (
+                "/points?collection=grid&at=1234",
+                "crux:unknown-version",
+                Some(("at", 1234.into())),
+            ),
+            (
+                "/points?collection=grid&filter=intensity%3E1",
+                "crux:unknown-column",
+                Some(("column", "intensity".into())),
+            ),
+            (
+                "/points?collection=grid&columns=x,classification",
+                "crux:unknown-column",
+                Some(("column", "classification".into())),
+            ),
+            (
+                "/points?collection=grid",
+                "crux:too-many-points",
+                Some(("limit", 50.into())),
+            ),
+            ("/points?collection=grid&near=1,2", "crux:bad-request", None),
+            // rejected by the query string extractor
+            ("/points?collection=grid&p=many", "crux:bad-request", None),
+            (
+                "/collections/missing/stats",
+                "crux:unknown-collection",
+                None,
+            ),
+            ("/jobs/missing", "crux:not-found", None),
+        ] {
+            let response = send(&app, Method::GET, uri, Body::empty()).await;
+            let problem = problem(response).await;
+            assert_eq!(problem.kind, kind, "{uri}");
+            assert!(!problem.detail.is_empty(), "{uri}");
+            if let Some((name, value)) = param {
+                assert_eq!(problem.params[name], value, "{uri}");
+            }
+        }
+
+        // uploads that are no Arrow IPC stream
+        let response = send(
+            &app,
+            Method::POST,
+            "/load?collection=broken",
+            Body::from("x,y,z"),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let problem = problem(response).await;
+        assert!(problem.detail.starts_with("invalid Arrow IPC stream"));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn disconnect() {
+        let app = crate::app(Config::parse_from(["crux-server", "--chunk-size", "0"]));
+
+        let response = send(
+            &app,
+            Method::POST,
+            "/load?collection=grid",
+            Body::from(grid(2000, 100)),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let scanned = || async {
+            let response = send(&app, Method::GET, "/status", Body::empty()).await;
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            let status: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            status["scanned_segments"].as_u64().unwrap()
+        };
+
+        // read the first message, then hang up
+        let response = send(&app, Method::GET, "/points?collection=grid", Body::empty()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let mut body = response.into_body();
+        body.frame().await.unwrap().unwrap();
+        drop(body);
+
+        // the query stops reading the store
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+        let stopped = scanned().await;
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+        assert_eq!(scanned().await, stopped);
+        assert!(stopped < 2000, "{stopped}");
+
+        // a complete query reads every entry
+        assert_eq!(count(&app, "/points?collection=grid").await, 200_000);
+        assert_eq!(scanned().await, stopped + 2000);
+    }
+}
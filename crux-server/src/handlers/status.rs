@@ -1,13 +1,25 @@
-use axum::{Extension, Json};
+use std::sync::{atomic::Ordering, Arc};
+
+use axum::{
+    http::{header::CONTENT_TYPE, StatusCode},
+    Extension, Json,
+};
 use serde::Serialize;
 
-use crate::{state::SharedState, Config};
+use crate::{
+    health::{Health, Readiness},
+    limits::Limiter,
+    state::SharedState,
+    Config,
+};
 
 // Status
 #[derive(Serialize)]
 pub(crate) struct Status {
     config: Config,
     workers: Vec<String>,
+    /// Store entries read by point queries since startup
+    scanned_segments: usize,
 }
 
 // #[axum::debug_handler]
@@ -15,9 +27,41 @@ pub(crate) async fn status(Extension(state): Extension<SharedState>) -> Json<Sta
     let state = state.read().await;
 
     let status = Status {
-        config: state.config.to_owned(),
+        config: state.config.redacted(),
         workers: state.workers.to_owned(),
+        scanned_segments: state.scanned.load(Ordering::Relaxed),
     };
 
     Json(status)
 }
+
+/// Effective configuration without credentials
+pub(crate) async fn config(Extension(state): Extension<SharedState>) -> Json<Config> {
+    Json(state.read().await.config.redacted())
+}
+
+/// Counters of the rate limiter in the Prometheus text format
+pub(crate) async fn metrics(
+    Extension(limiter): Extension<Arc<Limiter>>,
+) -> ([(axum::http::HeaderName, &'static str); 1], String) {
+    let header = [(CONTENT_TYPE, "text/plain; version=0.0.4")];
+    (header, limiter.metrics())
+}
+
+/// Liveness, the process serves requests
+pub(crate) async fn healthz() -> &'static str {
+    "ok"
+}
+
+/// Readiness with its checks, `503 Service Unavailable` unless ready
+pub(crate) async fn readyz(
+    Extension(health): Extension<Arc<Health>>,
+) -> (StatusCode, Json<Readiness>) {
+    let readiness = health.readiness();
+    let status = if readiness.ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json(readiness))
+}
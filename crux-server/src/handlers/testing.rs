@@ -0,0 +1,82 @@
+use std::sync::Arc;
+
+use arrow::{
+    array::{ArrayRef, Float64Array},
+    ipc::writer::StreamWriter,
+    record_batch::RecordBatch,
+};
+use axum::{
+    body::Body,
+    http::{header::CONTENT_TYPE, Method, Request},
+    response::Response,
+    Router,
+};
+use http_body_util::BodyExt;
+use tower::ServiceExt;
+
+use crux_format::{ArrowPointCloud, Point, PointCloudTrait, PointTrait, Synthetic};
+use crux_io::problem::{Problem, PROBLEM_CONTENT_TYPE};
+
+/// IPC stream of a `batches` x `rows` grid with `x = column`, `y = row` and
+/// `z` the point number
+pub(crate) fn grid(batches: usize, rows: usize) -> Vec<u8> {
+    let schema = Point::<f64, 3>::schema();
+    let mut writer = StreamWriter::try_new(Vec::new(), &schema).unwrap();
+    for i in 0..batches {
+        let x = Float64Array::from_iter_values((0..rows).map(|j| j as f64 + 0.5));
+        let y = Float64Array::from_iter_values((0..rows).map(|_| i as f64 + 0.5));
+        let z = Float64Array::from_iter_values((0..rows).map(|j| (i * rows + j) as f64));
+        let columns = vec![Arc::new(x) as ArrayRef, Arc::new(y), Arc::new(z)];
+        writer
+            .write(&RecordBatch::try_new(schema.clone(), columns).unwrap())
+            .unwrap();
+    }
+    writer.into_inner().unwrap()
+}
+
+/// IPC stream of a synthetic drive with `gps_time`, see [Synthetic::drive]
+pub(crate) fn drive(points: usize, batch_size: usize) -> Vec<u8> {
+    let pc = Synthetic::new(points)
+        .batch_size(batch_size)
+        .drive()
+        .unwrap();
+    stream(&pc)
+}
+
+/// IPC stream of classified ground and buildings, see [Synthetic::buildings]
+pub(crate) fn buildings(points: usize, batch_size: usize) -> Vec<u8> {
+    let pc = Synthetic::new(points)
+        .batch_size(batch_size)
+        .buildings(5)
+        .unwrap();
+    stream(&pc)
+}
+
+fn stream(pc: &ArrowPointCloud) -> Vec<u8> {
+    let mut writer = StreamWriter::try_new(Vec::new(), &pc.schema()).unwrap();
+    for e in pc.store.iter() {
+        for batch in pc.store.batches(e.key()) {
+            writer.write(&batch).unwrap();
+        }
+    }
+    writer.into_inner().unwrap()
+}
+
+pub(crate) async fn send(app: &Router, method: Method, uri: &str, body: Body) -> Response {
+    let request = Request::builder()
+        .method(method)
+        .uri(uri)
+        .body(body)
+        .unwrap();
+    app.clone().oneshot(request).await.unwrap()
+}
+
+/// Problem details of an error response
+pub(crate) async fn problem(response: Response) -> Problem {
+    assert_eq!(response.headers()[CONTENT_TYPE], PROBLEM_CONTENT_TYPE);
+    let status = response.status().as_u16();
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let problem = Problem::parse(&body).unwrap();
+    assert_eq!(problem.status, status);
+    problem
+}
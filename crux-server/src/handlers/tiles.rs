@@ -0,0 +1,213 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::Path,
+    http::{header::CONTENT_TYPE, HeaderMap},
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use moka::sync::Cache;
+
+use crux_format::{
+    color::{self, ColorMap, Stretch},
+    png, Point, PointCloudTrait, PointTrait, Tile, TileId, TileScheme,
+};
+
+use crate::{error::AppError, etag, state::SharedState};
+
+/// Number of cached tiles
+const CACHED_TILES: u64 = 4096;
+/// Pixels along each side of a sub-cell of a rendered tile
+const CELL_PIXELS: usize = 4;
+
+/// Tiles of a collection version, `name` and entity tag
+type Version = (String, String);
+
+/// Computed tiles and tiling schemes of recent collection versions
+#[derive(Clone)]
+pub(crate) struct TileCache {
+    tiles: Cache<(Version, TileId), Arc<Tile>>,
+    /// Scheme and z range to color tiles with
+    schemes: Cache<Version, (TileScheme, (f64, f64))>,
+}
+
+impl Default for TileCache {
+    fn default() -> Self {
+        Self {
+            tiles: Cache::new(CACHED_TILES),
+            schemes: Cache::new(CACHED_TILES / 64),
+        }
+    }
+}
+
+/// Tile `z/x/y.json` with the point count, z statistics and dominant class,
+/// or `z/x/y.png` colored by the mean z of its sub-cells
+pub(crate) async fn tile(
+    Extension(state): Extension<SharedState>,
+    Path((name, z, x, y)): Path<(String, u8, u32, String)>,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    let not_found =
+        || AppError::NotFound(format!("no tile `{z}/{x}/{y}`, expected `.json` or `.png`"));
+    let (row, format) = y.split_once('.').ok_or_else(not_found)?;
+    if !matches!(format, "json" | "png") {
+        return Err(not_found());
+    }
+    let y = row.parse().map_err(|_| not_found())?;
+    let id = TileId::new(z, x, y).map_err(|e| AppError::BadRequest(e.to_string()))?;
+
+    let (collection, version, cache) = {
+        let state = state.read().await;
+        let collection = state.collection(&name)?;
+        (
+            collection.snapshot(),
+            (name, collection.etag()),
+            state.tiles.clone(),
+        )
+    };
+
+    // the representations differ in the tag
+    let tag = format!("\"{}-{format}\"", version.1.trim_matches('"'));
+    if etag::matches(&headers, &tag) {
+        return Ok(etag::not_modified(&tag));
+    }
+
+    let png = format == "png";
+    let (tile, range) = tokio::task::spawn_blocking(move || {
+        let (scheme, range) = cache.schemes.get_with(version.clone(), || {
+            let aabb = collection.aabb::<Point<f64, 3>>();
+            (
+                TileScheme::for_cloud(&collection),
+                (aabb.lower().z(), aabb.upper().z()),
+            )
+        });
+        let tile = cache
+            .tiles
+            .try_get_with((version, id), || collection.tile(&scheme, id).map(Arc::new))
+            .map_err(|e| anyhow::anyhow!("{e}"))?;
+
+        Ok::<_, anyhow::Error>((tile, range))
+    })
+    .await
+    .map_err(anyhow::Error::from)??;
+
+    let response = if png {
+        let size = (crux_format::tiles::TILE_CELLS * CELL_PIXELS) as u32;
+        let map = ColorMap::Gradient {
+            gradient: color::gradient(None).map_err(anyhow::Error::from)?,
+            normalization: Stretch::Fixed(range),
+        };
+        let rgba = tile.to_rgba(&map, range, CELL_PIXELS);
+        (
+            [(CONTENT_TYPE, "image/png")],
+            png::encode_rgba(size, size, &rgba),
+        )
+            .into_response()
+    } else {
+        Json(&tile.summary).into_response()
+    };
+
+    Ok(etag::tag(response, &tag))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{
+            header::{CONTENT_TYPE, ETAG, IF_NONE_MATCH},
+            Method, Request, StatusCode,
+        },
+    };
+    use clap::Parser;
+    use http_body_util::BodyExt;
+    use tower::ServiceExt;
+
+    use crate::{
+        handlers::testing::{grid, send},
+        Config,
+    };
+
+    #[tokio::test]
+    async fn tiles() {
+        let app = crate::app(Config::parse_from(["crux-server"]));
+
+        // 4 x 4 points in [0, 4]², tiled over their bounds
+        let response = send(
+            &app,
+            Method::POST,
+            "/load?collection=grid",
+            Body::from(grid(4, 4)),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = send(
+            &app,
+            Method::GET,
+            "/collections/grid/tiles/0/0/0.json",
+            Body::empty(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let etag = response.headers()[ETAG].clone();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let summary: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        // the points on the right and bottom edge are in the neighbouring tiles
+        assert_eq!(summary["count"], 9);
+        assert_eq!(summary["z_min"], 4.);
+        assert_eq!(summary["z_max"], 14.);
+        assert_eq!(summary["classification"], serde_json::Value::Null);
+
+        // bottom right quadrant, one point
+        let response = send(
+            &app,
+            Method::GET,
+            "/collections/grid/tiles/1/1/1.json",
+            Body::empty(),
+        )
+        .await;
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let summary: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(summary["count"], 1);
+        assert_eq!(summary["z_mean"], 6.);
+
+        let request = Request::builder()
+            .uri("/collections/grid/tiles/0/0/0.json")
+            .header(IF_NONE_MATCH, etag)
+            .body(Body::empty())
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+
+        let response = send(
+            &app,
+            Method::GET,
+            "/collections/grid/tiles/2/1/3.png",
+            Body::empty(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[CONTENT_TYPE], "image/png");
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(&body[1..4], b"PNG");
+        // 256 x 256 pixels
+        assert_eq!(&body[16..24], &[0, 0, 1, 0, 0, 0, 1, 0]);
+
+        for (uri, status) in [
+            (
+                "/collections/grid/tiles/1/2/0.json",
+                StatusCode::BAD_REQUEST,
+            ),
+            ("/collections/grid/tiles/0/0/0.txt", StatusCode::NOT_FOUND),
+            ("/collections/grid/tiles/0/0/0", StatusCode::NOT_FOUND),
+            (
+                "/collections/missing/tiles/0/0/0.json",
+                StatusCode::NOT_FOUND,
+            ),
+        ] {
+            let response = send(&app, Method::GET, uri, Body::empty()).await;
+            assert_eq!(response.status(), status, "{uri}");
+        }
+    }
+}
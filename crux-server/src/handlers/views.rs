@@ -0,0 +1,335 @@
+use std::{collections::BTreeMap, path::Path as FsPath, time::SystemTime};
+
+use arrow::datatypes::SchemaRef;
+use axum::{
+    extract::Path,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crux_format::PointCloudTrait;
+
+use crate::{
+    error::AppError,
+    handlers::{check_schema, BoxQuery},
+    state::SharedState,
+};
+
+/// A named query of a collection, executed by
+/// `/collections/{name}/views/{view}/points`
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct View {
+    /// Parameters of `/collections/{name}/points`
+    pub(crate) query: BoxQuery,
+    /// Seconds since the Unix epoch
+    pub(crate) created: u64,
+    /// Entity tag of the definition, distinguishing the responses of a
+    /// redefined view
+    pub(crate) etag: String,
+    /// Why the query no longer applies to the collection, set when its schema
+    /// changed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) stale: Option<String>,
+}
+
+impl View {
+    fn new(query: BoxQuery) -> Self {
+        Self {
+            query,
+            created: SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            etag: format!("view{:08x}", rand::random::<u32>()),
+            stale: None,
+        }
+    }
+}
+
+/// Views of a collection by name, persisted with it
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(transparent)]
+pub(crate) struct Views(BTreeMap<String, View>);
+
+impl Views {
+    pub(crate) fn get(&self, name: &str) -> Option<&View> {
+        self.0.get(name)
+    }
+
+    /// Add or replace a view, returns whether it is new
+    pub(crate) fn insert(&mut self, name: &str, view: View) -> bool {
+        self.0.insert(name.to_owned(), view).is_none()
+    }
+
+    pub(crate) fn remove(&mut self, name: &str) -> Option<View> {
+        self.0.remove(name)
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Flag the views that no longer apply to the `schema` of `collection` as
+    /// stale and clear the flag of those that apply again, returns the names
+    /// of the views that became stale
+    pub(crate) fn revalidate(&mut self, collection: &str, schema: &SchemaRef) -> Vec<String> {
+        let mut stale = Vec::new();
+        for (name, view) in &mut self.0 {
+            let problem = view
+                .query
+                .check_view()
+                .and_then(|selection| check_schema(&selection, collection, schema))
+                .err()
+                .map(|e| e.to_string());
+            if problem.is_some() && view.stale.is_none() {
+                stale.push(name.to_owned());
+            }
+            view.stale = problem;
+        }
+        stale
+    }
+
+    /// Read the views written by [Views::write]
+    pub(crate) fn read(path: &FsPath) -> anyhow::Result<Self> {
+        Ok(serde_json::from_slice(&std::fs::read(path)?)?)
+    }
+
+    /// Write the views as JSON, or remove the file if there are none
+    pub(crate) fn write(&self, path: &FsPath) -> std::io::Result<()> {
+        if !self.is_empty() {
+            return std::fs::write(path, serde_json::to_vec(self)?);
+        }
+        match std::fs::remove_file(path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    /// The views as entries of the manifest of the object store
+    pub(crate) fn to_manifest(&self) -> BTreeMap<String, Value> {
+        self.0
+            .iter()
+            .filter_map(|(name, view)| Some((name.to_owned(), serde_json::to_value(view).ok()?)))
+            .collect()
+    }
+
+    /// Views of the manifest of the object store, invalid ones are skipped
+    pub(crate) fn from_manifest(views: BTreeMap<String, Value>) -> Self {
+        let views = views
+            .into_iter()
+            .filter_map(|(name, view)| match serde_json::from_value(view) {
+                Ok(view) => Some((name, view)),
+                Err(e) => {
+                    tracing::warn!("Ignoring view `{name}` of the manifest: {e}");
+                    None
+                }
+            })
+            .collect();
+        Self(views)
+    }
+}
+
+pub(crate) fn unknown_view(collection: &str, view: &str) -> AppError {
+    AppError::NotFound(format!("no view `{view}` of collection `{collection}`"))
+}
+
+/// Define view `view` of the collection, or redefine it. The query is
+/// rejected unless it applies to the collection.
+pub(crate) async fn put_view(
+    Extension(state): Extension<SharedState>,
+    Path((name, view)): Path<(String, String)>,
+    Json(query): Json<BoxQuery>,
+) -> Result<Response, AppError> {
+    let selection = query.check_view()?;
+
+    let mut state = state.write().await;
+    let collection = state.collection_mut(&name)?;
+    check_schema(&selection, &name, &collection.schema())?;
+
+    let definition = View::new(query);
+    let status = if collection.set_view(&view, definition.clone()) {
+        tracing::info!("Created view `{view}` of `{name}`");
+        StatusCode::CREATED
+    } else {
+        tracing::info!("Redefined view `{view}` of `{name}`");
+        StatusCode::OK
+    };
+    Ok((status, Json(definition)).into_response())
+}
+
+pub(crate) async fn views(
+    Extension(state): Extension<SharedState>,
+    Path(name): Path<String>,
+) -> Result<Json<Views>, AppError> {
+    let state = state.read().await;
+    Ok(Json(state.collection(&name)?.views().clone()))
+}
+
+pub(crate) async fn view(
+    Extension(state): Extension<SharedState>,
+    Path((name, view)): Path<(String, String)>,
+) -> Result<Json<View>, AppError> {
+    let state = state.read().await;
+    match state.collection(&name)?.views().get(&view) {
+        Some(definition) => Ok(Json(definition.clone())),
+        None => Err(unknown_view(&name, &view)),
+    }
+}
+
+pub(crate) async fn delete_view(
+    Extension(state): Extension<SharedState>,
+    Path((name, view)): Path<(String, String)>,
+) -> Result<StatusCode, AppError> {
+    let mut state = state.write().await;
+    if state.collection_mut(&name)?.remove_view(&view).is_none() {
+        return Err(unknown_view(&name, &view));
+    }
+
+    tracing::info!("Deleted view `{view}` of `{name}`");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{header::ETAG, Method, Request, StatusCode},
+        Router,
+    };
+    use http_body_util::BodyExt;
+    use serde_json::Value;
+    use tower::ServiceExt;
+
+    use crate::handlers::testing::{buildings, problem, send};
+
+    async fn json(app: &Router, method: Method, uri: &str, body: &str) -> (StatusCode, Value) {
+        let request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_owned()))
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        (status, serde_json::from_slice(&body).unwrap_or(Value::Null))
+    }
+
+    /// Points of a JSON response in a stable order
+    async fn points(app: &Router, uri: &str) -> Vec<Vec<f64>> {
+        let (status, response) = json(app, Method::GET, &format!("{uri}&format=json"), "").await;
+        assert_eq!(status, StatusCode::OK, "{uri}");
+        let mut points: Vec<Vec<f64>> = serde_json::from_value(response["points"].clone()).unwrap();
+        points.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        points
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn ground_view() {
+        let dir = tempfile::tempdir().unwrap();
+        let app = crate::app(clap::Parser::parse_from([
+            "crux-server",
+            "--storage-dir",
+            dir.path().to_str().unwrap(),
+        ]));
+        let response = send(
+            &app,
+            Method::POST,
+            "/load?collection=city",
+            Body::from(buildings(2000, 500)),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let ground = r#"{"filter":"classification=2","columns":"classification","p":1}"#;
+        let (status, view) =
+            json(&app, Method::PUT, "/collections/city/views/ground", ground).await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(view["query"]["filter"], "classification=2");
+        assert!(view.get("stale").is_none());
+
+        // executed like the equivalent ad-hoc query
+        let adhoc = points(
+            &app,
+            "/collections/city/points?filter=classification%3D2&columns=classification&p=1",
+        )
+        .await;
+        let executed = points(&app, "/collections/city/views/ground/points?").await;
+        assert_eq!(executed, adhoc);
+        assert!(!executed.is_empty() && executed.len() < 2000);
+        assert!(executed.iter().all(|point| point[4] == 2.));
+
+        // parameters of the request override those of the view
+        let overridden = points(&app, "/collections/city/views/ground/points?p=0").await;
+        assert!(overridden.is_empty());
+
+        // redefinitions change the entity tag of the responses
+        let uri = "/collections/city/views/ground/points";
+        let etag = send(&app, Method::GET, uri, Body::empty()).await.headers()[ETAG].clone();
+        let (status, _) = json(&app, Method::PUT, "/collections/city/views/ground", ground).await;
+        assert_eq!(status, StatusCode::OK);
+        let response = send(&app, Method::GET, uri, Body::empty()).await;
+        assert_ne!(response.headers()[ETAG], etag);
+
+        let (status, views) = json(&app, Method::GET, "/collections/city/views", "").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(views.as_object().unwrap().len(), 1);
+        assert!(dir
+            .path()
+            .join("city")
+            .join(crate::state::VIEWS_FILE)
+            .exists());
+
+        // views are validated against the schema
+        let unknown = r#"{"filter":"reflectance>1"}"#;
+        let request = Request::builder()
+            .method(Method::PUT)
+            .uri("/collections/city/views/bright")
+            .header("content-type", "application/json")
+            .body(Body::from(unknown))
+            .unwrap();
+        let problem = problem(app.clone().oneshot(request).await.unwrap()).await;
+        assert_eq!(problem.kind, "crux:unknown-column");
+        let (status, _) = json(&app, Method::GET, "/collections/city/views/bright", "").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let (status, _) = json(&app, Method::DELETE, "/collections/city/views/ground", "").await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let (status, _) = json(&app, Method::GET, uri, "").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert!(!dir
+            .path()
+            .join("city")
+            .join(crate::state::VIEWS_FILE)
+            .exists());
+    }
+
+    #[test]
+    fn revalidate() {
+        use std::sync::Arc;
+
+        use arrow::datatypes::{DataType, Field, Schema};
+
+        let query = serde_json::from_str(r#"{"filter":"classification=2"}"#).unwrap();
+        let mut views = super::Views::default();
+        views.insert("ground", super::View::new(query));
+
+        let xyz = ["x", "y", "z"].map(|name| Field::new(name, DataType::Float64, false));
+        let mut fields = xyz.to_vec();
+        fields.push(Field::new("classification", DataType::UInt8, false));
+        let classified = Arc::new(Schema::new(fields));
+        assert!(views.revalidate("city", &classified).is_empty());
+
+        // flagged once when the column disappears, cleared when it returns
+        let plain = Arc::new(Schema::new(xyz.to_vec()));
+        assert_eq!(views.revalidate("city", &plain), ["ground"]);
+        assert!(views.revalidate("city", &plain).is_empty());
+        let stale = views.get("ground").unwrap().stale.clone().unwrap();
+        assert!(stale.contains("classification"), "{stale}");
+        views.revalidate("city", &classified);
+        assert!(views.get("ground").unwrap().stale.is_none());
+    }
+}
@@ -0,0 +1,303 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use anyhow::{bail, Context};
+use serde::Serialize;
+
+use crate::{
+    state::{HISTORY_FILE, INDEX_FILE, VERSION_FILE},
+    Config,
+};
+
+/// File written to the storage directory to check that it is writable
+const PROBE_FILE: &str = ".crux-probe";
+
+/// Liveness and readiness of the server, reported by `/healthz` and
+/// `/readyz`.
+///
+/// The server is ready once the configured collections are loaded, while the
+/// storage directory is accessible and the watcher of the watched directory
+/// runs, until it drains before shutdown.
+#[derive(Debug)]
+pub struct Health {
+    loaded: AtomicBool,
+    watching: AtomicBool,
+    draining: AtomicBool,
+    storage_dir: Option<PathBuf>,
+    watch: bool,
+}
+
+/// Checks of `/readyz`, the watcher only if a directory is watched
+#[derive(Debug, Serialize, PartialEq)]
+pub(crate) struct Readiness {
+    pub(crate) ready: bool,
+    loaded: bool,
+    storage: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    watcher: Option<bool>,
+    draining: bool,
+}
+
+impl Health {
+    pub(crate) fn new(config: &Config) -> Self {
+        Self {
+            loaded: AtomicBool::new(false),
+            watching: AtomicBool::new(false),
+            draining: AtomicBool::new(false),
+            storage_dir: config.storage_dir.clone(),
+            watch: config.watch_dir.is_some(),
+        }
+    }
+
+    /// Mark the configured collections as loaded and queryable
+    pub(crate) fn set_loaded(&self) {
+        self.loaded.store(true, Ordering::Release);
+    }
+
+    /// Report the server as not ready until it shuts down
+    pub fn drain(&self) {
+        self.draining.store(true, Ordering::Release);
+    }
+
+    /// Guard reporting the watcher as running until it is dropped
+    pub(crate) fn watching(self: &Arc<Self>) -> Watching {
+        self.watching.store(true, Ordering::Release);
+        Watching(self.clone())
+    }
+
+    pub(crate) fn readiness(&self) -> Readiness {
+        let loaded = self.loaded.load(Ordering::Acquire);
+        let storage = self.storage_dir.as_deref().is_none_or(Path::is_dir);
+        let watcher = self.watch.then(|| self.watching.load(Ordering::Acquire));
+        let draining = self.draining.load(Ordering::Acquire);
+
+        Readiness {
+            ready: loaded && storage && watcher != Some(false) && !draining,
+            loaded,
+            storage,
+            watcher,
+            draining,
+        }
+    }
+}
+
+/// See [Health::watching]
+pub(crate) struct Watching(Arc<Health>);
+
+impl Drop for Watching {
+    fn drop(&mut self) {
+        self.0.watching.store(false, Ordering::Release);
+    }
+}
+
+/// Check the storage at startup, fails with a message naming the problem.
+///
+/// The storage directory has to be writable and the versions and histories of
+/// the collections persisted to it have to parse. Indices that cannot be read
+/// fail the check, indices built for other versions of the content are
+/// rebuilt and only logged.
+pub fn self_check(config: &Config) -> anyhow::Result<()> {
+    let Some(dir) = &config.storage_dir else {
+        return Ok(());
+    };
+
+    std::fs::create_dir_all(dir)
+        .with_context(|| format!("Create storage directory `{}`", dir.display()))?;
+    let probe = dir.join(PROBE_FILE);
+    std::fs::write(&probe, b"")
+        .and_then(|_| std::fs::remove_file(&probe))
+        .with_context(|| format!("Storage directory `{}` is not writable", dir.display()))?;
+
+    let entries = std::fs::read_dir(dir)
+        .with_context(|| format!("Read storage directory `{}`", dir.display()))?;
+    for entry in entries {
+        let path = entry?.path();
+        if path.is_dir() {
+            check_collection(&path)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Check the files persisted next to the segments of a collection
+fn check_collection(dir: &Path) -> anyhow::Result<()> {
+    let path = dir.join(VERSION_FILE);
+    if !path.exists() {
+        return Ok(());
+    }
+    let version: u64 = std::fs::read_to_string(&path)
+        .with_context(|| format!("Read `{}`", path.display()))?
+        .trim()
+        .parse()
+        .with_context(|| format!("Parse version `{}`", path.display()))?;
+
+    let path = dir.join(HISTORY_FILE);
+    if path.exists() {
+        let history = std::fs::read(&path).with_context(|| format!("Read `{}`", path.display()))?;
+        serde_json::from_slice::<Vec<serde_json::Value>>(&history)
+            .with_context(|| format!("Parse history `{}`", path.display()))?;
+    }
+
+    let path = dir.join(INDEX_FILE);
+    if path.exists() {
+        match crux_format::index::content_version(&path) {
+            Ok(Some(built)) if built == version => (),
+            Ok(Some(built)) => tracing::warn!(
+                "Index `{}` was built for version {built:016x}, not {version:016x}, it is rebuilt",
+                path.display()
+            ),
+            Ok(None) => bail!("Unknown format of index `{}`", path.display()),
+            Err(e) => bail!("Read index `{}`: {e}", path.display()),
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use arrow::ipc::{reader::StreamReader, writer::FileWriter};
+    use axum::{
+        body::Body,
+        http::{Method, StatusCode},
+    };
+    use http_body_util::BodyExt;
+    use tokio::sync::RwLock;
+
+    use crux_format::{IndexFile, PointCloudTrait, Synthetic};
+
+    use super::*;
+    use crate::{handlers::testing::send, state::AppState};
+
+    fn args(args: &[&str]) -> Config {
+        Config::load_from(std::iter::once("crux-server").chain(args.iter().copied())).unwrap()
+    }
+
+    #[test]
+    fn check() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = dir.path().join("storage");
+        let config = args(&["--storage-dir", storage.to_str().unwrap()]);
+        self_check(&config).unwrap();
+        assert!(storage.is_dir());
+
+        let collection = storage.join("ahn");
+        std::fs::create_dir(&collection).unwrap();
+        std::fs::write(collection.join(VERSION_FILE), "7").unwrap();
+        std::fs::write(collection.join(HISTORY_FILE), "[]").unwrap();
+        self_check(&config).unwrap();
+
+        // indices of other versions are rebuilt, unreadable ones fail
+        let pc = Synthetic::new(100).batch_size(10).terrain().unwrap();
+        let index = pc.batch_index(&()).unwrap();
+        index.write(&collection.join(INDEX_FILE), 6).unwrap();
+        self_check(&config).unwrap();
+        std::fs::write(collection.join(INDEX_FILE), "index").unwrap();
+        let e = self_check(&config).unwrap_err();
+        assert!(format!("{e:#}").contains("INDEX.arrow"), "{e:#}");
+        std::fs::remove_file(collection.join(INDEX_FILE)).unwrap();
+
+        std::fs::write(collection.join(HISTORY_FILE), "{").unwrap();
+        let e = self_check(&config).unwrap_err();
+        assert!(format!("{e:#}").contains("Parse history"), "{e:#}");
+        std::fs::write(collection.join(HISTORY_FILE), "[]").unwrap();
+        std::fs::write(collection.join(VERSION_FILE), "seven").unwrap();
+        let e = self_check(&config).unwrap_err();
+        assert!(format!("{e:#}").contains("Parse version"), "{e:#}");
+
+        // a file in place of the directory
+        let file = dir.path().join("file");
+        std::fs::write(&file, "").unwrap();
+        assert!(self_check(&args(&["--storage-dir", file.to_str().unwrap()])).is_err());
+    }
+
+    async fn ready(app: &axum::Router) -> (StatusCode, String) {
+        let response = send(app, Method::GET, "/readyz", Body::empty()).await;
+        let status = response.status();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn readiness() {
+        let dir = tempfile::tempdir().unwrap();
+
+        // IPC file of synthetic points
+        let pc = Synthetic::new(10_000).batch_size(1000).terrain().unwrap();
+        let file = std::fs::File::create(dir.path().join("terrain.arrow")).unwrap();
+        let mut writer = FileWriter::try_new(file, &pc.schema()).unwrap();
+        for e in pc.store.iter() {
+            for batch in pc.store.batches(e.key()) {
+                writer.write(&batch).unwrap();
+            }
+        }
+        writer.finish().unwrap();
+        let path = dir.path().join("crux.toml");
+        std::fs::write(&path, "[collections]\nterrain = \"terrain.arrow\"\n").unwrap();
+
+        // started like by `crate::start`, the pre-load is held up until the
+        // state is released
+        let config = args(&["--config", path.to_str().unwrap()]);
+        let state = Arc::new(RwLock::new(AppState::new(config)));
+        let health = state.read().await.health.clone();
+        let app = crate::router(state.clone());
+        let guard = state.write().await;
+        tokio::spawn(crate::load(state.clone()));
+        let response = send(&app, Method::GET, "/healthz", Body::empty()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let (status, body) = ready(&app).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert!(body.contains(r#""loaded":false"#), "{body}");
+        drop(guard);
+
+        let mut attempts = 0;
+        while ready(&app).await.0 != StatusCode::OK {
+            attempts += 1;
+            assert!(attempts < 500, "not ready after the pre-load");
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        // queryable once ready
+        let response = send(
+            &app,
+            Method::GET,
+            "/points?collection=terrain",
+            Body::empty(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let reader = StreamReader::try_new(std::io::Cursor::new(body), None).unwrap();
+        let rows: usize = reader.map(|batch| batch.unwrap().num_rows()).sum();
+        assert_eq!(rows, 10_000);
+
+        // not ready while draining
+        health.drain();
+        let (status, body) = ready(&app).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert!(body.contains(r#""draining":true"#), "{body}");
+    }
+
+    #[tokio::test]
+    async fn watcher() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = args(&["--watch-dir", dir.path().to_str().unwrap()]);
+        let health = Arc::new(Health::new(&config));
+        health.set_loaded();
+        assert!(!health.readiness().ready);
+        assert_eq!(health.readiness().watcher, Some(false));
+
+        let watching = health.watching();
+        assert!(health.readiness().ready);
+        drop(watching);
+        assert!(!health.readiness().ready);
+    }
+}
@@ -0,0 +1,457 @@
+use std::{
+    fs::File,
+    io::BufWriter,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+
+use crux_format::{
+    query::Expr, soa::Retained, ArrowPointCloud, AtomicProgress, PointCloudError, ProgressSink,
+};
+
+use crate::{
+    handlers::set_index,
+    preload::load_collection,
+    preview::Preview,
+    state::{Collection, Retention, SharedState},
+};
+
+/// Long running operation on a collection
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub(crate) enum JobSpec {
+    /// Build the batch index
+    Index,
+    /// Write the points to an Arrow IPC file
+    Export,
+    /// Draw the preview, queued after ingests
+    Preview,
+    /// Order the points by gps time, so that temporal queries find the first
+    /// batch within their range by binary search. Published as a new version
+    /// of collections kept in memory, persisted ones are not sorted.
+    Sort,
+    /// Delete the points matching `filter` and publish the others as a new
+    /// version, rewriting only the segments with matching points. Submitted
+    /// by `POST /collections/{name}/delete`.
+    #[serde(skip_deserializing)]
+    Delete { filter: String },
+    /// Load a file of the watched directory as the collection, not submitted
+    /// by clients
+    #[serde(skip_deserializing)]
+    Ingest { path: PathBuf },
+}
+
+/// Lifecycle of a job
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub(crate) enum JobStatus {
+    Queued,
+    Running,
+    Completed {
+        result: Option<PathBuf>,
+        /// Number of points removed by a deletion
+        #[serde(skip_serializing_if = "Option::is_none")]
+        deleted: Option<usize>,
+    },
+    Failed {
+        error: String,
+    },
+    Cancelled,
+}
+
+impl JobStatus {
+    pub(crate) fn is_finished(&self) -> bool {
+        !matches!(self, Self::Queued | Self::Running)
+    }
+}
+
+/// A submitted job, shared between the registry and its task
+pub(crate) struct Job {
+    pub(crate) id: String,
+    pub(crate) collection: String,
+    pub(crate) spec: JobSpec,
+    status: Mutex<JobStatus>,
+    /// When the job finished, for expiring it
+    finished: Mutex<Option<Instant>>,
+    progress: AtomicProgress,
+}
+
+/// Job as reported to clients
+#[derive(Serialize)]
+pub(crate) struct JobInfo {
+    id: String,
+    collection: String,
+    #[serde(flatten)]
+    spec: JobSpec,
+    #[serde(flatten)]
+    status: JobStatus,
+    done: usize,
+    total: usize,
+}
+
+impl Job {
+    pub(crate) fn new(collection: &str, spec: JobSpec) -> Self {
+        Self {
+            id: uuid::Uuid::new_v4().to_string(),
+            collection: collection.to_owned(),
+            spec,
+            status: Mutex::new(JobStatus::Queued),
+            finished: Mutex::new(None),
+            progress: AtomicProgress::default(),
+        }
+    }
+
+    pub(crate) fn status(&self) -> JobStatus {
+        self.status.lock().unwrap().clone()
+    }
+
+    fn set_status(&self, status: JobStatus) {
+        if status.is_finished() {
+            *self.finished.lock().unwrap() = Some(Instant::now());
+        }
+        *self.status.lock().unwrap() = status;
+    }
+
+    /// Whether the job finished more than `retention` ago
+    pub(crate) fn is_expired(&self, retention: Duration) -> bool {
+        self.finished
+            .lock()
+            .unwrap()
+            .is_some_and(|finished| finished.elapsed() > retention)
+    }
+
+    /// Request cancellation, returns `false` if the job already finished
+    pub(crate) fn cancel(&self) -> bool {
+        self.progress.token().cancel();
+        !self.status().is_finished()
+    }
+
+    pub(crate) fn info(&self) -> JobInfo {
+        JobInfo {
+            id: self.id.clone(),
+            collection: self.collection.clone(),
+            spec: self.spec.clone(),
+            status: self.status(),
+            done: self.progress.done(),
+            total: self.progress.total(),
+        }
+    }
+}
+
+impl ProgressSink for Job {
+    fn report(&self, done: usize, total: usize) {
+        self.progress.report(done, total);
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.progress.is_cancelled()
+    }
+}
+
+/// Run `job` in the background
+pub(crate) fn spawn(state: SharedState, job: Arc<Job>) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(run(state, job))
+}
+
+/// Draw the preview of collection `name` in the background, if it is missing
+/// or outdated
+pub(crate) async fn refresh_preview(state: &SharedState, name: &str) {
+    let job = state.write().await.preview_job(name);
+    if let Some(job) = job {
+        tracing::info!("Queued job {} drawing the preview of `{name}`", job.id);
+        spawn(state.clone(), job);
+    }
+}
+
+/// Run `job` once a slot of the job pool is available
+pub(crate) async fn run(state: SharedState, job: Arc<Job>) {
+    run_with(state, job, execute).await
+}
+
+/// Run `job` by `execute` once a slot of the job pool is available
+pub(crate) async fn run_with<F>(state: SharedState, job: Arc<Job>, execute: F)
+where
+    F: FnOnce(&Job, Option<&ArrowPointCloud>, &Context) -> anyhow::Result<Outcome> + Send + 'static,
+{
+    let (slots, context) = {
+        let state = state.read().await;
+        let exports = match &state.config.storage_dir {
+            Some(dir) => dir.join("exports"),
+            None => std::env::temp_dir().join("crux-exports"),
+        };
+        let context = Context {
+            exports,
+            chunk_size: state.config.chunk_size,
+            retention: Retention::from(&state.config),
+            preview_points: state.config.preview_points,
+        };
+        (state.job_slots.clone(), context)
+    };
+
+    let Ok(_permit) = slots.acquire_owned().await else {
+        return;
+    };
+    if job.is_cancelled() {
+        job.set_status(JobStatus::Cancelled);
+        return;
+    }
+
+    // ingests create the collection
+    let (pc, version) = match &job.spec {
+        JobSpec::Ingest { .. } => (None, None),
+        _ => {
+            let snapshot = state
+                .read()
+                .await
+                .data
+                .get(&job.collection)
+                .map(|collection| {
+                    let persisted = collection.is_persisted();
+                    (collection.snapshot(), collection.version(), persisted)
+                });
+            let error = match snapshot {
+                None => Some(format!("collection `{}` not found", job.collection)),
+                Some((_, _, true)) if matches!(job.spec, JobSpec::Sort) => Some(format!(
+                    "collection `{}` is persisted, only collections in memory are sorted",
+                    job.collection
+                )),
+                _ => None,
+            };
+            if let Some(error) = error {
+                job.set_status(JobStatus::Failed { error });
+                return;
+            }
+            let (pc, version, _) = snapshot.unwrap();
+            (Some(pc), Some(version))
+        }
+    };
+
+    tracing::info!("Running job {} on `{}`", job.id, job.collection);
+    job.set_status(JobStatus::Running);
+
+    let task = job.clone();
+    let result = tokio::task::spawn_blocking(move || execute(&task, pc.as_deref(), &context))
+        .await
+        .map_err(|e| anyhow!("job panicked: {e}"))
+        .and_then(|result| result);
+
+    // the preview of ingested collections
+    let mut preview = None;
+    let status = match result {
+        Ok(Outcome::Index(index)) => {
+            let mut state = state.write().await;
+            match state.data.get_mut(&job.collection) {
+                Some(collection) => {
+                    // the version of the snapshot the index was built from
+                    let version = version.unwrap_or_default();
+                    set_index(collection, index, version);
+                    JobStatus::Completed {
+                        result: None,
+                        deleted: None,
+                    }
+                }
+                None => JobStatus::Failed {
+                    error: format!("collection `{}` was deleted", job.collection),
+                },
+            }
+        }
+        Ok(Outcome::Preview(drawn)) => {
+            let mut state = state.write().await;
+            match state.data.get_mut(&job.collection) {
+                Some(collection) => {
+                    collection.set_preview(*drawn);
+                    JobStatus::Completed {
+                        result: None,
+                        deleted: None,
+                    }
+                }
+                None => JobStatus::Failed {
+                    error: format!("collection `{}` was deleted", job.collection),
+                },
+            }
+        }
+        Ok(Outcome::Sorted(pc)) => {
+            let mut state = state.write().await;
+            let retention = Retention::from(&state.config);
+            let status = match state.data.get_mut(&job.collection) {
+                // the version of the snapshot that was sorted
+                Some(collection) if Some(collection.version()) == version => {
+                    collection.publish(*pc);
+                    collection.touch();
+                    collection.commit(retention);
+                    JobStatus::Completed {
+                        result: None,
+                        deleted: None,
+                    }
+                }
+                Some(_) => JobStatus::Failed {
+                    error: format!("collection `{}` changed while sorting", job.collection),
+                },
+                None => JobStatus::Failed {
+                    error: format!("collection `{}` was deleted", job.collection),
+                },
+            };
+            if matches!(status, JobStatus::Completed { .. }) {
+                preview = state.preview_job(&job.collection);
+            }
+            status
+        }
+        Ok(Outcome::Retained(retained)) => {
+            let Retained {
+                pc,
+                dropped,
+                rewritten,
+            } = *retained;
+            let mut state = state.write().await;
+            let retention = Retention::from(&state.config);
+            let status = match state.data.get_mut(&job.collection) {
+                // the version of the snapshot the points were deleted from
+                Some(collection) if Some(collection.version()) == version => {
+                    if dropped > 0 {
+                        let rewritten: Vec<String> =
+                            rewritten.into_iter().map(|(key, _)| key).collect();
+                        collection.publish_rewritten(pc, &rewritten, retention);
+                        if collection.is_persisted() {
+                            collection.persist();
+                        }
+                        tracing::info!(
+                            "Deleted {dropped} points of `{}`, rewrote {} segment(s)",
+                            job.collection,
+                            rewritten.len()
+                        );
+                    }
+                    JobStatus::Completed {
+                        result: None,
+                        deleted: Some(dropped),
+                    }
+                }
+                Some(_) => JobStatus::Failed {
+                    error: format!("collection `{}` changed while deleting", job.collection),
+                },
+                None => JobStatus::Failed {
+                    error: format!("collection `{}` was deleted", job.collection),
+                },
+            };
+            if dropped > 0 && matches!(status, JobStatus::Completed { .. }) {
+                preview = state.preview_job(&job.collection);
+            }
+            status
+        }
+        Ok(Outcome::File(path)) => JobStatus::Completed {
+            result: Some(path),
+            deleted: None,
+        },
+        Ok(Outcome::Collection(collection)) => {
+            let mut state = state.write().await;
+            // the file changed or disappeared while it was loaded
+            let latest = state
+                .watched
+                .get(&job.collection)
+                .is_some_and(|latest| Arc::ptr_eq(latest, &job));
+            if latest {
+                // the views apply to the changed file unless its schema differs
+                let mut collection = *collection;
+                if let Some(replaced) = state.data.get_mut(&job.collection) {
+                    collection = collection.with_views(replaced.take_views());
+                    collection.revalidate_views(&job.collection);
+                }
+                state.remove(&job.collection);
+                state.data.insert(job.collection.clone(), collection);
+                preview = state.preview_job(&job.collection);
+                JobStatus::Completed {
+                    result: None,
+                    deleted: None,
+                }
+            } else {
+                JobStatus::Cancelled
+            }
+        }
+        Err(e) => match e.downcast_ref::<PointCloudError>() {
+            Some(PointCloudError::Cancelled) => JobStatus::Cancelled,
+            _ => JobStatus::Failed {
+                error: format!("{e:#}"),
+            },
+        },
+    };
+
+    tracing::info!("Job {} finished: {status:?}", job.id);
+    job.set_status(status);
+
+    if let Some(preview) = preview {
+        spawn(state, preview);
+    }
+}
+
+/// Settings of the job execution
+pub(crate) struct Context {
+    exports: PathBuf,
+    chunk_size: usize,
+    retention: Retention,
+    preview_points: usize,
+}
+
+/// Result of the job execution, applied to the state
+pub(crate) enum Outcome {
+    Index(crux_format::soa::BatchIndex),
+    File(PathBuf),
+    Preview(Box<Preview>),
+    Collection(Box<Collection>),
+    Sorted(Box<ArrowPointCloud>),
+    Retained(Box<Retained>),
+}
+
+/// Run the job on the snapshot `pc` of its collection, which is only missing
+/// for ingests
+pub(crate) fn execute(
+    job: &Job,
+    pc: Option<&ArrowPointCloud>,
+    context: &Context,
+) -> anyhow::Result<Outcome> {
+    let snapshot = || pc.ok_or_else(|| anyhow!("collection `{}` not found", job.collection));
+
+    match &job.spec {
+        JobSpec::Index => Ok(Outcome::Index(snapshot()?.batch_index(job)?)),
+        JobSpec::Export => {
+            let pc = snapshot()?;
+            std::fs::create_dir_all(&context.exports)?;
+            let path = context.exports.join(format!("{}.arrow", job.id));
+
+            let result = File::create(&path)
+                .map_err(anyhow::Error::from)
+                .and_then(|file| Ok(pc.write_ipc(BufWriter::new(file), None, job)?));
+            if let Err(e) = result {
+                let _ = std::fs::remove_file(&path);
+                return Err(e);
+            }
+
+            Ok(Outcome::File(path))
+        }
+        JobSpec::Preview => {
+            let preview = Preview::generate(snapshot()?, context.preview_points, job)?;
+            Ok(Outcome::Preview(Box::new(preview)))
+        }
+        JobSpec::Sort => {
+            let pc = snapshot()?.sort_by_time(context.chunk_size, job)?;
+            Ok(Outcome::Sorted(Box::new(pc)))
+        }
+        JobSpec::Delete { filter } => {
+            let filter: Expr = filter.parse()?;
+            let retained = snapshot()?.retain(|batch| filter.exclude(batch), job)?;
+            Ok(Outcome::Retained(Box::new(retained)))
+        }
+        JobSpec::Ingest { path } => {
+            job.report(0, 1);
+            // watched collections are kept in memory, the file is the durable copy
+            let pc = load_collection(path, None, context.chunk_size, Default::default())?;
+            let mut collection = Collection::new(pc);
+            collection.commit(context.retention);
+            job.report(1, 1);
+
+            Ok(Outcome::Collection(Box::new(collection)))
+        }
+    }
+}
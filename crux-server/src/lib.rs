@@ -1,10 +1,11 @@
-use std::{any::Any, collections::HashMap, sync::Arc};
+use std::{any::Any, sync::Arc, time::Duration};
 
 use axum::{
     body::Bytes,
     extract::{DefaultBodyLimit, FromRequestParts},
-    http::{header::CONTENT_TYPE, request::Parts, Response, StatusCode},
-    routing::get,
+    http::{header::CONTENT_TYPE, request::Parts, HeaderValue, Response, StatusCode},
+    middleware,
+    routing::{get, post},
 };
 use http_body_util::Full;
 use tokio::sync::RwLock;
@@ -13,27 +14,105 @@ use tower_http::{
     add_extension::AddExtensionLayer,
     catch_panic::CatchPanicLayer,
     compression::CompressionLayer,
-    cors::CorsLayer,
+    cors::{self, CorsLayer},
     trace::{DefaultMakeSpan, TraceLayer},
 };
 
 mod config;
 mod error;
+mod etag;
 mod handlers;
+mod health;
+mod jobs;
+mod limits;
+mod preload;
+mod preview;
+mod remote;
+mod replica;
 mod state;
+mod validate;
+mod watch;
 
 pub use config::Config;
-use state::AppState;
+use crux_io::problem::PROBLEM_CONTENT_TYPE;
+use error::AppError;
+pub use health::{self_check, Health};
+pub use limits::Limits;
+use state::{AppState, SharedState};
 
 pub fn app(config: Config) -> axum::Router {
-    let state = AppState {
-        config,
-        workers: Default::default(),
-        data: HashMap::default(),
+    let state = AppState::new(config);
+    state.health.set_loaded();
+    router(Arc::new(RwLock::new(state)))
+}
+
+/// Create the app after loading the configured collections
+pub async fn init(config: Config) -> axum::Router {
+    let state = Arc::new(RwLock::new(AppState::new(config)));
+    load(state.clone()).await;
+
+    router(state)
+}
+
+/// Create the app and load the configured collections in the background,
+/// `/readyz` reports the server as ready once they are queryable
+pub fn start(config: Config) -> (axum::Router, Arc<Health>) {
+    let state = Arc::new(RwLock::new(AppState::new(config)));
+    let health = state.try_read().expect("unshared state").health.clone();
+    let app = router(state.clone());
+    tokio::spawn(load(state));
+
+    (app, health)
+}
+
+/// Restore and pre-load the configured collections
+async fn load(state: SharedState) {
+    remote::restore(&state).await;
+    preload::preload(&state).await;
+    state.read().await.health.set_loaded();
+}
+
+fn router(state: SharedState) -> axum::Router {
+    let (config, health, limiter) = {
+        let state = state.try_read().expect("unshared state");
+        (
+            state.config.clone(),
+            state.health.clone(),
+            state.limiter.clone(),
+        )
     };
+    let gc_interval = Duration::from_secs(config.gc_interval);
+
+    // collect garbage of deleted collections in the background
+    if let Ok(handle) = tokio::runtime::Handle::try_current() {
+        handle.spawn(state::gc_task(Arc::downgrade(&state), gc_interval));
+
+        // pick up the versions published by the writer of the store
+        if config.read_only && config.store.is_some() {
+            handle.spawn(replica::poll_task(
+                Arc::downgrade(&state),
+                Duration::from_secs(config.poll_interval),
+            ));
+        }
+
+        // load collections from the watched directory
+        if let Some(dir) = config.watch_dir.clone() {
+            handle.spawn(watch::watch_task(
+                Arc::downgrade(&state),
+                dir,
+                Duration::from_secs(config.watch_interval),
+                config.watch_keep_removed,
+                health.clone(),
+            ));
+        }
+    }
 
     axum::Router::new()
+        .route("/healthz", get(handlers::healthz))
+        .route("/readyz", get(handlers::readyz))
+        .route("/metrics", get(handlers::metrics))
         .route("/status", get(handlers::status))
+        .route("/config", get(handlers::config))
         .route(
             "/workers",
             get(handlers::workers)
@@ -45,23 +124,96 @@ pub fn app(config: Config) -> axum::Router {
             get(handlers::load)
                 .post(handlers::push_batch)
                 .delete(handlers::delete)
-                .layer(DefaultBodyLimit::max(1024 * 1024 * 1024)),
+                .layer(DefaultBodyLimit::max(config.max_upload_size)),
         )
         .route(
             "/index",
             get(handlers::index).delete(handlers::remove_index),
         )
         .route("/points", get(handlers::points))
+        .route("/diff", get(handlers::diff))
+        .route("/collections", get(handlers::collections))
+        .route(
+            "/collections/:name",
+            get(handlers::collection).delete(handlers::delete_collection),
+        )
+        .route(
+            "/collections/:name/versions",
+            get(handlers::collection_versions),
+        )
+        .route(
+            "/collections/:name/points",
+            get(handlers::collection_points),
+        )
+        .route("/collections/:name/stats", get(handlers::collection_stats))
+        .route(
+            "/collections/:name/columns/:column/values",
+            get(handlers::column_values),
+        )
+        .route(
+            "/collections/:name/volume",
+            get(handlers::collection_volume),
+        )
+        .route("/collections/:name/gaps", get(handlers::collection_gaps))
+        .route("/collections/:name/views", get(handlers::views))
+        .route(
+            "/collections/:name/views/:view",
+            get(handlers::view)
+                .put(handlers::put_view)
+                .delete(handlers::delete_view),
+        )
+        .route(
+            "/collections/:name/views/:view/points",
+            get(handlers::view_points),
+        )
+        .route("/collections/:name/tiles/:z/:x/:y", get(handlers::tile))
+        .route(
+            "/collections/:name/validate",
+            post(handlers::validate_upload).layer(DefaultBodyLimit::max(config.max_upload_size)),
+        )
+        .route("/collections/:name/jobs", post(handlers::submit_job))
+        .route("/collections/:name/delete", post(handlers::delete_points))
+        .route("/jobs/:id", get(handlers::job).delete(handlers::cancel_job))
+        .layer(middleware::from_fn_with_state(
+            config.read_only,
+            replica::read_only,
+        ))
+        .layer(middleware::from_fn(limits::rate_limit))
         .layer(
             ServiceBuilder::new()
-                .layer(AddExtensionLayer::new(Arc::new(RwLock::new(state))))
+                .layer(AddExtensionLayer::new(state))
+                .layer(AddExtensionLayer::new(health))
+                .layer(AddExtensionLayer::new(limiter))
                 .layer(TraceLayer::new_for_http().make_span_with(DefaultMakeSpan::new()))
                 .layer(CompressionLayer::new())
-                .layer(CorsLayer::permissive())
+                .layer(cors(&config.cors_origins))
                 .layer(CatchPanicLayer::custom(handle_panic)),
         )
 }
 
+/// Restrict cross origin requests to `origins`, any origin is allowed if empty
+fn cors(origins: &[String]) -> CorsLayer {
+    if origins.is_empty() {
+        return CorsLayer::permissive();
+    }
+
+    let origins: Vec<HeaderValue> = origins
+        .iter()
+        .filter_map(|origin| match origin.parse() {
+            Ok(origin) => Some(origin),
+            Err(_) => {
+                tracing::warn!("Ignoring invalid CORS origin `{origin}`");
+                None
+            }
+        })
+        .collect();
+
+    CorsLayer::new()
+        .allow_origin(origins)
+        .allow_methods(cors::Any)
+        .allow_headers(cors::Any)
+}
+
 fn handle_panic(err: Box<dyn Any + Send + 'static>) -> Response<Full<Bytes>> {
     let details = if let Some(s) = err.downcast_ref::<String>() {
         s.clone()
@@ -73,10 +225,12 @@ fn handle_panic(err: Box<dyn Any + Send + 'static>) -> Response<Full<Bytes>> {
 
     tracing::error!(details);
 
+    // the details of the panic are logged, not exposed
+    let problem = AppError::from(anyhow::anyhow!(details)).problem();
     Response::builder()
         .status(StatusCode::INTERNAL_SERVER_ERROR)
-        .header(CONTENT_TYPE, "application/text")
-        .body(Full::from(details))
+        .header(CONTENT_TYPE, PROBLEM_CONTENT_TYPE)
+        .body(Full::from(serde_json::to_vec(&problem).unwrap()))
         .unwrap()
 }
 
@@ -89,13 +243,13 @@ where
     S: Send + Sync,
     T: serde::de::DeserializeOwned,
 {
-    type Rejection = (StatusCode, String);
+    type Rejection = AppError;
 
     async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Self, Self::Rejection> {
         let qs = parts.uri.query().unwrap_or("");
         match serde_qs::from_str(qs) {
             Ok(query) => Ok(Self(query)),
-            Err(e) => Err((StatusCode::BAD_REQUEST, e.to_string())),
+            Err(e) => Err(AppError::BadRequest(e.to_string())),
         }
     }
 }
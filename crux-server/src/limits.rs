@@ -0,0 +1,544 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    net::{IpAddr, SocketAddr},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::{ConnectInfo, Request},
+    http::{header::AUTHORIZATION, HeaderMap},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Extension,
+};
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+
+use crate::{error::AppError, Config};
+
+/// Paths that are never limited, for probes and scrapers
+const EXEMPT: [&str; 3] = ["/healthz", "/readyz", "/metrics"];
+
+/// Requests and points a client may receive per window, unlimited if not set
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Limits {
+    pub requests: Option<u64>,
+    pub points: Option<u64>,
+}
+
+impl Limits {
+    /// Limits of a token, unset ones from `global`
+    fn or(self, global: Limits) -> Self {
+        Self {
+            requests: self.requests.or(global.requests),
+            points: self.points.or(global.points),
+        }
+    }
+}
+
+/// Token bucket refilled continuously over the window up to its capacity,
+/// e.g. 600 requests per minute are 10 per second in bursts of up to 600
+#[derive(Debug, Clone)]
+struct Bucket {
+    capacity: f64,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn new(capacity: u64, now: Instant) -> Self {
+        Self {
+            capacity: capacity as f64,
+            tokens: capacity as f64,
+            updated: now,
+        }
+    }
+
+    fn refill(&mut self, window: Duration, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        let rate = self.capacity / window.as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate).min(self.capacity);
+        self.updated = now;
+    }
+
+    /// Time until `n` tokens are available, `None` if they are
+    fn wait(&mut self, n: f64, window: Duration, now: Instant) -> Option<Duration> {
+        self.refill(window, now);
+        let n = n.min(self.capacity);
+        if self.tokens >= n {
+            return None;
+        }
+        let rate = self.capacity / window.as_secs_f64();
+        Some(Duration::from_secs_f64((n - self.tokens) / rate))
+    }
+
+    /// Take `n` tokens, the bucket goes into debt if there are fewer
+    fn charge(&mut self, n: f64, window: Duration, now: Instant) {
+        self.refill(window, now);
+        self.tokens -= n;
+    }
+
+    fn is_full(&mut self, window: Duration, now: Instant) -> bool {
+        self.refill(window, now);
+        self.tokens >= self.capacity
+    }
+}
+
+/// Client the limits apply to, a configured token or else the address
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Client {
+    Token(String),
+    Address(IpAddr),
+    /// Requests without connection info, e.g. in tests
+    Unknown,
+}
+
+#[derive(Debug, Default)]
+struct Buckets {
+    requests: Option<Bucket>,
+    points: Option<Bucket>,
+}
+
+/// Counters of the rate limiter reported by `/metrics`
+#[derive(Debug, Default)]
+struct Counters {
+    admitted: AtomicU64,
+    limited_requests: AtomicU64,
+    limited_points: AtomicU64,
+    points: AtomicU64,
+}
+
+/// Rate limits of the clients of the server.
+///
+/// Clients sending `Authorization: Bearer <token>` with a token of the
+/// configuration file are limited by the limits of their token, all others by
+/// their address and the global limits. A request is rejected with `429 Too
+/// Many Requests` if the client has no requests left or has been sent its
+/// points of the window, the points of a response are charged as they are
+/// sent. Buckets are refilled continuously, so that clients recover within a
+/// fraction of the window.
+#[derive(Debug)]
+pub(crate) struct Limiter {
+    window: Duration,
+    limits: Limits,
+    tokens: BTreeMap<String, Limits>,
+    buckets: DashMap<Client, Arc<Mutex<Buckets>>>,
+    counters: Counters,
+}
+
+impl Limiter {
+    pub(crate) fn new(config: &Config) -> Self {
+        Self {
+            window: Duration::from_secs(config.rate_limit_window.max(1)),
+            limits: Limits {
+                requests: config.rate_limit_requests,
+                points: config.rate_limit_points,
+            },
+            tokens: config.tokens.clone(),
+            buckets: Default::default(),
+            counters: Default::default(),
+        }
+    }
+
+    /// Configured token of the request, its address otherwise
+    fn client(&self, headers: &HeaderMap, addr: Option<IpAddr>) -> Client {
+        let token = headers
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .map(str::trim);
+        match (token, addr) {
+            (Some(token), _) if self.tokens.contains_key(token) => Client::Token(token.to_owned()),
+            (_, Some(addr)) => Client::Address(addr),
+            (_, None) => Client::Unknown,
+        }
+    }
+
+    fn limits(&self, client: &Client) -> Limits {
+        match client {
+            Client::Token(token) => self.tokens[token].or(self.limits),
+            _ => self.limits,
+        }
+    }
+
+    /// Take a request of `client`, fails with the exceeded limit
+    fn admit(self: &Arc<Self>, client: Client, now: Instant) -> Result<Charge, AppError> {
+        let limits = self.limits(&client);
+        if limits == Limits::default() {
+            self.counters.admitted.fetch_add(1, Ordering::Relaxed);
+            return Ok(Charge::new(self, None));
+        }
+
+        let buckets = self
+            .buckets
+            .entry(client)
+            .or_insert_with(|| {
+                Arc::new(Mutex::new(Buckets {
+                    requests: limits.requests.map(|n| Bucket::new(n, now)),
+                    points: limits.points.map(|n| Bucket::new(n, now)),
+                }))
+            })
+            .clone();
+
+        let mut guard = buckets.lock().unwrap();
+        let limited = |kind: &'static str, limit: u64, wait: Duration| AppError::RateLimited {
+            kind,
+            limit,
+            window: self.window.as_secs(),
+            retry_after: wait.as_secs_f64().ceil().max(1.) as u64,
+        };
+        // responses are admitted while any points are left
+        if let (Some(bucket), Some(limit)) = (guard.points.as_mut(), limits.points) {
+            if let Some(wait) = bucket.wait(1., self.window, now) {
+                self.counters.limited_points.fetch_add(1, Ordering::Relaxed);
+                return Err(limited("points", limit, wait));
+            }
+        }
+        if let (Some(bucket), Some(limit)) = (guard.requests.as_mut(), limits.requests) {
+            match bucket.wait(1., self.window, now) {
+                Some(wait) => {
+                    self.counters
+                        .limited_requests
+                        .fetch_add(1, Ordering::Relaxed);
+                    return Err(limited("requests", limit, wait));
+                }
+                None => bucket.charge(1., self.window, now),
+            }
+        }
+        drop(guard);
+
+        self.counters.admitted.fetch_add(1, Ordering::Relaxed);
+        Ok(Charge::new(self, Some(buckets)))
+    }
+
+    /// Forget clients whose buckets refilled, they start over alike
+    pub(crate) fn prune(&self) {
+        let now = Instant::now();
+        self.buckets.retain(|_, buckets| {
+            let mut buckets = buckets.lock().unwrap();
+            let full = |bucket: &mut Option<Bucket>| {
+                bucket
+                    .as_mut()
+                    .is_none_or(|bucket| bucket.is_full(self.window, now))
+            };
+            !(full(&mut buckets.requests) && full(&mut buckets.points))
+        });
+    }
+
+    /// Counters in the Prometheus text format
+    pub(crate) fn metrics(&self) -> String {
+        let counters = &self.counters;
+        let mut metrics = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, values: &[(&str, u64)]| {
+            let _ = writeln!(metrics, "# HELP {name} {help}");
+            let _ = writeln!(metrics, "# TYPE {name} {kind}");
+            for (labels, value) in values {
+                let _ = writeln!(metrics, "{name}{labels} {value}");
+            }
+        };
+        metric(
+            "crux_requests_admitted_total",
+            "counter",
+            "Requests admitted by the rate limiter",
+            &[("", counters.admitted.load(Ordering::Relaxed))],
+        );
+        metric(
+            "crux_requests_limited_total",
+            "counter",
+            "Requests rejected with 429 by the exceeded limit",
+            &[
+                (
+                    "{limit=\"requests\"}",
+                    counters.limited_requests.load(Ordering::Relaxed),
+                ),
+                (
+                    "{limit=\"points\"}",
+                    counters.limited_points.load(Ordering::Relaxed),
+                ),
+            ],
+        );
+        metric(
+            "crux_points_sent_total",
+            "counter",
+            "Points sent in responses, as they are sent",
+            &[("", counters.points.load(Ordering::Relaxed))],
+        );
+        metric(
+            "crux_rate_limited_clients",
+            "gauge",
+            "Clients with requests or points taken in the current window",
+            &[("", self.buckets.len() as u64)],
+        );
+        metrics
+    }
+}
+
+/// Points sent to the client of a request, charged to its bucket
+#[derive(Clone)]
+pub(crate) struct Charge {
+    limiter: Option<Arc<Limiter>>,
+    buckets: Option<Arc<Mutex<Buckets>>>,
+}
+
+impl Charge {
+    fn new(limiter: &Arc<Limiter>, buckets: Option<Arc<Mutex<Buckets>>>) -> Self {
+        Self {
+            limiter: Some(limiter.clone()),
+            buckets,
+        }
+    }
+
+    /// Charge nothing, for requests that bypassed the limiter
+    pub(crate) fn none() -> Self {
+        Self {
+            limiter: None,
+            buckets: None,
+        }
+    }
+
+    /// Charge `n` points sent
+    pub(crate) fn points(&self, n: usize) {
+        let Some(limiter) = &self.limiter else {
+            return;
+        };
+        limiter
+            .counters
+            .points
+            .fetch_add(n as u64, Ordering::Relaxed);
+        if let Some(buckets) = &self.buckets {
+            if let Some(bucket) = buckets.lock().unwrap().points.as_mut() {
+                bucket.charge(n as f64, limiter.window, Instant::now());
+            }
+        }
+    }
+}
+
+/// Reject requests over the limits of their client, see [Limiter]
+pub(crate) async fn rate_limit(
+    Extension(limiter): Extension<Arc<Limiter>>,
+    addr: Option<ConnectInfo<SocketAddr>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    if EXEMPT.contains(&request.uri().path()) {
+        return next.run(request).await;
+    }
+
+    let client = limiter.client(request.headers(), addr.map(|ConnectInfo(addr)| addr.ip()));
+    match limiter.admit(client, Instant::now()) {
+        Ok(charge) => {
+            request.extensions_mut().insert(charge);
+            next.run(request).await
+        }
+        Err(e) => e.into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{header::RETRY_AFTER, Method, Request, StatusCode},
+    };
+    use clap::Parser;
+    use http_body_util::BodyExt;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::handlers::testing::{grid, problem, send};
+
+    #[test]
+    fn bucket() {
+        let window = Duration::from_secs(60);
+        let start = Instant::now();
+        let mut bucket = Bucket::new(600, start);
+
+        // bursts up to the capacity
+        for _ in 0..600 {
+            assert_eq!(bucket.wait(1., window, start), None);
+            bucket.charge(1., window, start);
+        }
+        let wait = bucket.wait(1., window, start).unwrap();
+        assert_eq!(wait.as_millis(), 100);
+
+        // 10 per second
+        let later = start + Duration::from_millis(500);
+        assert_eq!(bucket.wait(4., window, later), None);
+        assert!(!bucket.is_full(window, later));
+        assert!(bucket.is_full(window, start + window));
+
+        // debts of points sent are paid off over time
+        bucket.charge(1200., window, start + window);
+        let wait = bucket.wait(1., window, start + window).unwrap();
+        assert_eq!(wait.as_secs(), 60);
+    }
+
+    fn config(args: &[&str]) -> Config {
+        Config::parse_from(std::iter::once("crux-server").chain(args.iter().copied()))
+    }
+
+    #[test]
+    fn clients() {
+        let mut config = config(&["--rate-limit-requests", "10"]);
+        config.tokens.insert(
+            "secret".to_owned(),
+            Limits {
+                requests: None,
+                points: Some(1000),
+            },
+        );
+        let limiter = Limiter::new(&config);
+        let addr = IpAddr::from([10, 0, 0, 1]);
+
+        let mut headers = HeaderMap::new();
+        assert_eq!(limiter.client(&headers, Some(addr)), Client::Address(addr));
+        assert_eq!(limiter.client(&headers, None), Client::Unknown);
+
+        // unknown tokens are limited by their address
+        headers.insert(AUTHORIZATION, "Bearer guess".parse().unwrap());
+        assert_eq!(limiter.client(&headers, Some(addr)), Client::Address(addr));
+        headers.insert(AUTHORIZATION, "Bearer secret".parse().unwrap());
+        let client = limiter.client(&headers, Some(addr));
+        assert_eq!(client, Client::Token("secret".to_owned()));
+        assert_eq!(
+            limiter.limits(&client),
+            Limits {
+                requests: Some(10),
+                points: Some(1000)
+            }
+        );
+    }
+
+    async fn metric(app: &axum::Router, name: &str) -> u64 {
+        let response = send(app, Method::GET, "/metrics", Body::empty()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let metrics = String::from_utf8(body.to_vec()).unwrap();
+        metrics
+            .lines()
+            .find_map(|line| line.strip_prefix(name)?.strip_prefix(' '))
+            .unwrap_or_else(|| panic!("no `{name}` in\n{metrics}"))
+            .parse()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn burst() {
+        let app = crate::app(config(&[
+            "--rate-limit-requests",
+            "3",
+            "--rate-limit-window",
+            "1",
+        ]));
+
+        for _ in 0..3 {
+            let response = send(&app, Method::GET, "/collections", Body::empty()).await;
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+        let response = send(&app, Method::GET, "/collections", Body::empty()).await;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()[RETRY_AFTER], "1");
+        let problem = problem(response).await;
+        assert_eq!(problem.kind, "crux:rate-limited");
+        assert_eq!(problem.detail, "more than 3 requests per 1 s");
+        assert_eq!(problem.params["retry_after"], 1);
+
+        // probes are not limited, the counters are reported
+        let response = send(&app, Method::GET, "/healthz", Body::empty()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(metric(&app, "crux_requests_admitted_total").await, 3);
+        assert_eq!(
+            metric(&app, "crux_requests_limited_total{limit=\"requests\"}").await,
+            1
+        );
+        assert_eq!(metric(&app, "crux_rate_limited_clients").await, 1);
+
+        // a request is refilled after a third of the window
+        tokio::time::sleep(Duration::from_millis(400)).await;
+        let response = send(&app, Method::GET, "/collections", Body::empty()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = send(&app, Method::GET, "/collections", Body::empty()).await;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn points() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("crux.toml");
+        std::fs::write(
+            &path,
+            "rate_limit_points = 1000\nrate_limit_window = 1\n\n[tokens.secret]\npoints = 1000000\n",
+        )
+        .unwrap();
+        let config = Config::load_from(["crux-server", "--config", path.to_str().unwrap()]);
+        let app = crate::app(config.unwrap());
+
+        let response = send(
+            &app,
+            Method::POST,
+            "/load?collection=grid",
+            Body::from(grid(15, 100)),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // the response is sent while points are left, the next waits for the
+        // points sent beyond the limit
+        let response = send(&app, Method::GET, "/points?collection=grid", Body::empty()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        response.into_body().collect().await.unwrap();
+        assert_eq!(metric(&app, "crux_points_sent_total").await, 1500);
+        let response = send(&app, Method::GET, "/points?collection=grid", Body::empty()).await;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(problem(response).await.params["limit"], 1000);
+
+        // the token has its own limits
+        let request = Request::builder()
+            .uri("/points?collection=grid")
+            .header(AUTHORIZATION, "Bearer secret")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // recovered once the debt of 500 points is paid off
+        tokio::time::sleep(Duration::from_millis(600)).await;
+        let response = send(&app, Method::GET, "/points?collection=grid", Body::empty()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn aborted() {
+        let app = crate::app(config(&[
+            "--chunk-size",
+            "0",
+            "--rate-limit-points",
+            "1000000",
+        ]));
+        let response = send(
+            &app,
+            Method::POST,
+            "/load?collection=grid",
+            Body::from(grid(2000, 100)),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // read the first message, then hang up
+        let response = send(&app, Method::GET, "/points?collection=grid", Body::empty()).await;
+        let mut body = response.into_body();
+        body.frame().await.unwrap().unwrap();
+        drop(body);
+
+        // only the points sent before are charged
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        let sent = metric(&app, "crux_points_sent_total").await;
+        assert!(sent < 200_000, "{sent}");
+    }
+}
@@ -1,21 +1,36 @@
-use clap::Parser;
-use tracing_subscriber::prelude::*;
+use tracing_subscriber::{fmt::format::FmtSpan, prelude::*};
 
-use crux_server::Config;
+use std::{sync::Arc, time::Duration};
+
+use crux_server::{Config, Health};
 
 #[tokio::main]
 async fn main() {
     // load .env
     dotenvy::dotenv().ok();
 
-    // setup tracing
+    // setup tracing, closed spans are logged with their duration, e.g. the
+    // stages of queries with `RUST_LOG=crux_format=debug`
     tracing_subscriber::registry()
         .with(tracing_subscriber::EnvFilter::from_default_env())
-        .with(tracing_subscriber::fmt::layer().pretty())
+        .with(
+            tracing_subscriber::fmt::layer()
+                .pretty()
+                .with_span_events(FmtSpan::CLOSE),
+        )
         .init();
 
     // parse config
-    let mut config = Config::parse();
+    let mut config = Config::load().unwrap_or_else(|e| {
+        eprintln!("{e:#}");
+        std::process::exit(2)
+    });
+
+    // fail fast on inaccessible or corrupt storage
+    if let Err(e) = crux_server::self_check(&config) {
+        eprintln!("Self-check failed: {e:#}");
+        std::process::exit(1)
+    }
 
     // setup listener address
     let addr = std::net::SocketAddr::new(config.host, config.port);
@@ -40,17 +55,20 @@ async fn main() {
             .unwrap();
     }
 
-    // build our application
-    let app = crux_server::app(config.clone());
+    // build our application, ready once the collections are loaded
+    let (app, health) = crux_server::start(config.clone());
 
     // run it
-    axum::serve(listener, app.into_make_service())
-        .with_graceful_shutdown(shutdown_signal(client, config))
-        .await
-        .unwrap();
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<std::net::SocketAddr>(),
+    )
+    .with_graceful_shutdown(shutdown_signal(client, config, health))
+    .await
+    .unwrap();
 }
 
-async fn shutdown_signal(client: reqwest::Client, config: Config) {
+async fn shutdown_signal(client: reqwest::Client, config: Config, health: Arc<Health>) {
     let ctrl_c = async {
         tokio::signal::ctrl_c()
             .await
@@ -74,10 +92,22 @@ async fn shutdown_signal(client: reqwest::Client, config: Config) {
         },
         _ = terminate => {
             deregister(&client, &config).await;
+            drain(&health, &config).await;
         },
     }
 }
 
+/// Report the server as not ready while it still accepts connections, so
+/// that load balancers stop sending traffic before in-flight requests finish
+async fn drain(health: &Health, config: &Config) {
+    if config.drain_timeout == 0 {
+        return;
+    }
+    tracing::info!("Draining for {} s", config.drain_timeout);
+    health.drain();
+    tokio::time::sleep(Duration::from_secs(config.drain_timeout)).await;
+}
+
 async fn deregister(client: &reqwest::Client, config: &Config) {
     for coordinator in &config.coordinators {
         client
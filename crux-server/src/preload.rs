@@ -0,0 +1,299 @@
+use std::{
+    fs::File,
+    io::{BufReader, Read},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Context};
+use arrow::{
+    datatypes::{DataType, SchemaRef},
+    ipc::reader::{FileReader, StreamReader},
+    record_batch::{RecordBatch, RecordBatchReader},
+};
+
+use crux_format::{
+    ipc::FILE_MAGIC, soa::PointCloudStore, ArrowPointCloud, ColumnEncodings, IpcFormat,
+    PointCloudTrait, Rechunker,
+};
+use crux_io::{
+    las::LasDataSource, parquet::ParquetReader, ply::PlyReader, FormatExt, PointCloudReader,
+};
+
+use crate::{
+    jobs,
+    state::{Collection, Retention, SharedState},
+};
+
+/// Load the configured collections.
+///
+/// Failures are logged per collection and do not affect the others.
+pub(crate) async fn preload(state: &SharedState) {
+    let (collections, storage_dir, chunk_size, encodings, retention, remote) = {
+        let state = state.read().await;
+        (
+            state.config.collections.clone(),
+            state.config.storage_dir.clone(),
+            state.config.chunk_size,
+            state.config.encodings(),
+            Retention::from(&state.config),
+            // read-only servers keep their collections out of the store
+            state.remote.clone().filter(|_| !state.config.read_only),
+        )
+    };
+
+    for (name, path) in collections {
+        let store = storage_dir
+            .as_ref()
+            .or(remote.as_ref().map(|remote| &remote.cache_dir))
+            .map(|dir| dir.join(&name));
+        let remote = remote.as_ref().map(|remote| remote.collection(&name));
+
+        let spill = store.is_some();
+        let encodings = encodings.clone();
+        let collection_name = name.clone();
+        let result = tokio::task::spawn_blocking(move || {
+            load_collection(&path, store, chunk_size, encodings).map(|pc| {
+                let mut collection = Collection::new(pc);
+                if let Some(remote) = remote {
+                    collection = collection.with_remote(remote);
+                }
+                collection.commit(retention);
+                if spill {
+                    // views defined before the restart, on the reloaded points
+                    collection.restore_views();
+                    collection.revalidate_views(&collection_name);
+                    collection.persist();
+                }
+                collection
+            })
+        })
+        .await
+        .map_err(|e| anyhow!("Loader panicked: {e}"))
+        .and_then(|result| result);
+
+        match result {
+            Ok(collection) => {
+                tracing::info!(
+                    "Pre-loaded {} points into `{name}`",
+                    collection.num_points()
+                );
+                state.write().await.data.insert(name.clone(), collection);
+                jobs::refresh_preview(state, &name).await;
+            }
+            Err(e) => tracing::error!("Failed to pre-load collection `{name}`: {e:#}"),
+        }
+    }
+}
+
+/// Read a point cloud file with the reader matching its extension, in batches
+/// of `chunk_size` rows unless it is 0, attributes are normalized to the crux
+/// conventions. Spilled segments of the `store` are written with the column
+/// `encodings`.
+pub(crate) fn load_collection(
+    path: &Path,
+    store: Option<PathBuf>,
+    chunk_size: usize,
+    encodings: ColumnEncodings,
+) -> anyhow::Result<ArrowPointCloud> {
+    let format: FormatExt = path
+        .extension()
+        .ok_or_else(|| anyhow!("Missing extension of `{}`", path.display()))?
+        .try_into()
+        .map_err(|e: String| anyhow!(e))?;
+
+    let (schema, batches): (SchemaRef, Box<dyn Iterator<Item = _>>) = match format {
+        FormatExt::IPC => {
+            let open = || File::open(path).with_context(|| format!("Open `{}`", path.display()));
+            let mut head = Vec::with_capacity(FILE_MAGIC.len());
+            open()?
+                .take(FILE_MAGIC.len() as u64)
+                .read_to_end(&mut head)?;
+            // files e.g. written by pyarrow or pandas, streams by the server
+            match IpcFormat::sniff(&head) {
+                IpcFormat::File => {
+                    let reader = FileReader::try_new(BufReader::new(open()?), None)
+                        .context("Read IPC file")?;
+                    (reader.schema(), Box::new(reader))
+                }
+                IpcFormat::Stream => {
+                    let reader = StreamReader::try_new(BufReader::new(open()?), None)
+                        .context("Read IPC stream")?;
+                    (reader.schema(), Box::new(reader))
+                }
+            }
+        }
+        FormatExt::LAS | FormatExt::LAZ => {
+            let reader = LasDataSource::try_new(&[path.to_string_lossy()])?;
+            let batches: Vec<_> = reader.record_batch_iter().collect();
+            (reader.schema(), Box::new(batches.into_iter()))
+        }
+        FormatExt::Parquet => {
+            let reader = ParquetReader::from_path(path)?;
+            (reader.schema(), Box::new(reader))
+        }
+        FormatExt::PLY => {
+            let mut reader = PlyReader::from_path(path)?;
+            let batches: Vec<_> = reader.record_batch_reader().collect();
+            let schema = batches
+                .first()
+                .and_then(|batch| batch.as_ref().ok())
+                .map(RecordBatch::schema)
+                .ok_or_else(|| anyhow!("Empty PLY file `{}`", path.display()))?;
+            (schema, Box::new(batches.into_iter()))
+        }
+    };
+
+    let schema = crux_format::schema::add_importance(schema, "i", DataType::Float32, 0);
+
+    let mut pc = match store {
+        Some(store) => {
+            let capacity = 1000; // Cells
+            let store = PointCloudStore::try_new(capacity, store, false)?.with_encodings(encodings);
+            ArrowPointCloud::try_new_with(schema.clone(), store)?
+        }
+        None => ArrowPointCloud::try_new(schema.clone())?,
+    };
+
+    let mut rechunker = (chunk_size > 0).then(|| Rechunker::new(chunk_size));
+    for batch in batches {
+        let batch = crux_format::compute::add_importance(batch?, &schema)?;
+        match rechunker.as_mut() {
+            Some(rechunker) => {
+                for batch in rechunker.push(batch)? {
+                    pc.append(batch)?;
+                }
+            }
+            None => pc.append(batch)?,
+        }
+    }
+    if let Some(batch) = rechunker.map(Rechunker::finish).transpose()?.flatten() {
+        pc.append(batch)?;
+    }
+
+    // e.g. `Classification` as Int32 of other tools
+    pc.normalize_columns()?;
+
+    Ok(pc)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow::{
+        array::{ArrayRef, Float64Array, Int32Array},
+        datatypes::{DataType, Field, Schema},
+        ipc::{reader::StreamReader, writer::FileWriter},
+        record_batch::RecordBatch,
+    };
+    use axum::{
+        body::Body,
+        http::{Method, StatusCode},
+    };
+    use http_body_util::BodyExt;
+
+    use crux_format::{Point, PointCloudTrait, PointTrait};
+
+    use crate::{
+        handlers::testing::{grid, send},
+        Config,
+    };
+
+    #[test]
+    fn normalize() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("points.arrow");
+
+        let mut fields = Point::<f64, 3>::schema().fields().to_vec();
+        fields.push(Arc::new(Field::new(
+            "Classification",
+            DataType::Int32,
+            false,
+        )));
+        let schema = Arc::new(Schema::new(fields));
+        let coordinate = || Arc::new(Float64Array::from(vec![0., 1.])) as ArrayRef;
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                coordinate(),
+                coordinate(),
+                coordinate(),
+                Arc::new(Int32Array::from(vec![2, 6])),
+            ],
+        )
+        .unwrap();
+        let mut writer =
+            FileWriter::try_new(std::fs::File::create(&path).unwrap(), &schema).unwrap();
+        writer.write(&batch).unwrap();
+        writer.finish().unwrap();
+
+        let pc = super::load_collection(&path, None, 0, Default::default()).unwrap();
+        let field = pc
+            .schema()
+            .field_with_name("classification")
+            .unwrap()
+            .clone();
+        assert_eq!(field.data_type(), &DataType::UInt8);
+    }
+
+    #[tokio::test]
+    async fn boot_from_config() {
+        let dir = tempfile::tempdir().unwrap();
+
+        // IPC file of 2 x 5 points
+        let stream = grid(2, 5);
+        let reader = StreamReader::try_new(std::io::Cursor::new(stream), None).unwrap();
+        let file = std::fs::File::create(dir.path().join("grid.arrow")).unwrap();
+        let mut writer = FileWriter::try_new(file, &reader.schema()).unwrap();
+        for batch in reader {
+            writer.write(&batch.unwrap()).unwrap();
+        }
+        writer.finish().unwrap();
+
+        let path = dir.path().join("crux.toml");
+        std::fs::write(
+            &path,
+            r#"
+            max_points = 8
+
+            [collections]
+            grid = "grid.arrow"
+            missing = "missing.arrow"
+            "#,
+        )
+        .unwrap();
+
+        let config =
+            Config::load_from(["crux-server", "--config", path.to_str().unwrap()]).unwrap();
+        let app = crate::init(config).await;
+
+        // the missing file does not prevent startup
+        let response = send(&app, Method::GET, "/collections/missing", Body::empty()).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let response = send(&app, Method::GET, "/collections/grid/stats", Body::empty()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert!(std::str::from_utf8(&body)
+            .unwrap()
+            .contains(r#""num_points":10"#));
+
+        // responses are capped
+        let response = send(&app, Method::GET, "/points?collection=grid", Body::empty()).await;
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        let uri = "/points?collection=grid&bounds=0,0,0,5,1,100";
+        let response = send(&app, Method::GET, uri, Body::empty()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let reader = StreamReader::try_new(std::io::Cursor::new(body), None).unwrap();
+        let rows: usize = reader.map(|batch| batch.unwrap().num_rows()).sum();
+        assert_eq!(rows, 5);
+
+        let response = send(&app, Method::GET, "/config", Body::empty()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert!(std::str::from_utf8(&body)
+            .unwrap()
+            .contains(r#""max_points":8"#));
+    }
+}
@@ -0,0 +1,147 @@
+//! Fixed-budget previews of collections.
+//!
+//! The first view of a large collection should not require clients to guess
+//! a sampling fraction. After ingests a background job draws a [Preview] of
+//! about `preview_points` points, which is kept in memory, written next to the
+//! store directory as `{name}.preview` and served by `preview=true` without
+//! reading the store. It is drawn again once the number of points changed by
+//! more than `preview_refresh` of the points it was drawn from.
+
+use std::{
+    fs::File,
+    io::BufWriter,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::Context;
+use arrow::ipc::{reader::FileReader, writer::FileWriter};
+
+use crux_format::{ArrowPointCloud, PointCloudError, PointCloudTrait, ProgressSink, Query, Sample};
+
+/// Seed of the sample, previews of the same points are equal
+const SEED: u64 = 0;
+/// Custom metadata of the preview file, the number of points it was drawn from
+const SOURCE_KEY: &str = "crux:preview_source";
+
+pub(crate) struct Preview {
+    pc: Arc<ArrowPointCloud>,
+    /// Number of points of the collection the preview was drawn from
+    source: usize,
+    etag: String,
+}
+
+impl Preview {
+    fn new(pc: ArrowPointCloud, source: usize) -> Self {
+        let etag = format!("\"{:016x}-preview\"", pc.content_hash());
+        Self {
+            pc: Arc::new(pc),
+            source,
+            etag,
+        }
+    }
+
+    /// Seeded sample of about `budget` points of `pc`, all points of smaller
+    /// point clouds
+    pub(crate) fn generate(
+        pc: &ArrowPointCloud,
+        budget: usize,
+        progress: &dyn ProgressSink,
+    ) -> Result<Self, PointCloudError> {
+        let source = pc.num_points();
+        let p = (budget as f64 / source.max(1) as f64).min(1.);
+        let query = Query::new().sample(Sample::Seeded { p, seed: SEED });
+
+        let mut preview = ArrowPointCloud::try_new(query.schema(&pc.schema())?)?;
+        pc.stream_with(&query, progress, |batch| preview.append(batch))?;
+
+        Ok(Self::new(preview, source))
+    }
+
+    /// Preview written by [Preview::write]
+    pub(crate) fn read(path: &Path) -> anyhow::Result<Self> {
+        let reader = FileReader::try_new(File::open(path)?, None)?;
+        let source = reader
+            .custom_metadata()
+            .get(SOURCE_KEY)
+            .and_then(|source| source.parse().ok())
+            .context("Missing number of source points")?;
+
+        let mut pc = ArrowPointCloud::try_new(reader.schema())?;
+        for batch in reader {
+            pc.append(batch?)?;
+        }
+        Ok(Self::new(pc, source))
+    }
+
+    /// Write the points as Arrow IPC file with the number of source points
+    pub(crate) fn write(&self, path: &Path) -> anyhow::Result<()> {
+        let file = BufWriter::new(File::create(path)?);
+        let mut writer = FileWriter::try_new(file, &self.pc.schema())?;
+        writer.write_metadata(SOURCE_KEY, self.source.to_string());
+        for e in self.pc.store.iter() {
+            for batch in self.pc.store.batches(e.key()) {
+                writer.write(&batch)?;
+            }
+        }
+        writer.finish()?;
+        Ok(())
+    }
+
+    /// File of the preview of the collection stored in `dir`
+    pub(crate) fn path(dir: &Path) -> PathBuf {
+        let mut path = dir.as_os_str().to_owned();
+        path.push(".preview");
+        PathBuf::from(path)
+    }
+
+    /// Reference counted handle to the points
+    pub(crate) fn snapshot(&self) -> Arc<ArrowPointCloud> {
+        self.pc.clone()
+    }
+
+    /// Entity tag of the points
+    pub(crate) fn etag(&self) -> &str {
+        &self.etag
+    }
+
+    /// Whether `num_points` differ from the points the preview was drawn from
+    /// by more than `fraction` of them
+    pub(crate) fn is_outdated(&self, num_points: usize, fraction: f64) -> bool {
+        num_points.abs_diff(self.source) as f64 > fraction * self.source as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crux_format::{Point, PointTrait};
+
+    use super::*;
+
+    #[test]
+    fn budget() {
+        let pc = ArrowPointCloud::from_iter(
+            (0..10_000).map(|i| Point::<f64, 3>::from_slice(&[i as f64, 0., 0.])),
+        )
+        .unwrap();
+
+        let preview = Preview::generate(&pc, 1000, &()).unwrap();
+        let n = preview.snapshot().num_points();
+        assert!((900..=1100).contains(&n), "{n}");
+        assert!(!preview.is_outdated(10_500, 0.1));
+        assert!(preview.is_outdated(11_001, 0.1));
+        assert!(preview.is_outdated(8_000, 0.1));
+
+        // small point clouds are previewed as a whole
+        let preview = Preview::generate(&pc, 20_000, &()).unwrap();
+        assert_eq!(preview.snapshot().num_points(), 10_000);
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = Preview::path(&dir.path().join("epoch.1"));
+        assert_eq!(path, dir.path().join("epoch.1.preview"));
+        preview.write(&path).unwrap();
+        let read = Preview::read(&path).unwrap();
+        assert_eq!(read.etag(), preview.etag());
+        assert_eq!(read.source, 10_000);
+    }
+}
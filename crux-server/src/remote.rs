@@ -0,0 +1,663 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    future::Future,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use anyhow::Context;
+use arrow::ipc::{reader::StreamReader, writer::StreamWriter};
+use futures::TryStreamExt;
+use object_store::{path::Path as ObjectPath, ObjectStore, PutMode, UpdateVersion};
+use tokio::{
+    io::AsyncWriteExt,
+    runtime::{Handle, RuntimeFlavor},
+};
+
+use crux_format::{
+    inspect::Manifest,
+    soa::{PointCloudStore, SegmentSource},
+    ArrowPointCloud, Point, PointCloudError, PointCloudTrait, PointTrait,
+};
+
+use crate::{
+    handlers::Views,
+    jobs::{self, Job, JobSpec},
+    preview::Preview,
+    state::{Collection, SharedState},
+    Config,
+};
+
+/// Store entries in iteration order and the content version of a collection
+const MANIFEST_FILE: &str = "MANIFEST.json";
+/// Empty IPC stream of the schema of a collection
+const SCHEMA_FILE: &str = "SCHEMA.arrow";
+/// Size of the range requests segments are downloaded with
+const RANGE_SIZE: usize = 8 * 1024 * 1024;
+
+/// Object store collections are persisted to, see `--store`
+pub(crate) struct Remote {
+    store: Arc<dyn ObjectStore>,
+    prefix: ObjectPath,
+    /// Local directory of downloaded segments
+    pub(crate) cache_dir: PathBuf,
+    /// Maximum size of the downloaded segments in bytes
+    cache_size: u64,
+    /// Downloaded segments and their size, oldest first
+    fetched: Mutex<VecDeque<(PathBuf, u64)>>,
+    handle: Option<Handle>,
+}
+
+/// Object store and prefix of a location like `s3://bucket/prefix`
+pub(crate) fn parse(url: &str) -> anyhow::Result<(Box<dyn ObjectStore>, ObjectPath)> {
+    let url = reqwest::Url::parse(url).with_context(|| format!("Parse store url `{url}`"))?;
+    object_store::parse_url(&url).with_context(|| format!("Open store `{url}`"))
+}
+
+impl Remote {
+    pub(crate) fn new(
+        store: Arc<dyn ObjectStore>,
+        prefix: ObjectPath,
+        cache_dir: PathBuf,
+        cache_size: u64,
+    ) -> Self {
+        Self {
+            store,
+            prefix,
+            cache_dir,
+            cache_size,
+            fetched: Default::default(),
+            handle: Handle::try_current().ok(),
+        }
+    }
+
+    /// Store of the configuration, if any, cached in the storage directory
+    pub(crate) fn from_config(config: &Config) -> anyhow::Result<Option<Arc<Self>>> {
+        let Some(url) = &config.store else {
+            return Ok(None);
+        };
+        let (store, prefix) = parse(url)?;
+        let cache_dir = config
+            .storage_dir
+            .clone()
+            .unwrap_or_else(|| std::env::temp_dir().join("crux-store-cache"));
+        std::fs::create_dir_all(&cache_dir)
+            .with_context(|| format!("Create cache directory {cache_dir:?}"))?;
+
+        Ok(Some(Arc::new(Self::new(
+            store.into(),
+            prefix,
+            cache_dir,
+            config.store_cache_size * 1024 * 1024,
+        ))))
+    }
+
+    /// Run `future` to completion from synchronous code, such as the store.
+    ///
+    /// Blocks the worker thread of a multi-threaded runtime, otherwise the
+    /// future runs on a runtime of its own.
+    fn block_on<F>(&self, future: F) -> F::Output
+    where
+        F: Future + Send,
+        F::Output: Send,
+    {
+        match Handle::try_current().ok().or_else(|| self.handle.clone()) {
+            Some(handle) if handle.runtime_flavor() == RuntimeFlavor::MultiThread => {
+                tokio::task::block_in_place(|| handle.block_on(future))
+            }
+            _ => std::thread::scope(|scope| {
+                scope
+                    .spawn(|| {
+                        tokio::runtime::Builder::new_current_thread()
+                            .enable_all()
+                            .build()
+                            .expect("build runtime")
+                            .block_on(future)
+                    })
+                    .join()
+                    .unwrap_or_else(|e| std::panic::resume_unwind(e))
+            }),
+        }
+    }
+
+    fn path(&self, collection: &str, file: &str) -> ObjectPath {
+        self.prefix.child(collection).child(file)
+    }
+
+    /// Handle of the collection `name`
+    pub(crate) fn collection(self: &Arc<Self>, name: &str) -> Arc<RemoteCollection> {
+        Arc::new(RemoteCollection {
+            remote: self.clone(),
+            name: name.to_owned(),
+            uploaded: Default::default(),
+            published: Default::default(),
+        })
+    }
+
+    /// Names of the collections in the store
+    pub(crate) async fn collections(&self) -> object_store::Result<Vec<String>> {
+        let listing = self.store.list_with_delimiter(Some(&self.prefix)).await?;
+        Ok(listing
+            .common_prefixes
+            .iter()
+            .filter_map(|path| path.filename().map(str::to_owned))
+            .collect())
+    }
+
+    /// Download `location` to `path` in ranges, returns its size
+    async fn download(&self, location: &ObjectPath, path: &Path) -> anyhow::Result<u64> {
+        let size = self.store.head(location).await?.size;
+
+        // concurrent downloads of the same segment do not share the file
+        let tmp = path.with_extension(format!("{}.tmp", uuid::Uuid::new_v4()));
+        let mut file = std::fs::File::create(&tmp)?;
+        for start in (0..size).step_by(RANGE_SIZE) {
+            let range = start..(start + RANGE_SIZE).min(size);
+            file.write_all(&self.store.get_range(location, range).await?)?;
+        }
+        file.sync_all()?;
+        std::fs::rename(&tmp, path)?;
+
+        Ok(size as u64)
+    }
+
+    /// Upload the file at `path` to `location` in parts
+    async fn upload(&self, path: &Path, location: &ObjectPath) -> anyhow::Result<()> {
+        let (_, mut writer) = self.store.put_multipart(location).await?;
+        let mut file = tokio::fs::File::open(path).await?;
+        tokio::io::copy(&mut file, &mut writer).await?;
+        writer.shutdown().await?;
+        Ok(())
+    }
+
+    /// Track a downloaded segment and remove the oldest ones beyond the cache
+    /// size, unless they were rewritten since
+    fn track(&self, path: PathBuf, size: u64) {
+        let mut fetched = self.fetched.lock().unwrap();
+        fetched.push_back((path, size));
+
+        let mut total: u64 = fetched.iter().map(|(_, size)| size).sum();
+        while total > self.cache_size && fetched.len() > 1 {
+            let (path, size) = fetched.pop_front().unwrap();
+            total -= size;
+            if std::fs::metadata(&path).is_ok_and(|m| m.len() == size) {
+                if let Err(e) = std::fs::remove_file(&path) {
+                    tracing::warn!("Failed to remove cached segment {path:?}: {e}");
+                }
+            }
+        }
+    }
+}
+
+/// A collection in the object store
+pub(crate) struct RemoteCollection {
+    remote: Arc<Remote>,
+    name: String,
+    /// Size of the uploaded segment of each store entry
+    uploaded: Mutex<HashMap<String, u64>>,
+    /// Object version of the manifest last read or written by this server
+    published: Mutex<Option<UpdateVersion>>,
+}
+
+impl SegmentSource for RemoteCollection {
+    fn fetch(&self, key: &str, path: &Path) -> Result<(), PointCloudError> {
+        let location = self.remote.path(&self.name, &format!("{key}.arrow"));
+        let size = self
+            .remote
+            .block_on(self.remote.download(&location, path))
+            .map_err(|e| PointCloudError::CacheError(format!("Fetch `{location}`: {e:#}")))?;
+
+        tracing::debug!("Fetched segment `{location}` ({size} bytes)");
+        self.remote.track(path.to_owned(), size);
+        Ok(())
+    }
+}
+
+impl RemoteCollection {
+    /// Upload the segments changed since the last upload, then the schema and
+    /// manifest, and delete the segments of removed entries.
+    ///
+    /// The segments are spilled to the store directory before, see
+    /// [ArrowPointCloud::flush].
+    pub(crate) fn upload(
+        &self,
+        pc: &ArrowPointCloud,
+        version: u64,
+        views: &Views,
+    ) -> anyhow::Result<()> {
+        let entries: Vec<(String, PathBuf)> = pc
+            .store
+            .iter()
+            .map(|entry| (entry.key().to_owned(), entry.value().to_owned()))
+            .collect();
+
+        // for inspecting the collection without opening it
+        let num_points = pc.num_points();
+        let bounds = (num_points > 0).then(|| {
+            let aabb = pc.aabb::<Point<f64, 3>>();
+            let (lower, upper) = (aabb.lower(), aabb.upper());
+            [
+                [lower.x(), lower.y(), lower.z()],
+                [upper.x(), upper.y(), upper.z()],
+            ]
+        });
+
+        let mut schema = StreamWriter::try_new(Vec::new(), &pc.schema())?;
+        schema.finish()?;
+        let schema = schema.into_inner()?;
+
+        self.remote.block_on(async {
+            let mut uploaded = self.uploaded.lock().unwrap().clone();
+            let mut segments = Vec::new();
+
+            for (key, path) in &entries {
+                let size = match std::fs::metadata(path) {
+                    Ok(metadata) => metadata.len(),
+                    // not downloaded, unchanged
+                    Err(_) => match uploaded.get(key) {
+                        Some(size) => *size,
+                        None => anyhow::bail!("Missing segment {path:?}"),
+                    },
+                };
+
+                if uploaded.get(key) != Some(&size) {
+                    let location = self.remote.path(&self.name, &format!("{key}.arrow"));
+                    self.remote
+                        .upload(path, &location)
+                        .await
+                        .with_context(|| format!("Upload `{location}`"))?;
+                    uploaded.insert(key.to_owned(), size);
+                }
+                segments.push((key.to_owned(), size));
+            }
+
+            self.remote
+                .store
+                .put(&self.remote.path(&self.name, SCHEMA_FILE), schema.into())
+                .await?;
+
+            let manifest = serde_json::to_vec(&Manifest {
+                version,
+                segments,
+                num_points: Some(num_points as u64),
+                bounds,
+                views: views.to_manifest(),
+            })?;
+            self.publish(manifest).await?;
+
+            // segments of removed entries, also of a replaced collection
+            let prefix = self.remote.prefix.child(self.name.as_str());
+            let removed: Vec<ObjectPath> = self
+                .remote
+                .store
+                .list(Some(&prefix))
+                .map_ok(|meta| meta.location)
+                .try_filter(|location| {
+                    let key = location
+                        .filename()
+                        .and_then(|file| file.strip_suffix(".arrow"))
+                        .filter(|_| location.filename() != Some(SCHEMA_FILE));
+                    let stale = key.is_some_and(|key| !entries.iter().any(|(k, _)| k == key));
+                    futures::future::ready(stale)
+                })
+                .try_collect()
+                .await?;
+            for location in removed {
+                self.remote.store.delete(&location).await?;
+            }
+            uploaded.retain(|key, _| entries.iter().any(|(k, _)| k == key));
+
+            *self.uploaded.lock().unwrap() = uploaded;
+            Ok(())
+        })
+    }
+
+    /// Replace the manifest in a single put, so that replicas read either the
+    /// previous or the new one.
+    ///
+    /// The put is conditional on the manifest last read or written by this
+    /// server where the store supports it, so that concurrent writers fail
+    /// instead of overwriting each other. Other stores, like local files, write
+    /// a temporary file that is renamed.
+    async fn publish(&self, manifest: Vec<u8>) -> anyhow::Result<()> {
+        let location = self.remote.path(&self.name, MANIFEST_FILE);
+        let previous = self.published.lock().unwrap().clone();
+
+        let mode = previous.map_or(PutMode::Overwrite, PutMode::Update);
+        let conditional = matches!(mode, PutMode::Update(_));
+        let result = match self
+            .remote
+            .store
+            .put_opts(&location, manifest.clone().into(), mode.into())
+            .await
+        {
+            Err(object_store::Error::NotImplemented) if conditional => {
+                self.remote.store.put(&location, manifest.into()).await?
+            }
+            Err(e @ object_store::Error::Precondition { .. }) => {
+                return Err(e).context("Manifest was published by another server");
+            }
+            result => result?,
+        };
+
+        *self.published.lock().unwrap() = Some(UpdateVersion {
+            e_tag: result.e_tag,
+            version: result.version,
+        });
+        Ok(())
+    }
+
+    /// Content version of the published manifest, `None` if there is none,
+    /// e.g. after the collection was deleted
+    pub(crate) async fn version(&self) -> anyhow::Result<Option<u64>> {
+        match self.manifest().await {
+            Ok(manifest) => Ok(Some(manifest.version)),
+            Err(e)
+                if matches!(
+                    e.downcast_ref::<object_store::Error>(),
+                    Some(object_store::Error::NotFound { .. })
+                ) =>
+            {
+                Ok(None)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// The published manifest, whose object version is kept for [Self::publish]
+    async fn manifest(&self) -> anyhow::Result<Manifest> {
+        let result = self
+            .remote
+            .store
+            .get(&self.remote.path(&self.name, MANIFEST_FILE))
+            .await?;
+        let published = UpdateVersion {
+            e_tag: result.meta.e_tag.clone(),
+            version: result.meta.version.clone(),
+        };
+        let manifest = serde_json::from_slice(&result.bytes().await?).context("Parse manifest")?;
+        *self.published.lock().unwrap() = Some(published);
+        Ok(manifest)
+    }
+
+    /// Delete all objects of the collection
+    pub(crate) fn delete(&self) -> anyhow::Result<()> {
+        self.remote.block_on(async {
+            let prefix = self.remote.prefix.child(self.name.as_str());
+            let locations: Vec<ObjectPath> = self
+                .remote
+                .store
+                .list(Some(&prefix))
+                .map_ok(|meta| meta.location)
+                .try_collect()
+                .await?;
+            for location in locations {
+                self.remote.store.delete(&location).await?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Open the collection with its segments downloaded on first access
+    pub(crate) async fn open(self: &Arc<Self>) -> anyhow::Result<Collection> {
+        let remote = &self.remote;
+
+        let manifest = self.manifest().await?;
+
+        let schema = remote
+            .store
+            .get(&remote.path(&self.name, SCHEMA_FILE))
+            .await?
+            .bytes()
+            .await?;
+        let schema = StreamReader::try_new(std::io::Cursor::new(schema), None)?.schema();
+
+        let store = PointCloudStore::try_new(1000, remote.cache_dir.join(&self.name), false)?
+            .with_source(self.clone());
+        for (key, size) in &manifest.segments {
+            let path = store.insert_spilled(key);
+
+            // keep cached segments of the same size, they are never rewritten
+            // without changing it
+            match std::fs::metadata(&path) {
+                Ok(metadata) if metadata.len() == *size => remote.track(path, *size),
+                Ok(_) => std::fs::remove_file(&path)?,
+                Err(_) => (),
+            }
+        }
+        *self.uploaded.lock().unwrap() = manifest.segments.into_iter().collect();
+
+        let pc = ArrowPointCloud::try_new_with(schema, store)?;
+        let collection = Collection::with_version(pc, manifest.version)
+            .with_remote(self.clone())
+            .with_views(Views::from_manifest(manifest.views));
+
+        // the preview drawn by this server, if cached
+        let path = Preview::path(&remote.cache_dir.join(&self.name));
+        if !path.exists() {
+            return Ok(collection);
+        }
+        match Preview::read(&path) {
+            Ok(preview) => Ok(collection.with_preview(preview)),
+            Err(e) => {
+                tracing::warn!("Ignoring preview {path:?}: {e:#}");
+                Ok(collection)
+            }
+        }
+    }
+}
+
+/// Open the collections of the object store, if configured.
+///
+/// Failures are logged per collection and do not affect the others. The
+/// history of the collections is not restored, their previews and indices only
+/// if cached. Stale indices are rebuilt in the background.
+pub(crate) async fn restore(state: &SharedState) {
+    let Some(remote) = state.read().await.remote.clone() else {
+        return;
+    };
+
+    let names = match remote.collections().await {
+        Ok(names) => names,
+        Err(e) => {
+            tracing::error!("Failed to list the collections of the store: {e}");
+            return;
+        }
+    };
+
+    for name in names {
+        match remote.collection(&name).open().await {
+            Ok(collection) => {
+                tracing::info!("Opened collection `{name}` of the store");
+                publish(state, &name, collection).await;
+            }
+            Err(e) => tracing::error!("Failed to open collection `{name}` of the store: {e:#}"),
+        }
+    }
+}
+
+/// Publish a collection opened from the store, replacing the one of the same
+/// name, and rebuild its index in the background if the cached one is stale
+pub(crate) async fn publish(state: &SharedState, name: &str, mut collection: Collection) {
+    collection.revalidate_views(name);
+    let stale = collection.restore_index();
+    state.write().await.data.insert(name.to_owned(), collection);
+
+    if stale {
+        let job = Arc::new(Job::new(name, JobSpec::Index));
+        state.write().await.jobs.insert(job.id.clone(), job.clone());
+        tracing::info!("Queued job {} rebuilding the index of `{name}`", job.id);
+        jobs::spawn(state.clone(), job);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow::ipc::reader::StreamReader;
+    use axum::{
+        body::Body,
+        http::{Method, StatusCode},
+    };
+    use clap::Parser;
+    use http_body_util::BodyExt;
+    use object_store::memory::InMemory;
+
+    use super::*;
+    use crate::handlers::testing::{grid, send};
+
+    #[test]
+    fn urls() {
+        for url in [
+            "s3://bucket/prefix",
+            "gs://bucket/prefix",
+            "file:///tmp/prefix",
+        ] {
+            let (_, prefix) = parse(url).unwrap();
+            assert!(prefix.as_ref().ends_with("prefix"), "{url}: {prefix}");
+        }
+        assert!(parse("ftp://host/prefix").is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = Arc::new(InMemory::new());
+        for key in ["a", "b", "c"] {
+            let location = ObjectPath::from(format!("prefix/grid/{key}.arrow"));
+            store.put(&location, vec![0u8; 100].into()).await.unwrap();
+        }
+
+        let remote = Arc::new(Remote::new(
+            store,
+            ObjectPath::from("prefix"),
+            dir.path().to_owned(),
+            250,
+        ));
+        let collection = remote.collection("grid");
+
+        let path = |key: &str| dir.path().join(format!("{key}.arrow"));
+        for key in ["a", "b"] {
+            collection.fetch(key, &path(key)).unwrap();
+            assert_eq!(std::fs::read(path(key)).unwrap(), vec![0u8; 100]);
+        }
+        assert!(collection.fetch("missing", &path("missing")).is_err());
+
+        // the oldest segment exceeds the cache size
+        collection.fetch("c", &path("c")).unwrap();
+        assert!(!path("a").exists());
+        assert!(path("b").exists() && path("c").exists());
+
+        // rewritten segments are kept
+        std::fs::write(path("b"), [1u8; 10]).unwrap();
+        collection.fetch("a", &path("a")).unwrap();
+        assert!(path("b").exists());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn publish() {
+        let dir = tempfile::tempdir().unwrap();
+        let remote = Arc::new(Remote::new(
+            Arc::new(InMemory::new()),
+            ObjectPath::from("prefix"),
+            dir.path().to_owned(),
+            0,
+        ));
+        let manifest = |version| {
+            let segments = Vec::new();
+            let (num_points, bounds) = (None, None);
+            serde_json::to_vec(&Manifest {
+                version,
+                segments,
+                num_points,
+                bounds,
+                views: Default::default(),
+            })
+            .unwrap()
+        };
+
+        let (a, b) = (remote.collection("grid"), remote.collection("grid"));
+        assert_eq!(a.version().await.unwrap(), None);
+        a.publish(manifest(1)).await.unwrap();
+        assert_eq!(b.version().await.unwrap(), Some(1));
+
+        // the manifest read by the other writer was replaced since
+        a.publish(manifest(2)).await.unwrap();
+        assert!(b.publish(manifest(3)).await.is_err());
+        assert_eq!(b.version().await.unwrap(), Some(2));
+        b.publish(manifest(3)).await.unwrap();
+        assert_eq!(a.version().await.unwrap(), Some(3));
+    }
+
+    async fn count(app: &axum::Router, uri: &str) -> usize {
+        let response = send(app, Method::GET, uri, Body::empty()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        StreamReader::try_new(std::io::Cursor::new(body), None)
+            .unwrap()
+            .map(|batch| batch.unwrap().num_rows())
+            .sum()
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn restore() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("store");
+        std::fs::create_dir(&root).unwrap();
+        let url = format!("file://{}", root.display());
+        let config = |cache: &str| {
+            Config::parse_from([
+                "crux-server",
+                "--store",
+                &url,
+                "--storage-dir",
+                dir.path().join(cache).to_str().unwrap(),
+                "--chunk-size",
+                "10",
+                "--gc-interval",
+                "1",
+            ])
+        };
+
+        // uploaded on load
+        let app = crate::init(config("a")).await;
+        let response = send(
+            &app,
+            Method::POST,
+            "/load?collection=grid",
+            Body::from(grid(10, 10)),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let uploaded = std::fs::read_dir(root.join("grid")).unwrap().count();
+        assert_eq!(uploaded, 10 + 2);
+        let report = crux_format::inspect(root.join("grid").join(MANIFEST_FILE)).unwrap();
+        assert_eq!(report.num_points.unwrap().value, 100);
+        assert!(report.bounds.is_some_and(|bounds| bounds.exact));
+
+        // served by another server with an empty cache
+        let app = crate::init(config("b")).await;
+        let cached = || {
+            std::fs::read_dir(dir.path().join("b/grid"))
+                .unwrap()
+                .count()
+        };
+        assert_eq!(cached(), 0);
+        let bounded = "/points?collection=grid&bounds=0,0,0,10,1,100";
+        assert_eq!(count(&app, bounded).await, 10);
+        assert_eq!(count(&app, "/points?collection=grid").await, 100);
+        assert_eq!(cached(), 10);
+
+        // deleted from the store with the collection
+        let response = send(&app, Method::DELETE, "/load?collection=grid", Body::empty()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        for _ in 0..50 {
+            if !root.join("grid").join(MANIFEST_FILE).exists() {
+                return;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        }
+        panic!("collection not deleted from the store");
+    }
+}
@@ -0,0 +1,272 @@
+//! Read-only replicas of the collections of an object store.
+//!
+//! Several servers may serve the collections of the same `--store`, one of
+//! them writing and the others started with `--read-only`. Replicas refuse
+//! requests that change collections and poll the manifests of the store for
+//! the versions published by the writer, so that they converge within the
+//! poll interval. Responses are tagged and cached by content version, which
+//! the replicas take over from the manifests.
+
+use std::{sync::Weak, time::Duration};
+
+use axum::{
+    extract::{Request, State},
+    http::Method,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use tokio::sync::RwLock;
+
+use crate::{
+    error::AppError,
+    jobs, remote,
+    state::{AppState, SharedState},
+};
+
+/// Refuse requests changing the collections or the workers of read-only
+/// replicas with `405 Method Not Allowed`
+pub(crate) async fn read_only(
+    State(read_only): State<bool>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let path = request.uri().path();
+    if read_only && is_mutating(request.method(), path) {
+        return AppError::ReadOnly {
+            method: request.method().to_string(),
+            path: path.to_owned(),
+        }
+        .into_response();
+    }
+    next.run(request).await
+}
+
+/// Whether a request may change the collections or the workers, `GET /load`
+/// loads files while uploads are only validated. Jobs are refused by kind, see
+/// [crate::handlers::submit_job].
+fn is_mutating(method: &Method, path: &str) -> bool {
+    match *method {
+        Method::GET | Method::HEAD => path == "/load",
+        Method::OPTIONS => false,
+        Method::POST => !(path.ends_with("/validate") || path.ends_with("/jobs")),
+        // cancelling jobs
+        Method::DELETE => !path.starts_with("/jobs/"),
+        _ => true,
+    }
+}
+
+/// Poll the store for published versions until the state is dropped
+pub(crate) async fn poll_task(state: Weak<RwLock<AppState>>, interval: Duration) {
+    let mut interval = tokio::time::interval(interval);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    loop {
+        interval.tick().await;
+
+        let Some(state) = state.upgrade() else {
+            break;
+        };
+        poll(&state).await;
+    }
+}
+
+/// Open the collections of the store whose published version differs from
+/// the served one, and drop the ones deleted from the store.
+///
+/// Collections not opened from the store are left alone. Failures are logged
+/// per collection and retried by the next poll.
+async fn poll(state: &SharedState) {
+    let Some(remote) = state.read().await.remote.clone() else {
+        return;
+    };
+
+    let mut names = match remote.collections().await {
+        Ok(names) => names,
+        Err(e) => {
+            tracing::warn!("Failed to list the collections of the store: {e}");
+            return;
+        }
+    };
+
+    let mut published = Vec::new();
+    for name in names.drain(..) {
+        let collection = remote.collection(&name);
+        let version = match collection.version().await {
+            Ok(Some(version)) => version,
+            Ok(None) => continue,
+            Err(e) => {
+                tracing::warn!("Failed to read the manifest of `{name}`: {e:#}");
+                published.push(name);
+                continue;
+            }
+        };
+        published.push(name.clone());
+
+        let served = state
+            .read()
+            .await
+            .data
+            .get(&name)
+            .map(|c| (c.is_remote(), c.version()));
+        match served {
+            Some((false, _)) => continue,
+            Some((true, served)) if served == version => continue,
+            _ => (),
+        }
+
+        match collection.open().await {
+            Ok(collection) => {
+                tracing::info!("Opened version {version:016x} of collection `{name}`");
+                remote::publish(state, &name, collection).await;
+                jobs::refresh_preview(state, &name).await;
+            }
+            Err(e) => tracing::warn!("Failed to open collection `{name}` of the store: {e:#}"),
+        }
+    }
+
+    // the segments stay cached until they are evicted
+    let mut state = state.write().await;
+    let deleted: Vec<String> = state
+        .data
+        .iter()
+        .filter(|(name, c)| c.is_remote() && !published.contains(name))
+        .map(|(name, _)| name.clone())
+        .collect();
+    for name in deleted {
+        state.data.remove(&name);
+        tracing::info!("Dropped collection `{name}` deleted from the store");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow::ipc::reader::StreamReader;
+    use axum::{
+        body::Body,
+        http::{Method, StatusCode},
+    };
+    use clap::Parser;
+    use http_body_util::BodyExt;
+
+    use super::*;
+    use crate::{
+        handlers::testing::{grid, problem, send},
+        Config,
+    };
+
+    #[test]
+    fn mutating() {
+        assert!(is_mutating(&Method::POST, "/load"));
+        assert!(is_mutating(&Method::GET, "/load"));
+        assert!(is_mutating(&Method::DELETE, "/collections/grid"));
+        assert!(is_mutating(&Method::POST, "/workers"));
+        assert!(!is_mutating(&Method::GET, "/points"));
+        assert!(!is_mutating(&Method::GET, "/index"));
+        assert!(!is_mutating(&Method::POST, "/collections/grid/validate"));
+        assert!(!is_mutating(&Method::POST, "/collections/grid/jobs"));
+        assert!(!is_mutating(&Method::DELETE, "/jobs/1"));
+    }
+
+    /// Points of collection `grid`, `None` if it is unknown
+    async fn count(app: &axum::Router) -> Option<usize> {
+        let response = send(app, Method::GET, "/points?collection=grid", Body::empty()).await;
+        if response.status() == StatusCode::NOT_FOUND {
+            return None;
+        }
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let count = StreamReader::try_new(std::io::Cursor::new(body), None)
+            .unwrap()
+            .map(|batch| batch.unwrap().num_rows())
+            .sum();
+        Some(count)
+    }
+
+    /// Wait for the replica to serve `expected` points of `grid`
+    async fn converge(app: &axum::Router, expected: Option<usize>) {
+        for _ in 0..100 {
+            if count(app).await == expected {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        panic!("replica did not converge to {expected:?} points");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn replica() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("store");
+        std::fs::create_dir(&root).unwrap();
+        let url = format!("file://{}", root.display());
+        let config = |cache: &str, read_only: bool| {
+            let cache = dir.path().join(cache);
+            let mut args = vec![
+                "crux-server",
+                "--store",
+                &url,
+                "--storage-dir",
+                cache.to_str().unwrap(),
+                "--chunk-size",
+                "10",
+                "--gc-interval",
+                "1",
+                "--poll-interval",
+                "1",
+            ];
+            if read_only {
+                args.push("--read-only");
+            }
+            Config::parse_from(args)
+        };
+
+        let writer = crate::init(config("writer", false)).await;
+        let load = |rows: usize| {
+            let writer = writer.clone();
+            async move {
+                let uri = "/load?collection=grid";
+                let response = send(&writer, Method::POST, uri, Body::from(grid(2, rows))).await;
+                assert_eq!(response.status(), StatusCode::OK);
+            }
+        };
+        load(10).await;
+
+        let replica = crate::init(config("replica", true)).await;
+        assert_eq!(count(&replica).await, Some(20));
+
+        // appended on the writer
+        load(5).await;
+        assert_eq!(count(&writer).await, Some(30));
+        converge(&replica, Some(30)).await;
+
+        // changes are refused by the replica
+        for (method, uri) in [
+            (Method::POST, "/load?collection=grid"),
+            (Method::DELETE, "/collections/grid"),
+            (Method::GET, "/load?collection=other&file=points.las"),
+        ] {
+            let response = send(&replica, method.clone(), uri, Body::from(grid(1, 1))).await;
+            assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED, "{uri}");
+            let problem = problem(response).await;
+            assert_eq!(problem.kind, "crux:read-only");
+            assert_eq!(problem.params["method"], method.as_str());
+        }
+        assert_eq!(count(&writer).await, Some(30));
+        let request = axum::http::Request::builder()
+            .method(Method::POST)
+            .uri("/collections/grid/jobs")
+            .header("content-type", "application/json")
+            .body(Body::from(r#"{"kind":"sort"}"#))
+            .unwrap();
+        let response = tower::ServiceExt::oneshot(replica.clone(), request)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+
+        // deleted on the writer
+        let response = send(&writer, Method::DELETE, "/collections/grid", Body::empty()).await;
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        converge(&replica, None).await;
+    }
+}
@@ -1,17 +1,863 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::{HashMap, VecDeque},
+    ops::Deref,
+    path::PathBuf,
+    sync::{atomic::AtomicUsize, Arc, Weak},
+    time::{Duration, SystemTime},
+};
 
-use tokio::sync::RwLock;
+use anyhow::Context;
+use serde::Serialize;
+use tokio::sync::{RwLock, Semaphore};
 
-use crux_format::ArrowPointCloud;
+use arrow::datatypes::SchemaRef;
+use crux_format::{
+    soa::{BatchIndex, Index, PointCloudStore},
+    ArrowPointCloud, IndexFile, PointCloudError, PointCloudTrait,
+};
 
-use crate::Config;
+use crate::{
+    error::AppError,
+    handlers::{TileCache, View, Views},
+    health::Health,
+    jobs::{Job, JobSpec},
+    limits::Limiter,
+    preview::Preview,
+    remote::{Remote, RemoteCollection},
+    Config,
+};
 
 pub(crate) struct AppState {
     pub(crate) config: Config,
     pub(crate) workers: Vec<String>,
-    pub(crate) data: HashMap<String, ArrowPointCloud>,
+    pub(crate) data: HashMap<String, Collection>,
+    pub(crate) tombstones: Vec<Tombstone>,
+    pub(crate) jobs: HashMap<String, Arc<Job>>,
+    /// Latest ingest job of each collection loaded from the watched directory
+    pub(crate) watched: HashMap<String, Arc<Job>>,
+    /// Bounds the number of concurrently running jobs
+    pub(crate) job_slots: Arc<Semaphore>,
+    pub(crate) tiles: TileCache,
+    /// Store entries read by point queries, reported by `/status`
+    pub(crate) scanned: Arc<AtomicUsize>,
+    /// Object store collections are persisted to, see `--store`
+    pub(crate) remote: Option<Arc<Remote>>,
+    /// Readiness reported by `/readyz`
+    pub(crate) health: Arc<Health>,
+    /// Rate limits of the clients, see `--rate-limit-requests`
+    pub(crate) limiter: Arc<Limiter>,
 }
 
 unsafe impl Send for AppState {}
 
 pub(crate) type SharedState = Arc<RwLock<AppState>>;
+
+impl AppState {
+    pub(crate) fn new(config: Config) -> Self {
+        let job_slots = Arc::new(Semaphore::new(config.max_jobs.max(1)));
+        let remote = Remote::from_config(&config).unwrap_or_else(|e| {
+            tracing::error!("Failed to open the store: {e:#}");
+            None
+        });
+
+        let health = Arc::new(Health::new(&config));
+        let limiter = Arc::new(Limiter::new(&config));
+
+        Self {
+            config,
+            workers: Default::default(),
+            data: Default::default(),
+            tombstones: Default::default(),
+            jobs: Default::default(),
+            watched: Default::default(),
+            job_slots,
+            tiles: Default::default(),
+            scanned: Default::default(),
+            remote,
+            health,
+            limiter,
+        }
+    }
+
+    /// Collection `name`, an unknown collection error if there is none
+    pub(crate) fn collection(&self, name: &str) -> Result<&Collection, AppError> {
+        self.data
+            .get(name)
+            .ok_or_else(|| AppError::UnknownCollection(name.to_owned()))
+    }
+
+    pub(crate) fn collection_mut(&mut self, name: &str) -> Result<&mut Collection, AppError> {
+        self.data
+            .get_mut(name)
+            .ok_or_else(|| AppError::UnknownCollection(name.to_owned()))
+    }
+
+    /// Snapshot and entity tag of collection `name` at `version`, see
+    /// [Collection::at]
+    pub(crate) fn collection_at(
+        &self,
+        name: &str,
+        version: Option<u64>,
+    ) -> Result<(Arc<ArrowPointCloud>, String), AppError> {
+        let found = self
+            .collection(name)?
+            .at(version)
+            .ok_or_else(|| AppError::UnknownVersion {
+                collection: name.to_owned(),
+                at: version.unwrap_or_default(),
+            })?;
+        Ok(found.context("Restore version")?)
+    }
+
+    /// Remove a collection from the catalog.
+    ///
+    /// The segment files are kept until the garbage collector observes that no
+    /// snapshot of the collection is referenced anymore.
+    pub(crate) fn remove(&mut self, name: &str) -> bool {
+        let Some(collection) = self.data.remove(name) else {
+            return false;
+        };
+
+        tracing::info!("Tombstoned collection `{name}`");
+        self.tombstones.push(Tombstone::new(name, collection));
+
+        true
+    }
+
+    /// Drop versions of all collections that fell out of the retention window
+    pub(crate) fn expire_versions(&mut self) -> usize {
+        let retention = Retention::from(&self.config);
+        self.data
+            .values_mut()
+            .map(|collection| collection.prune(retention))
+            .sum()
+    }
+
+    /// Forget jobs that finished before the retention period
+    pub(crate) fn expire_jobs(&mut self) -> usize {
+        let retention = Duration::from_secs(self.config.job_retention);
+        let before = self.jobs.len();
+        self.jobs.retain(|_, job| !job.is_expired(retention));
+        before - self.jobs.len()
+    }
+
+    /// Job drawing the preview of collection `name`, unless previews are
+    /// disabled, the preview is up to date or already being drawn
+    pub(crate) fn preview_job(&mut self, name: &str) -> Option<Arc<Job>> {
+        let (budget, refresh) = (self.config.preview_points, self.config.preview_refresh);
+        let collection = self.data.get_mut(name)?;
+        let num_points = collection.num_points();
+        if budget == 0
+            || num_points == 0
+            || collection
+                .preview
+                .as_ref()
+                .is_some_and(|preview| !preview.is_outdated(num_points, refresh))
+            || collection
+                .preview_job
+                .as_ref()
+                .is_some_and(|job| !job.status().is_finished())
+        {
+            return None;
+        }
+
+        let job = Arc::new(Job::new(name, JobSpec::Preview));
+        collection.preview_job = Some(job.clone());
+        self.jobs.insert(job.id.clone(), job.clone());
+        Some(job)
+    }
+
+    /// Delete the spill files of store entries rewritten by deletions that are
+    /// no longer read, see [Collection::collect_segments]
+    pub(crate) fn collect_segments(&mut self) -> usize {
+        self.data
+            .values_mut()
+            .map(Collection::collect_segments)
+            .sum()
+    }
+
+    /// Delete segment files of tombstoned collections without live snapshots
+    pub(crate) fn collect_garbage(&mut self) -> usize {
+        let before = self.tombstones.len();
+
+        self.tombstones.retain(|tombstone| {
+            if tombstone.is_referenced() {
+                return true;
+            }
+
+            tombstone.purge();
+            false
+        });
+
+        before - self.tombstones.len()
+    }
+}
+
+/// A published collection
+///
+/// Readers take a reference counted [snapshot](Collection::snapshot) and
+/// release the state lock, so that they are not affected by concurrent
+/// deletion or republishing.
+pub(crate) struct Collection {
+    pc: Arc<ArrowPointCloud>,
+    superseded: Vec<Weak<ArrowPointCloud>>,
+    /// Content version, changed by every append or deletion of points
+    version: u64,
+    /// Committed versions within the retention window, oldest first
+    history: VecDeque<Version>,
+    /// Number of the next committed version
+    next_version: u64,
+    /// Object store the collection is uploaded to when persisted
+    remote: Option<Arc<RemoteCollection>>,
+    /// Sample served by `preview=true`
+    preview: Option<Preview>,
+    /// Latest job drawing the preview
+    preview_job: Option<Arc<Job>>,
+    /// Named queries of the points
+    views: Views,
+    /// Spill files of store entries rewritten by deletions
+    retired: Vec<Retired>,
+}
+
+/// Spill files replaced in the version `replaced_in`, still read by earlier
+/// versions and the snapshots of readers
+struct Retired {
+    paths: Vec<PathBuf>,
+    replaced_in: u64,
+}
+
+/// File of the content version in the store directory
+pub(crate) const VERSION_FILE: &str = "VERSION";
+/// File of the retained versions in the store directory
+pub(crate) const HISTORY_FILE: &str = "HISTORY.json";
+/// File of the spatial index in the store directory
+pub(crate) const INDEX_FILE: &str = "INDEX.arrow";
+/// File of the views in the store directory
+pub(crate) const VIEWS_FILE: &str = "VIEWS.json";
+
+/// How long committed versions of a collection are kept
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Retention {
+    /// Maximum number of versions, the latest one is always kept
+    pub(crate) versions: usize,
+    /// Maximum age of versions other than the latest one
+    pub(crate) age: Duration,
+}
+
+impl From<&Config> for Retention {
+    fn from(config: &Config) -> Self {
+        Self {
+            versions: config.history_versions.max(1),
+            age: Duration::from_secs(config.history_age),
+        }
+    }
+}
+
+/// A committed version of a collection, queryable until it falls out of the
+/// retention window
+#[derive(Clone, Serialize)]
+pub(crate) struct Version {
+    /// Sequence number, starting at 1
+    pub(crate) version: u64,
+    /// Seconds since the Unix epoch
+    pub(crate) created: u64,
+    pub(crate) num_points: usize,
+    /// Entity tag of the content
+    pub(crate) etag: String,
+    /// Number of batches of each store entry
+    manifest: Vec<(String, usize)>,
+    #[serde(skip)]
+    schema: SchemaRef,
+    /// Store shared with later versions
+    #[serde(skip)]
+    store: PointCloudStore,
+    #[serde(skip)]
+    committed: SystemTime,
+}
+
+impl Version {
+    /// The points as of this version.
+    ///
+    /// Batches are appended to store entries only, so the version is the
+    /// leading batches of the entries in its manifest.
+    pub(crate) fn snapshot(&self) -> Result<ArrowPointCloud, PointCloudError> {
+        let mut pc = ArrowPointCloud::try_new(self.schema.clone())?;
+        for (key, n) in &self.manifest {
+            for batch in self.store.batches(key).into_iter().take(*n) {
+                pc.append(batch)?;
+            }
+        }
+        Ok(pc)
+    }
+}
+
+impl Collection {
+    /// Collection of pre-loaded points, versioned by the content hash of their
+    /// digest, so that the same points loaded in other batches keep the version
+    pub(crate) fn new(pc: ArrowPointCloud) -> Self {
+        let version = pc.digest().content_hash;
+        Self::with_version(pc, version)
+    }
+
+    /// Collection filled by appends, starting at a random version so that a
+    /// recreated collection does not repeat the versions of a deleted one
+    pub(crate) fn empty(pc: ArrowPointCloud) -> Self {
+        Self::with_version(pc, rand::random())
+    }
+
+    pub(crate) fn with_version(pc: ArrowPointCloud, version: u64) -> Self {
+        Self {
+            pc: Arc::new(pc),
+            superseded: Vec::new(),
+            version,
+            history: VecDeque::new(),
+            next_version: 1,
+            remote: None,
+            preview: None,
+            preview_job: None,
+            views: Views::default(),
+            retired: Vec::new(),
+        }
+    }
+
+    /// Upload the collection to the object store when it is persisted
+    pub(crate) fn with_remote(mut self, remote: Arc<RemoteCollection>) -> Self {
+        self.remote = Some(remote);
+        self
+    }
+
+    /// Whether the collection is opened from or uploaded to the object store
+    pub(crate) fn is_remote(&self) -> bool {
+        self.remote.is_some()
+    }
+
+    /// Collection with a preview read from disk
+    pub(crate) fn with_preview(mut self, preview: Preview) -> Self {
+        self.preview = Some(preview);
+        self
+    }
+
+    /// Entity tag of the current version
+    pub(crate) fn etag(&self) -> String {
+        format!("\"{:016x}\"", self.version)
+    }
+
+    /// Version of the content
+    pub(crate) fn version(&self) -> u64 {
+        self.version
+    }
+
+    /// Mark the content as changed
+    pub(crate) fn touch(&mut self) {
+        self.version = self.version.wrapping_add(1);
+    }
+
+    /// Write the points to the store and the version, history and views next
+    /// to them, then upload them to the object store, if any
+    pub(crate) fn persist(&self) {
+        self.pc.flush();
+
+        let path = self.pc.store.dir.join(VERSION_FILE);
+        if let Err(e) = std::fs::write(&path, self.version.to_string()) {
+            tracing::warn!("Failed to persist version to {path:?}: {e}");
+        }
+
+        let path = self.pc.store.dir.join(HISTORY_FILE);
+        let history = serde_json::to_vec(&self.history).map_err(std::io::Error::from);
+        if let Err(e) = history.and_then(|history| std::fs::write(&path, history)) {
+            tracing::warn!("Failed to persist history to {path:?}: {e}");
+        }
+
+        let path = self.pc.store.dir.join(VIEWS_FILE);
+        if let Err(e) = self.views.write(&path) {
+            tracing::warn!("Failed to persist views to {path:?}: {e}");
+        }
+
+        if let Some(remote) = &self.remote {
+            if let Err(e) = remote.upload(&self.pc, self.version, &self.views) {
+                tracing::warn!("Failed to upload to the store: {e:#}");
+            }
+        }
+    }
+
+    /// Record the current content as the next version, unless it is unchanged
+    /// since the last commit, and drop versions outside of the retention window
+    pub(crate) fn commit(&mut self, retention: Retention) {
+        let etag = self.etag();
+        if self.history.back().is_some_and(|v| v.etag == etag) {
+            return;
+        }
+
+        let committed = SystemTime::now();
+        let manifest = self
+            .pc
+            .store
+            .iter()
+            .map(|e| (e.key().to_owned(), self.pc.store.batches(e.key()).len()))
+            .collect();
+
+        self.history.push_back(Version {
+            version: self.next_version,
+            created: committed
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            num_points: self.pc.num_points(),
+            etag,
+            manifest,
+            schema: self.pc.schema(),
+            store: self.pc.store.clone(),
+            committed,
+        });
+        self.next_version += 1;
+
+        self.prune(retention);
+    }
+
+    /// Drop versions outside of the retention window, returns their number
+    pub(crate) fn prune(&mut self, retention: Retention) -> usize {
+        let before = self.history.len();
+
+        while self.history.len() > retention.versions.max(1) {
+            self.history.pop_front();
+        }
+        while self.history.len() > 1
+            && self.history[0]
+                .committed
+                .elapsed()
+                .is_ok_and(|age| age > retention.age)
+        {
+            self.history.pop_front();
+        }
+
+        before - self.history.len()
+    }
+
+    pub(crate) fn views(&self) -> &Views {
+        &self.views
+    }
+
+    /// Collection with views, e.g. of the manifest of the object store
+    pub(crate) fn with_views(mut self, views: Views) -> Self {
+        self.views = views;
+        self
+    }
+
+    /// Add or replace a view and persist it with the collection, returns
+    /// whether it is new
+    pub(crate) fn set_view(&mut self, name: &str, view: View) -> bool {
+        let created = self.views.insert(name, view);
+        if self.is_persisted() {
+            self.persist();
+        }
+        created
+    }
+
+    /// Remove a view and persist the others with the collection
+    pub(crate) fn remove_view(&mut self, name: &str) -> Option<View> {
+        let view = self.views.remove(name)?;
+        if self.is_persisted() {
+            self.persist();
+        }
+        Some(view)
+    }
+
+    /// Hand over the views, e.g. to the collection replacing this one
+    pub(crate) fn take_views(&mut self) -> Views {
+        std::mem::take(&mut self.views)
+    }
+
+    /// Read the views written by [Collection::persist], if any
+    pub(crate) fn restore_views(&mut self) {
+        let path = self.pc.store.dir.join(VIEWS_FILE);
+        if !path.exists() {
+            return;
+        }
+        match Views::read(&path) {
+            Ok(views) => self.views = views,
+            Err(e) => tracing::warn!("Ignoring views {path:?}: {e:#}"),
+        }
+    }
+
+    /// Check the views against the current schema of collection `name` and
+    /// flag those that no longer apply as stale
+    pub(crate) fn revalidate_views(&mut self, name: &str) {
+        let schema = self.pc.schema();
+        for view in self.views.revalidate(name, &schema) {
+            tracing::warn!("View `{view}` of `{name}` is stale after its schema changed");
+        }
+    }
+
+    pub(crate) fn preview(&self) -> Option<&Preview> {
+        self.preview.as_ref()
+    }
+
+    /// Replace the preview, which is written next to the store directory if
+    /// the collection is persisted
+    pub(crate) fn set_preview(&mut self, preview: Preview) {
+        if self.is_persisted() {
+            let path = Preview::path(&self.pc.store.dir);
+            if let Err(e) = preview.write(&path) {
+                tracing::warn!("Failed to persist preview to {path:?}: {e:#}");
+            }
+        }
+        self.preview = Some(preview);
+    }
+
+    /// Write `index`, built at content `version`, to the store directory if
+    /// the collection is persisted, or remove the written index if there is
+    /// none. Indices of changed content are not written.
+    pub(crate) fn persist_index(&self, index: Option<&BatchIndex>, version: u64) {
+        let path = self.pc.store.dir.join(INDEX_FILE);
+        match index {
+            Some(index) if self.is_persisted() && version == self.version => {
+                if let Err(e) = index.write(&path, version) {
+                    tracing::warn!("Failed to persist index to {path:?}: {e}");
+                }
+            }
+            Some(_) => (),
+            None => {
+                if let Err(e) = std::fs::remove_file(&path) {
+                    if e.kind() != std::io::ErrorKind::NotFound {
+                        tracing::warn!("Failed to remove {path:?}: {e}");
+                    }
+                }
+            }
+        }
+    }
+
+    /// Read the index written by [Collection::persist_index], returns whether
+    /// it is stale and has to be rebuilt
+    pub(crate) fn restore_index(&mut self) -> bool {
+        let path = self.pc.store.dir.join(INDEX_FILE);
+        if !path.exists() {
+            return false;
+        }
+
+        let version = self.version;
+        let Some(pc) = self.get_mut() else {
+            return false;
+        };
+        match BatchIndex::read(&path, pc, version) {
+            Ok(Some(index)) => {
+                pc.index = Index::Batch(index);
+                false
+            }
+            Ok(None) => {
+                tracing::info!("Index {path:?} is stale");
+                true
+            }
+            Err(e) => {
+                tracing::warn!("Ignoring index {path:?}: {e}");
+                true
+            }
+        }
+    }
+
+    /// Whether the collection is written to disk, see [Collection::persist]
+    pub(crate) fn is_persisted(&self) -> bool {
+        self.remote.is_some() || self.pc.store.dir.join(VERSION_FILE).exists()
+    }
+
+    /// Retained versions, oldest first
+    pub(crate) fn versions(&self) -> impl Iterator<Item = &Version> {
+        self.history.iter()
+    }
+
+    /// Snapshot and entity tag of a retained version, or of the current one
+    pub(crate) fn at(
+        &self,
+        version: Option<u64>,
+    ) -> Option<Result<(Arc<ArrowPointCloud>, String), PointCloudError>> {
+        let Some(version) = version else {
+            return Some(Ok((self.snapshot(), self.etag())));
+        };
+
+        let version = self.history.iter().find(|v| v.version == version)?;
+        if version.etag == self.etag() {
+            return Some(Ok((self.snapshot(), version.etag.clone())));
+        }
+        Some(
+            version
+                .snapshot()
+                .map(|pc| (Arc::new(pc), version.etag.clone())),
+        )
+    }
+
+    /// Publish `pc` as the next version, which replaced the store entries
+    /// `rewritten` of the current one. Their spill files are removed by
+    /// [Collection::collect_segments] once no longer read.
+    pub(crate) fn publish_rewritten(
+        &mut self,
+        pc: ArrowPointCloud,
+        rewritten: &[String],
+        retention: Retention,
+    ) {
+        let paths = rewritten
+            .iter()
+            .filter_map(|key| self.pc.store.get(key))
+            .collect();
+        self.retired.push(Retired {
+            paths,
+            replaced_in: self.next_version,
+        });
+
+        self.publish(pc);
+        self.touch();
+        self.commit(retention);
+    }
+
+    /// Delete the spill files of rewritten store entries once neither a
+    /// snapshot of a reader nor a retained version reads them, returns their
+    /// number
+    pub(crate) fn collect_segments(&mut self) -> usize {
+        self.superseded.retain(|pc| pc.strong_count() > 0);
+        if !self.superseded.is_empty() {
+            return 0;
+        }
+
+        let oldest = self.history.front().map_or(u64::MAX, |v| v.version);
+        let mut removed = 0;
+        self.retired.retain(|retired| {
+            if retired.replaced_in > oldest {
+                return true;
+            }
+            for path in &retired.paths {
+                match std::fs::remove_file(path) {
+                    Ok(_) => removed += 1,
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => (),
+                    Err(e) => tracing::warn!("Failed to remove segment {path:?}: {e}"),
+                }
+            }
+            false
+        });
+        removed
+    }
+
+    /// Reference counted handle to the current version
+    pub(crate) fn snapshot(&self) -> Arc<ArrowPointCloud> {
+        self.pc.clone()
+    }
+
+    /// Mutable access to the current version, if it is not shared by readers
+    pub(crate) fn get_mut(&mut self) -> Option<&mut ArrowPointCloud> {
+        Arc::get_mut(&mut self.pc)
+    }
+
+    /// Replace the current version, which may still be held by readers
+    pub(crate) fn publish(&mut self, pc: ArrowPointCloud) {
+        let previous = std::mem::replace(&mut self.pc, Arc::new(pc));
+        self.superseded.push(Arc::downgrade(&previous));
+        drop(previous);
+
+        self.superseded.retain(|pc| pc.strong_count() > 0);
+    }
+}
+
+impl Deref for Collection {
+    type Target = ArrowPointCloud;
+
+    fn deref(&self) -> &Self::Target {
+        &self.pc
+    }
+}
+
+/// A deleted collection awaiting garbage collection
+pub(crate) struct Tombstone {
+    name: String,
+    dir: PathBuf,
+    segments: Vec<PathBuf>,
+    snapshots: Vec<Weak<ArrowPointCloud>>,
+    remote: Option<Arc<RemoteCollection>>,
+}
+
+impl Tombstone {
+    fn new(name: &str, collection: Collection) -> Self {
+        let segments = collection
+            .store
+            .iter()
+            .map(|e| e.value().to_owned())
+            .chain(collection.retired.iter().flat_map(|r| r.paths.clone()))
+            .collect();
+
+        let mut snapshots = collection.superseded;
+        snapshots.push(Arc::downgrade(&collection.pc));
+
+        Self {
+            name: name.to_owned(),
+            dir: collection.pc.store.dir.to_owned(),
+            segments,
+            snapshots,
+            remote: collection.remote,
+        }
+    }
+
+    fn is_referenced(&self) -> bool {
+        self.snapshots.iter().any(|pc| pc.strong_count() > 0)
+    }
+
+    fn purge(&self) {
+        for path in &self.segments {
+            match std::fs::remove_file(path) {
+                Ok(_) => (),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => (),
+                Err(e) => tracing::warn!("Failed to remove segment {path:?}: {e}"),
+            }
+        }
+
+        let files =
+            [VERSION_FILE, HISTORY_FILE, INDEX_FILE, VIEWS_FILE].map(|file| self.dir.join(file));
+        for path in files.into_iter().chain([Preview::path(&self.dir)]) {
+            if let Err(e) = std::fs::remove_file(&path) {
+                if e.kind() != std::io::ErrorKind::NotFound {
+                    tracing::warn!("Failed to remove {path:?}: {e}");
+                }
+            }
+        }
+
+        if let Some(remote) = &self.remote {
+            if let Err(e) = remote.delete() {
+                tracing::warn!("Failed to delete `{}` from the store: {e:#}", self.name);
+            }
+        }
+
+        // only remove the store directory if no other collection shares it
+        if std::fs::remove_dir(&self.dir).is_err() {
+            tracing::debug!("Keeping non-empty store directory {:?}", self.dir);
+        }
+
+        tracing::info!("Collected garbage of collection `{}`", self.name);
+    }
+}
+
+/// Periodically collect garbage of deleted collections until the state is dropped
+pub(crate) async fn gc_task(state: Weak<RwLock<AppState>>, interval: std::time::Duration) {
+    let mut interval = tokio::time::interval(interval);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    loop {
+        interval.tick().await;
+
+        let Some(state) = state.upgrade() else {
+            break;
+        };
+        let mut state = state.write().await;
+        state.limiter.prune();
+        let n = state.expire_versions();
+        if n > 0 {
+            tracing::debug!("Expired {n} collection version(s)");
+        }
+        let n = state.expire_jobs();
+        if n > 0 {
+            tracing::debug!("Forgot {n} finished job(s)");
+        }
+        let n = state.collect_segments();
+        if n > 0 {
+            tracing::debug!("Removed {n} rewritten segment(s)");
+        }
+        if !state.tombstones.is_empty() {
+            let n = state.collect_garbage();
+            tracing::debug!("Garbage collection removed {n} collection(s)");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow::array::{Array, Float64Array};
+    use crux_format::{Expr, Synthetic};
+
+    use super::*;
+
+    #[test]
+    fn index() {
+        let dir = tempfile::tempdir().unwrap();
+        let points = Synthetic::new(1000).batch_size(100).terrain().unwrap();
+        let store = PointCloudStore::try_new(u64::MAX, dir.path(), false).unwrap();
+        let mut pc = ArrowPointCloud::try_new_with(points.schema(), store).unwrap();
+        for e in points.store.iter() {
+            for batch in points.store.batches(e.key()) {
+                pc.append(batch).unwrap();
+            }
+        }
+        let reopen = |pc: &ArrowPointCloud, version| {
+            let pc = ArrowPointCloud::try_new_with(pc.schema(), pc.store.clone()).unwrap();
+            Collection::with_version(pc, version)
+        };
+
+        // only persisted collections write their index
+        let collection = Collection::with_version(pc, 7);
+        let index = collection.batch_index(&()).unwrap();
+        collection.persist_index(Some(&index), 7);
+        assert!(!reopen(&collection, 7).restore_index());
+        collection.persist();
+        collection.persist_index(Some(&index), 6);
+        assert!(!reopen(&collection, 7).restore_index());
+
+        collection.persist_index(Some(&index), 7);
+        let mut restored = reopen(&collection, 7);
+        assert!(!restored.restore_index());
+        assert!(matches!(&restored.index, Index::Batch(index) if index.size() == 10));
+
+        // stale after changes
+        assert!(reopen(&collection, 8).restore_index());
+
+        collection.persist_index(None, 7);
+        assert!(!reopen(&collection, 7).restore_index());
+    }
+
+    #[test]
+    fn collect_segments() {
+        let dir = tempfile::tempdir().unwrap();
+        let points = Synthetic::new(1000).batch_size(100).terrain().unwrap();
+        let store = PointCloudStore::try_new(u64::MAX, dir.path(), false).unwrap();
+        let mut pc = ArrowPointCloud::try_new_with(points.schema(), store).unwrap();
+        for e in points.store.iter() {
+            for batch in points.store.batches(e.key()) {
+                pc.append(batch).unwrap();
+            }
+        }
+        pc.flush();
+        let retention = Retention {
+            versions: 1,
+            age: Duration::from_secs(3600),
+        };
+        let mut collection = Collection::with_version(pc, 1);
+        collection.commit(retention);
+
+        // the first point of the first entry and those left of it
+        let first = collection.store.iter().next().unwrap();
+        let batch = &collection.store.batches(first.key())[0];
+        let x = batch.column_by_name("x").unwrap();
+        let x = x.as_any().downcast_ref::<Float64Array>().unwrap().value(0);
+        let filter: Expr = format!("x<={x}").parse().unwrap();
+        let retained = collection.retain(|b| filter.exclude(b), &()).unwrap();
+        assert!(retained.dropped > 0);
+        let rewritten: Vec<_> = retained.rewritten.iter().map(|(k, _)| k.clone()).collect();
+        let paths: Vec<_> = rewritten
+            .iter()
+            .filter_map(|key| collection.store.get(key))
+            .collect();
+        assert!(!paths.is_empty());
+        let kept: Vec<_> = collection
+            .store
+            .iter()
+            .filter(|e| !rewritten.iter().any(|key| key == e.key()))
+            .filter_map(|e| collection.store.get(e.key()))
+            .collect();
+
+        let reader = collection.snapshot();
+        collection.publish_rewritten(retained.pc, &rewritten, retention);
+        assert_eq!(collection.history.len(), 1);
+
+        // still read by the snapshot
+        assert_eq!(collection.collect_segments(), 0);
+        assert!(paths.iter().all(|path| path.exists()));
+
+        drop(reader);
+        assert_eq!(collection.collect_segments(), paths.len());
+        assert!(paths.iter().all(|path| !path.exists()));
+        assert!(kept.iter().all(|path| path.exists()));
+        assert_eq!(collection.collect_segments(), 0);
+    }
+}
@@ -0,0 +1,452 @@
+use std::{io::Cursor, sync::Arc};
+
+use anyhow::{anyhow, bail, Context};
+use arrow::{
+    array::AsArray,
+    compute::{cast, max, min},
+    csv,
+    datatypes::{DataType, Field, Float64Type, Schema, SchemaRef},
+    ipc::reader::StreamReader,
+    record_batch::RecordBatch,
+};
+use serde::{Deserialize, Serialize};
+
+use crux_format::{
+    columns::CONVENTIONS,
+    compute::add_importance,
+    inspect_with,
+    schema::{self, PCE_DIMENSION_KEY},
+    ArrowPointCloud, CloudMetadata, Point, PointCloudTrait, PointTrait,
+};
+
+/// Format of an upload
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum UploadFormat {
+    /// Arrow IPC stream, as posted to `/load`
+    Arrow,
+    Las,
+    Laz,
+    /// Text with a header row and `x`, `y` and `z` columns
+    Csv,
+}
+
+impl UploadFormat {
+    /// Guess the format from the first bytes
+    fn detect(bytes: &[u8]) -> Self {
+        if bytes.starts_with(b"LASF") {
+            // compression is flagged in the header
+            Self::Las
+        } else if bytes.starts_with(&[0xff, 0xff, 0xff, 0xff]) {
+            Self::Arrow
+        } else {
+            Self::Csv
+        }
+    }
+}
+
+/// Column of the validated schema
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub(crate) struct ColumnReport {
+    pub name: String,
+    pub data_type: String,
+    /// Dimension of coordinates, starting at 1
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dimension: Option<String>,
+}
+
+/// Result of an ingest dry run
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub(crate) struct Validation {
+    pub format: UploadFormat,
+    /// Columns as they would be stored, after normalization
+    pub columns: Vec<ColumnReport>,
+    /// Coordinate columns in order of their dimension
+    pub dimensions: Vec<String>,
+    #[serde(flatten)]
+    pub metadata: CloudMetadata,
+    /// Points in the validated bytes
+    pub points_read: usize,
+    /// Points of the complete upload
+    pub estimated_points: u64,
+    /// Bounds recorded in the header of LAS and LAZ files
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bounds: Option<[[f64; 3]; 2]>,
+    /// Only the head of the upload was validated
+    pub partial: bool,
+    pub warnings: Vec<String>,
+}
+
+/// Validate the upload `bytes` without storing it, the first bytes of an
+/// upload of `size` bytes if it is larger
+pub(crate) fn validate(
+    bytes: &[u8],
+    size: Option<u64>,
+    format: Option<UploadFormat>,
+) -> anyhow::Result<Validation> {
+    let size = size.unwrap_or(bytes.len() as u64).max(bytes.len() as u64);
+    let partial = size > bytes.len() as u64;
+    let mut warnings = Vec::new();
+
+    let mut bounds = None;
+    let (format, batches, header_points, consumed) =
+        match format.unwrap_or_else(|| UploadFormat::detect(bytes)) {
+            UploadFormat::Las | UploadFormat::Laz => {
+                let head = crux_io::las::read_head(bytes)?;
+                let format = if head.compressed {
+                    UploadFormat::Laz
+                } else {
+                    UploadFormat::Las
+                };
+                let header = inspect_with(bytes).context("Invalid LAS header")?;
+                let number_of_points = header.num_points.map_or(0, |n| n.value);
+                bounds = header.bounds.map(|bounds| bounds.value);
+
+                let read = head.batch.num_rows() as u64;
+                if !partial && read < number_of_points {
+                    warnings.push(format!(
+                        "file ends after {read} of {number_of_points} points"
+                    ));
+                }
+                (format, vec![head.batch], Some(number_of_points), size)
+            }
+            UploadFormat::Arrow => {
+                let batches = read_stream(bytes, partial, &mut warnings)?;
+                (UploadFormat::Arrow, batches, None, bytes.len() as u64)
+            }
+            UploadFormat::Csv => {
+                // the head ends within a row
+                let text = match (partial, bytes.iter().rposition(|b| *b == b'\n')) {
+                    (true, Some(end)) => &bytes[..=end],
+                    _ => bytes,
+                };
+                let batches = read_csv(text, &mut warnings)?;
+                (UploadFormat::Csv, batches, None, text.len() as u64)
+            }
+        };
+
+    let points_read = batches.iter().map(RecordBatch::num_rows).sum::<usize>();
+    let estimated_points = header_points.unwrap_or(if consumed > 0 {
+        (points_read as f64 * size as f64 / consumed as f64).round() as u64
+    } else {
+        0
+    });
+
+    let mut validation = Validation {
+        format,
+        columns: Vec::new(),
+        dimensions: Vec::new(),
+        metadata: CloudMetadata::default(),
+        points_read,
+        estimated_points,
+        bounds,
+        partial,
+        warnings,
+    };
+    if let Some(schema) = batches.first().map(RecordBatch::schema) {
+        inspect(&mut validation, schema, batches)?;
+    }
+
+    Ok(validation)
+}
+
+/// Batches of an IPC stream up to the first batch that cannot be read
+fn read_stream(
+    bytes: &[u8],
+    partial: bool,
+    warnings: &mut Vec<String>,
+) -> anyhow::Result<Vec<RecordBatch>> {
+    let reader =
+        StreamReader::try_new(Cursor::new(bytes), None).context("Invalid Arrow IPC stream")?;
+    let schema = reader.schema();
+
+    let mut batches = Vec::new();
+    for batch in reader {
+        match batch {
+            Ok(batch) => batches.push(batch),
+            Err(e) => {
+                if !partial {
+                    warnings.push(format!("stream ends within a batch: {e}"));
+                }
+                break;
+            }
+        }
+    }
+    if batches.is_empty() {
+        batches.push(RecordBatch::new_empty(schema));
+    }
+    Ok(batches)
+}
+
+/// Batches of a CSV file with the coordinates in the columns `x`, `y` and
+/// `z`, regardless of case. Reading stops at the batch of the first invalid row.
+fn read_csv(text: &[u8], warnings: &mut Vec<String>) -> anyhow::Result<Vec<RecordBatch>> {
+    let format = csv::reader::Format::default().with_header(true);
+    let (inferred, _) = format
+        .infer_schema(Cursor::new(text), Some(1000))
+        .context("Invalid CSV")?;
+
+    let mut missing = Vec::new();
+    let mut fields: Vec<Field> = inferred
+        .fields()
+        .iter()
+        .map(|f| f.as_ref().clone())
+        .collect();
+    for coordinate in Point::<f64, 3>::schema().fields().iter() {
+        let Some(field) = fields
+            .iter_mut()
+            .find(|field| field.name().eq_ignore_ascii_case(coordinate.name()))
+        else {
+            missing.push(coordinate.name().to_owned());
+            continue;
+        };
+        if !field.data_type().is_numeric() {
+            warnings.push(format!(
+                "coordinate column `{}` is not numeric but {}",
+                field.name(),
+                field.data_type()
+            ));
+            missing.push(coordinate.name().to_owned());
+            continue;
+        }
+        *field = coordinate.as_ref().clone().with_name(field.name());
+    }
+    if !missing.is_empty() {
+        warnings.push(format!(
+            "missing coordinate columns: {}",
+            missing.join(", ")
+        ));
+        return Ok(Vec::new());
+    }
+
+    let schema = Arc::new(Schema::new(fields));
+    let reader = csv::ReaderBuilder::new(schema.clone())
+        .with_header(true)
+        .build(Cursor::new(text))?;
+
+    let mut batches = Vec::new();
+    for batch in reader {
+        match batch {
+            Ok(batch) => batches.push(batch),
+            Err(e) => {
+                warnings.push(format!("unreadable rows are skipped: {e}"));
+                break;
+            }
+        }
+    }
+    if batches.is_empty() {
+        batches.push(RecordBatch::new_empty(schema));
+    }
+    Ok(batches)
+}
+
+/// Normalize the batches like an ingest and report the resulting schema
+fn inspect(
+    validation: &mut Validation,
+    schema: SchemaRef,
+    batches: Vec<RecordBatch>,
+) -> anyhow::Result<()> {
+    let dimensions = schema::dimensions(&schema);
+    if dimensions.is_empty() {
+        bail!("no coordinate columns, expected `{PCE_DIMENSION_KEY}` field metadata");
+    }
+
+    let schema = schema::add_importance(schema, "i", DataType::Float32, 0);
+    let mut pc = ArrowPointCloud::try_new(schema.clone())?;
+    for batch in batches.into_iter().filter(|batch| batch.num_rows() > 0) {
+        pc.append(add_importance(batch, &schema)?)?;
+    }
+    pc.normalize_columns()?;
+
+    let schema = pc.schema();
+    validation.metadata = CloudMetadata::from_schema(&schema);
+    validation.columns = schema
+        .fields()
+        .iter()
+        .map(|field| ColumnReport {
+            name: field.name().to_owned(),
+            data_type: field.data_type().to_string(),
+            dimension: field.metadata().get(PCE_DIMENSION_KEY).cloned(),
+        })
+        .collect();
+    let importance = schema::importance(&schema);
+    validation.dimensions = schema::dimensions(&schema)
+        .into_iter()
+        .filter(|i| Some(*i) != importance)
+        .map(|i| schema.field(i).name().to_owned())
+        .collect();
+
+    let warnings = &mut validation.warnings;
+    if validation.metadata.crs.is_none() {
+        warnings.push("no CRS metadata".to_string());
+    }
+
+    // columns of LAS files are given by the reader
+    if !matches!(validation.format, UploadFormat::Las | UploadFormat::Laz) {
+        for (i, field) in schema.fields().iter().enumerate() {
+            let known = CONVENTIONS.iter().any(|(name, _)| name == field.name());
+            if !known && !validation.dimensions.contains(field.name()) && Some(i) != importance {
+                warnings.push(format!("unknown column `{}`", field.name()));
+            }
+        }
+    }
+
+    // suspicious ranges
+    let batches: Vec<RecordBatch> = pc
+        .store
+        .iter()
+        .flat_map(|e| pc.store.batches(e.key()))
+        .collect();
+    let range = |name: &str| -> anyhow::Result<Option<(f64, f64)>> {
+        let mut range: Option<(f64, f64)> = None;
+        for batch in &batches {
+            let values = cast(
+                batch.column_by_name(name).ok_or(anyhow!("no column"))?,
+                &DataType::Float64,
+            )?;
+            let values = values.as_primitive::<Float64Type>();
+            if let (Some(lower), Some(upper)) = (min(values), max(values)) {
+                range = Some(range.map_or((lower, upper), |(l, u)| (l.min(lower), u.max(upper))));
+            }
+        }
+        Ok(range)
+    };
+    if validation.points_read > 1 {
+        for name in &validation.dimensions {
+            if let Some((lower, upper)) = range(name)? {
+                if lower == upper {
+                    warnings.push(format!("coordinate `{name}` is constant at {lower}"));
+                }
+            }
+        }
+    }
+    if schema.column_with_name("intensity").is_some() && range("intensity")? == Some((0., 0.)) {
+        warnings.push("intensity is zero for all points".to_string());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use las::Write;
+
+    use super::*;
+
+    /// LAS 1.2 file of 100 points along a line
+    fn las_file() -> Vec<u8> {
+        let mut builder = las::Builder::from((1, 2));
+        builder.point_format = las::point::Format::new(1).unwrap();
+        let mut writer =
+            las::Writer::new(Cursor::new(Vec::new()), builder.into_header().unwrap()).unwrap();
+        for i in 0..100 {
+            writer
+                .write(las::Point {
+                    x: i as f64,
+                    y: 2. * i as f64,
+                    z: (i % 7) as f64,
+                    intensity: 100,
+                    gps_time: Some(i as f64),
+                    ..Default::default()
+                })
+                .unwrap();
+        }
+        writer.into_inner().unwrap().into_inner()
+    }
+
+    #[test]
+    fn las() {
+        let bytes = las_file();
+        let validation = validate(&bytes, None, None).unwrap();
+        assert_eq!(validation.format, UploadFormat::Las);
+        assert_eq!(validation.points_read, 100);
+        assert_eq!(validation.estimated_points, 100);
+        assert_eq!(validation.bounds, Some([[0., 0., 0.], [99., 198., 6.]]));
+        assert_eq!(validation.dimensions, ["x", "y", "z"]);
+        assert!(validation
+            .columns
+            .iter()
+            .any(|c| c.name == "gps_time" && c.data_type == "Float64"));
+        assert_eq!(validation.warnings, ["no CRS metadata"]);
+
+        // the point count of the header is kept for the head of the file
+        let head = &bytes[..bytes.len() / 2];
+        let validation = validate(head, Some(bytes.len() as u64), None).unwrap();
+        assert!(validation.partial);
+        assert!(validation.points_read < 100);
+        assert_eq!(validation.estimated_points, 100);
+        assert_eq!(validation.warnings, ["no CRS metadata"]);
+
+        // but a complete file missing points is suspicious
+        let validation = validate(head, None, None).unwrap();
+        assert!(validation.warnings[0].starts_with("file ends after"));
+    }
+
+    #[test]
+    fn csv() {
+        let mut text = "X,Y,Z,Intensity,Classification,note\n".to_string();
+        for i in 0..1100 {
+            text += &match i {
+                // broken row
+                1050 => "1.0,2.0\n".to_string(),
+                i => format!("{i}.0,{i}.5,5.0,0,{},p{i}\n", 2 + i % 2 * 4),
+            };
+        }
+        let validation = validate(text.as_bytes(), None, None).unwrap();
+        assert_eq!(validation.format, UploadFormat::Csv);
+        assert_eq!(validation.dimensions, ["X", "Y", "Z"]);
+        // the first batch
+        assert_eq!(validation.points_read, 1024);
+        assert!(validation
+            .columns
+            .iter()
+            .any(|c| c.name == "classification" && c.data_type == "UInt8"));
+
+        let warnings = &validation.warnings;
+        assert_eq!(warnings.len(), 5, "{warnings:?}");
+        assert!(warnings[0].starts_with("unreadable rows are skipped"));
+        assert_eq!(warnings[1], "no CRS metadata");
+        assert_eq!(warnings[2], "unknown column `note`");
+        assert_eq!(warnings[3], "coordinate `Z` is constant at 5");
+        assert_eq!(warnings[4], "intensity is zero for all points");
+
+        // estimated from the share of the upload read, up to the last full row
+        let head = &text.as_bytes()[..60];
+        let validation = validate(head, Some(5500), None).unwrap();
+        assert!(validation.partial);
+        assert_eq!(validation.points_read, 1);
+        assert_eq!(validation.estimated_points, 100);
+
+        // no coordinates
+        let validation = validate(b"a,b\n1,2\n", None, None).unwrap();
+        assert_eq!(validation.points_read, 0);
+        assert!(validation.columns.is_empty());
+        assert_eq!(validation.warnings, ["missing coordinate columns: x, y, z"]);
+        let validation = validate(b"x,y,z\n1,2,a\n", None, None).unwrap();
+        assert_eq!(
+            validation.warnings,
+            [
+                "coordinate column `z` is not numeric but Utf8",
+                "missing coordinate columns: z"
+            ]
+        );
+    }
+
+    #[test]
+    fn stream() {
+        let bytes = crate::handlers::testing::grid(4, 10);
+        let validation = validate(&bytes, None, None).unwrap();
+        assert_eq!(validation.format, UploadFormat::Arrow);
+        assert_eq!(validation.points_read, 40);
+        assert_eq!(validation.dimensions, ["x", "y", "z"]);
+        // with importance
+        assert_eq!(validation.columns.len(), 4);
+
+        // cut within the third batch
+        let validation = validate(&bytes[..bytes.len() - 300], Some(bytes.len() as u64), None);
+        let validation = validation.unwrap();
+        assert!(validation.partial);
+        assert!(validation.warnings.iter().all(|w| !w.contains("stream")));
+        assert!(validation.points_read < 40);
+    }
+}
@@ -0,0 +1,390 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Arc, Weak},
+    time::{Duration, SystemTime},
+};
+
+use tokio::sync::RwLock;
+
+use crux_io::FormatExt;
+
+use crate::{
+    health::Health,
+    jobs::{self, Job, JobSpec},
+    state::{AppState, SharedState},
+};
+
+/// Size and modification time of a file, which change while it is written
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Signature {
+    len: u64,
+    modified: Option<SystemTime>,
+}
+
+#[derive(Debug, PartialEq)]
+enum Change {
+    Ingest { name: String, path: PathBuf },
+    Remove { name: String },
+}
+
+/// Changes of the watched directory from scan to scan
+#[derive(Default)]
+struct Watcher {
+    /// Files of the previous scan
+    seen: HashMap<PathBuf, Signature>,
+    /// Source file of each collection with its signature when it was ingested
+    sources: HashMap<String, (PathBuf, Signature)>,
+}
+
+impl Watcher {
+    /// Collections to ingest or remove given the files of a new scan.
+    ///
+    /// Files are ingested once they are unchanged since the previous scan, so
+    /// that partially written files are skipped. Of several files with the
+    /// same stem, the first one ingested is the source of the collection.
+    fn changes(&mut self, files: HashMap<PathBuf, Signature>) -> Vec<Change> {
+        let mut changes = Vec::new();
+
+        self.sources.retain(|name, (path, _)| {
+            let exists = files.contains_key(path);
+            if !exists {
+                changes.push(Change::Remove { name: name.clone() });
+            }
+            exists
+        });
+
+        let mut paths: Vec<&PathBuf> = files.keys().collect();
+        paths.sort();
+        for path in paths {
+            let signature = files[path];
+            if self.seen.get(path) != Some(&signature) {
+                continue;
+            }
+            let Some(name) = collection_name(path) else {
+                continue;
+            };
+
+            match self.sources.get(&name) {
+                Some((source, ingested)) if source == path && *ingested == signature => continue,
+                Some((source, _)) if source != path => {
+                    tracing::debug!(
+                        "Ignoring `{}`, collection `{name}` is loaded from `{}`",
+                        path.display(),
+                        source.display()
+                    );
+                    continue;
+                }
+                _ => (),
+            }
+
+            self.sources.insert(name.clone(), (path.clone(), signature));
+            changes.push(Change::Ingest {
+                name,
+                path: path.clone(),
+            });
+        }
+
+        self.seen = files;
+        changes
+    }
+}
+
+/// Collection name of a file, its stem
+fn collection_name(path: &Path) -> Option<String> {
+    path.file_stem()
+        .and_then(|stem| stem.to_str())
+        .map(str::to_owned)
+}
+
+/// Point cloud files in the directory.
+///
+/// Hidden files and files without a known extension, such as `*.tmp` files
+/// that are renamed once written, are skipped.
+fn scan(dir: &Path) -> std::io::Result<HashMap<PathBuf, Signature>> {
+    let mut files = HashMap::new();
+
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+
+        let hidden = entry.file_name().to_string_lossy().starts_with('.');
+        let known = path
+            .extension()
+            .is_some_and(|ext| FormatExt::try_from(ext).is_ok());
+        if hidden || !known {
+            continue;
+        }
+
+        // removed since listed
+        let Ok(metadata) = entry.metadata() else {
+            continue;
+        };
+        if metadata.is_file() {
+            let signature = Signature {
+                len: metadata.len(),
+                modified: metadata.modified().ok(),
+            };
+            files.insert(path, signature);
+        }
+    }
+
+    Ok(files)
+}
+
+/// Load files dropped into `dir` as collections, replace them when the files
+/// change and remove them with the files unless `keep_removed`
+pub(crate) async fn watch_task(
+    state: Weak<RwLock<AppState>>,
+    dir: PathBuf,
+    interval: Duration,
+    keep_removed: bool,
+    health: Arc<Health>,
+) {
+    let _watching = health.watching();
+    let mut interval = tokio::time::interval(interval);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    tracing::info!("Watching `{}` for collections", dir.display());
+    let mut watcher = Watcher::default();
+
+    loop {
+        interval.tick().await;
+
+        let Some(state) = state.upgrade() else {
+            break;
+        };
+
+        // an unreadable directory does not remove the collections
+        let files = match scan(&dir) {
+            Ok(files) => files,
+            Err(e) => {
+                tracing::warn!("Failed to scan `{}`: {e}", dir.display());
+                continue;
+            }
+        };
+
+        for change in watcher.changes(files) {
+            apply(&state, change, keep_removed).await;
+        }
+    }
+}
+
+async fn apply(state: &SharedState, change: Change, keep_removed: bool) {
+    match change {
+        Change::Ingest { name, path } => {
+            let job = {
+                let mut state = state.write().await;
+                if state.data.contains_key(&name) && !state.watched.contains_key(&name) {
+                    tracing::warn!(
+                        "Ignoring `{}`, collection `{name}` was not loaded from the watched directory",
+                        path.display()
+                    );
+                    return;
+                }
+
+                let job = Arc::new(Job::new(&name, JobSpec::Ingest { path }));
+                state.jobs.insert(job.id.clone(), job.clone());
+                if let Some(previous) = state.watched.insert(name.clone(), job.clone()) {
+                    previous.cancel();
+                }
+                job
+            };
+
+            tracing::info!("Queued ingest {} of `{name}`: {:?}", job.id, job.spec);
+            tokio::spawn(jobs::run(state.clone(), job));
+        }
+        Change::Remove { name } => {
+            if keep_removed {
+                tracing::info!("Source of collection `{name}` was removed, keeping it");
+                return;
+            }
+
+            let mut state = state.write().await;
+            if let Some(job) = state.watched.remove(&name) {
+                job.cancel();
+                state.remove(&name);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use arrow::ipc::{reader::StreamReader, writer::FileWriter};
+    use axum::{
+        body::Body,
+        http::{Method, StatusCode},
+    };
+    use clap::Parser;
+    use http_body_util::BodyExt;
+
+    use super::*;
+    use crate::{
+        handlers::testing::{grid, send},
+        Config,
+    };
+
+    fn files(entries: &[(&str, u64)]) -> HashMap<PathBuf, Signature> {
+        entries
+            .iter()
+            .map(|(path, len)| {
+                let signature = Signature {
+                    len: *len,
+                    modified: None,
+                };
+                (PathBuf::from(path), signature)
+            })
+            .collect()
+    }
+
+    fn ingest(name: &str, path: &str) -> Change {
+        Change::Ingest {
+            name: name.to_owned(),
+            path: PathBuf::from(path),
+        }
+    }
+
+    #[test]
+    fn changes() {
+        let mut watcher = Watcher::default();
+
+        // ingested once unchanged for a scan
+        assert!(watcher.changes(files(&[("a.arrow", 10)])).is_empty());
+        assert!(watcher.changes(files(&[("a.arrow", 20)])).is_empty());
+        assert_eq!(
+            watcher.changes(files(&[("a.arrow", 20)])),
+            [ingest("a", "a.arrow")]
+        );
+        assert!(watcher.changes(files(&[("a.arrow", 20)])).is_empty());
+
+        // rewritten
+        assert!(watcher.changes(files(&[("a.arrow", 30)])).is_empty());
+        assert_eq!(
+            watcher.changes(files(&[("a.arrow", 30)])),
+            [ingest("a", "a.arrow")]
+        );
+
+        // another file of the same name is ignored
+        let both = [("a.arrow", 30), ("a.las", 5)];
+        assert!(watcher.changes(files(&both)).is_empty());
+        assert!(watcher.changes(files(&both)).is_empty());
+
+        // removed, the other file takes over
+        assert_eq!(
+            watcher.changes(files(&[("a.las", 5)])),
+            [
+                Change::Remove {
+                    name: "a".to_owned()
+                },
+                ingest("a", "a.las")
+            ]
+        );
+        assert_eq!(
+            watcher.changes(files(&[])),
+            [Change::Remove {
+                name: "a".to_owned()
+            }]
+        );
+    }
+
+    /// Names and numbers of points of the listed collections
+    async fn listing(app: &axum::Router) -> Vec<(String, u64)> {
+        let response = send(app, Method::GET, "/collections", Body::empty()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let collections: Vec<serde_json::Value> = serde_json::from_slice(&body).unwrap();
+
+        collections
+            .iter()
+            .map(|c| {
+                (
+                    c["name"].as_str().unwrap().to_owned(),
+                    c["num_points"].as_u64().unwrap(),
+                )
+            })
+            .collect()
+    }
+
+    async fn wait_for(app: &axum::Router, expected: &[(&str, u64)]) {
+        let expected: Vec<(String, u64)> = expected
+            .iter()
+            .map(|(name, n)| (name.to_string(), *n))
+            .collect();
+
+        let start = Instant::now();
+        loop {
+            let collections = listing(app).await;
+            if collections == expected {
+                return;
+            }
+            assert!(
+                start.elapsed() < Duration::from_secs(30),
+                "{collections:?} != {expected:?}"
+            );
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn hot_reload() {
+        let dir = tempfile::tempdir().unwrap();
+        let app = crate::app(Config::parse_from([
+            "crux-server",
+            "--watch-dir",
+            dir.path().to_str().unwrap(),
+            "--watch-interval",
+            "1",
+        ]));
+
+        // written to a temporary file, then renamed
+        let write = |name: &str, rows: usize| {
+            let reader = StreamReader::try_new(std::io::Cursor::new(grid(1, rows)), None).unwrap();
+            let tmp = dir.path().join(format!("{name}.tmp"));
+            let mut writer =
+                FileWriter::try_new(std::fs::File::create(&tmp).unwrap(), &reader.schema())
+                    .unwrap();
+            for batch in reader {
+                writer.write(&batch.unwrap()).unwrap();
+            }
+            writer.finish().unwrap();
+            std::fs::rename(&tmp, dir.path().join(name)).unwrap();
+        };
+
+        write("grid.arrow", 10);
+        wait_for(&app, &[("grid", 10)]).await;
+
+        // the ingest job is listed with the collection
+        let response = send(&app, Method::GET, "/collections", Body::empty()).await;
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let collections: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(collections[0]["ingest"]["kind"], "ingest");
+        assert_eq!(collections[0]["ingest"]["state"], "completed");
+
+        // replaced
+        write("grid.arrow", 20);
+        wait_for(&app, &[("grid", 20)]).await;
+
+        // collections loaded otherwise are not replaced
+        let response = send(
+            &app,
+            Method::POST,
+            "/load?collection=other",
+            Body::from(grid(1, 5)),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        write("other.arrow", 10);
+        tokio::time::sleep(Duration::from_secs(3)).await;
+        assert_eq!(
+            listing(&app).await,
+            [("grid".to_owned(), 20), ("other".to_owned(), 5)]
+        );
+
+        // removed with the file
+        std::fs::remove_file(dir.path().join("grid.arrow")).unwrap();
+        wait_for(&app, &[("other", 5)]).await;
+    }
+}
@@ -0,0 +1,67 @@
+//! Harness of the end-to-end tests in `tests/`, which run the server in
+//! process and query it over HTTP like the viewer does.
+
+use arrow::ipc::writer::StreamWriter;
+use tokio::net::TcpListener;
+
+use crux_format::{ArrowPointCloud, PointCloudTrait};
+use crux_server::Config;
+
+/// Server listening on an ephemeral port of the loopback interface, it stops
+/// with the runtime of the test
+pub struct TestServer {
+    pub url: String,
+    client: reqwest::Client,
+}
+
+impl TestServer {
+    /// Serve the app configured by the command line `args`
+    pub async fn start(args: &[&str]) -> Self {
+        let config = Config::load_from(["crux-server"].iter().chain(args)).unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            axum::serve(listener, crux_server::app(config).into_make_service())
+                .await
+                .unwrap()
+        });
+
+        Self {
+            url,
+            client: reqwest::Client::new(),
+        }
+    }
+
+    /// Push the points of `pc` as `collection`
+    pub async fn load(&self, collection: &str, pc: &ArrowPointCloud) {
+        self.client
+            .post(format!("{}/load?collection={collection}", self.url))
+            .body(stream(pc))
+            .send()
+            .await
+            .unwrap()
+            .error_for_status()
+            .unwrap();
+    }
+
+    /// Response to a GET of `path` and query
+    pub async fn get(&self, path: &str) -> reqwest::Response {
+        self.client
+            .get(format!("{}{path}", self.url))
+            .send()
+            .await
+            .unwrap()
+    }
+}
+
+/// Arrow IPC stream of the batches of `pc`, as pushed to `/load`
+pub fn stream(pc: &ArrowPointCloud) -> Vec<u8> {
+    let mut writer = StreamWriter::try_new(Vec::new(), &pc.schema()).unwrap();
+    for e in pc.store.iter() {
+        for batch in pc.store.batches(e.key()) {
+            writer.write(&batch).unwrap();
+        }
+    }
+    writer.into_inner().unwrap()
+}
@@ -0,0 +1,444 @@
+use std::{collections::HashSet, io::Cursor, time::Duration};
+
+use arrow::ipc::reader::StreamReader;
+use bevy::{
+    app::{App, Update},
+    input::{keyboard::KeyCode, Input},
+    math::DVec3,
+    render::color::Color,
+};
+use bytes::Bytes;
+use reqwest::StatusCode;
+
+use crux_format::{
+    color::Palette,
+    synthetic::{Synthetic, BUILDING},
+    ArrowPointCloud, Point, PointCloudTrait, PointTrait, AABB,
+};
+use crux_tests::TestServer;
+use crux_viewer::{
+    fetch::{self, bounds_url, points_url, FetchError, Fetched, LoadState, RetryPolicy},
+    instances::{cloud_instances, SkippedPoints, CHUNK_SIZE},
+    keys::{Action, KeyBindings},
+    layers::{self, Layers},
+    normalize::{self, ScaleBounds},
+    session::{InstanceSummary, SessionEvent, SessionRecorder, SessionReplay, Step},
+    ViewerSettings,
+};
+
+const COLLECTION: &str = "survey";
+
+/// Synthetic survey with buildings and intensity
+fn survey(points: usize) -> ArrowPointCloud {
+    Synthetic::new(points)
+        .seed(7)
+        .intensity(true)
+        .buildings(5)
+        .unwrap()
+}
+
+/// Server with `pc` loaded as [COLLECTION] and viewer settings pointing at it
+async fn serve(args: &[&str], pc: &ArrowPointCloud) -> (TestServer, ViewerSettings) {
+    let server = TestServer::start(args).await;
+    server.load(COLLECTION, pc).await;
+
+    let settings = ViewerSettings {
+        server: server.url.clone(),
+        collection: COLLECTION.to_string(),
+        random_samples: true,
+        ..Default::default()
+    };
+    (server, settings)
+}
+
+/// Points of `url`, fetched and decoded like the viewer does
+async fn load(url: &str) -> Result<ArrowPointCloud, FetchError> {
+    let policy = RetryPolicy::new(Duration::from_secs(30));
+    match fetch::fetch(url, None, &policy, &LoadState::default()).await? {
+        Fetched::Modified { body, .. } => fetch::decode(body),
+        Fetched::NotModified => panic!("nothing is cached"),
+    }
+}
+
+fn names(pc: &ArrowPointCloud) -> Vec<String> {
+    pc.schema()
+        .fields()
+        .iter()
+        .map(|field| field.name().to_owned())
+        .collect()
+}
+
+fn coords(pc: &ArrowPointCloud) -> HashSet<[u64; 3]> {
+    pc.points::<Point<f64, 3>>()
+        .map(|p| [0, 1, 2].map(|d| p.coords()[d].to_bits()))
+        .collect()
+}
+
+fn inside(p: &[f64], lower: DVec3, upper: DVec3) -> bool {
+    (0..3).all(|d| p[d] >= lower[d] && p[d] <= upper[d])
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn full_fetch() {
+    let source = survey(20_000);
+    let (_server, mut settings) = serve(&[], &source).await;
+
+    let pc = load(&points_url(&settings, "")).await.unwrap();
+    assert_eq!(pc.num_points(), 20_000);
+    assert_eq!(pc.aabb::<Point<f64, 3>>(), source.aabb::<Point<f64, 3>>());
+    assert_eq!(coords(&pc), coords(&source));
+
+    // the importance of the server, followed by the columns of the upload
+    assert_eq!(names(&pc)[0], "i");
+    assert_eq!(names(&pc)[1..], names(&source));
+
+    // one instance per point, stretched by intensity
+    let aabb: AABB<Point<f64, 3>> = pc.aabb();
+    let origin = DVec3::from_slice(aabb.center().coords());
+    let mut scale = ScaleBounds::default();
+    settings.color_attribute = "intensity".to_string();
+    let instances = cloud_instances(
+        &pc,
+        COLLECTION,
+        None,
+        origin,
+        &settings,
+        &mut scale,
+        &mut SkippedPoints::default(),
+        Color::BLACK,
+        None,
+    );
+    assert_eq!(instances.iter().map(Vec::len).sum::<usize>(), 20_000);
+    assert!(instances.iter().all(|chunk| chunk.len() <= CHUNK_SIZE));
+    let (attribute, lower, upper) = scale.0.take().unwrap();
+    assert_eq!(attribute, "intensity");
+    assert!(lower < upper);
+
+    // buildings in their class color
+    settings.color_attribute = "classification".to_string();
+    let instances = cloud_instances(
+        &pc,
+        COLLECTION,
+        None,
+        origin,
+        &settings,
+        &mut scale,
+        &mut SkippedPoints::default(),
+        Color::BLACK,
+        None,
+    );
+    let building = normalize::to_color(Palette::classification().color(BUILDING.into()));
+    let colored = instances
+        .iter()
+        .flatten()
+        .filter(|cuboid| cuboid.color == building.as_rgba_u32())
+        .count();
+    let buildings = pc
+        .store
+        .iter()
+        .flat_map(|e| pc.store.batches(e.key()))
+        .map(|batch| {
+            let column = batch.column_by_name("classification").unwrap();
+            let classes = column
+                .as_any()
+                .downcast_ref::<arrow::array::UInt8Array>()
+                .unwrap();
+            classes.iter().filter(|c| *c == Some(BUILDING)).count()
+        })
+        .sum::<usize>();
+    assert!(buildings > 0);
+    assert_eq!(colored, buildings);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn bounds() {
+    let source = survey(20_000);
+    let (_server, settings) = serve(&[], &source).await;
+
+    let (lower, upper) = (DVec3::new(20., 20., 0.), DVec3::new(60., 60., 20.));
+    let expected: HashSet<_> = source
+        .points::<Point<f64, 3>>()
+        .filter(|p| inside(p.coords(), lower, upper))
+        .map(|p| [0, 1, 2].map(|d| p.coords()[d].to_bits()))
+        .collect();
+    assert!(!expected.is_empty());
+
+    // the region query of the viewer
+    let pc = load(&bounds_url(&settings, lower, upper, 1.))
+        .await
+        .unwrap();
+    assert_eq!(coords(&pc), expected);
+
+    // a sample of the region, reproducible with a seed
+    let params = "bounds=20,20,0,60,60,20&p=0.25";
+    let sample = load(&points_url(&settings, params)).await.unwrap();
+    assert!(sample.num_points() > 0 && sample.num_points() < expected.len());
+    assert!(coords(&sample).is_subset(&expected));
+
+    let params = format!("{params}&seed=3");
+    let seeded = load(&points_url(&settings, &params)).await.unwrap();
+    let again = load(&points_url(&settings, &params)).await.unwrap();
+    assert!(seeded.num_points() < expected.len());
+    assert_eq!(coords(&seeded), coords(&again));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn columns() {
+    let source = survey(5_000);
+    let (_server, mut settings) = serve(&[], &source).await;
+
+    let pc = load(&points_url(&settings, "columns=intensity"))
+        .await
+        .unwrap();
+    assert_eq!(pc.num_points(), 5_000);
+    assert_eq!(names(&pc), ["i", "x", "y", "z", "intensity"]);
+    assert_eq!(pc.aabb::<Point<f64, 3>>(), source.aabb::<Point<f64, 3>>());
+
+    // the projected column is colored, others fall back
+    let origin = DVec3::ZERO;
+    let mut scale = ScaleBounds::default();
+    settings.color_attribute = "intensity".to_string();
+    cloud_instances(
+        &pc,
+        COLLECTION,
+        None,
+        origin,
+        &settings,
+        &mut scale,
+        &mut SkippedPoints::default(),
+        Color::BLACK,
+        None,
+    );
+    assert!(scale.0.is_some());
+
+    settings.color_attribute = "classification".to_string();
+    let instances = cloud_instances(
+        &pc,
+        COLLECTION,
+        None,
+        origin,
+        &settings,
+        &mut scale,
+        &mut SkippedPoints::default(),
+        Color::BLACK,
+        None,
+    );
+    assert!(scale.0.is_none());
+    assert!(instances
+        .iter()
+        .flatten()
+        .all(|cuboid| cuboid.color == Color::ORANGE.as_rgba_u32()));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn empty() {
+    let source = survey(1_000);
+    let (_server, settings) = serve(&[], &source).await;
+
+    let (lower, upper) = (DVec3::splat(1000.), DVec3::splat(2000.));
+    let pc = load(&bounds_url(&settings, lower, upper, 1.))
+        .await
+        .unwrap();
+    assert_eq!(pc.num_points(), 0);
+    assert_eq!(names(&pc)[1..], names(&source));
+
+    let mut scale = ScaleBounds::default();
+    let instances = cloud_instances(
+        &pc,
+        COLLECTION,
+        None,
+        DVec3::ZERO,
+        &settings,
+        &mut scale,
+        &mut SkippedPoints::default(),
+        Color::BLACK,
+        None,
+    );
+    assert!(instances.is_empty());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn malformed_bounds() {
+    let source = survey(1_000);
+    let (server, settings) = serve(&[], &source).await;
+
+    for bounds in ["1,2,3", "1,2,3,4,5", "a,b,c,d,e,f"] {
+        let response = server
+            .get(&format!("/points?collection={COLLECTION}&bounds={bounds}"))
+            .await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{bounds}");
+    }
+
+    // client errors are not retried by the viewer
+    let result = load(&points_url(&settings, "bounds=1,2,3")).await;
+    assert!(matches!(
+        result,
+        Err(FetchError::Failed { attempts: 1, .. })
+    ));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn multi_batch() {
+    let source = survey(100_000);
+    let (server, settings) = serve(&["--chunk-size", "4096"], &source).await;
+
+    // streamed in many batches
+    let response = server
+        .get(&format!("/points?collection={COLLECTION}"))
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.bytes().await.unwrap();
+    let reader = StreamReader::try_new(Cursor::new(body), None).unwrap();
+    let batches = reader.map(Result::unwrap).collect::<Vec<_>>();
+    assert!(batches.len() > 1);
+    assert_eq!(
+        batches.iter().map(|batch| batch.num_rows()).sum::<usize>(),
+        100_000
+    );
+
+    let pc = load(&points_url(&settings, "")).await.unwrap();
+    assert_eq!(pc.num_points(), 100_000);
+    assert_eq!(pc.aabb::<Point<f64, 3>>(), source.aabb::<Point<f64, 3>>());
+
+    let mut scale = ScaleBounds::default();
+    let instances = cloud_instances(
+        &pc,
+        COLLECTION,
+        None,
+        DVec3::ZERO,
+        &settings,
+        &mut scale,
+        &mut SkippedPoints::default(),
+        Color::BLACK,
+        None,
+    );
+    assert_eq!(instances.iter().map(Vec::len).sum::<usize>(), 100_000);
+}
+
+/// Raw body of `url`, as the viewer records it
+async fn body(url: &str) -> Bytes {
+    let policy = RetryPolicy::new(Duration::from_secs(30));
+    match fetch::fetch(url, None, &policy, &LoadState::default())
+        .await
+        .unwrap()
+    {
+        Fetched::Modified { body, .. } => body,
+        Fetched::NotModified => panic!("nothing is cached"),
+    }
+}
+
+/// Summary of the instances of `pc` as the viewer renders them
+fn rendered(pc: &ArrowPointCloud, settings: &ViewerSettings) -> InstanceSummary {
+    let instances = cloud_instances(
+        pc,
+        COLLECTION,
+        None,
+        DVec3::ZERO,
+        settings,
+        &mut ScaleBounds::default(),
+        &mut SkippedPoints::default(),
+        Color::BLACK,
+        None,
+    );
+    InstanceSummary::of(&instances)
+}
+
+/// Viewer state changed by the controls, with the system of the color modes
+fn controls(settings: &ViewerSettings) -> App {
+    let mut app = App::new();
+    app.insert_resource(Input::<KeyCode>::default())
+        .insert_resource(settings.keys.clone())
+        .insert_resource(Layers::default())
+        .insert_resource(settings.clone())
+        .add_systems(Update, layers::layers_system);
+    app
+}
+
+/// Press the key of `action` for one frame
+fn press(app: &mut App, action: Action) {
+    let key = app.world.resource::<KeyBindings>().key(action);
+    app.world.resource_mut::<Input<KeyCode>>().press(key);
+    app.update();
+    let mut input = app.world.resource_mut::<Input<KeyCode>>();
+    input.release(key);
+    input.clear();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn session_replay() {
+    let source = survey(20_000);
+    let (_server, mut settings) = serve(&[], &source).await;
+    // the same sample for the same query
+    settings.random_samples = false;
+    settings.color_attribute = "intensity".to_string();
+
+    // two loads and a switch of the color mode
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("session.jsonl");
+    let mut recorder = SessionRecorder::create(&path, true).unwrap();
+    recorder.record(SessionEvent::Start {
+        settings: Box::new(settings.clone()),
+    });
+    let mut app = controls(&settings);
+    let mut shown = None;
+    for url in [points_url(&settings, "p=0.1"), points_url(&settings, "")] {
+        recorder.record(SessionEvent::Query {
+            collection: COLLECTION.to_string(),
+            url: url.clone(),
+        });
+        let body = body(&url).await;
+        let pc = fetch::decode(body.clone()).unwrap();
+        let body = recorder.record_body(&body);
+        recorder.record(SessionEvent::Response {
+            collection: COLLECTION.to_string(),
+            url,
+            points: pc.num_points(),
+            digest: pc.digest().content_hash,
+            body,
+        });
+        shown = Some(pc);
+    }
+    recorder.record(SessionEvent::Action {
+        action: Action::ColorByCollection,
+        shift: false,
+        camera: None,
+    });
+    press(&mut app, Action::ColorByCollection);
+    let shown = shown.unwrap();
+    let recorded = rendered(&shown, app.world.resource::<ViewerSettings>());
+    assert_eq!(recorded.instances, 20_000);
+    assert_ne!(recorded, rendered(&shown, &settings));
+    recorder.record(SessionEvent::End {
+        rendered: Some(recorded),
+    });
+    drop(recorder);
+
+    // from the recorded bodies and against the live server
+    for live in [false, true] {
+        let mut replay = SessionReplay::open(&path).unwrap();
+        let mut app = controls(&replay.take_settings().unwrap());
+        let mut shown = None;
+        while let Some(step) = replay.next_step() {
+            match step {
+                Step::Load {
+                    url, body: file, ..
+                } => {
+                    let body = match (file, live) {
+                        (Some(file), false) => Bytes::from(std::fs::read(file).unwrap()),
+                        _ => body(&url).await,
+                    };
+                    let pc = fetch::decode(body).unwrap();
+                    replay.verify(&url, pc.num_points(), pc.digest().content_hash);
+                    shown = Some(pc);
+                }
+                Step::Press { action, .. } => press(&mut app, action),
+                Step::Finish { expected } => {
+                    let settings = app.world.resource::<ViewerSettings>();
+                    replay.finish(shown.as_ref().map(|pc| rendered(pc, settings)), expected);
+                }
+            }
+        }
+        assert!(replay.mismatches.is_empty(), "{:?}", replay.mismatches);
+        assert_eq!(replay.outcome, Some(true));
+    }
+}
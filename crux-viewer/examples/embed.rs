@@ -0,0 +1,52 @@
+//! A Bevy app embedding the viewer plugin, showing a synthetic point cloud or,
+//! with a URL as argument, loading the points from a server
+//!
+//! `cargo run -p crux-viewer --example embed [-- <url>]`
+
+use bevy::prelude::*;
+
+use crux_format::synthetic::Synthetic;
+use crux_viewer::{CruxViewerPlugin, LoadRequest, PointCache, ViewerSettings};
+
+/// Name under which the points are cached and shown
+const COLLECTION: &str = "embedded";
+
+/// URL of the points to load instead of the synthetic cloud
+#[derive(Resource)]
+struct Source(Option<String>);
+
+fn main() {
+    // settings inserted before the plugin are kept
+    let settings = ViewerSettings {
+        collection: COLLECTION.to_owned(),
+        auto_lod: false,
+        ..default()
+    };
+
+    App::new()
+        .insert_resource(settings)
+        .insert_resource(Source(std::env::args().nth(1)))
+        .add_plugins((DefaultPlugins, CruxViewerPlugin))
+        .add_systems(Startup, load)
+        .run();
+}
+
+fn load(
+    mut commands: Commands,
+    source: Res<Source>,
+    settings: Res<ViewerSettings>,
+    mut cache: ResMut<PointCache>,
+    mut requests: EventWriter<LoadRequest>,
+) {
+    if let Some(url) = &source.0 {
+        requests.send(LoadRequest::new(url));
+        return;
+    }
+
+    let pc = Synthetic::new(200_000)
+        .extent([0., 0., 0.], [100., 100., 20.])
+        .intensity(true)
+        .terrain()
+        .expect("synthetic terrain");
+    cache.insert(&mut commands, &settings, COLLECTION, pc);
+}
@@ -0,0 +1,184 @@
+use std::collections::HashMap;
+
+use bevy::{math::DVec3, prelude::*};
+
+use crux_format::{compute::aabb, prelude::*};
+
+use crate::{
+    frame::{data_to_world, enu_size_to_bevy},
+    keys::{Action, KeyBindings},
+    PointCache, SpatialReference,
+};
+
+/// Maximum number of batch boxes drawn per collection
+const MAX_BATCH_BOXES: usize = 256;
+
+/// Which bounding boxes are drawn
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum BoundsMode {
+    #[default]
+    Off,
+    /// Overall bounds of each collection
+    Collections,
+    /// Overall bounds and the bounds of the batches
+    Batches,
+}
+
+impl BoundsMode {
+    fn next(self) -> Self {
+        match self {
+            Self::Off => Self::Collections,
+            Self::Collections => Self::Batches,
+            Self::Batches => Self::Off,
+        }
+    }
+}
+
+impl std::fmt::Display for BoundsMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Off => "off",
+            Self::Collections => "collections",
+            Self::Batches => "batches",
+        })
+    }
+}
+
+/// Bounds of a collection in the data reference system
+struct CollectionBounds {
+    generation: usize,
+    total: AABB<Point<f64, 3>>,
+    /// Decimated batch bounds
+    batches: Vec<AABB<Point<f64, 3>>>,
+    num_batches: usize,
+}
+
+#[derive(Resource, Default)]
+pub struct BoundsGizmos {
+    pub mode: BoundsMode,
+    bounds: HashMap<String, CollectionBounds>,
+}
+
+impl BoundsGizmos {
+    /// Number of drawn and total batch boxes
+    pub fn batch_counts(&self) -> (usize, usize) {
+        self.bounds.values().fold((0, 0), |(drawn, total), bounds| {
+            (drawn + bounds.batches.len(), total + bounds.num_batches)
+        })
+    }
+}
+
+/// Keep every n-th item so that at most `cap` remain
+pub fn decimate<T>(items: Vec<T>, cap: usize) -> Vec<T> {
+    let step = items.len().div_ceil(cap.max(1)).max(1);
+    items.into_iter().step_by(step).collect()
+}
+
+/// Gizmo transform of a box in the data reference system
+pub fn box_transform(origin: DVec3, aabb: &AABB<Point<f64, 3>>) -> Transform {
+    let lower = DVec3::from_slice(aabb.lower().coords());
+    let upper = DVec3::from_slice(aabb.upper().coords());
+    Transform::from_translation(data_to_world(origin, (lower + upper) / 2.))
+        .with_scale(enu_size_to_bevy(upper - lower))
+}
+
+/// Distinct color per collection
+fn collection_color(i: usize) -> Color {
+    // golden angle hue steps
+    Color::hsl((i as f32 * 137.508) % 360., 0.8, 0.6)
+}
+
+// Press 'B' to cycle through collection and batch bounds
+pub fn bounds_system(
+    key_input: Res<Input<KeyCode>>,
+    keys: Res<KeyBindings>,
+    cache: Res<PointCache>,
+    sr: Res<SpatialReference>,
+    mut state: ResMut<BoundsGizmos>,
+    mut gizmos: Gizmos,
+) {
+    if keys.just_pressed(&key_input, Action::CycleBounds) {
+        state.mode = state.mode.next();
+    }
+
+    let Some(origin) = sr.origin else {
+        return;
+    };
+    if state.mode == BoundsMode::Off {
+        return;
+    }
+
+    // bounds are computed once per load of a collection
+    if cache.is_changed() {
+        state.bounds.retain(|name, bounds| {
+            cache.generation.get(name) == Some(&bounds.generation) && cache.data.contains_key(name)
+        });
+    }
+    for (name, pc) in &cache.data {
+        if state.bounds.contains_key(name) {
+            continue;
+        }
+        let generation = cache.generation.get(name).copied().unwrap_or_default();
+
+        let batches: Vec<AABB<Point<f64, 3>>> = pc
+            .store
+            .iter()
+            .flat_map(|e| pc.store.batches(e.key()))
+            .filter(|batch| batch.num_rows() != 0)
+            .map(|batch| aabb(&batch))
+            .collect();
+        let num_batches = batches.len();
+
+        state.bounds.insert(
+            name.to_owned(),
+            CollectionBounds {
+                generation,
+                total: pc.aabb(),
+                batches: decimate(batches, MAX_BATCH_BOXES),
+                num_batches,
+            },
+        );
+    }
+
+    let mut names: Vec<&String> = state.bounds.keys().collect();
+    names.sort();
+    for (i, name) in names.into_iter().enumerate() {
+        let bounds = &state.bounds[name];
+        let color = collection_color(i);
+
+        gizmos.cuboid(box_transform(origin, &bounds.total), color);
+
+        if state.mode == BoundsMode::Batches {
+            let color = color.with_a(0.5);
+            for aabb in &bounds.batches {
+                gizmos.cuboid(box_transform(origin, aabb), color);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decimation() {
+        assert_eq!(decimate((0..10).collect(), 256).len(), 10);
+        assert_eq!(decimate((0..1000).collect(), 256).len(), 250);
+        assert_eq!(decimate((0..1000).collect(), 256)[1], 4);
+        assert!(decimate(Vec::<usize>::new(), 256).is_empty());
+    }
+
+    #[test]
+    fn transform() {
+        let aabb = AABB::from_corners(
+            Point::from_slice(&[100., 200., 10.]),
+            Point::from_slice(&[104., 202., 11.]),
+        );
+
+        // east-north-up to y-up relative to the origin
+        let transform = box_transform(DVec3::new(100., 200., 10.), &aabb);
+        assert_eq!(transform.translation, Vec3::new(2., 0.5, -1.));
+        assert_eq!(transform.scale, Vec3::new(4., 1., 2.));
+    }
+}
@@ -0,0 +1,113 @@
+//! Loaded point clouds per collection, the reference system of the scene and
+//! the requests to load more points
+
+use std::{collections::HashMap, sync::Arc, time::Instant};
+
+use bevy::{math::DVec3, prelude::*};
+
+use crux_format::ArrowPointCloud;
+
+use crate::{
+    fetch::{preview_url, RequestIds},
+    memory::{self, Level, MemoryUsage},
+    minimap,
+    picking::{self, PickIndex},
+    sizing::DensityGrid,
+    ViewerSettings,
+};
+
+/// Load of points from the server as the shown collection, e.g. of a
+/// [points_url](crate::fetch::points_url)
+#[derive(Event, Debug, Clone, PartialEq)]
+pub struct LoadRequest {
+    pub url: String,
+}
+
+impl LoadRequest {
+    pub fn new(url: impl Into<String>) -> Self {
+        Self { url: url.into() }
+    }
+}
+
+/// Point clouds by collection with their indices and the state of their loads
+#[derive(Resource, Default)]
+pub struct PointCache {
+    pub(crate) data: HashMap<String, Arc<ArrowPointCloud>>,
+    /// Picking index per collection, once built
+    pub(crate) index: HashMap<String, Arc<PickIndex>>,
+    /// Local point density per collection for adaptive sizes, once built
+    pub(crate) density: HashMap<String, Arc<DensityGrid>>,
+    /// Number of loads per collection, to discard outdated indices
+    pub(crate) generation: HashMap<String, usize>,
+    /// Memory held by the cached point clouds and their indices
+    pub(crate) memory: MemoryUsage,
+    /// URL and entity tag of the cached point cloud per collection
+    pub(crate) etags: HashMap<String, (String, String)>,
+    /// Latest load per collection, responses to older loads are discarded
+    pub(crate) requests: RequestIds,
+    /// Collection and time of the last discarded stale response
+    pub(crate) discarded: Option<(String, Instant)>,
+    /// Collection, error and time of the last failed load
+    pub(crate) failed: Option<(String, String, Instant)>,
+    /// Load per collection issued once its preview arrived
+    pub(crate) refine: HashMap<String, String>,
+}
+
+impl PointCache {
+    /// Points of a collection, if loaded
+    pub fn get(&self, collection: &str) -> Option<&ArrowPointCloud> {
+        self.data.get(collection).map(|pc| pc.as_ref())
+    }
+
+    /// Show points loaded without the server, e.g. generated or read from a
+    /// file, as `collection`, replacing its cached points
+    pub fn insert(
+        &mut self,
+        commands: &mut Commands,
+        settings: &ViewerSettings,
+        collection: &str,
+        pc: ArrowPointCloud,
+    ) {
+        self.memory
+            .insert(collection, Level::Points, memory::cloud_size(&pc));
+        self.data.insert(collection.to_owned(), Arc::new(pc));
+        picking::spawn_index_task(commands, self, collection);
+        minimap::spawn_minimap_task(commands, self, settings, collection);
+    }
+
+    /// Load `url`, on the first load of the collection after its preview
+    pub(crate) fn load(
+        &mut self,
+        settings: &ViewerSettings,
+        url: String,
+        requests: &mut EventWriter<LoadRequest>,
+    ) {
+        if self.data.contains_key(&settings.collection) {
+            requests.send(LoadRequest::new(url));
+        } else {
+            self.refine.insert(settings.collection.to_owned(), url);
+            requests.send(LoadRequest::new(preview_url(settings)));
+        }
+    }
+}
+
+/// Placement of the data in the scene
+#[derive(Resource, Default)]
+pub struct SpatialReference {
+    /// Data coordinates of the world origin, kept in f64 for large projected
+    /// coordinates
+    pub origin: Option<DVec3>,
+    /// Data coordinates of the camera focus
+    pub camera: DVec3,
+}
+
+/// The cache, the reference system and the [LoadRequest] events
+pub struct CachePlugin;
+
+impl Plugin for CachePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<PointCache>()
+            .init_resource::<SpatialReference>()
+            .add_event::<LoadRequest>();
+    }
+}
@@ -0,0 +1,135 @@
+//! The orbit camera, its placement in the data and the views derived from it
+
+use bevy::{math::DVec3, prelude::*};
+use bevy_panorbit_camera::{PanOrbitCamera, PanOrbitCameraPlugin};
+
+use crux_format::prelude::*;
+
+use crate::{
+    frame::world_to_data,
+    framing::{self, AutoFrame, Framing},
+    keys::{Action, KeyBindings},
+    render,
+    stereo_view::{self, AnaglyphPlugin, Stereo},
+    views::{self, Views},
+    PointCache, SpatialReference, ViewerSettings,
+};
+
+/// Orbit camera restored from the settings, with the standard views, framing
+/// and stereo modes
+pub struct CameraPlugin;
+
+impl Plugin for CameraPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Views>()
+            .init_resource::<AutoFrame>()
+            .init_resource::<Stereo>()
+            .add_plugins((PanOrbitCameraPlugin, AnaglyphPlugin))
+            .add_systems(Startup, setup_camera)
+            .add_systems(Update, camera_controls_system)
+            .add_systems(Update, views::views_system)
+            .add_systems(Update, stereo_view::stereo_controls_system)
+            .add_systems(Update, stereo_view::stereo_system)
+            .add_systems(
+                Update,
+                framing::auto_frame_system.after(render::update_instances),
+            );
+    }
+}
+
+/// Camera restored from the last session
+pub fn setup_camera(
+    mut commands: Commands,
+    settings: Res<ViewerSettings>,
+    mut sr: ResMut<SpatialReference>,
+) {
+    let mut camera = PanOrbitCamera::default();
+    if let Some(pose) = settings.camera {
+        camera.focus = Vec3::from(pose.focus);
+        camera.alpha = Some(pose.alpha);
+        camera.beta = Some(pose.beta);
+        camera.radius = Some(pose.radius);
+
+        sr.origin = Some(DVec3::from(pose.origin));
+    }
+    commands.spawn((Camera3dBundle::default(), camera));
+}
+
+/// Bounds of the query box gizmo, a cube of edge `radius` at `focus`, in the
+/// data reference system
+pub fn query_bounds(origin: DVec3, focus: Vec3, radius: f32) -> (DVec3, DVec3) {
+    let a = world_to_data(origin, focus - radius / 2.);
+    let b = world_to_data(origin, focus + radius / 2.);
+    (a.min(b), a.max(b))
+}
+
+/// Look at the data from the south, centered on the bounds `aabb`
+pub fn reset_camera(
+    camera: &mut PanOrbitCamera,
+    sr: &mut SpatialReference,
+    aabb: &AABB<Point<f64, 3>>,
+) {
+    let center = DVec3::from_slice(aabb.center().coords());
+    Framing::new(center, aabb).apply(camera);
+
+    sr.origin = Some(center);
+    sr.camera = center;
+}
+
+// Press 'R' to reset the camera
+pub fn camera_controls_system(
+    (key_input, keys): (Res<Input<KeyCode>>, Res<KeyBindings>),
+    mut camera: Query<&mut PanOrbitCamera>,
+    cache: Res<PointCache>,
+    mut sr: ResMut<SpatialReference>,
+    mut gizmos: Gizmos,
+) {
+    let mut camera = camera.get_single_mut().unwrap();
+
+    // camera reset
+    if keys.just_pressed(&key_input, Action::ResetCamera) {
+        let aabb: AABB<Point<f64, 3>> = cache
+            .data
+            .values()
+            .map(|pc| pc.aabb())
+            .reduce(|acc, aabb| acc.merged(&aabb))
+            .unwrap_or_else(AABB::new_empty);
+
+        // bounds are empty without finite points
+        if aabb != AABB::new_empty() {
+            reset_camera(&mut camera, &mut sr, &aabb);
+        }
+    }
+
+    // adjust origin from focus
+    if camera.is_changed() {
+        if let Some(origin) = sr.origin {
+            sr.camera = world_to_data(origin, camera.focus);
+        }
+    }
+
+    // display query box
+    let radius = camera.radius.unwrap_or(1.);
+    gizmos.cuboid(
+        Transform::from_translation(camera.focus).with_scale(Vec3::splat(radius)),
+        Color::WHITE,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frame::data_to_world;
+
+    #[test]
+    fn query_box() {
+        let origin = DVec3::new(100., 200., 10.);
+        let focus = Vec3::new(1., 2., -3.);
+
+        // the box shown by the gizmo around the focus
+        let (lower, upper) = query_bounds(origin, focus, 2.);
+        assert_eq!(lower, DVec3::new(100., 202., 11.));
+        assert_eq!(upper, DVec3::new(102., 204., 13.));
+        assert_eq!(data_to_world(origin, (lower + upper) / 2.), focus);
+    }
+}
@@ -0,0 +1,158 @@
+//! Classes of the collection with their number of points, fetched from the
+//! server, so that legends list the classes that occur instead of all ASPRS
+//! classes
+
+use std::collections::BTreeMap;
+
+use bevy::{
+    prelude::*,
+    tasks::{AsyncComputeTaskPool, Task},
+};
+use futures_lite::future::{self, block_on};
+use serde::Deserialize;
+
+use crux_format::color::Palette;
+
+use crate::ViewerSettings;
+
+/// Column of the classes
+const COLUMN: &str = "classification";
+
+/// Classes of the collection
+#[derive(Resource, Default)]
+pub struct Classes {
+    /// Collection of the fetched or fetching classes
+    collection: Option<String>,
+    /// Number of points by class, `None` until fetched or without classes
+    pub counts: Option<BTreeMap<i64, usize>>,
+}
+
+impl Classes {
+    /// Classes of `palette` that occur, all of them until fetched
+    pub fn palette(&self, palette: &Palette) -> Palette {
+        match &self.counts {
+            Some(counts) => palette.occurring(counts.keys().copied()),
+            None => palette.clone(),
+        }
+    }
+}
+
+#[derive(Component)]
+pub struct ClassesTask {
+    collection: String,
+    task: Task<Result<Option<BTreeMap<i64, usize>>, String>>,
+}
+
+/// Response of `/collections/{name}/columns/{column}/values`
+#[derive(Deserialize)]
+struct ColumnValues {
+    values: Vec<ValueCount>,
+}
+
+#[derive(Deserialize)]
+struct ValueCount {
+    value: i64,
+    count: usize,
+}
+
+/// Classes of `collection` on `server`, `None` if the collection has no
+/// categorical classification
+pub fn fetch(server: &str, collection: &str) -> Result<Option<BTreeMap<i64, usize>>, String> {
+    let url = format!(
+        "{}/collections/{collection}/columns/{COLUMN}/values",
+        server.trim_end_matches('/')
+    );
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_io()
+        .build()
+        .map_err(|e| e.to_string())?;
+    let body = rt
+        .block_on(async {
+            let response = reqwest::get(url).await?;
+            if response.status().is_client_error() {
+                return Ok(None);
+            }
+            response.error_for_status()?.bytes().await.map(Some)
+        })
+        .map_err(|e| e.to_string())?;
+    let Some(body) = body else {
+        return Ok(None);
+    };
+
+    parse(&body).map(Some)
+}
+
+fn parse(body: &[u8]) -> Result<BTreeMap<i64, usize>, String> {
+    let values: ColumnValues = serde_json::from_slice(body).map_err(|e| e.to_string())?;
+    Ok(values
+        .values
+        .into_iter()
+        .map(|v| (v.value, v.count))
+        .collect())
+}
+
+// Fetch the classes whenever the collection changes
+pub fn spawn_classes_task(
+    mut commands: Commands,
+    settings: Res<ViewerSettings>,
+    mut classes: ResMut<Classes>,
+) {
+    if !settings.is_changed() || classes.collection.as_ref() == Some(&settings.collection) {
+        return;
+    }
+
+    classes.collection = Some(settings.collection.clone());
+    classes.counts = None;
+
+    let (server, collection) = (settings.server.clone(), settings.collection.clone());
+    let task = AsyncComputeTaskPool::get().spawn(async move { fetch(&server, &collection) });
+    commands.spawn(ClassesTask {
+        collection: settings.collection.clone(),
+        task,
+    });
+}
+
+pub fn classes_system(
+    mut commands: Commands,
+    mut tasks: Query<(Entity, &mut ClassesTask)>,
+    mut classes: ResMut<Classes>,
+) {
+    for (entity, mut task) in &mut tasks {
+        let Some(result) = block_on(future::poll_once(&mut task.task)) else {
+            continue;
+        };
+        commands.entity(entity).despawn();
+        // superseded by a task of another collection
+        if classes.collection.as_ref() != Some(&task.collection) {
+            continue;
+        }
+
+        match result {
+            Ok(counts) => classes.counts = counts,
+            Err(e) => info!("No classes of the collection: {e}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn occurring() {
+        let body = br#"{"column":"classification","values":[{"value":2,"count":700},{"value":64,"count":3}],"null_count":0}"#;
+        let classes = Classes {
+            collection: Some("city".to_string()),
+            counts: Some(parse(body).unwrap()),
+        };
+        assert_eq!(classes.counts, Some(BTreeMap::from([(2, 700), (64, 3)])));
+
+        let asprs = Palette::classification();
+        let palette = classes.palette(&asprs);
+        assert_eq!(palette.classes.keys().collect::<Vec<_>>(), [&2, &64]);
+        assert_eq!(palette.color(64), asprs.other);
+
+        // all classes until fetched
+        assert_eq!(Classes::default().palette(&asprs), asprs);
+    }
+}
@@ -0,0 +1,25 @@
+//! Colors of the points: the stretch of scalar attributes, the layers of the
+//! collections and the fog
+
+use bevy::prelude::*;
+
+use crate::{
+    fog,
+    layers::{self, Layers},
+    normalize::{self, ScaleBounds},
+    render,
+};
+
+/// Stretch and gamma of the colored attribute, the opacity and order of the
+/// layers and the background and fog
+pub struct ColorPlugin;
+
+impl Plugin for ColorPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ScaleBounds>()
+            .init_resource::<Layers>()
+            .add_systems(Update, normalize::normalization_controls_system)
+            .add_systems(Update, layers::layers_system)
+            .add_systems(Update, fog::fog_system.before(render::update_instances));
+    }
+}
@@ -0,0 +1,312 @@
+use bevy::{prelude::*, window::PrimaryWindow};
+use bevy_aabb_instancing::Cuboid;
+use bevy_panorbit_camera::PanOrbitCamera;
+
+use crate::{
+    instances::CHUNK_SIZE,
+    keys::{Action, KeyBindings},
+    render::InstanceUpload,
+    ViewerSettings,
+};
+
+const DIVIDER_COLOR: Color = Color::WHITE;
+const DIVIDER_WIDTH: f32 = 2.;
+
+/// How the collection and the compared collection are shown
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum CompareMode {
+    /// The collection left of a divider, the compared collection right of it
+    #[default]
+    Swipe,
+    /// One of both at a time
+    Toggle,
+}
+
+impl std::fmt::Display for CompareMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Swipe => "swipe",
+            Self::Toggle => "toggle",
+        })
+    }
+}
+
+/// Comparison of two collections with the same camera
+#[derive(Resource)]
+pub struct Compare {
+    pub mode: CompareMode,
+    /// Divider position as fraction of the window width
+    pub divider: f32,
+    /// Whether the compared collection is shown in toggle mode
+    pub flipped: bool,
+    /// Instances of the collection and the compared collection, while both
+    /// are loaded
+    instances: Option<[Vec<Cuboid>; 2]>,
+    /// The shown instances need to be rebuilt
+    stale: bool,
+}
+
+impl Default for Compare {
+    fn default() -> Self {
+        Self {
+            mode: CompareMode::default(),
+            divider: 0.5,
+            flipped: false,
+            instances: None,
+            stale: false,
+        }
+    }
+}
+
+impl Compare {
+    /// Instances of both collections, shown by [compare_system]
+    pub fn set(&mut self, a: Vec<Vec<Cuboid>>, b: Vec<Vec<Cuboid>>) {
+        self.instances = Some([a.concat(), b.concat()]);
+        self.stale = true;
+    }
+
+    pub fn clear(&mut self) {
+        self.instances = None;
+    }
+
+    /// Overlay line, `None` without a compared collection
+    pub fn status(&self, settings: &ViewerSettings, keys: &KeyBindings) -> Option<String> {
+        let other = settings.compare.as_ref()?;
+        let (mode, flip) = (
+            keys.label(Action::CompareMode),
+            keys.label(Action::CompareFlip),
+        );
+        if self.instances.is_none() {
+            return Some(format!("Compare with `{other}` ({mode}, {flip}): loading"));
+        }
+
+        Some(match self.mode {
+            CompareMode::Swipe => format!(
+                "Compare ({mode}, alt + drag): `{}` | `{other}` at {:.0}%",
+                settings.collection,
+                self.divider * 100.
+            ),
+            CompareMode::Toggle => format!(
+                "Compare ({mode}, {flip}): showing `{}`",
+                if self.flipped {
+                    other
+                } else {
+                    &settings.collection
+                }
+            ),
+        })
+    }
+
+    /// Shown instances in chunks, `screen_x` maps a world position to the
+    /// fraction of the window width it is rendered at
+    fn shown(&self, screen_x: impl Fn(Vec3) -> Option<f32>) -> Vec<Vec<Cuboid>> {
+        let Some([a, b]) = &self.instances else {
+            return Vec::new();
+        };
+
+        let instances: Vec<Cuboid> = match self.mode {
+            CompareMode::Toggle if self.flipped => b.clone(),
+            CompareMode::Toggle => a.clone(),
+            CompareMode::Swipe => {
+                let left_of_divider = |c: &Cuboid| {
+                    screen_x((c.minimum + c.maximum) / 2.).is_some_and(|x| x < self.divider)
+                };
+                let a = a.iter().filter(|c| left_of_divider(c));
+                let b = b.iter().filter(|c| !left_of_divider(c));
+                a.chain(b).copied().collect()
+            }
+        };
+
+        instances.chunks(CHUNK_SIZE).map(<[_]>::to_vec).collect()
+    }
+}
+
+/// Points query of the collection and the compared one in a single response
+/// for a query of the collection, see [crate::fetch::split_collections]
+pub fn combined_url(url: &str, settings: &ViewerSettings) -> Option<String> {
+    let other = settings.compare.as_ref()?;
+    let (base, query) = url.split_once('?')?;
+
+    let collection = format!("collection={}", settings.collection);
+    let mut found = false;
+    let params: Vec<String> = query
+        .split('&')
+        .map(|param| {
+            if param == collection {
+                found = true;
+                format!("collections={},{other}", settings.collection)
+            } else {
+                param.to_owned()
+            }
+        })
+        .collect();
+
+    found.then(|| format!("{base}?{}", params.join("&")))
+}
+
+#[derive(Component)]
+pub struct CompareDivider;
+
+pub fn setup_compare(mut commands: Commands) {
+    commands.spawn((
+        NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                top: Val::Px(0.),
+                bottom: Val::Px(0.),
+                width: Val::Px(DIVIDER_WIDTH),
+                ..default()
+            },
+            background_color: DIVIDER_COLOR.into(),
+            visibility: Visibility::Hidden,
+            ..default()
+        },
+        CompareDivider,
+    ));
+}
+
+// Press 'C' to switch between swipe and toggle, 'X' flips the shown
+// collection, alt + drag moves the divider
+#[allow(clippy::too_many_arguments)]
+pub fn compare_system(
+    key_input: Res<Input<KeyCode>>,
+    keys: Res<KeyBindings>,
+    mouse_input: Res<Input<MouseButton>>,
+    settings: Res<ViewerSettings>,
+    window: Query<&Window, With<PrimaryWindow>>,
+    mut camera: Query<(&Camera, Ref<GlobalTransform>, &mut PanOrbitCamera)>,
+    mut divider: Query<(&mut Style, &mut Visibility), With<CompareDivider>>,
+    mut compare: ResMut<Compare>,
+    mut upload: ResMut<InstanceUpload>,
+    mut moving: Local<bool>,
+) {
+    let (Ok(window), Ok((camera, transform, mut orbit)), Ok((mut style, mut visibility))) = (
+        window.get_single(),
+        camera.get_single_mut(),
+        divider.get_single_mut(),
+    ) else {
+        return;
+    };
+
+    let active = settings.compare.is_some() && compare.instances.is_some();
+    let shown = if active && compare.mode == CompareMode::Swipe {
+        Visibility::Visible
+    } else {
+        Visibility::Hidden
+    };
+    if *visibility != shown {
+        *visibility = shown;
+    }
+    if !active {
+        return;
+    }
+
+    if keys.just_pressed(&key_input, Action::CompareMode) {
+        compare.mode = match compare.mode {
+            CompareMode::Swipe => CompareMode::Toggle,
+            CompareMode::Toggle => CompareMode::Swipe,
+        };
+        compare.stale = true;
+    }
+    if keys.just_pressed(&key_input, Action::CompareFlip) && compare.mode == CompareMode::Toggle {
+        compare.flipped = !compare.flipped;
+        compare.stale = true;
+    }
+
+    // the camera does not orbit while the divider is dragged
+    let alt = key_input.any_pressed([KeyCode::AltLeft, KeyCode::AltRight]);
+    let dragging = alt && compare.mode == CompareMode::Swipe;
+    if orbit.enabled == dragging {
+        orbit.enabled = !dragging;
+    }
+    if dragging && mouse_input.pressed(MouseButton::Left) {
+        if let Some(cursor) = window.cursor_position() {
+            let x = (cursor.x / window.width()).clamp(0., 1.);
+            if x != compare.divider {
+                compare.divider = x;
+                compare.stale = true;
+            }
+        }
+    }
+    style.left = Val::Percent(compare.divider * 100.);
+
+    // swiped instances are rebuilt once the camera comes to rest
+    if compare.mode == CompareMode::Swipe && transform.is_changed() {
+        *moving = true;
+        return;
+    }
+    if std::mem::take(&mut *moving) {
+        compare.stale = compare.mode == CompareMode::Swipe || compare.stale;
+    }
+
+    if compare.stale {
+        compare.stale = false;
+        let width = window.width();
+        upload.set(compare.shown(|p| {
+            camera
+                .world_to_viewport(&transform, p)
+                .map(|viewport| viewport.x / width)
+        }));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cuboid(x: f32, color: u32) -> Cuboid {
+        Cuboid::new(Vec3::new(x, 0., 0.), Vec3::new(x, 0., 0.), color)
+    }
+
+    #[test]
+    fn swipe() {
+        let mut compare = Compare::default();
+        assert!(compare.shown(|p| Some(p.x)).is_empty());
+
+        // the same positions in both collections
+        let positions = [0.1, 0.4, 0.6, 0.9];
+        compare.set(
+            vec![positions.iter().map(|x| cuboid(*x, 1)).collect()],
+            vec![positions.iter().map(|x| cuboid(*x, 2)).collect()],
+        );
+
+        let colors = |compare: &Compare| -> Vec<u32> {
+            compare
+                .shown(|p| Some(p.x))
+                .concat()
+                .iter()
+                .map(|c| c.color)
+                .collect()
+        };
+        assert_eq!(colors(&compare), [1, 1, 2, 2]);
+
+        compare.divider = 0.;
+        assert_eq!(colors(&compare), [2, 2, 2, 2]);
+
+        compare.mode = CompareMode::Toggle;
+        assert_eq!(colors(&compare), [1, 1, 1, 1]);
+        compare.flipped = true;
+        assert_eq!(colors(&compare), [2, 2, 2, 2]);
+    }
+
+    #[test]
+    fn url() {
+        let mut settings = ViewerSettings {
+            collection: "epoch1".to_string(),
+            ..default()
+        };
+        let url = "http://localhost:3000/points?collection=epoch1&p=0.1&seed=0";
+        assert_eq!(combined_url(url, &settings), None);
+
+        settings.compare = Some("epoch2".to_string());
+        assert_eq!(
+            combined_url(url, &settings).unwrap(),
+            "http://localhost:3000/points?collections=epoch1,epoch2&p=0.1&seed=0"
+        );
+        // other queries are not compared
+        assert_eq!(
+            combined_url("http://localhost:3000/points?collection=epoch10", &settings),
+            None
+        );
+    }
+}
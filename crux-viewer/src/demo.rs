@@ -0,0 +1,63 @@
+use bevy::prelude::*;
+
+use crux_format::{synthetic::Synthetic, ArrowPointCloud, PointCloudError};
+
+use crate::{settings::SettingsArgs, PointCache, ViewerSettings};
+
+/// Number of points of the demo scene
+const DEMO_POINTS: usize = 500_000;
+/// Number of buildings of the demo scene
+const DEMO_BUILDINGS: usize = 25;
+
+/// Buildings on terrain with intensity and height colors, see `--demo`
+pub fn scene(seed: u64) -> Result<ArrowPointCloud, PointCloudError> {
+    Synthetic::new(DEMO_POINTS)
+        .extent([0., 0., 0.], [200., 200., 40.])
+        .seed(seed)
+        .intensity(true)
+        .rgb(true)
+        .buildings(DEMO_BUILDINGS)
+}
+
+/// Show the demo scene as the collection instead of querying the server
+pub fn setup_demo(
+    mut commands: Commands,
+    args: Res<SettingsArgs>,
+    mut settings: ResMut<ViewerSettings>,
+    mut cache: ResMut<PointCache>,
+) {
+    if !args.demo {
+        return;
+    }
+
+    let pc = match scene(settings.seed) {
+        Ok(pc) => pc,
+        Err(e) => {
+            error!("Failed to generate the demo scene: {e}");
+            return;
+        }
+    };
+    info!("Generated a demo scene of {DEMO_POINTS} points");
+
+    // refinements would replace the scene by server data
+    settings.auto_lod = false;
+
+    let collection = settings.collection.to_owned();
+    cache.insert(&mut commands, &settings, &collection, pc);
+}
+
+#[cfg(test)]
+mod tests {
+    use crux_format::PointCloudTrait;
+
+    use super::*;
+
+    #[test]
+    fn scene() {
+        let pc = super::scene(0).unwrap();
+        assert_eq!(pc.num_points(), DEMO_POINTS);
+        for column in ["classification", "intensity", "red", "green", "blue"] {
+            assert!(pc.schema().field_with_name(column).is_ok());
+        }
+    }
+}
@@ -0,0 +1,912 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use arrow::{
+    array::{AsArray, BooleanArray, UInt32Array},
+    compute::{cast, filter_record_batch, take},
+    datatypes::{DataType, Schema},
+    error::ArrowError,
+    record_batch::RecordBatch,
+};
+use bevy::{log::warn, math::DVec3};
+use bytes::Bytes;
+use rand::Rng;
+use reqwest::{
+    header::{ETAG, IF_NONE_MATCH},
+    Client, StatusCode,
+};
+
+use crux_format::{
+    checksum::{CHECKSUM_HEADER, XXHASH64},
+    sample::{sample_batch, voxel_downsample},
+    ArrowPointCloud, PointCloudError, PointCloudTrait,
+};
+
+use crux_io::problem::Problem;
+
+use crate::{
+    memory::{self, MIB},
+    ViewerSettings,
+};
+
+/// Number of attempts of a load
+pub const ATTEMPTS: u32 = 3;
+/// Delay before the first retry, doubled for every further retry
+const BACKOFF: Duration = Duration::from_secs(2);
+/// Interval in which waiting retries check whether they were abandoned
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Timeout and retries of HTTP loads
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    pub attempts: u32,
+    pub backoff: Duration,
+    pub timeout: Duration,
+}
+
+impl RetryPolicy {
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            attempts: ATTEMPTS,
+            backoff: BACKOFF,
+            timeout,
+        }
+    }
+
+    /// Delay before retry `retry` (starting at 1), with up to 50% jitter
+    pub fn delay(&self, retry: u32, jitter: f64) -> Duration {
+        self.backoff
+            .saturating_mul(1 << (retry - 1).min(16))
+            .mul_f64(1. + jitter.clamp(0., 1.) / 2.)
+    }
+}
+
+/// Progress of a load, shared with the debug overlay
+#[derive(Default)]
+pub struct LoadState {
+    /// Next attempt, number of attempts and time of the next attempt
+    retry: Mutex<Option<(u32, u32, Instant)>>,
+    abandoned: AtomicBool,
+}
+
+impl LoadState {
+    /// Stop retrying and cancel a running attempt
+    pub fn abandon(&self) {
+        self.abandoned.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_abandoned(&self) -> bool {
+        self.abandoned.load(Ordering::Relaxed)
+    }
+
+    /// Overlay text, `None` unless a retry is pending
+    pub fn status(&self) -> Option<String> {
+        let (attempt, attempts, at) = (*self.retry.lock().unwrap())?;
+        let seconds = at.saturating_duration_since(Instant::now()).as_secs_f64();
+        Some(format!(
+            "attempt {attempt}/{attempts} in {}s",
+            seconds.ceil()
+        ))
+    }
+}
+
+/// Latest issued load request per collection, so that a slow response to an
+/// older request does not replace the points of a newer one
+#[derive(Debug, Default)]
+pub struct RequestIds {
+    latest: HashMap<String, u64>,
+    next: u64,
+}
+
+impl RequestIds {
+    /// Id of a new request, superseding the previous requests of `collection`
+    pub fn issue(&mut self, collection: &str) -> u64 {
+        self.next += 1;
+        self.latest.insert(collection.to_owned(), self.next);
+        self.next
+    }
+
+    /// Whether `id` is the latest request of `collection`
+    pub fn is_latest(&self, collection: &str, id: u64) -> bool {
+        self.latest.get(collection) == Some(&id)
+    }
+}
+
+#[derive(Debug)]
+pub enum FetchError {
+    /// Failed after all attempts or with a non retryable error
+    Failed { attempts: u32, error: String },
+    /// The response could not be decoded
+    Invalid(String),
+    /// A newer load of the same collection was requested
+    Abandoned,
+}
+
+impl fmt::Display for FetchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Failed { attempts: 1, error } => write!(f, "{error}"),
+            Self::Failed { attempts, error } => write!(f, "{error} (after {attempts} attempts)"),
+            Self::Invalid(error) => write!(f, "invalid response: {error}"),
+            Self::Abandoned => f.write_str("abandoned"),
+        }
+    }
+}
+
+/// Connection errors, timeouts and server errors are retried, client errors are not
+pub fn is_retryable(error: &reqwest::Error) -> bool {
+    match error.status() {
+        Some(status) => status.is_server_error(),
+        None => error.is_connect() || error.is_timeout() || error.is_request() || error.is_body(),
+    }
+}
+
+/// Failure of a single attempt
+#[derive(Debug)]
+enum AttemptError {
+    Request(reqwest::Error),
+    /// Error response with the detail of its problem, see [Problem]
+    Status(StatusCode, String),
+}
+
+impl AttemptError {
+    fn is_retryable(&self) -> bool {
+        match self {
+            Self::Request(error) => is_retryable(error),
+            Self::Status(status, _) => status.is_server_error(),
+        }
+    }
+}
+
+impl From<reqwest::Error> for AttemptError {
+    fn from(error: reqwest::Error) -> Self {
+        Self::Request(error)
+    }
+}
+
+impl fmt::Display for AttemptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Request(error) => write!(f, "{error}"),
+            Self::Status(status, detail) => write!(f, "{status}: {detail}"),
+        }
+    }
+}
+
+/// Response of a conditional load
+#[derive(Debug)]
+pub enum Fetched {
+    Modified {
+        body: Bytes,
+        etag: Option<String>,
+    },
+    /// The entity tag sent with the request is current
+    NotModified,
+}
+
+/// Points endpoint url for the configured collection
+pub fn points_url(settings: &ViewerSettings, params: &str) -> String {
+    collection_url(settings, &settings.collection, params)
+}
+
+/// Points endpoint url for `collection` on the configured server
+pub fn collection_url(settings: &ViewerSettings, collection: &str, params: &str) -> String {
+    let mut url = format!(
+        "{}/points?collection={collection}",
+        settings.server.trim_end_matches('/'),
+    );
+    if !params.is_empty() {
+        url.push('&');
+        url.push_str(params);
+    }
+    // reproducible samples
+    if !settings.random_samples {
+        url.push_str(&format!("&seed={}", settings.seed));
+    }
+    url
+}
+
+/// Query parameter of the preview the server draws after ingests
+const PREVIEW_PARAM: &str = "preview=true";
+
+/// Preview of the configured collection, which needs no sampling fraction
+pub fn preview_url(settings: &ViewerSettings) -> String {
+    format!(
+        "{}/points?collection={}&{PREVIEW_PARAM}",
+        settings.server.trim_end_matches('/'),
+        settings.collection,
+    )
+}
+
+/// Whether `url` loads a preview, see [preview_url]
+pub fn is_preview(url: &str) -> bool {
+    url.split(['?', '&']).any(|param| param == PREVIEW_PARAM)
+}
+
+/// Overview of the collection, a fraction `p` or the configured sample.
+/// Fractions are stable samples unless samples are random, so that points
+/// shown before an append are kept.
+pub fn overview_url(settings: &ViewerSettings, p: f64) -> String {
+    match &settings.sample {
+        Some(sample) => points_url(settings, &format!("sample={sample}")),
+        None if settings.random_samples => points_url(settings, &format!("p={p}")),
+        None => points_url(settings, &format!("sample=stable:{p}")),
+    }
+}
+
+/// Points between `lower` and `upper` in the data reference system, with the
+/// importance range `0..p` as fourth dimension of the bounds
+pub fn bounds_url(settings: &ViewerSettings, lower: DVec3, upper: DVec3, p: f64) -> String {
+    points_url(
+        settings,
+        &format!(
+            "bounds={},{},{},0,{},{},{},{p}",
+            lower.x, lower.y, lower.z, upper.x, upper.y, upper.z
+        ),
+    )
+}
+
+/// Points of an Arrow IPC stream response
+pub fn decode(body: Bytes) -> Result<ArrowPointCloud, FetchError> {
+    // verifies the batches if the server sent checksums, files are read as
+    // well, e.g. Feather files of the headless input
+    ArrowPointCloud::from_ipc_bytes(body).map_err(|e| FetchError::Invalid(e.to_string()))
+}
+
+/// Thinning of the batches of a response while they are decoded, see
+/// [decode_thinned]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Thinning {
+    /// Fraction of the points kept, a stable sample
+    pub sample: Option<f64>,
+    /// Voxel size in data units, one point is kept per voxel
+    pub voxel: Option<f64>,
+    /// Bytes the kept points may occupy, later batches are thinned further
+    pub budget: usize,
+}
+
+impl Thinning {
+    /// Thinning configured in the settings, `None` if the responses are
+    /// decoded in full
+    pub fn from_settings(settings: &ViewerSettings) -> Option<Self> {
+        (settings.decode_sample.is_some() || settings.decode_voxel.is_some()).then(|| Self {
+            sample: settings.decode_sample,
+            voxel: settings.decode_voxel,
+            budget: settings.memory_budget * MIB,
+        })
+    }
+}
+
+/// Points of a response before and after the thinning on decode
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LoadStats {
+    pub batches: usize,
+    /// Points in the response
+    pub decoded: usize,
+    /// Points kept
+    pub kept: usize,
+    /// Bytes of the kept points
+    pub bytes: usize,
+    /// Batches thinned further or dropped to stay within the budget
+    pub limited: usize,
+}
+
+impl LoadStats {
+    /// Rows of a batch of `rows` points and `size` bytes that fit into the
+    /// rest of the `budget`, `None` if all of them do
+    pub fn fitting(&self, rows: usize, size: usize, budget: usize) -> Option<usize> {
+        let available = budget.saturating_sub(self.bytes);
+        (rows > 0 && size > available)
+            .then(|| (rows as f64 * available as f64 / size as f64) as usize)
+    }
+
+    /// Account a batch of `decoded` points of which `kept` are stored
+    pub fn record(&mut self, decoded: usize, kept: &RecordBatch, limited: bool) {
+        self.batches += 1;
+        self.decoded += decoded;
+        self.kept += kept.num_rows();
+        // emptied batches are not stored
+        if kept.num_rows() > 0 {
+            self.bytes += memory::batch_size(kept);
+        }
+        self.limited += usize::from(limited);
+    }
+
+    /// Whether points were dropped
+    pub fn is_reduced(&self) -> bool {
+        self.kept < self.decoded
+    }
+
+    /// Reduction shown by the overlay
+    pub fn status(&self) -> String {
+        let percent = match self.decoded {
+            0 => 100.,
+            decoded => 100. * self.kept as f64 / decoded as f64,
+        };
+        let mut status = format!("thinned on decode to {percent:.1}% of {}", self.decoded);
+        if self.limited > 0 {
+            status += &format!(
+                ", {} of {} batches by the budget",
+                self.limited, self.batches
+            );
+        }
+        status
+    }
+}
+
+/// Store entry of the stable samples, decisions depend on the batch and row
+/// only unless the points have ids
+const THINNING_KEY: &str = "decode";
+
+/// Points of an Arrow IPC stream response thinned batch by batch as they are
+/// decoded, so that the points are never held at full resolution
+pub fn decode_thinned(
+    body: Bytes,
+    thinning: &Thinning,
+) -> Result<(ArrowPointCloud, LoadStats), FetchError> {
+    let mut stats = LoadStats::default();
+    let pc = ArrowPointCloud::from_ipc_bytes_map(body, |j, batch| {
+        let decoded = batch.num_rows();
+        let mut batch = match thinning.sample {
+            Some(p) => sample_batch(&batch, p, THINNING_KEY, j)?,
+            None => batch,
+        };
+        if let Some(size) = thinning.voxel {
+            batch = voxel_downsample(&batch, size)?;
+        }
+
+        let fits = |batch: &RecordBatch| {
+            stats.fitting(batch.num_rows(), memory::batch_size(batch), thinning.budget)
+        };
+        let limited = match fits(&batch) {
+            Some(fitting) => {
+                let p = fitting as f64 / batch.num_rows() as f64;
+                batch = sample_batch(&batch, p, THINNING_KEY, j)?;
+                // sizes are not proportional to the rows, the sample is
+                // truncated until it fits
+                while let Some(fitting) = fits(&batch) {
+                    let indices = UInt32Array::from_iter_values(0..fitting as u32);
+                    let columns = batch
+                        .columns()
+                        .iter()
+                        .map(|column| take(column, &indices, None))
+                        .collect::<Result<_, _>>()?;
+                    batch = RecordBatch::try_new(batch.schema(), columns)?;
+                }
+                true
+            }
+            None => false,
+        };
+        stats.record(decoded, &batch, limited);
+        Ok(batch)
+    })
+    .map_err(|e| FetchError::Invalid(e.to_string()))?;
+    Ok((pc, stats))
+}
+
+/// Column labeling the points of a response to several collections
+pub const COLLECTION_COLUMN: &str = "collection";
+
+/// Points of a response to several collections by collection, in order of
+/// their first point and without the label, `None` if they are not labeled
+pub fn split_collections(
+    pc: &ArrowPointCloud,
+) -> Result<Option<Vec<(String, ArrowPointCloud)>>, FetchError> {
+    let schema = pc.schema();
+    let Some((index, _)) = schema
+        .column_with_name(COLLECTION_COLUMN)
+        .filter(|(_, field)| matches!(field.data_type(), DataType::Dictionary(..)))
+    else {
+        return Ok(None);
+    };
+
+    let split = || -> Result<_, PointCloudError> {
+        let mut fields = schema.fields().to_vec();
+        fields.remove(index);
+        let unlabeled = Arc::new(Schema::new_with_metadata(
+            fields,
+            schema.metadata().to_owned(),
+        ));
+
+        let mut parts: Vec<(String, ArrowPointCloud)> = Vec::new();
+        for e in pc.store.iter() {
+            for batch in pc.store.batches(e.key()) {
+                let labels = cast(batch.column(index), &DataType::Utf8)?;
+                let labels = labels.as_string::<i32>();
+                let mut columns = batch.columns().to_vec();
+                columns.remove(index);
+                let batch = RecordBatch::try_new(unlabeled.clone(), columns)?;
+
+                // usually one collection per batch
+                let mut names: Vec<&str> = Vec::new();
+                for label in labels.iter() {
+                    let label = label.ok_or_else(|| {
+                        ArrowError::InvalidArgumentError("unlabeled points".to_owned())
+                    })?;
+                    if !names.contains(&label) {
+                        names.push(label);
+                    }
+                }
+
+                for label in names.iter().copied() {
+                    let part = if names.len() == 1 {
+                        batch.clone()
+                    } else {
+                        let mask: BooleanArray =
+                            labels.iter().map(|l| Some(l == Some(label))).collect();
+                        filter_record_batch(&batch, &mask)?
+                    };
+                    match parts.iter_mut().find(|(name, _)| name == label) {
+                        Some((_, pc)) => pc.append(part)?,
+                        None => {
+                            let mut pc = ArrowPointCloud::try_new(unlabeled.clone())?;
+                            pc.append(part)?;
+                            parts.push((label.to_owned(), pc));
+                        }
+                    }
+                }
+            }
+        }
+        Ok(parts)
+    };
+
+    split()
+        .map(Some)
+        .map_err(|e| FetchError::Invalid(e.to_string()))
+}
+
+async fn attempt(client: &Client, url: &str, etag: Option<&str>) -> Result<Fetched, AttemptError> {
+    let mut request = client.get(url).header(CHECKSUM_HEADER, XXHASH64);
+    if let Some(etag) = etag {
+        request = request.header(IF_NONE_MATCH, etag);
+    }
+
+    let response = request.send().await?;
+    if response.status() == StatusCode::NOT_MODIFIED {
+        return Ok(Fetched::NotModified);
+    }
+
+    let status = response.status();
+    if !status.is_success() {
+        let body = response.bytes().await?;
+        let detail = Problem::detail(status.as_u16(), &body);
+        return Err(AttemptError::Status(status, detail));
+    }
+    let etag = response
+        .headers()
+        .get(ETAG)
+        .and_then(|etag| etag.to_str().ok())
+        .map(str::to_owned);
+
+    Ok(Fetched::Modified {
+        body: response.bytes().await?,
+        etag,
+    })
+}
+
+/// Body of `url`, retried with exponential backoff according to `policy`.
+///
+/// With the entity tag of a previous response, the body is only transferred
+/// if it changed.
+pub async fn fetch(
+    url: &str,
+    etag: Option<&str>,
+    policy: &RetryPolicy,
+    state: &LoadState,
+) -> Result<Fetched, FetchError> {
+    let failed = |attempts, error: &dyn fmt::Display| FetchError::Failed {
+        attempts,
+        error: error.to_string(),
+    };
+    let client = Client::builder()
+        .timeout(policy.timeout)
+        .build()
+        .map_err(|e| failed(0, &e))?;
+
+    let mut n = 1;
+    loop {
+        if state.is_abandoned() {
+            return Err(FetchError::Abandoned);
+        }
+
+        // a running attempt is cancelled once the load is abandoned
+        let error = tokio::select! {
+            result = attempt(&client, url, etag) => match result {
+                Ok(fetched) => return Ok(fetched),
+                Err(e) => e,
+            },
+            _ = abandoned(state) => return Err(FetchError::Abandoned),
+        };
+        if n >= policy.attempts || !error.is_retryable() {
+            return Err(failed(n, &error));
+        }
+
+        let delay = policy.delay(n, rand::thread_rng().gen());
+        warn!("Load of `{url}` failed, retrying in {delay:?}: {error}");
+        n += 1;
+
+        let at = Instant::now() + delay;
+        *state.retry.lock().unwrap() = Some((n, policy.attempts, at));
+        while Instant::now() < at {
+            if state.is_abandoned() {
+                return Err(FetchError::Abandoned);
+            }
+            tokio::time::sleep(POLL_INTERVAL.min(at - Instant::now())).await;
+        }
+        *state.retry.lock().unwrap() = None;
+    }
+}
+
+/// Resolves once the load is abandoned
+async fn abandoned(state: &LoadState) {
+    while !state.is_abandoned() {
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        net::TcpListener,
+        sync::Arc,
+    };
+
+    use super::*;
+
+    /// Server answering consecutive requests with the given status codes, the
+    /// entity tag of the responses is `"v1"`
+    fn serve(statuses: Vec<u16>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/points", listener.local_addr().unwrap());
+
+        std::thread::spawn(move || {
+            for status in statuses {
+                let (mut stream, _) = listener.accept().unwrap();
+                let _ = stream.read(&mut [0; 1024]);
+                let status = StatusCode::from_u16(status).unwrap().to_string();
+                let body = if status.starts_with("304") {
+                    ""
+                } else {
+                    &status
+                };
+                write!(
+                    stream,
+                    "HTTP/1.1 {status}\r\nETag: \"v1\"\r\nContent-Length: {}\r\n\
+                     Connection: close\r\n\r\n{body}",
+                    body.len()
+                )
+                .unwrap();
+            }
+        });
+
+        url
+    }
+
+    fn block_on<T>(future: impl std::future::Future<Output = T>) -> T {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy {
+            attempts: 3,
+            backoff: Duration::from_millis(10),
+            timeout: Duration::from_secs(5),
+        }
+    }
+
+    #[test]
+    fn seed() {
+        let mut settings = ViewerSettings {
+            server: "http://localhost:3000/".to_string(),
+            seed: 7,
+            ..Default::default()
+        };
+        assert_eq!(
+            points_url(&settings, "p=0.1"),
+            "http://localhost:3000/points?collection=default&p=0.1&seed=7"
+        );
+        assert_eq!(
+            overview_url(&settings, 0.01),
+            "http://localhost:3000/points?collection=default&sample=stable:0.01&seed=7"
+        );
+
+        settings.random_samples = true;
+        assert_eq!(
+            points_url(&settings, ""),
+            "http://localhost:3000/points?collection=default"
+        );
+
+        // overviews keep rare classes
+        assert_eq!(
+            overview_url(&settings, 0.01),
+            "http://localhost:3000/points?collection=default&p=0.01"
+        );
+        settings.sample = Some("stratified:classification:1000".to_string());
+        assert_eq!(
+            overview_url(&settings, 0.01),
+            "http://localhost:3000/points?collection=default&sample=stratified:classification:1000"
+        );
+
+        let preview = preview_url(&settings);
+        assert_eq!(
+            preview,
+            "http://localhost:3000/points?collection=default&preview=true"
+        );
+        assert!(is_preview(&preview));
+        assert!(!is_preview(&overview_url(&settings, 0.01)));
+
+        // corners in the layout of the server, importance last
+        let (lower, upper) = (DVec3::new(1., 2., 3.), DVec3::new(4., 5., 6.));
+        assert_eq!(
+            bounds_url(&settings, lower, upper, 0.5),
+            "http://localhost:3000/points?collection=default&bounds=1,2,3,0,4,5,6,0.5"
+        );
+    }
+
+    #[test]
+    fn backoff() {
+        let policy = RetryPolicy::new(Duration::from_secs(30));
+        assert_eq!(policy.delay(1, 0.), Duration::from_secs(2));
+        assert_eq!(policy.delay(2, 0.), Duration::from_secs(4));
+        assert_eq!(policy.delay(2, 1.), Duration::from_secs(6));
+
+        let state = LoadState::default();
+        assert_eq!(state.status(), None);
+        *state.retry.lock().unwrap() = Some((2, 3, Instant::now() + Duration::from_millis(3500)));
+        assert_eq!(state.status().unwrap(), "attempt 2/3 in 4s");
+    }
+
+    #[test]
+    fn retries() {
+        // transient server errors are retried
+        let url = serve(vec![502, 503, 200]);
+        let fetched = block_on(fetch(&url, None, &policy(), &LoadState::default()));
+        assert!(matches!(
+            fetched,
+            Ok(Fetched::Modified { body, etag: Some(etag) }) if body.as_ref() == b"200 OK" && etag == "\"v1\""
+        ));
+
+        // client errors are not
+        let url = serve(vec![404, 200]);
+        let result = block_on(fetch(&url, None, &policy(), &LoadState::default()));
+        assert!(matches!(
+            result,
+            Err(FetchError::Failed { attempts: 1, .. })
+        ));
+
+        // attempts are limited
+        let url = serve(vec![502, 502, 502, 200]);
+        let result = block_on(fetch(&url, None, &policy(), &LoadState::default()));
+        assert!(matches!(
+            result,
+            Err(FetchError::Failed { attempts: 3, .. })
+        ));
+    }
+
+    #[test]
+    fn problem() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/points", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let _ = stream.read(&mut [0; 1024]);
+            let body = r#"{"type":"crux:unknown-collection","title":"Unknown collection","status":404,"detail":"no collection `missing`","collection":"missing"}"#;
+            write!(
+                stream,
+                "HTTP/1.1 404 Not Found\r\nContent-Type: application/problem+json\r\n\
+                 Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                body.len()
+            )
+            .unwrap();
+        });
+
+        // the detail of the problem is shown, client errors are not retried
+        let error = block_on(fetch(&url, None, &policy(), &LoadState::default())).unwrap_err();
+        assert!(matches!(error, FetchError::Failed { attempts: 1, .. }));
+        assert_eq!(error.to_string(), "404 Not Found: no collection `missing`");
+    }
+
+    #[test]
+    fn abandon() {
+        let url = serve(vec![502, 200]);
+        let state = Arc::new(LoadState::default());
+        let policy = RetryPolicy {
+            backoff: Duration::from_secs(10),
+            ..policy()
+        };
+
+        let handle = {
+            let state = state.clone();
+            std::thread::spawn(move || block_on(fetch(&url, None, &policy, &state)))
+        };
+        while state.status().is_none() {
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        state.abandon();
+
+        assert!(matches!(handle.join().unwrap(), Err(FetchError::Abandoned)));
+    }
+
+    #[test]
+    fn request_ids() {
+        let mut ids = RequestIds::default();
+        let full = ids.issue("default");
+        let overview = ids.issue("default");
+        let other = ids.issue("epoch2");
+
+        // F1 then F5, the full response arrives last and is stale
+        assert!(ids.is_latest("default", overview));
+        assert!(!ids.is_latest("default", full));
+        // ids are per collection
+        assert!(ids.is_latest("epoch2", other));
+        assert!(!ids.is_latest("missing", full));
+    }
+
+    #[test]
+    fn abandon_running() {
+        // a server that accepts but never answers
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/points", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            let _connections: Vec<_> = listener.incoming().collect();
+        });
+
+        let state = Arc::new(LoadState::default());
+        let handle = {
+            let state = state.clone();
+            std::thread::spawn(move || block_on(fetch(&url, None, &policy(), &state)))
+        };
+        std::thread::sleep(Duration::from_millis(50));
+        state.abandon();
+
+        let started = Instant::now();
+        assert!(matches!(handle.join().unwrap(), Err(FetchError::Abandoned)));
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+
+    #[test]
+    fn not_modified() {
+        let url = serve(vec![304]);
+        let fetched = block_on(fetch(
+            &url,
+            Some("\"v1\""),
+            &policy(),
+            &LoadState::default(),
+        ));
+        assert!(matches!(fetched, Ok(Fetched::NotModified)));
+    }
+
+    #[test]
+    fn thinning() {
+        use arrow::ipc::writer::StreamWriter;
+        use crux_format::synthetic::Synthetic;
+
+        let pc = Synthetic::new(4000)
+            .seed(1)
+            .batch_size(1000)
+            .extent([0., 0., 0.], [100., 100., 10.])
+            .uniform()
+            .unwrap();
+        let mut writer = StreamWriter::try_new(Vec::new(), &pc.schema()).unwrap();
+        for e in pc.store.iter() {
+            for batch in pc.store.batches(e.key()) {
+                writer.write(&batch).unwrap();
+            }
+        }
+        writer.finish().unwrap();
+        let body = Bytes::from(writer.into_inner().unwrap());
+        // decoded batches share the buffers of the messages
+        let full = memory::cloud_size(&decode(body.clone()).unwrap());
+
+        let thin = |sample, voxel, budget| {
+            let thinning = Thinning {
+                sample,
+                voxel,
+                budget,
+            };
+            let (pc, stats) = decode_thinned(body.clone(), &thinning).unwrap();
+            assert_eq!((stats.batches, stats.decoded), (4, 4000));
+            assert_eq!(stats.kept, pc.num_points());
+            assert_eq!(stats.bytes, memory::cloud_size(&pc));
+            stats
+        };
+
+        // within the budget
+        let stats = thin(None, None, full);
+        assert_eq!((stats.kept, stats.limited), (4000, 0));
+        assert!(!stats.is_reduced());
+        let stats = thin(Some(0.25), None, full);
+        assert!(stats.kept > 500 && stats.kept < 1500);
+        assert_eq!(stats.limited, 0);
+        assert!(stats.status().starts_with("thinned on decode to "));
+        assert!(stats.status().ends_with("% of 4000"));
+        let stats = thin(None, Some(50.), full);
+        assert_eq!(stats.kept, 4 * 4);
+
+        // later batches are thinned to stay within the budget
+        let compact = thin(Some(1.), None, full).bytes;
+        let stats = thin(Some(1.), None, compact / 2);
+        assert!(stats.bytes <= compact / 2);
+        assert!(stats.kept < 4000);
+        assert!(stats.limited >= 2);
+        assert!(stats.status().ends_with(" batches by the budget"));
+
+        // rows fitting into the rest of the budget
+        let stats = LoadStats {
+            bytes: 600,
+            ..Default::default()
+        };
+        assert_eq!(stats.fitting(100, 400, 1000), None);
+        assert_eq!(stats.fitting(100, 800, 1000), Some(50));
+        assert_eq!(stats.fitting(100, 800, 500), Some(0));
+        assert_eq!(stats.fitting(0, 800, 500), None);
+
+        assert!(decode_thinned(
+            body,
+            &Thinning {
+                sample: Some(2.),
+                voxel: None,
+                budget: full,
+            }
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn split() {
+        use arrow::array::{ArrayRef, StringArray};
+        use crux_format::{compute::append_constant_dictionary, synthetic::Synthetic};
+
+        let pc = Synthetic::new(100)
+            .seed(1)
+            .batch_size(40)
+            .uniform()
+            .unwrap();
+        assert_eq!(
+            split_collections(&pc).unwrap().map(|parts| parts.len()),
+            None
+        );
+
+        // epoch2 first, then a batch mixing both collections
+        let names: ArrayRef = Arc::new(StringArray::from(vec!["epoch1", "epoch2"]));
+        let batches: Vec<RecordBatch> = pc
+            .store
+            .iter()
+            .flat_map(|e| pc.store.batches(e.key()))
+            .collect();
+        let mut labeled = Vec::new();
+        for (i, batch) in batches.iter().enumerate() {
+            let key = usize::from(i == 0);
+            labeled
+                .push(append_constant_dictionary(batch, COLLECTION_COLUMN, &names, key).unwrap());
+        }
+        let mixed = arrow::compute::concat_batches(&labeled[0].schema(), &labeled[..2]).unwrap();
+        let mut combined = ArrowPointCloud::try_new(mixed.schema()).unwrap();
+        combined.append(labeled[0].clone()).unwrap();
+        combined.append(mixed).unwrap();
+
+        let parts = split_collections(&combined).unwrap().unwrap();
+        let counts: Vec<(&str, usize)> = parts
+            .iter()
+            .map(|(name, pc)| (name.as_str(), pc.num_points()))
+            .collect();
+        let first = batches[0].num_rows();
+        let second = batches[1].num_rows();
+        assert_eq!(counts, [("epoch2", 2 * first), ("epoch1", second)]);
+        assert!(parts
+            .iter()
+            .all(|(_, pc)| pc.schema().column_with_name(COLLECTION_COLUMN).is_none()));
+    }
+}
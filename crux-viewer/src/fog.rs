@@ -0,0 +1,257 @@
+//! Background color, fog and depth cueing. The colors of distant points are
+//! attenuated toward the background by their distance to the camera when the
+//! instances are generated, the instances are regenerated once the camera
+//! moved by a share of the data.
+
+use bevy::{math::DVec3, prelude::*};
+use bevy_panorbit_camera::PanOrbitCamera;
+use serde::{Deserialize, Serialize};
+
+use crux_format::prelude::*;
+
+use crate::{
+    frame::world_to_data,
+    keys::{Action, KeyBindings},
+    sizing, PointCache, SpatialReference, ViewerSettings,
+};
+
+/// Share of the diagonal of the data the camera moves before the fog is
+/// recomputed
+pub const REFRESH: f64 = 0.1;
+/// Change of the depth cueing strength per key press
+const STEP: f32 = 0.1;
+
+/// Color behind the points
+#[derive(Serialize, Deserialize, clap::ValueEnum, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Background {
+    /// The neutral grey of Bevy
+    #[default]
+    Grey,
+    /// Pure white, e.g. for figures
+    White,
+    /// Pure black
+    Black,
+}
+
+impl Background {
+    pub fn next(self) -> Self {
+        match self {
+            Self::Grey => Self::White,
+            Self::White => Self::Black,
+            Self::Black => Self::Grey,
+        }
+    }
+
+    pub fn color(self) -> Color {
+        match self {
+            Self::Grey => Color::rgb(0.4, 0.4, 0.4),
+            Self::White => Color::WHITE,
+            Self::Black => Color::BLACK,
+        }
+    }
+}
+
+impl std::fmt::Display for Background {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Grey => write!(f, "grey"),
+            Self::White => write!(f, "white"),
+            Self::Black => write!(f, "black"),
+        }
+    }
+}
+
+/// Linear fog from the nearest to the farthest distance of the bounds `aabb`
+/// around their center seen from `eye`, starting at the eye inside of them
+pub fn fog_range(eye: DVec3, aabb: &AABB<Point<f64, 3>>) -> (f64, f64) {
+    let center = DVec3::from_slice(aabb.center().coords());
+    let radius = sizing::size(aabb).length() / 2.;
+    let distance = eye.distance(center);
+    ((distance - radius).max(0.), distance + radius)
+}
+
+/// Share of the background in the color of a point at `distance`, rising
+/// linearly from 0 at `start` to `strength` at `end`
+pub fn attenuation(distance: f64, start: f64, end: f64, strength: f32) -> f32 {
+    if !distance.is_finite() {
+        return 0.;
+    }
+    let t = if end > start {
+        ((distance - start) / (end - start)).clamp(0., 1.)
+    } else if distance > start {
+        1.
+    } else {
+        0.
+    };
+    t as f32 * strength.clamp(0., 1.)
+}
+
+/// `color` mixed with `background` by `amount`, keeping its alpha
+pub fn attenuate(color: Color, background: Color, amount: f32) -> Color {
+    let [r, g, b, a] = color.as_rgba_f32();
+    let [br, bg, bb, _] = background.as_rgba_f32();
+    Color::rgba(
+        r + (br - r) * amount,
+        g + (bg - g) * amount,
+        b + (bb - b) * amount,
+        a,
+    )
+}
+
+/// Fog of the generated instances, seen from the camera
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DepthCue {
+    /// Camera in the data reference system
+    pub eye: DVec3,
+    /// Distance the fog starts at
+    pub start: f64,
+    /// Distance the fog reaches `strength` at
+    pub end: f64,
+    /// Largest share of the background, see [ViewerSettings::depth_cue]
+    pub strength: f32,
+}
+
+impl DepthCue {
+    /// Colors of the points of `pc` attenuated toward `background`
+    pub fn apply(&self, pc: &ArrowPointCloud, colors: &mut [Color], background: Color) {
+        for (p, color) in pc.points::<Point<f64, 3>>().zip(colors.iter_mut()) {
+            let distance = self.eye.distance(DVec3::from_slice(p.coords()));
+            let amount = attenuation(distance, self.start, self.end, self.strength);
+            *color = attenuate(*color, background, amount);
+        }
+    }
+}
+
+/// Fog the instances were generated with, `None` without fog
+#[derive(Resource, Default)]
+pub struct Fog(pub Option<DepthCue>);
+
+/// Background, fog and depth cueing strength for the overlay
+pub fn status(settings: &ViewerSettings, keys: &KeyBindings) -> String {
+    let fog = match settings.fog {
+        true => format!(
+            "depth cueing {:.0}% ({}/{})",
+            settings.depth_cue.clamp(0., 1.) * 100.,
+            keys.label(Action::DepthCueDown),
+            keys.label(Action::DepthCueUp),
+        ),
+        false => "off".to_string(),
+    };
+    format!(
+        "Background ({}): {}, fog ({}): {fog}",
+        keys.label(Action::Background),
+        settings.background,
+        keys.label(Action::Fog),
+    )
+}
+
+// Cycle the background, toggle the fog and change the depth cueing. The fog
+// follows the camera once it moved by a share of the data.
+pub fn fog_system(
+    (key_input, keys): (Res<Input<KeyCode>>, Res<KeyBindings>),
+    mut settings: ResMut<ViewerSettings>,
+    cache: Res<PointCache>,
+    sr: Res<SpatialReference>,
+    camera: Query<&GlobalTransform, With<PanOrbitCamera>>,
+    mut clear: ResMut<ClearColor>,
+    mut fog: ResMut<Fog>,
+) {
+    if keys.just_pressed(&key_input, Action::Background) {
+        settings.background = settings.background.next();
+    }
+    if keys.just_pressed(&key_input, Action::Fog) {
+        settings.fog = !settings.fog;
+    }
+    let down = keys.just_pressed(&key_input, Action::DepthCueDown);
+    if down || keys.just_pressed(&key_input, Action::DepthCueUp) {
+        let step = if down { -STEP } else { STEP };
+        settings.depth_cue = (settings.depth_cue + step).clamp(0., 1.);
+    }
+    if settings.is_changed() && clear.0 != settings.background.color() {
+        clear.0 = settings.background.color();
+    }
+
+    let cue = match (
+        settings.fog,
+        sr.origin,
+        camera.get_single(),
+        cache.get(&settings.collection),
+    ) {
+        (true, Some(origin), Ok(transform), Some(pc)) => {
+            let aabb: AABB<Point<f64, 3>> = pc.aabb();
+            let eye = world_to_data(origin, transform.translation());
+            let moved = fog.0.map_or(f64::INFINITY, |cue| cue.eye.distance(eye));
+            if moved <= REFRESH * sizing::size(&aabb).length() && !settings.is_changed() {
+                return;
+            }
+            let (start, end) = fog_range(eye, &aabb);
+            Some(DepthCue {
+                eye,
+                start,
+                end,
+                strength: settings.depth_cue,
+            })
+        }
+        _ => None,
+    };
+    // regenerates the instances
+    if fog.0 != cue {
+        fog.0 = cue;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn linear() {
+        assert_eq!(attenuation(5., 10., 20., 0.8), 0.);
+        assert_eq!(attenuation(15., 10., 20., 0.8), 0.4);
+        assert_eq!(attenuation(30., 10., 20., 0.8), 0.8);
+        // strengths above one do not overshoot the background
+        assert_eq!(attenuation(30., 10., 20., 2.), 1.);
+        // without extent
+        assert_eq!(attenuation(10., 10., 10., 1.), 0.);
+        assert_eq!(attenuation(11., 10., 10., 1.), 1.);
+        assert_eq!(attenuation(f64::NAN, 10., 20., 1.), 0.);
+
+        let color = attenuate(Color::rgba(1., 0., 0., 0.5), Color::BLACK, 0.25);
+        assert_eq!(color.as_rgba_f32(), [0.75, 0., 0., 0.5]);
+    }
+
+    #[test]
+    fn range() {
+        let aabb = AABB::from_corners(
+            Point::<f64, 3>::from_slice(&[0., 0., 0.]),
+            Point::from_slice(&[6., 0., 8.]),
+        );
+        // the bounds span 10 around (3, 0, 4)
+        let (start, end) = fog_range(DVec3::new(3., 20., 4.), &aabb);
+        assert_eq!((start, end), (15., 25.));
+        // from within
+        assert_eq!(fog_range(DVec3::new(3., 0., 4.), &aabb), (0., 5.));
+    }
+
+    #[test]
+    fn cue() {
+        let pc = ArrowPointCloud::from_iter(
+            (0..3).map(|i| Point::<f64, 3>::from_slice(&[i as f64 * 10., 0., 0.])),
+        )
+        .unwrap();
+        let cue = DepthCue {
+            eye: DVec3::ZERO,
+            start: 0.,
+            end: 20.,
+            strength: 1.,
+        };
+        let mut colors = vec![Color::WHITE; 3];
+        cue.apply(&pc, &mut colors, Color::BLACK);
+        let red: Vec<f32> = colors.iter().map(|c| c.r()).collect();
+        assert_eq!(red, [1., 0.5, 0.]);
+
+        assert_eq!(Background::Grey.next().next().next(), Background::Grey);
+        assert_eq!(Background::White.color(), Color::WHITE);
+    }
+}
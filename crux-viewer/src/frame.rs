@@ -0,0 +1,79 @@
+//! Conversion between the east-north-up axes of the data and the y-up axes of
+//! Bevy, where north points along -z.
+
+use bevy::math::{DVec3, Vec3};
+
+/// East-north-up vector to Bevy axes
+pub fn enu_to_bevy(v: DVec3) -> Vec3 {
+    Vec3::new(v.x as f32, v.z as f32, -v.y as f32)
+}
+
+/// Bevy vector to east-north-up axes
+pub fn bevy_to_enu(v: Vec3) -> DVec3 {
+    DVec3::new(v.x as f64, -v.z as f64, v.y as f64)
+}
+
+/// Extent of a box in east-north-up axes as a Bevy scale
+pub fn enu_size_to_bevy(size: DVec3) -> Vec3 {
+    enu_to_bevy(size).abs()
+}
+
+/// Data reference system to Bevy world coordinates
+pub fn data_to_world(origin: DVec3, p: DVec3) -> Vec3 {
+    // subtract in f64 before the cast to keep large coordinates precise
+    enu_to_bevy(p - origin)
+}
+
+/// Bevy world coordinates to the data reference system
+pub fn world_to_data(origin: DVec3, p: Vec3) -> DVec3 {
+    bevy_to_enu(p) + origin
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn axes() {
+        assert_eq!(enu_to_bevy(DVec3::X), Vec3::X);
+        assert_eq!(enu_to_bevy(DVec3::Y), Vec3::NEG_Z);
+        assert_eq!(enu_to_bevy(DVec3::Z), Vec3::Y);
+        assert_eq!(bevy_to_enu(Vec3::X), DVec3::X);
+        assert_eq!(bevy_to_enu(Vec3::Y), DVec3::Z);
+        assert_eq!(bevy_to_enu(Vec3::Z), DVec3::NEG_Y);
+
+        // both frames are right-handed
+        let (e, n, u) = (DVec3::X, DVec3::Y, DVec3::Z);
+        assert_eq!(enu_to_bevy(e).cross(enu_to_bevy(n)), enu_to_bevy(u));
+
+        assert_eq!(
+            enu_size_to_bevy(DVec3::new(4., 2., 1.)),
+            Vec3::new(4., 1., 2.)
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        for x in [-2.5, 0., 1., 3.25] {
+            for y in [-1., 0., 7.5] {
+                for z in [-4., 0., 0.5] {
+                    let v = DVec3::new(x, y, z);
+                    assert_eq!(bevy_to_enu(enu_to_bevy(v)), v);
+
+                    let w = Vec3::new(x as f32, y as f32, z as f32);
+                    assert_eq!(enu_to_bevy(bevy_to_enu(w)), w);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn swizzle() {
+        let origin = DVec3::new(100., 200., 10.);
+
+        // data point (101, 198, 13) is rendered at [1, 3, 2]
+        let p = world_to_data(origin, Vec3::new(1., 3., 2.));
+        assert_eq!(p, DVec3::new(101., 198., 13.));
+        assert_eq!(data_to_world(origin, p), Vec3::new(1., 3., 2.));
+    }
+}
@@ -0,0 +1,285 @@
+use std::time::Duration;
+
+use bevy::{math::DVec3, prelude::*};
+use bevy_panorbit_camera::PanOrbitCamera;
+
+use crux_format::{Point, PointCloudTrait, PointTrait, AABB};
+
+use crate::{
+    camera::{query_bounds, reset_camera},
+    frame::{data_to_world, enu_to_bevy},
+    headless::Headless,
+    keys::{Action, KeyBindings},
+    sizing::{self, POINT_SPACING},
+    PointCache, SpatialReference, ViewerSettings,
+};
+
+/// Duration of the transition to the framing of grown data
+const TRANSITION: Duration = Duration::from_millis(500);
+/// Share of the data footprint in view above which the camera is kept
+const IN_VIEW: f64 = 0.8;
+/// Smallest extent framed, relative to the largest one
+const MIN_EXTENT: f64 = 0.05;
+
+/// Extents of `aabb` along x, y and z, at least [MIN_EXTENT] of the largest
+/// one so that flat bounds are framed like thin ones, and [POINT_SPACING]
+/// along all axes without extent
+pub fn framed_size(aabb: &AABB<Point<f64, 3>>) -> DVec3 {
+    let size = sizing::size(aabb);
+    let largest = size.max_element();
+    if !largest.is_finite() || largest <= 0. {
+        return DVec3::splat(POINT_SPACING);
+    }
+    size.max(DVec3::splat(largest * MIN_EXTENT))
+}
+
+/// Camera targets looking at data from the south
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Framing {
+    pub focus: Vec3,
+    pub alpha: f32,
+    pub beta: f32,
+    pub radius: f32,
+}
+
+impl Framing {
+    /// Framing of the bounds `aabb` of data shifted to `origin`
+    pub fn new(origin: DVec3, aabb: &AABB<Point<f64, 3>>) -> Self {
+        let DVec3 {
+            x: dx,
+            y: dy,
+            z: dz,
+        } = framed_size(aabb);
+
+        // slightly south of and below the center
+        let extent = dy.max(dz);
+        let center = DVec3::from_slice(aabb.center().coords());
+        let focus = data_to_world(origin, center)
+            + enu_to_bevy(DVec3::new(0., -extent / 10., -extent / 10.));
+
+        Self {
+            focus,
+            alpha: 0.,
+            beta: 0.8,
+            radius: dx.max(dy).max(dz) as f32,
+        }
+    }
+
+    pub fn apply(&self, camera: &mut PanOrbitCamera) {
+        camera.target_focus = self.focus;
+        camera.target_alpha = self.alpha;
+        camera.target_beta = self.beta;
+        camera.target_radius = self.radius;
+    }
+}
+
+/// Share of the XY footprint of `aabb` within the view bounds `lower` to
+/// `upper`, in the data reference system
+pub fn share_in_view(lower: DVec3, upper: DVec3, aabb: &AABB<Point<f64, 3>>) -> f64 {
+    let (l, u) = (aabb.lower(), aabb.upper());
+    let overlap = |a: f64, b: f64, lower: f64, upper: f64| {
+        if b > a {
+            (b.min(upper) - a.max(lower)).max(0.) / (b - a)
+        } else {
+            // flat along this axis
+            f64::from(u8::from((lower..=upper).contains(&a)))
+        }
+    };
+
+    overlap(l.x(), u.x(), lower.x, upper.x) * overlap(l.y(), u.y(), lower.y, upper.y)
+}
+
+/// Transition of focus and radius of the camera
+struct Transition {
+    from: (Vec3, f32),
+    to: (Vec3, f32),
+    elapsed: Duration,
+}
+
+impl Transition {
+    /// Focus and radius after `elapsed`, eased in and out
+    fn at(&self, elapsed: Duration) -> (Vec3, f32) {
+        let t = (elapsed.as_secs_f32() / TRANSITION.as_secs_f32()).clamp(0., 1.);
+        let t = t * t * (3. - 2. * t);
+        (
+            self.from.0.lerp(self.to.0, t),
+            self.from.1 + (self.to.1 - self.from.1) * t,
+        )
+    }
+}
+
+/// Framing of the shown collection as loads arrive
+#[derive(Resource, Default)]
+pub struct AutoFrame {
+    /// Loads of the shown collection seen so far
+    generation: Option<usize>,
+    /// The first load was framed, or the camera pose of the last session restored
+    framed: bool,
+    transition: Option<Transition>,
+}
+
+// Press 'F' to toggle automatic framing: the first load is framed like 'R',
+// later loads are transitioned to if they mostly leave the view
+#[allow(clippy::too_many_arguments)]
+pub fn auto_frame_system(
+    time: Res<Time>,
+    key_input: Res<Input<KeyCode>>,
+    keys: Res<KeyBindings>,
+    mut settings: ResMut<ViewerSettings>,
+    cache: Res<PointCache>,
+    headless: Option<Res<Headless>>,
+    mut auto: ResMut<AutoFrame>,
+    mut sr: ResMut<SpatialReference>,
+    mut camera: Query<&mut PanOrbitCamera>,
+) {
+    // headless renders pose the camera themselves
+    let (None, Ok(mut camera)) = (headless, camera.get_single_mut()) else {
+        return;
+    };
+
+    if keys.just_pressed(&key_input, Action::ToggleAutoFrame) {
+        settings.auto_frame = !settings.auto_frame;
+    }
+
+    let generation = cache.generation.get(&settings.collection).copied();
+    let loaded = generation.is_some() && generation != auto.generation;
+    auto.generation = generation;
+
+    if !settings.auto_frame {
+        auto.transition = None;
+        return;
+    }
+
+    if let (true, Some(pc)) = (loaded, cache.data.get(&settings.collection)) {
+        let aabb: AABB<Point<f64, 3>> = pc.aabb();
+
+        if !std::mem::replace(&mut auto.framed, true) {
+            if settings.camera.is_none() {
+                reset_camera(&mut camera, &mut sr, &aabb);
+            }
+        } else if let Some(origin) = sr.origin {
+            let (lower, upper) = query_bounds(origin, camera.target_focus, camera.target_radius);
+            if share_in_view(lower, upper, &aabb) < IN_VIEW {
+                let to = Framing::new(origin, &aabb);
+                auto.transition = Some(Transition {
+                    from: (camera.target_focus, camera.target_radius),
+                    to: (to.focus, to.radius),
+                    elapsed: Duration::ZERO,
+                });
+            }
+        }
+    }
+
+    let Some(transition) = auto.transition.as_mut() else {
+        return;
+    };
+    // user input takes over
+    if (camera.target_focus, camera.target_radius) != transition.at(transition.elapsed) {
+        auto.transition = None;
+        return;
+    }
+
+    transition.elapsed += time.delta();
+    (camera.target_focus, camera.target_radius) = transition.at(transition.elapsed);
+    if transition.elapsed >= TRANSITION {
+        auto.transition = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn aabb(lower: [f64; 3], upper: [f64; 3]) -> AABB<Point<f64, 3>> {
+        AABB::from_corners(Point::from_slice(&lower), Point::from_slice(&upper))
+    }
+
+    #[test]
+    fn framing() {
+        let bounds = aabb([100., 200., 0.], [140., 220., 10.]);
+
+        // centered at the origin
+        let framing = Framing::new(DVec3::new(120., 210., 5.), &bounds);
+        assert_eq!(framing.focus, Vec3::new(0., -2., 2.));
+        assert_eq!(framing.radius, 40.);
+
+        // shifted by the offset of the origin
+        let framing = Framing::new(DVec3::new(110., 210., 5.), &bounds);
+        assert_eq!(framing.focus, Vec3::new(10., -2., 2.));
+    }
+
+    #[test]
+    fn degenerate() {
+        let framed = |lower, upper| {
+            let bounds = aabb(lower, upper);
+            let center = DVec3::from_slice(bounds.center().coords());
+            let framing = Framing::new(center, &bounds);
+            assert!(framing.focus.is_finite());
+            (framed_size(&bounds), framing.radius)
+        };
+
+        // a single point
+        let (size, radius) = framed([5., 5., 5.], [5., 5., 5.]);
+        assert_eq!(size, DVec3::ONE);
+        assert_eq!(radius, 1.);
+
+        // a scanline along x and a vertical one
+        let (size, radius) = framed([0., 0., 0.], [100., 0., 0.]);
+        assert_eq!(size, DVec3::new(100., 5., 5.));
+        assert_eq!(radius, 100.);
+        let (size, radius) = framed([0., 0., 0.], [0., 0., 30.]);
+        assert_eq!(size, DVec3::new(1.5, 1.5, 30.));
+        assert_eq!(radius, 30.);
+
+        // a planar cloud
+        let (size, radius) = framed([0., 0., 2.], [40., 20., 2.]);
+        assert_eq!(size, DVec3::new(40., 20., 2.));
+        assert_eq!(radius, 40.);
+
+        // empty bounds
+        assert_eq!(framed_size(&AABB::new_empty()), DVec3::ONE);
+    }
+
+    #[test]
+    fn in_view() {
+        let (lower, upper) = (DVec3::new(0., 0., 0.), DVec3::new(10., 10., 10.));
+
+        // refinements within the view
+        assert_eq!(
+            share_in_view(lower, upper, &aabb([2., 2., 0.], [8., 8., 1.])),
+            1.
+        );
+        // grown to the east
+        assert_eq!(
+            share_in_view(lower, upper, &aabb([0., 0., 0.], [20., 10., 1.])),
+            0.5
+        );
+        assert!(share_in_view(lower, upper, &aabb([0., 0., 0.], [11., 10., 1.])) > IN_VIEW);
+        // elsewhere
+        assert_eq!(
+            share_in_view(lower, upper, &aabb([20., 20., 0.], [30., 30., 1.])),
+            0.
+        );
+        // flat along x
+        assert_eq!(
+            share_in_view(lower, upper, &aabb([5., 0., 0.], [5., 20., 1.])),
+            0.5
+        );
+        assert_eq!(
+            share_in_view(lower, upper, &aabb([15., 0., 0.], [15., 5., 1.])),
+            0.
+        );
+    }
+
+    #[test]
+    fn transition() {
+        let transition = Transition {
+            from: (Vec3::ZERO, 10.),
+            to: (Vec3::X * 10., 30.),
+            elapsed: Duration::ZERO,
+        };
+        assert_eq!(transition.at(Duration::ZERO), (Vec3::ZERO, 10.));
+        assert_eq!(transition.at(TRANSITION / 2), (Vec3::X * 5., 20.));
+        assert_eq!(transition.at(TRANSITION * 2), (Vec3::X * 10., 30.));
+    }
+}
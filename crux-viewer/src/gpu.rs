@@ -0,0 +1,163 @@
+use bevy::prelude::*;
+use bevy_aabb_instancing::Cuboid;
+use wgpu_types::DeviceType;
+
+use crate::keys::{Action, KeyBindings};
+
+/// Bytes of one instance in the cuboids buffers
+pub const INSTANCE_SIZE: usize = std::mem::size_of::<Cuboid>();
+/// Bytes per MiB, the unit of the configured budget
+const MIB: usize = 1024 * 1024;
+/// Fraction of the budget degraded clouds are fitted to, so that small
+/// changes of the estimate do not degrade again
+const HEADROOM: f64 = 0.9;
+/// Points rendered per collection however small the budget
+pub const MIN_INSTANCES: usize = 10_000;
+
+/// Default GPU memory budget in MiB for the type of the adapter, a fraction
+/// of the memory usually available to it
+pub fn default_budget(device_type: DeviceType) -> usize {
+    match device_type {
+        DeviceType::DiscreteGpu => 4096,
+        DeviceType::IntegratedGpu | DeviceType::VirtualGpu => 1024,
+        DeviceType::Cpu | DeviceType::Other => 512,
+    }
+}
+
+/// Estimated GPU memory of the rendered instances and the cached textures
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct GpuEstimate {
+    /// Rendered instances of all collections
+    pub instances: usize,
+    /// Bytes of the cached textures
+    pub textures: usize,
+}
+
+impl GpuEstimate {
+    /// Estimated bytes in total
+    pub fn total(&self) -> usize {
+        self.instances * INSTANCE_SIZE + self.textures
+    }
+}
+
+/// Points to render per collection to fit `budget` bytes, `None` within the
+/// budget.
+///
+/// The largest collection renders `largest` points. Its points are reduced
+/// by the fraction of the instances fitting the budget next to the
+/// textures, at least to [MIN_INSTANCES]. Smaller collections may keep their
+/// points, so the estimate is only guaranteed to fit after repeated steps.
+pub fn degraded_limit(estimate: &GpuEstimate, budget: usize, largest: usize) -> Option<usize> {
+    if estimate.total() <= budget || estimate.instances == 0 {
+        return None;
+    }
+    let available = budget.saturating_sub(estimate.textures) as f64 * HEADROOM;
+    let fraction = available / (estimate.instances * INSTANCE_SIZE) as f64;
+    let limit = (largest as f64 * fraction) as usize;
+    Some(limit.max(MIN_INSTANCES).min(largest))
+}
+
+/// GPU memory estimate against the budget, see
+/// [ViewerSettings::gpu_budget](crate::ViewerSettings)
+#[derive(Resource, Debug, Default)]
+pub struct GpuMemory {
+    pub estimate: GpuEstimate,
+    /// Points rendered of the largest collection
+    pub largest: usize,
+    /// Budget in bytes, 0 until known
+    pub budget: usize,
+}
+
+impl GpuMemory {
+    /// Budget in bytes, configured in MiB or from the adapter type
+    pub fn set_budget(&mut self, configured: Option<usize>, device_type: Option<DeviceType>) {
+        let mib = configured.unwrap_or_else(|| {
+            device_type.map_or(default_budget(DeviceType::Other), default_budget)
+        });
+        self.budget = mib * MIB;
+    }
+
+    /// Points to render per collection to fit the budget, `None` within it
+    pub fn degrade(&self) -> Option<usize> {
+        if self.budget == 0 {
+            return None;
+        }
+        degraded_limit(&self.estimate, self.budget, self.largest)
+    }
+
+    /// Estimate against the budget with the point limit it caused, for
+    /// display
+    pub fn status(&self, limit: Option<usize>, keys: &KeyBindings) -> String {
+        let mut status = format!(
+            "GPU memory: ~{} / {} MiB",
+            self.estimate.total().div_ceil(MIB),
+            self.budget / MIB
+        );
+        if let Some(limit) = limit {
+            status.push_str(&format!(
+                ", degraded to {limit} points, shift + {} forces full",
+                keys.label(Action::LoadFull)
+            ));
+        }
+        status
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn degrade() {
+        assert_eq!(INSTANCE_SIZE, 32);
+        let estimate = GpuEstimate {
+            instances: 2_000_000,
+            textures: 4 * MIB,
+        };
+        assert_eq!(estimate.total(), 64_000_000 + 4 * MIB);
+
+        // within the budget
+        assert_eq!(degraded_limit(&estimate, 128 * MIB, 1_000_000), None);
+        assert_eq!(degraded_limit(&GpuEstimate::default(), 0, 0), None);
+
+        // two collections of 1M points into 36 MiB, 32 MiB next to the textures
+        let limit = degraded_limit(&estimate, 36 * MIB, 1_000_000).unwrap();
+        let fitted = GpuEstimate {
+            instances: 2 * limit,
+            ..estimate
+        };
+        assert!(fitted.total() <= 36 * MIB, "{limit}");
+        assert!(fitted.total() > 30 * MIB, "{limit}");
+        assert_eq!(degraded_limit(&fitted, 36 * MIB, limit), None);
+
+        // textures alone above the budget
+        assert_eq!(
+            degraded_limit(&estimate, 2 * MIB, 1_000_000),
+            Some(MIN_INSTANCES)
+        );
+        // never more points than rendered
+        let small = GpuEstimate {
+            instances: 5_000,
+            textures: 4 * MIB,
+        };
+        assert_eq!(degraded_limit(&small, 2 * MIB, 5_000), Some(5_000));
+
+        // budget from the settings or the adapter
+        let mut memory = GpuMemory {
+            estimate,
+            largest: 1_000_000,
+            budget: 0,
+        };
+        assert_eq!(memory.degrade(), None);
+        memory.set_budget(None, Some(DeviceType::IntegratedGpu));
+        assert_eq!(memory.budget, 1024 * MIB);
+        memory.set_budget(None, None);
+        assert_eq!(memory.budget, 512 * MIB);
+        memory.set_budget(Some(36), Some(DeviceType::DiscreteGpu));
+        assert_eq!(memory.degrade(), Some(limit));
+        let keys = KeyBindings::default();
+        assert!(memory
+            .status(None, &keys)
+            .starts_with("GPU memory: ~66 / 36 MiB"));
+    }
+}
@@ -0,0 +1,255 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use bevy::{
+    app::AppExit, prelude::*, render::view::screenshot::ScreenshotManager, window::PrimaryWindow,
+};
+use bevy_panorbit_camera::PanOrbitCamera;
+
+use crux_format::{png, prelude::*};
+
+use crate::{
+    camera::reset_camera,
+    classes::Classes,
+    fetch::points_url,
+    legend,
+    memory::{self, Level},
+    net::LoadTask,
+    normalize::ScaleBounds,
+    render::InstanceUpload,
+    settings::{CameraPose, SettingsArgs},
+    LoadRequest, PointCache, SpatialReference, ViewerSettings,
+};
+
+/// Size of rendered images in logical pixels
+pub const IMAGE_SIZE: (f32, f32) = (512., 512.);
+/// Query of the collection if no input is given
+const QUERY: &str = "p=0.1";
+/// Frames rendered after posing the camera before the capture
+const SETTLE_FRAMES: u32 = 5;
+
+/// Render a single image and exit, see `SettingsArgs::headless`
+#[derive(Resource)]
+pub struct Headless {
+    screenshot: PathBuf,
+    /// Write the legend of the colors next to the image
+    legend: bool,
+    pose: Option<CameraPose>,
+    state: State,
+    /// Outcome of the capture, set by the render world
+    saved: Arc<Mutex<Option<Result<(), String>>>>,
+}
+
+enum State {
+    Loading { started: bool },
+    Settling(u32),
+    Capturing,
+}
+
+impl Headless {
+    pub fn from_args(args: &SettingsArgs) -> Result<Self, String> {
+        let pose = args
+            .pose
+            .as_deref()
+            .map(|path| {
+                let content =
+                    std::fs::read_to_string(path).map_err(|e| format!("{path:?}: {e}"))?;
+                serde_json::from_str(&content).map_err(|e| format!("{path:?}: {e}"))
+            })
+            .transpose()?;
+
+        Ok(Self {
+            screenshot: args
+                .screenshot
+                .clone()
+                .unwrap_or_else(|| PathBuf::from("thumbnail.png")),
+            legend: args.legend,
+            pose,
+            state: State::Loading { started: false },
+            saved: Default::default(),
+        })
+    }
+}
+
+/// Window of the size of the rendered image
+pub fn window() -> Window {
+    Window {
+        title: "crux-viewer (headless)".to_string(),
+        resolution: IMAGE_SIZE.into(),
+        resizable: false,
+        decorations: false,
+        ..default()
+    }
+}
+
+/// Write a captured frame as PNG file
+pub fn write_png(image: Image, path: &Path) -> Result<(), String> {
+    let image = image
+        .try_into_dynamic()
+        .map_err(|e| e.to_string())?
+        .to_rgb8();
+    let png = png::encode_rgb(image.width(), image.height(), image.as_raw());
+    std::fs::write(path, png).map_err(|e| format!("{path:?}: {e}"))
+}
+
+/// Point cloud from an Arrow IPC stream or file
+pub fn read_input(path: &Path) -> Result<ArrowPointCloud, String> {
+    ArrowPointCloud::from_ipc_path(path).map_err(|e| format!("{path:?}: {e}"))
+}
+
+/// Queue the input and the camera pose before the scene is set up
+pub fn setup_headless(
+    args: Res<SettingsArgs>,
+    headless: Res<Headless>,
+    mut settings: ResMut<ViewerSettings>,
+    mut cache: ResMut<PointCache>,
+    mut requests: EventWriter<LoadRequest>,
+    mut gizmos: ResMut<GizmoConfig>,
+) {
+    settings.camera = headless.pose;
+    gizmos.enabled = false;
+
+    match args.input.as_deref() {
+        Some(url) if url.starts_with("http://") || url.starts_with("https://") => {
+            requests.send(LoadRequest::new(url));
+        }
+        Some(path) => match read_input(Path::new(path)) {
+            Ok(pc) => {
+                let collection = settings.collection.to_owned();
+                cache
+                    .memory
+                    .insert(&collection, Level::Points, memory::cloud_size(&pc));
+                cache.data.insert(collection, Arc::new(pc));
+            }
+            Err(e) => {
+                error!("Failed to read input: {e}");
+                std::process::exit(1);
+            }
+        },
+        // the scene is generated, see `demo::setup_demo`
+        None if args.demo => (),
+        None => requests.send(LoadRequest::new(points_url(&settings, QUERY))),
+    }
+}
+
+// Pose the camera once the points are uploaded, capture a frame and exit
+#[allow(clippy::too_many_arguments)]
+pub fn headless_system(
+    mut headless: ResMut<Headless>,
+    cache: Res<PointCache>,
+    settings: Res<ViewerSettings>,
+    upload: Res<InstanceUpload>,
+    tasks: Query<&LoadTask>,
+    window: Query<Entity, With<PrimaryWindow>>,
+    mut nodes: Query<&mut Visibility, With<Node>>,
+    mut camera: Query<&mut PanOrbitCamera>,
+    mut sr: ResMut<SpatialReference>,
+    scale: Res<ScaleBounds>,
+    classes: Res<Classes>,
+    mut screenshots: ResMut<ScreenshotManager>,
+    mut exit: EventWriter<AppExit>,
+) {
+    // no overlays in the image
+    for mut visibility in &mut nodes {
+        if *visibility != Visibility::Hidden {
+            *visibility = Visibility::Hidden;
+        }
+    }
+
+    let headless = headless.as_mut();
+    match &mut headless.state {
+        State::Loading { started } => {
+            let Some(pc) = cache.data.get(&settings.collection) else {
+                *started |= !tasks.is_empty();
+                if *started && tasks.is_empty() {
+                    error!("Failed to load `{}`", settings.collection);
+                    std::process::exit(1);
+                }
+                return;
+            };
+            if cache.is_changed() || !upload.pending.is_empty() || sr.origin.is_none() {
+                return;
+            }
+
+            let Ok(mut camera) = camera.get_single_mut() else {
+                return;
+            };
+            if headless.pose.is_none() {
+                let aabb: AABB<Point<f64, 3>> = pc.aabb();
+                reset_camera(&mut camera, &mut sr, &aabb);
+            }
+            // jump to the targets
+            camera.orbit_smoothness = 0.;
+            camera.pan_smoothness = 0.;
+            camera.zoom_smoothness = 0.;
+            camera.force_update = true;
+
+            headless.state = State::Settling(SETTLE_FRAMES);
+        }
+        State::Settling(0) => {
+            let Ok(window) = window.get_single() else {
+                return;
+            };
+            let (path, saved) = (headless.screenshot.clone(), headless.saved.clone());
+
+            let requested = screenshots.take_screenshot(window, move |image| {
+                *saved.lock().unwrap() = Some(write_png(image, &path));
+            });
+            if requested.is_ok() {
+                headless.state = State::Capturing;
+            }
+        }
+        State::Settling(frames) => *frames -= 1,
+        State::Capturing => match headless.saved.lock().unwrap().take() {
+            Some(Ok(())) => {
+                info!("Saved {:?}", headless.screenshot);
+                if headless.legend {
+                    let pc = cache.get(&settings.collection).expect("loaded points");
+                    if let Err(e) =
+                        legend::write_legend(&headless.screenshot, pc, &settings, &scale, &classes)
+                    {
+                        error!("Failed to save the legend: {e}");
+                        std::process::exit(1);
+                    }
+                }
+                exit.send(AppExit);
+            }
+            Some(Err(e)) => {
+                error!("Failed to save the image: {e}");
+                std::process::exit(1);
+            }
+            None => (),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn input() {
+        use arrow::ipc::writer::FileWriter;
+
+        let pc = ArrowPointCloud::from_iter(
+            (0..10).map(|i| Point::<f64, 3>::from_slice(&[i as f64, 0., 0.])),
+        )
+        .unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("points.arrow");
+        let mut writer =
+            FileWriter::try_new(std::fs::File::create(&path).unwrap(), &pc.schema()).unwrap();
+        for e in pc.store.iter() {
+            for batch in pc.store.batches(e.key()) {
+                writer.write(&batch).unwrap();
+            }
+        }
+        writer.finish().unwrap();
+
+        assert_eq!(read_input(&path).unwrap().num_points(), 10);
+        assert!(read_input(&dir.path().join("missing.arrow")).is_err());
+    }
+}
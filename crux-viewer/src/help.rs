@@ -0,0 +1,72 @@
+use bevy::prelude::*;
+
+use crate::keys::{Action, KeyBindings};
+
+/// Whether the list of controls is shown
+#[derive(Resource, Default)]
+pub struct Help {
+    pub open: bool,
+}
+
+#[derive(Component)]
+pub struct HelpText;
+
+pub fn setup_help(mut commands: Commands) {
+    commands.spawn((
+        TextBundle::from_section("", TextStyle::default()).with_style(Style {
+            position_type: PositionType::Absolute,
+            top: Val::Px(5.0),
+            right: Val::Px(15.0),
+            ..default()
+        }),
+        HelpText,
+    ));
+}
+
+/// Overlay text, the bindings while open and how to open them otherwise
+fn help_text(help: &Help, keys: &KeyBindings) -> String {
+    let key = keys.label(Action::Help);
+    if help.open {
+        format!("Controls ({key} closes)\n{}", keys.help())
+    } else {
+        format!("Help ({key})")
+    }
+}
+
+// Press 'H' to list the controls with their current keys
+pub fn help_system(
+    key_input: Res<Input<KeyCode>>,
+    keys: Res<KeyBindings>,
+    mut help: ResMut<Help>,
+    mut text: Query<&mut Text, With<HelpText>>,
+) {
+    let Ok(mut text) = text.get_single_mut() else {
+        return;
+    };
+
+    if keys.just_pressed(&key_input, Action::Help) {
+        help.open = !help.open;
+    }
+
+    if help.is_changed() || keys.is_changed() {
+        text.sections[0].value = help_text(&help, &keys);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn text() {
+        let mut keys = KeyBindings::default();
+        let mut help = Help::default();
+        assert_eq!(help_text(&help, &keys), "Help (H)");
+
+        help.open = true;
+        keys.bind(Action::LoadFull, KeyCode::F6);
+        let text = help_text(&help, &keys);
+        assert!(text.starts_with("Controls (H closes)\nF6 "));
+        assert!(text.contains("load all points"));
+    }
+}
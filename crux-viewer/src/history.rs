@@ -0,0 +1,212 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{fetch::collection_url, settings::CameraPose, ViewerSettings};
+
+/// Number of queried urls kept in the history, older ones are dropped
+pub const HISTORY_LIMIT: usize = 100;
+
+/// Points query independent of the server it was sent to
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct PointsQuery {
+    pub collection: String,
+    /// Bounds as sent, three or four dimensions per corner
+    pub bounds: Option<Vec<f64>>,
+    pub p: Option<f64>,
+    pub columns: Option<Vec<String>>,
+    /// Further parameters as `key=value`, e.g. the sampling
+    pub params: Vec<String>,
+}
+
+impl PointsQuery {
+    /// Query of a points url, `None` if it is not one
+    ///
+    /// The seed is dropped, it is added from the settings when rerun.
+    pub fn parse(url: &str) -> Option<Self> {
+        let (base, params) = url.split_once('?')?;
+        if !base.trim_end_matches('/').ends_with("/points") {
+            return None;
+        }
+
+        let mut query = PointsQuery::default();
+        let mut collection = None;
+        for param in params.split('&').filter(|param| !param.is_empty()) {
+            let (key, value) = param.split_once('=').unwrap_or((param, ""));
+            match key {
+                "collection" => collection = Some(value.to_owned()),
+                "bounds" => {
+                    let bounds = value
+                        .split(',')
+                        .map(str::parse)
+                        .collect::<Result<Vec<f64>, _>>()
+                        .ok()?;
+                    query.bounds = Some(bounds);
+                }
+                "p" => query.p = Some(value.parse().ok()?),
+                "columns" => {
+                    query.columns = Some(value.split(',').map(str::to_owned).collect());
+                }
+                "seed" => {}
+                _ => query.params.push(param.to_owned()),
+            }
+        }
+        query.collection = collection?;
+
+        Some(query)
+    }
+
+    /// Url of the query on the configured server
+    pub fn url(&self, settings: &ViewerSettings) -> String {
+        let mut params = Vec::new();
+        if let Some(bounds) = &self.bounds {
+            let bounds: Vec<String> = bounds.iter().map(f64::to_string).collect();
+            params.push(format!("bounds={}", bounds.join(",")));
+        }
+        if let Some(p) = self.p {
+            params.push(format!("p={p}"));
+        }
+        if let Some(columns) = &self.columns {
+            params.push(format!("columns={}", columns.join(",")));
+        }
+        params.extend(self.params.iter().cloned());
+
+        collection_url(settings, &self.collection, &params.join("&"))
+    }
+}
+
+/// Short description, e.g. `campus, p=0.01`
+impl fmt::Display for PointsQuery {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.collection)?;
+
+        // the importance range of the bounds queries of the viewer
+        let p = self.p.or_else(|| match self.bounds.as_deref() {
+            Some(bounds) if bounds.len() == 8 => Some(bounds[7]),
+            _ => None,
+        });
+        if let Some(p) = p {
+            write!(f, ", p={p}")?;
+        }
+        if let Some(bounds) = &self.bounds {
+            let (lower, upper) = bounds.split_at(bounds.len() / 2);
+            let corner = |c: &[f64]| {
+                c.iter()
+                    .take(3)
+                    .map(|v| format!("{v:.0}"))
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            };
+            write!(f, ", [{}] - [{}]", corner(lower), corner(upper))?;
+        }
+        if let Some(columns) = &self.columns {
+            write!(f, ", {}", columns.join(" "))?;
+        }
+        for param in &self.params {
+            write!(f, ", {param}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Named query with the camera pose at the time it was saved
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Bookmark {
+    pub name: String,
+    pub query: PointsQuery,
+    pub camera: Option<CameraPose>,
+}
+
+/// Remember a queried url, most recent first without duplicates
+pub fn record(history: &mut Vec<String>, url: &str) {
+    history.retain(|entry| entry != url);
+    history.insert(0, url.to_owned());
+    history.truncate(HISTORY_LIMIT);
+}
+
+/// Short description of a history entry, the url itself if it is no points
+/// query
+pub fn label(url: &str) -> String {
+    match PointsQuery::parse(url) {
+        Some(query) => query.to_string(),
+        None => url.to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cap() {
+        let mut history = Vec::new();
+        for i in 0..HISTORY_LIMIT + 20 {
+            record(
+                &mut history,
+                &format!("http://host/points?collection=c&p={i}"),
+            );
+        }
+        assert_eq!(history.len(), HISTORY_LIMIT);
+        assert!(history[0].ends_with(&format!("p={}", HISTORY_LIMIT + 19)));
+        assert!(history[HISTORY_LIMIT - 1].ends_with("p=20"));
+
+        // a rerun moves to the front
+        let rerun = history[10].clone();
+        record(&mut history, &rerun);
+        assert_eq!(history.len(), HISTORY_LIMIT);
+        assert_eq!(history[0], rerun);
+        assert_eq!(history.iter().filter(|url| **url == rerun).count(), 1);
+    }
+
+    #[test]
+    fn parse() {
+        let url = "http://0.0.0.0:3000/points?collection=campus&bounds=1,2,3,0,4,5,6,0.01\
+                   &columns=intensity,classification&sample=voxel:0.5&seed=3";
+        let query = PointsQuery::parse(url).unwrap();
+        assert_eq!(query.collection, "campus");
+        assert_eq!(query.bounds, Some(vec![1., 2., 3., 0., 4., 5., 6., 0.01]));
+        assert_eq!(query.p, None);
+        assert_eq!(
+            query.columns,
+            Some(vec!["intensity".to_string(), "classification".to_string()])
+        );
+        assert_eq!(query.params, ["sample=voxel:0.5"]);
+        assert_eq!(
+            query.to_string(),
+            "campus, p=0.01, [1 2 3] - [4 5 6], intensity classification, sample=voxel:0.5"
+        );
+
+        let query = PointsQuery::parse("http://host/points?collection=campus&p=0.01").unwrap();
+        assert_eq!(query.to_string(), "campus, p=0.01");
+
+        assert_eq!(PointsQuery::parse("http://host/collections"), None);
+        assert_eq!(PointsQuery::parse("http://host/points?p=0.1"), None);
+        assert_eq!(
+            PointsQuery::parse("http://host/points?collection=a&bounds=x"),
+            None
+        );
+        assert_eq!(label("http://host/collections"), "http://host/collections");
+    }
+
+    #[test]
+    fn rebuild() {
+        let query = PointsQuery::parse(
+            "http://old-host:3000/points?collection=campus&bounds=1.5,2,3,4,5,6&p=0.01&seed=3",
+        )
+        .unwrap();
+
+        // on the configured server with the configured seed
+        let settings = ViewerSettings {
+            server: "https://new-host/".to_string(),
+            collection: "other".to_string(),
+            seed: 7,
+            ..Default::default()
+        };
+        let url = query.url(&settings);
+        assert_eq!(
+            url,
+            "https://new-host/points?collection=campus&bounds=1.5,2,3,4,5,6&p=0.01&seed=7"
+        );
+        assert_eq!(PointsQuery::parse(&url), Some(query));
+    }
+}
@@ -0,0 +1,281 @@
+use bevy::{math::DVec3, prelude::*};
+use bevy_panorbit_camera::PanOrbitCamera;
+
+use crate::{
+    frame::{data_to_world, world_to_data},
+    history::{label, Bookmark, PointsQuery},
+    keys::{Action, KeyBindings},
+    settings::CameraPose,
+    views::{Pose, Views},
+    LoadRequest, SpatialReference, ViewerSettings,
+};
+
+/// Entries listed around the selected one
+const LISTED: usize = 10;
+
+/// List shown in the panel
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum List {
+    #[default]
+    History,
+    Bookmarks,
+}
+
+/// Recent queries and bookmarks, listed in the overlay while open
+#[derive(Resource, Default)]
+pub struct QueryPanel {
+    open: bool,
+    list: List,
+    selected: usize,
+    /// Name typed for a bookmark of the query
+    naming: Option<(String, PointsQuery)>,
+}
+
+impl QueryPanel {
+    /// Overlay lines, the entries around the selection while open
+    pub fn status(&self, settings: &ViewerSettings, keys: &KeyBindings) -> String {
+        let mut status = format!(
+            "History ({}): {} queries, {} bookmarks",
+            keys.label(Action::History),
+            settings.history.len(),
+            settings.bookmarks.len()
+        );
+        if !self.open {
+            return status;
+        }
+
+        if let Some((name, _)) = &self.naming {
+            status += &format!("\n  Bookmark name: {name}_ (Enter save, Esc cancel)");
+            return status;
+        }
+
+        let (title, entries): (_, Vec<String>) = match self.list {
+            List::History => (
+                "Recent queries (S bookmark, Del clear)",
+                settings.history.iter().map(|url| label(url)).collect(),
+            ),
+            List::Bookmarks => (
+                "Bookmarks (Del remove)",
+                settings
+                    .bookmarks
+                    .iter()
+                    .map(|bookmark| bookmark.name.to_owned())
+                    .collect(),
+            ),
+        };
+        status += &format!("\n  {title}, Left Right list, Up Down select, Enter run, Esc close");
+
+        let first = self.selected.saturating_sub(LISTED / 2);
+        for (i, entry) in entries.iter().enumerate().skip(first).take(LISTED) {
+            let marker = if i == self.selected { '>' } else { ' ' };
+            status += &format!("\n  {marker} {entry}");
+        }
+        if entries.is_empty() {
+            status += "\n    (empty)";
+        }
+        status
+    }
+
+    fn len(&self, settings: &ViewerSettings) -> usize {
+        match self.list {
+            List::History => settings.history.len(),
+            List::Bookmarks => settings.bookmarks.len(),
+        }
+    }
+}
+
+/// Camera at `pose`, moved to the world origin `sr` uses
+pub(crate) fn restore(pose: &CameraPose, camera: &mut PanOrbitCamera, sr: &mut SpatialReference) {
+    let saved = DVec3::from(pose.origin);
+    let origin = *sr.origin.get_or_insert(saved);
+    let focus = world_to_data(saved, Vec3::from(pose.focus));
+
+    Pose {
+        focus: data_to_world(origin, focus),
+        alpha: pose.alpha,
+        beta: pose.beta,
+        radius: pose.radius,
+    }
+    .apply(camera);
+}
+
+// Press 'Q' to list the recent queries and bookmarks. While the panel is open
+// it takes the keyboard: the arrow keys switch lists and select, 'Enter' runs
+// the selected query, 'S' names a bookmark of it and 'Delete' clears the
+// history or removes the selected bookmark.
+#[allow(clippy::too_many_arguments)]
+pub fn history_panel_system(
+    mut key_input: ResMut<Input<KeyCode>>,
+    keys: Res<KeyBindings>,
+    mut chars: EventReader<ReceivedCharacter>,
+    mut panel: ResMut<QueryPanel>,
+    mut settings: ResMut<ViewerSettings>,
+    mut requests: EventWriter<LoadRequest>,
+    mut sr: ResMut<SpatialReference>,
+    views: Res<Views>,
+    mut camera: Query<&mut PanOrbitCamera>,
+) {
+    let typed: String = chars
+        .read()
+        .map(|c| c.char)
+        .filter(|c| !c.is_control())
+        .collect();
+
+    if !panel.open {
+        if keys.just_pressed(&key_input, Action::History) {
+            panel.open = true;
+            key_input.reset_all();
+        }
+        return;
+    }
+
+    // other controls ignore the keys meant for the panel
+    let pressed = |key| key_input.just_pressed(key);
+
+    if let Some((mut name, query)) = panel.naming.take() {
+        name.push_str(&typed);
+        if pressed(KeyCode::Back) {
+            name.pop();
+        }
+        if pressed(KeyCode::Return) && !name.trim().is_empty() {
+            let camera = match (sr.origin, camera.get_single()) {
+                (Some(origin), Ok(camera)) => Some(views.camera_pose(camera, origin)),
+                _ => None,
+            };
+            settings.bookmarks.push(Bookmark {
+                name: name.trim().to_owned(),
+                query,
+                camera,
+            });
+            panel.list = List::Bookmarks;
+            panel.selected = settings.bookmarks.len() - 1;
+        } else if !pressed(KeyCode::Escape) {
+            panel.naming = Some((name, query));
+        }
+        key_input.reset_all();
+        return;
+    }
+
+    if pressed(KeyCode::Escape) || pressed(keys.key(Action::History)) {
+        panel.open = false;
+    }
+    if pressed(KeyCode::Left) || pressed(KeyCode::Right) {
+        panel.list = match panel.list {
+            List::History => List::Bookmarks,
+            List::Bookmarks => List::History,
+        };
+        panel.selected = 0;
+    }
+    if pressed(KeyCode::Up) {
+        panel.selected = panel.selected.saturating_sub(1);
+    }
+    if pressed(KeyCode::Down) {
+        panel.selected += 1;
+    }
+    panel.selected = panel.selected.min(panel.len(&settings).saturating_sub(1));
+
+    let selected = panel.selected;
+    match panel.list {
+        List::History if selected < settings.history.len() => {
+            let url = settings.history[selected].to_owned();
+            if pressed(KeyCode::Return) {
+                // loads are stored as the configured collection
+                if let Some(query) = PointsQuery::parse(&url) {
+                    if query.collection != settings.collection {
+                        settings.collection = query.collection;
+                    }
+                }
+                requests.send(LoadRequest::new(url));
+            } else if pressed(KeyCode::S) {
+                if let Some(query) = PointsQuery::parse(&url) {
+                    panel.naming = Some((query.to_string(), query));
+                }
+            } else if pressed(KeyCode::Delete) {
+                settings.history.clear();
+            }
+        }
+        List::Bookmarks if selected < settings.bookmarks.len() => {
+            if pressed(KeyCode::Return) {
+                let bookmark = settings.bookmarks[selected].clone();
+                if bookmark.query.collection != settings.collection {
+                    settings.collection = bookmark.query.collection.to_owned();
+                }
+                requests.send(LoadRequest::new(bookmark.query.url(&settings)));
+                if let (Some(pose), Ok(mut camera)) = (bookmark.camera, camera.get_single_mut()) {
+                    restore(&pose, &mut camera, &mut sr);
+                }
+            } else if pressed(KeyCode::Delete) {
+                settings.bookmarks.remove(selected);
+            }
+        }
+        _ => {}
+    }
+
+    key_input.reset_all();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn status() {
+        let mut settings = ViewerSettings {
+            history: vec![
+                "http://host/points?collection=campus&p=0.01".to_string(),
+                "http://host/collections".to_string(),
+            ],
+            ..default()
+        };
+        let mut panel = QueryPanel::default();
+        let keys = KeyBindings::default();
+        assert_eq!(
+            panel.status(&settings, &keys),
+            "History (Q): 2 queries, 0 bookmarks"
+        );
+
+        panel.open = true;
+        panel.selected = 1;
+        let status = panel.status(&settings, &keys);
+        assert!(status.contains("\n    campus, p=0.01\n  > http://host/collections"));
+
+        panel.list = List::Bookmarks;
+        assert!(panel.status(&settings, &keys).ends_with("(empty)"));
+
+        settings.bookmarks.push(Bookmark {
+            name: "campus north".to_string(),
+            query: PointsQuery::parse(&settings.history[0]).unwrap(),
+            camera: None,
+        });
+        panel.selected = 0;
+        assert!(panel.status(&settings, &keys).ends_with("> campus north"));
+    }
+
+    #[test]
+    fn restore_pose() {
+        let pose = CameraPose {
+            origin: [100., 200., 10.],
+            focus: [1., 2., -3.],
+            alpha: 0.5,
+            beta: 0.3,
+            radius: 50.,
+        };
+
+        // same data point in focus around another origin
+        let mut sr = SpatialReference {
+            origin: Some(DVec3::new(90., 200., 10.)),
+            ..default()
+        };
+        let mut camera = PanOrbitCamera::default();
+        restore(&pose, &mut camera, &mut sr);
+        assert_eq!(camera.focus, Vec3::new(11., 2., -3.));
+        assert_eq!(camera.target_radius, 50.);
+        assert_eq!(camera.alpha, Some(0.5));
+
+        // the pose's origin if none is set yet
+        let mut sr = SpatialReference::default();
+        restore(&pose, &mut camera, &mut sr);
+        assert_eq!(sr.origin, Some(DVec3::from(pose.origin)));
+        assert_eq!(camera.focus, Vec3::new(1., 2., -3.));
+    }
+}
@@ -0,0 +1,559 @@
+use std::time::Duration;
+
+use bevy::{
+    diagnostic::{DiagnosticsStore, FrameTimeDiagnosticsPlugin},
+    prelude::*,
+};
+use bevy_panorbit_camera::PanOrbitCamera;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    bounds::{BoundsGizmos, BoundsMode},
+    camera,
+    compare::Compare,
+    fetch::LoadStats,
+    fog,
+    gpu::GpuMemory,
+    history_panel::QueryPanel,
+    instances::{InstanceLimit, SkippedPoints},
+    keys::{Action, KeyBindings},
+    layers::Layers,
+    memory::MIB,
+    net::LoadTask,
+    normalize::ScaleBounds,
+    returns::{self, RETURNS_ATTRIBUTE},
+    schedule::LoadQueue,
+    session::SessionReplay,
+    stereo,
+    thinning::ThinningPreview,
+    trajectory::Trajectory,
+    units::{self, Units},
+    vector_overlay::VectorOverlay,
+    views::Views,
+    PointCache, SpatialReference, ViewerSettings,
+};
+
+/// Lines of the overlay text
+#[derive(Serialize, Deserialize, clap::ValueEnum, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+#[value(rename_all = "snake_case")]
+pub enum HudVerbosity {
+    /// Frame rate, instances, GPU memory, the last query and the loads
+    Compact,
+    /// Also the camera, the controls and the status of the tools
+    #[default]
+    Full,
+}
+
+impl HudVerbosity {
+    pub fn next(self) -> Self {
+        match self {
+            Self::Compact => Self::Full,
+            Self::Full => Self::Compact,
+        }
+    }
+}
+
+/// Completed load shown by the overlay
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueryStats {
+    pub url: String,
+    /// Time from sending the request to the decoded points
+    pub latency: Duration,
+    /// Returned points, `None` if the cached points were unchanged
+    pub points: Option<usize>,
+    /// Reduction on decode, if points were dropped
+    pub thinned: Option<LoadStats>,
+}
+
+/// Visibility of the overlay and the numbers recorded for it by the systems
+#[derive(Resource, Debug, Default)]
+pub struct Hud {
+    pub hidden: bool,
+    /// Rendered instances per collection
+    pub instances: Vec<(String, usize)>,
+    pub last_query: Option<QueryStats>,
+}
+
+/// Camera pose in world and data coordinates
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CameraState {
+    pub focus: [f32; 3],
+    pub alpha: f32,
+    pub beta: f32,
+    pub radius: f32,
+    /// Focus in the data reference system
+    pub focus_srs: [f64; 3],
+    /// Data coordinates of the world origin, once known
+    pub origin: Option<[f64; 3]>,
+}
+
+/// Everything the overlay shows, gathered from the resources each frame
+#[derive(Debug, Default)]
+pub struct HudState {
+    pub verbosity: HudVerbosity,
+    /// Smoothed frames per second and frame time in milliseconds
+    pub fps: Option<f64>,
+    pub frame_time: Option<f64>,
+    pub instances: Vec<(String, usize)>,
+    /// Estimated GPU memory of the buffers against the budget, see
+    /// [GpuMemory::status](crate::gpu::GpuMemory::status)
+    pub gpu: String,
+    pub last_query: Option<QueryStats>,
+    /// Loads in flight, queued and the maximum in flight
+    pub loads: (usize, usize, usize),
+    /// Shown in full only
+    pub camera: Option<CameraState>,
+    /// Unit of the camera radius
+    pub units: Units,
+    /// Shown first, also in compact, e.g. collections in different units
+    pub warnings: Vec<String>,
+    pub controls: Vec<String>,
+    pub statuses: Vec<String>,
+}
+
+/// Lines of the overlay text, the numbers first and all else in full
+pub fn format_lines(state: &HudState) -> Vec<String> {
+    let mut lines: Vec<String> = state
+        .warnings
+        .iter()
+        .map(|warning| format!("WARNING: {warning}"))
+        .collect();
+
+    lines.push(match (state.fps, state.frame_time) {
+        (Some(fps), Some(ms)) => format!("FPS: {fps:.0} ({ms:.1} ms)"),
+        (Some(fps), None) => format!("FPS: {fps:.0}"),
+        _ => "FPS: -".to_string(),
+    });
+    let instances: Vec<String> = state
+        .instances
+        .iter()
+        .map(|(collection, count)| format!("{collection} {count}"))
+        .collect();
+    lines.push(match instances.is_empty() {
+        true => "Instances: none".to_string(),
+        false => format!("Instances: {}", instances.join(", ")),
+    });
+    if !state.gpu.is_empty() {
+        lines.push(state.gpu.to_owned());
+    }
+    if let Some(query) = &state.last_query {
+        let points = match (query.points, &query.thinned) {
+            (Some(points), Some(thinned)) => format!("{points} points, {}", thinned.status()),
+            (Some(points), None) => format!("{points} points"),
+            (None, _) => "unchanged".to_string(),
+        };
+        lines.push(format!(
+            "Last query: {} ms, {points}\n  {}",
+            query.latency.as_millis(),
+            query.url
+        ));
+    }
+    let (in_flight, queued, max) = state.loads;
+    lines.push(format!(
+        "Loads: {in_flight} in flight, {queued} queued (max {max})"
+    ));
+
+    if state.verbosity == HudVerbosity::Compact {
+        return lines;
+    }
+    if let Some(camera) = &state.camera {
+        let [x, y, z] = camera.focus;
+        let [sx, sy, sz] = camera.focus_srs;
+        let [ox, oy, oz] = camera.origin.unwrap_or([f64::NAN; 3]);
+        lines.extend([
+            "Camera parameters".to_string(),
+            format!("Focus: [{x:.3}, {y:.3}, {z:.3}]"),
+            format!("Alpha: {:.3}", camera.alpha),
+            format!("Beta: {:.3}", camera.beta),
+            format!("Radius: {}", state.units.format(camera.radius as f64)),
+            format!("Focus in SRS: [{sx:.3}, {sy:.3}, {sz:.3}]"),
+            format!("Data Origin: [{ox:.3}, {oy:.3}, {oz:.3}]"),
+        ]);
+    }
+    lines.extend(state.controls.iter().cloned());
+    lines.extend(state.statuses.iter().cloned());
+    lines
+}
+
+/// Lines of the toggles, point size, stretch and gamma in the settings
+pub fn control_lines(
+    settings: &ViewerSettings,
+    keys: &KeyBindings,
+    scale: &ScaleBounds,
+    units: &Units,
+) -> Vec<String> {
+    let on_off = |on: bool| if on { "on" } else { "off" };
+    let stretch = format!(
+        "{} {}",
+        keys.label(Action::StretchDown),
+        keys.label(Action::StretchUp)
+    );
+    let (lower, upper) = (settings.normalization.lower, settings.normalization.upper);
+    vec![
+        format!(
+            "Auto LOD ({}): {}",
+            keys.label(Action::ToggleAutoLod),
+            on_off(settings.auto_lod)
+        ),
+        format!(
+            "Auto frame ({}): {}",
+            keys.label(Action::ToggleAutoFrame),
+            on_off(settings.auto_frame)
+        ),
+        match settings.point_extent {
+            Some(extent) => format!(
+                "{}, {} edges",
+                settings.point_sizing.status(keys),
+                units.format(units.to_data(extent))
+            ),
+            None => settings.point_sizing.status(keys),
+        },
+        match &scale.0 {
+            Some((attribute, min, max)) => format!(
+                "Stretch {attribute} ({stretch}, shift): p{lower} - p{upper} = [{min:.3}, {max:.3}]"
+            ),
+            None => format!("Stretch ({stretch}, shift): p{lower} - p{upper}"),
+        },
+        format!(
+            "Gamma ({} {}): {:.2}",
+            keys.label(Action::GammaDown),
+            keys.label(Action::GammaUp),
+            settings.normalization.gamma
+        ),
+    ]
+}
+
+/// Time the overlay notes a discarded stale response
+const STALE_NOTICE: Duration = Duration::from_secs(5);
+/// Time the overlay shows why a load failed
+const FAILURE_NOTICE: Duration = Duration::from_secs(10);
+
+/// Overlay text with the frame rate, the loads, the camera, the controls and
+/// the status of the tools
+pub struct HudPlugin;
+
+impl Plugin for HudPlugin {
+    fn build(&self, app: &mut App) {
+        if !app.is_plugin_added::<FrameTimeDiagnosticsPlugin>() {
+            app.add_plugins(FrameTimeDiagnosticsPlugin);
+        }
+        app.init_resource::<Hud>()
+            .add_systems(Startup, setup_hud)
+            .add_systems(Update, hud_system.before(camera::camera_controls_system));
+    }
+}
+
+/// Text of the overlay
+#[derive(Component)]
+pub struct DebugText;
+
+pub fn setup_hud(mut commands: Commands) {
+    commands.spawn((
+        TextBundle::from_sections([
+            TextSection::new("Debug text!", TextStyle::default()),
+            TextSection::default(),
+        ])
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            top: Val::Px(5.0),
+            left: Val::Px(15.0),
+            ..default()
+        }),
+        DebugText,
+    ));
+}
+
+// Press F10 to toggle the overlay
+#[allow(clippy::too_many_arguments)]
+pub fn hud_system(
+    (key_input, keys): (Res<Input<KeyCode>>, Res<KeyBindings>),
+    camera: Query<&PanOrbitCamera>,
+    mut query: Query<(&mut Text, &mut Visibility), With<DebugText>>,
+    cache: Res<PointCache>,
+    mut settings: ResMut<ViewerSettings>,
+    (scale, skipped, limit, replay): (
+        Res<ScaleBounds>,
+        Res<SkippedPoints>,
+        Res<InstanceLimit>,
+        Option<Res<SessionReplay>>,
+    ),
+    (bounds, gpu): (Res<BoundsGizmos>, Res<GpuMemory>),
+    (trajectory, vector, preview): (Res<Trajectory>, Res<VectorOverlay>, Res<ThinningPreview>),
+    (compare, mut hud, diagnostics): (Res<Compare>, ResMut<Hud>, Res<DiagnosticsStore>),
+    views: Res<Views>,
+    layers: Res<Layers>,
+    panel: Res<QueryPanel>,
+    loads: Query<&LoadTask>,
+    queue: Res<LoadQueue>,
+    sr: Res<SpatialReference>,
+    mut warned: Local<Option<String>>,
+) {
+    let camera = camera.get_single().unwrap();
+
+    // overlay, with shift compact or full
+    if keys.just_pressed(&key_input, Action::Hud) {
+        if key_input.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]) {
+            settings.hud = settings.hud.next();
+            hud.hidden = false;
+        } else {
+            hud.hidden = !hud.hidden;
+        }
+    }
+    let (mut text, mut visibility) = query.get_single_mut().unwrap();
+    let hidden = match hud.hidden {
+        true => Visibility::Hidden,
+        false => Visibility::Inherited,
+    };
+    if *visibility != hidden {
+        *visibility = hidden;
+    }
+
+    let smoothed = |diagnostic| {
+        diagnostics
+            .get(diagnostic)
+            .and_then(|diagnostic| diagnostic.smoothed())
+    };
+    let mut statuses = vec![match bounds.mode {
+        BoundsMode::Batches => {
+            let (drawn, total) = bounds.batch_counts();
+            format!(
+                "Bounds ({}): batches ({drawn} of {total} drawn)",
+                keys.label(Action::CycleBounds)
+            )
+        }
+        mode => format!("Bounds ({}): {mode}", keys.label(Action::CycleBounds)),
+    }];
+    statuses.extend(
+        [
+            replay.map(|replay| replay.status()),
+            limit.status(&keys),
+            preview.status(&keys),
+            Some(views.status(&keys)),
+            Some(stereo::status(
+                &settings,
+                &keys,
+                camera.radius.unwrap_or_default(),
+            )),
+            Some(layers.status(&settings, &keys)),
+            Some(fog::status(&settings, &keys)),
+            trajectory.status(&keys),
+            vector.status(&settings, &cache),
+            compare.status(&settings, &keys),
+            Some(panel.status(&settings, &keys)),
+        ]
+        .into_iter()
+        .flatten(),
+    );
+    for task in &loads {
+        if let Some(status) = task.state.status() {
+            statuses.push(format!("Load `{}`: {status}", task.collection));
+        }
+    }
+    if skipped.0 > 0 {
+        statuses.push(format!("Skipped points: {} non-finite", skipped.0));
+    }
+    if let Some((collection, at)) = &cache.discarded {
+        if at.elapsed() < STALE_NOTICE {
+            statuses.push(format!("Load `{collection}`: discarded stale response"));
+        }
+    }
+    if let Some((collection, error, at)) = &cache.failed {
+        if at.elapsed() < FAILURE_NOTICE {
+            statuses.push(format!("Load `{collection}` failed: {error}"));
+        }
+    }
+
+    // lengths of collections in different units are not comparable
+    let units = Units::of(&settings, &cache);
+    let mut shown = vec![settings.collection.as_str()];
+    shown.extend(settings.compare.as_deref());
+    let mixed = units::mixed(&cache, &shown);
+    if *warned != mixed {
+        if let Some(mixed) = &mixed {
+            warn!("{mixed}");
+        }
+        *warned = mixed.clone();
+    }
+
+    let mut controls = control_lines(&settings, &keys, &scale, &units);
+    controls.push(format!(
+        "Memory: {} / {} MiB",
+        cache.memory.total() / MIB,
+        settings.memory_budget
+    ));
+    let state = HudState {
+        verbosity: settings.hud,
+        fps: smoothed(FrameTimeDiagnosticsPlugin::FPS),
+        frame_time: smoothed(FrameTimeDiagnosticsPlugin::FRAME_TIME),
+        instances: hud.instances.clone(),
+        gpu: gpu.status(limit.gpu_limit, &keys),
+        last_query: hud.last_query.clone(),
+        loads: (loads.iter().count(), queue.len(), settings.max_loads),
+        camera: Some(CameraState {
+            focus: camera.focus.to_array(),
+            alpha: camera.alpha.unwrap_or_default(),
+            beta: camera.beta.unwrap_or_default(),
+            radius: camera.radius.unwrap_or_default(),
+            focus_srs: sr.camera.to_array(),
+            origin: sr.origin.map(|origin| origin.to_array()),
+        }),
+        units,
+        warnings: mixed.into_iter().collect(),
+        controls,
+        statuses,
+    };
+    text.sections[0].value = format_lines(&state).join("\n");
+
+    // returns options, greyed out if unavailable
+    let available = cache
+        .get(&settings.collection)
+        .is_some_and(returns::has_returns);
+    text.sections[1].value = format!(
+        "\nColor by returns ({}): {}\nReturns ({}): {}",
+        keys.label(Action::ColorByReturns),
+        if settings.color_attribute == RETURNS_ATTRIBUTE {
+            "on"
+        } else {
+            "off"
+        },
+        keys.label(Action::CycleReturns),
+        settings.returns_filter
+    );
+    text.sections[1].style.color = if available {
+        Color::WHITE
+    } else {
+        Color::DARK_GRAY
+    };
+    if settings.hud == HudVerbosity::Compact {
+        text.sections[1].value.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crux_format::LengthUnit;
+
+    use super::*;
+
+    fn state() -> HudState {
+        HudState {
+            fps: Some(59.6),
+            frame_time: Some(16.78),
+            instances: vec![("ahn".to_string(), 1000), ("ahn2".to_string(), 20)],
+            gpu: "GPU memory: ~4 / 1024 MiB".to_string(),
+            last_query: Some(QueryStats {
+                url: "http://0.0.0.0:3000/points?collection=ahn&p=0.01".to_string(),
+                latency: Duration::from_millis(125),
+                points: Some(1020),
+                thinned: None,
+            }),
+            loads: (1, 2, 4),
+            camera: Some(CameraState {
+                focus: [1., 2., 3.],
+                alpha: 0.5,
+                beta: 0.25,
+                radius: 10.,
+                focus_srs: [101., 202., 13.],
+                origin: None,
+            }),
+            controls: vec!["Auto LOD (L): off".to_string()],
+            statuses: vec!["Stereo (S): off".to_string()],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn compact() {
+        let mut state = state();
+        state.verbosity = HudVerbosity::Compact;
+        assert_eq!(
+            format_lines(&state),
+            [
+                "FPS: 60 (16.8 ms)",
+                "Instances: ahn 1000, ahn2 20",
+                "GPU memory: ~4 / 1024 MiB",
+                "Last query: 125 ms, 1020 points\n  http://0.0.0.0:3000/points?collection=ahn&p=0.01",
+                "Loads: 1 in flight, 2 queued (max 4)",
+            ]
+        );
+
+        // before the first frame and query
+        let state = HudState::default();
+        assert_eq!(
+            format_lines(&HudState {
+                verbosity: HudVerbosity::Compact,
+                ..state
+            }),
+            [
+                "FPS: -",
+                "Instances: none",
+                "Loads: 0 in flight, 0 queued (max 0)",
+            ]
+        );
+    }
+
+    #[test]
+    fn full() {
+        let mut state = state();
+        state.last_query.as_mut().unwrap().points = None;
+        let lines = format_lines(&state);
+
+        assert!(lines[3].starts_with("Last query: 125 ms, unchanged\n"));
+
+        state.last_query = Some(QueryStats {
+            points: Some(250),
+            thinned: Some(LoadStats {
+                batches: 4,
+                decoded: 1000,
+                kept: 250,
+                bytes: 6000,
+                limited: 1,
+            }),
+            ..state.last_query.unwrap()
+        });
+        assert!(format_lines(&state)[3].starts_with(
+            "Last query: 125 ms, 250 points, thinned on decode to 25.0% of 1000, \
+             1 of 4 batches by the budget\n"
+        ));
+        assert_eq!(lines[5], "Camera parameters");
+        assert_eq!(lines[6], "Focus: [1.000, 2.000, 3.000]");
+        assert_eq!(lines[9], "Radius: 10.00");
+        assert_eq!(lines[11], "Data Origin: [NaN, NaN, NaN]");
+        assert_eq!(lines[12..], ["Auto LOD (L): off", "Stereo (S): off"]);
+
+        assert_eq!(HudVerbosity::default().next(), HudVerbosity::Compact);
+
+        // in the display unit, with warnings first also in compact
+        state.units = Units::new(Some(LengthUnit::Metre), Some(LengthUnit::Foot));
+        state.warnings = vec!["Units differ: `ahn` in m, `ahn2` in ft".to_string()];
+        let lines = format_lines(&state);
+        assert_eq!(lines[0], "WARNING: Units differ: `ahn` in m, `ahn2` in ft");
+        assert_eq!(lines[10], "Radius: 32.81 ft");
+        state.verbosity = HudVerbosity::Compact;
+        assert!(format_lines(&state)[0].starts_with("WARNING: "));
+    }
+
+    #[test]
+    fn controls() {
+        let settings = ViewerSettings::default();
+        let keys = KeyBindings::default();
+        let scale = ScaleBounds(Some(("z".to_string(), -1., 2.5)));
+
+        let units = Units::new(Some(LengthUnit::Foot), None);
+
+        let lines = control_lines(&settings, &keys, &scale, &units);
+        assert_eq!(lines[0], "Auto LOD (L): off");
+        assert_eq!(lines[1], "Auto frame (F): on");
+        assert_eq!(lines[2], "Point size (Z): uniform");
+        assert!(lines[3].starts_with("Stretch z ([ ], shift): p"));
+        assert!(lines[3].ends_with(" = [-1.000, 2.500]"));
+
+        let settings = ViewerSettings {
+            point_extent: Some(0.25),
+            ..settings
+        };
+        let lines = control_lines(&settings, &keys, &scale, &units);
+        assert_eq!(lines[2], "Point size (Z): uniform, 0.2500 ft edges");
+    }
+}
@@ -0,0 +1,478 @@
+use bevy::{math::DVec3, prelude::*, utils::tracing::field};
+use bevy_aabb_instancing::Cuboid;
+
+use crux_format::{
+    color::ColorMap,
+    prelude::*,
+    query::{Query, Sample},
+    schema,
+};
+
+use crate::{
+    fog::DepthCue,
+    frame::data_to_world,
+    keys::{Action, KeyBindings},
+    layers::{self, COLLECTION_ATTRIBUTE},
+    normalize::{self, ScaleBounds, NO_DATA_COLOR},
+    returns::{self, RETURNS_ATTRIBUTE},
+    settings,
+    sizing::{self, DensityGrid, Extents},
+    units::Units,
+    ViewerSettings,
+};
+
+/// Maximum number of instances per cuboids entity
+pub const CHUNK_SIZE: usize = 1024 * 1024;
+const DELTA_ATTRIBUTE: &str = crux_format::diff::DELTA_COLUMN;
+/// Default color attribute, the height of the points
+pub const HEIGHT_ATTRIBUTE: &str = "z";
+/// Default number of points rendered per collection, integrated graphics
+/// struggle with more
+pub const MAX_INSTANCES: usize = 5_000_000;
+
+/// Points not rendered for non-finite coordinates, for display
+#[derive(Resource, Default)]
+pub struct SkippedPoints(pub usize);
+
+/// Fraction of `num_points` sampled to render at most `max_instances`,
+/// `None` to render all points, within the threshold or if `force_full`. A
+/// threshold of 0 renders all points.
+pub fn downsample_fraction(
+    num_points: usize,
+    max_instances: usize,
+    force_full: bool,
+) -> Option<f64> {
+    if force_full || max_instances == 0 || num_points <= max_instances {
+        return None;
+    }
+    Some(max_instances as f64 / num_points as f64)
+}
+
+/// Local downsampling of point clouds above
+/// [ViewerSettings::max_instances](crate::ViewerSettings)
+#[derive(Resource, Default)]
+pub struct InstanceLimit {
+    /// Render all points, also above the threshold
+    pub force_full: bool,
+    /// Rendered and decoded points of the downsampled collections, for
+    /// display
+    pub downsampled: Option<(usize, usize)>,
+    /// Lower threshold to fit the GPU memory budget, see
+    /// [GpuMemory](crate::gpu::GpuMemory)
+    pub gpu_limit: Option<usize>,
+}
+
+impl InstanceLimit {
+    /// Seeded sample of `pc` to render instead above the threshold, `None`
+    /// to render all points
+    pub fn sample(
+        &mut self,
+        pc: &ArrowPointCloud,
+        settings: &ViewerSettings,
+    ) -> Option<ArrowPointCloud> {
+        let num_points = pc.num_points();
+        let p = downsample_fraction(num_points, self.threshold(settings), self.force_full)?;
+        let sample = Sample::Seeded {
+            p,
+            seed: settings.seed,
+        };
+        match pc.execute(&Query::new().sample(sample)) {
+            Ok(sampled) => {
+                let (shown, total) = self.downsampled.unwrap_or_default();
+                self.downsampled = Some((shown + sampled.num_points(), total + num_points));
+                Some(sampled)
+            }
+            Err(e) => {
+                warn!("Failed to downsample {num_points} points, all are rendered: {e}");
+                None
+            }
+        }
+    }
+
+    /// Points rendered per collection, the lower of the configured and the
+    /// GPU memory threshold
+    pub fn threshold(&self, settings: &ViewerSettings) -> usize {
+        match (self.gpu_limit, settings.max_instances) {
+            (Some(gpu), 0) => gpu,
+            (Some(gpu), max) => gpu.min(max),
+            (None, max) => max,
+        }
+    }
+
+    /// Notice of the downsampled points, or of forced full rendering
+    pub fn status(&self, keys: &KeyBindings) -> Option<String> {
+        let key = keys.label(Action::LoadFull);
+        match self.downsampled {
+            Some((shown, total)) => Some(format!(
+                "Showing {} of {} points (local downsample), press shift + {key} to force full",
+                count(shown),
+                count(total),
+            )),
+            None if self.force_full => Some(format!(
+                "Showing all points (forced), press shift + {key} to downsample"
+            )),
+            None => None,
+        }
+    }
+}
+
+/// Compact number of points, e.g. `48.2M`
+fn count(n: usize) -> String {
+    match n {
+        0..=9_999 => n.to_string(),
+        10_000..=999_999 => format!("{:.1}k", n as f64 / 1e3),
+        _ => format!("{:.1}M", n as f64 / 1e6),
+    }
+}
+
+/// Colored instances of the points passing the returns filter, at the
+/// opacity, depth bias and lift of the collection, sized adaptively by the
+/// density `grid` if built and attenuated toward the `background` by the
+/// `fog`. Points with non-finite coordinates are added to `skipped`.
+#[allow(clippy::too_many_arguments)]
+pub fn cloud_instances(
+    pc: &ArrowPointCloud,
+    collection: &str,
+    grid: Option<&DensityGrid>,
+    origin: DVec3,
+    settings: &ViewerSettings,
+    scale: &mut ScaleBounds,
+    skipped: &mut SkippedPoints,
+    background: Color,
+    fog: Option<&DepthCue>,
+) -> Vec<Vec<Cuboid>> {
+    let opacity = settings.opacity(collection);
+    if opacity == 0 {
+        return Vec::new();
+    }
+    let span = info_span!("instances", collection, points = field::Empty).entered();
+
+    let aabb: AABB<Point<f64, 3>> = pc.aabb();
+
+    // filter returns
+    let filtered;
+    let pc = match returns::mask(pc, settings.returns_filter) {
+        Some(mask) => {
+            filtered = pc.filter_mask(&mask).unwrap();
+            &filtered
+        }
+        None => pc,
+    };
+
+    let num_points = pc.num_points();
+    info!("Generating {num_points} instances");
+    span.record("points", num_points);
+
+    let attribute = color_attribute(pc, settings);
+    scale.0 = None;
+    let mut colors = match attribute {
+        COLLECTION_ATTRIBUTE => vec![layers::collection_color(collection); num_points],
+        RETURNS_ATTRIBUTE if returns::has_returns(pc) => returns::colors(pc),
+        RETURNS_ATTRIBUTE => vec![NO_DATA_COLOR; num_points],
+        attribute => {
+            let map = ColorMap::for_attribute(
+                attribute,
+                settings::gradient(settings),
+                settings.normalization,
+            );
+            match normalize::attribute_colors(pc, attribute, &map) {
+                Ok((colors, bounds)) => {
+                    scale.0 = bounds.map(|(lower, upper)| (attribute.to_owned(), lower, upper));
+                    colors
+                }
+                Err(e) => {
+                    eprintln!("No colors of attribute `{attribute}`, fallback color used: {e}");
+                    vec![Color::ORANGE; num_points]
+                }
+            }
+        }
+    };
+
+    if let Some(fog) = fog {
+        fog.apply(pc, &mut colors, background);
+    }
+    let colors = match opacity {
+        100 => colors,
+        opacity => colors
+            .into_iter()
+            .map(|color| layers::with_opacity(color, opacity, background))
+            .collect(),
+    };
+
+    let half_extent = match settings.point_extent {
+        Some(extent) => {
+            let units = Units::new(pc.metadata().units, settings.units);
+            units.to_data(extent) as f32 / 2.
+        }
+        None => sizing::uniform_half_extent(&aabb, num_points, settings.point_size),
+    };
+    // lifted instances are shifted up, i.e. the origin down
+    let origin = origin - DVec3::Z * layers::lift(settings, collection);
+    let depth_bias = layers::depth_bias(settings, collection);
+    let extents = Extents::new(half_extent, grid, settings);
+    let (instances, nonfinite) = generate_instances(pc, origin, &extents, depth_bias, &colors);
+    skipped.0 += nonfinite;
+    instances
+}
+
+/// Cuboid instances of the points in chunks of `CHUNK_SIZE` and the number of
+/// points skipped for non-finite coordinates, see [layers::depth_bias] for the
+/// `depth_bias`.
+///
+/// Coordinates are read and shifted to the origin in f64 and only then cast
+/// to f32, large projected coordinates would otherwise be quantized.
+pub fn generate_instances(
+    pc: &ArrowPointCloud,
+    origin: DVec3,
+    extents: &Extents,
+    depth_bias: u16,
+    colors: &[Color],
+) -> (Vec<Vec<Cuboid>>, usize) {
+    let num_points = pc.num_points();
+    let mut instances: Vec<Vec<Cuboid>> = Vec::new();
+    let mut skipped = 0;
+
+    for (i, p) in pc.points::<Point<f64, 3>>().enumerate() {
+        if !p.coords().iter().all(|c| c.is_finite()) {
+            skipped += 1;
+            continue;
+        }
+
+        // shift to origin and convert from easting (x) northing (y) up (z) to
+        // right hand y up (bevy)
+        //
+        //     z y                y
+        //     |/                 |
+        //     0 –– x    ===>     0 –– x
+        //                       /
+        //                      z
+        //
+        let p = DVec3::from_slice(p.coords());
+        let half_extent = extents.at(p);
+        let p = data_to_world(origin, p);
+
+        let mut cuboid = Cuboid::new(p - half_extent, p + half_extent, colors[i].as_rgba_u32());
+        cuboid.set_depth_bias(depth_bias);
+
+        if instances.last().is_none_or(|c| c.len() == CHUNK_SIZE) {
+            instances.push(Vec::with_capacity(CHUNK_SIZE.min(num_points - i)));
+        }
+        instances.last_mut().unwrap().push(cuboid);
+    }
+
+    (instances, skipped)
+}
+
+/// Active color attribute (change detection results are colored by their
+/// delta, unless colored by collection). The height is the third coordinate,
+/// e.g. the depth of bathymetry.
+pub fn color_attribute<'a>(pc: &'a ArrowPointCloud, settings: &'a ViewerSettings) -> &'a str {
+    if settings.color_attribute == COLLECTION_ATTRIBUTE {
+        COLLECTION_ATTRIBUTE
+    } else if pc.schema.column_with_name(DELTA_ATTRIBUTE).is_some() {
+        DELTA_ATTRIBUTE
+    } else if settings.color_attribute == HEIGHT_ATTRIBUTE {
+        schema::coordinates(&pc.schema)
+            .get(2)
+            .map_or(HEIGHT_ATTRIBUTE, |i| pc.schema.field(*i).name())
+    } else {
+        settings.color_attribute.as_str()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow::datatypes::{DataType, Field, Schema};
+    use crux_format::CoordSpec;
+
+    use super::*;
+
+    #[test]
+    fn large_coordinates() {
+        // two points 1cm apart at a UTM easting, f32 resolves 0.5m there
+        let pc = ArrowPointCloud::from_iter(
+            [
+                Point::<f64, 3>::from_slice(&[5_500_000.00, 5_800_000., 120.]),
+                Point::<f64, 3>::from_slice(&[5_500_000.01, 5_800_000., 120.]),
+            ]
+            .into_iter(),
+        )
+        .unwrap();
+        let origin = DVec3::from_slice(pc.aabb::<Point<f64, 3>>().center().coords());
+
+        let (instances, _) =
+            generate_instances(&pc, origin, &Extents::Uniform(0.001), 0, &[Color::WHITE; 2]);
+        let [a, b] = &instances[0][..] else {
+            panic!("expected two instances");
+        };
+        let center = |c: &Cuboid| (c.minimum + c.maximum) / 2.;
+
+        assert_ne!(center(a), center(b));
+        assert!((center(b).x - center(a).x - 0.01).abs() < 1e-6);
+        assert!(a.maximum.x < b.minimum.x);
+    }
+
+    #[test]
+    fn nonfinite() {
+        // non-finite coordinates at the boundaries of two batches
+        let mut pc = ArrowPointCloud::try_new(Point::<f64, 3>::schema()).unwrap();
+        for z in [[f64::NAN, 1., 2.], [3., 4., f64::INFINITY]] {
+            let batch = ArrowPointCloud::from_iter(
+                z.iter()
+                    .enumerate()
+                    .map(|(i, z)| Point::<f64, 3>::from_slice(&[i as f64, 0., *z])),
+            )
+            .unwrap();
+            for e in batch.store.iter() {
+                for batch in batch.store.batches(e.key()) {
+                    pc.append(batch).unwrap();
+                }
+            }
+        }
+
+        let settings = ViewerSettings::default();
+        let mut scale = ScaleBounds::default();
+        let mut skipped = SkippedPoints::default();
+        let instances = cloud_instances(
+            &pc,
+            "default",
+            None,
+            DVec3::ZERO,
+            &settings,
+            &mut scale,
+            &mut skipped,
+            Color::BLACK,
+            None,
+        );
+        assert_eq!(instances.concat().len(), 4);
+        assert_eq!(skipped.0, 2);
+        for cuboid in instances.concat() {
+            assert!(cuboid.minimum.is_finite() && cuboid.maximum.is_finite());
+        }
+
+        // the height gradient spans the finite heights
+        let (_, lower, upper) = scale.0.unwrap();
+        assert!(lower.is_finite() && upper.is_finite() && lower < upper);
+    }
+
+    #[test]
+    fn depth() {
+        let pc =
+            ArrowPointCloud::from_iter([Point::<f64, 3>::from_slice(&[0., 0., 1.])].into_iter())
+                .unwrap();
+        let mut settings = ViewerSettings {
+            collection: "epoch1".to_string(),
+            compare: Some("epoch2".to_string()),
+            ..default()
+        };
+        let instance = |settings: &ViewerSettings, collection: &str| {
+            cloud_instances(
+                &pc,
+                collection,
+                None,
+                DVec3::ZERO,
+                settings,
+                &mut ScaleBounds::default(),
+                &mut SkippedPoints::default(),
+                Color::BLACK,
+                None,
+            )[0][0]
+        };
+
+        // the compared collection is drawn behind
+        assert_eq!(instance(&settings, "epoch1").meta_bits >> 16, 0);
+        assert_eq!(
+            instance(&settings, "epoch2").meta_bits >> 16,
+            layers::DEPTH_BIAS_STEP as u32
+        );
+
+        // and lifted in the exploded view, the height is the world y
+        settings.exploded = true;
+        let center = |c: Cuboid| (c.minimum + c.maximum) / 2.;
+        assert_eq!(center(instance(&settings, "epoch1")).y, 1.);
+        assert_eq!(center(instance(&settings, "epoch2")).y, 2.);
+    }
+
+    #[test]
+    fn downsample() {
+        // within the threshold
+        assert_eq!(downsample_fraction(0, 100, false), None);
+        assert_eq!(downsample_fraction(100, 100, false), None);
+        // above
+        assert_eq!(downsample_fraction(400, 100, false), Some(0.25));
+        assert_eq!(
+            downsample_fraction(48_200_000, MAX_INSTANCES, false),
+            Some(5_000_000. / 48_200_000.)
+        );
+        // forced full and without threshold
+        assert_eq!(downsample_fraction(400, 100, true), None);
+        assert_eq!(downsample_fraction(100, 100, true), None);
+        assert_eq!(downsample_fraction(400, 0, false), None);
+
+        let pc = ArrowPointCloud::from_iter(
+            (0..10_000).map(|i| Point::<f64, 3>::from_slice(&[i as f64, 0., 0.])),
+        )
+        .unwrap();
+        let mut settings = ViewerSettings {
+            max_instances: 1_000,
+            ..default()
+        };
+        let keys = KeyBindings::default();
+        let mut limit = InstanceLimit::default();
+        let sampled = limit.sample(&pc, &settings).unwrap().num_points();
+        assert!((500..1_500).contains(&sampled), "{sampled}");
+        assert_eq!(limit.downsampled, Some((sampled, 10_000)));
+        let status = limit.status(&keys).unwrap();
+        assert!(status.contains("of 10.0k points"), "{status}");
+        assert!(status.contains("shift + F1"), "{status}");
+
+        // the same sample for the same seed
+        let mut again = InstanceLimit::default();
+        assert_eq!(again.sample(&pc, &settings).unwrap().num_points(), sampled);
+
+        limit = InstanceLimit {
+            force_full: true,
+            ..default()
+        };
+        assert!(limit.sample(&pc, &settings).is_none());
+        assert!(limit.status(&keys).unwrap().contains("forced"));
+        limit.force_full = false;
+        settings.max_instances = 10_000;
+        assert!(InstanceLimit::default().sample(&pc, &settings).is_none());
+        assert_eq!(limit.status(&keys), None);
+
+        // degraded to fit the GPU memory, also without a threshold
+        limit.gpu_limit = Some(2_000);
+        assert_eq!(limit.threshold(&settings), 2_000);
+        settings.max_instances = 0;
+        assert_eq!(limit.threshold(&settings), 2_000);
+        settings.max_instances = 1_000;
+        assert_eq!(limit.threshold(&settings), 1_000);
+        let degraded = limit.sample(&pc, &settings).unwrap().num_points();
+        assert_eq!(degraded, sampled);
+
+        assert_eq!(count(950), "950");
+        assert_eq!(count(48_200_000), "48.2M");
+    }
+
+    #[test]
+    fn height_attribute() {
+        let settings = ViewerSettings::default();
+        let pc =
+            ArrowPointCloud::from_iter([Point::<f64, 3>::from_slice(&[0., 0., -12.])].into_iter())
+                .unwrap();
+        assert_eq!(color_attribute(&pc, &settings), HEIGHT_ATTRIBUTE);
+
+        // bathymetry, the depth is the height
+        let schema = Arc::new(Schema::new(
+            ["x", "y", "depth"]
+                .map(|name| Field::new(name, DataType::Float64, false))
+                .to_vec(),
+        ));
+        let depth = CoordSpec::new(&["x", "y", "depth"]);
+        let pc = ArrowPointCloud::try_new_with_coords(schema, &depth).unwrap();
+        assert_eq!(color_attribute(&pc, &settings), "depth");
+    }
+}
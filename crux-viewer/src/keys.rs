@@ -0,0 +1,416 @@
+use std::collections::BTreeMap;
+
+use bevy::prelude::*;
+use serde::{
+    de::{value::Error, IntoDeserializer},
+    Deserialize, Serialize,
+};
+
+/// Named viewer controls bound to a key
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    LoadFull,
+    LoadP1,
+    LoadP01,
+    LoadP001,
+    LoadP0001,
+    PreviewThinner,
+    PreviewDenser,
+    FetchPreview,
+    RefineView,
+    ToggleAutoLod,
+    ResetCamera,
+    ToggleAutoFrame,
+    ColorByReturns,
+    CycleReturns,
+    CycleBounds,
+    CompareMode,
+    CompareFlip,
+    ColorByCollection,
+    NextLayer,
+    LayerForward,
+    LayerBackward,
+    Explode,
+    Measure,
+    Profile,
+    ExportProfile,
+    Volume,
+    ClearFootprint,
+    StretchDown,
+    StretchUp,
+    GammaDown,
+    GammaUp,
+    PointSizing,
+    Background,
+    Fog,
+    DepthCueDown,
+    DepthCueUp,
+    TrajectoryBack,
+    TrajectoryForward,
+    TopView,
+    FrontView,
+    SideView,
+    PerspectiveView,
+    Stereo,
+    History,
+    Hud,
+    Screenshot,
+    Help,
+}
+
+impl Action {
+    /// All actions in the order of the help
+    pub const ALL: [Action; 47] = [
+        Action::LoadFull,
+        Action::LoadP1,
+        Action::LoadP01,
+        Action::LoadP001,
+        Action::LoadP0001,
+        Action::PreviewThinner,
+        Action::PreviewDenser,
+        Action::FetchPreview,
+        Action::RefineView,
+        Action::ToggleAutoLod,
+        Action::ResetCamera,
+        Action::ToggleAutoFrame,
+        Action::ColorByReturns,
+        Action::CycleReturns,
+        Action::CycleBounds,
+        Action::CompareMode,
+        Action::CompareFlip,
+        Action::ColorByCollection,
+        Action::NextLayer,
+        Action::LayerForward,
+        Action::LayerBackward,
+        Action::Explode,
+        Action::Measure,
+        Action::Profile,
+        Action::ExportProfile,
+        Action::Volume,
+        Action::ClearFootprint,
+        Action::StretchDown,
+        Action::StretchUp,
+        Action::GammaDown,
+        Action::GammaUp,
+        Action::PointSizing,
+        Action::Background,
+        Action::Fog,
+        Action::DepthCueDown,
+        Action::DepthCueUp,
+        Action::TrajectoryBack,
+        Action::TrajectoryForward,
+        Action::TopView,
+        Action::FrontView,
+        Action::SideView,
+        Action::PerspectiveView,
+        Action::Stereo,
+        Action::History,
+        Action::Hud,
+        Action::Screenshot,
+        Action::Help,
+    ];
+
+    pub fn default_key(self) -> KeyCode {
+        match self {
+            Action::LoadFull => KeyCode::F1,
+            Action::LoadP1 => KeyCode::F2,
+            Action::LoadP01 => KeyCode::F3,
+            Action::LoadP001 => KeyCode::F4,
+            Action::LoadP0001 => KeyCode::F5,
+            Action::PreviewThinner => KeyCode::F7,
+            Action::PreviewDenser => KeyCode::F8,
+            Action::FetchPreview => KeyCode::F9,
+            Action::RefineView => KeyCode::U,
+            Action::ToggleAutoLod => KeyCode::L,
+            Action::ResetCamera => KeyCode::R,
+            Action::ToggleAutoFrame => KeyCode::F,
+            Action::ColorByReturns => KeyCode::N,
+            Action::CycleReturns => KeyCode::T,
+            Action::CycleBounds => KeyCode::B,
+            Action::CompareMode => KeyCode::C,
+            Action::CompareFlip => KeyCode::X,
+            Action::ColorByCollection => KeyCode::K,
+            Action::NextLayer => KeyCode::Tab,
+            Action::LayerForward => KeyCode::PageUp,
+            Action::LayerBackward => KeyCode::PageDown,
+            Action::Explode => KeyCode::O,
+            Action::Measure => KeyCode::M,
+            Action::Profile => KeyCode::P,
+            Action::ExportProfile => KeyCode::E,
+            Action::Volume => KeyCode::V,
+            Action::ClearFootprint => KeyCode::Back,
+            Action::StretchDown => KeyCode::BracketLeft,
+            Action::StretchUp => KeyCode::BracketRight,
+            Action::GammaDown => KeyCode::Minus,
+            Action::GammaUp => KeyCode::Equals,
+            Action::PointSizing => KeyCode::Z,
+            Action::Background => KeyCode::Y,
+            Action::Fog => KeyCode::G,
+            Action::DepthCueDown => KeyCode::Semicolon,
+            Action::DepthCueUp => KeyCode::Apostrophe,
+            Action::TrajectoryBack => KeyCode::Comma,
+            Action::TrajectoryForward => KeyCode::Period,
+            Action::TopView => KeyCode::Numpad7,
+            Action::FrontView => KeyCode::Numpad1,
+            Action::SideView => KeyCode::Numpad3,
+            Action::PerspectiveView => KeyCode::Numpad5,
+            Action::Stereo => KeyCode::S,
+            Action::History => KeyCode::Q,
+            Action::Hud => KeyCode::F10,
+            Action::Screenshot => KeyCode::F12,
+            Action::Help => KeyCode::H,
+        }
+    }
+
+    pub fn description(self) -> &'static str {
+        match self {
+            Action::LoadFull => "load all points, render all above the limit with shift",
+            Action::LoadP1 => "load a sample of p = 0.1",
+            Action::LoadP01 => "load a sample of p = 0.01",
+            Action::LoadP001 => "load a sample of p = 0.001",
+            Action::LoadP0001 => "load a sample of p = 0.0001",
+            Action::PreviewThinner => "preview a smaller p on the loaded points while held",
+            Action::PreviewDenser => "preview a larger p on the loaded points while held",
+            Action::FetchPreview => "fetch the previewed p, leave the preview with shift",
+            Action::RefineView => "refine the view around the focus",
+            Action::ToggleAutoLod => "toggle automatic refinement",
+            Action::ResetCamera => "reset the camera",
+            Action::ToggleAutoFrame => "toggle automatic framing",
+            Action::ColorByReturns => "toggle coloring by returns",
+            Action::CycleReturns => "cycle the shown returns",
+            Action::CycleBounds => "cycle collection and batch bounds",
+            Action::CompareMode => "switch between swipe and toggle comparison",
+            Action::CompareFlip => "flip the shown collection of a toggle comparison",
+            Action::ColorByCollection => "toggle coloring by collection",
+            Action::NextLayer => "select the next collection for opacity and depth",
+            Action::LayerForward => "draw the selected collection further in front",
+            Action::LayerBackward => "draw the selected collection further behind",
+            Action::Explode => "toggle the exploded view, collections offset vertically",
+            Action::Measure => "measure tool",
+            Action::Profile => "profile tool",
+            Action::ExportProfile => "export the profile",
+            Action::Volume => "volume tool",
+            Action::ClearFootprint => "clear the volume footprint",
+            Action::StretchDown => "lower the stretch percentile, the upper with shift",
+            Action::StretchUp => "raise the stretch percentile, the upper with shift",
+            Action::GammaDown => "decrease the gamma",
+            Action::GammaUp => "increase the gamma",
+            Action::PointSizing => "toggle uniform and density adaptive point sizes",
+            Action::Background => "cycle grey, white and black backgrounds",
+            Action::Fog => "toggle the fog, distant points fade into the background",
+            Action::DepthCueDown => "weaken the depth cueing of the fog",
+            Action::DepthCueUp => "strengthen the depth cueing of the fog",
+            Action::TrajectoryBack => "move back along the trajectory, faster with shift",
+            Action::TrajectoryForward => "move forth along the trajectory, faster with shift",
+            Action::TopView => "orthographic top view",
+            Action::FrontView => "orthographic front view",
+            Action::SideView => "orthographic side view",
+            Action::PerspectiveView => "back to the perspective view",
+            Action::Stereo => "cycle side-by-side and anaglyph stereo",
+            Action::History => "recent queries and bookmarks",
+            Action::Hud => "toggle the overlay, compact or full with shift",
+            Action::Screenshot => "save a screenshot with the legend of the colors",
+            Action::Help => "this help",
+        }
+    }
+}
+
+/// Controls that cannot be remapped, listed after the bindings
+const FIXED: [(&str, &str); 5] = [
+    ("0-9", "opacity of the selected collection"),
+    (
+        "shift + click",
+        "pick points of the measure, profile and volume tools",
+    ),
+    ("ctrl + click", "inspect the vector feature near the cursor"),
+    ("alt + drag", "move the divider of a swipe comparison"),
+    ("mouse", "orbit, pan and zoom"),
+];
+
+/// Key of each action, the defaults for actions missing in the settings
+#[derive(Resource, Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(from = "BTreeMap<String, KeyCode>", into = "BTreeMap<Action, KeyCode>")]
+pub struct KeyBindings(BTreeMap<Action, KeyCode>);
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        Self(
+            Action::ALL
+                .iter()
+                .map(|action| (*action, action.default_key()))
+                .collect(),
+        )
+    }
+}
+
+impl From<BTreeMap<String, KeyCode>> for KeyBindings {
+    fn from(keys: BTreeMap<String, KeyCode>) -> Self {
+        let mut bindings = Self::default();
+        for (name, key) in keys {
+            let action: Result<Action, Error> =
+                Action::deserialize(name.as_str().into_deserializer());
+            match action {
+                Ok(action) => {
+                    bindings.0.insert(action, key);
+                }
+                Err(_) => warn!("Ignoring the key of unknown action `{name}`"),
+            }
+        }
+        bindings
+    }
+}
+
+impl From<KeyBindings> for BTreeMap<Action, KeyCode> {
+    fn from(bindings: KeyBindings) -> Self {
+        bindings.0
+    }
+}
+
+impl KeyBindings {
+    pub fn key(&self, action: Action) -> KeyCode {
+        self.0
+            .get(&action)
+            .copied()
+            .unwrap_or_else(|| action.default_key())
+    }
+
+    pub fn bind(&mut self, action: Action, key: KeyCode) {
+        self.0.insert(action, key);
+    }
+
+    /// Whether the key of `action` was pressed in this frame
+    pub fn just_pressed(&self, input: &Input<KeyCode>, action: Action) -> bool {
+        input.just_pressed(self.key(action))
+    }
+
+    /// Whether the key of `action` is held down
+    pub fn pressed(&self, input: &Input<KeyCode>, action: Action) -> bool {
+        input.pressed(self.key(action))
+    }
+
+    /// Name of the key of `action` for the overlay
+    pub fn label(&self, action: Action) -> String {
+        key_name(self.key(action))
+    }
+
+    /// Keys bound to more than one action, with those actions
+    pub fn conflicts(&self) -> Vec<(KeyCode, Vec<Action>)> {
+        let mut actions: BTreeMap<KeyCode, Vec<Action>> = BTreeMap::new();
+        for action in Action::ALL {
+            actions.entry(self.key(action)).or_default().push(action);
+        }
+        actions
+            .into_iter()
+            .filter(|(_, actions)| actions.len() > 1)
+            .collect()
+    }
+
+    /// Lines of the help overlay, one per control
+    pub fn help(&self) -> String {
+        let lines: Vec<(String, &str)> = Action::ALL
+            .iter()
+            .map(|action| (self.label(*action), action.description()))
+            .chain(FIXED.iter().map(|(key, text)| (key.to_string(), *text)))
+            .collect();
+        let width = lines.iter().map(|(key, _)| key.len()).max().unwrap_or(0);
+
+        lines
+            .iter()
+            .map(|(key, text)| format!("{key:<width$}  {text}"))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+/// Readable name of a key, e.g. `[` or `numpad 7`
+pub fn key_name(key: KeyCode) -> String {
+    let name = match key {
+        KeyCode::Key0 => "0",
+        KeyCode::Key1 => "1",
+        KeyCode::Key2 => "2",
+        KeyCode::Key3 => "3",
+        KeyCode::Key4 => "4",
+        KeyCode::Key5 => "5",
+        KeyCode::Key6 => "6",
+        KeyCode::Key7 => "7",
+        KeyCode::Key8 => "8",
+        KeyCode::Key9 => "9",
+        KeyCode::BracketLeft => "[",
+        KeyCode::BracketRight => "]",
+        KeyCode::Minus => "-",
+        KeyCode::Equals => "=",
+        KeyCode::Comma => ",",
+        KeyCode::Period => ".",
+        KeyCode::Back => "backspace",
+        KeyCode::Tab => "tab",
+        KeyCode::PageUp => "page up",
+        KeyCode::PageDown => "page down",
+        KeyCode::Return => "enter",
+        KeyCode::Space => "space",
+        key => {
+            let name = format!("{key:?}");
+            return match name.strip_prefix("Numpad") {
+                Some(rest) => format!("numpad {rest}"),
+                None => name,
+            };
+        }
+    };
+    name.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn defaults() {
+        let keys = KeyBindings::default();
+        assert!(keys.conflicts().is_empty());
+        assert_eq!(keys.key(Action::LoadFull), KeyCode::F1);
+        assert_eq!(keys.label(Action::StretchDown), "[");
+        assert_eq!(keys.label(Action::TopView), "numpad 7");
+
+        let help = keys.help();
+        assert_eq!(help.lines().count(), Action::ALL.len() + FIXED.len());
+        assert!(help.lines().next().unwrap().starts_with("F1 "));
+        assert!(help.contains("numpad 7       orthographic top view"));
+
+        let mut input = Input::default();
+        input.press(KeyCode::H);
+        assert!(keys.just_pressed(&input, Action::Help));
+        assert!(!keys.just_pressed(&input, Action::History));
+    }
+
+    #[test]
+    fn conflicts() {
+        let mut keys = KeyBindings::default();
+        keys.bind(Action::LoadFull, KeyCode::F6);
+        keys.bind(Action::Measure, KeyCode::R);
+        assert_eq!(
+            keys.conflicts(),
+            [(KeyCode::R, vec![Action::ResetCamera, Action::Measure])]
+        );
+    }
+
+    #[test]
+    fn parse() {
+        #[derive(Serialize, Deserialize)]
+        struct Settings {
+            keys: KeyBindings,
+        }
+
+        // missing actions keep their default, unknown ones are ignored
+        let settings: Settings =
+            toml::from_str("[keys]\nload_full = \"F6\"\nrefine_view = \"Space\"\nfly = \"W\"")
+                .unwrap();
+        assert_eq!(settings.keys.key(Action::LoadFull), KeyCode::F6);
+        assert_eq!(settings.keys.key(Action::RefineView), KeyCode::Space);
+        assert_eq!(settings.keys.key(Action::LoadP1), KeyCode::F2);
+
+        let content = toml::to_string(&settings).unwrap();
+        assert!(content.contains("load_full = \"F6\""));
+        let parsed: Settings = toml::from_str(&content).unwrap();
+        assert_eq!(parsed.keys, settings.keys);
+    }
+}
@@ -0,0 +1,283 @@
+use bevy::prelude::*;
+
+use crate::{
+    instances::HEIGHT_ATTRIBUTE,
+    keys::{Action, KeyBindings},
+    ViewerSettings,
+};
+
+/// Pseudo attribute coloring all points of a collection in its hue
+pub const COLLECTION_ATTRIBUTE: &str = "collection";
+
+/// Distinct hues of collections
+const PALETTE: [Color; 8] = [
+    Color::rgb(0.90, 0.30, 0.24),
+    Color::rgb(0.20, 0.60, 0.86),
+    Color::rgb(0.95, 0.77, 0.06),
+    Color::rgb(0.18, 0.80, 0.44),
+    Color::rgb(0.61, 0.35, 0.71),
+    Color::rgb(0.90, 0.49, 0.13),
+    Color::rgb(0.10, 0.74, 0.61),
+    Color::rgb(0.91, 0.33, 0.60),
+];
+
+/// Number keys setting the opacity of the selected collection, in tens of percent
+const OPACITY_KEYS: [KeyCode; 10] = [
+    KeyCode::Key0,
+    KeyCode::Key1,
+    KeyCode::Key2,
+    KeyCode::Key3,
+    KeyCode::Key4,
+    KeyCode::Key5,
+    KeyCode::Key6,
+    KeyCode::Key7,
+    KeyCode::Key8,
+    KeyCode::Key9,
+];
+
+/// Color of a collection, the same name is colored the same in every session
+pub fn collection_color(collection: &str) -> Color {
+    // FNV-1a, the hasher of std is not guaranteed to be stable between releases
+    let hash = collection
+        .bytes()
+        .fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+        });
+    PALETTE[(hash % PALETTE.len() as u64) as usize]
+}
+
+/// `color` with `opacity` percent in its alpha channel.
+///
+/// Instances replace the background instead of blending with it, so the color
+/// is mixed with `background` as well.
+pub fn with_opacity(color: Color, opacity: u8, background: Color) -> Color {
+    let a = opacity.min(100) as f32 / 100.;
+    let [r, g, b, _] = color.as_rgba_f32();
+    let [br, bg, bb, _] = background.as_rgba_f32();
+    Color::rgba(br + (r - br) * a, bg + (g - bg) * a, bb + (b - bb) * a, a)
+}
+
+/// Depth bias between consecutive shown collections. The renderer scales the
+/// depth of a cuboid by `1 - 8e-8 * bias`, which has to exceed the precision
+/// of `f32` depths to separate coincident surfaces.
+pub const DEPTH_BIAS_STEP: u16 = 64;
+
+/// Depth bias of `collection`, collections of a lower bias are drawn in front
+/// of overlapping ones. Unless set, the shown collections are biased by their
+/// order, so that the collection is drawn in front of the compared one.
+pub fn depth_bias(settings: &ViewerSettings, collection: &str) -> u16 {
+    settings
+        .depth_bias
+        .get(collection)
+        .copied()
+        .unwrap_or_else(|| position(settings, collection) as u16 * DEPTH_BIAS_STEP)
+}
+
+/// Vertical offset of `collection` in data units, the compared collection is
+/// lifted above the collection in the exploded view. The offset is applied to
+/// the rendered instances only, not to the points.
+pub fn lift(settings: &ViewerSettings, collection: &str) -> f64 {
+    if settings.exploded {
+        position(settings, collection) as f64 * settings.explode_offset
+    } else {
+        0.
+    }
+}
+
+/// Position of `collection` among the shown collections, 0 if not shown
+fn position(settings: &ViewerSettings, collection: &str) -> usize {
+    shown(settings)
+        .iter()
+        .position(|c| *c == collection)
+        .unwrap_or(0)
+}
+
+/// Shown collections, the collection and the compared collection
+fn shown(settings: &ViewerSettings) -> Vec<&String> {
+    std::iter::once(&settings.collection)
+        .chain(settings.compare.as_ref())
+        .collect()
+}
+
+/// Collection whose opacity is adjusted by the number keys
+#[derive(Resource, Default)]
+pub struct Layers {
+    selected: Option<String>,
+}
+
+impl Layers {
+    /// Selected collection, the collection unless another shown one was selected
+    pub fn selected<'a>(&self, settings: &'a ViewerSettings) -> &'a String {
+        let shown = shown(settings);
+        shown
+            .iter()
+            .find(|collection| self.selected.as_ref() == Some(**collection))
+            .unwrap_or(&shown[0])
+    }
+
+    /// Select the next shown collection
+    fn next(&mut self, settings: &ViewerSettings) {
+        let shown = shown(settings);
+        let selected = self.selected(settings);
+        let i = shown.iter().position(|c| *c == selected).unwrap_or(0);
+        self.selected = Some(shown[(i + 1) % shown.len()].to_owned());
+    }
+
+    /// Overlay lines
+    pub fn status(&self, settings: &ViewerSettings, keys: &KeyBindings) -> String {
+        let selected = self.selected(settings);
+        let mut status = format!(
+            "Opacity ({}, 0-9): `{selected}` at {}%, depth bias ({}/{}) {}\nColor by collection ({}): {}",
+            keys.label(Action::NextLayer),
+            settings.opacity(selected),
+            keys.label(Action::LayerForward),
+            keys.label(Action::LayerBackward),
+            depth_bias(settings, selected),
+            keys.label(Action::ColorByCollection),
+            if settings.color_attribute == COLLECTION_ATTRIBUTE {
+                "on"
+            } else {
+                "off"
+            }
+        );
+        if settings.exploded {
+            status.push_str(&format!(
+                "\nEXPLODED VIEW ({}): collections offset by {} vertically, do not measure heights",
+                keys.label(Action::Explode),
+                settings.explode_offset
+            ));
+        }
+        status
+    }
+}
+
+// Press 'K' to color by collection, 'Tab' selects the next shown collection,
+// the number keys set its opacity (1 for 10% to 0 for 100%) and page up and
+// down move it in front of or behind the others. 'O' toggles the exploded view.
+pub fn layers_system(
+    key_input: Res<Input<KeyCode>>,
+    keys: Res<KeyBindings>,
+    mut layers: ResMut<Layers>,
+    mut settings: ResMut<ViewerSettings>,
+) {
+    if keys.just_pressed(&key_input, Action::ColorByCollection) {
+        settings.color_attribute = if settings.color_attribute == COLLECTION_ATTRIBUTE {
+            HEIGHT_ATTRIBUTE.to_string()
+        } else {
+            COLLECTION_ATTRIBUTE.to_string()
+        };
+    }
+
+    if keys.just_pressed(&key_input, Action::NextLayer) {
+        layers.next(&settings);
+    }
+
+    let forward = keys.just_pressed(&key_input, Action::LayerForward);
+    if forward || keys.just_pressed(&key_input, Action::LayerBackward) {
+        let selected = layers.selected(&settings).to_owned();
+        let bias = depth_bias(&settings, &selected);
+        let bias = if forward {
+            bias.saturating_sub(DEPTH_BIAS_STEP)
+        } else {
+            bias.saturating_add(DEPTH_BIAS_STEP)
+        };
+        settings.depth_bias.insert(selected, bias);
+    }
+
+    if keys.just_pressed(&key_input, Action::Explode) {
+        settings.exploded = !settings.exploded;
+    }
+
+    if let Some(tens) = OPACITY_KEYS
+        .iter()
+        .position(|key| key_input.just_pressed(*key))
+    {
+        let opacity = if tens == 0 { 100 } else { tens as u8 * 10 };
+        let selected = layers.selected(&settings).to_owned();
+        if settings.opacity(&selected) != opacity {
+            match opacity {
+                100 => settings.opacity.remove(&selected),
+                opacity => settings.opacity.insert(selected, opacity),
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn colors() {
+        assert_eq!(collection_color("epoch1"), collection_color("epoch1"));
+        assert!(PALETTE.contains(&collection_color("")));
+        // the palette is spread over different names
+        let colors: Vec<Color> = (0..32)
+            .map(|i| collection_color(&format!("epoch{i}")))
+            .collect();
+        assert!(PALETTE.iter().all(|color| colors.contains(color)));
+
+        let background = Color::rgb(0., 0., 0.);
+        assert_eq!(with_opacity(Color::WHITE, 100, background), Color::WHITE);
+        assert_eq!(
+            with_opacity(Color::WHITE, 40, background),
+            Color::rgba(0.4, 0.4, 0.4, 0.4)
+        );
+        assert_eq!(with_opacity(Color::WHITE, 0, background).a(), 0.);
+    }
+
+    #[test]
+    fn select() {
+        let mut settings = ViewerSettings {
+            collection: "epoch1".to_string(),
+            ..default()
+        };
+        let mut layers = Layers::default();
+        assert_eq!(layers.selected(&settings), "epoch1");
+        layers.next(&settings);
+        assert_eq!(layers.selected(&settings), "epoch1");
+
+        settings.compare = Some("epoch2".to_string());
+        layers.next(&settings);
+        assert_eq!(layers.selected(&settings), "epoch2");
+        layers.next(&settings);
+        assert_eq!(layers.selected(&settings), "epoch1");
+
+        // falls back once the compared collection is gone
+        layers.next(&settings);
+        settings.compare = None;
+        assert_eq!(layers.selected(&settings), "epoch1");
+
+        settings.opacity.insert("epoch1".to_string(), 30);
+        let status = layers.status(&settings, &KeyBindings::default());
+        assert!(status.starts_with("Opacity (tab, 0-9): `epoch1` at 30%"));
+    }
+
+    #[test]
+    fn depth() {
+        let mut settings = ViewerSettings {
+            collection: "epoch1".to_string(),
+            compare: Some("epoch2".to_string()),
+            ..default()
+        };
+
+        // the collection in front of the compared one
+        assert_eq!(depth_bias(&settings, "epoch1"), 0);
+        assert_eq!(depth_bias(&settings, "epoch2"), DEPTH_BIAS_STEP);
+        settings
+            .depth_bias
+            .insert("epoch1".to_string(), 2 * DEPTH_BIAS_STEP);
+        assert_eq!(depth_bias(&settings, "epoch1"), 2 * DEPTH_BIAS_STEP);
+
+        // offsets only in the exploded view
+        assert_eq!(lift(&settings, "epoch2"), 0.);
+        settings.exploded = true;
+        settings.explode_offset = 2.5;
+        assert_eq!(lift(&settings, "epoch1"), 0.);
+        assert_eq!(lift(&settings, "epoch2"), 2.5);
+
+        let status = Layers::default().status(&settings, &KeyBindings::default());
+        assert!(status.contains("depth bias (page up/page down) 128"));
+        assert!(status.contains("EXPLODED VIEW (O): collections offset by 2.5"));
+    }
+}
@@ -0,0 +1,162 @@
+//! Legends of the colors next to screenshots: the colorbar or the occurring
+//! classes of the colored attribute and the parameters of its color map
+
+use std::{
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use bevy::{prelude::*, render::view::screenshot::ScreenshotManager, window::PrimaryWindow};
+use crux_format::{
+    color::ColorMap,
+    legend::{self, Mapping},
+    ArrowPointCloud,
+};
+
+use crate::{
+    classes::Classes,
+    headless,
+    instances::color_attribute,
+    keys::{Action, KeyBindings},
+    layers::COLLECTION_ATTRIBUTE,
+    normalize::ScaleBounds,
+    returns::RETURNS_ATTRIBUTE,
+    settings, PointCache, ViewerSettings,
+};
+
+/// Legend image and mapping parameters next to `screenshot`, e.g.
+/// `city.legend.png` and `city.legend.json` next to `city.png`
+pub fn legend_paths(screenshot: &Path) -> (PathBuf, PathBuf) {
+    let stem = screenshot
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "screenshot".to_owned());
+    (
+        screenshot.with_file_name(format!("{stem}.legend.png")),
+        screenshot.with_file_name(format!("{stem}.legend.json")),
+    )
+}
+
+/// Write the legend of the colors of `pc` next to `screenshot`, with the
+/// stretch bounds of the rendered points and the `classes` of the collection.
+/// Returns the paths of the image and the mapping.
+pub fn write_legend(
+    screenshot: &Path,
+    pc: &ArrowPointCloud,
+    settings: &ViewerSettings,
+    scale: &ScaleBounds,
+    classes: &Classes,
+) -> Result<(PathBuf, PathBuf), String> {
+    let attribute = color_attribute(pc, settings);
+    if attribute == COLLECTION_ATTRIBUTE || attribute == RETURNS_ATTRIBUTE {
+        return Err(format!("no color map of the {attribute} colors"));
+    }
+
+    let map = match ColorMap::for_attribute(
+        attribute,
+        settings::gradient(settings),
+        settings.normalization,
+    ) {
+        ColorMap::Categorical(palette) => ColorMap::Categorical(classes.palette(&palette)),
+        map => map,
+    };
+    let bounds = match (&map, &scale.0) {
+        (ColorMap::Categorical(_), _) => None,
+        (_, Some((name, lower, upper))) if name == attribute => Some((*lower, *upper)),
+        _ => return Err(format!("no stretch bounds of `{attribute}`")),
+    };
+
+    let mapping = Mapping::new(attribute, &map, settings.palette.as_deref(), bounds);
+    let (image, parameters) = legend_paths(screenshot);
+    std::fs::write(&image, legend::legend(&map, bounds).png())
+        .map_err(|e| format!("{image:?}: {e}"))?;
+    let json = serde_json::to_vec_pretty(&mapping).map_err(|e| e.to_string())?;
+    std::fs::write(&parameters, json).map_err(|e| format!("{parameters:?}: {e}"))?;
+    Ok((image, parameters))
+}
+
+// Save a screenshot with the legend of its colors into the working directory
+#[allow(clippy::too_many_arguments)]
+pub fn screenshot_system(
+    key_input: Res<Input<KeyCode>>,
+    keys: Res<KeyBindings>,
+    cache: Res<PointCache>,
+    settings: Res<ViewerSettings>,
+    scale: Res<ScaleBounds>,
+    classes: Res<Classes>,
+    window: Query<Entity, With<PrimaryWindow>>,
+    mut screenshots: ResMut<ScreenshotManager>,
+) {
+    if !keys.just_pressed(&key_input, Action::Screenshot) {
+        return;
+    }
+    let (Ok(window), Ok(dir)) = (window.get_single(), std::env::current_dir()) else {
+        return;
+    };
+    let seconds = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let path = dir.join(format!("screenshot-{seconds}.png"));
+
+    let screenshot = path.clone();
+    let requested = screenshots.take_screenshot(window, move |image| {
+        match headless::write_png(image, &screenshot) {
+            Ok(()) => info!("Saved {screenshot:?}"),
+            Err(e) => warn!("Failed to save the screenshot: {e}"),
+        }
+    });
+    if let Err(e) = requested {
+        warn!("Failed to take a screenshot: {e}");
+        return;
+    }
+
+    let Some(pc) = cache.get(&settings.collection) else {
+        return;
+    };
+    match write_legend(&path, pc, &settings, &scale, &classes) {
+        Ok((image, _)) => info!("Saved the legend {image:?}"),
+        Err(e) => warn!("No legend of the screenshot: {e}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crux_format::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn paths() {
+        let (image, parameters) = legend_paths(Path::new("out/city.png"));
+        assert_eq!(image, Path::new("out/city.legend.png"));
+        assert_eq!(parameters, Path::new("out/city.legend.json"));
+    }
+
+    #[test]
+    fn height_legend() {
+        let pc = ArrowPointCloud::from_iter(
+            (0..10).map(|i| Point::<f64, 3>::from_slice(&[0., 0., i as f64])),
+        )
+        .unwrap();
+        let settings = ViewerSettings::default();
+        let dir = tempfile::tempdir().unwrap();
+        let screenshot = dir.path().join("thumbnail.png");
+
+        // the bounds of the rendered points are required for gradients
+        let unknown = ScaleBounds(Some(("intensity".to_string(), 0., 1.)));
+        let classes = Classes::default();
+        assert!(write_legend(&screenshot, &pc, &settings, &unknown, &classes).is_err());
+
+        let scale = ScaleBounds(Some(("z".to_string(), 0.5, 8.5)));
+        let (image, parameters) =
+            write_legend(&screenshot, &pc, &settings, &scale, &classes).unwrap();
+        assert!(std::fs::read(image).unwrap().starts_with(b"\x89PNG"));
+        let mapping: Mapping = serde_json::from_slice(&std::fs::read(parameters).unwrap()).unwrap();
+        assert_eq!(mapping.attribute, "z");
+        assert_eq!(mapping.gradient.as_deref(), Some("turbo"));
+        assert_eq!(mapping.bounds, Some((0.5, 8.5)));
+        assert_eq!(mapping.normalization, Some(settings.normalization));
+        assert_eq!(mapping.ticks.first(), Some(&0.5));
+    }
+}
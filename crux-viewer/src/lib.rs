@@ -0,0 +1,58 @@
+//! The viewer as a Bevy plugin, see [CruxViewerPlugin], and the parts that do
+//! not need a running app: request urls, decoding of responses, settings and
+//! key bindings, the query history and the generation of colored and sized
+//! instances with their GPU memory, the accounting of cached points, the
+//! trace of loads, the recording of sessions, vector context and the eye
+//! cameras of the stereo mode and the lines of the overlay
+
+pub mod bounds;
+pub mod cache;
+pub mod camera;
+pub mod classes;
+pub mod color;
+pub mod compare;
+pub mod demo;
+pub mod fetch;
+pub mod fog;
+pub mod frame;
+pub mod framing;
+pub mod gpu;
+pub mod headless;
+pub mod help;
+pub mod history;
+pub mod history_panel;
+pub mod hud;
+pub mod instances;
+pub mod keys;
+pub mod layers;
+pub mod legend;
+pub mod measure;
+pub mod memory;
+pub mod minimap;
+pub mod net;
+pub mod normalize;
+pub mod picking;
+pub mod plugin;
+pub mod profile;
+pub mod render;
+pub mod replay;
+pub mod returns;
+pub mod schedule;
+pub mod session;
+pub mod settings;
+pub mod sizing;
+pub mod stereo;
+pub mod stereo_view;
+pub mod thinning;
+pub mod trace;
+pub mod trajectory;
+pub mod transition;
+pub mod units;
+pub mod vector;
+pub mod vector_overlay;
+pub mod views;
+pub mod volume;
+
+pub use cache::{LoadRequest, PointCache, SpatialReference};
+pub use plugin::CruxViewerPlugin;
+pub use settings::ViewerSettings;
@@ -0,0 +1,120 @@
+use bevy::{math::DVec3, prelude::*, window::PrimaryWindow};
+use bevy_panorbit_camera::PanOrbitCamera;
+
+use crate::{
+    frame::data_to_world,
+    keys::{Action, KeyBindings},
+    picking::pick_cursor,
+    units::Units,
+    PointCache, SpatialReference, ViewerSettings,
+};
+
+/// Distance measurement between two picked points
+#[derive(Resource, Default)]
+pub struct Measure {
+    pub active: bool,
+    points: Vec<DVec3>,
+}
+
+#[derive(Component)]
+pub struct MeasureText;
+
+pub fn setup_measure(mut commands: Commands) {
+    commands.spawn((
+        TextBundle::from_section("", TextStyle::default()).with_style(Style {
+            position_type: PositionType::Absolute,
+            bottom: Val::Px(12.0),
+            left: Val::Px(12.0),
+            ..default()
+        }),
+        MeasureText,
+    ));
+}
+
+// Press 'M' to measure, shift + click picks the end points
+#[allow(clippy::too_many_arguments)]
+pub fn measure_system(
+    key_input: Res<Input<KeyCode>>,
+    keys: Res<KeyBindings>,
+    mouse_input: Res<Input<MouseButton>>,
+    cache: Res<PointCache>,
+    settings: Res<ViewerSettings>,
+    sr: Res<SpatialReference>,
+    window: Query<&Window, With<PrimaryWindow>>,
+    camera: Query<(&Camera, &GlobalTransform, &Projection), With<PanOrbitCamera>>,
+    mut text: Query<&mut Text, With<MeasureText>>,
+    mut measure: ResMut<Measure>,
+    mut gizmos: Gizmos,
+) {
+    let (Ok(window), Ok((camera, transform, projection)), Ok(mut text)) = (
+        window.get_single(),
+        camera.get_single(),
+        text.get_single_mut(),
+    ) else {
+        return;
+    };
+
+    if keys.just_pressed(&key_input, Action::Measure) {
+        measure.active = !measure.active;
+        measure.points.clear();
+    }
+
+    let key = keys.label(Action::Measure);
+    if !measure.active {
+        if !text.sections[0].value.is_empty() {
+            text.sections[0].value.clear();
+        }
+        return;
+    }
+
+    let (Some(origin), Some(pc), Some(index)) = (
+        sr.origin,
+        cache.data.get(&settings.collection),
+        cache.index.get(&settings.collection),
+    ) else {
+        text.sections[0].value = format!("Measure ({key}): waiting for index");
+        return;
+    };
+
+    let shift = key_input.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]);
+    if shift && mouse_input.just_pressed(MouseButton::Left) {
+        let picked = window
+            .cursor_position()
+            .and_then(|cursor| pick_cursor(index, origin, (camera, transform, projection), cursor));
+
+        if let Some((_, p)) = picked {
+            if measure.points.len() == 2 {
+                measure.points.clear();
+            }
+            measure.points.push(p);
+        }
+    }
+
+    for p in &measure.points {
+        gizmos.sphere(
+            data_to_world(origin, *p),
+            Quat::IDENTITY,
+            0.1,
+            Color::YELLOW,
+        );
+    }
+
+    text.sections[0].value = match measure.points[..] {
+        [a, b] => {
+            gizmos.line(
+                data_to_world(origin, a),
+                data_to_world(origin, b),
+                Color::YELLOW,
+            );
+
+            let units = Units::new(pc.metadata().units, settings.units);
+            format!(
+                "Distance: {} (horizontal {}, vertical {})",
+                units.format(a.distance(b)),
+                units.format(a.truncate().distance(b.truncate())),
+                units.format((b.z - a.z).abs())
+            )
+        }
+        _ => format!("Measure ({key}): shift + click two points"),
+    };
+}
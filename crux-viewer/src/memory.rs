@@ -0,0 +1,183 @@
+use arrow::{array::Array, record_batch::RecordBatch};
+
+use crux_format::ArrowPointCloud;
+
+/// Bytes per MiB, the unit of the configured budget
+pub const MIB: usize = 1024 * 1024;
+
+/// Memory held by the arrays of a point cloud
+pub fn cloud_size(pc: &ArrowPointCloud) -> usize {
+    pc.store
+        .iter()
+        .flat_map(|e| pc.store.batches(e.key()))
+        .map(|batch| batch_size(&batch))
+        .sum()
+}
+
+/// Memory held by the arrays of a batch
+pub fn batch_size(batch: &RecordBatch) -> usize {
+    batch
+        .columns()
+        .iter()
+        .map(|column| column.get_array_memory_size())
+        .sum()
+}
+
+/// Separately accounted data of a cached collection
+///
+/// Levels derived from the points sort first, they are evicted before the
+/// points of the same collection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    /// Picking index and density grid, rebuilt from the points
+    Index,
+    /// The loaded points
+    Points,
+}
+
+/// Byte accounting of cached entries by collection and level in least
+/// recently rendered order
+#[derive(Debug, Default)]
+pub struct MemoryUsage {
+    /// Entries with their size, least recently rendered first
+    entries: Vec<(String, Level, usize)>,
+}
+
+impl MemoryUsage {
+    /// Account a new or replaced entry, its collection as most recently
+    /// rendered
+    pub fn insert(&mut self, collection: &str, level: Level, size: usize) {
+        self.remove(collection, level);
+        self.entries.push((collection.to_owned(), level, size));
+        self.touch(collection);
+    }
+
+    /// Mark the entries of a collection as most recently rendered
+    pub fn touch(&mut self, collection: &str) {
+        let (mut touched, rest) = std::mem::take(&mut self.entries)
+            .into_iter()
+            .partition::<Vec<_>, _>(|(c, _, _)| c == collection);
+        touched.sort_by_key(|(_, level, _)| *level);
+        self.entries = rest;
+        self.entries.append(&mut touched);
+    }
+
+    pub fn remove(&mut self, collection: &str, level: Level) {
+        self.entries
+            .retain(|(c, l, _)| (c.as_str(), *l) != (collection, level));
+    }
+
+    pub fn contains(&self, collection: &str, level: Level) -> bool {
+        self.entries
+            .iter()
+            .any(|(c, l, _)| (c.as_str(), *l) == (collection, level))
+    }
+
+    /// Total bytes accounted
+    pub fn total(&self) -> usize {
+        self.entries.iter().map(|(_, _, size)| size).sum()
+    }
+
+    /// Drop least recently rendered entries until the total fits `budget`.
+    ///
+    /// The entries of the `pinned` collections are never evicted, even if
+    /// they exceed the budget on their own. Returns the evicted collections
+    /// and levels.
+    pub fn evict(&mut self, budget: usize, pinned: &[&str]) -> Vec<(String, Level)> {
+        let mut total = self.total();
+        let mut evicted = Vec::new();
+
+        self.entries.retain(|(collection, level, size)| {
+            if total <= budget || pinned.contains(&collection.as_str()) {
+                return true;
+            }
+            total -= size;
+            evicted.push((collection.to_owned(), *level));
+            false
+        });
+
+        evicted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crux_format::prelude::*;
+
+    #[test]
+    fn accounting() {
+        let pc = ArrowPointCloud::from_iter(
+            (0..1000).map(|i| Point::<f64, 3>::from_slice(&[i as f64, 0., 0.])),
+        )
+        .unwrap();
+
+        // three f64 columns
+        let size = cloud_size(&pc);
+        assert!(size >= 3 * 1000 * 8, "{size}");
+        assert!(size < 2 * 3 * 1000 * 8, "{size}");
+    }
+
+    #[test]
+    fn evict_least_recently_rendered() {
+        use Level::Points;
+
+        let mut usage = MemoryUsage::default();
+        usage.insert("a", Points, 40);
+        usage.insert("b", Points, 30);
+        usage.insert("c", Points, 20);
+        usage.touch("a");
+        assert_eq!(usage.total(), 90);
+
+        // within budget
+        assert!(usage.evict(100, &["c"]).is_empty());
+
+        // b is the least recently rendered
+        assert_eq!(usage.evict(70, &["c"]), vec![("b".to_owned(), Points)]);
+        assert_eq!(usage.total(), 60);
+
+        // replacing an entry updates its size
+        usage.insert("c", Points, 50);
+        assert_eq!(usage.total(), 90);
+
+        // the displayed entry is kept even when over budget
+        assert_eq!(usage.evict(10, &["c"]), vec![("a".to_owned(), Points)]);
+        assert_eq!(usage.total(), 50);
+        assert!(usage.evict(0, &["c"]).is_empty());
+
+        usage.remove("c", Points);
+        assert_eq!(usage.total(), 0);
+
+        // so are both collections of a comparison
+        usage.insert("c", Points, 20);
+        usage.insert("d", Points, 10);
+        assert!(usage.evict(0, &["c", "d"]).is_empty());
+        assert_eq!(usage.total(), 30);
+    }
+
+    #[test]
+    fn evict_levels() {
+        use Level::{Index, Points};
+
+        let mut usage = MemoryUsage::default();
+        usage.insert("a", Points, 40);
+        usage.insert("a", Index, 20);
+        usage.insert("b", Points, 30);
+        assert_eq!(usage.total(), 90);
+
+        // the index of a goes first, the points of a stay loaded
+        assert_eq!(usage.evict(75, &["b"]), vec![("a".to_owned(), Index)]);
+        assert!(usage.contains("a", Points));
+        assert!(!usage.contains("a", Index));
+        assert_eq!(usage.total(), 70);
+
+        // a rebuilt index is evicted before the points again
+        usage.insert("a", Index, 20);
+        usage.touch("b");
+        assert_eq!(
+            usage.evict(0, &["b"]),
+            vec![("a".to_owned(), Index), ("a".to_owned(), Points)]
+        );
+        assert_eq!(usage.total(), 30);
+    }
+}
@@ -0,0 +1,506 @@
+use std::collections::HashMap;
+
+use bevy::{
+    math::{DVec2, DVec3},
+    prelude::*,
+    render::render_resource::{Extent3d, TextureDimension, TextureFormat},
+    tasks::{AsyncComputeTaskPool, Task},
+    ui::RelativeCursorPosition,
+};
+use bevy_panorbit_camera::PanOrbitCamera;
+use futures_lite::future::{block_on, poll_once};
+
+use crux_format::{prelude::*, CancelToken, ProgressSink};
+
+use crate::{
+    camera::query_bounds,
+    frame::{data_to_world, world_to_data},
+    PointCache, SpatialReference, ViewerSettings,
+};
+
+/// Maximum width and height of the minimap image in pixels
+const IMAGE_SIZE: u32 = 256;
+/// Maximum width and height of the minimap on screen in logical pixels
+const PANEL_SIZE: f32 = 200.;
+/// Distance of the minimap to the window border
+const PANEL_MARGIN: f32 = 12.;
+const FOOTPRINT_COLOR: Color = Color::YELLOW;
+/// Outline of the coverage gaps
+const GAP_COLOR: [u8; 4] = [255, 0, 0, 255];
+/// Empty cells of the smallest outlined gap
+const MIN_GAP_CELLS: usize = 4;
+/// Points rasterized between cancellation checks
+const CHUNK_SIZE: usize = 1 << 16;
+
+/// Top-down image of the highest point per pixel, north up
+#[derive(Debug, Clone, PartialEq)]
+pub struct HeightImage {
+    pub width: u32,
+    pub height: u32,
+    /// RGBA pixels row by row from north to south, transparent without points
+    pub data: Vec<u8>,
+    /// Covered extent in the data reference system
+    pub lower: DVec2,
+    pub upper: DVec2,
+}
+
+impl HeightImage {
+    /// Rasterize the points into square pixels, at most `max_size` along the
+    /// longer side of their extent, `None` without points or once cancelled
+    pub fn new(points: &[[f64; 3]], max_size: u32, progress: &dyn ProgressSink) -> Option<Self> {
+        let (lower, upper, z_min, z_max) = points.iter().fold(
+            (DVec2::MAX, DVec2::MIN, f64::MAX, f64::MIN),
+            |(lower, upper, z_min, z_max), [x, y, z]| {
+                let p = DVec2::new(*x, *y);
+                (lower.min(p), upper.max(p), z_min.min(*z), z_max.max(*z))
+            },
+        );
+        if points.is_empty() {
+            return None;
+        }
+
+        let extent = upper - lower;
+        let cell = match extent.max_element() / max_size as f64 {
+            cell if cell > 0. => cell,
+            _ => 1.,
+        };
+        let width = ((extent.x / cell).ceil() as u32).clamp(1, max_size);
+        let height = ((extent.y / cell).ceil() as u32).clamp(1, max_size);
+        let upper = lower + DVec2::new(width as f64, height as f64) * cell;
+
+        let mut heights = vec![f64::NAN; (width * height) as usize];
+        for (i, chunk) in points.chunks(CHUNK_SIZE).enumerate() {
+            if progress.is_cancelled() {
+                return None;
+            }
+            for [x, y, z] in chunk {
+                let column = (((x - lower.x) / cell) as u32).min(width - 1);
+                let row = (((upper.y - y) / cell) as u32).min(height - 1);
+                let pixel = &mut heights[(row * width + column) as usize];
+                if pixel.is_nan() || *z > *pixel {
+                    *pixel = *z;
+                }
+            }
+            progress.report(i * CHUNK_SIZE + chunk.len(), points.len());
+        }
+
+        let data = heights
+            .iter()
+            .flat_map(|z| {
+                if z.is_nan() {
+                    return [0; 4];
+                }
+                let t = if z_max > z_min {
+                    (z - z_min) / (z_max - z_min)
+                } else {
+                    1.
+                };
+                let shade = (64. + t * 191.) as u8;
+                [shade, shade, shade, 255]
+            })
+            .collect();
+
+        Some(Self {
+            width,
+            height,
+            data,
+            lower,
+            upper,
+        })
+    }
+
+    /// Position in the image relative to its size, (0, 0) is the north-west
+    /// and (1, 1) the south-east corner
+    pub fn relative(&self, p: DVec2) -> Vec2 {
+        let extent = self.upper - self.lower;
+        Vec2::new(
+            ((p.x - self.lower.x) / extent.x) as f32,
+            ((self.upper.y - p.y) / extent.y) as f32,
+        )
+    }
+
+    /// Data position of a relative position in the image, see [Self::relative]
+    pub fn position_at(&self, r: Vec2) -> DVec2 {
+        let extent = self.upper - self.lower;
+        DVec2::new(
+            self.lower.x + r.x as f64 * extent.x,
+            self.upper.y - r.y as f64 * extent.y,
+        )
+    }
+
+    /// Outline the pixels covering the box from `lower` to `upper` in the
+    /// data reference system with `color`, clamped to the image
+    pub fn outline(&mut self, lower: DVec2, upper: DVec2, color: [u8; 4]) {
+        let (width, height) = (self.width as f32, self.height as f32);
+        let pixel = |p: DVec2| {
+            let r = self.relative(p);
+            (r.x * width, r.y * height)
+        };
+        // north-west and south-east corner
+        let (x0, y0) = pixel(DVec2::new(lower.x, upper.y));
+        let (x1, y1) = pixel(DVec2::new(upper.x, lower.y));
+        let clamp = |v: f32, size: u32| (v.max(0.) as u32).min(size - 1);
+        let (c0, r0) = (clamp(x0, self.width), clamp(y0, self.height));
+        let (c1, r1) = (
+            clamp(x1.ceil() - 1., self.width).max(c0),
+            clamp(y1.ceil() - 1., self.height).max(r0),
+        );
+
+        for row in r0..=r1 {
+            for column in c0..=c1 {
+                if row == r0 || row == r1 || column == c0 || column == c1 {
+                    let i = ((row * self.width + column) * 4) as usize;
+                    self.data[i..i + 4].copy_from_slice(&color);
+                }
+            }
+        }
+    }
+
+    /// Size on screen, the longer side spans `PANEL_SIZE`
+    fn panel_size(&self) -> Vec2 {
+        let scale = PANEL_SIZE / self.width.max(self.height) as f32;
+        Vec2::new(self.width as f32, self.height as f32) * scale
+    }
+}
+
+/// Footprint of the camera, the query box around the focus, as relative
+/// corners of the minimap clamped to the image
+pub fn footprint(image: &HeightImage, origin: DVec3, focus: Vec3, radius: f32) -> (Vec2, Vec2) {
+    let (lower, upper) = query_bounds(origin, focus, radius);
+    let a = image.relative(lower.truncate());
+    let b = image.relative(upper.truncate());
+    (
+        a.min(b).clamp(Vec2::ZERO, Vec2::ONE),
+        a.max(b).clamp(Vec2::ZERO, Vec2::ONE),
+    )
+}
+
+/// Minimap of a loaded collection
+struct CollectionMap {
+    generation: usize,
+    image: HeightImage,
+    handle: Handle<Image>,
+}
+
+#[derive(Resource, Default)]
+pub struct Minimap {
+    maps: HashMap<String, CollectionMap>,
+}
+
+/// Background rasterization of the minimap of a collection
+#[derive(Component)]
+pub struct MinimapTask {
+    collection: String,
+    generation: usize,
+    task: Task<Option<HeightImage>>,
+    /// Cancelled once the data is replaced before the image is done
+    token: CancelToken,
+}
+
+#[derive(Component)]
+pub struct MinimapImage;
+
+#[derive(Component)]
+pub struct MinimapFootprint;
+
+pub fn setup_minimap(mut commands: Commands) {
+    commands
+        .spawn((
+            ImageBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    top: Val::Px(PANEL_MARGIN),
+                    right: Val::Px(PANEL_MARGIN),
+                    width: Val::Px(PANEL_SIZE),
+                    height: Val::Px(PANEL_SIZE),
+                    ..default()
+                },
+                background_color: Color::rgba(0., 0., 0., 0.5).into(),
+                visibility: Visibility::Hidden,
+                ..default()
+            },
+            RelativeCursorPosition::default(),
+            MinimapImage,
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                NodeBundle {
+                    style: Style {
+                        position_type: PositionType::Absolute,
+                        border: UiRect::all(Val::Px(1.)),
+                        ..default()
+                    },
+                    border_color: FOOTPRINT_COLOR.into(),
+                    ..default()
+                },
+                MinimapFootprint,
+            ));
+        });
+}
+
+/// Start rasterizing the minimap of freshly loaded data, once per load
+pub fn spawn_minimap_task(
+    commands: &mut Commands,
+    cache: &PointCache,
+    settings: &ViewerSettings,
+    collection: &str,
+) {
+    let Some(pc) = cache.data.get(collection) else {
+        return;
+    };
+    let generation = cache
+        .generation
+        .get(collection)
+        .copied()
+        .unwrap_or_default();
+
+    let points: Vec<[f64; 3]> = pc
+        .points::<Point<f64, 3>>()
+        .map(|p| [p.x(), p.y(), p.z()])
+        .collect();
+
+    let token = CancelToken::new();
+    let progress = token.clone();
+    let gap_cell = settings.minimap_gaps;
+    let task = AsyncComputeTaskPool::get().spawn(async move {
+        let mut image = HeightImage::new(&points, IMAGE_SIZE, &progress)?;
+        if let Some(cell) = gap_cell {
+            let pc =
+                ArrowPointCloud::from_iter(points.iter().map(|p| Point::<f64, 3>::from_slice(p)))
+                    .ok()?;
+            match pc.coverage_gaps_with(cell, MIN_GAP_CELLS, &progress) {
+                Ok(gaps) => {
+                    for gap in gaps {
+                        let (lower, upper) = (gap.lower(), gap.upper());
+                        image.outline(
+                            DVec2::new(lower.x(), lower.y()),
+                            DVec2::new(upper.x(), upper.y()),
+                            GAP_COLOR,
+                        );
+                    }
+                }
+                Err(_) if progress.is_cancelled() => return None,
+                Err(e) => warn!("No coverage gaps on the minimap: {e}"),
+            }
+        }
+        Some(image)
+    });
+
+    commands.spawn(MinimapTask {
+        collection: collection.to_owned(),
+        generation,
+        task,
+        token,
+    });
+}
+
+pub fn handle_minimap_task(
+    mut commands: Commands,
+    mut tasks: Query<(Entity, &mut MinimapTask)>,
+    cache: Res<PointCache>,
+    mut minimap: ResMut<Minimap>,
+    mut images: ResMut<Assets<Image>>,
+) {
+    for (entity, mut task) in &mut tasks {
+        // discard images of replaced data
+        let replaced = cache.generation.get(&task.collection) != Some(&task.generation);
+        if replaced {
+            task.token.cancel();
+        }
+
+        let Some(image) = block_on(poll_once(&mut task.task)) else {
+            continue;
+        };
+        commands.entity(entity).despawn();
+
+        if replaced {
+            continue;
+        }
+        let Some(image) = image else {
+            minimap.maps.remove(&task.collection);
+            continue;
+        };
+
+        let handle = images.add(Image::new(
+            Extent3d {
+                width: image.width,
+                height: image.height,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            image.data.clone(),
+            TextureFormat::Rgba8UnormSrgb,
+        ));
+        // the image of the previous load is dropped with its handle
+        minimap.maps.insert(
+            task.collection.to_owned(),
+            CollectionMap {
+                generation: task.generation,
+                image,
+                handle,
+            },
+        );
+    }
+}
+
+// The minimap of the shown collection with the camera footprint, click to jump
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+pub fn minimap_system(
+    mouse_input: Res<Input<MouseButton>>,
+    cache: Res<PointCache>,
+    settings: Res<ViewerSettings>,
+    sr: Res<SpatialReference>,
+    minimap: Res<Minimap>,
+    mut camera: Query<&mut PanOrbitCamera>,
+    mut panel: Query<
+        (
+            &mut Style,
+            &mut UiImage,
+            &mut Visibility,
+            &RelativeCursorPosition,
+        ),
+        (With<MinimapImage>, Without<MinimapFootprint>),
+    >,
+    mut footprint_node: Query<&mut Style, (With<MinimapFootprint>, Without<MinimapImage>)>,
+) {
+    let (Ok(mut camera), Ok((mut style, mut ui_image, mut visibility, cursor)), Ok(mut frame)) = (
+        camera.get_single_mut(),
+        panel.get_single_mut(),
+        footprint_node.get_single_mut(),
+    ) else {
+        return;
+    };
+
+    // evicted collections keep their map until they are loaded again
+    let map = minimap
+        .maps
+        .get(&settings.collection)
+        .filter(|_| cache.data.contains_key(&settings.collection))
+        .filter(|map| cache.generation.get(&settings.collection) == Some(&map.generation));
+    let (Some(map), Some(origin)) = (map, sr.origin) else {
+        *visibility = Visibility::Hidden;
+        return;
+    };
+    *visibility = Visibility::Inherited;
+
+    if ui_image.texture != map.handle {
+        ui_image.texture = map.handle.clone();
+        let size = map.image.panel_size();
+        style.width = Val::Px(size.x);
+        style.height = Val::Px(size.y);
+    }
+
+    // current footprint
+    let (a, b) = footprint(
+        &map.image,
+        origin,
+        camera.focus,
+        camera.radius.unwrap_or(1.),
+    );
+    frame.left = Val::Percent(a.x * 100.);
+    frame.top = Val::Percent(a.y * 100.);
+    frame.width = Val::Percent((b.x - a.x) * 100.);
+    frame.height = Val::Percent((b.y - a.y) * 100.);
+
+    // fly to the clicked position, keeping the height of the focus
+    if mouse_input.just_pressed(MouseButton::Left) && cursor.mouse_over() {
+        if let Some(relative) = cursor.normalized {
+            let target = map.image.position_at(relative);
+            let height = world_to_data(origin, camera.focus).z;
+            camera.target_focus = data_to_world(origin, target.extend(height));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn raster() {
+        // 4 x 2 units, north-east corner highest
+        let points = [
+            [0., 0., 1.],
+            [4., 0., 2.],
+            [0., 2., 3.],
+            [4., 2., 5.],
+            [3.9, 1.9, 4.],
+        ];
+        let image = HeightImage::new(&points, 4, &()).unwrap();
+        assert_eq!((image.width, image.height), (4, 2));
+        assert_eq!(image.lower, DVec2::new(0., 0.));
+        assert_eq!(image.upper, DVec2::new(4., 2.));
+
+        let pixel = |column: u32, row: u32| {
+            let i = ((row * image.width + column) * 4) as usize;
+            image.data[i..i + 4].to_vec()
+        };
+        // the highest point per pixel, shaded by height
+        assert_eq!(pixel(3, 0), [255, 255, 255, 255]);
+        assert_eq!(pixel(0, 1), [64, 64, 64, 255]);
+        assert_eq!(pixel(0, 0)[0], 64 + (2. / 4. * 191.) as u8);
+        // no points
+        assert_eq!(pixel(1, 0), [0; 4]);
+
+        assert!(HeightImage::new(&[], 4, &()).is_none());
+        let single = HeightImage::new(&[[1., 1., 1.]], 4, &()).unwrap();
+        assert_eq!((single.width, single.height), (1, 1));
+        assert_eq!(single.data, [255; 4]);
+    }
+
+    #[test]
+    fn outline() {
+        // 8 x 8 pixels of a unit each
+        let mut image = HeightImage::new(&[[0., 0., 0.], [8., 8., 0.]], 8, &()).unwrap();
+        image.outline(DVec2::new(2., 1.), DVec2::new(6., 4.), GAP_COLOR);
+
+        let red: Vec<(u32, u32)> = (0..64)
+            .filter(|i| image.data[i * 4..i * 4 + 4] == GAP_COLOR)
+            .map(|i| (i as u32 % 8, i as u32 / 8))
+            .collect();
+        // rows 4 to 6 from the north, columns 2 to 5
+        assert_eq!(red.len(), 2 * 4 + 2);
+        assert!(red.contains(&(2, 4)) && red.contains(&(5, 6)));
+        assert!(!red.contains(&(3, 5)));
+
+        // clamped to the image
+        image.outline(DVec2::new(-10., -10.), DVec2::new(20., 20.), [1; 4]);
+        assert_eq!(image.data[..4], [1; 4]);
+        assert_eq!(image.data[63 * 4..], [1; 4]);
+    }
+
+    #[test]
+    fn cancel() {
+        let points = [[0., 0., 0.], [1., 1., 1.]];
+        let token = CancelToken::new();
+        assert!(HeightImage::new(&points, 4, &token).is_some());
+        token.cancel();
+        assert!(HeightImage::new(&points, 4, &token).is_none());
+    }
+
+    #[test]
+    fn relative() {
+        let image = HeightImage::new(&[[100., 200., 0.], [140., 220., 0.]], 8, &()).unwrap();
+        assert_eq!(image.relative(DVec2::new(100., 220.)), Vec2::ZERO);
+        assert_eq!(image.relative(DVec2::new(140., 200.)), Vec2::ONE);
+
+        for r in [Vec2::new(0.25, 0.5), Vec2::new(1., 0.)] {
+            assert_eq!(image.relative(image.position_at(r)), r);
+        }
+    }
+
+    #[test]
+    fn camera_footprint() {
+        let image = HeightImage::new(&[[100., 200., 0.], [140., 220., 0.]], 8, &()).unwrap();
+        let origin = DVec3::new(120., 210., 0.);
+
+        // focus 5 units east and north of the center, north is -z in Bevy
+        let (a, b) = footprint(&image, origin, Vec3::new(5., 0., -5.), 10.);
+        assert_eq!(a, image.relative(DVec2::new(120., 220.)));
+        assert_eq!(b, image.relative(DVec2::new(130., 210.)));
+
+        // clamped to the image
+        let (a, b) = footprint(&image, origin, Vec3::ZERO, 1000.);
+        assert_eq!((a, b), (Vec2::ZERO, Vec2::ONE));
+    }
+}
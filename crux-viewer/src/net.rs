@@ -0,0 +1,463 @@
+//! Loads of point clouds from the server, scheduled from the [LoadRequest]
+//! events of the controls, the tools and embedding apps
+
+use std::{
+    path::Path,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use bevy::{
+    prelude::*,
+    tasks::{AsyncComputeTaskPool, Task},
+};
+use bevy_panorbit_camera::PanOrbitCamera;
+use bytes::Bytes;
+use futures_lite::future::{self, block_on};
+
+use crux_format::prelude::*;
+
+use crate::{
+    camera::query_bounds,
+    compare,
+    fetch::{
+        self, bounds_url, is_preview, overview_url, points_url, FetchError, Fetched, LoadState,
+        LoadStats, RetryPolicy, Thinning,
+    },
+    history,
+    hud::{Hud, QueryStats},
+    instances::{InstanceLimit, HEIGHT_ATTRIBUTE},
+    keys::{Action, KeyBindings},
+    memory::{self, Level, MIB},
+    minimap, picking,
+    returns::{self, RETURNS_ATTRIBUTE},
+    schedule::{LoadQueue, QueuedLoad},
+    session::{SessionEvent, SessionRecorder, SessionReplay},
+    LoadRequest, PointCache, SpatialReference, ViewerSettings,
+};
+
+/// Time the camera has to rest before the view is refined automatically
+const AUTO_LOD_DELAY: Duration = Duration::from_secs(1);
+
+/// Loads of the [LoadRequest] events, at most `max_loads` at once, and the
+/// load controls
+pub struct NetPlugin;
+
+impl Plugin for NetPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<LoadQueue>()
+            .add_systems(Update, load_controll_system)
+            .add_systems(Update, spawn_load_task)
+            .add_systems(Update, handle_load_task)
+            .add_systems(Update, auto_lod_system);
+    }
+}
+
+/// Decoded points of a modified response
+pub(crate) struct Loaded {
+    pc: ArrowPointCloud,
+    etag: Option<String>,
+    /// Response body and digest of the points, kept for the session
+    body: Option<Bytes>,
+    digest: Option<u64>,
+    /// Reduction of the points on decode, if configured
+    stats: Option<LoadStats>,
+}
+
+/// Load of a collection from the server
+#[derive(Component)]
+pub struct LoadTask {
+    task: Task<Result<Option<Loaded>, FetchError>>,
+    pub(crate) collection: String,
+    url: String,
+    /// Request id, see [RequestIds](crate::fetch::RequestIds)
+    request: u64,
+    pub(crate) state: Arc<LoadState>,
+    /// Time the load was spawned, for its latency
+    started: Instant,
+}
+
+pub fn spawn_load_task(
+    mut commands: Commands,
+    mut requests: EventReader<LoadRequest>,
+    mut cache: ResMut<PointCache>,
+    mut queue: ResMut<LoadQueue>,
+    mut settings: ResMut<ViewerSettings>,
+    running: Query<&LoadTask>,
+    (mut recorder, replay): (Option<ResMut<SessionRecorder>>, Option<Res<SessionReplay>>),
+) {
+    // replays issue the recorded loads only
+    let urls: Vec<String> = requests
+        .read()
+        .map(|request| request.url.to_owned())
+        .collect();
+    if replay.is_none() && !urls.is_empty() {
+        // superseded loads of a collection are cancelled
+        for task in running.iter().filter(|t| {
+            t.collection == settings.collection || settings.compare.as_ref() == Some(&t.collection)
+        }) {
+            task.state.abandon();
+        }
+
+        // the compared collection is loaded with the same query, in the
+        // same response
+        for url in urls {
+            // the history is persisted with the next save, without a redraw,
+            // previews are followed by the recorded load
+            if !is_preview(&url) {
+                history::record(&mut settings.bypass_change_detection().history, &url);
+            }
+
+            let url = compare::combined_url(&url, &settings).unwrap_or(url);
+            let collection = settings.collection.to_owned();
+            let request = cache.requests.issue(&collection);
+            queue.push(QueuedLoad {
+                collection,
+                url,
+                request,
+            });
+        }
+    }
+
+    // responses to superseded loads would be discarded anyway
+    if !queue.is_empty() {
+        let requests = &cache.requests;
+        queue.retain(|load| requests.is_latest(&load.collection, load.request));
+    }
+
+    let loads = queue.next(running.iter().count(), settings.max_loads);
+    if loads.is_empty() {
+        return;
+    }
+
+    let thread_pool = AsyncComputeTaskPool::get();
+    let policy = RetryPolicy::new(Duration::from_secs(settings.request_timeout));
+    for QueuedLoad {
+        collection,
+        url,
+        request,
+    } in loads
+    {
+        let state = Arc::new(LoadState::default());
+
+        // Spawn new task on the AsyncComputeTaskPool; the task will be
+        // executed in the background, and the Task future returned by
+        // spawn() can be used to poll for the result
+        // revalidate the cached points if they were loaded from the same URL
+        let etag = cache
+            .etags
+            .get(&collection)
+            .filter(|(cached, _)| *cached == url)
+            .map(|(_, etag)| etag.to_owned());
+
+        if let Some(recorder) = recorder.as_mut() {
+            recorder.record(SessionEvent::Query {
+                collection: collection.to_owned(),
+                url: url.to_owned(),
+            });
+        }
+        let recorded = replay
+            .as_ref()
+            .and_then(|replay| replay.body(&url))
+            .map(Path::to_path_buf);
+        let digest = recorder.is_some() || replay.is_some();
+        let keep_body = recorder.as_ref().is_some_and(|r| r.records_bodies());
+        let thinning = Thinning::from_settings(&settings);
+
+        let task = thread_pool.spawn({
+            let (url, state) = (url.clone(), state.clone());
+            let collection = collection.clone();
+            async move {
+                let _span = info_span!("load", collection).entered();
+
+                // get pointcloud
+                let rt = tokio::runtime::Builder::new_current_thread()
+                    .enable_all()
+                    .build()
+                    .unwrap();
+
+                let fetched = match recorded {
+                    // the recorded response of a replayed load
+                    Some(path) => Fetched::Modified {
+                        body: std::fs::read(&path)
+                            .map_err(|e| FetchError::Failed {
+                                attempts: 1,
+                                error: format!("{path:?}: {e}"),
+                            })?
+                            .into(),
+                        etag: None,
+                    },
+                    None => info_span!("fetch", url).in_scope(|| {
+                        rt.block_on(fetch::fetch(&url, etag.as_deref(), &policy, &state))
+                    })?,
+                };
+                let Fetched::Modified { body, etag } = fetched else {
+                    return Ok(None);
+                };
+                let (pc, stats) = match &thinning {
+                    Some(thinning) => {
+                        let (pc, stats) = fetch::decode_thinned(body.clone(), thinning)?;
+                        (pc, Some(stats))
+                    }
+                    None => (fetch::decode(body.clone())?, None),
+                };
+                Ok(Some(Loaded {
+                    digest: digest.then(|| pc.digest().content_hash),
+                    body: keep_body.then_some(body),
+                    pc,
+                    etag,
+                    stats,
+                }))
+            }
+        });
+
+        // Spawn new entity and add our new task as a component
+        commands.spawn(LoadTask {
+            task,
+            collection,
+            url,
+            request,
+            state,
+            started: Instant::now(),
+        });
+    }
+}
+
+pub fn handle_load_task(
+    mut commands: Commands,
+    mut load_tasks: Query<(Entity, &mut LoadTask)>,
+    mut cache: ResMut<PointCache>,
+    mut requests: EventWriter<LoadRequest>,
+    settings: Res<ViewerSettings>,
+    (mut recorder, mut replay): (
+        Option<ResMut<SessionRecorder>>,
+        Option<ResMut<SessionReplay>>,
+    ),
+    mut hud: ResMut<Hud>,
+) {
+    for (entity, mut task) in &mut load_tasks {
+        let Some(result) = block_on(future::poll_once(&mut task.task)) else {
+            continue;
+        };
+        // Task is complete, so remove task component from entity
+        commands.entity(entity).remove::<LoadTask>();
+
+        // a newer load of the collection was issued while this one ran
+        if !cache.requests.is_latest(&task.collection, task.request) {
+            if matches!(result, Ok(Some(_))) {
+                info!("Discarded stale response for `{}`", task.collection);
+                cache.bypass_change_detection().discarded =
+                    Some((task.collection.to_owned(), Instant::now()));
+            }
+            continue;
+        }
+
+        if let Ok(loaded) = &result {
+            hud.last_query = Some(QueryStats {
+                url: task.url.to_owned(),
+                latency: task.started.elapsed(),
+                points: loaded.as_ref().map(|loaded| loaded.pc.num_points()),
+                thinned: loaded
+                    .as_ref()
+                    .and_then(|loaded| loaded.stats)
+                    .filter(LoadStats::is_reduced),
+            });
+        }
+
+        // the preview is refined by the requested load, also if there is none
+        if is_preview(&task.url) {
+            if let Some(url) = cache.refine.remove(&task.collection) {
+                requests.send(LoadRequest::new(url));
+            }
+        }
+
+        let Loaded {
+            pc,
+            etag,
+            body,
+            digest,
+            stats,
+        } = match result {
+            Ok(Some(loaded)) => loaded,
+            Ok(None) => {
+                info!("`{}` is unchanged", task.collection);
+                // the index may have been evicted while the points were kept
+                if !cache.memory.contains(&task.collection, Level::Index) {
+                    picking::spawn_index_task(&mut commands, &mut cache, &task.collection);
+                }
+                continue;
+            }
+            Err(FetchError::Abandoned) => continue,
+            Err(e) => {
+                warn!("Failed to load `{}`: {e}", task.collection);
+                cache.failed = Some((task.collection.to_owned(), e.to_string(), Instant::now()));
+                continue;
+            }
+        };
+
+        if let Some(stats) = stats.filter(LoadStats::is_reduced) {
+            info!("`{}` {}", task.collection, stats.status());
+        }
+
+        if let (Some(recorder), Some(digest)) = (recorder.as_mut(), digest) {
+            let body = body.and_then(|body| recorder.record_body(&body));
+            recorder.record(SessionEvent::Response {
+                collection: task.collection.to_owned(),
+                url: task.url.to_owned(),
+                points: pc.num_points(),
+                digest,
+                body,
+            });
+        }
+        if let (Some(replay), Some(digest)) = (replay.as_mut(), digest) {
+            replay.verify(&task.url, pc.num_points(), digest);
+        }
+
+        // a comparison is loaded in one response
+        let parts = match info_span!("split").in_scope(|| fetch::split_collections(&pc)) {
+            Ok(Some(parts)) => parts,
+            Ok(None) => vec![(task.collection.to_owned(), pc)],
+            Err(e) => {
+                warn!("Failed to load `{}`: {e}", task.collection);
+                continue;
+            }
+        };
+        for (collection, pc) in parts {
+            let size = memory::cloud_size(&pc);
+            cache.memory.insert(&collection, Level::Points, size);
+            cache.data.insert(collection.to_owned(), Arc::new(pc));
+            // revalidated with the load of the collection
+            match (&etag, collection == task.collection) {
+                (Some(etag), true) => cache.etags.insert(
+                    collection.to_owned(),
+                    (task.url.to_owned(), etag.to_owned()),
+                ),
+                _ => cache.etags.remove(&collection),
+            };
+            picking::spawn_index_task(&mut commands, &mut cache, &collection);
+            minimap::spawn_minimap_task(&mut commands, &cache, &settings, &collection);
+        }
+
+        // evict least recently rendered data, except the displayed collections
+        let budget = settings.memory_budget * MIB;
+        let mut pinned = vec![settings.collection.as_str()];
+        pinned.extend(settings.compare.as_deref());
+        for (collection, level) in cache.memory.evict(budget, &pinned) {
+            info!("Evicted {level:?} of `{collection}` from the point cache");
+            // the index is evicted before the points it was built from
+            if level == Level::Points {
+                cache.data.remove(&collection);
+                cache.etags.remove(&collection);
+            }
+            cache.index.remove(&collection);
+            cache.density.remove(&collection);
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn load_controll_system(
+    key_input: Res<Input<KeyCode>>,
+    keys: Res<KeyBindings>,
+    mut cache: ResMut<PointCache>,
+    mut requests: EventWriter<LoadRequest>,
+    mut settings: ResMut<ViewerSettings>,
+    sr: Res<SpatialReference>,
+    camera: Query<&PanOrbitCamera>,
+    mut limit: ResMut<InstanceLimit>,
+) {
+    let shift = key_input.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]);
+
+    // get p=0.0001
+    if keys.just_pressed(&key_input, Action::LoadP0001) {
+        cache.load(&settings, overview_url(&settings, 0.0001), &mut requests);
+    }
+
+    // get p=0.001
+    if keys.just_pressed(&key_input, Action::LoadP001) {
+        cache.load(&settings, overview_url(&settings, 0.001), &mut requests);
+    }
+    // get p=0.01
+    if keys.just_pressed(&key_input, Action::LoadP01) {
+        cache.load(&settings, overview_url(&settings, 0.01), &mut requests);
+    }
+    // get p=0.1
+    if keys.just_pressed(&key_input, Action::LoadP1) {
+        cache.load(&settings, overview_url(&settings, 0.1), &mut requests);
+    }
+    // get full dataset, with shift render all loaded points
+    if keys.just_pressed(&key_input, Action::LoadFull) {
+        if shift {
+            limit.force_full = !limit.force_full;
+        } else {
+            cache.load(&settings, points_url(&settings, ""), &mut requests);
+        }
+    }
+    // update
+    if keys.just_pressed(&key_input, Action::RefineView) {
+        let camera = camera.get_single().unwrap();
+        requests.send(LoadRequest::new(refine_url(&settings, &sr, camera)));
+    }
+    // toggle automatic refinement
+    if keys.just_pressed(&key_input, Action::ToggleAutoLod) {
+        settings.auto_lod = !settings.auto_lod;
+    }
+    // uniform or density adaptive point sizes
+    if keys.just_pressed(&key_input, Action::PointSizing) {
+        settings.point_sizing = settings.point_sizing.next();
+    }
+    // returns coloring and filtering, if available
+    if cache
+        .get(&settings.collection)
+        .is_some_and(returns::has_returns)
+    {
+        if keys.just_pressed(&key_input, Action::ColorByReturns) {
+            settings.color_attribute = if settings.color_attribute == RETURNS_ATTRIBUTE {
+                HEIGHT_ATTRIBUTE.to_string()
+            } else {
+                RETURNS_ATTRIBUTE.to_string()
+            };
+        }
+        if keys.just_pressed(&key_input, Action::CycleReturns) {
+            settings.returns_filter = settings.returns_filter.next();
+        }
+    }
+}
+
+/// Bounds query around the camera focus with density adapted to the radius
+fn refine_url(settings: &ViewerSettings, sr: &SpatialReference, camera: &PanOrbitCamera) -> String {
+    let radius = camera.radius.unwrap_or(1.);
+    let (lower, upper) = query_bounds(sr.origin.unwrap_or(sr.camera), camera.focus, radius);
+    let radius = radius as f64;
+
+    bounds_url(settings, lower, upper, 1. / radius.sqrt() / 1000.)
+}
+
+// Refine the view once the camera comes to rest
+pub fn auto_lod_system(
+    time: Res<Time>,
+    settings: Res<ViewerSettings>,
+    mut requests: EventWriter<LoadRequest>,
+    sr: Res<SpatialReference>,
+    camera: Query<Ref<PanOrbitCamera>>,
+    mut rest: Local<Option<Duration>>,
+) {
+    let Ok(camera) = camera.get_single() else {
+        return;
+    };
+
+    if !settings.auto_lod || sr.origin.is_none() {
+        *rest = None;
+        return;
+    }
+
+    if camera.is_changed() {
+        *rest = Some(Duration::ZERO);
+    } else if let Some(elapsed) = rest.as_mut() {
+        *elapsed += time.delta();
+        if *elapsed >= AUTO_LOD_DELAY {
+            requests.send(LoadRequest::new(refine_url(&settings, &sr, &camera)));
+            *rest = None;
+        }
+    }
+}
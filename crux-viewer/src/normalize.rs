@@ -0,0 +1,136 @@
+use bevy::prelude::*;
+
+pub use crux_format::color::Normalization;
+use crux_format::{
+    color::{self, Bounds, ColorMap},
+    ArrowPointCloud, PointCloudError,
+};
+
+use crate::{
+    keys::{Action, KeyBindings},
+    ViewerSettings,
+};
+
+/// Color of points without a value of the colored attribute
+pub const NO_DATA_COLOR: Color = Color::rgb(0.25, 0.25, 0.3);
+
+/// Step of the stretch bounds in percent
+const PERCENTILE_STEP: f64 = 1.;
+/// Factor of a gamma step
+const GAMMA_STEP: f64 = 1.1;
+
+pub fn to_color([r, g, b, a]: [u8; 4]) -> Color {
+    Color::rgba_u8(r, g, b, a)
+}
+
+/// Colors of an attribute by `map`, null values are drawn in `NO_DATA_COLOR`.
+/// Returns the stretch bounds of gradients in attribute units.
+pub fn attribute_colors(
+    pc: &ArrowPointCloud,
+    attribute: &str,
+    map: &ColorMap,
+) -> Result<(Vec<Color>, Option<Bounds>), PointCloudError> {
+    let (colors, bounds) = color::colors(pc, attribute, map)?;
+
+    let colors = colors
+        .into_iter()
+        .map(|c| c.map_or(NO_DATA_COLOR, to_color))
+        .collect();
+
+    Ok((colors, bounds))
+}
+
+/// Stretch bounds of the colored attribute, for display
+#[derive(Resource, Default)]
+pub struct ScaleBounds(pub Option<(String, f64, f64)>);
+
+// Adjust the lower ([ ]) and, with shift, upper stretch percentile and gamma (- =)
+pub fn normalization_controls_system(
+    key_input: Res<Input<KeyCode>>,
+    keys: Res<KeyBindings>,
+    mut settings: ResMut<ViewerSettings>,
+) {
+    let shift = key_input.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]);
+
+    let step = if keys.just_pressed(&key_input, Action::StretchDown) {
+        -PERCENTILE_STEP
+    } else if keys.just_pressed(&key_input, Action::StretchUp) {
+        PERCENTILE_STEP
+    } else {
+        0.
+    };
+
+    if step != 0. {
+        let n = &mut settings.normalization;
+        if shift {
+            n.upper = (n.upper + step).clamp(n.lower + PERCENTILE_STEP, 100.);
+        } else {
+            n.lower = (n.lower + step).clamp(0., n.upper - PERCENTILE_STEP);
+        }
+    }
+
+    if keys.just_pressed(&key_input, Action::GammaDown) {
+        settings.normalization.gamma /= GAMMA_STEP;
+    }
+    if keys.just_pressed(&key_input, Action::GammaUp) {
+        settings.normalization.gamma *= GAMMA_STEP;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow::{
+        array::{ArrayRef, Float64Array, UInt16Array},
+        datatypes::{DataType, Field, Schema},
+        record_batch::RecordBatch,
+    };
+    use crux_format::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn null_colors() {
+        // intensity is null for every second point
+        let mut fields: Vec<Field> = Point::<f64, 3>::schema()
+            .fields()
+            .iter()
+            .map(|field| field.as_ref().clone())
+            .collect();
+        fields.push(Field::new("intensity", DataType::UInt16, true));
+        let schema = Arc::new(Schema::new(fields));
+
+        let coordinate =
+            || Arc::new(Float64Array::from_iter_values((0..100).map(f64::from))) as ArrayRef;
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                coordinate(),
+                coordinate(),
+                coordinate(),
+                Arc::new(UInt16Array::from_iter(
+                    (0..100).map(|i| (i % 2 == 0).then_some(i)),
+                )),
+            ],
+        )
+        .unwrap();
+        let mut pc = ArrowPointCloud::try_new(schema).unwrap();
+        pc.append(batch).unwrap();
+
+        let map =
+            ColorMap::for_attribute("intensity", colorgrad::turbo(), Normalization::default());
+        let (colors, bounds) = attribute_colors(&pc, "intensity", &map).unwrap();
+        let bounds = bounds.unwrap();
+
+        assert_eq!(colors.len(), 100);
+        for (i, color) in colors.iter().enumerate() {
+            assert_eq!(*color == NO_DATA_COLOR, i % 2 == 1, "{i}");
+        }
+        assert_eq!(colors[0], Color::rgb(0., 0., 0.));
+        assert_eq!(colors[98], Color::rgb(1., 1., 1.));
+
+        // bounds of the valid values only
+        assert!(bounds.0 >= 0. && bounds.1 <= 98., "{bounds:?}");
+    }
+}
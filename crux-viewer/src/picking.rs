@@ -0,0 +1,288 @@
+use std::{sync::Arc, time::Duration};
+
+use arrow::util::display::array_value_to_string;
+use bevy::{
+    math::DVec3,
+    prelude::*,
+    tasks::{AsyncComputeTaskPool, Task},
+    window::PrimaryWindow,
+};
+use bevy_panorbit_camera::PanOrbitCamera;
+use futures_lite::future::{block_on, poll_once};
+use rstar::{primitives::GeomWithData, RTree};
+
+use crux_format::prelude::*;
+
+use crate::{
+    frame::world_to_data,
+    instances::color_attribute,
+    layers::COLLECTION_ATTRIBUTE,
+    memory::Level,
+    sizing::{DensityGrid, PointSizing},
+    PointCache, SpatialReference, ViewerSettings,
+};
+
+/// Time the cursor has to rest before the hover tooltip is shown
+const HOVER_DELAY: Duration = Duration::from_millis(300);
+/// Number of depths sampled along the picking ray
+const RAY_SAMPLES: usize = 64;
+/// Maximum distance of a picked point to the ray relative to its depth
+const RAY_TOLERANCE: f64 = 0.01;
+
+/// Spatial index over point positions (data reference system) and row numbers
+pub type PickIndex = RTree<GeomWithData<[f64; 3], usize>>;
+
+/// Background construction of the picking index and density grid of a
+/// collection
+#[derive(Component)]
+pub struct IndexTask {
+    collection: String,
+    generation: usize,
+    task: Task<(PickIndex, Option<DensityGrid>)>,
+}
+
+/// Start building the picking index and density grid for freshly loaded data
+pub fn spawn_index_task(commands: &mut Commands, cache: &mut PointCache, collection: &str) {
+    let Some(pc) = cache.data.get(collection).cloned() else {
+        return;
+    };
+
+    let generation = cache.generation.entry(collection.to_owned()).or_default();
+    *generation += 1;
+
+    cache.index.remove(collection);
+    cache.density.remove(collection);
+    cache
+        .memory
+        .insert(collection, Level::Index, index_size(pc.num_points()));
+
+    // the points are read off the main thread
+    let task = AsyncComputeTaskPool::get().spawn(async move {
+        let points: Vec<GeomWithData<[f64; 3], usize>> = pc
+            .points::<Point<f64, 3>>()
+            .enumerate()
+            .map(|(i, p)| GeomWithData::new([p.x(), p.y(), p.z()], i))
+            .collect();
+        let coords: Vec<[f64; 3]> = points.iter().map(|p| *p.geom()).collect();
+        (RTree::bulk_load(points), DensityGrid::new(&coords))
+    });
+
+    commands.spawn(IndexTask {
+        collection: collection.to_owned(),
+        generation: *generation,
+        task,
+    });
+}
+
+/// Approximate memory of the picking index of `points` points, dominated by
+/// its leaves
+fn index_size(points: usize) -> usize {
+    points * std::mem::size_of::<GeomWithData<[f64; 3], usize>>()
+}
+
+pub fn handle_index_task(
+    mut commands: Commands,
+    mut tasks: Query<(Entity, &mut IndexTask)>,
+    mut cache: ResMut<PointCache>,
+    settings: Res<ViewerSettings>,
+) {
+    for (entity, mut task) in &mut tasks {
+        if let Some((index, grid)) = block_on(poll_once(&mut task.task)) {
+            // discard indices of replaced data and evicted indices
+            if cache.generation.get(&task.collection) == Some(&task.generation)
+                && cache.memory.contains(&task.collection, Level::Index)
+            {
+                info!("Indexed {} points of `{}`", index.size(), task.collection);
+                // the index does not affect the rendered instances, the grid
+                // only adaptive sizes
+                let cache = cache.bypass_change_detection();
+                cache
+                    .index
+                    .insert(task.collection.to_owned(), Arc::new(index));
+                if let Some(grid) = grid {
+                    cache
+                        .density
+                        .insert(task.collection.to_owned(), Arc::new(grid));
+                }
+            }
+            if settings.point_sizing == PointSizing::Adaptive {
+                cache.set_changed();
+            }
+
+            commands.entity(entity).despawn();
+        }
+    }
+}
+
+/// Point closest to a ray, both in the data reference system.
+///
+/// The ray is sampled at `RAY_SAMPLES` depths up to `far`, the nearest point of
+/// each sample is a candidate. Returns the row and position of the candidate
+/// with the smallest distance to the ray, if within tolerance.
+pub fn pick(
+    index: &PickIndex,
+    origin: DVec3,
+    direction: DVec3,
+    far: f64,
+) -> Option<(usize, DVec3)> {
+    let direction = direction.normalize();
+
+    (0..=RAY_SAMPLES)
+        .filter_map(|i| {
+            let sample = origin + direction * far * i as f64 / RAY_SAMPLES as f64;
+            index.nearest_neighbor(&sample.to_array())
+        })
+        .filter_map(|candidate| {
+            let p = DVec3::from_array(*candidate.geom());
+            let depth = (p - origin).dot(direction);
+            if depth <= 0. {
+                return None;
+            }
+
+            let distance = (p - (origin + direction * depth)).length();
+            (distance <= depth * RAY_TOLERANCE).then_some((distance, depth, candidate.data, p))
+        })
+        .min_by(|a, b| a.0.total_cmp(&b.0).then(a.1.total_cmp(&b.1)))
+        .map(|(_, _, row, p)| (row, p))
+}
+
+/// Point under the cursor, in the data reference system
+pub fn pick_cursor(
+    index: &PickIndex,
+    origin: DVec3,
+    (camera, transform, projection): (&Camera, &GlobalTransform, &Projection),
+    cursor: Vec2,
+) -> Option<(usize, DVec3)> {
+    let ray = camera.viewport_to_world(transform, cursor)?;
+
+    let far = match projection {
+        Projection::Perspective(p) => p.far,
+        Projection::Orthographic(p) => p.far,
+    } as f64;
+
+    let start = world_to_data(origin, ray.origin);
+    let direction = world_to_data(origin, ray.origin + ray.direction) - start;
+
+    pick(index, start, direction, far)
+}
+
+/// Value of `attribute` in the `row`-th point
+pub fn attribute_value(pc: &ArrowPointCloud, attribute: &str, mut row: usize) -> Option<String> {
+    for batch in pc.store.iter().flat_map(|e| pc.store.batches(e.key())) {
+        if row < batch.num_rows() {
+            let column = batch.column_by_name(attribute)?;
+            return array_value_to_string(column, row).ok();
+        }
+        row -= batch.num_rows();
+    }
+
+    None
+}
+
+#[derive(Component)]
+pub struct HoverText;
+
+pub fn setup_hover(mut commands: Commands) {
+    commands.spawn((
+        TextBundle::from_section("", TextStyle::default()).with_style(Style {
+            position_type: PositionType::Absolute,
+            ..default()
+        }),
+        HoverText,
+    ));
+}
+
+// Show the nearest point when the cursor rests
+#[allow(clippy::too_many_arguments)]
+pub fn hover_system(
+    time: Res<Time>,
+    cache: Res<PointCache>,
+    settings: Res<ViewerSettings>,
+    sr: Res<SpatialReference>,
+    window: Query<&Window, With<PrimaryWindow>>,
+    camera: Query<(&Camera, Ref<GlobalTransform>, &Projection), With<PanOrbitCamera>>,
+    mut text: Query<(&mut Text, &mut Style), With<HoverText>>,
+    mut rest: Local<(Option<Vec2>, Duration, bool)>,
+) {
+    let (Ok(window), Ok((camera, transform, projection)), Ok((mut text, mut style))) = (
+        window.get_single(),
+        camera.get_single(),
+        text.get_single_mut(),
+    ) else {
+        return;
+    };
+
+    let (last, elapsed, shown) = &mut *rest;
+
+    // reset on cursor or camera movement
+    let cursor = window.cursor_position();
+    if cursor != *last || transform.is_changed() {
+        *last = cursor;
+        *elapsed = Duration::ZERO;
+        *shown = false;
+        if !text.sections[0].value.is_empty() {
+            text.sections[0].value.clear();
+        }
+        return;
+    }
+
+    *elapsed += time.delta();
+    if *shown || *elapsed < HOVER_DELAY {
+        return;
+    }
+
+    // no-op until the index is available
+    let (Some(cursor), Some(origin), Some(pc), Some(index)) = (
+        cursor,
+        sr.origin,
+        cache.data.get(&settings.collection),
+        cache.index.get(&settings.collection),
+    ) else {
+        return;
+    };
+    *shown = true;
+
+    let Some((row, p)) = pick_cursor(index, origin, (camera, &transform, projection), cursor)
+    else {
+        return;
+    };
+
+    let attribute = color_attribute(pc, &settings);
+    let value = match attribute {
+        COLLECTION_ATTRIBUTE => Some(settings.collection.to_owned()),
+        attribute => attribute_value(pc, attribute, row),
+    }
+    .unwrap_or_else(|| "-".to_string());
+
+    text.sections[0].value = format!("[{:.3}, {:.3}, {:.3}]\n{attribute}: {value}", p.x, p.y, p.z);
+    style.left = Val::Px(cursor.x + 12.);
+    style.top = Val::Px(cursor.y + 12.);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grid() -> PickIndex {
+        RTree::bulk_load(
+            (0..100)
+                .map(|i| GeomWithData::new([(i % 10) as f64, (i / 10) as f64, 0.], i))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn pick_from_above() {
+        let index = grid();
+
+        // vertical ray slightly offset from point 23 = (3, 2, 0)
+        let hit = pick(&index, DVec3::new(3.02, 1.99, 10.), DVec3::NEG_Z, 100.);
+        assert_eq!(hit.map(|(row, _)| row), Some(23));
+
+        // ray pointing away
+        assert!(pick(&index, DVec3::new(3., 2., 10.), DVec3::Z, 100.).is_none());
+
+        // ray missing the data
+        assert!(pick(&index, DVec3::new(50., 50., 10.), DVec3::NEG_Z, 100.).is_none());
+    }
+}
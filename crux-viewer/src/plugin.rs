@@ -0,0 +1,109 @@
+//! The viewer as a Bevy plugin, for the binary and for apps embedding it
+
+use bevy::{input::InputSystem, prelude::*};
+
+use crate::{
+    bounds::{self, BoundsGizmos},
+    cache::CachePlugin,
+    camera::CameraPlugin,
+    classes::{self, Classes},
+    color::ColorPlugin,
+    help::{self, Help},
+    history_panel::{self, QueryPanel},
+    hud::HudPlugin,
+    keys::KeyBindings,
+    legend,
+    measure::{self, Measure},
+    minimap::{self, Minimap},
+    net::NetPlugin,
+    picking,
+    profile::{self, ProfileTool},
+    render::RenderPlugin,
+    settings, thinning,
+    trajectory::{self, Trajectory},
+    vector_overlay::{self, VectorOverlay},
+    volume::{self, VolumeTool},
+    ViewerSettings,
+};
+
+/// Picking, measurements, profiles, volumes, the minimap, the thinning
+/// preview, trajectories, vector overlays, bounds, screenshots with legends
+/// of the occurring classes, the query history and the help
+pub struct ToolsPlugin;
+
+impl Plugin for ToolsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Measure>()
+            .init_resource::<BoundsGizmos>()
+            .init_resource::<ProfileTool>()
+            .init_resource::<VolumeTool>()
+            .init_resource::<Trajectory>()
+            .init_resource::<Classes>()
+            .init_resource::<VectorOverlay>()
+            .init_resource::<Minimap>()
+            .init_resource::<QueryPanel>()
+            .init_resource::<Help>()
+            .add_systems(
+                Startup,
+                (
+                    picking::setup_hover,
+                    measure::setup_measure,
+                    profile::setup_profile,
+                    volume::setup_volume,
+                    minimap::setup_minimap,
+                    help::setup_help,
+                ),
+            )
+            .add_systems(
+                PreUpdate,
+                history_panel::history_panel_system.after(InputSystem),
+            )
+            .add_systems(Update, picking::handle_index_task)
+            .add_systems(Update, picking::hover_system)
+            .add_systems(Update, minimap::handle_minimap_task)
+            .add_systems(Update, minimap::minimap_system)
+            .add_systems(Update, measure::measure_system)
+            .add_systems(Update, profile::profile_system)
+            .add_systems(Update, volume::volume_system)
+            .add_systems(Update, thinning::thinning_system)
+            .add_systems(Update, trajectory::spawn_trajectory_task)
+            .add_systems(Update, trajectory::trajectory_system)
+            .add_systems(Update, vector_overlay::vector_drop_system)
+            .add_systems(Update, vector_overlay::spawn_vector_task)
+            .add_systems(Update, vector_overlay::vector_system)
+            .add_systems(Update, bounds::bounds_system)
+            .add_systems(Update, classes::spawn_classes_task)
+            .add_systems(Update, classes::classes_system)
+            .add_systems(Update, legend::screenshot_system)
+            .add_systems(Update, help::help_system);
+    }
+}
+
+/// The viewer without a window, settings files or sessions: the point cache
+/// and its loads, the rendering, colors, camera, overlay and tools.
+///
+/// Settings inserted before the plugin is added are kept, defaults are used
+/// otherwise. Points are loaded by sending a [LoadRequest](crate::LoadRequest)
+/// or shown directly with [PointCache::insert](crate::PointCache::insert).
+pub struct CruxViewerPlugin;
+
+impl Plugin for CruxViewerPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ViewerSettings>();
+        if !app.world.contains_resource::<KeyBindings>() {
+            let keys = app.world.resource::<ViewerSettings>().keys.clone();
+            app.insert_resource(keys);
+        }
+
+        app.add_plugins((
+            CachePlugin,
+            NetPlugin,
+            RenderPlugin,
+            ColorPlugin,
+            CameraPlugin,
+            HudPlugin,
+            ToolsPlugin,
+        ))
+        .add_systems(PreStartup, settings::key_conflicts_system);
+    }
+}
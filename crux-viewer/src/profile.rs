@@ -0,0 +1,294 @@
+use std::path::PathBuf;
+
+use bevy::{
+    core_pipeline::clear_color::ClearColorConfig, math::DVec3, prelude::*,
+    render::view::RenderLayers, window::PrimaryWindow,
+};
+use bevy_panorbit_camera::PanOrbitCamera;
+
+use crux_format::profile::Profile;
+
+use crate::{
+    frame::data_to_world,
+    keys::{Action, KeyBindings},
+    measure::Measure,
+    picking::pick_cursor,
+    stereo_view::OVERLAY_LAYER,
+    units::Units,
+    PointCache, SpatialReference, ViewerSettings,
+};
+
+/// Size of the profile plot in logical pixels
+const PANEL_SIZE: Vec2 = Vec2::new(400., 160.);
+/// Distance of the plot to the window border
+const PANEL_MARGIN: f32 = 12.;
+/// File the profile is exported to
+const EXPORT_FILE: &str = "profile.csv";
+
+/// Height profile along a line between two picked points
+#[derive(Resource, Default)]
+pub struct ProfileTool {
+    pub active: bool,
+    points: Vec<DVec3>,
+    profile: Option<Profile>,
+    status: String,
+}
+
+#[derive(Component)]
+pub struct ProfileText;
+
+/// Camera drawing the screen space plot on top of the scene
+#[derive(Component)]
+pub struct OverlayCamera;
+
+pub fn setup_profile(mut commands: Commands) {
+    commands.spawn((
+        Camera2dBundle {
+            camera: Camera {
+                order: 1,
+                ..default()
+            },
+            camera_2d: Camera2d {
+                clear_color: ClearColorConfig::None,
+            },
+            ..default()
+        },
+        UiCameraConfig { show_ui: false },
+        // the plot is drawn while the gizmos are moved to the left eye
+        RenderLayers::layer(0).with(OVERLAY_LAYER),
+        OverlayCamera,
+    ));
+
+    commands.spawn((
+        TextBundle::from_section("", TextStyle::default()).with_style(Style {
+            position_type: PositionType::Absolute,
+            bottom: Val::Px(PANEL_SIZE.y + 2. * PANEL_MARGIN),
+            right: Val::Px(PANEL_MARGIN),
+            ..default()
+        }),
+        ProfileText,
+    ));
+}
+
+/// Minimum, mean and maximum polylines of the profile within a plot with its
+/// lower left corner at `lower`, empty bins are skipped
+pub fn plot_lines(profile: &Profile, lower: Vec2, size: Vec2) -> [Vec<Vec2>; 3] {
+    let mut lines = [Vec::new(), Vec::new(), Vec::new()];
+    let Some((z_min, z_max)) = profile.z_range() else {
+        return lines;
+    };
+
+    let length = profile.length();
+    let y = |z: f64| {
+        let t = if z_max > z_min {
+            (z - z_min) / (z_max - z_min)
+        } else {
+            0.5
+        };
+        lower.y + t as f32 * size.y
+    };
+
+    for bin in &profile.bins {
+        let x = lower.x + (bin.distance / length) as f32 * size.x;
+        for (line, z) in lines.iter_mut().zip([bin.min, bin.mean, bin.max]) {
+            if let Some(z) = z {
+                line.push(Vec2::new(x, y(z)));
+            }
+        }
+    }
+
+    lines
+}
+
+/// Write the profile to the working directory
+fn export(profile: &Profile) -> std::io::Result<PathBuf> {
+    let path = std::env::current_dir()?.join(EXPORT_FILE);
+    profile.write_csv(std::io::BufWriter::new(std::fs::File::create(&path)?))?;
+    Ok(path)
+}
+
+// Press 'P' for the profile tool, shift + click picks the end points, 'E' exports
+#[allow(clippy::too_many_arguments)]
+pub fn profile_system(
+    key_input: Res<Input<KeyCode>>,
+    keys: Res<KeyBindings>,
+    mouse_input: Res<Input<MouseButton>>,
+    cache: Res<PointCache>,
+    settings: Res<ViewerSettings>,
+    sr: Res<SpatialReference>,
+    window: Query<&Window, With<PrimaryWindow>>,
+    camera: Query<(&Camera, &GlobalTransform, &Projection), With<PanOrbitCamera>>,
+    mut text: Query<&mut Text, With<ProfileText>>,
+    mut tool: ResMut<ProfileTool>,
+    mut measure: ResMut<Measure>,
+    mut gizmos: Gizmos,
+) {
+    let (Ok(window), Ok((camera, transform, projection)), Ok(mut text)) = (
+        window.get_single(),
+        camera.get_single(),
+        text.get_single_mut(),
+    ) else {
+        return;
+    };
+
+    // the tools share the picking gesture
+    if keys.just_pressed(&key_input, Action::Profile) {
+        tool.active = !tool.active;
+        if tool.active {
+            measure.active = false;
+        }
+    } else if measure.active {
+        tool.active = false;
+    }
+
+    let key = keys.label(Action::Profile);
+    if !tool.active {
+        if !text.sections[0].value.is_empty() {
+            text.sections[0].value.clear();
+        }
+        return;
+    }
+
+    let (Some(origin), Some(pc), Some(index)) = (
+        sr.origin,
+        cache.data.get(&settings.collection),
+        cache.index.get(&settings.collection),
+    ) else {
+        text.sections[0].value = format!("Profile ({key}): waiting for index");
+        return;
+    };
+
+    let shift = key_input.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]);
+    if shift && mouse_input.just_pressed(MouseButton::Left) {
+        let picked = window
+            .cursor_position()
+            .and_then(|cursor| pick_cursor(index, origin, (camera, transform, projection), cursor));
+
+        if let Some((_, p)) = picked {
+            if tool.points.len() == 2 {
+                tool.points.clear();
+                tool.profile = None;
+            }
+            tool.points.push(p);
+
+            if let [a, b] = tool.points[..] {
+                let result = pc.profile(
+                    [a.x, a.y],
+                    [b.x, b.y],
+                    settings.profile_width,
+                    settings.profile_bins,
+                );
+                match result {
+                    Ok(profile) => {
+                        tool.status.clear();
+                        tool.profile = Some(profile);
+                    }
+                    Err(e) => tool.status = format!("Profile failed: {e}"),
+                }
+            }
+        }
+    }
+
+    // line and corridor in the scene
+    for p in &tool.points {
+        gizmos.sphere(data_to_world(origin, *p), Quat::IDENTITY, 0.1, Color::CYAN);
+    }
+    if let [a, b] = tool.points[..] {
+        let across = (b - a).truncate().perp().normalize_or_zero() * settings.profile_width / 2.;
+        let across = across.extend(0.);
+        gizmos.line(
+            data_to_world(origin, a),
+            data_to_world(origin, b),
+            Color::CYAN,
+        );
+        for side in [across, -across] {
+            gizmos.line(
+                data_to_world(origin, a + side),
+                data_to_world(origin, b + side),
+                Color::CYAN.with_a(0.4),
+            );
+        }
+    }
+
+    // borrow the fields separately
+    let tool = tool.as_mut();
+    let Some(profile) = &tool.profile else {
+        text.sections[0].value = if tool.status.is_empty() {
+            format!("Profile ({key}): shift + click two points")
+        } else {
+            tool.status.clone()
+        };
+        return;
+    };
+
+    if keys.just_pressed(&key_input, Action::ExportProfile) {
+        let status = match export(profile) {
+            Ok(path) => format!("Exported to {}", path.display()),
+            Err(e) => format!("Export failed: {e}"),
+        };
+        info!("{status}");
+        tool.status = status;
+    }
+
+    // screen space plot, the overlay camera has its origin at the window center
+    let lower = Vec2::new(
+        window.width() / 2. - PANEL_MARGIN - PANEL_SIZE.x,
+        -window.height() / 2. + PANEL_MARGIN,
+    );
+    gizmos.rect_2d(lower + PANEL_SIZE / 2., 0., PANEL_SIZE, Color::WHITE);
+
+    let [min, mean, max] = plot_lines(profile, lower, PANEL_SIZE);
+    gizmos.linestrip_2d(min, Color::GRAY);
+    gizmos.linestrip_2d(max, Color::GRAY);
+    gizmos.linestrip_2d(mean, Color::YELLOW);
+
+    let units = Units::new(pc.metadata().units, settings.units);
+    let (z_min, z_max) = profile.z_range().unwrap_or((f64::NAN, f64::NAN));
+    text.sections[0].value = format!(
+        "Profile: {} long, z {} - {} (E: export CSV)\n{}",
+        units.format(profile.length()),
+        units.format(z_min),
+        units.format(z_max),
+        tool.status
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use crux_format::profile::ProfileBin;
+
+    use super::*;
+
+    #[test]
+    fn plot() {
+        let bin = |distance: f64, z: Option<f64>| ProfileBin {
+            distance,
+            count: z.map_or(0, |_| 1),
+            min: z.map(|z| z - 1.),
+            mean: z,
+            max: z.map(|z| z + 1.),
+        };
+        let profile = Profile {
+            a: [0., 0.],
+            b: [4., 0.],
+            width: 1.,
+            bins: vec![
+                bin(0.5, Some(1.)),
+                bin(1.5, None),
+                bin(2.5, Some(3.)),
+                bin(3.5, Some(5.)),
+            ],
+        };
+
+        let [min, mean, max] = plot_lines(&profile, Vec2::new(10., 20.), Vec2::new(400., 60.));
+
+        // the empty bin is skipped
+        assert_eq!(mean.len(), 3);
+        assert_eq!(mean[0], Vec2::new(60., 30.));
+        assert_eq!(mean[2], Vec2::new(360., 70.));
+
+        // z range spans the plot height
+        assert_eq!(min[0].y, 20.);
+        assert_eq!(max[2].y, 80.);
+    }
+}
@@ -0,0 +1,278 @@
+//! Instances of the shown collections, their upload to the renderer and the
+//! GPU memory they take
+
+use std::{collections::VecDeque, sync::Arc};
+
+use bevy::{
+    math::DVec3,
+    prelude::*,
+    render::{primitives::Aabb, renderer::RenderAdapterInfo},
+};
+use bevy_aabb_instancing::{Cuboid, CuboidMaterialId, Cuboids, VertexPullingRenderPlugin};
+
+use crux_format::prelude::*;
+
+use crate::{
+    compare::{self, Compare},
+    fog::Fog,
+    gpu::GpuMemory,
+    hud::Hud,
+    instances::{cloud_instances, InstanceLimit, SkippedPoints},
+    memory::MIB,
+    normalize::ScaleBounds,
+    session::{InstanceSummary, RenderedSummary},
+    thinning::ThinningPreview,
+    transition::{self, Source, Transition},
+    PointCache, SpatialReference, ViewerSettings,
+};
+
+/// Instances of the cached points, faded between loads or compared side by
+/// side, within the GPU memory budget
+pub struct RenderPlugin;
+
+impl Plugin for RenderPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<InstanceUpload>()
+            .init_resource::<Transition>()
+            .init_resource::<SkippedPoints>()
+            .init_resource::<InstanceLimit>()
+            .init_resource::<ThinningPreview>()
+            .init_resource::<Fog>()
+            .init_resource::<GpuMemory>()
+            .init_resource::<Compare>()
+            .add_plugins(VertexPullingRenderPlugin::default())
+            .add_systems(Startup, (compare::setup_compare, setup_gpu_budget))
+            .add_systems(Update, update_instances)
+            .add_systems(Update, gpu_memory_system.after(update_instances))
+            .add_systems(Update, compare::compare_system.before(upload_instances))
+            .add_systems(
+                Update,
+                transition::transition_system
+                    .after(update_instances)
+                    .before(upload_instances),
+            )
+            .add_systems(Update, upload_instances);
+    }
+}
+
+// Generate the instances of the shown collections when they or the settings
+// change
+#[allow(clippy::too_many_arguments)]
+pub fn update_instances(
+    mut cache: ResMut<PointCache>,
+    settings: Res<ViewerSettings>,
+    mut sr: ResMut<SpatialReference>,
+    mut upload: ResMut<InstanceUpload>,
+    mut transition: ResMut<Transition>,
+    time: Res<Time>,
+    (mut scale, mut skipped): (ResMut<ScaleBounds>, ResMut<SkippedPoints>),
+    mut compare: ResMut<Compare>,
+    (background, fog): (Res<ClearColor>, Res<Fog>),
+    (mut limit, mut preview): (ResMut<InstanceLimit>, ResMut<ThinningPreview>),
+    mut gpu: ResMut<GpuMemory>,
+    rendered: Option<ResMut<RenderedSummary>>,
+    mut hud: ResMut<Hud>,
+) {
+    let changed = cache.is_changed() || settings.is_changed() || limit.is_changed();
+    if (changed || preview.is_changed() || fog.is_changed())
+        && cache.data.contains_key(&settings.collection)
+    {
+        // rendering does not change the cached data
+        let other = settings
+            .compare
+            .as_ref()
+            .filter(|other| cache.data.contains_key(*other));
+        for collection in [Some(&settings.collection), other].into_iter().flatten() {
+            cache.bypass_change_detection().memory.touch(collection);
+        }
+
+        let pc = cache.data.get(&settings.collection).unwrap();
+        let aabb: AABB<Point<f64, 3>> = pc.aabb();
+
+        let origin = if let Some(o) = sr.origin {
+            // TODO: update sr
+            o
+        } else {
+            // set origin to center
+            let p = DVec3::from_slice(aabb.center().coords());
+            sr.origin = Some(p);
+            sr.camera = p;
+            p
+        };
+
+        // the compared collection first, so that the scale shows the collection
+        // clouds above the threshold are downsampled, counted for display
+        skipped.0 = 0;
+        let limit = limit.bypass_change_detection();
+        limit.downsampled = None;
+        let compared = other.map(|other| {
+            let pc = cache.data.get(other).unwrap();
+            let sampled = limit.sample(pc, &settings);
+            cloud_instances(
+                sampled.as_ref().unwrap_or(pc),
+                other,
+                cache.density.get(other).map(Arc::as_ref),
+                origin,
+                &settings,
+                &mut scale,
+                &mut skipped,
+                background.0,
+                fog.0.as_ref(),
+            )
+        });
+        // the local preview of a fraction, which the limit may sample further
+        let thinned = preview.bypass_change_detection().sample(pc);
+        let pc = thinned.as_ref().unwrap_or(pc);
+        let sampled = limit.sample(pc, &settings);
+        let instances = cloud_instances(
+            sampled.as_ref().unwrap_or(pc),
+            &settings.collection,
+            cache.density.get(&settings.collection).map(Arc::as_ref),
+            origin,
+            &settings,
+            &mut scale,
+            &mut skipped,
+            background.0,
+            fog.0.as_ref(),
+        );
+
+        // the buffers of the instances count against the GPU memory budget
+        let counts = [
+            instances.iter().map(Vec::len).sum::<usize>(),
+            compared.iter().flatten().map(Vec::len).sum(),
+        ];
+        gpu.estimate.instances = counts.iter().sum();
+        gpu.largest = counts.into_iter().max().unwrap_or_default();
+        hud.instances = [Some(&settings.collection), other]
+            .into_iter()
+            .flatten()
+            .zip(counts)
+            .map(|(collection, count)| (collection.to_owned(), count))
+            .collect();
+
+        // compared with the recording of a session
+        if let Some(mut rendered) = rendered {
+            rendered.0 = Some(InstanceSummary::of(
+                instances.iter().chain(compared.iter().flatten()),
+            ));
+        }
+
+        match compared {
+            Some(compared) => {
+                transition.cancel();
+                compare.set(instances, compared);
+            }
+            None => {
+                compare.clear();
+                // denser or sparser loads of the collection are faded in
+                let source = Source {
+                    collection: settings.collection.to_owned(),
+                    generation: cache
+                        .generation
+                        .get(&settings.collection)
+                        .copied()
+                        .unwrap_or_default(),
+                };
+                let available = (settings.memory_budget * MIB).saturating_sub(cache.memory.total());
+                transition.show(instances, source, &mut upload, available, time.elapsed());
+            }
+        }
+    }
+}
+
+/// GPU memory budget from the settings or the type of the adapter
+pub fn setup_gpu_budget(
+    settings: Res<ViewerSettings>,
+    adapter: Option<Res<RenderAdapterInfo>>,
+    mut gpu: ResMut<GpuMemory>,
+) {
+    let device_type = adapter.map(|adapter| adapter.device_type);
+    gpu.set_budget(settings.gpu_budget, device_type);
+    info!("GPU memory budget of {} MiB", gpu.budget / MIB);
+}
+
+/// Degrade to fewer points per collection when the rendered instances are
+/// estimated above the GPU memory budget, unless all points are forced
+pub fn gpu_memory_system(
+    images: Res<Assets<Image>>,
+    mut gpu: ResMut<GpuMemory>,
+    mut limit: ResMut<InstanceLimit>,
+) {
+    let textures = images.iter().map(|(_, image)| image.data.len()).sum();
+    gpu.bypass_change_detection().estimate.textures = textures;
+    // once per rendering of the instances
+    if limit.force_full || !gpu.is_changed() {
+        return;
+    }
+    match gpu.degrade() {
+        Some(degraded) if degraded < gpu.largest => {
+            info!(
+                "GPU memory estimate of {} MiB above the budget of {} MiB, \
+                 degraded from {} to {degraded} points per collection",
+                gpu.estimate.total() / MIB,
+                gpu.budget / MIB,
+                gpu.largest
+            );
+            limit.gpu_limit = Some(degraded);
+        }
+        _ => (),
+    }
+}
+
+/// Chunk of the instances, rendered by its own cuboids entity
+#[derive(Component)]
+pub struct PointChunk(usize);
+
+/// Instance chunks waiting to be handed over to the renderer
+#[derive(Resource, Default)]
+pub struct InstanceUpload {
+    pub(crate) pending: VecDeque<(usize, Vec<Cuboid>)>,
+    chunks: usize,
+}
+
+impl InstanceUpload {
+    pub(crate) fn set(&mut self, instances: Vec<Vec<Cuboid>>) {
+        self.chunks = instances.len();
+        self.pending = instances.into_iter().enumerate().collect();
+    }
+}
+
+// Upload one chunk per frame, so only the changed entity is re-uploaded
+pub fn upload_instances(
+    mut commands: Commands,
+    mut upload: ResMut<InstanceUpload>,
+    mut chunks: Query<(&PointChunk, &mut Cuboids, &mut Aabb)>,
+) {
+    let Some((index, instances)) = upload.pending.pop_front() else {
+        return;
+    };
+    let _span = info_span!("upload", chunk = index, instances = instances.len()).entered();
+
+    let cuboids = Cuboids::new(instances);
+    let aabb = cuboids.aabb();
+
+    match chunks.iter_mut().find(|(chunk, ..)| chunk.0 == index) {
+        Some((_, mut c, mut a)) => {
+            *c = cuboids;
+            *a = aabb;
+        }
+        None => {
+            commands.spawn((
+                SpatialBundle::default(),
+                cuboids,
+                aabb,
+                CuboidMaterialId(0),
+                PointChunk(index),
+            ));
+        }
+    }
+
+    // empty chunks left over from a larger previous upload
+    if upload.pending.is_empty() {
+        for (chunk, mut c, _) in chunks.iter_mut() {
+            if chunk.0 >= upload.chunks && !c.instances.is_empty() {
+                c.instances.clear();
+            }
+        }
+    }
+}
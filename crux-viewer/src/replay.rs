@@ -0,0 +1,155 @@
+use bevy::prelude::*;
+use bevy_panorbit_camera::PanOrbitCamera;
+
+use crate::{
+    history_panel::restore,
+    keys::{Action, KeyBindings},
+    net::LoadTask,
+    picking::IndexTask,
+    schedule::{LoadQueue, QueuedLoad},
+    session::{RenderedSummary, SessionEvent, SessionRecorder, SessionReplay, Step},
+    views::Views,
+    PointCache, SpatialReference, ViewerSettings,
+};
+
+/// Frames without loads before the next step, so that the last load is
+/// rendered
+const SETTLE_FRAMES: u32 = 2;
+
+/// Keys pressed by the replay, released the frame after
+#[derive(Resource, Default)]
+pub struct ReplayKeys {
+    held: Vec<KeyCode>,
+    idle: u32,
+}
+
+/// Record the settings at the start, or start from the recorded ones
+pub fn start_session_system(
+    mut commands: Commands,
+    settings: Res<ViewerSettings>,
+    recorder: Option<ResMut<SessionRecorder>>,
+    replay: Option<ResMut<SessionReplay>>,
+) {
+    if let Some(mut recorder) = recorder {
+        recorder.record(SessionEvent::Start {
+            settings: Box::new(settings.clone()),
+        });
+    }
+    if let Some(settings) = replay.and_then(|mut replay| replay.take_settings()) {
+        commands.insert_resource(settings.keys.clone());
+        commands.insert_resource(settings);
+    }
+}
+
+// Record the controls with the camera pose at the time
+pub fn record_actions_system(
+    key_input: Res<Input<KeyCode>>,
+    keys: Res<KeyBindings>,
+    recorder: Option<ResMut<SessionRecorder>>,
+    sr: Res<SpatialReference>,
+    views: Res<Views>,
+    camera: Query<&PanOrbitCamera>,
+) {
+    let Some(mut recorder) = recorder else {
+        return;
+    };
+    let shift = key_input.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]);
+    for action in Action::ALL {
+        if keys.just_pressed(&key_input, action) {
+            let camera = match (sr.origin, camera.get_single()) {
+                (Some(origin), Ok(camera)) => Some(views.camera_pose(camera, origin)),
+                _ => None,
+            };
+            recorder.record(SessionEvent::Action {
+                action,
+                shift,
+                camera,
+            });
+        }
+    }
+}
+
+// Take the next step of the replay once the loads of the previous ones are
+// rendered
+#[allow(clippy::too_many_arguments)]
+pub fn replay_system(
+    replay: Option<ResMut<SessionReplay>>,
+    mut held: ResMut<ReplayKeys>,
+    mut key_input: ResMut<Input<KeyCode>>,
+    keys: Res<KeyBindings>,
+    (mut settings, mut cache, mut queue): (
+        ResMut<ViewerSettings>,
+        ResMut<PointCache>,
+        ResMut<LoadQueue>,
+    ),
+    (loads, indices): (Query<&LoadTask>, Query<&IndexTask>),
+    rendered: Res<RenderedSummary>,
+    mut camera: Query<&mut PanOrbitCamera>,
+    mut sr: ResMut<SpatialReference>,
+) {
+    let Some(mut replay) = replay else {
+        return;
+    };
+    for key in std::mem::take(&mut held.held) {
+        key_input.release(key);
+    }
+
+    if !loads.is_empty() || !indices.is_empty() || !queue.is_empty() {
+        held.idle = 0;
+        return;
+    }
+    if held.idle < SETTLE_FRAMES {
+        held.idle += 1;
+        return;
+    }
+
+    match replay.next_step() {
+        Some(Step::Load {
+            collection,
+            url,
+            body: _,
+        }) => {
+            // loads are issued for the shown collection
+            if settings.collection != collection {
+                settings.collection = collection.to_owned();
+            }
+            let request = cache.requests.issue(&collection);
+            queue.push(QueuedLoad {
+                collection,
+                url,
+                request,
+            });
+            held.idle = 0;
+        }
+        Some(Step::Press {
+            action,
+            shift,
+            camera: pose,
+        }) => {
+            if let (Some(pose), Ok(mut camera)) = (pose, camera.get_single_mut()) {
+                restore(&pose, &mut camera, &mut sr);
+            }
+            let key = keys.key(action);
+            key_input.press(key);
+            held.held.push(key);
+            if shift {
+                key_input.press(KeyCode::ShiftLeft);
+                held.held.push(KeyCode::ShiftLeft);
+            }
+            held.idle = 0;
+        }
+        Some(Step::Finish { expected }) => {
+            replay.finish(rendered.0, expected);
+            for mismatch in &replay.mismatches {
+                warn!("Replay differs: {mismatch}");
+            }
+            match rendered.0 {
+                Some(rendered) if replay.outcome == Some(true) => {
+                    info!("Replayed the session: {rendered}, as recorded")
+                }
+                _ => warn!("Replayed the session with differences to the recording"),
+            }
+        }
+        None => (),
+    }
+}
@@ -0,0 +1,141 @@
+use arrow::{
+    array::{AsArray, BooleanArray},
+    datatypes::{DataType, UInt8Type},
+};
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crux_format::{encoding::cast, ArrowPointCloud, PointCloudTrait};
+
+use crate::normalize::NO_DATA_COLOR;
+
+/// Color mode distinguishing first, intermediate and last returns
+pub const RETURNS_ATTRIBUTE: &str = "returns";
+
+const RETURN_NUMBER: &str = "return_number";
+const NUMBER_OF_RETURNS: &str = "number_of_returns";
+
+const FIRST_COLOR: Color = Color::LIME_GREEN;
+const INTERMEDIATE_COLOR: Color = Color::ORANGE;
+const LAST_COLOR: Color = Color::MAROON;
+
+/// Returns shown in the viewer
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ReturnsFilter {
+    #[default]
+    All,
+    First,
+    Last,
+}
+
+impl ReturnsFilter {
+    pub fn next(self) -> Self {
+        match self {
+            Self::All => Self::First,
+            Self::First => Self::Last,
+            Self::Last => Self::All,
+        }
+    }
+
+    fn matches(self, (number, count): (u8, u8)) -> bool {
+        match self {
+            Self::All => true,
+            Self::First => number == 1,
+            Self::Last => number == count,
+        }
+    }
+}
+
+impl std::fmt::Display for ReturnsFilter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::All => write!(f, "all"),
+            Self::First => write!(f, "first"),
+            Self::Last => write!(f, "last"),
+        }
+    }
+}
+
+/// Point cloud provides return number and number of returns
+pub fn has_returns(pc: &ArrowPointCloud) -> bool {
+    let schema = pc.schema();
+    schema.column_with_name(RETURN_NUMBER).is_some()
+        && schema.column_with_name(NUMBER_OF_RETURNS).is_some()
+}
+
+/// Return number and number of returns per point, `None` if either is null
+fn returns(pc: &ArrowPointCloud) -> Vec<Option<(u8, u8)>> {
+    pc.store
+        .iter()
+        .flat_map(|e| pc.store.batches(e.key()))
+        .flat_map(|batch| {
+            let column = |name| {
+                let column = batch.column_by_name(name).unwrap();
+                cast(column, &DataType::UInt8).unwrap()
+            };
+            let (number, count) = (column(RETURN_NUMBER), column(NUMBER_OF_RETURNS));
+            let (number, count) = (
+                number.as_primitive::<UInt8Type>(),
+                count.as_primitive::<UInt8Type>(),
+            );
+
+            number
+                .iter()
+                .zip(count.iter())
+                .map(|(n, c)| n.zip(c))
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+/// Mask of the points matching `filter`, `None` if all points are shown
+pub fn mask(pc: &ArrowPointCloud, filter: ReturnsFilter) -> Option<BooleanArray> {
+    if filter == ReturnsFilter::All || !has_returns(pc) {
+        return None;
+    }
+
+    Some(
+        returns(pc)
+            .into_iter()
+            .map(|r| Some(r.is_some_and(|r| filter.matches(r))))
+            .collect(),
+    )
+}
+
+/// Color first, intermediate and last returns, single returns count as first
+pub fn colors(pc: &ArrowPointCloud) -> Vec<Color> {
+    returns(pc)
+        .into_iter()
+        .map(|r| r.map_or(NO_DATA_COLOR, color))
+        .collect()
+}
+
+fn color((number, count): (u8, u8)) -> Color {
+    if number <= 1 {
+        FIRST_COLOR
+    } else if number >= count {
+        LAST_COLOR
+    } else {
+        INTERMEDIATE_COLOR
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classify() {
+        assert_eq!(color((1, 1)), FIRST_COLOR);
+        assert_eq!(color((1, 3)), FIRST_COLOR);
+        assert_eq!(color((2, 3)), INTERMEDIATE_COLOR);
+        assert_eq!(color((3, 3)), LAST_COLOR);
+
+        assert!(ReturnsFilter::First.matches((1, 3)));
+        assert!(!ReturnsFilter::First.matches((3, 3)));
+        assert!(ReturnsFilter::Last.matches((3, 3)));
+        assert!(ReturnsFilter::Last.matches((1, 1)));
+        assert!(!ReturnsFilter::Last.matches((2, 3)));
+    }
+}
@@ -0,0 +1,215 @@
+use bevy::prelude::Resource;
+
+/// Default maximum number of loads in flight
+pub const MAX_LOADS: usize = 4;
+
+/// Load waiting for a free slot
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueuedLoad {
+    pub collection: String,
+    pub url: String,
+    /// Request id, see [crate::fetch::RequestIds], increasing with recency
+    pub request: u64,
+}
+
+/// Selected region of a points query, the rest of the query is kept as is
+#[derive(Debug, Clone, PartialEq)]
+struct Extent {
+    /// Query without the selected region
+    base: String,
+    lower: [f64; 3],
+    upper: [f64; 3],
+    /// Selected importance range, the density of the load
+    importance: (f64, f64),
+    /// Fraction of a stable sample, whose smaller fractions select subsets
+    stable: f64,
+}
+
+impl Extent {
+    /// Parse the `bounds`, `p` and stable `sample` parameters, `None` for
+    /// URLs that do not describe a region, e.g. stratified samples
+    fn parse(url: &str) -> Option<Self> {
+        let (path, query) = url.split_once('?').unwrap_or((url, ""));
+
+        let mut extent = Self {
+            base: String::new(),
+            lower: [f64::NEG_INFINITY; 3],
+            upper: [f64::INFINITY; 3],
+            importance: (0., 1.),
+            stable: 1.,
+        };
+        let mut rest = Vec::new();
+        for param in query.split('&').filter(|param| !param.is_empty()) {
+            match param.split_once('=') {
+                Some(("bounds", bounds)) => {
+                    let values = bounds
+                        .split(',')
+                        .map(|v| v.parse::<f64>().ok())
+                        .collect::<Option<Vec<_>>>()?;
+                    if values.len() < 6 || values.len() % 2 != 0 {
+                        return None;
+                    }
+                    let (lower, upper) = values.split_at(values.len() / 2);
+                    extent.lower.copy_from_slice(&lower[..3]);
+                    extent.upper.copy_from_slice(&upper[..3]);
+                    if let (Some(l), Some(u)) = (lower.get(3), upper.get(3)) {
+                        extent.importance =
+                            (extent.importance.0.max(*l), extent.importance.1.min(*u));
+                    }
+                }
+                Some(("p", p)) => {
+                    let p = p.parse::<f64>().ok()?;
+                    extent.importance.1 = extent.importance.1.min(p);
+                }
+                Some(("sample", sample)) => {
+                    let p = sample.strip_prefix("stable:")?.parse::<f64>().ok()?;
+                    extent.stable = extent.stable.min(p);
+                }
+                _ => rest.push(param),
+            }
+        }
+
+        rest.sort_unstable();
+        extent.base = format!("{path}?{}", rest.join("&"));
+        Some(extent)
+    }
+
+    /// Whether all points of `other` are selected at least as densely
+    fn covers(&self, other: &Self) -> bool {
+        self.base == other.base
+            && (0..3).all(|i| self.lower[i] <= other.lower[i] && self.upper[i] >= other.upper[i])
+            && self.importance.0 <= other.importance.0
+            && self.importance.1 >= other.importance.1
+            && self.stable >= other.stable
+    }
+}
+
+/// Whether the points of load `b` are part of load `a`
+pub fn covers(a: &QueuedLoad, b: &QueuedLoad) -> bool {
+    if a.collection != b.collection {
+        return false;
+    }
+    match (Extent::parse(&a.url), Extent::parse(&b.url)) {
+        (Some(a), Some(b)) => a.covers(&b),
+        _ => false,
+    }
+}
+
+/// Drop loads that are covered by another queued load, of two equal loads
+/// the more recent one is kept
+pub fn coalesce(pending: &mut Vec<QueuedLoad>) {
+    let loads = std::mem::take(pending);
+    for (i, load) in loads.iter().enumerate() {
+        let covered = loads.iter().enumerate().any(|(j, other)| {
+            j != i && covers(other, load) && (!covers(load, other) || other.request > load.request)
+        });
+        if !covered {
+            pending.push(load.clone());
+        }
+    }
+}
+
+/// Loads waiting for one of `max` slots, most recent first
+#[derive(Resource, Debug, Default)]
+pub struct LoadQueue {
+    pending: Vec<QueuedLoad>,
+}
+
+impl LoadQueue {
+    pub fn push(&mut self, load: QueuedLoad) {
+        self.pending.push(load);
+        coalesce(&mut self.pending);
+    }
+
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// Remove the loads that `is_current` rejects, e.g. superseded ones
+    pub fn retain(&mut self, is_current: impl Fn(&QueuedLoad) -> bool) {
+        self.pending.retain(is_current);
+    }
+
+    /// Loads to start with `in_flight` of `max` loads running, most recent first
+    pub fn next(&mut self, in_flight: usize, max: usize) -> Vec<QueuedLoad> {
+        let free = max.max(1).saturating_sub(in_flight);
+        self.pending
+            .sort_by_key(|load| std::cmp::Reverse(load.request));
+        let rest = self.pending.split_off(free.min(self.pending.len()));
+        std::mem::replace(&mut self.pending, rest)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(request: u64, params: &str) -> QueuedLoad {
+        QueuedLoad {
+            collection: "default".to_string(),
+            url: format!("http://localhost:3000/points?collection=default&{params}&seed=0"),
+            request,
+        }
+    }
+
+    #[test]
+    fn cover() {
+        let wide = load(1, "bounds=0,0,0,0,10,10,10,0.01");
+        let narrow = load(2, "bounds=2,2,2,0,8,8,8,0.01");
+        let dense = load(3, "bounds=2,2,2,0,8,8,8,0.1");
+        assert!(covers(&wide, &narrow));
+        assert!(!covers(&narrow, &wide));
+        // denser queries are not covered by sparser ones
+        assert!(!covers(&wide, &dense));
+        assert!(covers(&dense, &narrow));
+
+        // overviews cover every region at their density
+        assert!(covers(&load(4, "p=0.1"), &dense));
+        assert!(!covers(&load(4, "p=0.01"), &dense));
+        assert!(covers(&load(4, ""), &load(5, "p=0.1")));
+
+        // stable samples select subsets at smaller fractions
+        let stable = load(6, "sample=stable:0.1");
+        assert!(covers(&stable, &load(7, "sample=stable:0.01")));
+        assert!(!covers(&load(7, "sample=stable:0.01"), &stable));
+        assert!(!covers(&stable, &dense) && !covers(&dense, &stable));
+        assert!(covers(&load(8, ""), &stable));
+
+        // different queries, collections and server side samples
+        assert!(!covers(&load(4, "p=0.1&columns=intensity"), &dense));
+        let mut other = narrow.clone();
+        other.collection = "epoch2".to_string();
+        assert!(!covers(&wide, &other));
+        let sample = load(6, "sample=stratified:classification:1000");
+        assert!(!covers(&sample, &sample));
+    }
+
+    #[test]
+    fn schedule() {
+        let mut queue = LoadQueue::default();
+        queue.push(load(1, "bounds=2,2,2,0,8,8,8,0.01"));
+        queue.push(load(2, "bounds=20,20,0,0,30,30,10,0.01"));
+        // covers the first load
+        queue.push(load(3, "bounds=0,0,0,0,10,10,10,0.01"));
+        queue.push(load(4, "bounds=40,40,0,0,50,50,10,0.01"));
+        // equal to the previous load
+        queue.push(load(5, "bounds=40,40,0,0,50,50,10,0.01"));
+        assert_eq!(queue.len(), 3);
+
+        let requests = |loads: Vec<QueuedLoad>| -> Vec<u64> {
+            loads.iter().map(|load| load.request).collect()
+        };
+        assert!(queue.next(4, 4).is_empty());
+        assert_eq!(requests(queue.next(2, 4)), [5, 3]);
+        assert_eq!(queue.len(), 1);
+
+        queue.push(load(6, "p=0.001"));
+        queue.retain(|load| load.request != 6);
+        assert_eq!(requests(queue.next(0, 4)), [2]);
+        assert_eq!(queue.len(), 0);
+    }
+}
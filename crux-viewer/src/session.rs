@@ -0,0 +1,482 @@
+//! Recording and replay of viewer sessions, see `--record` and `--replay`.
+//!
+//! A session is a JSONL file of timestamped [SessionEvent]s: the settings at
+//! the start, the loads leaving the queue, the decoded responses with their
+//! digests, the controls with the camera pose at the time and the rendered
+//! instances at the end. The replay issues the loads and presses the keys of
+//! the controls in the recorded order, each once the previous loads are
+//! rendered, and compares the rendered instances with the recording.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    time::Instant,
+};
+
+use bevy::prelude::*;
+use bevy_aabb_instancing::Cuboid;
+use serde::{Deserialize, Serialize};
+
+use crate::{keys::Action, settings::CameraPose, ViewerSettings};
+
+/// Event of a session
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum SessionEvent {
+    /// Settings at the start of the session
+    Start { settings: Box<ViewerSettings> },
+    /// Load of a collection leaving the queue
+    Query { collection: String, url: String },
+    /// Decoded response to a load, with the file of the response body
+    /// relative to the session if bodies are recorded
+    Response {
+        collection: String,
+        url: String,
+        points: usize,
+        digest: u64,
+        body: Option<String>,
+    },
+    /// Control pressed at a camera pose
+    Action {
+        action: Action,
+        shift: bool,
+        camera: Option<CameraPose>,
+    },
+    /// Rendered instances at the end of the session
+    End { rendered: Option<InstanceSummary> },
+}
+
+/// Line of a session file
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Record {
+    /// Seconds since the start of the session
+    pub t: f64,
+    #[serde(flatten)]
+    pub event: SessionEvent,
+}
+
+/// Number and colors of rendered instances, to compare a replay with its
+/// recording
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InstanceSummary {
+    pub instances: usize,
+    /// FNV-1a hash of the instance colors in order
+    pub colors: u64,
+}
+
+impl InstanceSummary {
+    pub fn of<'a>(chunks: impl IntoIterator<Item = &'a Vec<Cuboid>>) -> Self {
+        let mut instances = 0;
+        let mut colors = 0xcbf2_9ce4_8422_2325_u64;
+        for cuboid in chunks.into_iter().flatten() {
+            instances += 1;
+            for byte in cuboid.color.to_le_bytes() {
+                colors = (colors ^ byte as u64).wrapping_mul(0x0100_0000_01b3);
+            }
+        }
+        Self { instances, colors }
+    }
+}
+
+impl fmt::Display for InstanceSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} instances, colors {:016x}",
+            self.instances, self.colors
+        )
+    }
+}
+
+/// Instances rendered last, kept while a session is recorded or replayed
+#[derive(Resource, Default)]
+pub struct RenderedSummary(pub Option<InstanceSummary>);
+
+/// Writes the events of a session as they happen
+#[derive(Resource)]
+pub struct SessionRecorder {
+    writer: BufWriter<File>,
+    start: Instant,
+    /// Directory of the session and of the recorded bodies, if recorded
+    dir: PathBuf,
+    bodies: Option<PathBuf>,
+    recorded: usize,
+}
+
+impl SessionRecorder {
+    /// Record to `path`, with the response bodies in a directory next to it,
+    /// e.g. `session.bodies/` of `session.jsonl`
+    pub fn create(path: &Path, record_bodies: bool) -> io::Result<Self> {
+        let dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+        let bodies = match record_bodies {
+            true => {
+                let bodies = path.with_extension("bodies");
+                std::fs::create_dir_all(&bodies)?;
+                Some(bodies)
+            }
+            false => None,
+        };
+
+        Ok(Self {
+            writer: BufWriter::new(File::create(path)?),
+            start: Instant::now(),
+            dir,
+            bodies,
+            recorded: 0,
+        })
+    }
+
+    pub fn records_bodies(&self) -> bool {
+        self.bodies.is_some()
+    }
+
+    /// Append `event`, flushed so that the session survives a crash
+    pub fn record(&mut self, event: SessionEvent) {
+        let record = Record {
+            t: self.start.elapsed().as_secs_f64(),
+            event,
+        };
+        let written = serde_json::to_writer(&mut self.writer, &record)
+            .map_err(io::Error::from)
+            .and_then(|_| self.writer.write_all(b"\n"))
+            .and_then(|_| self.writer.flush());
+        if let Err(e) = written {
+            warn!("Failed to record the session: {e}");
+        }
+    }
+
+    /// Write a response body, its path relative to the session
+    pub fn record_body(&mut self, body: &[u8]) -> Option<String> {
+        let bodies = self.bodies.as_ref()?;
+        self.recorded += 1;
+        let path = bodies.join(format!("{:06}.arrow", self.recorded));
+        match std::fs::write(&path, body) {
+            Ok(_) => Some(
+                path.strip_prefix(&self.dir)
+                    .unwrap_or(&path)
+                    .to_string_lossy()
+                    .into_owned(),
+            ),
+            Err(e) => {
+                warn!("Failed to record the response body to `{path:?}`: {e}");
+                None
+            }
+        }
+    }
+}
+
+/// Step of a replay
+#[derive(Debug, Clone, PartialEq)]
+pub enum Step {
+    /// Load `url` as `collection`, the shown collection at the time, from the
+    /// recorded `body` if any
+    Load {
+        collection: String,
+        url: String,
+        body: Option<PathBuf>,
+    },
+    /// Press the key of `action`, with shift, at the camera pose
+    Press {
+        action: Action,
+        shift: bool,
+        camera: Option<CameraPose>,
+    },
+    /// Compare the rendered instances with the recording
+    Finish { expected: Option<InstanceSummary> },
+}
+
+/// Recorded response to a load
+#[derive(Debug, Clone, PartialEq)]
+struct Expected {
+    points: usize,
+    digest: u64,
+    body: Option<PathBuf>,
+}
+
+/// Steps of a recorded session
+#[derive(Resource, Debug)]
+pub struct SessionReplay {
+    settings: Option<ViewerSettings>,
+    steps: VecDeque<Step>,
+    total: usize,
+    /// Responses of the issued loads by url
+    responses: HashMap<String, Expected>,
+    /// Recorded responses by the index of their load
+    expected: HashMap<usize, Expected>,
+    taken: usize,
+    /// Differences to the recording so far
+    pub mismatches: Vec<String>,
+    /// Rendered instances matched the recording at the end, if finished
+    pub outcome: Option<bool>,
+}
+
+impl SessionReplay {
+    pub fn open(path: &Path) -> Result<Self, String> {
+        let content = std::fs::read_to_string(path).map_err(|e| format!("{path:?}: {e}"))?;
+        let dir = path.parent().unwrap_or(Path::new(""));
+        Self::parse(&content, dir).map_err(|e| format!("{path:?}: {e}"))
+    }
+
+    /// Session of JSONL `content`, bodies relative to `dir`
+    pub fn parse(content: &str, dir: &Path) -> Result<Self, String> {
+        let records = content
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(i, line)| {
+                serde_json::from_str::<Record>(line).map_err(|e| format!("line {}: {e}", i + 1))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut settings = None;
+        let mut steps = VecDeque::new();
+        let mut expected = HashMap::new();
+        let mut answered = vec![false; records.len()];
+        for (i, record) in records.iter().enumerate() {
+            match &record.event {
+                SessionEvent::Start { settings: start } => {
+                    settings = Some(start.as_ref().clone());
+                }
+                SessionEvent::Query { collection, url } => {
+                    // the response of the load, unless it is loaded again first
+                    let response = records[i + 1..]
+                        .iter()
+                        .enumerate()
+                        .take_while(|(_, r)| {
+                            !matches!(&r.event, SessionEvent::Query { url: u, .. } if u == url)
+                        })
+                        .find_map(|(j, r)| match &r.event {
+                            SessionEvent::Response {
+                                url: u,
+                                points,
+                                digest,
+                                body,
+                                ..
+                            } if u == url && !answered[i + 1 + j] => Some((
+                                i + 1 + j,
+                                Expected {
+                                    points: *points,
+                                    digest: *digest,
+                                    body: body.as_ref().map(|body| dir.join(body)),
+                                },
+                            )),
+                            _ => None,
+                        });
+                    let body = response.as_ref().and_then(|(_, e)| e.body.clone());
+                    if let Some((j, response)) = response {
+                        answered[j] = true;
+                        expected.insert(steps.len(), response);
+                    }
+                    steps.push_back(Step::Load {
+                        collection: collection.to_owned(),
+                        url: url.to_owned(),
+                        body,
+                    });
+                }
+                SessionEvent::Response { .. } => (),
+                SessionEvent::Action {
+                    action,
+                    shift,
+                    camera,
+                } => steps.push_back(Step::Press {
+                    action: *action,
+                    shift: *shift,
+                    camera: *camera,
+                }),
+                SessionEvent::End { rendered } => steps.push_back(Step::Finish {
+                    expected: *rendered,
+                }),
+            }
+        }
+
+        Ok(Self {
+            settings,
+            total: steps.len(),
+            steps,
+            responses: HashMap::new(),
+            expected,
+            taken: 0,
+            mismatches: Vec::new(),
+            outcome: None,
+        })
+    }
+
+    /// Settings at the start of the session, once
+    pub fn take_settings(&mut self) -> Option<ViewerSettings> {
+        self.settings.take()
+    }
+
+    /// Next step, the response of a load is expected from then on
+    pub fn next_step(&mut self) -> Option<Step> {
+        let step = self.steps.pop_front()?;
+        if let (Step::Load { url, .. }, Some(expected)) = (&step, self.expected.remove(&self.taken))
+        {
+            self.responses.insert(url.to_owned(), expected);
+        }
+        self.taken += 1;
+        Some(step)
+    }
+
+    /// Recorded body of the response to `url`
+    pub fn body(&self, url: &str) -> Option<&Path> {
+        self.responses.get(url)?.body.as_deref()
+    }
+
+    /// Compare a response to `url` with the recording, differences are kept
+    /// in `mismatches`
+    pub fn verify(&mut self, url: &str, points: usize, digest: u64) {
+        let Some(expected) = self.responses.remove(url) else {
+            return;
+        };
+        if (expected.points, expected.digest) != (points, digest) {
+            self.mismatches.push(format!(
+                "response to `{url}`: {points} points, digest {digest:016x}, recorded {} points, digest {:016x}",
+                expected.points, expected.digest
+            ));
+        }
+    }
+
+    /// Compare the rendered instances at the end with the recording
+    pub fn finish(&mut self, rendered: Option<InstanceSummary>, expected: Option<InstanceSummary>) {
+        let matches = rendered == expected;
+        if !matches {
+            let show =
+                |s: Option<InstanceSummary>| s.map_or("nothing".to_string(), |s| s.to_string());
+            self.mismatches.push(format!(
+                "rendered {}, recorded {}",
+                show(rendered),
+                show(expected)
+            ));
+        }
+        self.outcome = Some(matches && self.mismatches.is_empty());
+    }
+
+    /// Progress of the replay for the overlay
+    pub fn status(&self) -> String {
+        match self.outcome {
+            None => format!("Replay: step {} of {}", self.taken, self.total),
+            Some(true) => {
+                "Replay: finished, the rendered instances match the recording".to_string()
+            }
+            Some(false) => format!(
+                "Replay: finished with {} differences to the recording",
+                self.mismatches.len()
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn record_and_replay() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("session.jsonl");
+        let pose = CameraPose {
+            origin: [1., 2., 3.],
+            focus: [0., 0., 0.],
+            alpha: 0.5,
+            beta: 0.8,
+            radius: 100.,
+        };
+        let url = |p| format!("http://0.0.0.0:3000/points?collection=default&p={p}");
+        let rendered = InstanceSummary::of(&[vec![Cuboid::new(Vec3::ZERO, Vec3::ONE, 7)]]);
+
+        let mut recorder = SessionRecorder::create(&path, true).unwrap();
+        let settings = ViewerSettings {
+            color_attribute: "intensity".to_string(),
+            ..default()
+        };
+        recorder.record(SessionEvent::Start {
+            settings: Box::new(settings.clone()),
+        });
+        for (p, points) in [(0.01, 10), (0.1, 100)] {
+            recorder.record(SessionEvent::Query {
+                collection: "default".to_string(),
+                url: url(p),
+            });
+            let body = recorder.record_body(&[points as u8]);
+            recorder.record(SessionEvent::Response {
+                collection: "default".to_string(),
+                url: url(p),
+                points,
+                digest: points as u64,
+                body,
+            });
+        }
+        recorder.record(SessionEvent::Action {
+            action: Action::ColorByCollection,
+            shift: false,
+            camera: Some(pose),
+        });
+        recorder.record(SessionEvent::End {
+            rendered: Some(rendered),
+        });
+        drop(recorder);
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(content.lines().count(), 7);
+        assert!(content.contains(r#""event":"action","action":"color_by_collection""#));
+
+        let mut replay = SessionReplay::open(&path).unwrap();
+        assert_eq!(replay.take_settings(), Some(settings));
+        assert_eq!(replay.take_settings(), None);
+
+        // loads from the recorded bodies
+        let Some(Step::Load {
+            url: first, body, ..
+        }) = replay.next_step()
+        else {
+            panic!("expected a load");
+        };
+        assert_eq!(first, url(0.01));
+        assert_eq!(std::fs::read(body.unwrap()).unwrap(), [10]);
+        assert_eq!(
+            replay.body(&first).map(|b| std::fs::read(b).unwrap()),
+            Some(vec![10])
+        );
+        replay.verify(&first, 10, 10);
+        assert!(replay.mismatches.is_empty());
+
+        let Some(Step::Load { url: second, .. }) = replay.next_step() else {
+            panic!("expected a load");
+        };
+        replay.verify(&second, 100, 99);
+        assert_eq!(replay.mismatches.len(), 1);
+        replay.mismatches.clear();
+
+        assert_eq!(
+            replay.next_step(),
+            Some(Step::Press {
+                action: Action::ColorByCollection,
+                shift: false,
+                camera: Some(pose),
+            })
+        );
+        assert_eq!(replay.status(), "Replay: step 3 of 4");
+
+        let Some(Step::Finish { expected }) = replay.next_step() else {
+            panic!("expected the end");
+        };
+        assert_eq!(expected, Some(rendered));
+        replay.finish(Some(rendered), expected);
+        assert_eq!(replay.outcome, Some(true));
+        assert_eq!(replay.next_step(), None);
+
+        // different colors
+        let other = InstanceSummary::of(&[vec![Cuboid::new(Vec3::ZERO, Vec3::ONE, 8)]]);
+        assert_eq!(other.instances, rendered.instances);
+        assert_ne!(other.colors, rendered.colors);
+        replay.finish(Some(other), expected);
+        assert_eq!(replay.outcome, Some(false));
+
+        assert!(SessionReplay::parse("{\"t\":0}", dir.path())
+            .unwrap_err()
+            .starts_with("line 1"));
+    }
+}
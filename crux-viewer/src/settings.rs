@@ -0,0 +1,700 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use bevy::prelude::*;
+use clap::Parser;
+use serde::{Deserialize, Serialize};
+
+use crux_format::LengthUnit;
+
+use crate::{
+    fog::Background,
+    history::Bookmark,
+    hud::HudVerbosity,
+    instances::{HEIGHT_ATTRIBUTE, MAX_INSTANCES},
+    keys::KeyBindings,
+    normalize::Normalization,
+    returns::ReturnsFilter,
+    schedule::MAX_LOADS,
+    sizing::PointSizing,
+    stereo::{StereoMode, StereoOverlays},
+};
+
+/// Current version of the settings file layout
+///
+/// Bump this when adding fields that need more than a serde default and add
+/// the upgrade step to [ViewerSettings::migrate].
+pub const SETTINGS_VERSION: u32 = 1;
+
+const SETTINGS_FILE: &str = "settings.toml";
+
+/// Viewer settings persisted between runs
+#[derive(Resource, Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct ViewerSettings {
+    /// Layout version of the settings file
+    #[serde(default)]
+    pub version: u32,
+    /// Server base url
+    pub server: String,
+    /// Collection to query
+    pub collection: String,
+    /// Second collection compared with `collection`, e.g. another epoch
+    pub compare: Option<String>,
+    /// Attribute used for coloring
+    pub color_attribute: String,
+    /// Optional gradient palette file with one hex color per line
+    pub palette: Option<PathBuf>,
+    /// Point size relative to the mean point spacing
+    pub point_size: f32,
+    /// Edge length of the points in `units`, instead of relative to the
+    /// point spacing
+    pub point_extent: Option<f64>,
+    /// Unit distances and point extents are shown and entered in, the unit
+    /// of the collection if not set
+    pub units: Option<LengthUnit>,
+    /// Uniform point size or adapted to the local point density
+    pub point_sizing: PointSizing,
+    /// Smallest adaptive point size relative to the uniform size
+    pub adaptive_min: f32,
+    /// Largest adaptive point size relative to the uniform size
+    pub adaptive_max: f32,
+    /// Automatically refine the view when the camera comes to rest
+    pub auto_lod: bool,
+    /// Frame the first load and follow data growing out of view
+    pub auto_frame: bool,
+    /// Returns shown, if the data provides return numbers
+    pub returns_filter: ReturnsFilter,
+    /// Normalization of scalar attributes
+    pub normalization: Normalization,
+    /// Memory budget of cached point clouds in MiB
+    pub memory_budget: usize,
+    /// Points rendered per collection, larger loads are downsampled locally
+    pub max_instances: usize,
+    /// GPU memory budget of the rendered points and textures in MiB, from
+    /// the adapter type if not set
+    pub gpu_budget: Option<usize>,
+    /// Fraction of the points of every decoded batch kept, a stable sample
+    pub decode_sample: Option<f64>,
+    /// Voxel size in data units of the thinning of every decoded batch
+    pub decode_voxel: Option<f64>,
+    /// Cell size in data units of the coverage gaps outlined on the minimap
+    pub minimap_gaps: Option<f64>,
+    /// Corridor width of height profiles in data units
+    pub profile_width: f64,
+    /// Number of distance bins of height profiles
+    pub profile_bins: usize,
+    /// Cell size of volume estimations in data units
+    pub volume_cell: f64,
+    /// Sensor trajectory, a CSV file or a collection with `gps_time`
+    pub trajectory: Option<String>,
+    /// GeoJSON file of lines and polygons drawn over the points, e.g.
+    /// building footprints, in the reference system of the points
+    pub vector: Option<PathBuf>,
+    /// Height of the vector features in data units, draped on the ground
+    /// below them if not set
+    pub vector_z: Option<f64>,
+    /// Timeout of a single server request in seconds
+    pub request_timeout: u64,
+    /// Maximum number of loads in flight, further loads are queued
+    pub max_loads: usize,
+    /// Seed of the sampled points, the same query returns the same points
+    pub seed: u64,
+    /// Request a fresh random sample on every load instead
+    pub random_samples: bool,
+    /// Server side sampling of overview loads instead of `p`, e.g.
+    /// `stratified:classification:1000000`
+    pub sample: Option<String>,
+    /// Opacity per collection in percent, fully opaque if missing
+    pub opacity: BTreeMap<String, u8>,
+    /// Depth bias per collection, see [crate::layers::depth_bias]
+    pub depth_bias: BTreeMap<String, u16>,
+    /// Offset the shown collections vertically, in the viewer only
+    pub exploded: bool,
+    /// Vertical offset between the collections of the exploded view in data
+    /// units
+    pub explode_offset: f64,
+    /// Side-by-side or anaglyph stereo output
+    pub stereo: StereoMode,
+    /// Distance between the eye cameras in world units, a fraction of the
+    /// distance to the focus if not set, see [crate::stereo::SEPARATION]
+    pub interocular: Option<f32>,
+    /// Eyes showing the gizmos and the overlay text in stereo
+    pub stereo_overlays: StereoOverlays,
+    /// Compact or full overlay text
+    pub hud: HudVerbosity,
+    /// Color behind the points
+    pub background: Background,
+    /// Attenuate distant points toward the background
+    pub fog: bool,
+    /// Share of the background in the farthest points of the fog, from 0 to 1
+    pub depth_cue: f32,
+    /// Last camera pose
+    pub camera: Option<CameraPose>,
+    /// Queried urls, most recent first
+    pub history: Vec<String>,
+    /// Named queries
+    pub bookmarks: Vec<Bookmark>,
+    /// Key of each control, e.g. `load_full = "F6"`
+    pub keys: KeyBindings,
+}
+
+/// Camera pose in the data reference system
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct CameraPose {
+    pub origin: [f64; 3],
+    pub focus: [f32; 3],
+    pub alpha: f32,
+    pub beta: f32,
+    pub radius: f32,
+}
+
+impl Default for ViewerSettings {
+    fn default() -> Self {
+        Self {
+            version: SETTINGS_VERSION,
+            server: "http://0.0.0.0:3000".to_string(),
+            collection: "default".to_string(),
+            compare: None,
+            color_attribute: HEIGHT_ATTRIBUTE.to_string(),
+            palette: None,
+            point_size: 1.,
+            point_extent: None,
+            units: None,
+            point_sizing: PointSizing::Uniform,
+            adaptive_min: 0.25,
+            adaptive_max: 4.,
+            auto_lod: false,
+            auto_frame: true,
+            returns_filter: ReturnsFilter::All,
+            normalization: Normalization::default(),
+            memory_budget: 2048,
+            max_instances: MAX_INSTANCES,
+            gpu_budget: None,
+            decode_sample: None,
+            decode_voxel: None,
+            minimap_gaps: None,
+            profile_width: 1.,
+            profile_bins: 100,
+            volume_cell: 0.5,
+            trajectory: None,
+            vector: None,
+            vector_z: None,
+            request_timeout: 30,
+            max_loads: MAX_LOADS,
+            seed: 0,
+            random_samples: false,
+            sample: None,
+            opacity: BTreeMap::new(),
+            depth_bias: BTreeMap::new(),
+            exploded: false,
+            explode_offset: 1.,
+            stereo: StereoMode::Off,
+            interocular: None,
+            stereo_overlays: StereoOverlays::Left,
+            hud: HudVerbosity::Full,
+            background: Background::Grey,
+            fog: false,
+            depth_cue: 0.6,
+            camera: None,
+            history: Vec::new(),
+            bookmarks: Vec::new(),
+            keys: KeyBindings::default(),
+        }
+    }
+}
+
+impl ViewerSettings {
+    /// Opacity of a collection in percent
+    pub fn opacity(&self, collection: &str) -> u8 {
+        self.opacity
+            .get(collection)
+            .copied()
+            .unwrap_or(100)
+            .min(100)
+    }
+
+    /// Platform specific default location of the settings file
+    pub fn default_path() -> Option<PathBuf> {
+        directories::ProjectDirs::from("de", "tum-bgd", "crux")
+            .map(|dirs| dirs.config_dir().join(SETTINGS_FILE))
+    }
+
+    /// Load settings, falling back to defaults if missing or corrupt
+    pub fn load(path: &Path) -> Self {
+        match std::fs::read_to_string(path) {
+            Ok(content) => match Self::parse(&content) {
+                Ok(settings) => settings,
+                Err(e) => {
+                    warn!("Failed to parse settings `{path:?}`, using defaults: {e}");
+                    Self::default()
+                }
+            },
+            Err(e) => {
+                info!("No settings loaded from `{path:?}`, using defaults: {e}");
+                Self::default()
+            }
+        }
+    }
+
+    /// Parse and migrate serialized settings
+    pub fn parse(content: &str) -> Result<Self, toml::de::Error> {
+        toml::from_str::<Self>(content).map(Self::migrate)
+    }
+
+    /// Upgrade settings written by older versions, newer ones keep their
+    /// version
+    pub fn migrate(mut self) -> Self {
+        if self.is_newer() {
+            warn!(
+                "Settings version {} is newer than supported version {SETTINGS_VERSION}, \
+                 changes are not saved",
+                self.version
+            );
+            return self;
+        }
+
+        // version 0: files written before versioning, all fields compatible
+
+        self.version = SETTINGS_VERSION;
+        self
+    }
+
+    /// Whether the settings were written by a newer viewer, saving them would
+    /// drop the fields unknown to this one
+    pub fn is_newer(&self) -> bool {
+        self.version > SETTINGS_VERSION
+    }
+
+    /// Write settings, creating parent directories as required
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let content = toml::to_string_pretty(self)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+        std::fs::write(path, content)
+    }
+}
+
+/// Point cloud viewer
+#[derive(Parser, Resource, Debug, Default)]
+#[command(author, version, about, long_about = None)]
+pub struct SettingsArgs {
+    /// Settings file (defaults to the platform config directory)
+    #[arg(long)]
+    pub settings: Option<PathBuf>,
+    /// Server base url
+    #[arg(long)]
+    pub server: Option<String>,
+    /// Collection to query
+    #[arg(long)]
+    pub collection: Option<String>,
+    /// Second collection compared with the collection, e.g. another epoch
+    #[arg(long)]
+    pub compare: Option<String>,
+    /// Attribute used for coloring
+    #[arg(long)]
+    pub color: Option<String>,
+    /// Gradient palette file with one hex color per line
+    #[arg(long)]
+    pub palette: Option<PathBuf>,
+    /// Point size relative to the mean point spacing
+    #[arg(long)]
+    pub point_size: Option<f32>,
+    /// Edge length of the points in `--units`, instead of relative to the
+    /// point spacing
+    #[arg(long)]
+    pub point_extent: Option<f64>,
+    /// Unit distances and point extents are shown and entered in (m, ft or
+    /// us-ft), the unit of the collection if not set
+    #[arg(long)]
+    pub units: Option<LengthUnit>,
+    /// Uniform point size or adapted to the local point density
+    #[arg(long)]
+    pub point_sizing: Option<PointSizing>,
+    /// Smallest adaptive point size relative to the uniform size
+    #[arg(long)]
+    pub adaptive_min: Option<f32>,
+    /// Largest adaptive point size relative to the uniform size
+    #[arg(long)]
+    pub adaptive_max: Option<f32>,
+    /// Automatically refine the view when the camera comes to rest
+    #[arg(long)]
+    pub auto_lod: Option<bool>,
+    /// Frame the first load and follow data growing out of view
+    #[arg(long)]
+    pub auto_frame: Option<bool>,
+    /// Memory budget of cached point clouds in MiB
+    #[arg(long)]
+    pub memory_budget: Option<usize>,
+    /// Points rendered per collection, larger loads are downsampled locally
+    #[arg(long)]
+    pub max_instances: Option<usize>,
+    /// GPU memory budget of the rendered points and textures in MiB, from
+    /// the adapter type if not set
+    #[arg(long)]
+    pub gpu_budget: Option<usize>,
+    /// Fraction of the points of every decoded batch kept, a stable sample
+    #[arg(long)]
+    pub decode_sample: Option<f64>,
+    /// Voxel size in data units of the thinning of every decoded batch
+    #[arg(long)]
+    pub decode_voxel: Option<f64>,
+    /// Cell size in data units of the coverage gaps outlined on the minimap
+    #[arg(long)]
+    pub minimap_gaps: Option<f64>,
+    /// Corridor width of height profiles in data units
+    #[arg(long)]
+    pub profile_width: Option<f64>,
+    /// Number of distance bins of height profiles
+    #[arg(long)]
+    pub profile_bins: Option<usize>,
+    /// Cell size of volume estimations in data units
+    #[arg(long)]
+    pub volume_cell: Option<f64>,
+    /// Sensor trajectory, a CSV file or a collection with `gps_time`
+    #[arg(long)]
+    pub trajectory: Option<String>,
+    /// GeoJSON file of lines and polygons drawn over the points, e.g. building footprints
+    #[arg(long)]
+    pub vector: Option<PathBuf>,
+    /// Height of the vector features in data units, draped on the ground if not set
+    #[arg(long)]
+    pub vector_z: Option<f64>,
+    /// Timeout of a single server request in seconds
+    #[arg(long)]
+    pub request_timeout: Option<u64>,
+    /// Maximum number of loads in flight
+    #[arg(long)]
+    pub max_loads: Option<usize>,
+    /// Seed of the sampled points
+    #[arg(long)]
+    pub seed: Option<u64>,
+    /// Request a fresh random sample on every load
+    #[arg(long)]
+    pub random_samples: Option<bool>,
+    /// Sampling of overview loads instead of `p`, e.g. `stratified:classification:1000000`
+    #[arg(long)]
+    pub sample: Option<String>,
+    /// Vertical offset between the collections of the exploded view in data units
+    #[arg(long)]
+    pub explode_offset: Option<f64>,
+    /// Side-by-side or anaglyph stereo output
+    #[arg(long)]
+    pub stereo: Option<StereoMode>,
+    /// Distance between the eye cameras in world units, from the distance to the focus if not set
+    #[arg(long)]
+    pub interocular: Option<f32>,
+    /// Eyes showing the gizmos and the overlay text in stereo
+    #[arg(long)]
+    pub stereo_overlays: Option<StereoOverlays>,
+    /// Compact or full overlay text
+    #[arg(long)]
+    pub hud: Option<HudVerbosity>,
+    /// Color behind the points
+    #[arg(long)]
+    pub background: Option<Background>,
+    /// Attenuate distant points toward the background
+    #[arg(long)]
+    pub fog: Option<bool>,
+    /// Share of the background in the farthest points of the fog, from 0 to 1
+    #[arg(long)]
+    pub depth_cue: Option<f32>,
+    /// Show a generated scene of buildings on terrain instead of querying the server
+    #[arg(long)]
+    pub demo: bool,
+    /// Render a single image and exit, without reading or writing the settings file
+    #[arg(long)]
+    pub headless: bool,
+    /// Image written in headless mode
+    #[arg(long, requires = "headless")]
+    pub screenshot: Option<PathBuf>,
+    /// Write the legend of the colors next to the image, e.g. `thumbnail.legend.png`
+    /// and the color map as `thumbnail.legend.json`
+    #[arg(long, requires = "headless")]
+    pub legend: bool,
+    /// JSON camera pose of the image, defaults to the reset view (R)
+    #[arg(long, requires = "headless")]
+    pub pose: Option<PathBuf>,
+    /// Arrow file or points URL rendered instead of a sample of the collection
+    #[arg(long, requires = "headless")]
+    pub input: Option<String>,
+    /// Chrome trace of the loads written on exit, e.g. for chrome://tracing
+    #[arg(long)]
+    pub trace: Option<PathBuf>,
+    /// Record the loads, responses and controls of the session to a JSONL file
+    #[arg(long, conflicts_with = "replay")]
+    pub record: Option<PathBuf>,
+    /// Record the response bodies next to the session, for replays without the server
+    #[arg(long, requires = "record")]
+    pub record_bodies: bool,
+    /// Replay a recorded session, without reading or writing the settings file
+    #[arg(long, conflicts_with = "headless")]
+    pub replay: Option<PathBuf>,
+}
+
+impl SettingsArgs {
+    /// Override settings by command line flags
+    pub fn apply(&self, settings: &mut ViewerSettings) {
+        if let Some(server) = &self.server {
+            settings.server = server.to_owned();
+        }
+        if let Some(collection) = &self.collection {
+            settings.collection = collection.to_owned();
+        }
+        if let Some(compare) = &self.compare {
+            settings.compare = Some(compare.to_owned());
+        }
+        if let Some(color) = &self.color {
+            settings.color_attribute = color.to_owned();
+        }
+        if let Some(palette) = &self.palette {
+            settings.palette = Some(palette.to_owned());
+        }
+        if let Some(point_size) = self.point_size {
+            settings.point_size = point_size;
+        }
+        if let Some(point_extent) = self.point_extent {
+            settings.point_extent = Some(point_extent);
+        }
+        if let Some(units) = self.units {
+            settings.units = Some(units);
+        }
+        if let Some(point_sizing) = self.point_sizing {
+            settings.point_sizing = point_sizing;
+        }
+        if let Some(adaptive_min) = self.adaptive_min {
+            settings.adaptive_min = adaptive_min;
+        }
+        if let Some(adaptive_max) = self.adaptive_max {
+            settings.adaptive_max = adaptive_max;
+        }
+        if let Some(auto_lod) = self.auto_lod {
+            settings.auto_lod = auto_lod;
+        }
+        if let Some(auto_frame) = self.auto_frame {
+            settings.auto_frame = auto_frame;
+        }
+        if let Some(memory_budget) = self.memory_budget {
+            settings.memory_budget = memory_budget;
+        }
+        if let Some(max_instances) = self.max_instances {
+            settings.max_instances = max_instances;
+        }
+        if let Some(gpu_budget) = self.gpu_budget {
+            settings.gpu_budget = Some(gpu_budget);
+        }
+        if let Some(decode_sample) = self.decode_sample {
+            settings.decode_sample = Some(decode_sample);
+        }
+        if let Some(decode_voxel) = self.decode_voxel {
+            settings.decode_voxel = Some(decode_voxel);
+        }
+        if let Some(minimap_gaps) = self.minimap_gaps {
+            settings.minimap_gaps = Some(minimap_gaps);
+        }
+        if let Some(profile_width) = self.profile_width {
+            settings.profile_width = profile_width;
+        }
+        if let Some(profile_bins) = self.profile_bins {
+            settings.profile_bins = profile_bins;
+        }
+        if let Some(volume_cell) = self.volume_cell {
+            settings.volume_cell = volume_cell;
+        }
+        if let Some(trajectory) = &self.trajectory {
+            settings.trajectory = Some(trajectory.to_owned());
+        }
+        if let Some(vector) = &self.vector {
+            settings.vector = Some(vector.to_owned());
+        }
+        if let Some(vector_z) = self.vector_z {
+            settings.vector_z = Some(vector_z);
+        }
+        if let Some(request_timeout) = self.request_timeout {
+            settings.request_timeout = request_timeout;
+        }
+        if let Some(max_loads) = self.max_loads {
+            settings.max_loads = max_loads;
+        }
+        if let Some(seed) = self.seed {
+            settings.seed = seed;
+        }
+        if let Some(random_samples) = self.random_samples {
+            settings.random_samples = random_samples;
+        }
+        if let Some(sample) = &self.sample {
+            settings.sample = Some(sample.to_owned());
+        }
+        if let Some(explode_offset) = self.explode_offset {
+            settings.explode_offset = explode_offset;
+        }
+        if let Some(stereo) = self.stereo {
+            settings.stereo = stereo;
+        }
+        if let Some(interocular) = self.interocular {
+            settings.interocular = Some(interocular);
+        }
+        if let Some(stereo_overlays) = self.stereo_overlays {
+            settings.stereo_overlays = stereo_overlays;
+        }
+        if let Some(hud) = self.hud {
+            settings.hud = hud;
+        }
+        if let Some(background) = self.background {
+            settings.background = background;
+        }
+        if let Some(fog) = self.fog {
+            settings.fog = fog;
+        }
+        if let Some(depth_cue) = self.depth_cue {
+            settings.depth_cue = depth_cue;
+        }
+    }
+}
+
+/// Location the settings are persisted to
+#[derive(Resource, Default)]
+pub struct SettingsPath(pub Option<PathBuf>);
+
+/// Settings of the path, if any, overridden by the arguments, loaded before
+/// the viewer plugin is added
+pub fn load_settings(path: &SettingsPath, args: &SettingsArgs) -> ViewerSettings {
+    let mut settings = path
+        .0
+        .as_deref()
+        .map(ViewerSettings::load)
+        .unwrap_or_default();
+    args.apply(&mut settings);
+    settings
+}
+
+/// Warn about keys bound to more than one action
+pub fn key_conflicts_system(keys: Res<KeyBindings>) {
+    for (key, actions) in keys.conflicts() {
+        let actions: Vec<String> = actions.iter().map(|a| format!("{a:?}")).collect();
+        warn!("Key {key:?} is bound to {}", actions.join(" and "));
+    }
+}
+
+/// Gradient for scalar attributes, defaults to turbo
+pub fn gradient(settings: &ViewerSettings) -> colorgrad::Gradient {
+    crux_format::color::gradient(settings.palette.as_deref()).unwrap_or_else(|e| {
+        warn!("Fallback to turbo: {e}");
+        colorgrad::turbo()
+    })
+}
+
+/// Persist settings whenever they change, unless written by a newer viewer
+pub fn save_settings_system(settings: Res<ViewerSettings>, path: Res<SettingsPath>) {
+    if settings.is_changed() && !settings.is_added() && !settings.is_newer() {
+        if let Some(path) = &path.0 {
+            if let Err(e) = settings.save(path) {
+                warn!("Failed to save settings to `{path:?}`: {e}");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{history::PointsQuery, keys::Action};
+
+    #[test]
+    fn round_trip() {
+        let settings = ViewerSettings {
+            server: "http://example.com:3000".to_string(),
+            color_attribute: "intensity".to_string(),
+            palette: Some(PathBuf::from("palette.txt")),
+            point_size: 2.5,
+            point_extent: Some(0.5),
+            units: Some(LengthUnit::UsSurveyFoot),
+            point_sizing: PointSizing::Adaptive,
+            adaptive_max: 8.,
+            auto_lod: true,
+            returns_filter: ReturnsFilter::Last,
+            normalization: Normalization {
+                lower: 5.,
+                upper: 95.,
+                gamma: 2.2,
+            },
+            opacity: BTreeMap::from([("epoch2".to_string(), 40)]),
+            depth_bias: BTreeMap::from([("epoch2".to_string(), 128)]),
+            exploded: true,
+            stereo: StereoMode::SideBySide,
+            interocular: Some(0.065),
+            stereo_overlays: StereoOverlays::Both,
+            hud: HudVerbosity::Compact,
+            max_instances: 1_000_000,
+            gpu_budget: Some(1024),
+            camera: Some(CameraPose {
+                origin: [1., 2., 3.],
+                focus: [0., 1., 0.],
+                alpha: 0.5,
+                beta: 0.8,
+                radius: 100.,
+            }),
+            history: vec!["http://example.com:3000/points?collection=default&p=0.01".to_string()],
+            bookmarks: vec![Bookmark {
+                name: "campus north, p=0.01".to_string(),
+                query: PointsQuery {
+                    collection: "campus".to_string(),
+                    bounds: Some(vec![1., 2., 3., 4., 5., 6.]),
+                    p: Some(0.01),
+                    columns: Some(vec!["intensity".to_string()]),
+                    params: Vec::new(),
+                },
+                camera: None,
+            }],
+            keys: {
+                let mut keys = KeyBindings::default();
+                keys.bind(Action::RefineView, KeyCode::Space);
+                keys
+            },
+            ..Default::default()
+        };
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("nested").join(SETTINGS_FILE);
+
+        settings.save(&path).unwrap();
+        assert_eq!(ViewerSettings::load(&path), settings);
+    }
+
+    #[test]
+    fn fallback() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(SETTINGS_FILE);
+
+        // missing
+        assert_eq!(ViewerSettings::load(&path), ViewerSettings::default());
+
+        // corrupt
+        std::fs::write(&path, "point_size = [").unwrap();
+        assert_eq!(ViewerSettings::load(&path), ViewerSettings::default());
+    }
+
+    #[test]
+    fn migrate() {
+        // unversioned file with a subset of the fields
+        let settings = ViewerSettings::parse("point_size = 3.0").unwrap();
+
+        assert_eq!(settings.version, SETTINGS_VERSION);
+        assert_eq!(settings.point_size, 3.);
+        assert_eq!(settings.server, ViewerSettings::default().server);
+        assert!(!settings.is_newer());
+
+        // file of a newer viewer
+        let newer = SETTINGS_VERSION + 1;
+        let settings =
+            ViewerSettings::parse(&format!("version = {newer}\npoint_size = 3.0")).unwrap();
+
+        assert_eq!(settings.version, newer);
+        assert_eq!(settings.point_size, 3.);
+        assert!(settings.is_newer());
+    }
+}
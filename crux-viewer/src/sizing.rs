@@ -0,0 +1,304 @@
+use std::collections::HashMap;
+
+use bevy::math::DVec3;
+use serde::{Deserialize, Serialize};
+
+use crux_format::{Point, PointTrait, AABB};
+
+use crate::{
+    keys::{Action, KeyBindings},
+    ViewerSettings,
+};
+
+/// Cells along the longest extent of the density grid
+const GRID_RESOLUTION: f64 = 128.;
+/// Extents below this share of the largest one are flat
+pub const FLAT: f64 = 1e-3;
+/// Spacing of points without extent, e.g. a single one
+pub const POINT_SPACING: f64 = 1.;
+
+/// Extents of `aabb` along x, y and z
+pub fn size(aabb: &AABB<Point<f64, 3>>) -> DVec3 {
+    DVec3::from_slice(aabb.upper().coords()) - DVec3::from_slice(aabb.lower().coords())
+}
+
+/// Mean spacing of `num_points` points within `aabb`: the edge of the cube
+/// each of them fills, or of the square or segment along the axes the bounds
+/// are not [FLAT] in, e.g. of a planar cloud or a single scanline.
+/// [POINT_SPACING] without extent.
+pub fn mean_spacing(aabb: &AABB<Point<f64, 3>>, num_points: usize) -> f64 {
+    let size = size(aabb);
+    let largest = size.max_element();
+    if !largest.is_finite() || largest <= 0. {
+        return POINT_SPACING;
+    }
+
+    let spread: Vec<f64> = size
+        .to_array()
+        .into_iter()
+        .filter(|extent| *extent > largest * FLAT)
+        .collect();
+    let content: f64 = spread.iter().product();
+    (content / num_points.max(1) as f64).powf(1. / spread.len() as f64)
+}
+
+/// Half extent of uniformly sized points, a tenth of their mean spacing
+/// scaled by the `point_size` of the settings
+pub fn uniform_half_extent(aabb: &AABB<Point<f64, 3>>, num_points: usize, point_size: f32) -> f32 {
+    (mean_spacing(aabb, num_points) / 10.) as f32 * point_size
+}
+
+/// Size of the rendered points
+#[derive(Serialize, Deserialize, clap::ValueEnum, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PointSizing {
+    /// The same size for all points, from the mean point spacing
+    #[default]
+    Uniform,
+    /// Larger points in sparse regions, smaller ones in dense regions
+    Adaptive,
+}
+
+impl PointSizing {
+    pub fn next(self) -> Self {
+        match self {
+            Self::Uniform => Self::Adaptive,
+            Self::Adaptive => Self::Uniform,
+        }
+    }
+
+    /// Active sizing for the overlay
+    pub fn status(self, keys: &KeyBindings) -> String {
+        format!("Point size ({}): {self}", keys.label(Action::PointSizing))
+    }
+}
+
+impl std::fmt::Display for PointSizing {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Uniform => write!(f, "uniform"),
+            Self::Adaptive => write!(f, "adaptive"),
+        }
+    }
+}
+
+/// Local point spacing of a collection on a regular grid, in the data
+/// reference system.
+///
+/// The spacing in a cell of `n` points is `cell / n^(1/3)`, like the mean
+/// spacing of the uniform size over the bounds. It is stored relative to the
+/// mean spacing of all points, so that adaptive sizes scale the uniform size of
+/// any sample of the collection. The lowest height of each column of cells is
+/// kept as the ground vector features are draped on.
+#[derive(Debug, Clone)]
+pub struct DensityGrid {
+    lower: DVec3,
+    cell: f64,
+    scales: HashMap<[i64; 3], f32>,
+    ground: HashMap<[i64; 2], f64>,
+}
+
+impl DensityGrid {
+    /// Grid over the finite points, `None` without extent
+    pub fn new(points: &[[f64; 3]]) -> Option<Self> {
+        let finite = points
+            .iter()
+            .map(|p| DVec3::from_array(*p))
+            .filter(|p| p.is_finite());
+        let (lower, upper) = finite.clone().fold(
+            (DVec3::INFINITY, DVec3::NEG_INFINITY),
+            |(lower, upper), p| (lower.min(p), upper.max(p)),
+        );
+        let cell = (upper - lower).max_element() / GRID_RESOLUTION;
+        if !cell.is_finite() || cell <= 0. {
+            return None;
+        }
+
+        let mut counts: HashMap<[i64; 3], u32> = HashMap::new();
+        let mut ground: HashMap<[i64; 2], f64> = HashMap::new();
+        for p in finite {
+            let key = key(lower, cell, p);
+            *counts.entry(key).or_default() += 1;
+            let z = ground.entry([key[0], key[1]]).or_insert(p.z);
+            *z = z.min(p.z);
+        }
+
+        // mean spacing of the points, in cells
+        let spacing = |count: u32| (count as f64).powf(-1. / 3.);
+        let total: u32 = counts.values().sum();
+        let mean = counts
+            .values()
+            .map(|count| *count as f64 * spacing(*count))
+            .sum::<f64>()
+            / total as f64;
+
+        let scales = counts
+            .into_iter()
+            .map(|(key, count)| (key, (spacing(count) / mean) as f32))
+            .collect();
+        Some(Self {
+            lower,
+            cell,
+            scales,
+            ground,
+        })
+    }
+
+    /// Lowest height of the points in the column of cells at `x`, `y`, the
+    /// bottom of the grid outside of it
+    pub fn ground(&self, x: f64, y: f64) -> f64 {
+        let key = key(self.lower, self.cell, DVec3::new(x, y, self.lower.z));
+        self.ground
+            .get(&[key[0], key[1]])
+            .copied()
+            .unwrap_or(self.lower.z)
+    }
+
+    /// Spacing at `p` relative to the mean spacing, 1 outside of the grid
+    pub fn scale(&self, p: DVec3) -> f32 {
+        self.scales
+            .get(&key(self.lower, self.cell, p))
+            .copied()
+            .unwrap_or(1.)
+    }
+}
+
+fn key(lower: DVec3, cell: f64, p: DVec3) -> [i64; 3] {
+    let cell = ((p - lower) / cell).floor();
+    [cell.x as i64, cell.y as i64, cell.z as i64]
+}
+
+/// Half extents of the instances of a collection
+pub enum Extents<'a> {
+    Uniform(f32),
+    /// The uniform half extent scaled by the local spacing, within the
+    /// bounds relative to the uniform one
+    Adaptive {
+        half_extent: f32,
+        grid: &'a DensityGrid,
+        min: f32,
+        max: f32,
+    },
+}
+
+impl<'a> Extents<'a> {
+    /// Extents of the sizing in the settings, uniform until the grid is built
+    pub fn new(half_extent: f32, grid: Option<&'a DensityGrid>, settings: &ViewerSettings) -> Self {
+        match (settings.point_sizing, grid) {
+            (PointSizing::Adaptive, Some(grid)) => Self::Adaptive {
+                half_extent,
+                grid,
+                min: settings.adaptive_min,
+                max: settings.adaptive_max,
+            },
+            _ => Self::Uniform(half_extent),
+        }
+    }
+
+    /// Half extent of a point at `p`, in the data reference system
+    pub fn at(&self, p: DVec3) -> f32 {
+        match self {
+            Self::Uniform(half_extent) => *half_extent,
+            Self::Adaptive {
+                half_extent,
+                grid,
+                min,
+                max,
+            } => half_extent * grid.scale(p).max(*min).min(*max),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn adaptive() {
+        // a dense and a sparse block of points along x
+        let dense = (0..1000).map(|i| {
+            [
+                (i % 10) as f64 * 0.01,
+                (i / 10 % 10) as f64 * 0.01,
+                (i / 100) as f64 * 0.01,
+            ]
+        });
+        let sparse = (0..8).map(|i| {
+            [
+                10. + (i % 2) as f64 * 0.5,
+                (i / 2 % 2) as f64 * 0.5,
+                (i / 4) as f64 * 0.5,
+            ]
+        });
+        let points: Vec<[f64; 3]> = dense.chain(sparse).collect();
+        let grid = DensityGrid::new(&points).unwrap();
+
+        let (dense, sparse) = (grid.scale(DVec3::ZERO), grid.scale(DVec3::new(10., 0., 0.)));
+        assert!(dense < 1. && sparse > 1., "{dense} {sparse}");
+        assert!(sparse > dense * 2.);
+        assert_eq!(grid.scale(DVec3::new(-100., 0., 0.)), 1.);
+
+        // sizes within the bounds, uniform without a grid
+        let settings = ViewerSettings {
+            point_sizing: PointSizing::Adaptive,
+            adaptive_min: 0.5,
+            adaptive_max: 2.,
+            ..Default::default()
+        };
+        let extents = Extents::new(0.1, Some(&grid), &settings);
+        assert_eq!(extents.at(DVec3::ZERO), 0.1 * dense.max(0.5));
+        assert_eq!(extents.at(DVec3::new(10., 0., 0.)), 0.2);
+        let settings = ViewerSettings {
+            adaptive_min: 1.,
+            ..settings
+        };
+        let extents = Extents::new(0.1, Some(&grid), &settings);
+        assert_eq!(extents.at(DVec3::ZERO), 0.1);
+        assert_eq!(Extents::new(0.1, None, &settings).at(DVec3::ZERO), 0.1);
+        let settings = ViewerSettings::default();
+        assert_eq!(
+            Extents::new(0.1, Some(&grid), &settings).at(DVec3::ZERO),
+            0.1
+        );
+
+        // lowest points of the columns, the bottom between them
+        let grid = DensityGrid::new(&[[0., 0., 5.], [0., 0., 7.], [10., 10., 2.]]).unwrap();
+        assert_eq!(grid.ground(0., 0.), 5.);
+        assert_eq!(grid.ground(10., 10.), 2.);
+        assert_eq!(grid.ground(5., 5.), 2.);
+
+        // no extent
+        assert!(DensityGrid::new(&[[1., 2., 3.]; 4]).is_none());
+        assert!(DensityGrid::new(&[[0., 0., 0.], [10., 0., 0.]]).is_some());
+        assert!(DensityGrid::new(&[]).is_none());
+        assert_eq!(PointSizing::Uniform.next(), PointSizing::Adaptive);
+    }
+
+    fn aabb(lower: [f64; 3], upper: [f64; 3]) -> AABB<Point<f64, 3>> {
+        AABB::from_corners(Point::from_slice(&lower), Point::from_slice(&upper))
+    }
+
+    #[test]
+    fn degenerate() {
+        // a single point
+        let point = aabb([5., 5., 5.], [5., 5., 5.]);
+        assert_eq!(mean_spacing(&point, 1), POINT_SPACING);
+        assert_eq!(uniform_half_extent(&point, 1, 2.), 0.2);
+        assert_eq!(mean_spacing(&AABB::new_empty(), 0), POINT_SPACING);
+
+        // a scanline of 11 points 1 m apart, and one that is not quite straight
+        let line = aabb([0., 0., 0.], [10., 0., 0.]);
+        assert!((mean_spacing(&line, 11) - 10. / 11.).abs() < 1e-12);
+        let line = aabb([0., 0., 0.], [10., 1e-6, 0.]);
+        assert!((mean_spacing(&line, 11) - 10. / 11.).abs() < 1e-12);
+
+        // a planar cloud of 100 points on 10 x 10 m
+        let plane = aabb([0., 0., 2.], [10., 10., 2.]);
+        assert!((mean_spacing(&plane, 100) - 1.).abs() < 1e-12);
+        assert!((uniform_half_extent(&plane, 100, 1.) - 0.1).abs() < 1e-6);
+
+        // the spacing within the volume otherwise
+        let volume = aabb([0., 0., 0.], [10., 10., 10.]);
+        assert!((mean_spacing(&volume, 1000) - 1.).abs() < 1e-12);
+    }
+}
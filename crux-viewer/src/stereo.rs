@@ -0,0 +1,201 @@
+//! Stereo rendering for 3D projectors: two eye cameras offset from the orbit
+//! camera to either side, shown in the halves of the window or composited to
+//! a red-cyan anaglyph.
+
+use bevy::{prelude::*, render::camera::Viewport};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    keys::{Action, KeyBindings},
+    ViewerSettings,
+};
+
+/// Interocular distance relative to the distance to the focus unless set,
+/// the 1/30 rule of stereo photography
+pub const SEPARATION: f32 = 1. / 30.;
+
+/// Output of the stereo mode
+#[derive(Serialize, Deserialize, clap::ValueEnum, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum StereoMode {
+    /// A single camera
+    #[default]
+    Off,
+    /// The left eye in the left half of the window, the right eye in the
+    /// right half, e.g. for projectors with a side-by-side 3D input
+    SideBySide,
+    /// Red-cyan anaglyph of both eyes for colored glasses
+    Anaglyph,
+}
+
+/// Eyes showing the gizmos, drawn with a depth bias, and the overlay text
+#[derive(Serialize, Deserialize, clap::ValueEnum, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum StereoOverlays {
+    /// The left eye only, overlays show up once on the screen
+    #[default]
+    Left,
+    /// Both eyes, gizmos appear at their depth
+    Both,
+}
+
+impl StereoMode {
+    pub fn next(self) -> Self {
+        match self {
+            Self::Off => Self::SideBySide,
+            Self::SideBySide => Self::Anaglyph,
+            Self::Anaglyph => Self::Off,
+        }
+    }
+
+    pub fn is_on(self) -> bool {
+        self != Self::Off
+    }
+}
+
+impl std::fmt::Display for StereoMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Off => write!(f, "off"),
+            Self::SideBySide => write!(f, "side-by-side"),
+            Self::Anaglyph => write!(f, "anaglyph"),
+        }
+    }
+}
+
+/// Eye camera of the stereo mode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Eye {
+    Left,
+    Right,
+}
+
+impl Eye {
+    pub const BOTH: [Eye; 2] = [Eye::Left, Eye::Right];
+
+    /// Transform relative to the orbit camera at `distance` from the focus,
+    /// offset by half the interocular distance perpendicular to the view
+    /// direction and turned towards the focus, so that it appears at the depth
+    /// of the screen
+    pub fn transform(self, interocular: f32, distance: f32) -> Transform {
+        let side = match self {
+            Eye::Left => -0.5,
+            Eye::Right => 0.5,
+        };
+        let transform = Transform::from_xyz(side * interocular, 0., 0.);
+        if distance > 0. {
+            transform.looking_at(Vec3::new(0., 0., -distance), Vec3::Y)
+        } else {
+            transform
+        }
+    }
+
+    /// Viewport in a window of `size` physical pixels, the whole target
+    /// unless the eyes are side by side
+    pub fn viewport(self, mode: StereoMode, size: UVec2) -> Option<Viewport> {
+        if mode != StereoMode::SideBySide {
+            return None;
+        }
+        let half = (size.x / 2).max(1);
+        let (x, width) = match self {
+            Eye::Left => (0, half),
+            Eye::Right => (half, size.x.saturating_sub(half).max(1)),
+        };
+        Some(Viewport {
+            physical_position: UVec2::new(x, 0),
+            physical_size: UVec2::new(width, size.y.max(1)),
+            ..default()
+        })
+    }
+
+    /// Whether the eye shows the gizmos and the overlay text
+    pub fn shows_overlays(self, overlays: StereoOverlays) -> bool {
+        self == Eye::Left || overlays == StereoOverlays::Both
+    }
+}
+
+/// Interocular distance in world units with the focus at `distance`
+pub fn interocular(settings: &ViewerSettings, distance: f32) -> f32 {
+    settings.interocular.unwrap_or(distance * SEPARATION)
+}
+
+/// Overlay line of the stereo mode
+pub fn status(settings: &ViewerSettings, keys: &KeyBindings, distance: f32) -> String {
+    let mut status = format!(
+        "Stereo ({}): {}",
+        keys.label(Action::Stereo),
+        settings.stereo
+    );
+    if settings.stereo.is_on() {
+        status.push_str(&format!(
+            ", interocular {:.3}, overlays on {}",
+            interocular(settings, distance),
+            match settings.stereo_overlays {
+                StereoOverlays::Left => "the left eye",
+                StereoOverlays::Both => "both eyes",
+            }
+        ));
+    }
+    status
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn eyes() {
+        let left = Eye::Left.transform(2., 30.);
+        let right = Eye::Right.transform(2., 30.);
+        assert_eq!(left.translation, Vec3::new(-1., 0., 0.));
+        assert_eq!(right.translation, Vec3::new(1., 0., 0.));
+
+        // both converge on the focus in front of the orbit camera
+        let focus = Vec3::new(0., 0., -30.);
+        for eye in [left, right] {
+            let direction = (focus - eye.translation).normalize();
+            assert!(eye.forward().distance(direction) < 1e-6);
+        }
+
+        // parallel without a focus distance
+        assert_eq!(Eye::Left.transform(2., 0.).forward(), Vec3::NEG_Z);
+    }
+
+    #[test]
+    fn viewports() {
+        let size = UVec2::new(1921, 1080);
+        assert!(Eye::Left.viewport(StereoMode::Anaglyph, size).is_none());
+
+        let left = Eye::Left.viewport(StereoMode::SideBySide, size).unwrap();
+        let right = Eye::Right.viewport(StereoMode::SideBySide, size).unwrap();
+        assert_eq!(left.physical_position, UVec2::ZERO);
+        assert_eq!(left.physical_size, UVec2::new(960, 1080));
+        assert_eq!(right.physical_position, UVec2::new(960, 0));
+        assert_eq!(right.physical_size, UVec2::new(961, 1080));
+
+        // never empty, e.g. for minimized windows
+        let left = Eye::Left.viewport(StereoMode::SideBySide, UVec2::ZERO);
+        assert_eq!(left.unwrap().physical_size, UVec2::ONE);
+    }
+
+    #[test]
+    fn settings() {
+        let mut settings = ViewerSettings::default();
+        assert_eq!(interocular(&settings, 60.), 2.);
+        settings.interocular = Some(0.065);
+        assert_eq!(interocular(&settings, 60.), 0.065);
+
+        assert!(Eye::Left.shows_overlays(StereoOverlays::Left));
+        assert!(!Eye::Right.shows_overlays(StereoOverlays::Left));
+        assert!(Eye::Right.shows_overlays(StereoOverlays::Both));
+
+        let keys = KeyBindings::default();
+        assert_eq!(status(&settings, &keys, 60.), "Stereo (S): off");
+        settings.stereo = settings.stereo.next().next();
+        assert_eq!(
+            status(&settings, &keys, 60.),
+            "Stereo (S): anaglyph, interocular 0.065, overlays on the left eye"
+        );
+        assert_eq!(settings.stereo.next(), StereoMode::Off);
+    }
+}
@@ -0,0 +1,305 @@
+use bevy::{
+    asset::load_internal_asset,
+    core_pipeline::clear_color::ClearColorConfig,
+    prelude::*,
+    render::{
+        camera::{RenderTarget, Viewport},
+        render_resource::{
+            AsBindGroup, Extent3d, ShaderRef, TextureDescriptor, TextureDimension, TextureFormat,
+            TextureUsages,
+        },
+        view::RenderLayers,
+    },
+    sprite::{Material2d, Material2dPlugin, MaterialMesh2dBundle},
+    window::PrimaryWindow,
+};
+use bevy_panorbit_camera::PanOrbitCamera;
+
+use crate::{
+    keys::{Action, KeyBindings},
+    stereo::{self, Eye, StereoMode},
+    ViewerSettings,
+};
+
+const ANAGLYPH_SHADER: Handle<Shader> =
+    Handle::weak_from_u128(0x7c1d_2f4e_9a53_4b8e_a0c6_5e21_d9f3_8b47);
+
+/// Layer of the gizmos while they are shown by the left eye only
+pub const OVERLAY_LAYER: u8 = 1;
+/// Layer of the anaglyph composite, hidden from the other cameras
+const COMPOSITE_LAYER: u8 = 2;
+
+/// Eye camera of the stereo mode, a child of the orbit camera
+#[derive(Component)]
+pub struct StereoEye(Eye);
+
+/// Camera and quad compositing the anaglyph
+#[derive(Component)]
+pub struct StereoComposite;
+
+/// Red-cyan composite of the images of the eyes, see `anaglyph.wgsl`
+#[derive(Asset, TypePath, AsBindGroup, Clone)]
+pub struct AnaglyphMaterial {
+    #[texture(0)]
+    #[sampler(1)]
+    left: Handle<Image>,
+    #[texture(2)]
+    #[sampler(3)]
+    right: Handle<Image>,
+}
+
+impl Material2d for AnaglyphMaterial {
+    fn fragment_shader() -> ShaderRef {
+        ANAGLYPH_SHADER.into()
+    }
+}
+
+pub struct AnaglyphPlugin;
+
+impl Plugin for AnaglyphPlugin {
+    fn build(&self, app: &mut App) {
+        load_internal_asset!(app, ANAGLYPH_SHADER, "anaglyph.wgsl", Shader::from_wgsl);
+        app.add_plugins(Material2dPlugin::<AnaglyphMaterial>::default());
+    }
+}
+
+/// Stereo mode of the spawned cameras
+#[derive(Resource, Default)]
+pub struct Stereo {
+    mode: StereoMode,
+    /// Render targets of the eyes of the anaglyph
+    images: Option<[Handle<Image>; 2]>,
+}
+
+// Press 'S' to cycle the stereo modes
+pub fn stereo_controls_system(
+    key_input: Res<Input<KeyCode>>,
+    keys: Res<KeyBindings>,
+    mut settings: ResMut<ViewerSettings>,
+) {
+    if keys.just_pressed(&key_input, Action::Stereo) {
+        settings.stereo = settings.stereo.next();
+    }
+}
+
+/// Render target of an eye of the anaglyph
+fn eye_image(size: UVec2) -> Image {
+    let size = extent(size);
+    let mut image = Image {
+        texture_descriptor: TextureDescriptor {
+            label: None,
+            size,
+            dimension: TextureDimension::D2,
+            format: TextureFormat::Bgra8UnormSrgb,
+            mip_level_count: 1,
+            sample_count: 1,
+            usage: TextureUsages::TEXTURE_BINDING
+                | TextureUsages::COPY_DST
+                | TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        },
+        ..default()
+    };
+    image.resize(size);
+    image
+}
+
+fn extent(size: UVec2) -> Extent3d {
+    Extent3d {
+        width: size.x.max(1),
+        height: size.y.max(1),
+        ..default()
+    }
+}
+
+/// Layers of an eye, the gizmos on [OVERLAY_LAYER] are hidden from the right
+/// eye unless both eyes show the overlays
+fn eye_layers(eye: Eye, settings: &ViewerSettings) -> RenderLayers {
+    if eye.shows_overlays(settings.stereo_overlays) {
+        RenderLayers::layer(0).with(OVERLAY_LAYER)
+    } else {
+        RenderLayers::layer(0)
+    }
+}
+
+// Spawn the eye cameras as children of the orbit camera when the stereo mode
+// changes, which stays in control of the center pose but renders nothing
+// while the eyes do, and follow the focus distance, the projection and the
+// size of the window
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+pub fn stereo_system(
+    mut commands: Commands,
+    settings: Res<ViewerSettings>,
+    mut stereo: ResMut<Stereo>,
+    window: Query<&Window, With<PrimaryWindow>>,
+    mut camera: Query<(Entity, &mut Camera, &PanOrbitCamera, Ref<Projection>), Without<StereoEye>>,
+    mut eyes: Query<
+        (
+            Entity,
+            &StereoEye,
+            &mut Camera,
+            &mut Transform,
+            &mut Projection,
+            &mut UiCameraConfig,
+            &mut RenderLayers,
+        ),
+        Without<PanOrbitCamera>,
+    >,
+    composite: Query<Entity, With<StereoComposite>>,
+    mut quad: Query<&mut Transform, (With<Handle<AnaglyphMaterial>>, Without<StereoEye>)>,
+    (mut images, mut materials, mut meshes): (
+        ResMut<Assets<Image>>,
+        ResMut<Assets<AnaglyphMaterial>>,
+        ResMut<Assets<Mesh>>,
+    ),
+    mut gizmos: ResMut<GizmoConfig>,
+) {
+    let (Ok(window), Ok((entity, mut main, orbit, projection))) =
+        (window.get_single(), camera.get_single_mut())
+    else {
+        return;
+    };
+    let size = UVec2::new(window.physical_width(), window.physical_height());
+    let distance = orbit.radius.unwrap_or_default();
+    let interocular = stereo::interocular(&settings, distance);
+
+    if stereo.mode != settings.stereo {
+        stereo.mode = settings.stereo;
+        main.is_active = !stereo.mode.is_on();
+        for entity in eyes.iter().map(|(entity, ..)| entity).chain(&composite) {
+            commands.entity(entity).despawn_recursive();
+        }
+        stereo.images = None;
+        if !stereo.mode.is_on() {
+            info!("Stereo off");
+            return;
+        }
+
+        let images = (stereo.mode == StereoMode::Anaglyph)
+            .then(|| [images.add(eye_image(size)), images.add(eye_image(size))]);
+        for (i, eye) in Eye::BOTH.into_iter().enumerate() {
+            let target = match &images {
+                Some(images) => RenderTarget::Image(images[i].clone()),
+                None => RenderTarget::default(),
+            };
+            // the right eye keeps the left half of a shared window
+            let clear_color = match (eye, &images) {
+                (Eye::Right, None) => ClearColorConfig::None,
+                _ => ClearColorConfig::default(),
+            };
+            let child = commands
+                .spawn((
+                    Camera3dBundle {
+                        camera: Camera {
+                            order: i as isize - 2,
+                            viewport: eye.viewport(stereo.mode, size),
+                            target,
+                            ..default()
+                        },
+                        camera_3d: Camera3d {
+                            clear_color,
+                            ..default()
+                        },
+                        projection: projection.clone(),
+                        transform: eye.transform(interocular, distance),
+                        ..default()
+                    },
+                    UiCameraConfig {
+                        show_ui: images.is_none() && eye.shows_overlays(settings.stereo_overlays),
+                    },
+                    eye_layers(eye, &settings),
+                    StereoEye(eye),
+                ))
+                .id();
+            commands.entity(entity).add_child(child);
+        }
+
+        if let Some([left, right]) = &images {
+            commands.spawn((
+                Camera2dBundle::default(),
+                RenderLayers::layer(COMPOSITE_LAYER),
+                StereoComposite,
+            ));
+            commands.spawn((
+                MaterialMesh2dBundle {
+                    mesh: meshes.add(shape::Quad::new(Vec2::ONE).into()).into(),
+                    material: materials.add(AnaglyphMaterial {
+                        left: left.clone(),
+                        right: right.clone(),
+                    }),
+                    transform: Transform::from_scale(Vec3::new(
+                        window.width(),
+                        window.height(),
+                        1.,
+                    )),
+                    ..default()
+                },
+                RenderLayers::layer(COMPOSITE_LAYER),
+                StereoComposite,
+            ));
+        }
+        stereo.images = images;
+        info!(
+            "Stereo {} with an interocular distance of {interocular:.3}",
+            stereo.mode
+        );
+    }
+
+    let gizmo_layers = if stereo.mode.is_on() {
+        RenderLayers::layer(OVERLAY_LAYER)
+    } else {
+        RenderLayers::default()
+    };
+    if gizmos.render_layers != gizmo_layers {
+        gizmos.render_layers = gizmo_layers;
+    }
+
+    let rect = |viewport: &Option<Viewport>| {
+        viewport
+            .as_ref()
+            .map(|v| (v.physical_position, v.physical_size))
+    };
+
+    for (_, StereoEye(eye), mut camera, mut transform, mut eye_projection, mut ui, mut layers) in
+        &mut eyes
+    {
+        let eye_transform = eye.transform(interocular, distance);
+        if *transform != eye_transform {
+            *transform = eye_transform;
+        }
+        if projection.is_changed() {
+            *eye_projection = projection.clone();
+        }
+        let viewport = eye.viewport(stereo.mode, size);
+        if rect(&camera.viewport) != rect(&viewport) {
+            camera.viewport = viewport;
+        }
+        let show_ui = stereo.images.is_none() && eye.shows_overlays(settings.stereo_overlays);
+        if ui.show_ui != show_ui {
+            ui.show_ui = show_ui;
+        }
+        let eye_layers = eye_layers(*eye, &settings);
+        if *layers != eye_layers {
+            *layers = eye_layers;
+        }
+    }
+
+    if let Some(handles) = &stereo.images {
+        for handle in handles {
+            if images
+                .get(handle)
+                .is_some_and(|image| image.size() != size.max(UVec2::ONE))
+            {
+                if let Some(image) = images.get_mut(handle) {
+                    image.resize(extent(size));
+                }
+            }
+        }
+        let scale = Vec3::new(window.width(), window.height(), 1.);
+        for mut transform in &mut quad {
+            if transform.scale != scale {
+                transform.scale = scale;
+            }
+        }
+    }
+}
@@ -0,0 +1,241 @@
+//! Local preview of a sampling fraction on the loaded points, so that `p` is
+//! chosen without a round trip per try.
+//!
+//! The preview keeps a [stable](Sample::Stable) sample of the loaded points,
+//! so that changing the fraction only adds or removes points. Fetching at
+//! the previewed fraction queries the same stable sample on the server, which
+//! matches the preview exactly for points with a
+//! [point_id](crux_format::query::ID_COLUMN) and in density otherwise.
+
+use std::time::Duration;
+
+use bevy::prelude::*;
+
+use crux_format::{
+    prelude::*,
+    query::{Query, Sample, ID_COLUMN},
+};
+
+use crate::{
+    fetch::points_url,
+    gpu::INSTANCE_SIZE,
+    keys::{Action, KeyBindings},
+    memory::MIB,
+    LoadRequest, PointCache, ViewerSettings,
+};
+
+/// Time the fraction rests before the preview is regenerated
+pub const DEBOUNCE: Duration = Duration::from_millis(250);
+/// Change of the fraction per second while a key is held
+const RATE: f64 = 2.;
+/// Smallest previewed fraction
+const MIN_FRACTION: f64 = 1e-5;
+
+/// Previewed fraction of the collection
+#[derive(Resource)]
+pub struct ThinningPreview {
+    /// Applied fraction, `None` without preview
+    pub p: Option<f64>,
+    /// Fraction of the loaded points, the upper end of the preview
+    loaded: f64,
+    /// Fraction while held and the time it last changed, applied once it
+    /// rests for [DEBOUNCE]
+    pending: Option<(f64, Duration)>,
+    /// Rendered points of the preview, counted for display
+    pub shown: Option<usize>,
+}
+
+impl Default for ThinningPreview {
+    fn default() -> Self {
+        Self {
+            p: None,
+            loaded: 1.,
+            pending: None,
+            shown: None,
+        }
+    }
+}
+
+impl ThinningPreview {
+    /// Move the fraction by `factor` at `now`, starting from the loaded
+    /// fraction
+    pub fn drag(&mut self, factor: f64, now: Duration) {
+        let current = self
+            .pending
+            .map(|(p, _)| p)
+            .or(self.p)
+            .unwrap_or(self.loaded);
+        let p = (current * factor).clamp(MIN_FRACTION.min(self.loaded), self.loaded);
+        self.pending = Some((p, now));
+    }
+
+    /// Fraction that rested for [DEBOUNCE] at `now`, to be applied
+    pub fn settled(&self, now: Duration) -> Option<f64> {
+        let (p, changed) = self.pending?;
+        (now.saturating_sub(changed) >= DEBOUNCE).then_some(p)
+    }
+
+    /// Stable sample of the loaded `pc` at the previewed fraction, `None`
+    /// without preview
+    pub fn sample(&mut self, pc: &ArrowPointCloud) -> Option<ArrowPointCloud> {
+        let p = self.p?;
+        // hashes of identities are compared with the fraction of the
+        // collection, others are drawn from the loaded points only
+        let p = match pc.schema.column_with_name(ID_COLUMN) {
+            Some(_) => p,
+            None => p / self.loaded,
+        };
+        match pc.execute(&Query::new().sample(Sample::Stable(p))) {
+            Ok(sampled) => {
+                self.shown = Some(sampled.num_points());
+                Some(sampled)
+            }
+            Err(e) => {
+                warn!("Failed to preview a fraction of {p}: {e}");
+                None
+            }
+        }
+    }
+
+    /// Parameters of the query fetching the previewed fraction
+    pub fn params(&self) -> Option<String> {
+        self.p.map(|p| format!("sample=stable:{p}"))
+    }
+
+    /// Fraction, rendered points and their estimated GPU memory
+    pub fn status(&self, keys: &KeyBindings) -> Option<String> {
+        let p = self.pending.map(|(p, _)| p).or(self.p)?;
+        let shown = match self.shown.filter(|_| self.pending.is_none()) {
+            Some(shown) => format!(
+                "{shown} points, {:.1} MiB GPU",
+                (shown * INSTANCE_SIZE) as f64 / MIB as f64
+            ),
+            None => "updating".to_string(),
+        };
+        Some(format!(
+            "Preview p = {p:.5} ({}/{}): {shown}, {} to fetch, shift + {} to leave",
+            keys.label(Action::PreviewThinner),
+            keys.label(Action::PreviewDenser),
+            keys.label(Action::FetchPreview),
+            keys.label(Action::FetchPreview),
+        ))
+    }
+}
+
+/// Fraction of the collection loaded by `url`, 1 if not sampled
+pub fn loaded_fraction(url: &str) -> f64 {
+    let query = url.split_once('?').map_or("", |(_, query)| query);
+    query
+        .split('&')
+        .find_map(|param| match param.split_once('=')? {
+            ("p", p) => p.parse().ok(),
+            ("sample", sample) => sample.strip_prefix("stable:")?.parse().ok(),
+            _ => None,
+        })
+        .filter(|p: &f64| *p > 0. && *p <= 1.)
+        .unwrap_or(1.)
+}
+
+// Hold the keys to preview a thinner or denser fraction, fetch it or leave
+// the preview with shift
+pub fn thinning_system(
+    key_input: Res<Input<KeyCode>>,
+    keys: Res<KeyBindings>,
+    time: Res<Time>,
+    settings: Res<ViewerSettings>,
+    cache: Res<PointCache>,
+    mut preview: ResMut<ThinningPreview>,
+    mut requests: EventWriter<LoadRequest>,
+) {
+    if cache.get(&settings.collection).is_none() {
+        return;
+    }
+    let now = time.elapsed();
+    let shift = key_input.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]);
+
+    // dragging does not regenerate the instances, only the settled fraction
+    let dragged = preview.bypass_change_detection();
+    if dragged.p.is_none() && dragged.pending.is_none() {
+        dragged.loaded = cache
+            .etags
+            .get(&settings.collection)
+            .map_or(1., |(url, _)| loaded_fraction(url));
+    }
+    let step = RATE.powf(time.delta_seconds_f64());
+    if keys.pressed(&key_input, Action::PreviewThinner) {
+        dragged.drag(1. / step, now);
+    }
+    if keys.pressed(&key_input, Action::PreviewDenser) {
+        dragged.drag(step, now);
+    }
+
+    if let Some(p) = preview.settled(now) {
+        preview.p = Some(p);
+        preview.pending = None;
+    }
+
+    if keys.just_pressed(&key_input, Action::FetchPreview) {
+        if !shift {
+            if let Some(params) = preview.params() {
+                requests.send(LoadRequest::new(points_url(&settings, &params)));
+            }
+        }
+        // the fetched points replace the preview
+        *preview = ThinningPreview::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fractions() {
+        let url = "http://localhost:3000/points?collection=default&sample=stable:0.1&seed=0";
+        assert_eq!(loaded_fraction(url), 0.1);
+        assert_eq!(loaded_fraction("http://localhost:3000/points?p=0.01"), 0.01);
+        assert_eq!(
+            loaded_fraction("http://localhost:3000/points?collection=a"),
+            1.
+        );
+
+        let mut preview = ThinningPreview {
+            loaded: 0.1,
+            ..Default::default()
+        };
+        let start = Duration::from_secs(1);
+        preview.drag(0.5, start);
+        preview.drag(0.5, start + DEBOUNCE / 2);
+        assert_eq!(preview.settled(start + DEBOUNCE), None);
+        assert_eq!(preview.settled(start + DEBOUNCE * 2), Some(0.025));
+
+        // never denser than loaded
+        preview.drag(100., start);
+        assert_eq!(preview.pending.map(|(p, _)| p), Some(0.1));
+    }
+
+    #[test]
+    fn monotonic() {
+        let pc = ArrowPointCloud::from_iter(
+            (0..1000).map(|i| Point::<f64, 3>::from_slice(&[i as f64, 0., 0.])),
+        )
+        .unwrap();
+        let xs = |pc: &ArrowPointCloud| -> Vec<u64> {
+            pc.points::<Point<f64, 3>>()
+                .map(|p| p.coords()[0] as u64)
+                .collect()
+        };
+
+        let mut preview = ThinningPreview::default();
+        assert!(preview.sample(&pc).is_none());
+
+        preview.p = Some(0.5);
+        let dense = xs(&preview.sample(&pc).unwrap());
+        preview.p = Some(0.1);
+        let sparse = xs(&preview.sample(&pc).unwrap());
+        assert_eq!(preview.shown, Some(sparse.len()));
+        assert!((50..150).contains(&sparse.len()), "{}", sparse.len());
+        assert!(sparse.iter().all(|x| dense.contains(x)));
+        assert_eq!(preview.params().as_deref(), Some("sample=stable:0.1"));
+    }
+}
@@ -0,0 +1,252 @@
+//! Chrome trace of the spans of a session, see `--trace`.
+//!
+//! [ChromeLayer] keeps the closed spans as complete events of the Trace
+//! Event Format, which `chrome://tracing` and Perfetto show as a flame graph
+//! per thread, e.g. the decode of a load next to the generation of its
+//! instances.
+
+use std::{
+    fmt,
+    fs::File,
+    io::{self, BufWriter},
+    path::Path,
+    sync::{Arc, Mutex},
+    thread::{self, ThreadId},
+    time::{Duration, Instant},
+};
+
+use bevy::{
+    ecs::system::Resource,
+    utils::tracing::{
+        field::{Field, Visit},
+        span::{Attributes, Id, Record},
+        Level, Subscriber,
+    },
+};
+use serde_json::{json, Map, Value};
+use tracing_subscriber::{
+    filter::Targets, layer::Context, prelude::*, registry::LookupSpan, EnvFilter, Layer,
+};
+
+/// Log filter if `RUST_LOG` is not set, the default of bevy's `LogPlugin`
+const LOG_FILTER: &str = "info,wgpu=error,naga=warn";
+
+/// Traced targets, the stages of the format and the viewer
+const TRACED: [&str; 2] = ["crux_format", "crux_viewer"];
+
+#[derive(Default)]
+struct State {
+    events: Vec<Value>,
+    /// Threads by their index in the trace
+    threads: Vec<(ThreadId, String)>,
+}
+
+/// Closed spans, shared with the [ChromeLayer] recording them
+#[derive(Resource, Clone)]
+pub struct ChromeTrace {
+    epoch: Instant,
+    state: Arc<Mutex<State>>,
+}
+
+impl Default for ChromeTrace {
+    fn default() -> Self {
+        Self {
+            epoch: Instant::now(),
+            state: Arc::default(),
+        }
+    }
+}
+
+impl ChromeTrace {
+    pub fn layer(&self) -> ChromeLayer {
+        ChromeLayer(self.clone())
+    }
+
+    /// Log to stderr like bevy's `LogPlugin`, which has to be disabled, and
+    /// trace the spans of the format and the viewer
+    pub fn init(&self) {
+        let filter =
+            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(LOG_FILTER));
+        let traced = Targets::new().with_targets(TRACED.map(|target| (target, Level::TRACE)));
+
+        tracing_subscriber::registry()
+            .with(
+                tracing_subscriber::fmt::layer()
+                    .with_writer(io::stderr)
+                    .with_filter(filter),
+            )
+            .with(self.layer().with_filter(traced))
+            .init();
+    }
+
+    /// Index of the current thread
+    fn thread(&self) -> usize {
+        let current = thread::current();
+        let mut state = self.state.lock().unwrap();
+        match state.threads.iter().position(|(id, _)| *id == current.id()) {
+            Some(i) => i,
+            None => {
+                let name = current
+                    .name()
+                    .map_or_else(|| format!("{:?}", current.id()), str::to_owned);
+                state.threads.push((current.id(), name));
+                state.threads.len() - 1
+            }
+        }
+    }
+
+    /// Trace of the spans closed so far
+    pub fn to_json(&self) -> Value {
+        let state = self.state.lock().unwrap();
+        let threads = state.threads.iter().enumerate().map(|(tid, (_, name))| {
+            json!({
+                "name": "thread_name",
+                "ph": "M",
+                "pid": 1,
+                "tid": tid,
+                "args": { "name": name },
+            })
+        });
+
+        json!({
+            "traceEvents": threads.chain(state.events.iter().cloned()).collect::<Vec<_>>(),
+            "displayTimeUnit": "ms",
+        })
+    }
+
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        let writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer(writer, &self.to_json())?;
+        Ok(())
+    }
+}
+
+/// Start and fields of an open span
+struct Timing {
+    start: Instant,
+    tid: usize,
+    args: Map<String, Value>,
+}
+
+struct Args<'a>(&'a mut Map<String, Value>);
+
+impl Visit for Args<'_> {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.0.insert(field.name().to_owned(), value.into());
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().to_owned(), value.into());
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().to_owned(), value.into());
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.insert(field.name().to_owned(), value.into());
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().to_owned(), value.into());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0
+            .insert(field.name().to_owned(), format!("{value:?}").into());
+    }
+}
+
+fn micros(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1e6
+}
+
+/// Layer recording closed spans into a [ChromeTrace]
+pub struct ChromeLayer(ChromeTrace);
+
+impl<S> Layer<S> for ChromeLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let mut args = Map::new();
+        attrs.record(&mut Args(&mut args));
+        span.extensions_mut().insert(Timing {
+            start: Instant::now(),
+            tid: self.0.thread(),
+            args,
+        });
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            if let Some(timing) = span.extensions_mut().get_mut::<Timing>() {
+                values.record(&mut Args(&mut timing.args));
+            }
+        }
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(&id) else {
+            return;
+        };
+        let Some(timing) = span.extensions_mut().remove::<Timing>() else {
+            return;
+        };
+
+        let metadata = span.metadata();
+        let event = json!({
+            "name": metadata.name(),
+            "cat": metadata.target(),
+            "ph": "X",
+            "ts": micros(timing.start.saturating_duration_since(self.0.epoch)),
+            "dur": micros(timing.start.elapsed()),
+            "pid": 1,
+            "tid": timing.tid,
+            "args": timing.args,
+        });
+        self.0.state.lock().unwrap().events.push(event);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::utils::tracing::{self, info_span};
+
+    use super::*;
+
+    #[test]
+    fn spans() {
+        let trace = ChromeTrace::default();
+        let subscriber = tracing_subscriber::registry().with(trace.layer());
+        tracing::subscriber::with_default(subscriber, || {
+            let load = info_span!("load", collection = "default").entered();
+            let decode = info_span!("decode", rows = tracing::field::Empty);
+            decode.record("rows", 100u64);
+            decode.in_scope(|| std::thread::sleep(Duration::from_millis(2)));
+            drop(decode);
+            load.exit();
+        });
+
+        let json = trace.to_json();
+        let events = json["traceEvents"].as_array().unwrap();
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[0]["ph"], "M");
+        assert_eq!(events[0]["args"]["name"], "trace::tests::spans");
+
+        // spans in the order they closed
+        let (decode, load) = (&events[1], &events[2]);
+        assert_eq!(decode["name"], "decode");
+        assert_eq!(decode["args"]["rows"], 100);
+        assert_eq!(load["args"]["collection"], "default");
+        assert_eq!(load["cat"], "crux_viewer::trace::tests");
+
+        let times = |e: &Value| (e["ts"].as_f64().unwrap(), e["dur"].as_f64().unwrap());
+        let ((decode_ts, decode_dur), (load_ts, load_dur)) = (times(decode), times(load));
+        assert!(decode_dur >= 2000.);
+        assert!(load_ts <= decode_ts && decode_ts + decode_dur <= load_ts + load_dur);
+    }
+}
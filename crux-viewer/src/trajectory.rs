@@ -0,0 +1,202 @@
+use std::{io::Cursor, path::Path};
+
+use arrow::ipc::reader::StreamReader;
+use bevy::{
+    math::DVec3,
+    prelude::*,
+    tasks::{AsyncComputeTaskPool, Task},
+};
+use futures_lite::future::{self, block_on};
+
+use crux_format::{trajectory, ArrowPointCloud};
+
+use crate::{
+    bounds::decimate,
+    frame::data_to_world,
+    keys::{Action, KeyBindings},
+    SpatialReference, ViewerSettings,
+};
+
+/// Maximum number of polyline vertices drawn
+const MAX_VERTICES: usize = 10_000;
+/// Number of time steps over the whole trajectory, ten times coarser with shift
+const TIME_STEPS: f64 = 500.;
+
+const LINE_COLOR: Color = Color::YELLOW;
+const SENSOR_COLOR: Color = Color::RED;
+
+/// Sensor trajectory drawn as a polyline with the sensor position at `time`
+#[derive(Resource, Default)]
+pub struct Trajectory {
+    /// Source of the loaded or loading trajectory
+    source: Option<String>,
+    data: Option<trajectory::Trajectory>,
+    pub time: f64,
+    status: String,
+}
+
+impl Trajectory {
+    /// Overlay line, `None` without a trajectory source
+    pub fn status(&self, keys: &KeyBindings) -> Option<String> {
+        self.source.as_ref()?;
+
+        Some(
+            match (&self.data, self.data.as_ref().and_then(|t| t.time_range())) {
+                (Some(_), Some((start, end))) => format!(
+                    "Trajectory ({} {}): t = {:.2} of [{start:.2}, {end:.2}]",
+                    keys.label(Action::TrajectoryBack),
+                    keys.label(Action::TrajectoryForward),
+                    self.time
+                ),
+                (Some(_), None) => "Trajectory: no poses".to_string(),
+                (None, _) => format!("Trajectory: {}", self.status),
+            },
+        )
+    }
+}
+
+#[derive(Component)]
+pub struct TrajectoryTask(Task<Result<trajectory::Trajectory, String>>);
+
+/// Trajectory from a CSV file, or from the collection `source` on the server
+/// with a `gps_time` attribute
+pub fn load(source: &str, server: &str) -> Result<trajectory::Trajectory, String> {
+    let path = Path::new(source);
+    if path.is_file() || path.extension().is_some_and(|ext| ext == "csv") {
+        let file = std::fs::File::open(path).map_err(|e| format!("{source}: {e}"))?;
+        return trajectory::Trajectory::read_csv(std::io::BufReader::new(file))
+            .map_err(|e| e.to_string());
+    }
+
+    let url = format!(
+        "{}/points?collection={source}",
+        server.trim_end_matches('/')
+    );
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_io()
+        .build()
+        .map_err(|e| e.to_string())?;
+    let body = rt
+        .block_on(async { reqwest::get(url).await?.error_for_status()?.bytes().await })
+        .map_err(|e| e.to_string())?;
+    let reader = StreamReader::try_new(Cursor::new(body), None).map_err(|e| e.to_string())?;
+
+    trajectory::Trajectory::from_point_cloud(&ArrowPointCloud::from(reader))
+        .map_err(|e| e.to_string())
+}
+
+// Load the trajectory whenever its source changes
+pub fn spawn_trajectory_task(
+    mut commands: Commands,
+    settings: Res<ViewerSettings>,
+    mut trajectory: ResMut<Trajectory>,
+) {
+    if !settings.is_changed() || trajectory.source == settings.trajectory {
+        return;
+    }
+
+    trajectory.source = settings.trajectory.clone();
+    trajectory.data = None;
+    let Some(source) = trajectory.source.clone() else {
+        return;
+    };
+    trajectory.status = "loading".to_string();
+
+    let server = settings.server.clone();
+    let task = AsyncComputeTaskPool::get().spawn(async move { load(&source, &server) });
+    commands.spawn(TrajectoryTask(task));
+}
+
+// Draw the trajectory, ',' and '.' move the sensor position back and forth in time
+pub fn trajectory_system(
+    mut commands: Commands,
+    key_input: Res<Input<KeyCode>>,
+    keys: Res<KeyBindings>,
+    sr: Res<SpatialReference>,
+    mut tasks: Query<(Entity, &mut TrajectoryTask)>,
+    mut trajectory: ResMut<Trajectory>,
+    mut gizmos: Gizmos,
+) {
+    for (entity, mut task) in &mut tasks {
+        if let Some(result) = block_on(future::poll_once(&mut task.0)) {
+            commands.entity(entity).despawn();
+
+            match result {
+                Ok(data) => {
+                    info!("Loaded trajectory with {} poses", data.poses.len());
+                    trajectory.time = data.time_range().map_or(0., |(start, _)| start);
+                    trajectory.data = Some(data);
+                }
+                Err(e) => {
+                    warn!("Failed to load trajectory: {e}");
+                    trajectory.status = format!("failed ({e})");
+                }
+            }
+        }
+    }
+
+    let (Some(origin), Some((start, end))) = (
+        sr.origin,
+        trajectory.data.as_ref().and_then(|t| t.time_range()),
+    ) else {
+        return;
+    };
+
+    let shift = key_input.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]);
+    let step = (end - start) / TIME_STEPS * if shift { 10. } else { 1. };
+    if keys.pressed(&key_input, Action::TrajectoryBack) {
+        trajectory.time = (trajectory.time - step).max(start);
+    }
+    if keys.pressed(&key_input, Action::TrajectoryForward) {
+        trajectory.time = (trajectory.time + step).min(end);
+    }
+
+    let Some(data) = &trajectory.data else {
+        return;
+    };
+    let world = |p: [f64; 3]| data_to_world(origin, DVec3::from_array(p));
+
+    let mut vertices: Vec<Vec3> = decimate(data.poses.iter().collect(), MAX_VERTICES)
+        .into_iter()
+        .map(|pose| world(pose.position))
+        .collect();
+    // keep the end of the trajectory
+    if let Some(last) = data.poses.last() {
+        vertices.push(world(last.position));
+    }
+    gizmos.linestrip(vertices, LINE_COLOR);
+
+    if let Some(p) = data.position_at(trajectory.time) {
+        gizmos.sphere(world(p), Quat::IDENTITY, 0.5, SENSOR_COLOR);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn load_csv() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("trajectory.csv");
+        std::fs::write(&path, "gps_time,x,y,z\n1,0,0,0\n2,4,0,0\n").unwrap();
+
+        let data = load(path.to_str().unwrap(), "http://0.0.0.0:0").unwrap();
+        assert_eq!(data.position_at(1.25), Some([1., 0., 0.]));
+
+        // missing files are not queried from the server
+        let missing = dir.path().join("missing.csv");
+        assert!(load(missing.to_str().unwrap(), "http://0.0.0.0:0").is_err());
+
+        let mut trajectory = Trajectory::default();
+        let keys = KeyBindings::default();
+        assert_eq!(trajectory.status(&keys), None);
+        trajectory.source = Some("trajectory.csv".to_string());
+        trajectory.data = Some(data);
+        trajectory.time = 1.5;
+        assert_eq!(
+            trajectory.status(&keys).unwrap(),
+            "Trajectory (, .): t = 1.50 of [1.00, 2.00]"
+        );
+    }
+}
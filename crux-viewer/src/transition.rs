@@ -0,0 +1,264 @@
+use std::time::Duration;
+
+use bevy::{prelude::*, render::primitives::Aabb};
+use bevy_aabb_instancing::{Cuboid, CuboidMaterialId, Cuboids};
+
+use crate::render::InstanceUpload;
+
+/// Time the instances of a new load take to grow to their full size
+pub const FADE: Duration = Duration::from_millis(500);
+
+/// Phase of the transition to the instances of a new load
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+enum Phase {
+    #[default]
+    Idle,
+    /// The new instances grow from zero size over the old ones since `start`
+    Fading { start: Duration },
+    /// The new instances replace the old ones chunk by chunk, shown at full
+    /// size until all are uploaded
+    Settling,
+}
+
+/// Load of a collection the shown instances are generated from
+#[derive(Debug, Clone, PartialEq)]
+pub struct Source {
+    pub collection: String,
+    /// Number of loads of the collection, see `PointCache::generation`
+    pub generation: usize,
+}
+
+/// Animated transition between the instances of loads of different density,
+/// the old instances are kept while the new ones grow from zero size
+#[derive(Resource, Default)]
+pub struct Transition {
+    phase: Phase,
+    /// Instances faded in
+    target: Vec<Vec<Cuboid>>,
+    /// Source and number of the shown instances
+    shown: Option<(Source, usize)>,
+}
+
+impl Transition {
+    /// Show `instances` of `source`, faded in over the shown instances of an
+    /// earlier load of the same collection if both fit into `available` bytes.
+    ///
+    /// A running transition is completed at once, so that consecutive loads
+    /// never wait for each other.
+    pub fn show(
+        &mut self,
+        instances: Vec<Vec<Cuboid>>,
+        source: Source,
+        upload: &mut InstanceUpload,
+        available: usize,
+        now: Duration,
+    ) {
+        let count = instances.iter().map(Vec::len).sum();
+        let fade = match &self.shown {
+            Some((shown, shown_count)) => {
+                self.phase == Phase::Idle
+                    && shown.collection == source.collection
+                    && shown.generation != source.generation
+                    && *shown_count > 0
+                    && (shown_count + count) * std::mem::size_of::<Cuboid>() <= available
+            }
+            None => false,
+        };
+        self.shown = Some((source, count));
+
+        if fade {
+            self.phase = Phase::Fading { start: now };
+            self.target = instances;
+        } else {
+            self.phase = Phase::Idle;
+            self.target = Vec::new();
+            upload.set(instances);
+        }
+    }
+
+    /// Stop a running transition, e.g. when collections are compared
+    pub fn cancel(&mut self) {
+        self.phase = Phase::Idle;
+        self.target = Vec::new();
+        self.shown = None;
+    }
+
+    /// Advance to `now`, returns the scale of the new instances or `None` if
+    /// there are none besides the uploaded ones
+    fn tick(&mut self, now: Duration, upload: &mut InstanceUpload) -> Option<f32> {
+        match self.phase {
+            Phase::Idle => None,
+            Phase::Fading { start } => {
+                let t = progress(start, now);
+                if t >= 1. {
+                    upload.set(self.target.clone());
+                    self.phase = Phase::Settling;
+                }
+                Some(t)
+            }
+            Phase::Settling if upload.pending.is_empty() => {
+                self.phase = Phase::Idle;
+                self.target = Vec::new();
+                None
+            }
+            Phase::Settling => Some(1.),
+        }
+    }
+}
+
+/// Eased share of the transition started at `start` that passed at `now`
+fn progress(start: Duration, now: Duration) -> f32 {
+    let t = (now.saturating_sub(start).as_secs_f32() / FADE.as_secs_f32()).min(1.);
+    t * t * (3. - 2. * t)
+}
+
+/// Cuboid of the same center, `scale` times the size
+fn scaled(cuboid: &Cuboid, scale: f32) -> Cuboid {
+    let center = (cuboid.minimum + cuboid.maximum) / 2.;
+    let half = (cuboid.maximum - cuboid.minimum) / 2. * scale;
+    Cuboid {
+        minimum: center - half,
+        maximum: center + half,
+        ..*cuboid
+    }
+}
+
+/// Chunk of the instances faded in, rendered by its own cuboids entity
+#[derive(Component)]
+pub struct FadeChunk(usize);
+
+// Grow the instances of a new load, the uploaded chunks are kept meanwhile
+pub fn transition_system(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut transition: ResMut<Transition>,
+    mut upload: ResMut<InstanceUpload>,
+    mut chunks: Query<(&FadeChunk, &mut Cuboids, &mut Aabb)>,
+    mut applied: Local<Option<f32>>,
+) {
+    let scale = transition.tick(time.elapsed(), &mut upload);
+    if scale == *applied {
+        return;
+    }
+    *applied = scale;
+
+    let Some(scale) = scale else {
+        for (_, mut c, _) in chunks.iter_mut() {
+            if !c.instances.is_empty() {
+                c.instances.clear();
+            }
+        }
+        return;
+    };
+
+    for (index, instances) in transition.target.iter().enumerate() {
+        let cuboids = Cuboids::new(instances.iter().map(|c| scaled(c, scale)).collect());
+        let aabb = cuboids.aabb();
+
+        match chunks.iter_mut().find(|(chunk, ..)| chunk.0 == index) {
+            Some((_, mut c, mut a)) => {
+                *c = cuboids;
+                *a = aabb;
+            }
+            None => {
+                commands.spawn((
+                    SpatialBundle::default(),
+                    cuboids,
+                    aabb,
+                    CuboidMaterialId(0),
+                    FadeChunk(index),
+                ));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn instances(n: usize) -> Vec<Vec<Cuboid>> {
+        vec![(0..n)
+            .map(|i| Cuboid::new(Vec3::splat(i as f32), Vec3::splat(i as f32 + 2.), 0))
+            .collect()]
+    }
+
+    fn source(collection: &str, generation: usize) -> Source {
+        Source {
+            collection: collection.to_owned(),
+            generation,
+        }
+    }
+
+    #[test]
+    fn timing() {
+        let start = Duration::from_secs(10);
+        assert_eq!(progress(start, start), 0.);
+        assert_eq!(progress(start, start + FADE / 2), 0.5);
+        assert_eq!(progress(start, start + FADE), 1.);
+        assert_eq!(progress(start, start + FADE * 3), 1.);
+        // clocks never run backwards, but must not panic
+        assert_eq!(progress(start, Duration::ZERO), 0.);
+
+        let cuboid = scaled(&instances(1)[0][0], 0.5);
+        assert_eq!(cuboid.minimum, Vec3::splat(0.5));
+        assert_eq!(cuboid.maximum, Vec3::splat(1.5));
+    }
+
+    #[test]
+    fn phases() {
+        let (mut transition, mut upload) = (Transition::default(), InstanceUpload::default());
+        let now = Duration::from_secs(1);
+
+        // the first instances are uploaded at once
+        transition.show(instances(10), source("a", 1), &mut upload, usize::MAX, now);
+        assert_eq!(transition.phase, Phase::Idle);
+        assert_eq!(upload.pending.len(), 1);
+        upload.pending.clear();
+
+        // a denser load fades in, the old instances stay uploaded meanwhile
+        transition.show(instances(100), source("a", 2), &mut upload, usize::MAX, now);
+        assert_eq!(transition.phase, Phase::Fading { start: now });
+        assert!(upload.pending.is_empty());
+        assert_eq!(transition.tick(now, &mut upload), Some(0.));
+        assert_eq!(transition.tick(now + FADE / 2, &mut upload), Some(0.5));
+        assert!(upload.pending.is_empty());
+
+        // then it is uploaded in place of the old instances
+        assert_eq!(transition.tick(now + FADE, &mut upload), Some(1.));
+        assert_eq!(transition.phase, Phase::Settling);
+        assert_eq!(upload.pending[0].1.len(), 100);
+        assert_eq!(transition.tick(now + FADE * 2, &mut upload), Some(1.));
+        upload.pending.clear();
+        assert_eq!(transition.tick(now + FADE * 2, &mut upload), None);
+        assert_eq!(transition.phase, Phase::Idle);
+        assert!(transition.target.is_empty());
+    }
+
+    #[test]
+    fn skipped() {
+        let (mut transition, mut upload) = (Transition::default(), InstanceUpload::default());
+        let now = Duration::from_secs(1);
+        let size = std::mem::size_of::<Cuboid>();
+        transition.show(instances(10), source("a", 1), &mut upload, usize::MAX, now);
+
+        // old and new instances exceed the available memory
+        transition.show(instances(100), source("a", 2), &mut upload, 109 * size, now);
+        assert_eq!(transition.phase, Phase::Idle);
+        assert_eq!(upload.pending.back().unwrap().1.len(), 100);
+
+        // other collections and changed colors of the same load
+        transition.show(instances(10), source("b", 1), &mut upload, usize::MAX, now);
+        assert_eq!(transition.phase, Phase::Idle);
+        transition.show(instances(10), source("b", 1), &mut upload, usize::MAX, now);
+        assert_eq!(transition.phase, Phase::Idle);
+
+        // a load during a transition completes it at once
+        transition.show(instances(20), source("b", 2), &mut upload, usize::MAX, now);
+        assert!(matches!(transition.phase, Phase::Fading { .. }));
+        transition.show(instances(30), source("b", 3), &mut upload, usize::MAX, now);
+        assert_eq!(transition.phase, Phase::Idle);
+        assert_eq!(upload.pending.back().unwrap().1.len(), 30);
+        assert_eq!(transition.tick(now + FADE, &mut upload), None);
+    }
+}
@@ -0,0 +1,174 @@
+//! Lengths in the linear unit of a collection, shown and entered in the unit
+//! of the settings
+
+use crux_format::LengthUnit;
+
+use crate::{PointCache, ViewerSettings};
+
+/// Significant digits of formatted lengths
+const SIGNIFICANT: i32 = 4;
+/// Most decimals of formatted lengths
+const MAX_DECIMALS: i32 = 6;
+
+/// Length `value` in `from` as length in `to`
+pub fn convert(value: f64, from: LengthUnit, to: LengthUnit) -> f64 {
+    match from == to {
+        true => value,
+        false => value * from.metres() / to.metres(),
+    }
+}
+
+/// Decimals of `value` with four significant digits, none from 1000 on
+pub fn decimals(value: f64) -> usize {
+    if value == 0. || !value.is_finite() {
+        return 0;
+    }
+    let magnitude = value.abs().log10().floor() as i32;
+    (SIGNIFICANT - 1 - magnitude).clamp(0, MAX_DECIMALS) as usize
+}
+
+/// Conversion of the lengths of the data to the display unit
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Units {
+    /// Unit of the data, unknown if not set
+    pub data: Option<LengthUnit>,
+    /// Unit shown and entered, the unit of the data if not set
+    pub display: Option<LengthUnit>,
+}
+
+impl Units {
+    pub fn new(data: Option<LengthUnit>, display: Option<LengthUnit>) -> Self {
+        Self { data, display }
+    }
+
+    /// Units of the shown collection, once loaded
+    pub fn of(settings: &ViewerSettings, cache: &PointCache) -> Self {
+        let data = cache
+            .get(&settings.collection)
+            .and_then(|pc| pc.metadata().units);
+        Self::new(data, settings.units)
+    }
+
+    /// Unit lengths are shown in, none without a unit of the data
+    pub fn unit(&self) -> Option<LengthUnit> {
+        self.data.and(self.display.or(self.data))
+    }
+
+    /// Length of the data in the display unit, unchanged without units
+    pub fn to_display(&self, value: f64) -> f64 {
+        match (self.data, self.unit()) {
+            (Some(data), Some(display)) => convert(value, data, display),
+            _ => value,
+        }
+    }
+
+    /// Length in the display unit in the unit of the data
+    pub fn to_data(&self, value: f64) -> f64 {
+        match (self.data, self.unit()) {
+            (Some(data), Some(display)) => convert(value, display, data),
+            _ => value,
+        }
+    }
+
+    /// Length of the data in the display unit, labelled with the unit if known
+    pub fn format(&self, value: f64) -> String {
+        self.format_power(value, 1)
+    }
+
+    /// Area of the data, e.g. `12.5 m²`
+    pub fn format_area(&self, value: f64) -> String {
+        self.format_power(value, 2)
+    }
+
+    /// Volume of the data, e.g. `3.2 m³`
+    pub fn format_volume(&self, value: f64) -> String {
+        self.format_power(value, 3)
+    }
+
+    fn format_power(&self, value: f64, power: i32) -> String {
+        let value = value * self.to_display(1.).powi(power);
+        let precision = decimals(value);
+        match (self.unit(), power) {
+            (Some(unit), 1) => format!("{value:.precision$} {unit}"),
+            (Some(unit), 2) => format!("{value:.precision$} {unit}²"),
+            (Some(unit), _) => format!("{value:.precision$} {unit}³"),
+            (None, _) => format!("{value:.precision$}"),
+        }
+    }
+}
+
+/// Warning about loaded `collections` in different units, whose lengths are
+/// not comparable
+pub fn mixed(cache: &PointCache, collections: &[&str]) -> Option<String> {
+    let units: Vec<(&str, LengthUnit)> = collections
+        .iter()
+        .filter_map(|collection| {
+            let units = cache.get(collection)?.metadata().units?;
+            Some((*collection, units))
+        })
+        .collect();
+    let (_, first) = units.first()?;
+    if units.iter().all(|(_, units)| units == first) {
+        return None;
+    }
+
+    let units: Vec<String> = units
+        .iter()
+        .map(|(collection, units)| format!("`{collection}` in {units}"))
+        .collect();
+    Some(format!("Units differ: {}", units.join(", ")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn conversion() {
+        assert_eq!(convert(2., LengthUnit::Metre, LengthUnit::Metre), 2.);
+        assert!((convert(1., LengthUnit::Foot, LengthUnit::Metre) - 0.3048).abs() < 1e-12);
+        assert!((convert(1., LengthUnit::Metre, LengthUnit::Foot) - 3.280_839_9).abs() < 1e-6);
+        // the survey foot is 2 ppm longer than the international foot
+        let survey = convert(1e6, LengthUnit::UsSurveyFoot, LengthUnit::Foot);
+        assert!((survey - 1_000_002.).abs() < 0.01);
+
+        let units = Units::new(Some(LengthUnit::Foot), Some(LengthUnit::Metre));
+        assert!((units.to_display(10.) - 3.048).abs() < 1e-12);
+        assert!((units.to_data(units.to_display(10.)) - 10.).abs() < 1e-12);
+    }
+
+    #[test]
+    fn precision() {
+        assert_eq!(decimals(0.), 0);
+        assert_eq!(decimals(f64::NAN), 0);
+        assert_eq!(decimals(1234.5), 0);
+        assert_eq!(decimals(123.45), 1);
+        assert_eq!(decimals(-1.5), 3);
+        assert_eq!(decimals(0.0123), 5);
+        assert_eq!(decimals(1e-9), 6);
+    }
+
+    #[test]
+    fn formatting() {
+        let metres = Units::new(Some(LengthUnit::Metre), None);
+        assert_eq!(metres.format(1.5), "1.500 m");
+        assert_eq!(metres.format(2345.678), "2346 m");
+        assert_eq!(metres.format_area(252.), "252.0 m²");
+        assert_eq!(metres.format_volume(60.), "60.00 m³");
+
+        let feet = Units::new(Some(LengthUnit::Metre), Some(LengthUnit::Foot));
+        assert_eq!(feet.unit(), Some(LengthUnit::Foot));
+        assert_eq!(feet.format(1.), "3.281 ft");
+        assert_eq!(feet.format_area(1.), "10.76 ft²");
+        assert_eq!(
+            Units::new(Some(LengthUnit::UsSurveyFoot), None).format(2.),
+            "2.000 us-ft"
+        );
+
+        // lengths of data without units are shown unconverted and unlabelled
+        let unknown = Units::new(None, Some(LengthUnit::Foot));
+        assert_eq!(unknown.unit(), None);
+        assert_eq!(unknown.to_data(2.), 2.);
+        assert_eq!(unknown.format(2.), "2.000");
+    }
+}
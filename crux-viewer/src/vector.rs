@@ -0,0 +1,287 @@
+//! Vector context like building footprints or parcel boundaries, read from
+//! GeoJSON in the reference system of the points and drawn as lines over them.
+
+use std::path::Path;
+
+use bevy::math::{DVec2, Vec2};
+use geo_types::{Geometry, LineString};
+use geojson::{GeoJson, JsonObject, JsonValue};
+
+/// Feature of a vector layer with its properties
+#[derive(Debug, Clone, PartialEq)]
+pub struct VectorFeature {
+    /// Polylines in the data reference system, the rings of polygons are
+    /// closed
+    pub lines: Vec<Vec<DVec2>>,
+    pub properties: JsonObject,
+}
+
+/// Line and polygon features of a GeoJSON file, other geometries are skipped
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct VectorLayer {
+    pub features: Vec<VectorFeature>,
+    /// Reference system named by the `crs` member of older GeoJSON, e.g.
+    /// `EPSG:28992`
+    pub crs: Option<String>,
+}
+
+impl VectorLayer {
+    pub fn read(path: &Path) -> Result<Self, String> {
+        let s = std::fs::read_to_string(path).map_err(|e| format!("{}: {e}", path.display()))?;
+        Self::parse(&s)
+    }
+
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let geojson: GeoJson = s.parse().map_err(|e| format!("{e}"))?;
+
+        let (features, foreign_members) = match geojson {
+            GeoJson::FeatureCollection(collection) => {
+                (collection.features, collection.foreign_members)
+            }
+            GeoJson::Feature(feature) => {
+                let foreign_members = feature.foreign_members.clone();
+                (vec![feature], foreign_members)
+            }
+            GeoJson::Geometry(geometry) => {
+                let foreign_members = geometry.foreign_members.clone();
+                (vec![geometry.into()], foreign_members)
+            }
+        };
+
+        let mut layer = Self {
+            features: Vec::new(),
+            crs: foreign_members.as_ref().and_then(crs_name),
+        };
+        for feature in features {
+            let Some(geometry) = feature.geometry else {
+                continue;
+            };
+            let geometry = Geometry::<f64>::try_from(geometry.value).map_err(|e| format!("{e}"))?;
+            let mut lines = Vec::new();
+            push_lines(&geometry, &mut lines);
+            if !lines.is_empty() {
+                layer.features.push(VectorFeature {
+                    lines,
+                    properties: feature.properties.unwrap_or_default(),
+                });
+            }
+        }
+
+        Ok(layer)
+    }
+
+    pub fn num_vertices(&self) -> usize {
+        self.features
+            .iter()
+            .flat_map(|feature| &feature.lines)
+            .map(Vec::len)
+            .sum()
+    }
+
+    /// Fails with a message for the overlay if both the features and the
+    /// points name their reference system and they differ, reprojection is
+    /// not supported
+    pub fn check_crs(&self, data: Option<&str>) -> Result<(), String> {
+        match (self.crs.as_deref(), data) {
+            (Some(features), Some(data)) if normalize_crs(features) != normalize_crs(data) => {
+                Err(format!(
+                    "features in {} but points in {}, reprojection is not supported",
+                    normalize_crs(features),
+                    normalize_crs(data)
+                ))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Feature with a line closest to `cursor` within `max_distance`, all in
+    /// screen coordinates given by `project`
+    pub fn nearest(
+        &self,
+        cursor: Vec2,
+        max_distance: f32,
+        project: impl Fn(DVec2) -> Option<Vec2>,
+    ) -> Option<usize> {
+        let mut nearest = None;
+        let mut min = max_distance;
+        for (i, feature) in self.features.iter().enumerate() {
+            for line in &feature.lines {
+                let screen: Vec<Option<Vec2>> = line.iter().map(|p| project(*p)).collect();
+                for segment in screen.windows(2) {
+                    let [Some(a), Some(b)] = [segment[0], segment[1]] else {
+                        continue;
+                    };
+                    let distance = segment_distance(cursor, a, b);
+                    if distance <= min {
+                        min = distance;
+                        nearest = Some(i);
+                    }
+                }
+            }
+        }
+        nearest
+    }
+}
+
+impl VectorFeature {
+    /// Properties as `key: value` lines
+    pub fn describe(&self) -> String {
+        if self.properties.is_empty() {
+            return "no properties".to_string();
+        }
+        self.properties
+            .iter()
+            .map(|(key, value)| match value {
+                JsonValue::String(s) => format!("{key}: {s}"),
+                value => format!("{key}: {value}"),
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+/// `EPSG:28992` for the common spellings like `urn:ogc:def:crs:EPSG::28992`
+pub fn normalize_crs(name: &str) -> String {
+    let name = name.trim().to_ascii_uppercase();
+    let parts: Vec<&str> = name.split(':').filter(|part| !part.is_empty()).collect();
+    match parts[..] {
+        ["URN", "OGC", "DEF", "CRS", authority, .., code] => format!("{authority}:{code}"),
+        _ => name,
+    }
+}
+
+/// Name of the `crs` member, `{"type": "name", "properties": {"name": ...}}`
+fn crs_name(members: &JsonObject) -> Option<String> {
+    members
+        .get("crs")?
+        .get("properties")?
+        .get("name")?
+        .as_str()
+        .map(str::to_owned)
+}
+
+fn push_lines(geometry: &Geometry<f64>, lines: &mut Vec<Vec<DVec2>>) {
+    let line = |line: &LineString<f64>| line.coords().map(|c| DVec2::new(c.x, c.y)).collect();
+    match geometry {
+        Geometry::Line(l) => lines.push(vec![
+            DVec2::new(l.start.x, l.start.y),
+            DVec2::new(l.end.x, l.end.y),
+        ]),
+        Geometry::LineString(l) => lines.push(line(l)),
+        Geometry::MultiLineString(m) => lines.extend(m.iter().map(line)),
+        Geometry::Polygon(p) => {
+            lines.push(line(p.exterior()));
+            lines.extend(p.interiors().iter().map(line));
+        }
+        Geometry::MultiPolygon(m) => {
+            for p in m {
+                push_lines(&Geometry::Polygon(p.clone()), lines);
+            }
+        }
+        Geometry::Rect(r) => push_lines(&Geometry::Polygon(r.to_polygon()), lines),
+        Geometry::Triangle(t) => push_lines(&Geometry::Polygon(t.to_polygon()), lines),
+        Geometry::GeometryCollection(c) => {
+            for g in c {
+                push_lines(g, lines);
+            }
+        }
+        Geometry::Point(_) | Geometry::MultiPoint(_) => (),
+    }
+}
+
+/// Distance of `p` to the segment from `a` to `b`
+fn segment_distance(p: Vec2, a: Vec2, b: Vec2) -> f32 {
+    let ab = b - a;
+    let t = if ab.length_squared() > 0. {
+        ((p - a).dot(ab) / ab.length_squared()).clamp(0., 1.)
+    } else {
+        0.
+    };
+    p.distance(a + ab * t)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FOOTPRINTS: &str = r#"{
+        "type": "FeatureCollection",
+        "crs": { "type": "name", "properties": { "name": "urn:ogc:def:crs:EPSG::28992" } },
+        "features": [
+            {
+                "type": "Feature",
+                "properties": { "name": "town hall", "floors": 3 },
+                "geometry": {
+                    "type": "Polygon",
+                    "coordinates": [[[0, 0], [10, 0], [10, 10], [0, 10], [0, 0]]]
+                }
+            },
+            {
+                "type": "Feature",
+                "properties": null,
+                "geometry": { "type": "LineString", "coordinates": [[20, 0], [30, 0]] }
+            },
+            {
+                "type": "Feature",
+                "properties": { "name": "tree" },
+                "geometry": { "type": "Point", "coordinates": [5, 5] }
+            }
+        ]
+    }"#;
+
+    #[test]
+    fn parse() {
+        let layer = VectorLayer::parse(FOOTPRINTS).unwrap();
+        assert_eq!(layer.crs.as_deref(), Some("urn:ogc:def:crs:EPSG::28992"));
+        // points are skipped
+        assert_eq!(layer.features.len(), 2);
+        assert_eq!(layer.num_vertices(), 7);
+        assert_eq!(layer.features[0].lines[0][2], DVec2::new(10., 10.));
+        assert_eq!(layer.features[0].describe(), "floors: 3\nname: town hall");
+        assert_eq!(layer.features[1].describe(), "no properties");
+
+        // a bare geometry without crs
+        let layer = VectorLayer::parse(
+            r#"{"type": "MultiLineString", "coordinates": [[[0, 0], [1, 1]], [[2, 2], [3, 3]]]}"#,
+        )
+        .unwrap();
+        assert_eq!(layer.crs, None);
+        assert_eq!(layer.features[0].lines.len(), 2);
+
+        assert!(VectorLayer::parse("{").is_err());
+    }
+
+    #[test]
+    fn crs() {
+        assert_eq!(normalize_crs("urn:ogc:def:crs:EPSG::28992"), "EPSG:28992");
+        assert_eq!(normalize_crs("epsg:28992"), "EPSG:28992");
+        assert_eq!(normalize_crs("urn:ogc:def:crs:OGC:1.3:CRS84"), "OGC:CRS84");
+
+        let layer = VectorLayer::parse(FOOTPRINTS).unwrap();
+        assert!(layer.check_crs(Some("EPSG:28992")).is_ok());
+        assert!(layer.check_crs(None).is_ok());
+        assert_eq!(
+            layer.check_crs(Some("EPSG:4326")).unwrap_err(),
+            "features in EPSG:28992 but points in EPSG:4326, reprojection is not supported"
+        );
+
+        // features without crs are in the reference system of the points
+        let layer = VectorLayer::default();
+        assert!(layer.check_crs(Some("EPSG:4326")).is_ok());
+    }
+
+    #[test]
+    fn nearest() {
+        let layer = VectorLayer::parse(FOOTPRINTS).unwrap();
+        // screen pixels are 10 times the data units
+        let project = |p: DVec2| Some((p * 10.).as_vec2());
+
+        // near the right edge of the footprint and the end of the line
+        assert_eq!(layer.nearest(Vec2::new(104., 50.), 5., project), Some(0));
+        assert_eq!(layer.nearest(Vec2::new(303., 2.), 5., project), Some(1));
+        // inside the footprint, far from its edges
+        assert_eq!(layer.nearest(Vec2::new(50., 50.), 5., project), None);
+        // vertices behind the camera
+        assert_eq!(layer.nearest(Vec2::new(104., 50.), 5., |_| None), None);
+    }
+}
@@ -0,0 +1,191 @@
+use std::path::PathBuf;
+
+use bevy::{
+    math::{DVec2, DVec3},
+    prelude::*,
+    tasks::{AsyncComputeTaskPool, Task},
+    window::{FileDragAndDrop, PrimaryWindow},
+};
+use bevy_panorbit_camera::PanOrbitCamera;
+use futures_lite::future::{self, block_on};
+
+use crate::{
+    frame::data_to_world, vector::VectorLayer, PointCache, SpatialReference, ViewerSettings,
+};
+
+const LINE_COLOR: Color = Color::CYAN;
+const SELECTED_COLOR: Color = Color::ORANGE_RED;
+/// Screen distance in pixels of clicks selecting a line
+const PICK_DISTANCE: f32 = 8.;
+
+/// Vector features drawn over the points, see [ViewerSettings::vector]
+#[derive(Resource, Default)]
+pub struct VectorOverlay {
+    /// Source of the loaded or loading layer
+    source: Option<PathBuf>,
+    layer: Option<VectorLayer>,
+    selected: Option<usize>,
+    status: String,
+}
+
+impl VectorOverlay {
+    /// Overlay lines, `None` without a vector source
+    pub fn status(&self, settings: &ViewerSettings, cache: &PointCache) -> Option<String> {
+        let source = self.source.as_ref()?;
+        let name = source.file_name().map_or_else(
+            || source.display().to_string(),
+            |name| name.to_string_lossy().to_string(),
+        );
+
+        let Some(layer) = &self.layer else {
+            return Some(format!("Vector `{name}`: {}", self.status));
+        };
+        if let Err(e) = check_crs(layer, settings, cache) {
+            return Some(format!("Vector `{name}`: {e}"));
+        }
+
+        let mut status = format!(
+            "Vector `{name}` (ctrl + click): {} features",
+            layer.features.len()
+        );
+        if let Some(feature) = self.selected.and_then(|i| layer.features.get(i)) {
+            status.push('\n');
+            status.push_str(&feature.describe());
+        }
+        Some(status)
+    }
+}
+
+#[derive(Component)]
+pub struct VectorTask(Task<Result<VectorLayer, String>>);
+
+/// Features are drawn unless the points name another reference system
+fn check_crs(
+    layer: &VectorLayer,
+    settings: &ViewerSettings,
+    cache: &PointCache,
+) -> Result<(), String> {
+    let crs = cache
+        .data
+        .get(&settings.collection)
+        .and_then(|pc| pc.metadata().crs);
+    layer.check_crs(crs.as_deref())
+}
+
+// Show GeoJSON files dropped onto the window
+pub fn vector_drop_system(
+    mut events: EventReader<FileDragAndDrop>,
+    mut settings: ResMut<ViewerSettings>,
+) {
+    for event in events.read() {
+        if let FileDragAndDrop::DroppedFile { path_buf, .. } = event {
+            let geojson = path_buf
+                .extension()
+                .is_some_and(|ext| ext.eq_ignore_ascii_case("geojson") || ext == "json");
+            if geojson {
+                settings.vector = Some(path_buf.clone());
+            }
+        }
+    }
+}
+
+// Load the features whenever their source changes
+pub fn spawn_vector_task(
+    mut commands: Commands,
+    settings: Res<ViewerSettings>,
+    mut overlay: ResMut<VectorOverlay>,
+) {
+    if !settings.is_changed() || overlay.source == settings.vector {
+        return;
+    }
+
+    overlay.source = settings.vector.clone();
+    overlay.layer = None;
+    overlay.selected = None;
+    let Some(source) = overlay.source.clone() else {
+        return;
+    };
+    overlay.status = "loading".to_string();
+
+    let task = AsyncComputeTaskPool::get().spawn(async move { VectorLayer::read(&source) });
+    commands.spawn(VectorTask(task));
+}
+
+// Draw the features draped on the ground, ctrl + click selects the feature
+// of the line near the cursor
+#[allow(clippy::too_many_arguments)]
+pub fn vector_system(
+    mut commands: Commands,
+    key_input: Res<Input<KeyCode>>,
+    mouse_input: Res<Input<MouseButton>>,
+    settings: Res<ViewerSettings>,
+    cache: Res<PointCache>,
+    sr: Res<SpatialReference>,
+    window: Query<&Window, With<PrimaryWindow>>,
+    camera: Query<(&Camera, &GlobalTransform), With<PanOrbitCamera>>,
+    mut tasks: Query<(Entity, &mut VectorTask)>,
+    mut overlay: ResMut<VectorOverlay>,
+    mut gizmos: Gizmos,
+) {
+    for (entity, mut task) in &mut tasks {
+        if let Some(result) = block_on(future::poll_once(&mut task.0)) {
+            commands.entity(entity).despawn();
+
+            match result {
+                Ok(layer) => {
+                    info!(
+                        "Loaded {} vector features with {} vertices",
+                        layer.features.len(),
+                        layer.num_vertices()
+                    );
+                    overlay.layer = Some(layer);
+                }
+                Err(e) => {
+                    warn!("Failed to load vector features: {e}");
+                    overlay.status = format!("failed ({e})");
+                }
+            }
+        }
+    }
+
+    let (Some(origin), Some(layer)) = (sr.origin, overlay.layer.as_ref()) else {
+        return;
+    };
+    if check_crs(layer, &settings, &cache).is_err() {
+        return;
+    }
+
+    // on the ground once the density grid is built, at the origin before
+    let grid = cache.density.get(&settings.collection);
+    let z = |p: DVec2| match (settings.vector_z, grid) {
+        (Some(z), _) => z,
+        (None, Some(grid)) => grid.ground(p.x, p.y),
+        (None, None) => origin.z,
+    };
+    let world = |p: DVec2| data_to_world(origin, DVec3::new(p.x, p.y, z(p)));
+
+    let mut selected = overlay.selected;
+    let ctrl = key_input.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight]);
+    if ctrl && mouse_input.just_pressed(MouseButton::Left) {
+        let cursor = window.get_single().ok().and_then(Window::cursor_position);
+        if let (Some(cursor), Ok((camera, transform))) = (cursor, camera.get_single()) {
+            let project = |p: DVec2| camera.world_to_viewport(transform, world(p));
+            selected = layer.nearest(cursor, PICK_DISTANCE, project);
+        }
+    }
+
+    for (i, feature) in layer.features.iter().enumerate() {
+        let color = if selected == Some(i) {
+            SELECTED_COLOR
+        } else {
+            LINE_COLOR
+        };
+        for line in &feature.lines {
+            gizmos.linestrip(line.iter().map(|p| world(*p)), color);
+        }
+    }
+
+    if overlay.selected != selected {
+        overlay.selected = selected;
+    }
+}
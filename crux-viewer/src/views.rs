@@ -0,0 +1,267 @@
+use std::{f32::consts::FRAC_PI_2, fmt};
+
+use bevy::{math::DVec3, prelude::*, render::camera::ScalingMode, window::PrimaryWindow};
+use bevy_panorbit_camera::PanOrbitCamera;
+
+use crux_format::prelude::*;
+
+use crate::{
+    frame::data_to_world,
+    framing::framed_size,
+    keys::{Action, KeyBindings},
+    settings::CameraPose,
+    PointCache, SpatialReference,
+};
+
+/// Margin around the data framed by the orthographic views
+const FRAME_MARGIN: f32 = 1.05;
+
+/// Orthographic view along an axis of the data, in Blender's numpad layout
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ViewPreset {
+    /// Plan view, looking down with north up
+    Top,
+    /// Elevation from the south, looking north
+    Front,
+    /// Elevation from the east, looking west
+    Side,
+}
+
+impl fmt::Display for ViewPreset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Top => "top",
+            Self::Front => "front",
+            Self::Side => "side",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Orbit parameters of the camera
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Pose {
+    pub focus: Vec3,
+    pub alpha: f32,
+    pub beta: f32,
+    pub radius: f32,
+}
+
+impl Pose {
+    fn of(camera: &PanOrbitCamera) -> Self {
+        Self {
+            focus: camera.target_focus,
+            alpha: camera.target_alpha,
+            beta: camera.target_beta,
+            radius: camera.target_radius,
+        }
+    }
+
+    /// Move the camera to the pose without transition
+    pub fn apply(&self, camera: &mut PanOrbitCamera) {
+        camera.focus = self.focus;
+        camera.target_focus = self.focus;
+        camera.alpha = Some(self.alpha);
+        camera.target_alpha = self.alpha;
+        camera.beta = Some(self.beta);
+        camera.target_beta = self.beta;
+        camera.radius = Some(self.radius);
+        camera.target_radius = self.radius;
+        camera.force_update = true;
+    }
+}
+
+/// Active orthographic view, if any
+#[derive(Resource, Default)]
+pub struct Views {
+    active: Option<ViewPreset>,
+    /// Perspective pose to return to
+    perspective: Option<Pose>,
+}
+
+impl Views {
+    /// Overlay line
+    pub fn status(&self, keys: &KeyBindings) -> String {
+        let keys = [
+            Action::TopView,
+            Action::FrontView,
+            Action::SideView,
+            Action::PerspectiveView,
+        ]
+        .map(|action| keys.label(action))
+        .join(", ");
+        match self.active {
+            Some(preset) => format!("View ({keys}): orthographic {preset}"),
+            None => format!("View ({keys}): perspective"),
+        }
+    }
+
+    /// Pose to restore in the next session, the perspective one while an
+    /// orthographic view is active
+    pub fn perspective_pose(&self, camera: &PanOrbitCamera) -> Pose {
+        self.perspective.unwrap_or_else(|| Pose::of(camera))
+    }
+
+    /// Perspective pose of the camera around the world `origin`, as persisted
+    pub fn camera_pose(&self, camera: &PanOrbitCamera, origin: DVec3) -> CameraPose {
+        let pose = self.perspective_pose(camera);
+        CameraPose {
+            origin: origin.to_array(),
+            focus: pose.focus.to_array(),
+            alpha: pose.alpha,
+            beta: pose.beta,
+            radius: pose.radius,
+        }
+    }
+}
+
+/// Camera pose and vertical extent of the orthographic projection framing the
+/// bounds `aabb` in a viewport of width / height `aspect`
+pub fn preset_pose(
+    preset: ViewPreset,
+    aabb: &AABB<Point<f64, 3>>,
+    origin: DVec3,
+    aspect: f32,
+) -> (Pose, f32) {
+    let size = framed_size(aabb);
+    let center = DVec3::from_slice(aabb.center().coords());
+
+    // orbit angles and the horizontal and vertical extent on screen
+    let (alpha, beta, width, height) = match preset {
+        ViewPreset::Top => (0., FRAC_PI_2, size.x, size.y),
+        ViewPreset::Front => (0., 0., size.x, size.z),
+        ViewPreset::Side => (FRAC_PI_2, 0., size.y, size.z),
+    };
+    let height = (height as f32).max(width as f32 / aspect) * FRAME_MARGIN;
+
+    // outside of the data in any direction
+    let radius = (size.length() as f32).max(1.);
+
+    let pose = Pose {
+        focus: data_to_world(origin, center),
+        alpha,
+        beta,
+        radius,
+    };
+    (pose, height.max(f32::EPSILON))
+}
+
+// Numpad 7, 1 and 3 switch to the top, front and side view, numpad 5 returns
+// to the perspective view
+pub fn views_system(
+    key_input: Res<Input<KeyCode>>,
+    keys: Res<KeyBindings>,
+    mut views: ResMut<Views>,
+    mut camera: Query<(&mut PanOrbitCamera, &mut Projection)>,
+    window: Query<&Window, With<PrimaryWindow>>,
+    cache: Res<PointCache>,
+    sr: Res<SpatialReference>,
+) {
+    let Ok((mut camera, mut projection)) = camera.get_single_mut() else {
+        return;
+    };
+
+    if keys.just_pressed(&key_input, Action::PerspectiveView) {
+        if let Some(pose) = views.perspective.take() {
+            *projection = Projection::Perspective(PerspectiveProjection::default());
+            camera.scale = None;
+            pose.apply(&mut camera);
+            views.active = None;
+        }
+        return;
+    }
+
+    let preset = if keys.just_pressed(&key_input, Action::TopView) {
+        ViewPreset::Top
+    } else if keys.just_pressed(&key_input, Action::FrontView) {
+        ViewPreset::Front
+    } else if keys.just_pressed(&key_input, Action::SideView) {
+        ViewPreset::Side
+    } else {
+        return;
+    };
+
+    let Some(origin) = sr.origin else {
+        return;
+    };
+    let Some(aabb) = cache
+        .data
+        .values()
+        .map(|pc| pc.aabb::<Point<f64, 3>>())
+        .reduce(|acc, aabb| acc.merged(&aabb))
+    else {
+        return;
+    };
+    let aspect = window
+        .get_single()
+        .map(|window| window.width() / window.height())
+        .ok()
+        .filter(|aspect| aspect.is_finite() && *aspect > 0.)
+        .unwrap_or(1.);
+
+    let (pose, height) = preset_pose(preset, &aabb, origin, aspect);
+
+    if views.active.is_none() {
+        views.perspective = Some(Pose::of(&camera));
+    }
+    views.active = Some(preset);
+
+    *projection = Projection::Orthographic(OrthographicProjection {
+        scaling_mode: ScalingMode::FixedVertical(1.),
+        near: 0.,
+        far: pose.radius * 2.,
+        ..default()
+    });
+    camera.scale = Some(height);
+    camera.target_scale = height;
+    pose.apply(&mut camera);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn presets() {
+        let aabb = AABB::from_corners(
+            Point::from_slice(&[100., 200., 10.]),
+            Point::from_slice(&[140., 220., 15.]),
+        );
+        let origin = DVec3::new(100., 200., 10.);
+
+        // centered on the data, the wider side fits the viewport
+        let (top, height) = preset_pose(ViewPreset::Top, &aabb, origin, 1.);
+        assert_eq!(top.focus, Vec3::new(20., 2.5, -10.));
+        assert_eq!((top.alpha, top.beta), (0., FRAC_PI_2));
+        assert_eq!(height, 40. * FRAME_MARGIN);
+        let (_, height) = preset_pose(ViewPreset::Top, &aabb, origin, 4.);
+        assert_eq!(height, 20. * FRAME_MARGIN);
+
+        let (front, height) = preset_pose(ViewPreset::Front, &aabb, origin, 16. / 9.);
+        assert_eq!((front.alpha, front.beta), (0., 0.));
+        assert_eq!(height, 40. * 9. / 16. * FRAME_MARGIN);
+
+        let (side, height) = preset_pose(ViewPreset::Side, &aabb, origin, 1.);
+        assert_eq!((side.alpha, side.beta), (FRAC_PI_2, 0.));
+        assert_eq!(height, 20. * FRAME_MARGIN);
+
+        // the camera is outside of the data
+        assert!(side.radius > 20.);
+    }
+
+    #[test]
+    fn status() {
+        let mut views = Views::default();
+        let mut keys = KeyBindings::default();
+        assert_eq!(
+            views.status(&keys),
+            "View (numpad 7, numpad 1, numpad 3, numpad 5): perspective"
+        );
+        views.active = Some(ViewPreset::Side);
+        keys.bind(Action::SideView, KeyCode::F9);
+        assert_eq!(
+            views.status(&keys),
+            "View (numpad 7, numpad 1, F9, numpad 5): orthographic side"
+        );
+    }
+}
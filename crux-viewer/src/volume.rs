@@ -0,0 +1,185 @@
+use bevy::{math::DVec3, prelude::*, window::PrimaryWindow};
+use bevy_panorbit_camera::PanOrbitCamera;
+
+use crux_format::{BaseSurface, VolumeReport};
+
+use crate::{
+    frame::data_to_world,
+    keys::{Action, KeyBindings},
+    measure::Measure,
+    picking::pick_cursor,
+    profile::ProfileTool,
+    units::Units,
+    PointCache, SpatialReference, ViewerSettings,
+};
+
+/// Volume above the lowest vertex of a picked footprint polygon
+#[derive(Resource, Default)]
+pub struct VolumeTool {
+    pub active: bool,
+    vertices: Vec<DVec3>,
+    result: Option<Result<VolumeReport, String>>,
+}
+
+#[derive(Component)]
+pub struct VolumeText;
+
+pub fn setup_volume(mut commands: Commands) {
+    commands.spawn((
+        TextBundle::from_section("", TextStyle::default()).with_style(Style {
+            position_type: PositionType::Absolute,
+            bottom: Val::Px(12.0),
+            left: Val::Px(12.0),
+            ..default()
+        }),
+        VolumeText,
+    ));
+}
+
+/// Base plane through the lowest vertex, e.g. ground points picked around a stockpile
+pub fn base_plane(vertices: &[DVec3]) -> BaseSurface {
+    BaseSurface::Plane(vertices.iter().map(|p| p.z).fold(f64::INFINITY, f64::min))
+}
+
+/// Volume report in the display unit, if the unit of the data is known
+pub fn format_report(report: &VolumeReport, units: &Units) -> String {
+    format!(
+        "Volume: {}\nArea: {} ({} cells, {:.1}% covered)",
+        units.format_volume(report.volume),
+        units.format_area(report.area),
+        report.cell_count,
+        report.coverage * 100.
+    )
+}
+
+// Press 'V' for the volume tool, shift + click adds footprint vertices, backspace clears
+#[allow(clippy::too_many_arguments)]
+pub fn volume_system(
+    key_input: Res<Input<KeyCode>>,
+    keys: Res<KeyBindings>,
+    mouse_input: Res<Input<MouseButton>>,
+    cache: Res<PointCache>,
+    settings: Res<ViewerSettings>,
+    sr: Res<SpatialReference>,
+    window: Query<&Window, With<PrimaryWindow>>,
+    camera: Query<(&Camera, &GlobalTransform, &Projection), With<PanOrbitCamera>>,
+    mut text: Query<&mut Text, With<VolumeText>>,
+    mut tool: ResMut<VolumeTool>,
+    mut others: (ResMut<Measure>, ResMut<ProfileTool>),
+    mut gizmos: Gizmos,
+) {
+    let (Ok(window), Ok((camera, transform, projection)), Ok(mut text)) = (
+        window.get_single(),
+        camera.get_single(),
+        text.get_single_mut(),
+    ) else {
+        return;
+    };
+
+    // the tools share the picking gesture
+    let (measure, profile) = (&mut others.0, &mut others.1);
+    if keys.just_pressed(&key_input, Action::Volume) {
+        tool.active = !tool.active;
+        if tool.active {
+            measure.active = false;
+            profile.active = false;
+        }
+    } else if measure.active || profile.active {
+        tool.active = false;
+    }
+
+    let key = keys.label(Action::Volume);
+    if !tool.active {
+        if !text.sections[0].value.is_empty() {
+            text.sections[0].value.clear();
+        }
+        return;
+    }
+
+    let (Some(origin), Some(pc), Some(index)) = (
+        sr.origin,
+        cache.data.get(&settings.collection),
+        cache.index.get(&settings.collection),
+    ) else {
+        text.sections[0].value = format!("Volume ({key}): waiting for index");
+        return;
+    };
+
+    if keys.just_pressed(&key_input, Action::ClearFootprint) {
+        tool.vertices.clear();
+        tool.result = None;
+    }
+
+    let shift = key_input.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]);
+    if shift && mouse_input.just_pressed(MouseButton::Left) {
+        let picked = window
+            .cursor_position()
+            .and_then(|cursor| pick_cursor(index, origin, (camera, transform, projection), cursor));
+
+        if let Some((_, p)) = picked {
+            tool.vertices.push(p);
+
+            if tool.vertices.len() >= 3 {
+                let footprint: Vec<[f64; 2]> = tool.vertices.iter().map(|p| [p.x, p.y]).collect();
+                tool.result = Some(
+                    pc.volume_above(&footprint, settings.volume_cell, base_plane(&tool.vertices))
+                        .map_err(|e| e.to_string()),
+                );
+            }
+        }
+    }
+
+    // footprint outline, closed once it is a polygon
+    let outline: Vec<Vec3> = tool
+        .vertices
+        .iter()
+        .chain(tool.vertices.first().filter(|_| tool.vertices.len() >= 3))
+        .map(|p| data_to_world(origin, *p))
+        .collect();
+    for p in &outline {
+        gizmos.sphere(*p, Quat::IDENTITY, 0.1, Color::FUCHSIA);
+    }
+    gizmos.linestrip(outline, Color::FUCHSIA);
+
+    text.sections[0].value = match &tool.result {
+        Some(Ok(report)) => format!(
+            "{}\nVolume ({key}): shift + click adds vertices, {} clears",
+            format_report(report, &Units::new(pc.metadata().units, settings.units)),
+            keys.label(Action::ClearFootprint)
+        ),
+        Some(Err(e)) => format!("Invalid footprint: {e}"),
+        None => format!("Volume ({key}): shift + click at least three footprint vertices"),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use crux_format::LengthUnit;
+
+    use super::*;
+
+    #[test]
+    fn readout() {
+        let vertices = [
+            DVec3::new(0., 0., 2.),
+            DVec3::new(1., 0., 1.5),
+            DVec3::new(0., 1., 3.),
+        ];
+        assert_eq!(base_plane(&vertices), BaseSurface::Plane(1.5));
+
+        let report = VolumeReport {
+            volume: 60.,
+            area: 252.,
+            cell_count: 1008,
+            coverage: 0.984375,
+        };
+        let metres = Units::new(Some(LengthUnit::Metre), None);
+        assert_eq!(
+            format_report(&report, &metres),
+            "Volume: 60.00 m³\nArea: 252.0 m² (1008 cells, 98.4% covered)"
+        );
+        let feet = Units::new(Some(LengthUnit::Metre), Some(LengthUnit::Foot));
+        assert!(format_report(&report, &feet).starts_with("Volume: 2119 ft³\n"));
+        assert!(format_report(&report, &Units::default()).starts_with("Volume: 60.00\n"));
+    }
+}